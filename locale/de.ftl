@@ -0,0 +1,26 @@
+# dotlnx user-facing messages, German. See en.ftl.
+
+## Launching apps
+
+app-not-found = App nicht gefunden: { $name }
+executable-not-found = Programmdatei nicht gefunden: { $path }
+launch-denied = { $app } darf nur von { $who } gestartet werden, und { $user } gehört nicht dazu; bitten Sie Ihre Administration um Zugriff
+launch-denied-members = Mitgliedern von { $groups }
+launch-denied-either = { $first } oder { $second }
+no-launch-log = kein Startprotokoll für { $app } (nur Starts über `dotlnx run` aus dem Menü werden protokolliert)
+
+## The dialog shown when an app started from the menu fails
+
+launch-failed-title = { $app } konnte nicht gestartet werden
+launch-failed-logs = Details zeigt `dotlnx logs { $app }`.
+launch-exited = wurde direkt nach dem Start mit Status { $code } beendet
+launch-killed = wurde direkt nach dem Start durch Signal { $signal } beendet
+launch-stopped = wurde direkt nach dem Start angehalten
+
+## Notifications from the dotlnx service
+
+quarantine-title = dotlnx: { $app } in Quarantäne
+quarantine-body = { $bundle } wurde nicht installiert: { $reasons }. { $hint }
+quarantine-hint-ownership = Korrigieren Sie Besitzer und Berechtigungen des Ordners, dann wird er automatisch installiert.
+quarantine-hint-privileged = Entfernen Sie die setuid/setgid-Bits oder Datei-Capabilities (`dotlnx validate --fix`), dann wird er automatisch installiert.
+quarantine-hint-scan = Entfernen oder ersetzen Sie die gemeldeten Dateien, dann wird er erneut geprüft.
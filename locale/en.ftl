@@ -0,0 +1,31 @@
+# dotlnx user-facing messages, English (the fallback for every other language).
+#
+# Fluent syntax (https://projectfluent.org), the subset dotlnx reads: `id = text`, with
+# `{ $name }` for values, indented lines continuing the text and `#` comments. The IDs are
+# stable; scripts may match them (errors are logged with message_id="..."). To add a language,
+# copy this file to <language>.ftl (de.ftl, pt_BR.ftl) and translate the text, not the IDs.
+
+## Launching apps
+
+app-not-found = app not found: { $name }
+executable-not-found = executable not found: { $path }
+launch-denied = { $app } may only be run by { $who }, and { $user } is not one of them; ask your administrator for access
+launch-denied-members = members of { $groups }
+launch-denied-either = { $first } or { $second }
+no-launch-log = no launch log for { $app } (only launches through `dotlnx run` from the menu are logged)
+
+## The dialog shown when an app started from the menu fails
+
+launch-failed-title = Could not start { $app }
+launch-failed-logs = See `dotlnx logs { $app }` for details.
+launch-exited = exited with status { $code } right after starting
+launch-killed = was killed by signal { $signal } right after starting
+launch-stopped = stopped right after starting
+
+## Notifications from the dotlnx service
+
+quarantine-title = dotlnx: { $app } quarantined
+quarantine-body = { $bundle } was not installed: { $reasons }. { $hint }
+quarantine-hint-ownership = Fix the folder's ownership/permissions and it will be installed automatically.
+quarantine-hint-privileged = Remove the setuid/setgid bits or file capabilities (`dotlnx validate --fix`) and it will be installed automatically.
+quarantine-hint-scan = Remove or replace the flagged files and it will be scanned again.
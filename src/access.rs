@@ -0,0 +1,145 @@
+//! Which users a system-tier app is for. A bundle (or the admin's override) can list
+//! `allowed_users` and `allowed_groups`; sync then gives only those users a menu entry, in
+//! `~/.local/share/applications/dotlnx-system/`, instead of one in the shared applications dir.
+//! With neither set, the app is for everyone.
+//!
+//! Hiding a menu entry is not access control, so `dotlnx run` checks the lists too, and pinned
+//! apps' menu entries launch through it (see `Config::needs_runner`).
+
+use anyhow::Result;
+
+use crate::config::Config;
+use crate::i18n::{self, Lang, Message};
+
+/// Subdirectory of a user's applications dir holding the entries of system apps pinned to them.
+/// Menus read subdirectories too; the user tier's own reconcile only looks at top-level files.
+pub const MEMBER_MENU_DIR: &str = "dotlnx-system";
+
+/// True when the app is only for some users.
+pub fn is_restricted(cfg: &Config) -> bool {
+    !cfg.allowed_users.is_empty() || !cfg.allowed_groups.is_empty()
+}
+
+/// True when `username`, in `groups`, is one of `users` or in one of `allowed_groups`.
+pub fn is_listed(
+    username: &str,
+    groups: &[String],
+    users: &[String],
+    allowed_groups: &[String],
+) -> bool {
+    users.iter().any(|u| u == username) || allowed_groups.iter().any(|g| groups.contains(g))
+}
+
+/// Refuse to launch an app the invoking user is not listed for. Groups are those of this process
+/// (as for file permissions, a new group membership counts from the next login). Root may run
+/// anything.
+pub fn check_launch(cfg: &Config) -> Result<()> {
+    use nix::unistd::{getegid, getgid, getgroups, getuid, Group, User};
+    let uid = getuid();
+    if !is_restricted(cfg) || uid.is_root() {
+        return Ok(());
+    }
+    let username = User::from_uid(uid)
+        .ok()
+        .flatten()
+        .map(|u| u.name)
+        .unwrap_or_else(|| uid.to_string());
+    let mut gids = getgroups().unwrap_or_default();
+    gids.extend([getgid(), getegid()]);
+    let groups: Vec<String> = gids
+        .into_iter()
+        .filter_map(|gid| Group::from_gid(gid).ok().flatten())
+        .map(|g| g.name)
+        .collect();
+    if is_listed(&username, &groups, &cfg.allowed_users, &cfg.allowed_groups) {
+        return Ok(());
+    }
+    Err(denied(cfg, &username, i18n::lang()).into())
+}
+
+/// Why `username` may not run the app.
+fn denied(cfg: &Config, username: &str, lang: &Lang) -> Message {
+    let mut who = Vec::new();
+    if !cfg.allowed_groups.is_empty() {
+        who.push(i18n::tr_in(
+            lang,
+            "launch-denied-members",
+            &[("groups", cfg.allowed_groups.join(", "))],
+        ));
+    }
+    if !cfg.allowed_users.is_empty() {
+        who.push(cfg.allowed_users.join(", "));
+    }
+    let who = who
+        .into_iter()
+        .reduce(|first, second| {
+            i18n::tr_in(
+                lang,
+                "launch-denied-either",
+                &[("first", first), ("second", second)],
+            )
+        })
+        .unwrap_or_default();
+    Message::new(
+        "launch-denied",
+        &[
+            ("app", cfg.name.clone()),
+            ("who", who),
+            ("user", username.to_string()),
+        ],
+    )
+}
+
+/// Names of the groups `username` is in; none when the user or its groups cannot be looked up.
+pub fn groups_of(username: &str) -> Vec<String> {
+    use nix::unistd::{getgrouplist, Group, User};
+    let Some(user) = User::from_name(username).ok().flatten() else {
+        return Vec::new();
+    };
+    let Ok(name) = std::ffi::CString::new(username) else {
+        return Vec::new();
+    };
+    getgrouplist(&name, user.gid)
+        .unwrap_or_else(|_| vec![user.gid])
+        .into_iter()
+        .filter_map(|gid| Group::from_gid(gid).ok().flatten())
+        .map(|g| g.name)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn users_and_groups_restrict_who_sees_the_app() {
+        let cfg = |extra: &str| -> Config {
+            toml::from_str(&format!(
+                "name = \"lab\"\nexecutable = \"run.sh\"\n{}",
+                extra
+            ))
+            .unwrap()
+        };
+        let open = cfg("");
+        assert!(!is_restricted(&open));
+
+        let pinned = cfg("allowed_users = [\"alice\"]\nallowed_groups = [\"physics\"]\n");
+        assert!(is_restricted(&pinned));
+        let (users, groups) = (&pinned.allowed_users, &pinned.allowed_groups);
+        assert!(is_listed("alice", &[], users, groups));
+        assert!(is_listed(
+            "bob",
+            &["staff".into(), "physics".into()],
+            users,
+            groups
+        ));
+        assert!(!is_listed("bob", &["staff".into()], users, groups));
+        assert!(!is_listed("bob", &["physics".into()], users, &[]));
+        assert_eq!(
+            denied(&pinned, "bob", &Lang::english()).english(),
+            "lab may only be run by members of physics or alice, and bob is not one of them; ask your administrator for access"
+        );
+        assert!(pinned.needs_runner() && !open.needs_runner());
+        assert!(check_launch(&open).is_ok());
+    }
+}
@@ -0,0 +1,510 @@
+//! `dotlnx adopt`: bring menu entries installed by hand (.desktop files in
+//! ~/.local/share/applications) under dotlnx without re-packaging the apps first. Each entry
+//! becomes a wrapper bundle in ~/Applications whose run.sh starts the entry's command, with its
+//! name, icon, comment, categories and translations. The original is kept in the bundle as
+//! original.desktop and hidden from the menu (NoDisplay=true), so the app is not listed twice.
+//!
+//! The command of an adopted entry lives outside its bundle, so adopted bundles run unconfined, as
+//! the app did before; tighten `[security]` when the app is re-packaged.
+
+use anyhow::{Context, Result};
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::appmeta;
+use crate::bundle;
+use crate::paths::{self, Paths};
+use crate::sync;
+use crate::validate;
+use crate::watch;
+
+/// Field codes of an Exec line. None of them has a value when the app is started from its bundle:
+/// files and URLs are passed on by run.sh's `"$@"` instead.
+const FIELD_CODES: &[char] = &[
+    'f', 'F', 'u', 'U', 'i', 'c', 'k', 'd', 'D', 'n', 'N', 'v', 'm',
+];
+
+/// File name the adopted entry is kept under in its bundle.
+pub const ORIGINAL: &str = "original.desktop";
+
+/// A menu entry that can be adopted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// The .desktop file.
+    pub path: PathBuf,
+    /// Name=, the app name of the bundle.
+    pub name: String,
+    /// Exec= split into the program and its arguments, field codes removed.
+    pub command: Vec<String>,
+    pub icon: Option<String>,
+    pub terminal: bool,
+    /// Path=, the directory the command runs in.
+    pub working_dir: Option<String>,
+}
+
+/// Unlocalized keys of the [Desktop Entry] group with their raw values (Exec has its own quoting,
+/// see `split_exec`).
+fn keys(text: &str) -> Vec<(String, String)> {
+    let mut out = Vec::new();
+    let mut in_entry = false;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_entry = line == "[Desktop Entry]";
+            continue;
+        }
+        if !in_entry || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim();
+        if key.contains('[') {
+            continue;
+        }
+        out.push((key.to_string(), value.trim().to_string()));
+    }
+    out
+}
+
+/// Program and arguments of an Exec value: escapes undone, double-quoted arguments joined, field
+/// codes dropped (`%%` is a literal `%`).
+pub fn split_exec(exec: &str) -> Result<Vec<String>> {
+    let exec = appmeta::unescape(exec);
+    let mut args = Vec::new();
+    // The argument being read, and whether it had a field code: an argument that was only field
+    // codes (`%U`) is dropped rather than passed empty.
+    let mut current: Option<(String, bool)> = None;
+    let mut quoted = false;
+    let mut chars = exec.chars();
+    let arg = || (String::new(), false);
+    let finish = |current: Option<(String, bool)>, args: &mut Vec<String>| {
+        if let Some((arg, had_code)) = current {
+            if !(arg.is_empty() && had_code) {
+                args.push(arg);
+            }
+        }
+    };
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.get_or_insert_with(arg);
+            }
+            '\\' if quoted => match chars.next() {
+                Some(c) => current.get_or_insert_with(arg).0.push(c),
+                None => anyhow::bail!("Exec ends with a backslash"),
+            },
+            ' ' | '\t' if !quoted => finish(current.take(), &mut args),
+            // A field code stays a field code even inside quotes; `%%` keeps one `%`.
+            '%' => match chars.next() {
+                Some('%') => current.get_or_insert_with(arg).0.push('%'),
+                Some(code) if FIELD_CODES.contains(&code) => {
+                    current.get_or_insert_with(arg).1 = true
+                }
+                Some(other) => anyhow::bail!("Exec has an unknown field code %{}", other),
+                None => anyhow::bail!("Exec ends with %"),
+            },
+            c => current.get_or_insert_with(arg).0.push(c),
+        }
+    }
+    if quoted {
+        anyhow::bail!("Exec has an unterminated quote");
+    }
+    finish(current, &mut args);
+    if args.first().is_none_or(|program| program.is_empty()) {
+        anyhow::bail!("Exec has no program");
+    }
+    Ok(args)
+}
+
+/// Parse a .desktop file; Ok(None) when it is not a visible application (hidden, a link, a
+/// directory), Err with the reason when it is one that cannot be adopted.
+pub fn parse(path: &Path, text: &str) -> Result<Option<Entry>> {
+    let keys = keys(text);
+    let get = |key: &str| {
+        keys.iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    };
+    let flag = |key: &str| get(key).is_some_and(|v| v == "true");
+    if get("Type") != Some("Application") || flag("NoDisplay") || flag("Hidden") {
+        return Ok(None);
+    }
+    let name = appmeta::unescape(get("Name").unwrap_or_default());
+    validate::validate_app_name(&name).context("its Name cannot be an app name")?;
+    let command = split_exec(get("Exec").context("it has no Exec line (D-Bus activated?)")?)?;
+    let text_value = |key: &str| get(key).map(appmeta::unescape).filter(|v| !v.is_empty());
+    Ok(Some(Entry {
+        path: path.to_path_buf(),
+        name,
+        command,
+        icon: text_value("Icon"),
+        terminal: flag("Terminal"),
+        working_dir: text_value("Path"),
+    }))
+}
+
+/// `s` as one single-quoted shell word.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// run.sh of the wrapper bundle.
+fn run_script(entry: &Entry) -> String {
+    let mut out = format!(
+        "#!/bin/sh\n# Adopted by dotlnx from {}\n",
+        entry.path.display()
+    );
+    if let Some(dir) = &entry.working_dir {
+        out.push_str(&format!("cd {} || exit 1\n", shell_quote(dir)));
+    }
+    let words: Vec<String> = entry.command.iter().map(|w| shell_quote(w)).collect();
+    out.push_str(&format!("exec {} \"$@\"\n", words.join(" ")));
+    out
+}
+
+/// config.toml of the wrapper bundle. `icon` is the value to use (a file copied into the bundle,
+/// or the entry's theme icon name).
+fn config_toml(entry: &Entry, icon: Option<&str>, text: &str) -> String {
+    let string = |s: &str| toml::Value::String(s.to_string()).to_string();
+    let mut out = format!(
+        "# Adopted by dotlnx from {}; the original entry is {}.\n# run.sh starts the command it ran.\nname = {}\nexecutable = \"run.sh\"\n",
+        entry.path.display(),
+        ORIGINAL,
+        string(&entry.name)
+    );
+    if let Some(icon) = icon {
+        out.push_str(&format!("icon = {}\n", string(icon)));
+    }
+    if entry.terminal {
+        out.push_str("terminal = true\n");
+    }
+    out.push_str(
+        "\n[security]\n# The command is outside this bundle, so it runs unconfined as it did before adoption.\nconfine = false\n",
+    );
+    appmeta::add_to_config(&out, &appmeta::parse_desktop_entry(text))
+}
+
+/// The entry with NoDisplay=true (and a note saying where the app went) in its [Desktop Entry]
+/// group, replacing any NoDisplay it had.
+pub fn hidden(text: &str, bundle: &Path) -> String {
+    let mut out = String::with_capacity(text.len() + 64);
+    let mut in_entry = false;
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            in_entry = trimmed == "[Desktop Entry]";
+            out.push_str(line);
+            out.push('\n');
+            if in_entry {
+                out.push_str(&format!(
+                    "# Hidden by dotlnx adopt: now {}\nNoDisplay=true\n",
+                    bundle.display()
+                ));
+            }
+            continue;
+        }
+        if in_entry
+            && trimmed
+                .split_once('=')
+                .is_some_and(|(k, _)| k.trim() == "NoDisplay")
+        {
+            continue;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+/// Create the wrapper bundle for `entry` in `apps_dir` and hide the original. Returns the bundle.
+/// The bundle is put together and validated in a staging dir, then moved into place, so the
+/// watcher never sees half of it.
+pub fn adopt_one(entry: &Entry, text: &str, apps_dir: &Path) -> Result<PathBuf> {
+    use std::os::unix::fs::PermissionsExt;
+    let dest = apps_dir.join(format!("{}.lnx", entry.name));
+    if dest.exists() {
+        anyhow::bail!("{} already exists", dest.display());
+    }
+    let staging = apps_dir.join(".dotlnx-adopt");
+    let bundle = staging.join(format!("{}.lnx", entry.name));
+    if bundle.exists() {
+        std::fs::remove_dir_all(&bundle).with_context(|| format!("remove {}", bundle.display()))?;
+    }
+    std::fs::create_dir_all(&bundle).with_context(|| format!("create {}", bundle.display()))?;
+    let result = (|| -> Result<()> {
+        // An icon given as a file is copied in, so it moves with the bundle.
+        let mut icon = entry.icon.clone();
+        if let Some(file) = entry
+            .icon
+            .as_deref()
+            .map(Path::new)
+            .filter(|p| p.is_absolute() && p.is_file())
+        {
+            let ext = file.extension().and_then(|e| e.to_str()).unwrap_or("png");
+            let relative = format!("assets/icon.{}", ext);
+            std::fs::create_dir_all(bundle.join("assets"))?;
+            std::fs::copy(file, bundle.join(&relative))
+                .with_context(|| format!("copy {}", file.display()))?;
+            icon = Some(relative);
+        }
+        std::fs::write(bundle.join("run.sh"), run_script(entry))?;
+        std::fs::set_permissions(
+            bundle.join("run.sh"),
+            std::fs::Permissions::from_mode(0o755),
+        )?;
+        std::fs::write(
+            bundle.join("config.toml"),
+            config_toml(entry, icon.as_deref(), text),
+        )?;
+        std::fs::write(bundle.join(ORIGINAL), text)?;
+        validate::validate_bundle(&bundle)?;
+        std::fs::rename(&bundle, &dest)
+            .with_context(|| format!("move bundle to {}", dest.display()))?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_dir_all(&bundle);
+    }
+    let _ = std::fs::remove_dir(&staging);
+    result?;
+
+    let dir = entry.path.parent().unwrap_or(Path::new("."));
+    let file_name = entry.path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = dir.join(format!(".{}.dotlnx-tmp", file_name));
+    std::fs::write(&tmp, hidden(text, &dest))
+        .with_context(|| format!("write {}", tmp.display()))?;
+    std::fs::rename(&tmp, &entry.path).with_context(|| format!("hide {}", entry.path.display()))?;
+    Ok(dest)
+}
+
+/// Menu entries in the user's applications dir that dotlnx did not write, with their contents.
+/// Entries that cannot be adopted are reported and left out.
+fn candidates(paths: &Paths) -> Result<Vec<(Entry, String)>> {
+    let dir = paths.user_desktop_dir()?;
+    let prefix = paths.desktop_prefix(&dir).to_string();
+    let mut files: Vec<PathBuf> = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e).with_context(|| format!("read {}", dir.display())),
+    };
+    files.sort();
+    let mut out = Vec::new();
+    for path in files {
+        let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        if !file_name.ends_with(".desktop") || file_name.starts_with(&prefix) || !path.is_file() {
+            continue;
+        }
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) => {
+                println!("skipping {}: {}", file_name, e);
+                continue;
+            }
+        };
+        match parse(&path, &text) {
+            Ok(Some(entry)) => out.push((entry, text)),
+            Ok(None) => {}
+            Err(e) => println!("skipping {}: {:#}", file_name, e),
+        }
+    }
+    Ok(out)
+}
+
+/// Ask a yes/no question on the terminal; anything but yes is no.
+fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Entry point for `dotlnx adopt [entry...] [--yes] [--dry-run]`. Entries are picked by file name
+/// or Name; naming them counts as confirming them. Otherwise each one is asked about on a
+/// terminal, or needs `--yes`.
+pub fn run(names: &[String], yes: bool, dry_run: bool) -> Result<()> {
+    if bundle::is_root() {
+        anyhow::bail!("run dotlnx adopt as the user whose menu entries to adopt");
+    }
+    let paths = paths::load()?;
+    let mut found = candidates(&paths)?;
+    if !names.is_empty() {
+        for name in names {
+            let matches = |(e, _): &(Entry, String)| {
+                e.name == *name || e.path.file_name().is_some_and(|f| f == name.as_str())
+            };
+            if !found.iter().any(matches) {
+                anyhow::bail!(
+                    "no menu entry {:?} to adopt in {}",
+                    name,
+                    paths.user_desktop_dir()?.display()
+                );
+            }
+        }
+        found.retain(|(e, _)| {
+            names
+                .iter()
+                .any(|n| e.name == *n || e.path.file_name().is_some_and(|f| f == n.as_str()))
+        });
+    }
+    let mut adopted = 0;
+    let ask = !yes && names.is_empty();
+    let interactive = std::io::stdin().is_terminal();
+    for (entry, text) in &found {
+        let file_name = entry.path.file_name().unwrap_or_default().to_string_lossy();
+        if let Some((dir, _, _)) = bundle::resolve_bundle_by_name(&paths, &entry.name)? {
+            println!(
+                "skipping {}: an app named {:?} is already installed from {}",
+                file_name,
+                entry.name,
+                dir.display()
+            );
+            continue;
+        }
+        let what = format!(
+            "{} ({}) -> {}",
+            entry.name,
+            file_name,
+            paths
+                .user_apps
+                .join(format!("{}.lnx", entry.name))
+                .display()
+        );
+        if dry_run || (ask && !interactive) {
+            println!("would adopt {}", what);
+            continue;
+        }
+        if ask && !confirm(&format!("adopt {}?", what))? {
+            continue;
+        }
+        let dest = adopt_one(entry, text, &paths.user_apps)
+            .with_context(|| format!("adopt {}", file_name))?;
+        info!(app = %entry.name, from = %entry.path.display(), bundle = %dest.display(), "adopted");
+        println!("adopted {} into {}", entry.name, dest.display());
+        adopted += 1;
+    }
+    if ask && !interactive && !dry_run && !found.is_empty() {
+        println!("not on a terminal: rerun with --yes, or name the entries to adopt");
+    }
+    if adopted > 0 && !watch::service_active() {
+        sync::run(false)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_exec_lines_like_launchers_do() {
+        assert_eq!(split_exec("firefox %u").unwrap(), ["firefox"]);
+        assert_eq!(
+            split_exec("\"/opt/My App/run\" --flag \"%F\"").unwrap(),
+            ["/opt/My App/run", "--flag"]
+        );
+        assert_eq!(
+            split_exec("sh -c \"echo \\\\\"hi\\\\\" \\\\\\\\$HOME\" 100%%").unwrap(),
+            ["sh", "-c", "echo \"hi\" \\$HOME", "100%"]
+        );
+        assert_eq!(
+            split_exec("app --file=%f \"\"").unwrap(),
+            ["app", "--file=", ""]
+        );
+        assert!(split_exec("\"unterminated").is_err());
+        assert!(split_exec("app %z").is_err());
+        assert!(split_exec("%U").is_err());
+    }
+
+    #[test]
+    fn adopts_an_entry_into_a_wrapper_bundle_and_hides_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let (menu, apps) = (
+            dir.path().join("applications"),
+            dir.path().join("Applications"),
+        );
+        std::fs::create_dir_all(&menu).unwrap();
+        std::fs::create_dir_all(&apps).unwrap();
+        let icon = dir.path().join("tool.svg");
+        std::fs::write(&icon, "<svg/>").unwrap();
+        let path = menu.join("tool.desktop");
+        let text = format!(
+            "[Desktop Entry]\nType=Application\nName=Tool\nName[de]=Werkzeug\nComment=Does things\nExec=\"/opt/tool/bin/tool\" --open %F\nIcon={}\nPath=/opt/tool\nTerminal=true\nNoDisplay=false\nCategories=Utility;\n\n[Desktop Action new]\nName=New\nExec=tool --new\n",
+            icon.display()
+        );
+        std::fs::write(&path, &text).unwrap();
+
+        let entry = parse(&path, &text).unwrap().unwrap();
+        assert_eq!(entry.command, ["/opt/tool/bin/tool", "--open"]);
+        let bundle = adopt_one(&entry, &text, &apps).unwrap();
+        assert_eq!(bundle, apps.join("Tool.lnx"));
+        assert!(!apps.join(".dotlnx-adopt").exists());
+
+        let cfg = crate::config::load(&bundle).unwrap();
+        assert_eq!(cfg.name, "Tool");
+        assert_eq!(cfg.icon.as_deref(), Some("assets/icon.svg"));
+        assert!(cfg.terminal);
+        assert_eq!(cfg.comment.as_deref(), Some("Does things"));
+        assert_eq!(cfg.categories, Some(vec!["Utility".to_string()]));
+        assert_eq!(cfg.translations["de"].name.as_deref(), Some("Werkzeug"));
+        assert!(!cfg.security.unwrap().confine);
+        let script = std::fs::read_to_string(bundle.join("run.sh")).unwrap();
+        assert!(
+            script
+                .ends_with("cd '/opt/tool' || exit 1\nexec '/opt/tool/bin/tool' '--open' \"$@\"\n"),
+            "{}",
+            script
+        );
+        assert_eq!(
+            std::fs::read_to_string(bundle.join(ORIGINAL)).unwrap(),
+            text
+        );
+
+        let now = std::fs::read_to_string(&path).unwrap();
+        assert!(now.starts_with("[Desktop Entry]\n# Hidden by dotlnx adopt: now "));
+        assert_eq!(now.matches("NoDisplay").count(), 1);
+        assert!(
+            now.contains("NoDisplay=true\n") && now.contains("[Desktop Action new]\nName=New\n")
+        );
+        assert_eq!(parse(&path, &now).unwrap(), None);
+        assert!(adopt_one(&entry, &text, &apps).is_err());
+    }
+
+    #[test]
+    fn skips_entries_that_are_not_apps() {
+        let path = Path::new("/x.desktop");
+        assert_eq!(
+            parse(
+                path,
+                "[Desktop Entry]\nType=Link\nName=Site\nURL=https://example.org\n"
+            )
+            .unwrap(),
+            None
+        );
+        assert_eq!(
+            parse(
+                path,
+                "[Desktop Entry]\nType=Application\nName=Bg\nExec=bg\nHidden=true\n"
+            )
+            .unwrap(),
+            None
+        );
+        assert!(parse(
+            path,
+            "[Desktop Entry]\nType=Application\nName=Svc\nDBusActivatable=true\n"
+        )
+        .is_err());
+        assert!(parse(
+            path,
+            "[Desktop Entry]\nType=Application\nName=a/b\nExec=ab\n"
+        )
+        .is_err());
+    }
+}
@@ -1,47 +1,31 @@
 //! Generate AppArmor profile from config security section; load/unload via apparmor_parser.
 
 use anyhow::{Context, Result};
+use nix::fcntl::{Flock, FlockArg};
+use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 
-use crate::config::Config;
+use crate::config::{Config, Userns};
+use crate::paths::Paths;
 
-/// Locations to check for apparmor_parser (root/sudo/systemd often have minimal PATH without /usr/sbin).
-const APPARMOR_PARSER_CANDIDATES: &[&str] = &["/usr/sbin/apparmor_parser", "/sbin/apparmor_parser"];
-
-/// Resolve path to apparmor_parser: check /usr/sbin and /sbin first, then PATH.
-fn find_apparmor_parser() -> Option<PathBuf> {
-    for p in APPARMOR_PARSER_CANDIDATES {
-        let path = Path::new(p);
-        if path.is_file() {
-            return Some(path.to_path_buf());
-        }
-    }
-    if let Some(path_env) = std::env::var_os("PATH") {
-        for dir in std::env::split_paths(&path_env) {
-            let candidate = dir.join("apparmor_parser");
-            if candidate.is_file() {
-                return Some(candidate);
-            }
-        }
-    }
-    None
-}
-
-/// Sanitize path for AppArmor rule: strip comments (#), no newline, no comma (would break profile).
+/// Sanitize path for AppArmor rule: strip comments (#), no newline or other control character,
+/// no comma (would break profile).
 fn sanitize_apparmor_path(p: &str) -> String {
     let without_comment = p.split('#').next().unwrap_or(p).trim();
     without_comment
-        .replace(['\n', '\r', ','], " ")
+        .replace(|c: char| c == ',' || c.is_control(), " ")
         .trim()
         .to_string()
 }
 
 /// Quote a path for use in an AppArmor file rule if it contains spaces or other characters that
 /// would break the lexer. Rules with embedded spaces or tabs must be quoted per apparmor.d(5).
-fn quote_path_for_apparmor(path: &str) -> String {
+/// Bundle paths are not sanitized, so `#` and `,` are quoted too and control characters (which
+/// cannot appear in a rule) become the `?` wildcard, which still matches the real path.
+pub(crate) fn quote_path_for_apparmor(path: &str) -> String {
     let needs_quote = path
         .chars()
-        .any(|c| c == ' ' || c == '\t' || c == '"' || c == '\\');
+        .any(|c| matches!(c, ' ' | '"' | '\\' | '#' | ',') || c.is_control());
     if !needs_quote {
         return path.to_string();
     }
@@ -50,6 +34,7 @@ fn quote_path_for_apparmor(path: &str) -> String {
         match c {
             '\\' => escaped.push_str("\\\\"),
             '"' => escaped.push_str("\\\""),
+            c if c.is_control() => escaped.push('?'),
             c => escaped.push(c),
         }
     }
@@ -59,75 +44,480 @@ fn quote_path_for_apparmor(path: &str) -> String {
 /// Sanitize a segment for use in profile name (no path sep, no ..). Keeps alphanumeric, -, _.
 fn sanitize_profile_segment(s: &str) -> String {
     s.chars()
-        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
         .collect()
 }
 
-/// Profile name for user tier: dotlnx-<username>-<name> (avoids collision across users).
-pub fn profile_name_user(username: &str, app_name: &str) -> String {
+/// Profile-name form of a user or app name. Names made only of [A-Za-z0-9_-] are used as they
+/// are; any other is sanitized and suffixed with a hash of the original, so "my app", "my.app"
+/// and "my_app" get three different profiles. Sanitized output never contains '.', so a
+/// suffixed segment cannot equal a plain one.
+fn profile_segment(s: &str) -> String {
+    let clean = sanitize_profile_segment(s);
+    if clean == s {
+        clean
+    } else {
+        format!("{}.{:08x}", clean, crc32fast::hash(s.as_bytes()))
+    }
+}
+
+/// Profile name for user tier: <prefix><username>-<name> (avoids collision across users).
+/// The prefix is "dotlnx-" unless [paths] says otherwise.
+pub fn profile_name_user(prefix: &str, username: &str, app_name: &str) -> String {
     format!(
-        "dotlnx-{}-{}",
-        sanitize_profile_segment(username),
-        sanitize_profile_segment(app_name)
+        "{}{}-{}",
+        prefix,
+        profile_segment(username),
+        profile_segment(app_name)
     )
 }
 
-/// Profile name for system tier: dotlnx-<name>.
-pub fn profile_name_system(app_name: &str) -> String {
-    format!("dotlnx-{}", sanitize_profile_segment(app_name))
+/// Profile name for system tier: <prefix><name>.
+pub fn profile_name_system(prefix: &str, app_name: &str) -> String {
+    format!("{}{}", prefix, profile_segment(app_name))
+}
+
+/// Name the app's profile had before sanitized names were suffixed, when that differs from the
+/// current one (`username` None for the system tier). Sync removes it once it owns it.
+pub fn legacy_profile_name(prefix: &str, username: Option<&str>, app_name: &str) -> Option<String> {
+    let legacy = match username {
+        Some(u) => format!(
+            "{}{}-{}",
+            prefix,
+            sanitize_profile_segment(u),
+            sanitize_profile_segment(app_name)
+        ),
+        None => format!("{}{}", prefix, sanitize_profile_segment(app_name)),
+    };
+    let current = match username {
+        Some(u) => profile_name_user(prefix, u, app_name),
+        None => profile_name_system(prefix, app_name),
+    };
+    (legacy != current).then_some(legacy)
+}
+
+/// AppArmor policy ABI a generated profile targets. Determines the `abi` header and include syntax.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Abi {
+    /// Pre-3.0 parsers: no abi line, `#include` syntax.
+    #[default]
+    Legacy,
+    /// AppArmor 3.x: `abi <abi/3.0>,` and bare `include`.
+    V3,
+    /// AppArmor 4.x: `abi <abi/4.0>,` (adds userns and other 4.0 mediation).
+    V4,
+}
+
+impl Abi {
+    /// `abi <abi/X>,` line for the profile preamble, or None for legacy parsers.
+    fn header(self) -> Option<&'static str> {
+        match self {
+            Abi::Legacy => None,
+            Abi::V3 => Some("abi <abi/3.0>,"),
+            Abi::V4 => Some("abi <abi/4.0>,"),
+        }
+    }
+
+    /// Include directive: `#include` is deprecated from 3.0 on and warns on newer parsers.
+    fn include_keyword(self) -> &'static str {
+        match self {
+            Abi::Legacy => "#include",
+            Abi::V3 | Abi::V4 => "include",
+        }
+    }
+}
+
+/// Detect the newest policy ABI the host parser ships (checks /etc/apparmor.d/abi/{4.0,3.0}).
+pub fn detect_abi(paths: &Paths) -> Abi {
+    let dir = &paths.apparmor_abi_dir;
+    if dir.join("4.0").is_file() {
+        Abi::V4
+    } else if dir.join("3.0").is_file() {
+        Abi::V3
+    } else {
+        Abi::Legacy
+    }
+}
+
+/// Longest path a generated rule may name (PATH_MAX less the terminating NUL).
+pub const MAX_RULE_PATH_LEN: usize = 4095;
+
+/// Deepest directory level inside a bundle that listed bundle rules go down to.
+const MAX_BUNDLE_DEPTH: usize = 32;
+
+/// How a profile grants read/map access to the bundle's own files ([apparmor] bundle_rules).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BundleRules {
+    /// One `<bundle>/** rm,` rule: small, and covers files added later.
+    #[default]
+    Recursive,
+    /// `<dir>/* rm,` for every directory in the bundle: no `**`, new files in existing
+    /// directories are still covered.
+    Directories,
+    /// `<file> rm,` for every file in the bundle: the narrowest grant; anything added later
+    /// is denied until the next sync.
+    Files,
+}
+
+/// Host-dependent inputs to profile generation, resolved once per sync from site settings.
+#[derive(Debug, Default, Clone)]
+pub struct ProfileOptions {
+    pub abi: Abi,
+    /// Site base policy merged into every profile (and helper child profile).
+    pub base_policy: Vec<crate::policy::Fragment>,
+    pub bundle_rules: BundleRules,
+    /// Most rules a listed strategy may emit before falling back to `Recursive`; 0 is no limit.
+    pub max_bundle_rules: usize,
+    /// Site templates: the profile snippets go into every app's profile (not helpers'); sync
+    /// takes the menu-entry snippets from here too.
+    pub templates: crate::templates::Templates,
+    /// GPU driver libraries outside /usr/lib on this host, mapped into `gpu = true` profiles.
+    pub gpu: crate::gpu::Layout,
+}
+
+impl ProfileOptions {
+    /// Resolve options from site settings (abi = "auto" probes the host; base policy is read
+    /// from the configured policy directory).
+    pub fn from_settings(settings: &crate::settings::Settings, paths: &Paths) -> Result<Self> {
+        let abi = match settings.apparmor.abi {
+            crate::settings::AbiSetting::Auto => detect_abi(paths),
+            crate::settings::AbiSetting::Fixed(abi) => abi,
+        };
+        let base_policy = crate::policy::load_base_policy(&settings.apparmor.policy_dir)?;
+        Ok(Self {
+            abi,
+            base_policy,
+            bundle_rules: settings.apparmor.bundle_rules,
+            max_bundle_rules: settings.apparmor.max_bundle_rules,
+            templates: crate::templates::load(&settings.templates.dir)?,
+            gpu: crate::gpu::detect(settings.paths.root.as_deref().unwrap_or(Path::new("/"))),
+        })
+    }
+
+    /// Append site base policy lines at the given indent, each fragment tagged with its source.
+    fn push_base_policy(&self, rules: &mut Vec<String>, indent: &str) {
+        for fragment in &self.base_policy {
+            rules.push(format!("{}# site policy: {}", indent, fragment.source));
+            for line in &fragment.rules {
+                rules.push(format!("{}{}", indent, line));
+            }
+        }
+    }
+}
+
+/// Child profile name for a helper: sanitized file name of its path (e.g. bin/ffmpeg -> ffmpeg).
+pub fn helper_profile_name(helper_path: &str) -> String {
+    let file_name = Path::new(helper_path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(helper_path);
+    sanitize_profile_segment(file_name)
+}
+
+/// Expand `@{BUNDLE}` (the bundle root) in a security path. `@{HOME}` and other AppArmor
+/// variables are left for the parser.
+pub fn expand_bundle_var(p: &str, bundle_root: &Path) -> String {
+    p.replace("@{BUNDLE}", &bundle_root.display().to_string())
+}
+
+/// Append read (r), read/write (rw) and inherit-exec (ix) file rules, skipping paths that
+/// sanitize to nothing.
+fn push_path_rules(
+    rules: &mut Vec<String>,
+    indent: &str,
+    bundle_root: &Path,
+    paths: &[(&[String], &str)],
+) {
+    for (list, perms) in paths {
+        for p in list.iter() {
+            let safe = sanitize_apparmor_path(&expand_bundle_var(p, bundle_root));
+            if !safe.is_empty() {
+                rules.push(format!(
+                    "{}{} {},",
+                    indent,
+                    quote_path_for_apparmor(&safe),
+                    perms
+                ));
+            }
+        }
+    }
 }
 
-/// Safe profile name for user tier (use when name may not have been validated).
-pub fn profile_name_safe(username: &str, app_name: &str) -> String {
-    profile_name_user(username, app_name)
+/// Rules for an ephemeral_home app: its scratch homes in the runtime dir (see `ephemeral`) and
+/// the persisted paths of the real home they link to.
+fn push_ephemeral_home_rules(
+    rules: &mut Vec<String>,
+    indent: &str,
+    app_id: &str,
+    persist: &[String],
+) {
+    rules.push(format!("{}# ephemeral home", indent));
+    let scratch = format!("/run/user/*/{}/{}.*", crate::ephemeral::DIR_NAME, app_id);
+    rules.push(format!(
+        "{}owner {} rw,",
+        indent,
+        quote_path_for_apparmor(&format!("{}/", scratch))
+    ));
+    rules.push(format!(
+        "{}owner {} rwk,",
+        indent,
+        quote_path_for_apparmor(&format!("{}/**", scratch))
+    ));
+    for p in persist {
+        let safe = sanitize_apparmor_path(p.trim_end_matches('/'));
+        if safe.is_empty() {
+            continue;
+        }
+        for path in [
+            format!("@{{HOME}}/{}", safe),
+            format!("@{{HOME}}/{}/**", safe),
+        ] {
+            rules.push(format!(
+                "{}owner {} rwk,",
+                indent,
+                quote_path_for_apparmor(&path)
+            ));
+        }
+    }
 }
 
-/// Safe profile name for system tier.
-pub fn profile_name_safe_system(app_name: &str) -> String {
-    profile_name_system(app_name)
+/// Rules behind the coarse [security] toggles (gpu, audio, portals, v4l, camera,
+/// screen_capture, printing, removable_media, ssh_agent, secrets, accessibility).
+fn push_preset_rules(rules: &mut Vec<String>, indent: &str, sec: &crate::config::Security) {
+    let mut lines: Vec<&str> = Vec::new();
+    // Portal interfaces the presets call; the bus rules for them are added once, at the end.
+    let mut portals: Vec<&str> = Vec::new();
+    if sec.gpu {
+        lines.extend([
+            "# gpu",
+            "/dev/dri/ r,",
+            "/dev/dri/** rw,",
+            "/sys/devices/** r,",
+            "/sys/bus/pci/devices/ r,",
+            "/sys/dev/char/ r,",
+            "/usr/share/drirc.d/** r,",
+            "/usr/share/glvnd/** r,",
+            "/usr/share/vulkan/** r,",
+            "/etc/vulkan/** r,",
+            "owner @{HOME}/.cache/mesa_shader_cache/** rwk,",
+        ]);
+        lines.extend(crate::gpu::NVIDIA_RULES);
+    }
+    if sec.audio {
+        lines.extend([
+            "# audio",
+            "/dev/snd/ r,",
+            "/dev/snd/* rw,",
+            "owner /run/user/*/pulse/ r,",
+            "owner /run/user/*/pulse/native rw,",
+            "owner /run/user/*/pipewire-0 rw,",
+            "/etc/pulse/** r,",
+            "/etc/alsa/** r,",
+            "/usr/share/alsa/** r,",
+        ]);
+    }
+    if sec.portals {
+        lines.extend([
+            "# portals",
+            "owner /run/user/*/doc/ r,",
+            "owner /run/user/*/doc/** rw,",
+        ]);
+        portals.push("org.freedesktop.portal.*");
+    }
+    if sec.v4l || sec.camera {
+        lines.extend([
+            "# v4l",
+            "/dev/ r,",
+            "/dev/video[0-9]* rw,",
+            "/dev/media[0-9]* rw,",
+            "/dev/v4l/** r,",
+            "/sys/class/video4linux/ r,",
+            "/sys/devices/** r,",
+            "/run/udev/data/c81:* r,",
+        ]);
+    }
+    if sec.camera {
+        lines.extend(["# camera", "owner /run/user/*/pipewire-0 rw,"]);
+        portals.push("org.freedesktop.portal.Camera");
+    }
+    if sec.screen_capture {
+        lines.extend(["# screen_capture", "owner /run/user/*/pipewire-0 rw,"]);
+        portals.push("org.freedesktop.portal.ScreenCast");
+    }
+    if sec.printing {
+        lines.extend([
+            "# printing",
+            "/{,var/}run/cups/cups.sock rw,",
+            "/etc/cups/client.conf r,",
+            "/etc/cups/lpoptions r,",
+            "owner @{HOME}/.cups/ r,",
+            "owner @{HOME}/.cups/** r,",
+            "/etc/papersize r,",
+            "/usr/share/cups/** r,",
+            "dbus receive bus=system interface=org.cups.cupsd.Notifier,",
+        ]);
+        portals.push("org.freedesktop.portal.Print");
+    }
+    if sec.removable_media {
+        lines.extend([
+            "# removable_media",
+            "/media/ r,",
+            "/media/** r,",
+            "/run/media/ r,",
+            "/run/media/*/ r,",
+            "/run/media/*/** r,",
+            "owner /run/user/*/gvfs/ r,",
+            "owner /run/user/*/gvfs/** r,",
+        ]);
+    }
+    if sec.ssh_agent {
+        // Where agents put SSH_AUTH_SOCK: OpenSSH, systemd's ssh-agent unit, GNOME Keyring/gcr
+        // and gpg-agent.
+        lines.extend([
+            "# ssh_agent",
+            "owner /tmp/ssh-*/agent.* rw,",
+            "owner /run/user/*/ssh-agent.socket rw,",
+            "owner /run/user/*/openssh_agent rw,",
+            "owner /run/user/*/keyring/ssh rw,",
+            "owner /run/user/*/gcr/ssh rw,",
+            "owner /run/user/*/gnupg/S.gpg-agent.ssh rw,",
+        ]);
+    }
+    if sec.secrets {
+        lines.extend([
+            "# secrets",
+            "dbus send bus=session path=/org/freedesktop/secrets{,/**} \
+             interface={org.freedesktop.Secret.*,org.freedesktop.DBus.Properties} \
+             peer=(name=org.freedesktop.secrets),",
+            "dbus receive bus=session path=/org/freedesktop/secrets{,/**} interface=org.freedesktop.Secret.*,",
+        ]);
+    }
+    if sec.accessibility {
+        // The session bus hands out the accessibility bus's address: a socket in the runtime
+        // dir (at-spi2-core 2.46+), in ~/.cache/at-spi, or an abstract one on older hosts.
+        lines.extend([
+            "# accessibility",
+            "owner /run/user/*/at-spi/ r,",
+            "owner /run/user/*/at-spi/bus* rw,",
+            "owner @{HOME}/.cache/at-spi/ r,",
+            "owner @{HOME}/.cache/at-spi/bus* rw,",
+            "unix (connect, send, receive) type=stream peer=(addr=\"@/tmp/dbus-*\"),",
+            "dbus send bus=session path=/org/a11y/bus \
+             interface={org.a11y.Bus,org.freedesktop.DBus.Properties} peer=(name=org.a11y.Bus),",
+            "dbus (send, receive) bus=accessibility,",
+        ]);
+    }
+    if !portals.is_empty() {
+        // Specific portals also need the request and session objects their calls return.
+        if !sec.portals {
+            portals.extend([
+                "org.freedesktop.portal.Request",
+                "org.freedesktop.portal.Session",
+            ]);
+        }
+        portals.push("org.freedesktop.DBus.Properties");
+        let interfaces = portals.join(",");
+        lines.push("# desktop portals");
+        rules.extend(lines.drain(..).map(|l| format!("{}{}", indent, l)));
+        rules.push(format!(
+            "{}dbus send bus=session path=/org/freedesktop/portal/desktop interface={{{}}} \
+             peer=(name=org.freedesktop.portal.Desktop),",
+            indent, interfaces
+        ));
+        rules.push(format!(
+            "{}dbus receive bus=session path=/org/freedesktop/portal/desktop{{,/**}} interface={{{}}},",
+            indent, interfaces
+        ));
+    }
+    if !portals.is_empty() || sec.secrets || sec.accessibility {
+        lines.extend([
+            "# session bus",
+            "owner /run/user/*/bus rw,",
+            "dbus send bus=session path=/org/freedesktop/DBus interface=org.freedesktop.DBus \
+             member={Hello,AddMatch,RemoveMatch,GetNameOwner,NameHasOwner,StartServiceByName} \
+             peer=(name=org.freedesktop.DBus),",
+        ]);
+    }
+    rules.extend(lines.into_iter().map(|l| format!("{}{}", indent, l)));
 }
 
 /// Generate AppArmor profile text from config (bundle path + security section).
 /// `profile_name` is either dotlnx-<username>-<name> (user) or dotlnx-<name> (system).
 /// Only used when [security] confine = true; when false, no profile is loaded.
-pub fn generate_profile(bundle_root: &Path, config: &Config, profile_name: &str) -> String {
-    generate_profile_minimal(bundle_root, config, profile_name)
+pub fn generate_profile(
+    bundle_root: &Path,
+    config: &Config,
+    profile_name: &str,
+    opts: &ProfileOptions,
+) -> String {
+    generate_profile_minimal(bundle_root, config, profile_name, opts)
 }
 
 fn generate_profile_minimal(
     bundle_root: &Path,
     config: &Config,
     profile_name: &str,
+    opts: &ProfileOptions,
 ) -> String {
-    let bundle_path = bundle_root.display().to_string();
     let exec_path = bundle_root.join(&config.executable);
     let exec_path_str = exec_path.display().to_string();
 
     let mut rules = Vec::new();
     rules.push(format!("  {} ix,", quote_path_for_apparmor(&exec_path_str)));
     // rm: read + memory-map executable (needed for loading .so from bundle)
-    rules.push(format!(
-        "  {} rm,",
-        quote_path_for_apparmor(&format!("{}/**", bundle_path))
-    ));
+    push_bundle_rules(&mut rules, bundle_root, &config.exclude, opts);
 
     if let Some(ref sec) = config.security {
-        for p in &sec.read_paths {
-            let safe = sanitize_apparmor_path(p);
-            if !safe.is_empty() {
-                rules.push(format!("  {} r,", quote_path_for_apparmor(&safe)));
-            }
+        push_path_rules(
+            &mut rules,
+            "  ",
+            bundle_root,
+            &[
+                (&sec.read_paths, "r"),
+                (&sec.write_paths, "rw"),
+                (&sec.exec_paths, "ix"),
+            ],
+        );
+        push_preset_rules(&mut rules, "  ", sec);
+        if sec.gpu {
+            rules.extend(opts.gpu.rules("  "));
         }
-        for p in &sec.write_paths {
-            let safe = sanitize_apparmor_path(p);
-            if !safe.is_empty() {
-                rules.push(format!("  {} rw,", quote_path_for_apparmor(&safe)));
-            }
+        if sec.ephemeral_home {
+            push_ephemeral_home_rules(&mut rules, "  ", &config.app_id(), &sec.persist_paths);
         }
         if sec.network {
             rules.push("  network inet stream,".to_string());
             rules.push("  network inet6 stream,".to_string());
         }
+        // userns mediation only exists from ABI 4.0; older parsers reject the rule and older
+        // kernels do not restrict unprivileged namespaces in the first place.
+        if sec.userns != Userns::Deny && opts.abi >= Abi::V4 {
+            rules.push("  userns,".to_string());
+        }
+        if sec.userns == Userns::Allow {
+            // Chromium's namespace sandbox needs these inside its new user namespace.
+            rules.push("  capability sys_admin,".to_string());
+            rules.push("  capability sys_chroot,".to_string());
+        }
+    }
+    // A socket-activated service accepts on the Unix sockets systemd passes it (IP sockets need
+    // network, which validate enforces).
+    if config
+        .service
+        .as_ref()
+        .is_some_and(|s| s.listen.iter().any(|l| !l.is_inet()))
+    {
+        rules.push(
+            "  unix (accept, receive, send, getattr, getopt, setopt, shutdown) type=stream,"
+                .to_string(),
+        );
     }
 
     // Minimal system: libs, proc (read), config/data dirs, tmp, shm
@@ -139,23 +529,407 @@ fn generate_profile_minimal(
     rules.push("  owner @{HOME}/.local/share/** rw,".to_string());
     rules.push("  /tmp/** rw,".to_string());
     rules.push("  /dev/shm/** rw,".to_string());
+    opts.push_base_policy(&mut rules, "  ");
+    let vars = crate::templates::Vars {
+        name: &config.name,
+        bundle: bundle_root,
+        profile: Some(profile_name),
+    };
+    rules.extend(opts.templates.profile_lines(&vars, "  "));
+
+    // Helpers run under their own child profile (cx), so their rules do not widen the parent.
+    if let Some(ref sec) = config.security {
+        for helper in &sec.helpers {
+            let helper_path = bundle_root.join(&helper.path).display().to_string();
+            let child = helper_profile_name(&helper.path);
+            rules.push(format!(
+                "  {} cx -> {},",
+                quote_path_for_apparmor(&helper_path),
+                child
+            ));
+            rules.push(String::new());
+            rules.push(format!("  profile {} {{", child));
+            rules.push(format!(
+                "    {} <abstractions/base>",
+                opts.abi.include_keyword()
+            ));
+            rules.push(format!("    {} rm,", quote_path_for_apparmor(&helper_path)));
+            rules.push("    /usr/lib/** rm,".to_string());
+            rules.push("    /lib/** rm,".to_string());
+            push_path_rules(
+                &mut rules,
+                "    ",
+                bundle_root,
+                &[(&helper.read_paths, "r"), (&helper.write_paths, "rw")],
+            );
+            if helper.network {
+                rules.push("    network inet stream,".to_string());
+                rules.push("    network inet6 stream,".to_string());
+            }
+            opts.push_base_policy(&mut rules, "    ");
+            rules.push("  }".to_string());
+        }
+    }
 
     let rules_text = rules.join("\n");
+    let include = opts.abi.include_keyword();
+    let flags = match config.security.as_ref().map(|s| s.userns) {
+        Some(Userns::Unconfined) => " flags=(unconfined)",
+        _ => "",
+    };
+    let abi_line = opts
+        .abi
+        .header()
+        .map(|h| format!("{}\n", h))
+        .unwrap_or_default();
     format!(
         "# dotlnx generated profile for {}\n\
-         #include <tunables/global>\n\
-         profile {} {{\n\
-         #include <abstractions/base>\n\
+         {}\
+         {} <tunables/global>\n\
+         profile {}{} {{\n\
+         {} <abstractions/base>\n\
          {}\n\
          }}\n",
-        config.name, profile_name, rules_text
+        config.name, abi_line, include, profile_name, flags, include, rules_text
     )
 }
 
+/// Rules granting read/map access to the bundle, per `opts.bundle_rules`. A bundle a listed
+/// strategy cannot express (too many rules, too deep, paths too long or with pattern characters)
+/// gets the recursive rule instead, with a comment saying why.
+fn push_bundle_rules(
+    rules: &mut Vec<String>,
+    bundle_root: &Path,
+    exclude: &[String],
+    opts: &ProfileOptions,
+) {
+    let recursive = format!(
+        "  {} rm,",
+        quote_path_for_apparmor(&format!("{}/**", bundle_root.display()))
+    );
+    if opts.bundle_rules == BundleRules::Recursive {
+        rules.push(recursive);
+        return;
+    }
+    match listed_bundle_rules(
+        bundle_root,
+        exclude,
+        opts.bundle_rules,
+        opts.max_bundle_rules,
+    ) {
+        Ok(listed) => rules.extend(listed),
+        Err(why) => {
+            rules.push(format!(
+                "  # {}; granting the whole bundle",
+                sanitize_apparmor_path(&why)
+            ));
+            rules.push(recursive);
+        }
+    }
+}
+
+/// One rule per directory (and, for `Files`, per file) of the bundle, in path order. Symlinks
+/// are not followed: AppArmor checks the resolved path, which needs its own rule anyway.
+/// Excluded paths (the app's own cache or logs) get no rule; write_paths cover them.
+fn listed_bundle_rules(
+    bundle_root: &Path,
+    exclude: &[String],
+    strategy: BundleRules,
+    max_rules: usize,
+) -> std::result::Result<Vec<String>, String> {
+    let mut out = Vec::new();
+    let walker = walkdir::WalkDir::new(bundle_root)
+        .follow_links(false)
+        .max_depth(MAX_BUNDLE_DEPTH + 1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| {
+            let relative = e.path().strip_prefix(bundle_root).unwrap_or(e.path());
+            !crate::bundle::is_excluded(exclude, relative)
+        });
+    for entry in walker {
+        let entry = entry.map_err(|e| format!("cannot list bundle: {}", e))?;
+        if entry.depth() > MAX_BUNDLE_DEPTH {
+            return Err(format!("bundle is deeper than {} levels", MAX_BUNDLE_DEPTH));
+        }
+        let path = entry.path().display().to_string();
+        if path.len() > MAX_RULE_PATH_LEN {
+            return Err(format!(
+                "a bundle path is longer than {} bytes",
+                MAX_RULE_PATH_LEN
+            ));
+        }
+        let relative = entry
+            .path()
+            .strip_prefix(bundle_root)
+            .unwrap_or(entry.path());
+        if relative
+            .to_string_lossy()
+            .contains(['*', '?', '[', ']', '{', '}', '^', '@', '\\'])
+        {
+            return Err(format!(
+                "{} contains AppArmor pattern characters",
+                relative.display()
+            ));
+        }
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            out.push(format!(
+                "  {} r,",
+                quote_path_for_apparmor(&format!("{}/", path))
+            ));
+            if strategy == BundleRules::Directories {
+                out.push(format!(
+                    "  {} rm,",
+                    quote_path_for_apparmor(&format!("{}/*", path))
+                ));
+            }
+        } else if file_type.is_file() && strategy == BundleRules::Files {
+            out.push(format!("  {} rm,", quote_path_for_apparmor(&path)));
+        }
+        if max_rules != 0 && out.len() > max_rules {
+            return Err(format!("bundle needs more than {} rules", max_rules));
+        }
+    }
+    Ok(out)
+}
+
+/// True if AppArmor is available for use: aa-exec is on PATH so the generated .desktop Exec= line would work.
+/// When false, sync must not put aa-exec in .desktop files (use plain executable path instead).
+pub fn is_available() -> bool {
+    !matches!(
+        std::process::Command::new("aa-exec").arg("--help").status(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound
+    )
+}
+
+/// Hidden subdirectory of the profile dir holding a lock and a metadata file per profile.
+/// apparmor_parser skips dotfiles when it loads a directory, so nothing here is read as policy.
+const META_DIR: &str = ".dotlnx";
+
+/// Who last wrote a generated profile. Several dotlnx processes can manage the same profile
+/// directory (the service, `sudo dotlnx sync`, `dotlnx uninstall`); this is what lets each decide
+/// whether a profile file is theirs to replace or remove.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfileMeta {
+    pub app: String,
+    /// "system" or "user:<name>".
+    pub tier: String,
+    /// Bundle the profile was generated from.
+    pub bundle: PathBuf,
+    /// Checksum of the profile as written; a mismatch means it was edited outside dotlnx.
+    pub hash: String,
+    /// Unix time of the write.
+    pub written: u64,
+    /// Checksum of the profile as last loaded into the kernel; None until a load succeeds.
+    #[serde(default)]
+    pub loaded: Option<String>,
+}
+
+impl ProfileMeta {
+    /// True when the profile was written for a different bundle than `bundle` and `live` says
+    /// that bundle is still installed, so it is not `bundle`'s to replace or remove.
+    pub fn belongs_elsewhere(&self, bundle: &Path, live: impl Fn(&Path) -> bool) -> bool {
+        self.bundle != bundle && live(&self.bundle)
+    }
+}
+
+/// The bundle a profile is being written for.
+pub struct ProfileOwner<'a> {
+    pub app: &'a str,
+    pub tier: String,
+    pub bundle: &'a Path,
+}
+
+fn profile_hash(content: &str) -> String {
+    format!("crc32:{:08x}", crc32fast::hash(content.as_bytes()))
+}
+
+fn meta_path(paths: &Paths, profile_name: &str) -> PathBuf {
+    paths
+        .apparmor_dir
+        .join(META_DIR)
+        .join(format!("{}.json", profile_name))
+}
+
+/// Metadata of an installed profile, None when missing or unreadable (written by an older dotlnx).
+pub fn profile_meta(paths: &Paths, profile_name: &str) -> Option<ProfileMeta> {
+    let text = std::fs::read_to_string(meta_path(paths, profile_name)).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+/// Write `content` to `path` through a hidden temp file and a rename, so neither another dotlnx
+/// nor apparmor_parser ever sees it half-written.
+fn write_replace(path: &Path, content: &str) -> Result<()> {
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    let tmp = path.with_file_name(format!(".{}.tmp", name));
+    std::fs::write(&tmp, content)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Exclusive hold on one profile (its file, its metadata and loading it), for as long as this
+/// lives. Other dotlnx processes block in [`lock_profile`] until it is dropped.
+pub struct ProfileLock<'a> {
+    paths: &'a Paths,
+    name: String,
+    _lock: Flock<std::fs::File>,
+}
+
+/// Lock `profile_name` in the profile directory, waiting for any other holder.
+pub fn lock_profile<'a>(paths: &'a Paths, profile_name: &str) -> Result<ProfileLock<'a>> {
+    let dir = paths.apparmor_dir.join(META_DIR);
+    std::fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+    let lock_path = dir.join(format!("{}.lock", profile_name));
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .with_context(|| format!("open {}", lock_path.display()))?;
+    let lock = Flock::lock(file, FlockArg::LockExclusive)
+        .map_err(|(_, e)| anyhow::anyhow!("lock {}: {}", lock_path.display(), e))?;
+    Ok(ProfileLock {
+        paths,
+        name: profile_name.to_string(),
+        _lock: lock,
+    })
+}
+
+impl ProfileLock<'_> {
+    pub fn meta(&self) -> Option<ProfileMeta> {
+        profile_meta(self.paths, &self.name)
+    }
+
+    /// True when the profile file no longer matches what dotlnx last wrote.
+    pub fn modified_outside(&self) -> bool {
+        match (self.meta(), installed_profile(self.paths, &self.name)) {
+            (Some(meta), Some(content)) => profile_hash(&content) != meta.hash,
+            _ => false,
+        }
+    }
+
+    /// Write the profile (only when its contents change) and its metadata, then apparmor_parser -r,
+    /// writing the parser's cache so later loads (and boot) skip compiling it again.
+    /// A profile that fails to load is removed again unless an earlier version was installed.
+    pub fn load(&self, profile_content: &str, owner: &ProfileOwner) -> Result<()> {
+        let parser = self.paths.apparmor_parser()?;
+        let path = self.paths.profile_path(&self.name);
+        let existed = path.exists();
+        let recorded = self.meta();
+        let meta = ProfileMeta {
+            app: owner.app.to_string(),
+            tier: owner.tier.clone(),
+            bundle: owner.bundle.to_path_buf(),
+            hash: profile_hash(profile_content),
+            written: crate::state::now(),
+            loaded: recorded.as_ref().and_then(|m| m.loaded.clone()),
+        };
+        if installed_profile(self.paths, &self.name).as_deref() != Some(profile_content) {
+            write_replace(&path, profile_content)?;
+        }
+        self.record(recorded.as_ref(), &meta)?;
+        let mut parse = std::process::Command::new(parser);
+        parse.args(["-r", "--write-cache"]);
+        if let Some(cache) = &self.paths.apparmor_cache {
+            parse.arg("--cache-loc").arg(cache);
+        }
+        let out = parse.arg(&path).output()?;
+        if !out.status.success() {
+            if !existed {
+                let _ = std::fs::remove_file(&path);
+                let _ = std::fs::remove_file(meta_path(self.paths, &self.name));
+            }
+            anyhow::bail!(
+                "apparmor_parser -r failed: {}",
+                String::from_utf8_lossy(&out.stderr)
+            );
+        }
+        let loaded = ProfileMeta {
+            loaded: Some(meta.hash.clone()),
+            ..meta.clone()
+        };
+        self.record(Some(&meta), &loaded)
+    }
+
+    /// Write `meta` unless `recorded` already says the same (the write time aside).
+    fn record(&self, recorded: Option<&ProfileMeta>, meta: &ProfileMeta) -> Result<()> {
+        if recorded
+            .map(|m| ProfileMeta {
+                written: meta.written,
+                ..m.clone()
+            })
+            .as_ref()
+            != Some(meta)
+        {
+            write_replace(
+                &meta_path(self.paths, &self.name),
+                &serde_json::to_string_pretty(meta)?,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// apparmor_parser -R, then remove the profile and its metadata.
+    pub fn unload(&self) -> Result<()> {
+        let path = self.paths.profile_path(&self.name);
+        let meta = meta_path(self.paths, &self.name);
+        if path.exists() {
+            let parser = self.paths.apparmor_parser()?;
+            // A failure usually means the profile was already unloaded; remove the file anyway.
+            let _ = std::process::Command::new(parser)
+                .args(["-R", path.to_str().unwrap_or_default()])
+                .output()?;
+            std::fs::remove_file(&path)?;
+        }
+        match std::fs::remove_file(&meta) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// True when `content` is what dotlnx last loaded as `profile_name` and the kernel still has it,
+/// so loading it again would change nothing (see [`ProfileMeta::loaded`]).
+pub fn is_current(paths: &Paths, profile_name: &str, content: &str) -> bool {
+    profile_meta(paths, profile_name).and_then(|m| m.loaded) == Some(profile_hash(content))
+        && is_loaded(paths, profile_name)
+}
+
+/// True when the kernel has `profile_name` loaded; false when that cannot be read (not root,
+/// AppArmor off), so callers load it to be safe.
+pub fn is_loaded(paths: &Paths, profile_name: &str) -> bool {
+    std::fs::read_to_string(&paths.loaded_profiles)
+        .is_ok_and(|list| profile_listed(&list, profile_name))
+}
+
+fn profile_listed(list: &str, profile_name: &str) -> bool {
+    list.lines().any(|l| {
+        l.rsplit_once(" (")
+            .is_some_and(|(name, _)| name == profile_name)
+    })
+}
+
+/// Contents of an installed profile, None when dotlnx has not written it.
+pub fn installed_profile(paths: &Paths, profile_name: &str) -> Option<String> {
+    std::fs::read_to_string(paths.profile_path(profile_name)).ok()
+}
+
+/// Unload/remove a profile (apparmor_parser -R, then remove file) under its lock. May require root.
+pub fn unload_profile(paths: &Paths, profile_name: &str) -> Result<()> {
+    if !paths.profile_path(profile_name).exists() && !meta_path(paths, profile_name).exists() {
+        return Ok(());
+    }
+    lock_profile(paths, profile_name)?.unload()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{Config, Security};
+    use crate::config::{Config, Helper, Security};
 
     fn minimal_config() -> Config {
         Config {
@@ -163,19 +937,40 @@ mod tests {
             executable: "bin/myapp".into(),
             args: vec![],
             env: vec![],
+            site_env: vec![],
             working_dir: None,
             icon: None,
             comment: None,
             categories: None,
+            generic_name: None,
+            keywords: vec![],
+            translations: Default::default(),
+            allowed_users: vec![],
+            allowed_groups: vec![],
             security: None,
+            kind: Default::default(),
+            service: None,
+            schedule: None,
+            thumbnailers: Vec::new(),
+            search_provider: None,
+            update: None,
+            accessibility: None,
+            keep_versions: None,
+            data_dirs: vec![],
+            vendor: None,
+            exclude: Vec::new(),
+            arch: vec![],
             terminal: false,
+            umask: None,
+            nice: None,
+            ionice: None,
         }
     }
 
     #[test]
     fn profile_name_user_format() {
         assert_eq!(
-            profile_name_user("alice", "myapp"),
+            profile_name_user("dotlnx-", "alice", "myapp"),
             "dotlnx-alice-myapp"
         );
     }
@@ -183,23 +978,202 @@ mod tests {
     #[test]
     fn profile_name_user_sanitizes() {
         assert_eq!(
-            profile_name_user("user@host", "app.name"),
-            "dotlnx-user_host-app_name"
+            profile_name_user("dotlnx-", "user@host", "app.name"),
+            format!(
+                "dotlnx-user_host.{:08x}-app_name.{:08x}",
+                crc32fast::hash(b"user@host"),
+                crc32fast::hash(b"app.name")
+            )
         );
     }
 
     #[test]
     fn profile_name_user_spaces_become_underscores() {
         // Names with spaces (e.g. "hello-world 2") must produce a single profile name used by both sync and run.
+        let name = profile_name_user("dotlnx-", "kevin", "hello-world 2");
+        assert!(name.starts_with("dotlnx-kevin-hello-world_2."), "{}", name);
+        assert_eq!(name, profile_name_user("dotlnx-", "kevin", "hello-world 2"));
+    }
+
+    #[test]
+    fn sanitized_names_do_not_collide() {
+        let names = ["my app", "my_app", "my.app", "my?app", "my app "];
+        let profiles: std::collections::HashSet<String> = names
+            .iter()
+            .map(|n| profile_name_system("dotlnx-", n))
+            .collect();
+        assert_eq!(profiles.len(), names.len(), "{:?}", profiles);
+        assert_eq!(profile_name_system("dotlnx-", "my_app"), "dotlnx-my_app");
+        assert_eq!(legacy_profile_name("dotlnx-", None, "my_app"), None);
+        assert_eq!(
+            legacy_profile_name("dotlnx-", None, "my app").as_deref(),
+            Some("dotlnx-my_app")
+        );
         assert_eq!(
-            profile_name_user("kevin", "hello-world 2"),
-            "dotlnx-kevin-hello-world_2"
+            legacy_profile_name("dotlnx-", Some("kevin"), "hello world").as_deref(),
+            Some("dotlnx-kevin-hello_world")
+        );
+    }
+
+    fn bundle_with_files(n: usize) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("bin")).unwrap();
+        std::fs::create_dir_all(dir.path().join("lib")).unwrap();
+        std::fs::write(dir.path().join("bin/myapp"), "").unwrap();
+        for i in 0..n {
+            std::fs::write(dir.path().join(format!("lib/lib{}.so", i)), "").unwrap();
+        }
+        dir
+    }
+
+    fn opts_with(bundle_rules: BundleRules, max_bundle_rules: usize) -> ProfileOptions {
+        ProfileOptions {
+            bundle_rules,
+            max_bundle_rules,
+            ..ProfileOptions::default()
+        }
+    }
+
+    #[test]
+    fn directory_rules_list_each_directory() {
+        let dir = bundle_with_files(2);
+        let b = dir.path().display();
+        let out = generate_profile(
+            dir.path(),
+            &minimal_config(),
+            "p",
+            &opts_with(BundleRules::Directories, 0),
+        );
+        assert!(
+            out.contains(&format!("  {}/ r,\n  {}/* rm,\n", b, b)),
+            "{}",
+            out
+        );
+        assert!(out.contains(&format!("  {}/lib/* rm,\n", b)));
+        assert!(!out.contains(&format!("{}/** rm,", b)));
+        assert!(!out.contains("lib0.so"));
+    }
+
+    #[test]
+    fn file_rules_list_each_file() {
+        let dir = bundle_with_files(2);
+        let b = dir.path().display();
+        let out = generate_profile(
+            dir.path(),
+            &minimal_config(),
+            "p",
+            &opts_with(BundleRules::Files, 0),
+        );
+        assert!(
+            out.contains(&format!(
+                "  {}/lib/ r,\n  {}/lib/lib0.so rm,\n  {}/lib/lib1.so rm,\n",
+                b, b, b
+            )),
+            "{}",
+            out
+        );
+        assert!(out.contains(&format!("  {}/bin/myapp rm,\n", b)));
+        assert!(!out.contains(&format!("{}/** rm,", b)));
+    }
+
+    #[test]
+    fn too_many_listed_rules_fall_back_to_recursive() {
+        let dir = bundle_with_files(2000);
+        let b = dir.path().display();
+        let limited = generate_profile(
+            dir.path(),
+            &minimal_config(),
+            "p",
+            &opts_with(BundleRules::Files, 1000),
+        );
+        assert!(limited.contains("# bundle needs more than 1000 rules; granting the whole bundle"));
+        assert!(limited.contains(&format!("  {}/** rm,", b)));
+        assert!(!limited.contains("lib0.so"));
+        // The fallback keeps the profile as small as the default one.
+        let recursive = generate_profile(
+            dir.path(),
+            &minimal_config(),
+            "p",
+            &ProfileOptions::default(),
+        );
+        assert!(
+            limited.len() < recursive.len() + 100,
+            "{} vs {}",
+            limited.len(),
+            recursive.len()
+        );
+
+        // Without a limit every file gets a rule, and the profile grows with the bundle.
+        let unlimited = generate_profile(
+            dir.path(),
+            &minimal_config(),
+            "p",
+            &opts_with(BundleRules::Files, 0),
+        );
+        assert_eq!(
+            unlimited.matches(" rm,").count(),
+            recursive.matches(" rm,").count() + 2000
+        );
+    }
+
+    #[test]
+    fn unlistable_bundles_fall_back_to_recursive() {
+        let dir = bundle_with_files(0);
+        std::fs::write(dir.path().join("lib/lib[1].so"), "").unwrap();
+        let out = generate_profile(
+            dir.path(),
+            &minimal_config(),
+            "p",
+            &opts_with(BundleRules::Files, 0),
+        );
+        assert!(
+            out.contains("contains AppArmor pattern characters; granting the whole bundle"),
+            "{}",
+            out
+        );
+
+        let dir = bundle_with_files(0);
+        let mut deep = dir.path().to_path_buf();
+        for _ in 0..=MAX_BUNDLE_DEPTH {
+            deep.push("d");
+        }
+        std::fs::create_dir_all(&deep).unwrap();
+        let out = generate_profile(
+            dir.path(),
+            &minimal_config(),
+            "p",
+            &opts_with(BundleRules::Directories, 0),
+        );
+        assert!(
+            out.contains(&format!(
+                "# bundle is deeper than {} levels",
+                MAX_BUNDLE_DEPTH
+            )),
+            "{}",
+            out
+        );
+        assert!(out.contains(&format!("  {}/** rm,", dir.path().display())));
+
+        // Excluded paths are not listed, so they cannot force the fallback either.
+        let mut cfg = minimal_config();
+        cfg.exclude = vec!["d/**".into()];
+        let out = generate_profile(
+            dir.path(),
+            &cfg,
+            "p",
+            &opts_with(BundleRules::Directories, 0),
+        );
+        assert!(
+            !out.contains(&format!("{}/** rm,", dir.path().display())),
+            "{}",
+            out
         );
+        assert!(!out.contains(&format!("{}/d/", dir.path().display())));
     }
 
     #[test]
     fn profile_name_system_format() {
-        assert_eq!(profile_name_system("myapp"), "dotlnx-myapp");
+        assert_eq!(profile_name_system("dotlnx-", "myapp"), "dotlnx-myapp");
     }
 
     #[test]
@@ -207,7 +1181,7 @@ mod tests {
         let dir = tempfile::tempdir().unwrap();
         let bundle = dir.path();
         let cfg = minimal_config();
-        let out = generate_profile(bundle, &cfg, "dotlnx-myapp");
+        let out = generate_profile(bundle, &cfg, "dotlnx-myapp", &ProfileOptions::default());
         assert!(out.contains("profile dotlnx-myapp {"));
         assert!(out.contains("# dotlnx generated profile for myapp"));
         assert!(out.contains("ix,"));
@@ -224,14 +1198,127 @@ mod tests {
             read_paths: vec!["/tmp/read".into()],
             write_paths: vec!["/tmp/write".into()],
             network: true,
-            capabilities: vec![],
+            ..Security::default()
         });
-        let out = generate_profile(dir.path(), &cfg, "dotlnx-myapp");
+        let out = generate_profile(dir.path(), &cfg, "dotlnx-myapp", &ProfileOptions::default());
         assert!(out.contains("/tmp/read r,"));
         assert!(out.contains("/tmp/write rw,"));
         assert!(out.contains("network inet stream"));
     }
 
+    #[test]
+    fn generate_profile_accepts_on_unix_listen_sockets() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cfg = minimal_config();
+        cfg.service = Some(crate::config::Service {
+            listen: vec![crate::config::Listen::Path("/run/a.sock".into())],
+            ..Default::default()
+        });
+        let out = generate_profile(dir.path(), &cfg, "p", &ProfileOptions::default());
+        assert!(out.contains(
+            "  unix (accept, receive, send, getattr, getopt, setopt, shutdown) type=stream,"
+        ));
+        assert!(!generate_profile(
+            dir.path(),
+            &minimal_config(),
+            "p",
+            &ProfileOptions::default()
+        )
+        .contains("unix ("));
+    }
+
+    #[test]
+    fn generate_profile_presets_and_bundle_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cfg = minimal_config();
+        cfg.security = Some(Security {
+            write_paths: vec!["@{BUNDLE}/data/**".into()],
+            exec_paths: vec!["/usr/bin/wine*".into()],
+            gpu: true,
+            audio: true,
+            ..Security::default()
+        });
+        let out = generate_profile(dir.path(), &cfg, "dotlnx-myapp", &ProfileOptions::default());
+        assert!(out.contains(&format!("{}/data/** rw,", dir.path().display())));
+        assert!(out.contains("/usr/bin/wine* ix,"));
+        assert!(out.contains("/dev/dri/** rw,"));
+        assert!(out.contains("  /dev/nvidiactl rw,"));
+        assert!(out.contains("owner /run/user/*/pulse/native rw,"));
+        assert!(!out.contains("dbus "));
+        cfg.security.as_mut().unwrap().ephemeral_home = true;
+        cfg.security.as_mut().unwrap().persist_paths = vec![".config/My App/".into()];
+        let out = generate_profile(dir.path(), &cfg, "dotlnx-myapp", &ProfileOptions::default());
+        assert!(out.contains("  owner /run/user/*/dotlnx-home/myapp.*/** rwk,\n"));
+        assert!(out.contains("  owner \"@{HOME}/.config/My App/**\" rwk,\n"));
+        let sec = cfg.security.as_mut().unwrap();
+        (sec.ephemeral_home, sec.persist_paths) = (false, vec![]);
+        let gpu = crate::gpu::Layout {
+            lib_dirs: vec![PathBuf::from("/usr/lib64/nvidia")],
+            ..Default::default()
+        };
+        let opts = ProfileOptions {
+            gpu,
+            ..ProfileOptions::default()
+        };
+        assert!(generate_profile(dir.path(), &cfg, "dotlnx-myapp", &opts)
+            .contains("  /usr/lib64/nvidia/** rm,"));
+        assert!(
+            !generate_profile(dir.path(), &minimal_config(), "dotlnx-myapp", &opts)
+                .contains("nvidia")
+        );
+        cfg.security.as_mut().unwrap().portals = true;
+        let out = generate_profile(dir.path(), &cfg, "dotlnx-myapp", &ProfileOptions::default());
+        assert!(out.contains(
+            "  dbus send bus=session path=/org/freedesktop/portal/desktop \
+             interface={org.freedesktop.portal.*,org.freedesktop.DBus.Properties} \
+             peer=(name=org.freedesktop.portal.Desktop),\n"
+        ));
+        assert!(out.contains("  owner /run/user/*/doc/** rw,"));
+        let sec = cfg.security.as_mut().unwrap();
+        (sec.portals, sec.camera, sec.screen_capture) = (false, true, true);
+        let out = generate_profile(dir.path(), &cfg, "dotlnx-myapp", &ProfileOptions::default());
+        assert!(out.contains("  /dev/video[0-9]* rw,"));
+        assert!(out.contains(
+            "interface={org.freedesktop.portal.Camera,org.freedesktop.portal.ScreenCast,\
+             org.freedesktop.portal.Request,org.freedesktop.portal.Session,org.freedesktop.DBus.Properties} "
+        ));
+        assert!(!out.contains("/doc/"));
+        let sec = cfg.security.as_mut().unwrap();
+        (sec.camera, sec.screen_capture, sec.printing) = (false, false, true);
+        let out = generate_profile(dir.path(), &cfg, "dotlnx-myapp", &ProfileOptions::default());
+        assert!(out.contains("  /{,var/}run/cups/cups.sock rw,"));
+        assert!(
+            out.contains("interface={org.freedesktop.portal.Print,org.freedesktop.portal.Request,")
+        );
+        cfg.security.as_mut().unwrap().removable_media = true;
+        let out = generate_profile(dir.path(), &cfg, "dotlnx-myapp", &ProfileOptions::default());
+        assert!(out.contains("  /run/media/*/** r,\n  owner /run/user/*/gvfs/ r,"));
+        assert!(!out.contains("secrets"));
+        let sec = cfg.security.as_mut().unwrap();
+        (sec.printing, sec.ssh_agent, sec.secrets) = (false, true, true);
+        let out = generate_profile(dir.path(), &cfg, "dotlnx-myapp", &ProfileOptions::default());
+        assert!(out.contains("  owner /run/user/*/keyring/ssh rw,"));
+        assert!(out.contains("peer=(name=org.freedesktop.secrets),"));
+        assert!(out.contains("  owner /run/user/*/bus rw,"));
+        assert!(!out.contains("portal"));
+        assert!(!out.contains("a11y"));
+        let sec = cfg.security.as_mut().unwrap();
+        (sec.ssh_agent, sec.secrets, sec.accessibility) = (false, false, true);
+        let out = generate_profile(dir.path(), &cfg, "dotlnx-myapp", &ProfileOptions::default());
+        assert!(out.contains("  owner /run/user/*/at-spi/bus* rw,"));
+        assert!(
+            out.contains("peer=(name=org.a11y.Bus),\n  dbus (send, receive) bus=accessibility,")
+        );
+        assert!(out.contains("  owner /run/user/*/bus rw,"));
+        let plain = generate_profile(
+            dir.path(),
+            &minimal_config(),
+            "p",
+            &ProfileOptions::default(),
+        );
+        assert!(!plain.contains("/dev/dri"));
+    }
+
     #[test]
     fn generate_profile_skips_empty_sanitized_paths() {
         let dir = tempfile::tempdir().unwrap();
@@ -241,9 +1328,9 @@ mod tests {
             read_paths: vec!["###".into(), "/valid".into()],
             write_paths: vec![],
             network: false,
-            capabilities: vec![],
+            ..Security::default()
         });
-        let out = generate_profile(dir.path(), &cfg, "dotlnx-myapp");
+        let out = generate_profile(dir.path(), &cfg, "dotlnx-myapp", &ProfileOptions::default());
         assert!(out.contains("/valid r,"));
         assert!(!out.contains("r,\n  r,"));
     }
@@ -256,7 +1343,12 @@ mod tests {
         std::fs::create_dir_all(bundle_with_space.join("bin")).unwrap();
         std::fs::write(bundle_with_space.join("bin/myapp"), b"").unwrap();
         let cfg = minimal_config();
-        let out = generate_profile(&bundle_with_space, &cfg, "dotlnx-myapp");
+        let out = generate_profile(
+            &bundle_with_space,
+            &cfg,
+            "dotlnx-myapp",
+            &ProfileOptions::default(),
+        );
         assert!(
             out.contains("\"/") && out.contains("hello world") && out.contains("\" ix,"),
             "exec path with space should be quoted: {}",
@@ -267,72 +1359,371 @@ mod tests {
             "bundle path with space should be quoted"
         );
     }
-}
 
-/// Directory under which dotlnx stores generated profiles. Requires root to write.
-pub const DOTLNX_APPARMOR_DIR: &str = "/etc/apparmor.d/dotlnx.d";
+    #[test]
+    fn generate_profile_legacy_abi_uses_hash_include() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = generate_profile(
+            dir.path(),
+            &minimal_config(),
+            "dotlnx-myapp",
+            &ProfileOptions::default(),
+        );
+        assert!(!out.contains("abi <"));
+        assert!(out.contains("#include <tunables/global>"));
+        assert!(out.contains("#include <abstractions/base>"));
+    }
 
-/// True if AppArmor is available for use: aa-exec is on PATH so the generated .desktop Exec= line would work.
-/// When false, sync must not put aa-exec in .desktop files (use plain executable path instead).
-pub fn is_available() -> bool {
-    !matches!(
-        std::process::Command::new("aa-exec").arg("--help").status(),
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound
-    )
-}
+    #[test]
+    fn generate_profile_abi4_header_and_modern_include() {
+        let dir = tempfile::tempdir().unwrap();
+        let opts = ProfileOptions {
+            abi: Abi::V4,
+            ..ProfileOptions::default()
+        };
+        let out = generate_profile(dir.path(), &minimal_config(), "dotlnx-myapp", &opts);
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines[1], "abi <abi/4.0>,");
+        assert_eq!(lines[2], "include <tunables/global>");
+        assert!(out.contains("\ninclude <abstractions/base>\n"));
+        assert!(!out.contains("#include"));
+    }
 
-/// Load a profile (write to DOTLNX_APPARMOR_DIR, then apparmor_parser -r). Requires root when AppArmor is present.
-pub fn load_profile(profile_name: &str, profile_content: &str) -> Result<()> {
-    let parser = find_apparmor_parser().with_context(|| {
-        "apparmor_parser not found (checked /usr/sbin, /sbin, and PATH)"
-    })?;
-    let path = std::path::Path::new(DOTLNX_APPARMOR_DIR).join(profile_name);
-    if path.exists() {
-        std::fs::write(&path, profile_content)?;
-        let out = std::process::Command::new(&parser)
-            .args(["-r", path.to_str().unwrap_or_default()])
-            .output()?;
-        if !out.status.success() {
-            anyhow::bail!(
-                "apparmor_parser -r failed: {}",
-                String::from_utf8_lossy(&out.stderr)
-            );
+    #[test]
+    fn generate_profile_userns_allow_on_abi4() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cfg = minimal_config();
+        cfg.security = Some(Security {
+            userns: Userns::Allow,
+            ..Security::default()
+        });
+        let v4 = ProfileOptions {
+            abi: Abi::V4,
+            ..ProfileOptions::default()
+        };
+        let out = generate_profile(dir.path(), &cfg, "dotlnx-myapp", &v4);
+        assert!(out.contains("  userns,"));
+        assert!(out.contains("capability sys_admin,"));
+        assert!(out.contains("profile dotlnx-myapp {"));
+        // Older ABI has no userns rule; emitting it would fail to parse.
+        let v3 = ProfileOptions {
+            abi: Abi::V3,
+            ..ProfileOptions::default()
+        };
+        let out = generate_profile(dir.path(), &cfg, "dotlnx-myapp", &v3);
+        assert!(!out.contains("userns,"));
+    }
+
+    #[test]
+    fn generate_profile_userns_unconfined_flag() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cfg = minimal_config();
+        cfg.security = Some(Security {
+            userns: Userns::Unconfined,
+            ..Security::default()
+        });
+        let opts = ProfileOptions {
+            abi: Abi::V4,
+            ..ProfileOptions::default()
+        };
+        let out = generate_profile(dir.path(), &cfg, "dotlnx-myapp", &opts);
+        assert!(out.contains("profile dotlnx-myapp flags=(unconfined) {"));
+        assert!(out.contains("  userns,"));
+        assert!(!out.contains("capability sys_admin"));
+    }
+
+    #[test]
+    fn generate_profile_helper_child_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cfg = minimal_config();
+        cfg.security = Some(Security {
+            network: true,
+            helpers: vec![Helper {
+                path: "bin/ffmpeg".into(),
+                read_paths: vec!["/srv/media/**".into()],
+                write_paths: vec![],
+                network: false,
+            }],
+            ..Security::default()
+        });
+        let out = generate_profile(dir.path(), &cfg, "dotlnx-myapp", &ProfileOptions::default());
+        assert!(out.contains("/bin/ffmpeg cx -> ffmpeg,"));
+        let child_start = out.find("  profile ffmpeg {").expect("child profile block");
+        let child = &out[child_start..];
+        let child = &child[..child.find("\n  }").unwrap()];
+        assert!(child.contains("/srv/media/** r,"));
+        assert!(child.contains("/bin/ffmpeg rm,"));
+        // Parent network grant must not leak into the helper.
+        assert!(!child.contains("network"));
+        // Child block is nested inside the parent profile.
+        assert!(out.trim_end().ends_with("  }\n}"));
+    }
+
+    #[test]
+    fn helper_profile_name_uses_file_name() {
+        assert_eq!(helper_profile_name("bin/ffmpeg"), "ffmpeg");
+        assert_eq!(helper_profile_name("libexec/my helper.sh"), "my_helper_sh");
+    }
+
+    #[test]
+    fn generate_profile_merges_base_policy_into_parent_and_helpers() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cfg = minimal_config();
+        cfg.security = Some(Security {
+            helpers: vec![Helper {
+                path: "bin/tool".into(),
+                read_paths: vec![],
+                write_paths: vec![],
+                network: false,
+            }],
+            ..Security::default()
+        });
+        let mut opts = ProfileOptions {
+            base_policy: vec![crate::policy::parse_fragment(
+                "10-ssh.conf",
+                "deny @{HOME}/.ssh/** rw,",
+            )
+            .unwrap()],
+            ..ProfileOptions::default()
+        };
+        opts.templates.profile =
+            vec![crate::templates::parse_profile("tag.apparmor", "# asset %{profile}").unwrap()];
+        let out = generate_profile(dir.path(), &cfg, "dotlnx-myapp", &opts);
+        assert!(out.contains("  # site policy: 10-ssh.conf\n  deny @{HOME}/.ssh/** rw,"));
+        assert_eq!(
+            out.matches("# asset dotlnx-myapp").count(),
+            1,
+            "templates go into the app's profile only"
+        );
+        assert!(
+            out.contains("    deny @{HOME}/.ssh/** rw,"),
+            "helper child gets base policy too"
+        );
+    }
+
+    #[test]
+    fn profile_listed_matches_whole_names() {
+        let list = "dotlnx-alice-App (enforce)\ndotlnx-alice-App//helper (enforce)\n/usr/bin/man (enforce)\n";
+        assert!(profile_listed(list, "dotlnx-alice-App"));
+        assert!(profile_listed(list, "/usr/bin/man"));
+        assert!(!profile_listed(list, "dotlnx-alice"));
+    }
+
+    // Property tests for path sanitizing and quoting. Failing seeds are saved under
+    // proptest-regressions/; inputs that once got through go in the corpus.
+
+    const PATH_CORPUS: &[&str] = &[
+        "/tmp/x,\n  /** rwx",
+        "/tmp/x # comment",
+        "/tmp/\"q\"",
+        "/tmp/back\\",
+        "/tmp/a\u{c}b",
+        "/tmp/a\tb",
+        "@{HOME}/My Files/**",
+        ",",
+    ];
+
+    /// The path a rule token stands for, or None when the token could end the rule early or spill
+    /// into the next one (unbalanced quotes, unquoted separators, raw control characters).
+    fn parse_rule_path(token: &str) -> Option<String> {
+        if token.chars().any(|c| c.is_control()) {
+            return None;
         }
-        return Ok(());
+        let Some(inner) = token.strip_prefix('"') else {
+            return (!token.is_empty() && !token.contains([' ', '"', '\\', '#', ',']))
+                .then(|| token.to_string());
+        };
+        let inner = inner.strip_suffix('"')?;
+        let mut out = String::new();
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => return None,
+                '\\' => out.push(chars.next()?),
+                c => out.push(c),
+            }
+        }
+        Some(out)
     }
-    std::fs::create_dir_all(path.parent().unwrap())?;
-    std::fs::write(&path, profile_content)?;
-    let out = std::process::Command::new(&parser)
-        .args(["-r", path.to_str().unwrap_or_default()])
-        .output()?;
-    if !out.status.success() {
-        let _ = std::fs::remove_file(&path);
-        anyhow::bail!(
-            "apparmor_parser -r failed: {}",
-            String::from_utf8_lossy(&out.stderr)
+
+    fn check_quote(path: &str) {
+        if path.is_empty() {
+            return;
+        }
+        let token = quote_path_for_apparmor(path);
+        let expected: String = path
+            .chars()
+            .map(|c| if c.is_control() { '?' } else { c })
+            .collect();
+        assert_eq!(
+            parse_rule_path(&token),
+            Some(expected),
+            "{:?} -> {:?}",
+            path,
+            token
         );
     }
-    Ok(())
-}
 
-/// Unload/remove a profile (apparmor_parser -R, then remove file). May require root.
-pub fn unload_profile(profile_name: &str) -> Result<()> {
-    let parser = find_apparmor_parser().with_context(|| {
-        "apparmor_parser not found (checked /usr/sbin, /sbin, and PATH)"
-    })?;
-    let path = std::path::Path::new(DOTLNX_APPARMOR_DIR).join(profile_name);
-    if !path.exists() {
-        return Ok(());
+    fn check_path_rule(path: &str) {
+        let safe = sanitize_apparmor_path(path);
+        assert!(!safe.contains(['#', ',']), "{:?} -> {:?}", path, safe);
+        assert!(
+            !safe.chars().any(|c| c.is_control()),
+            "{:?} -> {:?}",
+            path,
+            safe
+        );
+        assert_eq!(safe, safe.trim());
+        let mut rules = Vec::new();
+        let list = vec![path.to_string()];
+        push_path_rules(&mut rules, "  ", Path::new("/b"), &[(&list, "rw")]);
+        for rule in &rules {
+            let token = rule
+                .strip_prefix("  ")
+                .and_then(|r| r.strip_suffix(" rw,"))
+                .unwrap();
+            assert!(parse_rule_path(token).is_some(), "{:?} -> {:?}", path, rule);
+        }
     }
-    let path_str = path.to_str().unwrap_or_default();
-    let out = std::process::Command::new(&parser)
-        .args(["-R", path_str])
-        .output()?;
-    if !out.status.success() {
-        // Profile may already be unloaded; try removing file anyway
-        let _ = std::fs::remove_file(&path);
-        return Ok(());
+
+    #[test]
+    fn path_corpus_regressions() {
+        for p in PATH_CORPUS {
+            check_quote(p);
+            check_path_rule(p);
+        }
+    }
+
+    fn profile_dir_paths(root: &Path) -> Paths {
+        Paths {
+            apparmor_parser: Some(PathBuf::from("/bin/true")),
+            ..Paths::under(root)
+        }
+    }
+
+    #[test]
+    fn profile_lock_records_writer_and_spots_outside_edits() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = profile_dir_paths(root.path());
+        let bundle = Path::new("/Applications/App.lnx");
+        let owner = ProfileOwner {
+            app: "App",
+            tier: "system".into(),
+            bundle,
+        };
+        let lock = lock_profile(&paths, "dotlnx-App").unwrap();
+        assert!(lock.meta().is_none());
+        lock.load("profile dotlnx-App {}\n", &owner).unwrap();
+        let meta = lock.meta().unwrap();
+        assert_eq!((meta.app.as_str(), meta.tier.as_str()), ("App", "system"));
+        assert_eq!(meta.bundle, bundle);
+        assert!(!lock.modified_outside());
+        assert!(meta.belongs_elsewhere(Path::new("/Applications/Other.lnx"), |_| true));
+        assert!(!meta.belongs_elsewhere(Path::new("/Applications/Other.lnx"), |_| false));
+        assert!(!meta.belongs_elsewhere(bundle, |_| true));
+
+        std::fs::write(paths.profile_path("dotlnx-App"), "edited").unwrap();
+        assert!(lock.modified_outside());
+        assert_eq!(meta.loaded, Some(meta.hash.clone()));
+        drop(lock);
+        // Metadata lives in a hidden directory, out of apparmor_parser's way.
+        let listed: Vec<_> = std::fs::read_dir(&paths.apparmor_dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|n| !n.starts_with('.'))
+            .collect();
+        assert_eq!(listed, ["dotlnx-App"]);
+
+        unload_profile(&paths, "dotlnx-App").unwrap();
+        assert!(!paths.profile_path("dotlnx-App").exists());
+        assert!(profile_meta(&paths, "dotlnx-App").is_none());
+    }
+
+    #[test]
+    fn only_profiles_the_kernel_lacks_as_written_need_loading() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = Paths {
+            apparmor_parser: Some(PathBuf::from("/bin/false")),
+            ..profile_dir_paths(root.path())
+        };
+        let owner = ProfileOwner {
+            app: "App",
+            tier: "system".into(),
+            bundle: Path::new("/Applications/App.lnx"),
+        };
+        let (v1, v2) = (
+            "profile dotlnx-App { /a r, }\n",
+            "profile dotlnx-App { /b r, }\n",
+        );
+        std::fs::create_dir_all(paths.loaded_profiles.parent().unwrap()).unwrap();
+        std::fs::write(&paths.loaded_profiles, "dotlnx-App (enforce)\n").unwrap();
+        assert!(
+            !is_current(&paths, "dotlnx-App", v1),
+            "never loaded by dotlnx"
+        );
+
+        lock_profile(&profile_dir_paths(root.path()), "dotlnx-App")
+            .unwrap()
+            .load(v1, &owner)
+            .unwrap();
+        assert!(is_current(&paths, "dotlnx-App", v1));
+        assert!(!is_current(&paths, "dotlnx-App", v2));
+        // A failed reload leaves the new file on disk but the kernel on the old version.
+        assert!(lock_profile(&paths, "dotlnx-App")
+            .unwrap()
+            .load(v2, &owner)
+            .is_err());
+        assert_eq!(installed_profile(&paths, "dotlnx-App").as_deref(), Some(v2));
+        assert!(!is_current(&paths, "dotlnx-App", v2));
+        std::fs::write(&paths.loaded_profiles, "").unwrap();
+        assert!(
+            !is_current(&paths, "dotlnx-App", v1),
+            "unloaded since (AppArmor restart)"
+        );
+    }
+
+    #[test]
+    fn profile_lock_is_exclusive() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = profile_dir_paths(root.path());
+        let held = lock_profile(&paths, "dotlnx-App").unwrap();
+        let acquired = std::sync::atomic::AtomicBool::new(false);
+        std::thread::scope(|s| {
+            let waiter = s.spawn(|| {
+                let _lock = lock_profile(&paths, "dotlnx-App").unwrap();
+                acquired.store(true, std::sync::atomic::Ordering::SeqCst);
+            });
+            // Other profiles are not blocked.
+            drop(lock_profile(&paths, "dotlnx-Other").unwrap());
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            assert!(!acquired.load(std::sync::atomic::Ordering::SeqCst));
+            drop(held);
+            waiter.join().unwrap();
+        });
+        assert!(acquired.load(std::sync::atomic::Ordering::SeqCst));
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn quoted_paths_round_trip(path in "\\PC*|[\\x00-\\x1f\\\\ \"#,/a]*") {
+            check_quote(&path);
+        }
+
+        #[test]
+        fn path_rules_stay_one_rule(path in "\\PC*|[\\x00-\\x1f\\\\ \"#,/*a]*") {
+            check_path_rule(&path);
+        }
+
+        #[test]
+        fn bundle_paths_cannot_add_rules(dir in "[\\x00-\\x1f\\\\ \"#,a-z]+") {
+            let root = Path::new("/home/u/Applications").join(format!("{}.lnx", dir));
+            let out = generate_profile(&root, &minimal_config(), "dotlnx-u-myapp", &ProfileOptions::default());
+            let rules: Vec<&str> = out.lines().filter(|l| l.contains(".lnx")).collect();
+            proptest::prop_assert_eq!(rules.len(), 2, "{}", out);
+            for rule in rules {
+                let token = rule.trim().rsplit_once(' ').map(|(t, _)| t).unwrap();
+                proptest::prop_assert!(parse_rule_path(token).is_some(), "{}", rule);
+            }
+        }
     }
-    std::fs::remove_file(&path)?;
-    Ok(())
 }
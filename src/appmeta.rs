@@ -0,0 +1,502 @@
+//! Desktop metadata for `dotlnx bundle`: the menu text an app already ships, in its .desktop
+//! entry or AppStream metainfo, carried into the generated config.toml (comment, generic_name,
+//! categories, keywords and [translations.<locale>]), so an imported bundle turns up in the same
+//! menu and launcher searches as a packaged app.
+//!
+//! An AppImage's own entry and metainfo are read from its squashfs with `unsquashfs`, without
+//! running the AppImage; that is best effort (no unsquashfs, a type 1 AppImage: no metadata).
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::config::Translation;
+use crate::validate;
+
+/// Metadata found for one app. The untranslated Name is left out: the bundle is named by
+/// `--appname`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Metadata {
+    pub comment: Option<String>,
+    pub generic_name: Option<String>,
+    pub categories: Vec<String>,
+    pub keywords: Vec<String>,
+    pub translations: BTreeMap<String, Translation>,
+}
+
+impl Metadata {
+    pub fn is_empty(&self) -> bool {
+        *self == Metadata::default()
+    }
+
+    /// Fill what is still missing from `other` (an earlier source wins per key).
+    pub fn fill(&mut self, other: Metadata) {
+        fill_opt(&mut self.comment, other.comment);
+        fill_opt(&mut self.generic_name, other.generic_name);
+        fill_vec(&mut self.categories, other.categories);
+        fill_vec(&mut self.keywords, other.keywords);
+        for (locale, theirs) in other.translations {
+            let ours = self.translations.entry(locale).or_default();
+            fill_opt(&mut ours.name, theirs.name);
+            fill_opt(&mut ours.generic_name, theirs.generic_name);
+            fill_opt(&mut ours.comment, theirs.comment);
+            fill_vec(&mut ours.keywords, theirs.keywords);
+        }
+    }
+}
+
+fn fill_opt(ours: &mut Option<String>, theirs: Option<String>) {
+    if ours.is_none() {
+        *ours = theirs;
+    }
+}
+
+fn fill_vec(ours: &mut Vec<String>, theirs: Vec<String>) {
+    if ours.is_empty() {
+        *ours = theirs;
+    }
+}
+
+/// Read a .desktop file.
+pub fn from_desktop_file(path: &Path) -> Result<Metadata> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+    Ok(parse_desktop_entry(&text))
+}
+
+/// Read an AppStream metainfo (or older appdata) file.
+pub fn from_metainfo_file(path: &Path) -> Result<Metadata> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+    Ok(parse_metainfo(&text))
+}
+
+/// The [Desktop Entry] keys of a .desktop file. Keys in locales desktop files would not accept
+/// and values with control characters are skipped.
+pub fn parse_desktop_entry(text: &str) -> Metadata {
+    let mut meta = Metadata::default();
+    let mut in_entry = false;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_entry = line == "[Desktop Entry]";
+            continue;
+        }
+        if !in_entry || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let (key, locale) = match key.trim().split_once('[') {
+            Some((key, locale)) => (key, locale.strip_suffix(']')),
+            None => (key.trim(), None),
+        };
+        if locale.is_some_and(|l| !validate::is_locale(l)) {
+            continue;
+        }
+        let value = value.trim();
+        let list = || split_list(value);
+        let one = || {
+            Some(unescape(value)).filter(|v| !v.is_empty() && !v.contains(|c: char| c.is_control()))
+        };
+        match (key, locale) {
+            ("Comment", None) => meta.comment = one(),
+            ("GenericName", None) => meta.generic_name = one(),
+            ("Categories", None) => meta.categories = list(),
+            ("Keywords", None) => meta.keywords = list(),
+            ("Name", Some(l)) => meta.translations.entry(l.into()).or_default().name = one(),
+            ("Comment", Some(l)) => meta.translations.entry(l.into()).or_default().comment = one(),
+            ("GenericName", Some(l)) => {
+                meta.translations.entry(l.into()).or_default().generic_name = one()
+            }
+            ("Keywords", Some(l)) => {
+                meta.translations.entry(l.into()).or_default().keywords = list()
+            }
+            _ => {}
+        }
+    }
+    meta.translations
+        .retain(|_, t| *t != Translation::default());
+    meta
+}
+
+/// Undo desktop-file escapes (`\s`, `\n`, `\t`, `\r`, `\\`; `\;` is handled by `split_list`).
+pub fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => out.push(' '),
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some(other) => out.push(other),
+            None => {}
+        }
+    }
+    out
+}
+
+/// Items of a `;`-separated list value. An escaped `\;` inside an item cannot be kept (config
+/// keywords may not contain `;`), so it ends the item like a plain one.
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .replace("\\;", ";")
+        .split(';')
+        .map(|item| unescape(item).trim().to_string())
+        .filter(|item| !item.is_empty() && !item.contains(|c: char| c.is_control()))
+        .collect()
+}
+
+/// Elements whose subtrees hold a `<name>` or `<keyword>` that is not the app's.
+const SKIPPED_ELEMENTS: &[&str] = &[
+    "developer",
+    "releases",
+    "description",
+    "screenshots",
+    "provides",
+    "requires",
+    "recommends",
+    "supports",
+    "content_rating",
+    "branding",
+    "custom",
+];
+
+/// The component's name, summary, keywords and categories from AppStream metainfo. A small
+/// scanner, not an XML parser: enough for the flat layout metainfo files use.
+pub fn parse_metainfo(xml: &str) -> Metadata {
+    let mut meta = Metadata::default();
+    let mut rest = xml;
+    let mut keywords_lang: Option<String> = None;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        if let Some(after) = rest.strip_prefix("!--") {
+            rest = after.split_once("-->").map(|(_, r)| r).unwrap_or("");
+            continue;
+        }
+        let Some(end) = rest.find('>') else { break };
+        let tag = &rest[..end];
+        rest = &rest[end + 1..];
+        if tag.starts_with(['?', '!']) {
+            continue;
+        }
+        if let Some(closing) = tag.strip_prefix('/') {
+            if closing.trim() == "keywords" {
+                keywords_lang = None;
+            }
+            continue;
+        }
+        let self_closing = tag.ends_with('/');
+        let tag = tag.trim_end_matches('/');
+        let (element, attrs) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+        let lang = attribute(attrs, "xml:lang");
+        if self_closing {
+            continue;
+        }
+        if SKIPPED_ELEMENTS.contains(&element) {
+            let close = format!("</{}>", element);
+            rest = rest
+                .split_once(close.as_str())
+                .map(|(_, r)| r)
+                .unwrap_or("");
+            continue;
+        }
+        if element == "keywords" {
+            keywords_lang = lang;
+            continue;
+        }
+        if !["name", "summary", "keyword", "category"].contains(&element) {
+            continue;
+        }
+        let close = format!("</{}>", element);
+        let Some((text, after)) = rest.split_once(close.as_str()) else {
+            break;
+        };
+        rest = after;
+        let text = decode_entities(text.trim());
+        if text.is_empty() || text.contains(['<', '>']) || text.contains(|c: char| c.is_control()) {
+            continue;
+        }
+        let lang = lang
+            .or_else(|| keywords_lang.clone())
+            .filter(|l| l != "C" && l != "en");
+        let lang = match lang {
+            Some(l) if !validate::is_locale(&l) => continue,
+            Some(l) => Some(l.replace('-', "_")),
+            None => None,
+        };
+        match (element, lang) {
+            ("summary", None) => fill_opt(&mut meta.comment, Some(text)),
+            ("keyword", None) if !text.contains(';') => meta.keywords.push(text),
+            ("category", None) => meta.categories.push(text),
+            ("name", Some(l)) => fill_opt(
+                &mut meta.translations.entry(l).or_default().name,
+                Some(text),
+            ),
+            ("summary", Some(l)) => fill_opt(
+                &mut meta.translations.entry(l).or_default().comment,
+                Some(text),
+            ),
+            ("keyword", Some(l)) if !text.contains(';') => {
+                meta.translations.entry(l).or_default().keywords.push(text)
+            }
+            _ => {}
+        }
+    }
+    meta
+}
+
+/// Value of `name="..."` (or single-quoted) in a tag's attributes.
+fn attribute(attrs: &str, name: &str) -> Option<String> {
+    let at = attrs.find(&format!("{}=", name))?;
+    let value = &attrs[at + name.len() + 1..];
+    let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let value = &value[1..];
+    Some(decode_entities(&value[..value.find(quote)?]))
+}
+
+fn decode_entities(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Where the squashfs image of a type 2 AppImage starts: right after the ELF runtime, whose
+/// section header table comes last.
+fn squashfs_offset(appimage: &Path) -> Option<u64> {
+    use std::io::Read;
+    let mut header = [0u8; 64];
+    std::fs::File::open(appimage)
+        .ok()?
+        .read_exact(&mut header)
+        .ok()?;
+    if &header[..4] != b"\x7fELF" {
+        return None;
+    }
+    let little = header[5] == 1;
+    let read = |at: usize, len: usize| {
+        let bytes = &header[at..at + len];
+        let fold = |acc: u64, b: &u8| (acc << 8) | u64::from(*b);
+        match little {
+            true => bytes.iter().rev().fold(0, fold),
+            false => bytes.iter().fold(0, fold),
+        }
+    };
+    let (shoff, shentsize, shnum) = match header[4] {
+        1 => (read(0x20, 4), read(0x2e, 2), read(0x30, 2)),
+        2 => (read(0x28, 8), read(0x3a, 2), read(0x3c, 2)),
+        _ => return None,
+    };
+    shoff.checked_add(shentsize * shnum)
+}
+
+/// Metadata embedded in an AppImage: its top-level .desktop entry first, then its metainfo.
+/// None (with the reason logged) when nothing could be read.
+pub fn from_appimage(appimage: &Path) -> Option<Metadata> {
+    let offset = squashfs_offset(appimage)?;
+    let tmp = std::env::temp_dir().join(format!("dotlnx-appmeta-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&tmp);
+    let status = Command::new("unsquashfs")
+        .args(["-no-progress", "-o", &offset.to_string(), "-d"])
+        .arg(&tmp)
+        .arg(appimage)
+        .args([
+            "*.desktop",
+            "usr/share/applications",
+            "usr/share/metainfo",
+            "usr/share/appdata",
+        ])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    let meta = match status {
+        Ok(s) if s.success() => Some(read_extracted(&tmp)),
+        Ok(s) => {
+            tracing::debug!("unsquashfs {} failed ({})", appimage.display(), s);
+            None
+        }
+        Err(e) => {
+            tracing::warn!(
+                "cannot read the AppImage's desktop entry (needs unsquashfs): {}",
+                e
+            );
+            None
+        }
+    };
+    let _ = std::fs::remove_dir_all(&tmp);
+    meta.filter(|m| !m.is_empty())
+}
+
+/// Metadata from an extracted AppImage tree. Symlinks are not followed (they may point at the
+/// host's files).
+fn read_extracted(root: &Path) -> Metadata {
+    let files = |dir: PathBuf, ext: &str| -> Vec<PathBuf> {
+        let mut found: Vec<PathBuf> = std::fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+            .map(|e| e.path())
+            .filter(|p| p.to_string_lossy().ends_with(ext))
+            .collect();
+        found.sort();
+        found
+    };
+    let mut meta = Metadata::default();
+    let desktop = files(root.to_path_buf(), ".desktop")
+        .into_iter()
+        .chain(files(root.join("usr/share/applications"), ".desktop"))
+        .next();
+    if let Some(m) = desktop.and_then(|p| from_desktop_file(&p).ok()) {
+        meta.fill(m);
+    }
+    let metainfo = files(root.join("usr/share/metainfo"), ".xml")
+        .into_iter()
+        .chain(files(root.join("usr/share/appdata"), ".xml"))
+        .next();
+    if let Some(m) = metainfo.and_then(|p| from_metainfo_file(&p).ok()) {
+        meta.fill(m);
+    }
+    meta
+}
+
+/// `config` (a scaffold's config.toml) with the metadata added: top-level keys before the first
+/// table, [translations.<locale>] tables at the end.
+pub fn add_to_config(config: &str, meta: &Metadata) -> String {
+    let string = |s: &str| toml::Value::String(s.to_string()).to_string();
+    let array = |items: &[String]| {
+        toml::Value::Array(
+            items
+                .iter()
+                .map(|s| toml::Value::String(s.clone()))
+                .collect(),
+        )
+        .to_string()
+    };
+    let mut keys = String::new();
+    for (key, value) in [
+        ("generic_name", &meta.generic_name),
+        ("comment", &meta.comment),
+    ] {
+        if let Some(value) = value {
+            keys.push_str(&format!("{} = {}\n", key, string(value)));
+        }
+    }
+    for (key, items) in [
+        ("categories", &meta.categories),
+        ("keywords", &meta.keywords),
+    ] {
+        if !items.is_empty() {
+            keys.push_str(&format!("{} = {}\n", key, array(items)));
+        }
+    }
+    let at = config
+        .match_indices('\n')
+        .map(|(i, _)| i + 1)
+        .find(|&i| config[i..].starts_with('['))
+        .unwrap_or(config.len());
+    let mut out = format!("{}{}", &config[..at], keys);
+    if at < config.len() {
+        out.push('\n');
+    }
+    out.push_str(&config[at..]);
+    for (locale, t) in &meta.translations {
+        let key = match locale
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            true => locale.clone(),
+            false => string(locale),
+        };
+        out.push_str(&format!("\n[translations.{}]\n", key));
+        for (k, value) in [
+            ("name", &t.name),
+            ("generic_name", &t.generic_name),
+            ("comment", &t.comment),
+        ] {
+            if let Some(value) = value {
+                out.push_str(&format!("{} = {}\n", k, string(value)));
+            }
+        }
+        if !t.keywords.is_empty() {
+            out.push_str(&format!("keywords = {}\n", array(&t.keywords)));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn desktop_entry_and_metainfo_become_config_keys() {
+        let desktop = "[Desktop Entry]\nType=Application\nName=Browser\nName[de]=Netz Browser\n\
+            GenericName=Web Browser\nComment=Browse the web\nComment[sr@latin]=Pregledač\n\
+            Keywords=internet;WWW;\\sweb page;\nKeywords[de]=Netz;\nCategories=Network;WebBrowser;\n\
+            Name[de]]=bad\n[Desktop Action new]\nName[fr]=Nouvelle fenêtre\n";
+        let mut meta = parse_desktop_entry(desktop);
+        assert_eq!(meta.generic_name.as_deref(), Some("Web Browser"));
+        assert_eq!(meta.keywords, ["internet", "WWW", "web page"]);
+        assert_eq!(meta.categories, ["Network", "WebBrowser"]);
+        assert_eq!(
+            meta.translations["de"].name.as_deref(),
+            Some("Netz Browser")
+        );
+        assert_eq!(meta.translations["de"].keywords, ["Netz"]);
+        assert!(!meta.translations.contains_key("fr"));
+
+        let metainfo = r#"<?xml version="1.0"?>
+<component type="desktop-application">
+  <id>org.example.Browser</id>
+  <name>Browser</name>
+  <name xml:lang="fr">Navigateur</name>
+  <summary>Browse the web &amp; more</summary>
+  <summary xml:lang="de">Im Netz surfen</summary>
+  <developer><name>Example &lt;Org&gt;</name></developer>
+  <!-- <name xml:lang="it">commented out</name> -->
+  <description><p>Long text</p></description>
+  <keywords><keyword>browser</keyword><keyword xml:lang="fr">navigateur</keyword></keywords>
+</component>"#;
+        let from_xml = parse_metainfo(metainfo);
+        assert_eq!(from_xml.comment.as_deref(), Some("Browse the web & more"));
+        assert_eq!(from_xml.keywords, ["browser"]);
+        assert_eq!(
+            from_xml.translations["fr"].name.as_deref(),
+            Some("Navigateur")
+        );
+        assert!(!from_xml.translations.contains_key("it"));
+        meta.fill(from_xml);
+        assert_eq!(meta.comment.as_deref(), Some("Browse the web"));
+        assert_eq!(
+            meta.translations["de"].comment.as_deref(),
+            Some("Im Netz surfen")
+        );
+        assert_eq!(meta.translations["fr"].keywords, ["navigateur"]);
+
+        let scaffold =
+            "name = \"Browser\"\nexecutable = \"run.sh\"\n\n[security]\nnetwork = true\n";
+        let text = add_to_config(scaffold, &meta);
+        let cfg: crate::config::Config = toml::from_str(&text).unwrap();
+        assert!(cfg.security.unwrap().network);
+        assert_eq!(cfg.keywords, meta.keywords);
+        assert_eq!(cfg.categories.unwrap(), meta.categories);
+        assert_eq!(cfg.translations, meta.translations);
+        assert!(text.contains("[translations.\"sr@latin\"]"), "{}", text);
+    }
+
+    #[test]
+    fn squashfs_offset_is_end_of_elf() {
+        let exe = std::env::current_exe().unwrap();
+        let offset = squashfs_offset(&exe).unwrap();
+        assert!(offset > 64 && offset <= std::fs::metadata(&exe).unwrap().len());
+        assert_eq!(squashfs_offset(Path::new("/nonexistent")), None);
+    }
+}
@@ -0,0 +1,196 @@
+//! CPU architectures: of the host, of ELF binaries in a bundle, and as named in `arch = [...]`
+//! and in AppImage file names. Used to warn about bundles built for another machine (an x86_64
+//! AppImage dropped onto a Raspberry Pi) and to make run.sh pick the AppImage for the machine.
+
+use serde::Deserialize;
+use std::io::Read;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arch {
+    X86_64,
+    Aarch64,
+    Armhf,
+    I686,
+    Riscv64,
+}
+
+/// Every architecture, for generated scripts.
+pub const ALL: &[Arch] = &[
+    Arch::X86_64,
+    Arch::Aarch64,
+    Arch::Armhf,
+    Arch::I686,
+    Arch::Riscv64,
+];
+
+impl Arch {
+    /// Name as in config.toml and AppImage file names ("x86_64", "aarch64", "armhf", ...).
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Arch::X86_64 => "x86_64",
+            Arch::Aarch64 => "aarch64",
+            Arch::Armhf => "armhf",
+            Arch::I686 => "i686",
+            Arch::Riscv64 => "riscv64",
+        }
+    }
+
+    /// Debian's name ("amd64", "arm64", ...), also common in release file names.
+    pub fn debian_name(self) -> &'static str {
+        match self {
+            Arch::X86_64 => "amd64",
+            Arch::Aarch64 => "arm64",
+            Arch::Armhf => "armhf",
+            Arch::I686 => "i386",
+            Arch::Riscv64 => "riscv64",
+        }
+    }
+
+    /// `uname -m` values of machines of this architecture, as a shell `case` pattern.
+    pub fn uname_pattern(self) -> &'static str {
+        match self {
+            Arch::X86_64 => "x86_64|amd64",
+            Arch::Aarch64 => "aarch64|arm64",
+            Arch::Armhf => "armv7*|armv8l",
+            Arch::I686 => "i?86",
+            Arch::Riscv64 => "riscv64",
+        }
+    }
+
+    /// From either naming; None for anything else.
+    pub fn parse(s: &str) -> Option<Arch> {
+        ALL.iter()
+            .copied()
+            .find(|a| s == a.as_str() || s == a.debian_name())
+            .or(match s {
+                "armv7" | "armv7l" | "arm" => Some(Arch::Armhf),
+                "x86" | "i386" | "i586" => Some(Arch::I686),
+                _ => None,
+            })
+    }
+
+    /// The machine dotlnx was built for (and so runs on).
+    pub fn host() -> Option<Arch> {
+        match std::env::consts::ARCH {
+            "x86" => Some(Arch::I686),
+            "arm" => Some(Arch::Armhf),
+            other => Arch::parse(other),
+        }
+    }
+
+    /// True when binaries for `self` run on a `host` machine (32-bit x86 and ARM binaries run on
+    /// the 64-bit machines of their family).
+    pub fn runs_on(self, host: Arch) -> bool {
+        self == host
+            || matches!(
+                (self, host),
+                (Arch::I686, Arch::X86_64) | (Arch::Armhf, Arch::Aarch64)
+            )
+    }
+
+    /// Architecture of an ELF file; None when `path` is not ELF (scripts, data) or not readable.
+    pub fn of_file(path: &Path) -> Option<Arch> {
+        let mut header = [0u8; 20];
+        std::fs::File::open(path)
+            .ok()?
+            .read_exact(&mut header)
+            .ok()?;
+        if header[..4] != *b"\x7fELF" {
+            return None;
+        }
+        let is_64 = header[4] == 2;
+        let machine = match header[5] {
+            1 => u16::from_le_bytes([header[18], header[19]]),
+            2 => u16::from_be_bytes([header[18], header[19]]),
+            _ => return None,
+        };
+        match (machine, is_64) {
+            (0x3e, true) => Some(Arch::X86_64),
+            (0xb7, true) => Some(Arch::Aarch64),
+            (0x28, false) => Some(Arch::Armhf),
+            (0x03, false) => Some(Arch::I686),
+            (0xf3, true) => Some(Arch::Riscv64),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Arch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Arch {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        Arch::parse(&s).ok_or_else(|| {
+            let names: Vec<&str> = ALL.iter().map(|a| a.as_str()).collect();
+            serde::de::Error::custom(format!(
+                "unknown arch {:?} (expected one of {})",
+                s,
+                names.join(", ")
+            ))
+        })
+    }
+}
+
+/// Architecture named in a file name (e.g. "App-1.2-aarch64.AppImage"), with the name as written.
+pub fn in_file_name(name: &str) -> Option<(Arch, &str)> {
+    name.split(['-', '.'])
+        .flat_map(|word| std::iter::once(word).chain(word.split('_')))
+        .find_map(|word| {
+            Arch::parse(word)
+                .filter(|_| word.len() > 3)
+                .map(|a| (a, word))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn elf(class: u8, data: u8, machine: u16) -> Vec<u8> {
+        let mut h = vec![0x7f, b'E', b'L', b'F', class, data];
+        h.resize(18, 0);
+        let m = if data == 1 {
+            machine.to_le_bytes()
+        } else {
+            machine.to_be_bytes()
+        };
+        h.extend(m);
+        h.resize(64, 0);
+        h
+    }
+
+    #[test]
+    fn elf_machine_and_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = |name: &str, bytes: &[u8]| {
+            let p = dir.path().join(name);
+            std::fs::write(&p, bytes).unwrap();
+            Arch::of_file(&p)
+        };
+        assert_eq!(file("a", &elf(2, 1, 0x3e)), Some(Arch::X86_64));
+        assert_eq!(file("b", &elf(2, 1, 0xb7)), Some(Arch::Aarch64));
+        assert_eq!(file("c", &elf(1, 1, 0x28)), Some(Arch::Armhf));
+        assert_eq!(file("d", &elf(2, 2, 0x15)), None);
+        assert_eq!(file("e", b"#!/bin/sh\nexec true\n"), None);
+        assert_eq!(file("f", b"\x7fEL"), None);
+
+        assert_eq!(Arch::parse("arm64"), Some(Arch::Aarch64));
+        assert_eq!(Arch::parse("sparc"), None);
+        assert!(Arch::I686.runs_on(Arch::X86_64) && !Arch::X86_64.runs_on(Arch::Aarch64));
+        assert_eq!(
+            in_file_name("App-1.2-arm64.AppImage"),
+            Some((Arch::Aarch64, "arm64"))
+        );
+        assert_eq!(
+            in_file_name("Cursor-0.1.0-x86_64.appimage"),
+            Some((Arch::X86_64, "x86_64"))
+        );
+        // "x86" and "arm" are too short to tell from ordinary words in a name.
+        assert_eq!(in_file_name("arm-tool.AppImage"), None);
+    }
+}
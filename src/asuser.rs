@@ -0,0 +1,302 @@
+//! File operations in users' homes, performed as that user. When the root daemon needs to read a
+//! bundle's config.toml or write a .desktop/.directory file under /home/<user>, it re-executes
+//! itself (`dotlnx __as-user`) with the user's uid/gid and sends one request over stdin. Path
+//! tricks in the home then only ever reach what the user could already touch, and homes on
+//! root-squashed NFS work because the user, not root, does the I/O.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::safefs::SafeDir;
+
+/// One operation for the helper. Paths are absolute; `name` is a single file name in `dir`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Request {
+    /// Read `bundle`/config.toml, with the files it includes merged in. With `nofollow`, a
+    /// symlinked bundle dir is refused.
+    ReadConfig { bundle: PathBuf, nofollow: bool },
+    /// Create or replace `dir`/`name` (creating `dir` first when `create_dir`).
+    WriteFile {
+        dir: PathBuf,
+        name: String,
+        contents: String,
+        create_dir: bool,
+    },
+    /// Read `dir`/`name` (a regular file); no contents when it or `dir` does not exist.
+    ReadFile { dir: PathBuf, name: String },
+    /// Remove `dir`/`name` if present. A missing `dir` is not an error.
+    RemoveFile { dir: PathBuf, name: String },
+    /// Names of the regular files in `dir`, one per line; none when `dir` does not exist.
+    ListFiles { dir: PathBuf },
+    /// Unpack a packed bundle dropped into an Applications dir (see `pack::extract_dropped`);
+    /// returns the new bundle's path.
+    ExtractArchive { archive: PathBuf },
+    /// Unpack a bundle staged by `dotlnx import` into `apps_dir` (see
+    /// `backup::restore_bundle`); returns the bundle's path.
+    RestoreBundle {
+        archive: PathBuf,
+        apps_dir: PathBuf,
+        replace: bool,
+    },
+}
+
+/// Largest generated file read back for comparison (.desktop entries are a few hundred bytes).
+const MAX_READ_BYTES: u64 = 1024 * 1024;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Response {
+    Ok(Option<String>),
+    Err(String),
+}
+
+/// Run `req` as `user` when we are root, otherwise in this process as the current user.
+/// Returns the file contents for ReadConfig and ReadFile (None when missing), the listing for
+/// ListFiles, the bundle for ExtractArchive and RestoreBundle, None for the others.
+pub fn perform(user: Option<&str>, req: &Request) -> Result<Option<String>> {
+    match user {
+        Some(username) if crate::bundle::is_root() => perform_as(username, req),
+        _ => execute(req),
+    }
+}
+
+/// Read a bundle's config.toml as `user` (see `perform`) and parse it.
+pub fn load_config(
+    user: Option<&str>,
+    bundle: &Path,
+    nofollow: bool,
+) -> Result<crate::config::Config> {
+    let req = Request::ReadConfig {
+        bundle: bundle.to_path_buf(),
+        nofollow,
+    };
+    let text = perform(user, &req)?.unwrap_or_default();
+    crate::config::parse(&text)
+}
+
+/// Carry out a request in this process.
+fn execute(req: &Request) -> Result<Option<String>> {
+    match req {
+        Request::ReadConfig { bundle, nofollow } => {
+            let dir = if *nofollow {
+                SafeDir::open(bundle)?
+            } else {
+                SafeDir::open_under(bundle, Path::new(""))?
+            };
+            let text = dir
+                .read_to_string("config.toml", crate::config::MAX_CONFIG_BYTES)
+                .map_err(|e| anyhow::anyhow!("failed to read config.toml: {:#}", e))?;
+            let text = crate::config::expand_includes(&text, |rel| {
+                dir.read_nested(rel, crate::config::MAX_CONFIG_BYTES)
+            })?;
+            Ok(Some(text))
+        }
+        Request::WriteFile {
+            dir,
+            name,
+            contents,
+            create_dir,
+        } => {
+            if *create_dir {
+                crate::safefs::create_dirs(dir)
+                    .with_context(|| format!("create {}", dir.display()))?;
+            }
+            SafeDir::open(dir)?.write(name, contents.as_bytes())?;
+            Ok(None)
+        }
+        Request::ReadFile { dir, name } => {
+            if !dir.exists() {
+                return Ok(None);
+            }
+            let dir = SafeDir::open(dir)?;
+            match dir.read_to_string(name, MAX_READ_BYTES) {
+                Ok(text) => Ok(Some(text)),
+                Err(e) if is_not_found(&e) => Ok(None),
+                Err(e) => Err(e),
+            }
+        }
+        Request::RemoveFile { dir, name } => {
+            if !dir.exists() {
+                return Ok(None);
+            }
+            SafeDir::open(dir)?.remove(name)?;
+            Ok(None)
+        }
+        Request::ListFiles { dir } => {
+            let entries = match std::fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e).with_context(|| format!("list {}", dir.display())),
+            };
+            let mut names: Vec<String> = entries
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+                .filter_map(|e| e.file_name().into_string().ok())
+                .filter(|n| !n.contains('\n'))
+                .collect();
+            names.sort();
+            Ok(Some(names.join("\n")))
+        }
+        Request::ExtractArchive { archive } => {
+            let bundle = crate::pack::extract_dropped(archive)?;
+            Ok(Some(bundle.to_string_lossy().into_owned()))
+        }
+        Request::RestoreBundle {
+            archive,
+            apps_dir,
+            replace,
+        } => {
+            let bundle = crate::backup::restore_bundle(archive, apps_dir, *replace)?;
+            Ok(Some(bundle.to_string_lossy().into_owned()))
+        }
+    }
+}
+
+fn is_not_found(e: &anyhow::Error) -> bool {
+    e.chain().any(|c| {
+        c.downcast_ref::<nix::errno::Errno>() == Some(&nix::errno::Errno::ENOENT)
+            || c.downcast_ref::<std::io::Error>()
+                .is_some_and(|io| io.kind() == std::io::ErrorKind::NotFound)
+    })
+}
+
+#[cfg(unix)]
+fn perform_as(username: &str, req: &Request) -> Result<Option<String>> {
+    use std::os::unix::process::CommandExt;
+    use std::process::{Command, Stdio};
+
+    let user = nix::unistd::User::from_name(username)
+        .map_err(|e| anyhow::anyhow!("lookup user {:?}: {}", username, e))?
+        .ok_or_else(|| anyhow::anyhow!("no such user: {:?}", username))?;
+    if user.uid.is_root() {
+        return execute(req);
+    }
+    let exe = std::env::current_exe().context("locate dotlnx binary")?;
+    // uid()/gid() make the child setgid+setuid (dropping supplementary groups) before exec.
+    let mut child = Command::new(exe)
+        .arg("__as-user")
+        .uid(user.uid.as_raw())
+        .gid(user.gid.as_raw())
+        .env_clear()
+        .env("PATH", "/usr/local/bin:/usr/bin:/bin")
+        .env("HOME", &user.dir)
+        .env("USER", username)
+        .env("LOGNAME", username)
+        .current_dir("/")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .context("spawn dotlnx __as-user")?;
+    {
+        let mut stdin = child.stdin.take().expect("piped stdin");
+        stdin.write_all(serde_json::to_string(req)?.as_bytes())?;
+    }
+    let mut out = String::new();
+    child
+        .stdout
+        .take()
+        .expect("piped stdout")
+        .read_to_string(&mut out)?;
+    let status = child.wait()?;
+    match serde_json::from_str(&out) {
+        Ok(Response::Ok(v)) => Ok(v),
+        Ok(Response::Err(e)) => Err(anyhow::anyhow!("{} (as user {})", e, username)),
+        Err(_) => anyhow::bail!("dotlnx __as-user for {} failed ({})", username, status),
+    }
+}
+
+#[cfg(not(unix))]
+fn perform_as(_username: &str, req: &Request) -> Result<Option<String>> {
+    execute(req)
+}
+
+/// Entry point for the hidden `__as-user` subcommand: one JSON request on stdin, one JSON
+/// response on stdout. Refuses to run as root so a bad spawn can never act with root's rights.
+pub fn serve() -> Result<()> {
+    if crate::bundle::is_root() {
+        anyhow::bail!("__as-user must not run as root");
+    }
+    let mut input = String::new();
+    std::io::stdin().read_to_string(&mut input)?;
+    let resp = match serde_json::from_str::<Request>(&input) {
+        Ok(req) => match execute(&req) {
+            Ok(v) => Response::Ok(v),
+            Err(e) => Response::Err(format!("{:#}", e)),
+        },
+        Err(e) => Response::Err(format!("bad request: {}", e)),
+    };
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(serde_json::to_string(&resp)?.as_bytes())?;
+    stdout.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_roundtrips_as_tagged_json() {
+        let req = Request::RemoveFile {
+            dir: PathBuf::from("/home/alice/.local/share/applications"),
+            name: "dotlnx-a.desktop".into(),
+        };
+        let json = serde_json::to_string(&req).unwrap();
+        assert!(json.contains("\"op\":\"remove_file\""));
+        assert!(matches!(
+            serde_json::from_str::<Request>(&json).unwrap(),
+            Request::RemoveFile { .. }
+        ));
+    }
+
+    #[test]
+    fn execute_write_read_remove_in_process() {
+        let tmp = tempfile::tempdir().unwrap();
+        let bundle = tmp.path().join("a.lnx");
+        let write = Request::WriteFile {
+            dir: bundle.clone(),
+            name: "config.toml".into(),
+            contents: "name = \"a\"\nexecutable = \"run.sh\"\n".into(),
+            create_dir: true,
+        };
+        perform(None, &write).unwrap();
+        let cfg = load_config(None, &bundle, true).unwrap();
+        assert_eq!(cfg.name, "a");
+        let read = Request::ReadFile {
+            dir: bundle.clone(),
+            name: "config.toml".into(),
+        };
+        assert!(perform(None, &read).unwrap().unwrap().starts_with("name"));
+        let remove = Request::RemoveFile {
+            dir: bundle.clone(),
+            name: "config.toml".into(),
+        };
+        perform(None, &remove).unwrap();
+        assert!(!bundle.join("config.toml").exists());
+        assert_eq!(perform(None, &read).unwrap(), None);
+        let missing = Request::RemoveFile {
+            dir: tmp.path().join("missing"),
+            name: "x".into(),
+        };
+        perform(None, &missing).unwrap();
+    }
+
+    #[test]
+    fn read_config_nofollow_refuses_symlinked_bundle() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(tmp.path().join("real.lnx")).unwrap();
+        std::fs::write(
+            tmp.path().join("real.lnx/config.toml"),
+            "name = \"a\"\nexecutable = \"x\"\n",
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(tmp.path().join("real.lnx"), tmp.path().join("link.lnx"))
+            .unwrap();
+        assert!(load_config(None, &tmp.path().join("link.lnx"), true).is_err());
+        assert!(load_config(None, &tmp.path().join("link.lnx"), false).is_ok());
+    }
+}
@@ -0,0 +1,580 @@
+//! `dotlnx export` / `dotlnx import`: move an app setup to another machine.
+//!
+//! The archive (tar.gz) holds every bundle dotlnx manages (or only their config.toml with
+//! --configs-only), the generated .desktop files and AppArmor profiles, and state.json:
+//!
+//! ```text
+//! manifest.json
+//! bundles/user/<user>/<App>.lnx/...
+//! bundles/system/<App>.lnx/...
+//! generated/desktop/<user|system>/dotlnx-<App>.desktop
+//! generated/apparmor/<profile>
+//! state.json
+//! ```
+//!
+//! Import only restores bundles. Generated files embed host paths (home dirs, profile names), so
+//! they are kept for reference and regenerated by the sync that runs after import. Each bundle
+//! is unpacked like a dropped `.lnx.tar.gz` (see `pack::unpack`): files, directories and
+//! symlinks within the bundle only, without setuid bits, and as the user for a user's home.
+
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::bundle;
+use crate::config;
+use crate::paths;
+use crate::progress::{Progress, ProgressArgs};
+use crate::state;
+
+const MANIFEST: &str = "manifest.json";
+const FORMAT_VERSION: u32 = 1;
+
+/// Describes the archive contents (informational on import; the tar paths are authoritative).
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    created: u64,
+    configs_only: bool,
+    bundles: Vec<ManifestBundle>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ManifestBundle {
+    name: String,
+    /// Owning user for the user tier; None for /Applications.
+    user: Option<String>,
+    /// Bundle directory name (e.g. "MyApp.lnx").
+    dir: String,
+}
+
+/// Entry point for `dotlnx export <archive>`.
+pub fn export(output: &Path, configs_only: bool, progress_args: ProgressArgs) -> Result<()> {
+    let file =
+        std::fs::File::create(output).with_context(|| format!("create {}", output.display()))?;
+    // Counts the uncompressed tar stream; each bundle's share is added to the total as it is found.
+    let progress = Progress::new(progress_args, "export", 0);
+    let mut tar =
+        tar::Builder::new(progress.writer(GzEncoder::new(file, flate2::Compression::default())));
+    tar.follow_symlinks(false);
+
+    let paths = paths::load()?;
+    let mut tiers: Vec<(PathBuf, PathBuf, Option<String>)> = bundle::user_tier_entries(&paths)?
+        .into_iter()
+        .map(|(apps, desktop_dir, user)| (apps, desktop_dir, Some(user)))
+        .collect();
+    if bundle::is_root() {
+        tiers.push((
+            paths.system_apps.clone(),
+            paths.system_desktop.clone(),
+            None,
+        ));
+    }
+
+    let mut manifest = Manifest {
+        version: FORMAT_VERSION,
+        created: state::now(),
+        configs_only,
+        bundles: Vec::new(),
+    };
+    for (apps_dir, desktop_dir, user) in &tiers {
+        let tier_prefix = tier_prefix(user.as_deref());
+        for dir in bundle::discover_lnx_dirs(apps_dir) {
+            let cfg = match config::load(&dir) {
+                Ok(c) => c,
+                Err(e) => {
+                    warn!(bundle = %dir.display(), "not exporting bundle: {}", e);
+                    continue;
+                }
+            };
+            let dir_name = dir
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default()
+                .to_string();
+            let dest = Path::new("bundles").join(&tier_prefix).join(&dir_name);
+            progress.set_item(&dir_name);
+            progress.add_total(if configs_only {
+                tar_size(&dir.join("config.toml"))
+            } else {
+                tar_size(&dir)
+            });
+            if configs_only {
+                tar.append_path_with_name(dir.join("config.toml"), dest.join("config.toml"))?;
+                for file in config::included_files(&dir) {
+                    if dir.join(&file).is_file() {
+                        tar.append_path_with_name(dir.join(&file), dest.join(&file))?;
+                    }
+                }
+            } else {
+                tar.append_dir_all(&dest, &dir)
+                    .with_context(|| format!("archive {}", dir.display()))?;
+            }
+            let entry = desktop_dir.join(paths.desktop_file_name(desktop_dir, &cfg.app_id()));
+            if entry.is_file() {
+                tar.append_path_with_name(
+                    &entry,
+                    Path::new("generated/desktop")
+                        .join(user.as_deref().unwrap_or("system"))
+                        .join(entry.file_name().unwrap_or_default()),
+                )?;
+            }
+            if bundle::is_root() {
+                let profile = paths.profile_name(user.as_deref(), &cfg.app_id());
+                let path = paths.profile_path(&profile);
+                if path.is_file() {
+                    tar.append_path_with_name(
+                        &path,
+                        Path::new("generated/apparmor").join(&profile),
+                    )?;
+                }
+            }
+            manifest.bundles.push(ManifestBundle {
+                name: cfg.name,
+                user: user.clone(),
+                dir: dir_name,
+            });
+        }
+    }
+
+    let state_file = state::state_dir()?.join("state.json");
+    if state_file.is_file() {
+        tar.append_path_with_name(&state_file, "state.json")?;
+    }
+    let manifest_json = serde_json::to_vec_pretty(&manifest)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest_json.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(manifest.created);
+    tar.append_data(&mut header, MANIFEST, manifest_json.as_slice())?;
+    tar.into_inner()?.into_inner().finish()?;
+    progress.finish();
+    info!(
+        archive = %output.display(),
+        bundles = manifest.bundles.len(),
+        "exported"
+    );
+    Ok(())
+}
+
+/// Bytes `path` takes in a tar stream: a 512-byte header per entry, file data padded to 512.
+fn tar_size(path: &Path) -> u64 {
+    walkdir::WalkDir::new(path)
+        .follow_links(false)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .map(|e| {
+            let data = match e.metadata() {
+                Ok(m) if m.is_file() => m.len().div_ceil(512) * 512,
+                _ => 0,
+            };
+            512 + data
+        })
+        .sum()
+}
+
+fn tier_prefix(user: Option<&str>) -> PathBuf {
+    match user {
+        Some(u) => Path::new("user").join(u),
+        None => PathBuf::from("system"),
+    }
+}
+
+/// Where one bundle from the archive should land on this host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Target {
+    apps_dir: PathBuf,
+    /// Chown restored files to this user (root importing into someone's home).
+    owner: Option<String>,
+}
+
+/// Map an archived tier to a local Applications dir. Users that exist here keep their bundles;
+/// when importing for a single user (non-root, or sudo) everything user-tier goes to that user,
+/// since the username on the old machine may differ.
+fn resolve_target(
+    archived_user: Option<&str>,
+    local_users: &HashMap<String, PathBuf>,
+    system_apps: &Path,
+    is_root: bool,
+) -> Option<Target> {
+    let owner_if_root = |u: &str| (is_root && u != "root").then(|| u.to_string());
+    match archived_user {
+        None if is_root => Some(Target {
+            apps_dir: system_apps.to_path_buf(),
+            owner: None,
+        }),
+        None => None,
+        Some(u) => {
+            if let Some(apps) = local_users.get(u) {
+                return Some(Target {
+                    apps_dir: apps.clone(),
+                    owner: owner_if_root(u),
+                });
+            }
+            if local_users.len() == 1 {
+                let (only, apps) = local_users.iter().next()?;
+                return Some(Target {
+                    apps_dir: apps.clone(),
+                    owner: owner_if_root(only),
+                });
+            }
+            None
+        }
+    }
+}
+
+/// An archived bundle: (user for the user tier, bundle dir name).
+type BundleKey = (Option<String>, String);
+
+/// Split "bundles/user/<u>/<X.lnx>/rest" or "bundles/system/<X.lnx>/rest" into
+/// (user, bundle dir name, path inside the bundle). Rejects anything but plain components.
+fn split_bundle_path(path: &Path) -> Option<(Option<String>, String, PathBuf)> {
+    let mut parts = Vec::new();
+    for c in path.components() {
+        match c {
+            Component::Normal(p) => parts.push(p.to_str()?.to_string()),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    let (user, rest) = match parts.as_slice() {
+        [b, t, u, rest @ ..] if b == "bundles" && t == "user" => (Some(u.clone()), rest),
+        [b, t, rest @ ..] if b == "bundles" && t == "system" => (None, rest),
+        _ => return None,
+    };
+    let (dir, inner) = rest.split_first()?;
+    if !dir.ends_with(".lnx") {
+        return None;
+    }
+    Some((user, dir.clone(), inner.iter().collect()))
+}
+
+/// A bundle being restored: where it goes, and its entries copied into a tar.gz of their own
+/// for `restore_bundle` to unpack.
+struct Restore {
+    target: Target,
+    bundle_root: PathBuf,
+    tar: tar::Builder<GzEncoder<tempfile::NamedTempFile>>,
+}
+
+/// Entry point for `dotlnx import <archive>`: restore bundles, then sync to regenerate the rest.
+pub fn import(archive: &Path, force: bool, progress_args: ProgressArgs) -> Result<()> {
+    let is_root = bundle::is_root();
+    let paths = paths::load()?;
+    let local_users: HashMap<String, PathBuf> = bundle::user_tier_entries(&paths)?
+        .into_iter()
+        .map(|(apps, _, user)| (user, apps))
+        .collect();
+    let file =
+        std::fs::File::open(archive).with_context(|| format!("open {}", archive.display()))?;
+    // Counts the compressed archive read so far against its size.
+    let progress = Progress::new(progress_args, "import", file.metadata()?.len());
+    let mut tar = tar::Archive::new(GzDecoder::new(progress.reader(file)));
+    let holds = crate::hold::load(&crate::state::state_dir()?).unwrap_or_default();
+
+    // Per bundle: Some when restoring it, None when skipping it.
+    let mut decisions: HashMap<BundleKey, Option<Restore>> = HashMap::new();
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let Some((user, dir, inner)) = split_bundle_path(&path) else {
+            continue;
+        };
+        let key = (user.clone(), dir.clone());
+        let decision = match decisions.entry(key) {
+            std::collections::hash_map::Entry::Occupied(o) => o.into_mut(),
+            std::collections::hash_map::Entry::Vacant(v) => {
+                let Some(target) =
+                    resolve_target(user.as_deref(), &local_users, &paths.system_apps, is_root)
+                else {
+                    warn!(bundle = %dir, user = ?user, "no matching Applications dir here; skipping");
+                    v.insert(None);
+                    continue;
+                };
+                let bundle_root = target.apps_dir.join(&dir);
+                if bundle_root.exists() && !force {
+                    warn!(bundle = %bundle_root.display(), "already exists; skipping (use --force to overwrite)");
+                    v.insert(None);
+                    continue;
+                }
+                if holds.values().any(|h| h.bundle == bundle_root) {
+                    warn!(bundle = %bundle_root.display(), "held; skipping (dotlnx unhold it first)");
+                    v.insert(None);
+                    continue;
+                }
+                info!(bundle = %bundle_root.display(), "restoring");
+                let staged = tempfile::Builder::new()
+                    .prefix("dotlnx-import-")
+                    .tempfile()?;
+                let tar = tar::Builder::new(GzEncoder::new(staged, flate2::Compression::fast()));
+                v.insert(Some(Restore {
+                    target,
+                    bundle_root,
+                    tar,
+                }))
+            }
+        };
+        let Some(restore) = decision else {
+            continue;
+        };
+        progress.set_item(&dir);
+        check_entry(&entry, &inner)
+            .with_context(|| format!("{}: refusing {}", archive.display(), path.display()))?;
+        let mut header = entry.header().clone();
+        let staged_path = Path::new(&dir).join(&inner);
+        match entry.link_name()? {
+            Some(target) => restore.tar.append_link(&mut header, &staged_path, target)?,
+            None => restore
+                .tar
+                .append_data(&mut header, &staged_path, &mut entry)?,
+        }
+    }
+    progress.finish();
+
+    let mut restored = 0;
+    for restore in decisions.into_values().flatten() {
+        let staged = restore.tar.into_inner()?.finish()?;
+        #[cfg(unix)]
+        if let Some(ref owner) = restore.target.owner {
+            // The user's helper reads it; the archive was theirs to begin with.
+            let user = nix::unistd::User::from_name(owner)?
+                .ok_or_else(|| anyhow::anyhow!("no such user: {:?}", owner))?;
+            std::os::unix::fs::fchown(
+                staged.as_file(),
+                Some(user.uid.as_raw()),
+                Some(user.gid.as_raw()),
+            )?;
+        }
+        let req = crate::asuser::Request::RestoreBundle {
+            archive: staged.path().to_path_buf(),
+            apps_dir: restore.target.apps_dir.clone(),
+            replace: force,
+        };
+        crate::asuser::perform(restore.target.owner.as_deref(), &req)
+            .with_context(|| format!("restore {}", restore.bundle_root.display()))?;
+        restored += 1;
+    }
+    info!(bundles = restored, "imported; syncing");
+    crate::sync::run(false).map(|_| ())
+}
+
+/// Refuse what an exported bundle never holds and unpacking must not create: hard links,
+/// devices and fifos, setuid or setgid files, and symlinks that point outside the bundle.
+/// `inner` is the entry's path inside its bundle.
+fn check_entry<R: Read>(entry: &tar::Entry<R>, inner: &Path) -> Result<()> {
+    let kind = entry.header().entry_type();
+    if !(kind.is_file() || kind.is_dir() || kind.is_symlink()) {
+        anyhow::bail!("not a file, directory or symlink");
+    }
+    if entry.header().mode()? & 0o6000 != 0 {
+        anyhow::bail!("setuid or setgid file");
+    }
+    if let Some(target) = entry.link_name()? {
+        // How deep below the bundle folder the link resolves, component by component.
+        let mut depth = inner.components().count().saturating_sub(1);
+        for c in target.components() {
+            match c {
+                Component::Normal(_) => depth += 1,
+                Component::CurDir => {}
+                Component::ParentDir if depth > 0 => depth -= 1,
+                _ => anyhow::bail!("symlink to {} leaves the bundle", target.display()),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Unpack a bundle `import` staged (a tar.gz of one `<Name>.lnx` folder) into `apps_dir`,
+/// replacing a bundle of that name when `replace`. For a user's bundle this runs as that user
+/// (see `asuser`), so root never creates or renames anything in the home itself.
+pub fn restore_bundle(archive: &Path, apps_dir: &Path, replace: bool) -> Result<PathBuf> {
+    crate::safefs::create_dirs(apps_dir)
+        .with_context(|| format!("create {}", apps_dir.display()))?;
+    let staging = tempfile::Builder::new()
+        .prefix(".dotlnx-import-")
+        .tempdir_in(apps_dir)
+        .with_context(|| format!("create a staging dir in {}", apps_dir.display()))?;
+    let unpacked = crate::pack::unpack(archive, staging.path())?;
+    let dest = apps_dir.join(unpacked.file_name().unwrap_or_default());
+    if dest.symlink_metadata().is_ok() {
+        if !replace {
+            anyhow::bail!("{} already exists", dest.display());
+        }
+        // Moved into the staging dir, which takes it along when dropped.
+        std::fs::rename(&dest, staging.path().join("replaced"))
+            .with_context(|| format!("move {} aside", dest.display()))?;
+    }
+    std::fs::rename(&unpacked, &dest)
+        .with_context(|| format!("move {} into place", dest.display()))?;
+    Ok(dest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_bundle_path_tiers() {
+        assert_eq!(
+            split_bundle_path(Path::new("bundles/user/alice/My App.lnx/bin/run")),
+            Some((
+                Some("alice".into()),
+                "My App.lnx".into(),
+                PathBuf::from("bin/run")
+            ))
+        );
+        assert_eq!(
+            split_bundle_path(Path::new("bundles/system/X.lnx/config.toml")),
+            Some((None, "X.lnx".into(), PathBuf::from("config.toml")))
+        );
+        assert_eq!(
+            split_bundle_path(Path::new("bundles/system/X.lnx")),
+            Some((None, "X.lnx".into(), PathBuf::new()))
+        );
+        assert!(split_bundle_path(Path::new("bundles/system/X.lnx/../../etc/passwd")).is_none());
+        assert!(split_bundle_path(Path::new("bundles/system/notabundle/x")).is_none());
+        assert!(split_bundle_path(Path::new("generated/desktop/alice/dotlnx-x.desktop")).is_none());
+    }
+
+    #[test]
+    fn resolve_target_maps_users() {
+        let mut users = HashMap::new();
+        users.insert("bob".to_string(), PathBuf::from("/home/bob/Applications"));
+        // Single local user: bundles from any archived user land there.
+        let system = Path::new("/Applications");
+        let t = resolve_target(Some("alice"), &users, system, false).unwrap();
+        assert_eq!(t.apps_dir, PathBuf::from("/home/bob/Applications"));
+        assert_eq!(t.owner, None);
+        // System tier needs root.
+        assert!(resolve_target(None, &users, system, false).is_none());
+        assert_eq!(
+            resolve_target(None, &users, system, true).unwrap().apps_dir,
+            system
+        );
+        users.insert(
+            "carol".to_string(),
+            PathBuf::from("/home/carol/Applications"),
+        );
+        let t = resolve_target(Some("carol"), &users, system, true).unwrap();
+        assert_eq!(t.owner.as_deref(), Some("carol"));
+        assert!(resolve_target(Some("alice"), &users, system, true).is_none());
+    }
+
+    /// Check every entry of a tar built by `build`, in order.
+    fn checked(build: impl FnOnce(&mut tar::Builder<Vec<u8>>)) -> Vec<Result<()>> {
+        let mut builder = tar::Builder::new(Vec::new());
+        build(&mut builder);
+        let bytes = builder.into_inner().unwrap();
+        let mut archive = tar::Archive::new(bytes.as_slice());
+        let entries = archive.entries().unwrap();
+        entries
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let path = entry.path().unwrap().into_owned();
+                let (_, _, inner) = split_bundle_path(&path).unwrap();
+                check_entry(&entry, &inner)
+            })
+            .collect()
+    }
+
+    fn header(kind: tar::EntryType, mode: u32) -> tar::Header {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(kind);
+        header.set_mode(mode);
+        header.set_size(0);
+        header
+    }
+
+    #[test]
+    fn import_refuses_hard_links_setuid_files_and_escaping_symlinks() {
+        let results = checked(|b| {
+            let link = |b: &mut tar::Builder<Vec<u8>>, kind, path: &str, target: &str| {
+                b.append_link(&mut header(kind, 0o777), path, target)
+                    .unwrap();
+            };
+            b.append_data(
+                &mut header(tar::EntryType::Regular, 0o755),
+                "bundles/system/X.lnx/run",
+                &[][..],
+            )
+            .unwrap();
+            link(
+                b,
+                tar::EntryType::Symlink,
+                "bundles/system/X.lnx/bin/app",
+                "../run",
+            );
+            link(
+                b,
+                tar::EntryType::Link,
+                "bundles/user/alice/X.lnx/shadow",
+                "/etc/shadow",
+            );
+            b.append_data(
+                &mut header(tar::EntryType::Regular, 0o4755),
+                "bundles/system/X.lnx/suid",
+                &[][..],
+            )
+            .unwrap();
+            b.append_data(
+                &mut header(tar::EntryType::Fifo, 0o644),
+                "bundles/system/X.lnx/fifo",
+                &[][..],
+            )
+            .unwrap();
+            link(
+                b,
+                tar::EntryType::Symlink,
+                "bundles/system/X.lnx/etc",
+                "/etc",
+            );
+            link(
+                b,
+                tar::EntryType::Symlink,
+                "bundles/system/X.lnx/bin/up",
+                "../../Y.lnx/run",
+            );
+        });
+        let ok: Vec<bool> = results.iter().map(|r| r.is_ok()).collect();
+        assert_eq!(ok, [true, true, false, false, false, false, false]);
+    }
+
+    #[test]
+    fn restore_bundle_unpacks_and_replaces_only_when_asked() {
+        let tmp = tempfile::tempdir().unwrap();
+        let staged = tmp.path().join("staged.tar.gz");
+        let mut builder = tar::Builder::new(GzEncoder::new(
+            std::fs::File::create(&staged).unwrap(),
+            flate2::Compression::fast(),
+        ));
+        let data = b"name = \"X\"\nexecutable = \"run\"\n";
+        let mut h = header(tar::EntryType::Regular, 0o644);
+        h.set_size(data.len() as u64);
+        builder
+            .append_data(&mut h, "X.lnx/config.toml", &data[..])
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let apps = tmp.path().join("Applications");
+        let bundle = restore_bundle(&staged, &apps, false).unwrap();
+        assert_eq!(bundle, apps.join("X.lnx"));
+        assert_eq!(std::fs::read(bundle.join("config.toml")).unwrap(), data);
+
+        std::fs::write(bundle.join("old-file"), "").unwrap();
+        assert!(restore_bundle(&staged, &apps, false).is_err());
+        assert!(bundle.join("old-file").exists());
+        restore_bundle(&staged, &apps, true).unwrap();
+        assert!(!bundle.join("old-file").exists());
+        // Only the bundle is left: no staging dirs.
+        let names: Vec<_> = std::fs::read_dir(&apps)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["X.lnx"]);
+    }
+}
@@ -5,25 +5,7 @@ use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
 
 use crate::config;
-use crate::desktop;
-
-/// Path to scan for .lnx bundles (user tier). Uses DOTLNX_APPLICATIONS or ~/Applications.
-pub fn user_applications_dir() -> PathBuf {
-    std::env::var("DOTLNX_APPLICATIONS")
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| {
-            dirs::home_dir()
-                .unwrap_or_else(|| PathBuf::from("."))
-                .join("Applications")
-        })
-}
-
-/// System-wide Applications directory.
-pub fn system_applications_dir() -> PathBuf {
-    std::env::var("DOTLNX_SYSTEM_APPLICATIONS")
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| PathBuf::from("/Applications"))
-}
+use crate::paths::Paths;
 
 /// Discover all .lnx directories under a root path (e.g. ~/Applications or /Applications).
 pub fn discover_lnx_dirs(root: &Path) -> Vec<PathBuf> {
@@ -45,6 +27,8 @@ pub fn discover_lnx_dirs(root: &Path) -> Vec<PathBuf> {
             }
         }
     }
+    // Sorted, so which of two bundles with the same app name wins does not depend on readdir.
+    out.sort();
     out
 }
 
@@ -58,9 +42,377 @@ pub fn is_lnx_bundle(path: &Path) -> bool {
             .unwrap_or(false)
 }
 
+/// Match a file name against a pattern where `*` is any run of characters and `?` any one.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let (p, n): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut pi, mut ni) = (0, 0);
+    // Where the last `*` was and how much of the name it has taken so far.
+    let mut star: Option<(usize, usize)> = None;
+    while ni < n.len() {
+        match p.get(pi) {
+            Some('*') => {
+                star = Some((pi, ni));
+                pi += 1;
+            }
+            Some(&c) if c == '?' || c == n[ni] => {
+                pi += 1;
+                ni += 1;
+            }
+            _ => match star {
+                Some((sp, sn)) => {
+                    pi = sp + 1;
+                    ni = sn + 1;
+                    star = Some((sp, sn + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+/// True when a path inside a bundle (relative to its root) matches one of the config's
+/// `exclude` patterns, or lies under a directory that does. A pattern without `/` matches a
+/// name at any depth (`*.log`, `__pycache__`); one with `/` is matched from the bundle root,
+/// with `**` standing for any number of directories (`cache/**`, `data/*/tmp`).
+pub fn is_excluded(patterns: &[String], relative: &Path) -> bool {
+    let names: Vec<&str> = relative
+        .components()
+        .filter_map(|c| match c {
+            std::path::Component::Normal(n) => n.to_str(),
+            _ => None,
+        })
+        .collect();
+    patterns.iter().any(|pattern| {
+        let pattern = pattern.trim_end_matches('/');
+        if !pattern.contains('/') {
+            return names.iter().any(|n| glob_match(pattern, n));
+        }
+        let parts: Vec<&str> = pattern.split('/').filter(|p| !p.is_empty()).collect();
+        (1..=names.len()).any(|len| path_glob_match(&parts, &names[..len]))
+    })
+}
+
+/// Match path components against pattern components, `**` taking zero or more of them.
+fn path_glob_match(pattern: &[&str], names: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => names.is_empty(),
+        Some((&"**", rest)) => (0..=names.len()).any(|skip| path_glob_match(rest, &names[skip..])),
+        Some((first, rest)) => names
+            .split_first()
+            .is_some_and(|(name, names)| glob_match(first, name) && path_glob_match(rest, names)),
+    }
+}
+
+/// Path of a bundle's executable. A wildcard in its file name (`bin/MyApp-*.AppImage`) picks the
+/// newest matching file, skipping files whose name names an architecture this machine cannot run
+/// and preferring its own architecture, so dropping a new version into bin/ updates the app and
+/// one bundle can carry builds for several machines. Without a match, the pattern itself is
+/// returned (and does not exist).
+pub fn resolve_executable(bundle_root: &Path, executable: &str) -> PathBuf {
+    let path = bundle_root.join(executable);
+    let (Some(dir), Some(pattern)) = (path.parent(), path.file_name().and_then(|n| n.to_str()))
+    else {
+        return path;
+    };
+    if !pattern.contains(['*', '?']) {
+        return path;
+    }
+    let host = crate::arch::Arch::host();
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return path;
+    };
+    entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let name = e.file_name().into_string().ok()?;
+            if !glob_match(pattern, &name) {
+                return None;
+            }
+            // Follows symlinks: a bin/MyApp-latest.AppImage link is a fine match.
+            let meta = std::fs::metadata(e.path()).ok().filter(|m| m.is_file())?;
+            let arch = crate::arch::in_file_name(&name).map(|(a, _)| a);
+            let runs = match (arch, host) {
+                (Some(a), Some(h)) => a.runs_on(h),
+                _ => true,
+            };
+            let native = arch.is_none() || arch == host;
+            runs.then(|| ((native, meta.modified().ok(), name), e.path()))
+        })
+        .max_by(|a, b| a.0.cmp(&b.0))
+        .map(|(_, p)| p)
+        .unwrap_or(path)
+}
+
+/// AppImages beyond the newest `keep` (at least one) of each version series of the bundle's
+/// executable, by modification time as at launch. Only files the executable names count: those
+/// its pattern (`bin/MyApp-*.AppImage`) matches or, for a fixed name, the `.old-<time>` copies
+/// `dotlnx update` leaves. Within that, a series is the files with one `derive_appimage_pattern`
+/// ("MyApp-*-x86_64.AppImage"), so other machines' builds count separately. The file the
+/// executable resolves to and symlinks are never returned.
+pub fn surplus_appimages(bundle_root: &Path, executable: &str, keep: usize) -> Vec<PathBuf> {
+    let path = bundle_root.join(executable);
+    let (Some(bin), Some(pattern)) = (path.parent(), path.file_name().and_then(|n| n.to_str()))
+    else {
+        return Vec::new();
+    };
+    // Through a symlink (bin/MyApp-latest.AppImage) when the executable names one.
+    let current = std::fs::canonicalize(resolve_executable(bundle_root, executable)).ok();
+    let current_series = current
+        .as_deref()
+        .and_then(|p| p.file_name()?.to_str())
+        .and_then(appimage_series);
+    let Ok(entries) = std::fs::read_dir(bin) else {
+        return Vec::new();
+    };
+    let mut series: std::collections::BTreeMap<String, Vec<(std::time::SystemTime, PathBuf)>> =
+        Default::default();
+    for entry in entries.filter_map(|e| e.ok()) {
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        if !glob_match(pattern, without_old_mark(&name)) {
+            continue;
+        }
+        let Some(key) = appimage_series(&name) else {
+            continue;
+        };
+        let Some(meta) = entry.metadata().ok().filter(|m| m.is_file()) else {
+            continue;
+        };
+        if current.is_some() && std::fs::canonicalize(entry.path()).ok() == current {
+            continue;
+        }
+        let modified = meta.modified().unwrap_or(std::time::UNIX_EPOCH);
+        series
+            .entry(key)
+            .or_default()
+            .push((modified, entry.path()));
+    }
+    let mut out: Vec<PathBuf> = series
+        .into_iter()
+        .flat_map(|(key, mut versions)| {
+            versions.sort_by(|a, b| b.cmp(a));
+            // The current version is one of those its series keeps.
+            let kept = keep.max(1) - usize::from(current_series.as_ref() == Some(&key));
+            versions.into_iter().skip(kept).map(|(_, p)| p)
+        })
+        .collect();
+    out.sort();
+    out
+}
+
+/// A file name without the `.old-<time>` mark `dotlnx update` gives a replaced fixed-name
+/// AppImage.
+fn without_old_mark(name: &str) -> &str {
+    match name.rsplit_once(".old-") {
+        Some((base, time)) if !time.is_empty() && time.bytes().all(|b| b.is_ascii_digit()) => base,
+        _ => name,
+    }
+}
+
+/// Version series of an AppImage file name, None for other files. `dotlnx update` marks a
+/// replaced fixed-name AppImage `.old-<time>`; it stays in the series of the name it had.
+fn appimage_series(name: &str) -> Option<String> {
+    let base = without_old_mark(name);
+    let is_appimage =
+        base.len() > ".AppImage".len() && base.to_ascii_lowercase().ends_with(".appimage");
+    is_appimage.then(|| crate::bundler::derive_appimage_pattern(Path::new(base)))
+}
+
+/// Resolve an app by name or ID (`<vendor>.<name>`): user tier first (~/Applications), then system (/Applications), or the
+/// other way round with `[paths] precedence = "system"`. Returns (bundle_path, config,
+/// is_user_tier). With `precedence = "error"`, a name in both tiers is an error.
+/// If the exact name is not found and the name contains underscores, also tries with underscores
+/// replaced by spaces (some launchers incorrectly replace spaces with underscores in the Exec command).
+/// Looks the name up in the index sync keeps in state.json first (no scanning); falls back to
+/// scanning a tier when the index has no usable entry in it (never synced, bundle added, moved or
+/// renamed) before going on to the next tier.
+pub fn resolve_bundle_by_name(
+    paths: &Paths,
+    name: &str,
+) -> anyhow::Result<Option<(PathBuf, config::Config, bool)>> {
+    let spaced = name.contains('_').then(|| name.replace('_', " "));
+    let candidates: Vec<&str> = std::iter::once(name).chain(spaced.as_deref()).collect();
+    let index = load_name_indexes();
+    let tiers = paths.tiers();
+    let Some(found) = find_in_tiers(&index, &tiers, &candidates) else {
+        return Ok(None);
+    };
+    if paths.precedence == crate::settings::Precedence::Error {
+        let other = [tiers
+            .into_iter()
+            .find(|&(_, is_user)| is_user != found.2)
+            .expect("two tiers")];
+        if let Some((shadowed, _, _)) = find_in_tiers(&index, &other, &candidates) {
+            anyhow::bail!(
+                "{} is installed as both {} and {}, and the site config ([paths] precedence = \"error\") lets neither win: remove one",
+                found.1.name,
+                found.0.display(),
+                shadowed.display()
+            );
+        }
+    }
+    Ok(Some(found))
+}
+
+/// The bundle answering to one of `names` in the first of `tiers` that has one. A tier is only
+/// scanned when the index has nothing for it, but it is scanned before the next tier is tried:
+/// an index hit in a lower tier must not shadow a bundle added to a higher one since the last sync.
+fn find_in_tiers(
+    index: &[crate::state::State],
+    tiers: &[(&Path, bool)],
+    names: &[&str],
+) -> Option<(PathBuf, config::Config, bool)> {
+    tiers.iter().find_map(|tier| {
+        let tier = std::slice::from_ref(tier);
+        names
+            .iter()
+            .find_map(|n| lookup_name_index(index, n, tier))
+            .or_else(|| names.iter().find_map(|n| scan_tiers(tier, n)))
+    })
+}
+
+/// Name indexes from this user's state and the daemon's (each covers the tiers its sync saw).
+fn load_name_indexes() -> Vec<crate::state::State> {
+    let mut dirs = vec![PathBuf::from(crate::state::SYSTEM_STATE_DIR)];
+    if let Ok(own) = crate::state::state_dir() {
+        if !dirs.contains(&own) {
+            dirs.insert(0, own);
+        }
+    }
+    dirs.iter()
+        .filter_map(|d| crate::state::State::load(d).ok())
+        .collect()
+}
+
+/// Indexed bundle for `name` in the first of `tiers` (Applications dir, is user tier) that has
+/// one. Each hit is confirmed by loading its config, so a stale index only costs the fallback scan.
+fn lookup_name_index(
+    states: &[crate::state::State],
+    name: &str,
+    tiers: &[(&Path, bool)],
+) -> Option<(PathBuf, config::Config, bool)> {
+    let paths: Vec<&String> = states
+        .iter()
+        .filter_map(|s| s.names.get(name))
+        .flatten()
+        .collect();
+    for &(root, is_user_tier) in tiers {
+        for path in &paths {
+            let dir = Path::new(path.as_str());
+            if dir.parent() != Some(root) || !is_lnx_bundle(dir) {
+                continue;
+            }
+            if let Ok(cfg) = config::load(dir) {
+                if cfg.answers_to(name) {
+                    return Some((dir.to_path_buf(), cfg, is_user_tier));
+                }
+            }
+        }
+    }
+    None
+}
+
+/// First bundle named `name` in `tiers`, by reading every config.
+fn scan_tiers(tiers: &[(&Path, bool)], name: &str) -> Option<(PathBuf, config::Config, bool)> {
+    for &(root, is_user_tier) in tiers {
+        for dir in discover_lnx_dirs(root) {
+            let Ok(cfg) = config::load(&dir) else {
+                continue;
+            };
+            if cfg.answers_to(name) {
+                return Some((dir, cfg, is_user_tier));
+            }
+        }
+    }
+    None
+}
+
+/// Username for user-tier profile: derived from bundle path (e.g. /home/alice/Applications/foo.lnx -> alice).
+pub fn username_from_bundle_path(bundle_path: &Path) -> Option<String> {
+    let apps_dir = bundle_path.parent()?;
+    let home = apps_dir.parent()?;
+    home.file_name().and_then(|n| n.to_str().map(String::from))
+}
+
+/// True when running with effective uid 0 (root). On Unix uses geteuid(); otherwise falls back to USER.
+pub fn is_root() -> bool {
+    #[cfg(unix)]
+    {
+        nix::unistd::geteuid().is_root()
+    }
+    #[cfg(not(unix))]
+    {
+        std::env::var("USER").unwrap_or_default() == "root"
+    }
+}
+
+/// Numeric uid for a username, or None if the user does not exist.
+#[cfg(unix)]
+pub fn user_uid(username: &str) -> Option<u32> {
+    nix::unistd::User::from_name(username)
+        .ok()
+        .flatten()
+        .map(|u| u.uid.as_raw())
+}
+
+#[cfg(not(unix))]
+pub fn user_uid(_username: &str) -> Option<u32> {
+    None
+}
+
+/// User-tier entries (apps_dir, desktop_dir, username) for sync/watch.
+/// When root + SUDO_USER: invoking user only. When root + no SUDO_USER (e.g. daemon): all users. When non-root: current user only.
+/// Non-root uses XDG_DATA_HOME/applications for desktop_dir; root/daemon use default .local/share/applications per user.
+pub fn user_tier_entries(paths: &Paths) -> Result<Vec<(PathBuf, PathBuf, String)>> {
+    let is_root = is_root();
+
+    if is_root {
+        if let Ok(sudo_user) = std::env::var("SUDO_USER") {
+            let home: PathBuf = if sudo_user == "root" {
+                paths.root_home.clone()
+            } else {
+                paths.home_dirs.join(&sudo_user)
+            };
+            let apps = home.join("Applications");
+            let desktop = home.join(".local/share/applications");
+            return Ok(vec![(apps, desktop, sudo_user)]);
+        }
+        // Daemon mode (no SUDO_USER): all users
+        let mut entries = Vec::new();
+        let root_home = &paths.root_home;
+        entries.push((
+            root_home.join("Applications"),
+            root_home.join(".local/share/applications"),
+            "root".into(),
+        ));
+        if let Ok(rd) = std::fs::read_dir(&paths.home_dirs) {
+            for e in rd.filter_map(|e| e.ok()) {
+                let path = e.path();
+                if path.is_dir() {
+                    if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+                        entries.push((
+                            path.join("Applications"),
+                            path.join(".local/share/applications"),
+                            name.to_string(),
+                        ));
+                    }
+                }
+            }
+        }
+        return Ok(entries);
+    }
+
+    let desktop_dir = paths.user_desktop_dir()?;
+    let user = std::env::var("USER").unwrap_or_else(|_| "unknown".into());
+    Ok(vec![(paths.user_apps.clone(), desktop_dir, user)])
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::settings::Precedence;
 
     #[test]
     fn discover_lnx_dirs_finds_bundles() {
@@ -111,6 +463,132 @@ mod tests {
         assert!(!is_lnx_bundle(&dir));
     }
 
+    #[test]
+    fn glob_match_wildcards() {
+        assert!(glob_match(
+            "App-*-x86_64.AppImage",
+            "App-1.2.3-x86_64.AppImage"
+        ));
+        assert!(glob_match("App-*.AppImage", "App-.AppImage"));
+        assert!(glob_match("a?c*", "abc"));
+        assert!(glob_match("*-*-*", "a-b-c-d"));
+        assert!(!glob_match("App-*.AppImage", "App-1.AppImage.part"));
+        assert!(!glob_match("a?c", "ac"));
+        assert!(!glob_match("App-*", "Other-1"));
+    }
+
+    #[test]
+    fn exclude_patterns() {
+        let patterns: Vec<String> = ["cache/**", "*.log", "data/*/tmp"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let excluded = |p: &str| is_excluded(&patterns, Path::new(p));
+        assert!(excluded("cache"));
+        assert!(excluded("cache/a/b.bin"));
+        assert!(excluded("app.log"));
+        assert!(excluded("logs/old/app.log"));
+        assert!(excluded("data/user1/tmp/x"));
+        assert!(!excluded("bin/cache"));
+        assert!(!excluded("app.log.txt"));
+        assert!(!excluded("data/tmp"));
+        assert!(!excluded("config.toml"));
+        assert!(!is_excluded(&[], Path::new("cache")));
+    }
+
+    #[test]
+    fn resolve_executable_picks_newest_match() {
+        let root = tempfile::tempdir().unwrap();
+        let bin = root.path().join("bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        let touch = |name: &str, secs: u64| {
+            let f = std::fs::File::create(bin.join(name)).unwrap();
+            f.set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs))
+                .unwrap();
+        };
+        // Plain paths are not looked at; a pattern without a match stays as it is.
+        assert_eq!(resolve_executable(root.path(), "bin/app"), bin.join("app"));
+        assert_eq!(
+            resolve_executable(root.path(), "bin/App-*.AppImage"),
+            bin.join("App-*.AppImage")
+        );
+
+        touch("App-1.0.AppImage", 100);
+        touch("App-2.0.AppImage", 200);
+        touch("App-3.0.AppImage.zs-old", 300);
+        assert_eq!(
+            resolve_executable(root.path(), "bin/App-*.AppImage"),
+            bin.join("App-2.0.AppImage")
+        );
+
+        // AppImages for another machine are skipped, even when newer.
+        let Some(host) = crate::arch::Arch::host() else {
+            return;
+        };
+        let foreign = crate::arch::ALL.iter().find(|a| !a.runs_on(host)).unwrap();
+        touch(&format!("App-2.0-{}.AppImage", host), 100);
+        touch(&format!("App-3.0-{}.AppImage", foreign), 400);
+        assert_eq!(
+            resolve_executable(root.path(), "bin/App-*-*.AppImage"),
+            bin.join(format!("App-2.0-{}.AppImage", host))
+        );
+    }
+
+    #[test]
+    fn surplus_appimages_keeps_the_newest_of_each_series() {
+        let root = tempfile::tempdir().unwrap();
+        let bin = root.path().join("bin");
+        std::fs::create_dir(&bin).unwrap();
+        let touch = |name: &str, secs: u64| {
+            let f = std::fs::File::create(bin.join(name)).unwrap();
+            f.set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs))
+                .unwrap();
+        };
+        for (i, v) in ["1.0", "1.1", "1.2", "2.0"].iter().enumerate() {
+            touch(&format!("App-{}.AppImage", v), 100 + i as u64);
+        }
+        touch("App-1.0-aarch64.AppImage", 50);
+        touch("Tool-0.1.AppImage", 10);
+        touch("notes-1.txt", 1);
+        touch("Fixed.AppImage", 300);
+        touch("Fixed.AppImage.old-1700000000", 200);
+        touch("Fixed.AppImage.old-1600000000", 100);
+        std::os::unix::fs::symlink("App-1.0.AppImage", bin.join("App-0.1.AppImage")).unwrap();
+
+        // Only the pattern's own files: not Tool, not Fixed; the aarch64 build is its own series.
+        let pattern = "bin/App-*.AppImage";
+        assert_eq!(
+            surplus_appimages(root.path(), pattern, 2),
+            [bin.join("App-1.0.AppImage"), bin.join("App-1.1.AppImage")]
+        );
+        assert_eq!(
+            surplus_appimages(root.path(), pattern, 0).len(),
+            surplus_appimages(root.path(), pattern, 1).len()
+        );
+        assert!(surplus_appimages(root.path(), pattern, 5).is_empty());
+        assert!(surplus_appimages(&root.path().join("missing"), pattern, 1).is_empty());
+        // A fixed name: only the copies `dotlnx update` moved aside are versions of it.
+        assert_eq!(
+            surplus_appimages(root.path(), "bin/Fixed.AppImage", 2),
+            [bin.join("Fixed.AppImage.old-1600000000")]
+        );
+    }
+
+    #[test]
+    fn surplus_appimages_never_prunes_a_fixed_executable() {
+        let root = tempfile::tempdir().unwrap();
+        let bin = root.path().join("bin");
+        std::fs::create_dir(&bin).unwrap();
+        let touch = |name: &str, secs: u64| {
+            let f = std::fs::File::create(bin.join(name)).unwrap();
+            f.set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs))
+                .unwrap();
+        };
+        touch("App.AppImage", 100);
+        touch("App-2.0.AppImage", 200);
+        assert!(surplus_appimages(root.path(), "bin/App.AppImage", 1).is_empty());
+    }
+
     #[test]
     fn username_from_bundle_path_linux_style() {
         let path = PathBuf::from("/home/alice/Applications/myapp.lnx");
@@ -145,127 +623,125 @@ executable = "bin/app"
         std::fs::create_dir_all(bundle_dir.join("bin")).unwrap();
         std::fs::write(bundle_dir.join("bin/app"), "#!/bin/sh\nexit 0").unwrap();
 
-        let prev = std::env::var_os("DOTLNX_APPLICATIONS");
-        std::env::set_var("DOTLNX_APPLICATIONS", apps);
-        let result = resolve_bundle_by_name("My_App");
-        match &prev {
-            Some(v) => std::env::set_var("DOTLNX_APPLICATIONS", v),
-            None => std::env::remove_var("DOTLNX_APPLICATIONS"),
-        }
+        let paths = Paths {
+            user_apps: apps.to_path_buf(),
+            ..Paths::under(&root.path().join("host"))
+        };
+        let result = resolve_bundle_by_name(&paths, "My_App");
 
         let (path, cfg, _) = result.unwrap().unwrap();
         assert_eq!(cfg.name, "My App");
         assert!(path.ends_with("My App.lnx"));
     }
-}
-
-/// Resolve an app by name: user tier first (~/Applications), then system (/Applications).
-/// Returns (bundle_path, config, is_user_tier). User tier wins when same name exists in both.
-/// If the exact name is not found and the name contains underscores, also tries with underscores
-/// replaced by spaces (some launchers incorrectly replace spaces with underscores in the Exec command).
-pub fn resolve_bundle_by_name(name: &str) -> anyhow::Result<Option<(PathBuf, config::Config, bool)>> {
-    if let Some(r) = resolve_bundle_by_name_exact(name)? {
-        return Ok(Some(r));
-    }
-    if name.contains('_') {
-        let name_with_spaces = name.replace('_', " ");
-        if let Some(r) = resolve_bundle_by_name_exact(&name_with_spaces)? {
-            return Ok(Some(r));
-        }
-    }
-    Ok(None)
-}
 
-fn resolve_bundle_by_name_exact(name: &str) -> anyhow::Result<Option<(PathBuf, config::Config, bool)>> {
-    let user_root = user_applications_dir();
-    for dir in discover_lnx_dirs(&user_root) {
-        let cfg = match config::load(&dir) {
-            Ok(c) => c,
-            Err(_) => continue,
-            };
-        if cfg.name == name {
-            return Ok(Some((dir, cfg, true)));
-        }
-    }
-    let system_root = system_applications_dir();
-    for dir in discover_lnx_dirs(&system_root) {
-        let cfg = match config::load(&dir) {
-            Ok(c) => c,
-            Err(_) => continue,
-            };
-        if cfg.name == name {
-            return Ok(Some((dir, cfg, false)));
+    #[test]
+    fn precedence_picks_the_tier_of_a_name_in_both() {
+        let root = tempfile::tempdir().unwrap();
+        let mut paths = Paths {
+            user_apps: root.path().join("user"),
+            system_apps: root.path().join("system"),
+            ..Paths::under(&root.path().join("host"))
+        };
+        for apps in [&paths.user_apps, &paths.system_apps] {
+            let b = apps.join("Dup.lnx");
+            std::fs::create_dir_all(&b).unwrap();
+            std::fs::write(
+                b.join("config.toml"),
+                "name = \"Dup App\"\nexecutable = \"bin/app\"\n",
+            )
+            .unwrap();
         }
-    }
-    Ok(None)
-}
+        let (_, _, is_user) = resolve_bundle_by_name(&paths, "Dup App").unwrap().unwrap();
+        assert!(is_user, "user tier wins by default");
 
-/// Username for user-tier profile: derived from bundle path (e.g. /home/alice/Applications/foo.lnx -> alice).
-pub fn username_from_bundle_path(bundle_path: &Path) -> Option<String> {
-    let apps_dir = bundle_path.parent()?;
-    let home = apps_dir.parent()?;
-    home.file_name().and_then(|n| n.to_str().map(String::from))
-}
+        paths.precedence = Precedence::System;
+        let (path, _, is_user) = resolve_bundle_by_name(&paths, "Dup_App").unwrap().unwrap();
+        assert_eq!(path, paths.system_apps.join("Dup.lnx"));
+        assert!(!is_user);
 
-/// True when running with effective uid 0 (root). On Unix uses geteuid(); otherwise falls back to USER.
-pub fn is_root() -> bool {
-    #[cfg(unix)]
-    {
-        nix::unistd::geteuid().is_root()
+        paths.precedence = Precedence::Error;
+        let err = resolve_bundle_by_name(&paths, "Dup App")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("precedence"), "{}", err);
+        std::fs::remove_dir_all(paths.system_apps.join("Dup.lnx")).unwrap();
+        assert!(
+            resolve_bundle_by_name(&paths, "Dup App")
+                .unwrap()
+                .unwrap()
+                .2
+        );
     }
-    #[cfg(not(unix))]
-    {
-        std::env::var("USER").unwrap_or_default() == "root"
-    }
-}
 
-/// User-tier entries (apps_dir, desktop_dir, username) for sync/watch.
-/// When root + SUDO_USER: invoking user only. When root + no SUDO_USER (e.g. daemon): all users. When non-root: current user only.
-/// Non-root uses XDG_DATA_HOME/applications for desktop_dir; root/daemon use default .local/share/applications per user.
-pub fn user_tier_entries() -> Result<Vec<(PathBuf, PathBuf, String)>> {
-    let is_root = is_root();
-
-    if is_root {
-        if let Ok(sudo_user) = std::env::var("SUDO_USER") {
-            let home: PathBuf = if sudo_user == "root" {
-                PathBuf::from("/root")
-            } else {
-                PathBuf::from("/home").join(&sudo_user)
-            };
-            let apps = home.join("Applications");
-            let desktop = home.join(".local/share/applications");
-            return Ok(vec![(apps, desktop, sudo_user)]);
-        }
-        // Daemon mode (no SUDO_USER): all users
-        let mut entries = Vec::new();
-        let root_home = PathBuf::from("/root");
-        entries.push((
-            root_home.join("Applications"),
-            root_home.join(".local/share/applications"),
-            "root".into(),
-        ));
-        if let Ok(rd) = std::fs::read_dir("/home") {
-            for e in rd.filter_map(|e| e.ok()) {
-                let path = e.path();
-                if path.is_dir() {
-                    if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                        entries.push((
-                            path.join("Applications"),
-                            path.join(".local/share/applications"),
-                            name.to_string(),
-                        ));
-                    }
-                }
-            }
+    #[test]
+    fn name_index_hits_are_verified() {
+        let root = tempfile::tempdir().unwrap();
+        let user = root.path().join("user");
+        let system = root.path().join("system");
+        for (dir, name) in [(&user, "Editor"), (&system, "Editor")] {
+            let b = dir.join("Ed.lnx");
+            std::fs::create_dir_all(&b).unwrap();
+            std::fs::write(
+                b.join("config.toml"),
+                format!("name = \"{}\"\nexecutable = \"bin/app\"\n", name),
+            )
+            .unwrap();
         }
-        return Ok(entries);
+        let mut state = crate::state::State::default();
+        state.names.insert(
+            "Editor".into(),
+            vec![
+                system.join("Ed.lnx").display().to_string(),
+                user.join("Ed.lnx").display().to_string(),
+            ],
+        );
+        state.names.insert(
+            "Gone".into(),
+            vec![user.join("Gone.lnx").display().to_string()],
+        );
+        let states = [state];
+        let (path, _, is_user) =
+            lookup_name_index(&states, "Editor", &[(&user, true), (&system, false)]).unwrap();
+        assert_eq!(path, user.join("Ed.lnx"), "user tier wins");
+        assert!(is_user);
+        assert!(lookup_name_index(&states, "Gone", &[(&user, true), (&system, false)]).is_none());
+        // Renamed since the last sync: the index entry no longer matches the config.
+        std::fs::write(
+            user.join("Ed.lnx/config.toml"),
+            "name = \"Other\"\nexecutable = \"bin/app\"\n",
+        )
+        .unwrap();
+        let (path, _, is_user) =
+            lookup_name_index(&states, "Editor", &[(&user, true), (&system, false)]).unwrap();
+        assert_eq!(path, system.join("Ed.lnx"));
+        assert!(!is_user);
     }
 
-    let home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
-    let apps = std::env::var("DOTLNX_APPLICATIONS")
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| home.join("Applications"));
-    let desktop_dir = desktop::user_applications_dir()?;
-    let user = std::env::var("USER").unwrap_or_else(|_| "unknown".into());
-    Ok(vec![(apps, desktop_dir, user)])
+    #[test]
+    fn unindexed_bundle_in_a_higher_tier_wins_over_an_indexed_one() {
+        let root = tempfile::tempdir().unwrap();
+        let (user, system) = (root.path().join("user"), root.path().join("system"));
+        for dir in [&user, &system] {
+            let b = dir.join("Ed.lnx");
+            std::fs::create_dir_all(&b).unwrap();
+            std::fs::write(
+                b.join("config.toml"),
+                "name = \"Editor\"\nexecutable = \"bin/app\"\n",
+            )
+            .unwrap();
+        }
+        // Synced before the user added their own copy.
+        let mut state = crate::state::State::default();
+        state.names.insert(
+            "Editor".into(),
+            vec![system.join("Ed.lnx").display().to_string()],
+        );
+        let states = [state];
+        let tiers = [(user.as_path(), true), (system.as_path(), false)];
+        let (path, _, is_user) = find_in_tiers(&states, &tiers, &["Editor"]).unwrap();
+        assert_eq!(path, user.join("Ed.lnx"));
+        assert!(is_user);
+        let (path, _, _) = find_in_tiers(&states, &tiers[1..], &["Editor"]).unwrap();
+        assert_eq!(path, system.join("Ed.lnx"));
+    }
 }
@@ -3,86 +3,133 @@
 use anyhow::Result;
 use std::path::{Path, PathBuf};
 
+use crate::appmeta;
+use crate::arch::{self, Arch};
+use crate::oci;
+use crate::progress::{self, Progress, ProgressArgs};
 use crate::validate;
 
 /// Derive a glob pattern from an AppImage path so run.sh can pick the newest of multiple versions.
-/// E.g. "Cursor-0.1.0-x86_64.appimage" -> "Cursor-*-x86_64.appimage".
+/// E.g. "Cursor-0.1.0-x86_64.AppImage" -> "Cursor-*-x86_64.AppImage". The extension keeps its case
+/// and an architecture in the name (whose digits are not a version) is kept as is.
 pub fn derive_appimage_pattern(appimage_path: &Path) -> String {
     let name = appimage_path
         .file_name()
         .and_then(|n| n.to_str())
-        .unwrap_or("*.appimage");
-    if !name.ends_with(".appimage") {
-        return "*.appimage".to_string();
+        .unwrap_or("*.AppImage");
+    let ext_at = name.len().saturating_sub(".appimage".len());
+    if !name.is_char_boundary(ext_at) || !name[ext_at..].eq_ignore_ascii_case(".appimage") {
+        return "*.AppImage".to_string();
     }
-    // Replace first version-like segment (digits and dots) with *
-    let base = &name[..name.len() - ".appimage".len()];
+    let (base, ext) = name.split_at(ext_at);
+    let arch_span = arch::in_file_name(base).and_then(|(_, word)| {
+        let at = base.find(word)?;
+        Some(at..at + word.len())
+    });
+    // Replace the first version-like segment (digits and dots) with *
     let mut in_version = false;
     let mut version_start = 0;
     for (i, c) in base.char_indices() {
-        if c.is_ascii_digit() || c == '.' {
+        let versionish =
+            (c.is_ascii_digit() || c == '.') && !arch_span.as_ref().is_some_and(|r| r.contains(&i));
+        if versionish {
             if !in_version {
                 in_version = true;
                 version_start = i;
             }
-        } else {
-            if in_version {
-                // Replace segment [version_start, i) with *
-                return format!(
-                    "{}*{}.appimage",
-                    &base[..version_start],
-                    &base[i..]
-                );
-            }
+        } else if in_version {
+            return format!("{}*{}{}", &base[..version_start], &base[i..], ext);
         }
     }
     if in_version {
-        return format!("{}*.appimage", &base[..version_start]);
+        return format!("{}*{}", &base[..version_start], ext);
     }
-    "*.appimage".to_string()
+    format!("*{}", ext)
 }
 
-/// Escape for use inside a bash double-quoted string (backslash and double-quote).
+/// Escape for use inside a bash double-quoted string (backslash, double-quote, $ and backtick).
 fn escape_bash_double_quoted(s: &str) -> String {
-    s.replace('\\', "\\\\").replace('"', "\\\"")
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('$', "\\$")
+        .replace('`', "\\`")
 }
 
-/// Generate run.sh content for an appimage bundle: finds latest matching appimage in bin/ and execs it.
+/// Generate run.sh content for an appimage bundle: picks the most recently added AppImage in bin/
+/// matching the pattern and execs it. POSIX sh, so it also runs on minimal (busybox) systems. When
+/// the pattern names an architecture, the script substitutes the machine's, so one bundle can
+/// carry AppImages for several.
 fn run_sh_appimage(app_name: &str, appimage_pattern: &str) -> String {
     let name_escaped = escape_bash_double_quoted(app_name);
-    format!(
-        r#"#!/usr/bin/env bash
-
-APPIMAGE="{pattern}"
-
-set -e
-cd "$(dirname "$0")"
-latest=$(ls bin/$APPIMAGE 2>/dev/null | sort -V | tail -1)
-if [[ -z "$latest" ]]; then
-  echo "No {name} appimage (bin/$APPIMAGE) found in $(pwd)" >&2
+    let mut script = String::from("#!/bin/sh\nset -e\ncd \"$(dirname \"$0\")\"\n");
+    let pattern = match arch::in_file_name(appimage_pattern) {
+        Some((found, word)) => {
+            // Name the machine the way the file does ("x86_64" or Debian's "amd64").
+            let debian = word == found.debian_name() && word != found.as_str();
+            script.push_str("case \"$(uname -m)\" in\n");
+            for a in arch::ALL {
+                let name = if debian { a.debian_name() } else { a.as_str() };
+                script.push_str(&format!("  {}) arch={} ;;\n", a.uname_pattern(), name));
+            }
+            script.push_str("  *) arch=$(uname -m) ;;\nesac\n");
+            let (before, after) =
+                appimage_pattern.split_at(appimage_pattern.find(word).unwrap_or(0));
+            format!(
+                "{}${{arch}}{}",
+                escape_bash_double_quoted(before),
+                escape_bash_double_quoted(&after[word.len()..])
+            )
+        }
+        None => escape_bash_double_quoted(appimage_pattern),
+    };
+    script.push_str(&format!(
+        r#"APPIMAGE="{pattern}"
+latest=
+for f in bin/$APPIMAGE; do
+  [ -f "$f" ] || continue
+  if [ -z "$latest" ] || [ "$f" -nt "$latest" ]; then
+    latest=$f
+  fi
+done
+if [ -z "$latest" ]; then
+  echo "No {name} AppImage (bin/$APPIMAGE) found in $(pwd)" >&2
   exit 1
 fi
-exec "$(pwd)/bin/$latest" "$@"
+exec "$(pwd)/$latest" "$@"
 "#,
-        pattern = appimage_pattern,
+        pattern = pattern,
         name = name_escaped
-    )
+    ));
+    script
+}
+
+/// `arch = [...]` line for config.toml recording what a copied-in binary was built for, so
+/// validate can warn on other machines; empty for scripts.
+fn arch_line(binary: &Path) -> String {
+    match Arch::of_file(binary) {
+        Some(a) => format!(
+            "# Built for {}; validate warns on other machines.\narch = [\"{}\"]\n",
+            a, a
+        ),
+        None => String::new(),
+    }
 }
 
 /// Create an appimage-type .lnx bundle: bin/ (AppImage copied in), config.toml, run.sh, assets/.
+/// With `builtin_launcher`, no run.sh: `executable` is the AppImage pattern itself and `dotlnx run`
+/// picks the newest match (see `bundle::resolve_executable`).
 pub fn create_appimage_bundle(
     app_name: &str,
     appimage_path: &Path,
     output_dir: &Path,
+    builtin_launcher: bool,
+    progress: &Progress,
 ) -> Result<PathBuf> {
     let dir_name = format!("{}.lnx", app_name.trim());
     let bundle_root = output_dir.join(&dir_name);
 
     if bundle_root.exists() {
-        anyhow::bail!(
-            "bundle directory already exists: {}",
-            bundle_root.display()
-        );
+        anyhow::bail!("bundle directory already exists: {}", bundle_root.display());
     }
 
     if !appimage_path.exists() {
@@ -101,7 +148,7 @@ pub fn create_appimage_bundle(
         .and_then(|n| n.to_str())
         .unwrap_or("app.appimage");
     let dest = bin_dir.join(filename);
-    std::fs::copy(appimage_path, &dest)?;
+    copy_file(appimage_path, &dest, progress)?;
     #[cfg(unix)]
     {
         use std::os::unix::fs::PermissionsExt;
@@ -111,6 +158,29 @@ pub fn create_appimage_bundle(
     }
 
     let pattern = derive_appimage_pattern(appimage_path);
+    if builtin_launcher {
+        // The launcher skips AppImages for other machines, so any architecture may match.
+        let pattern = match arch::in_file_name(&pattern) {
+            Some((_, word)) => pattern.replacen(word, "*", 1),
+            None => pattern,
+        };
+        let config_toml = format!(
+            r#"# dotlnx bundle: {}
+# bin/ (AppImage copied in). dotlnx runs the newest AppImage in bin/ matching executable.
+# Drop icon.png into assets/.
+
+name = "{}"
+executable = "bin/{}"
+icon = "assets/icon.png"
+{}"#,
+            app_name,
+            app_name.replace('"', "\\\""),
+            pattern.replace('\\', "\\\\").replace('"', "\\\""),
+            arch_line(&dest)
+        );
+        std::fs::write(bundle_root.join("config.toml"), config_toml)?;
+        return Ok(bundle_root);
+    }
     let run_sh = run_sh_appimage(app_name, &pattern);
     let run_sh_path = bundle_root.join("run.sh");
     std::fs::write(&run_sh_path, run_sh)?;
@@ -129,9 +199,10 @@ pub fn create_appimage_bundle(
 name = "{}"
 executable = "run.sh"
 icon = "assets/icon.png"
-"#,
+{}"#,
         app_name,
-        app_name.replace('"', "\\\"")
+        app_name.replace('"', "\\\""),
+        arch_line(&dest)
     );
     std::fs::write(bundle_root.join("config.toml"), config_toml)?;
 
@@ -143,22 +214,23 @@ pub fn create_bin_bundle(
     app_name: &str,
     executable_path: &Path,
     output_dir: &Path,
+    progress: &Progress,
 ) -> Result<PathBuf> {
     let dir_name = format!("{}.lnx", app_name.trim());
     let bundle_root = output_dir.join(&dir_name);
 
     if bundle_root.exists() {
-        anyhow::bail!(
-            "bundle directory already exists: {}",
-            bundle_root.display()
-        );
+        anyhow::bail!("bundle directory already exists: {}", bundle_root.display());
     }
 
     if !executable_path.exists() {
         anyhow::bail!("executable not found: {}", executable_path.display());
     }
     if !executable_path.is_file() {
-        anyhow::bail!("executable path is not a file: {}", executable_path.display());
+        anyhow::bail!(
+            "executable path is not a file: {}",
+            executable_path.display()
+        );
     }
 
     let bin_dir = bundle_root.join("bin");
@@ -170,7 +242,7 @@ pub fn create_bin_bundle(
         .and_then(|n| n.to_str())
         .unwrap_or("run");
     let dest = bin_dir.join(filename);
-    std::fs::copy(executable_path, &dest)?;
+    copy_file(executable_path, &dest, progress)?;
     #[cfg(unix)]
     {
         use std::os::unix::fs::PermissionsExt;
@@ -187,47 +259,424 @@ pub fn create_bin_bundle(
 name = "{}"
 executable = "{}"
 icon = "assets/icon.png"
-"#,
+{}"#,
         app_name,
         app_name.replace('"', "\\\""),
-        executable
+        executable,
+        arch_line(&dest)
     );
     std::fs::write(bundle_root.join("config.toml"), config_toml)?;
 
     Ok(bundle_root)
 }
 
-/// Entry point for `dotlnx bundle --appname "..." --appimage <path>` or `--bin <path>`.
+/// run.sh for a container bundle: hands off to dotlnx, which builds the bubblewrap sandbox from
+/// config.toml each launch.
+const RUN_SH_OCI: &str = r#"#!/bin/sh
+# Starts the container image in rootfs/ in a bubblewrap sandbox built from [security] in config.toml.
+exec dotlnx __oci-run "$(dirname "$(readlink -f "$0")")" "$@"
+"#;
+
+/// Create a container-image .lnx bundle: rootfs/ (image layers applied), oci.json (entrypoint,
+/// env), run.sh, config.toml, assets/.
+pub fn create_oci_bundle(app_name: &str, reference: &str, output_dir: &Path) -> Result<PathBuf> {
+    let dir_name = format!("{}.lnx", app_name.trim());
+    let bundle_root = output_dir.join(&dir_name);
+
+    if bundle_root.exists() {
+        anyhow::bail!("bundle directory already exists: {}", bundle_root.display());
+    }
+    std::fs::create_dir_all(bundle_root.join("assets"))?;
+    let image = match oci::pull_into(reference, &bundle_root) {
+        Ok(image) => image,
+        Err(e) => {
+            let _ = std::fs::remove_dir_all(&bundle_root);
+            return Err(e);
+        }
+    };
+    write_oci_files(app_name, &image, &bundle_root)?;
+    Ok(bundle_root)
+}
+
+/// Everything in a container bundle except rootfs/.
+fn write_oci_files(app_name: &str, image: &oci::ImageConfig, bundle_root: &Path) -> Result<()> {
+    std::fs::write(
+        bundle_root.join(oci::IMAGE_FILE),
+        serde_json::to_string_pretty(image)?,
+    )?;
+    let run_sh_path = bundle_root.join("run.sh");
+    std::fs::write(&run_sh_path, RUN_SH_OCI)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut perms = std::fs::metadata(&run_sh_path)?.permissions();
+        perms.set_mode(0o755);
+        std::fs::set_permissions(&run_sh_path, perms)?;
+    }
+
+    let config_toml = format!(
+        r#"# dotlnx bundle: {}
+# rootfs/ (from {}). run.sh starts it with bubblewrap: read-only image, private /tmp and HOME,
+# no network. [security] network, read_paths and write_paths open the sandbox up. AppArmor
+# confinement is off because bubblewrap needs user namespaces; the sandbox is the confinement.
+
+name = "{}"
+executable = "run.sh"
+icon = "assets/icon.png"
+
+[security]
+confine = false
+network = false
+read_paths = []
+write_paths = []
+"#,
+        app_name,
+        image.image,
+        app_name.replace('"', "\\\"")
+    );
+    std::fs::write(bundle_root.join("config.toml"), config_toml)?;
+    Ok(())
+}
+
+/// Wine prefix location inside a Wine bundle.
+const WINE_PREFIX_DIR: &str = "data/prefix";
+
+/// Host locations of wine, wineserver and their helpers (distro packages and /opt builds).
+const WINE_EXEC_PATHS: &[&str] = &[
+    "/usr/bin/bash",
+    "/usr/bin/wine*",
+    "/usr/lib/wine/**",
+    "/usr/lib/x86_64-linux-gnu/wine/**",
+    "/usr/lib/i386-linux-gnu/wine/**",
+    "/usr/lib64/wine/**",
+    "/opt/wine*/**",
+];
+
+/// Generate run.sh for a Wine bundle: runs `program` (relative to the prefix) with the bundle's
+/// own WINEPREFIX. Uses only bash builtins before exec so the profile needs no extra tools.
+fn run_sh_wine(app_name: &str, program: &str) -> String {
+    format!(
+        r#"#!/bin/bash
+# Runs PROGRAM (relative to data/prefix) with Wine, using the bundle's own prefix.
+# Set WINE to use another build (e.g. Proton's files/bin/wine).
+
+PROGRAM="{program}"
+
+set -e
+here="$(cd "${{0%/*}}" && pwd)"
+export WINEPREFIX="$here/{prefix}"
+export WINEDEBUG="${{WINEDEBUG:--all}}"
+if [[ -z "$PROGRAM" ]]; then
+  echo "{name}: set PROGRAM in $here/run.sh to the .exe to start (relative to {prefix})" >&2
+  exit 1
+fi
+exec "${{WINE:-wine}}" "$WINEPREFIX/$PROGRAM" "$@"
+"#,
+        program = escape_bash_double_quoted(program),
+        prefix = WINE_PREFIX_DIR,
+        name = escape_bash_double_quoted(app_name)
+    )
+}
+
+/// Accept `C:\Program Files\App\app.exe` or `drive_c/Program Files/App/app.exe`; returns the
+/// path relative to the prefix.
+fn wine_program_path(program: &str) -> Result<String> {
+    let unix = program.replace('\\', "/");
+    let rel = match unix.get(..2) {
+        Some(drive) if drive.eq_ignore_ascii_case("c:") => {
+            format!("drive_c/{}", unix[2..].trim_start_matches('/'))
+        }
+        _ => unix,
+    };
+    validate::path_stays_in_bundle(&rel)?;
+    Ok(rel)
+}
+
+/// Installed programs in a prefix: .exe files under Program Files, minus uninstallers and setup
+/// programs. Paths are relative to the prefix.
+fn find_wine_programs(prefix: &Path) -> Vec<String> {
+    let mut found = Vec::new();
+    for dir in ["drive_c/Program Files", "drive_c/Program Files (x86)"] {
+        for entry in walkdir::WalkDir::new(prefix.join(dir))
+            .into_iter()
+            .filter_map(|e| e.ok())
+        {
+            let name = entry.file_name().to_string_lossy().to_lowercase();
+            let skip = ["unins", "uninst", "setup", "install", "update"];
+            if entry.file_type().is_file()
+                && name.ends_with(".exe")
+                && !skip.iter().any(|s| name.starts_with(s))
+            {
+                if let Ok(rel) = entry.path().strip_prefix(prefix) {
+                    found.push(rel.to_string_lossy().into_owned());
+                }
+            }
+        }
+    }
+    found.sort();
+    found
+}
+
+/// Copy a directory tree, recreating symlinks as symlinks (a prefix's dosdevices/ links drive
+/// letters to ../drive_c and /).
+pub fn copy_tree(src: &Path, dest: &Path, progress: &Progress) -> Result<()> {
+    for entry in walkdir::WalkDir::new(src).follow_links(false) {
+        let entry = entry?;
+        let target = dest.join(entry.path().strip_prefix(src)?);
+        let ft = entry.file_type();
+        if ft.is_dir() {
+            std::fs::create_dir_all(&target)?;
+        } else if ft.is_symlink() {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(std::fs::read_link(entry.path())?, &target)?;
+        } else if ft.is_file() {
+            copy_file(entry.path(), &target, progress)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copy one file's contents and permissions like `std::fs::copy`, counting the bytes as done.
+pub fn copy_file(src: &Path, dest: &Path, progress: &Progress) -> Result<u64> {
+    let mut from = std::fs::File::open(src)?;
+    let perms = from.metadata()?.permissions();
+    let mut to = std::fs::File::create(dest)?;
+    let n = std::io::copy(&mut progress.reader(&mut from), &mut to)?;
+    to.set_permissions(perms)?;
+    Ok(n)
+}
+
+/// Run a Windows installer (.exe or .msi) with Wine into `prefix` (created by Wine on first use).
+fn run_wine_installer(installer: &Path, prefix: &Path) -> Result<()> {
+    let mut cmd = std::process::Command::new("wine");
+    let is_msi = installer
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("msi"));
+    if is_msi {
+        cmd.args(["msiexec", "/i"]);
+    }
+    let status = cmd
+        .arg(installer)
+        .env("WINEPREFIX", prefix)
+        .env("WINEDEBUG", "-all")
+        .status()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                anyhow::anyhow!("wine not found; install Wine to bundle Windows applications")
+            }
+            _ => anyhow::anyhow!("run wine: {}", e),
+        })?;
+    if !status.success() {
+        anyhow::bail!("installer {} failed ({})", installer.display(), status);
+    }
+    Ok(())
+}
+
+/// Create a Wine .lnx bundle: data/prefix (from running `source` as an installer, or a copy of an
+/// existing WINEPREFIX directory), run.sh, config.toml with GPU/audio presets, assets/.
+/// `program`: the .exe to start; guessed from Program Files when there is exactly one candidate.
+pub fn create_wine_bundle(
+    app_name: &str,
+    source: &Path,
+    program: Option<&str>,
+    output_dir: &Path,
+    progress: &Progress,
+) -> Result<PathBuf> {
+    let dir_name = format!("{}.lnx", app_name.trim());
+    let bundle_root = output_dir.join(&dir_name);
+
+    if bundle_root.exists() {
+        anyhow::bail!("bundle directory already exists: {}", bundle_root.display());
+    }
+    if !source.exists() {
+        anyhow::bail!("installer or prefix not found: {}", source.display());
+    }
+    let program = program.map(wine_program_path).transpose()?;
+
+    std::fs::create_dir_all(bundle_root.join("assets"))?;
+    let prefix = bundle_root.join(WINE_PREFIX_DIR);
+    let populated = if source.is_dir() {
+        if !source.join("drive_c").is_dir() {
+            anyhow::bail!("{} is not a Wine prefix (no drive_c/)", source.display());
+        }
+        copy_tree(source, &prefix, progress)
+    } else {
+        std::fs::create_dir_all(&prefix)?;
+        run_wine_installer(source, &prefix)
+    };
+    if let Err(e) = populated {
+        let _ = std::fs::remove_dir_all(&bundle_root);
+        return Err(e);
+    }
+
+    let program = match program {
+        Some(p) => p,
+        None => {
+            let candidates = find_wine_programs(&prefix);
+            match candidates.as_slice() {
+                [only] => only.clone(),
+                _ => {
+                    tracing::warn!(
+                        candidates = ?candidates,
+                        "could not tell which program to start; set PROGRAM in run.sh (or re-run with --wine-program)"
+                    );
+                    String::new()
+                }
+            }
+        }
+    };
+    write_wine_files(app_name, &program, &bundle_root)?;
+    Ok(bundle_root)
+}
+
+/// run.sh and config.toml for a Wine bundle.
+fn write_wine_files(app_name: &str, program: &str, bundle_root: &Path) -> Result<()> {
+    let run_sh_path = bundle_root.join("run.sh");
+    std::fs::write(&run_sh_path, run_sh_wine(app_name, program))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut perms = std::fs::metadata(&run_sh_path)?.permissions();
+        perms.set_mode(0o755);
+        std::fs::set_permissions(&run_sh_path, perms)?;
+    }
+
+    let exec_paths = WINE_EXEC_PATHS
+        .iter()
+        .map(|p| format!("\"{}\"", p))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let config_toml = format!(
+        r#"# dotlnx bundle: {}
+# Windows application: Wine prefix in {}/, run.sh starts PROGRAM from it with wine.
+# The prefix must stay writable by the user, so keep this bundle in ~/Applications.
+
+name = "{}"
+executable = "run.sh"
+icon = "assets/icon.png"
+
+[security]
+gpu = true
+audio = true
+network = false
+write_paths = ["@{{BUNDLE}}/data/**"]
+exec_paths = [{}]
+"#,
+        app_name,
+        WINE_PREFIX_DIR,
+        app_name.replace('"', "\\\""),
+        exec_paths
+    );
+    std::fs::write(bundle_root.join("config.toml"), config_toml)?;
+    Ok(())
+}
+
+/// Add imported desktop metadata (keywords, translations, ...) to a new bundle's config.toml.
+fn add_metadata(bundle_root: &Path, meta: &appmeta::Metadata) -> Result<()> {
+    if meta.is_empty() {
+        return Ok(());
+    }
+    let path = bundle_root.join("config.toml");
+    let config = std::fs::read_to_string(&path)?;
+    std::fs::write(&path, appmeta::add_to_config(&config, meta))?;
+    tracing::info!(
+        "added the app's menu metadata (comment, keywords, translations) to config.toml"
+    );
+    Ok(())
+}
+
+/// Entry point for `dotlnx bundle --appname "..." --appimage <path>`, `--bin <path>`, `--oci <ref>` or `--wine <path>`.
+#[allow(clippy::too_many_arguments)] // one per CLI flag
 pub fn run(
     appname: &str,
     appimage: Option<&Path>,
     bin: Option<&Path>,
+    oci: Option<&str>,
+    wine: Option<&Path>,
+    wine_program: Option<&str>,
+    builtin_launcher: bool,
+    desktop_file: Option<&Path>,
+    metainfo: Option<&Path>,
     output_dir: &Path,
+    progress_args: ProgressArgs,
 ) -> Result<()> {
     if appname.trim().is_empty() {
         anyhow::bail!("app name must not be empty");
     }
     validate::validate_app_name(appname)?;
 
-    match (appimage, bin) {
-        (Some(path), None) => {
-            let bundle_root = create_appimage_bundle(appname, path, output_dir)?;
+    if wine_program.is_some() && wine.is_none() {
+        anyhow::bail!("--wine-program only applies to --wine bundles");
+    }
+    if builtin_launcher && appimage.is_none() {
+        anyhow::bail!("--builtin-launcher only applies to --appimage bundles");
+    }
+    // Read before anything is copied so a bad path fails early.
+    let mut meta = appmeta::Metadata::default();
+    if let Some(path) = desktop_file {
+        meta.fill(appmeta::from_desktop_file(path)?);
+    }
+    if let Some(path) = metainfo {
+        meta.fill(appmeta::from_metainfo_file(path)?);
+    }
+    // Copies of a local AppImage, binary or Wine prefix can be gigabytes; skopeo and Wine
+    // installers report their own progress.
+    let progress = match appimage.or(bin).or(wine.filter(|w| w.is_dir())) {
+        Some(path) => {
+            let progress = Progress::new(progress_args, "bundle", progress::tree_size(path));
+            progress.set_item(&path.file_name().unwrap_or_default().to_string_lossy());
+            progress
+        }
+        None => Progress::hidden(),
+    };
+    match (appimage, bin, oci, wine) {
+        (Some(path), None, None, None) => {
+            let bundle_root =
+                create_appimage_bundle(appname, path, output_dir, builtin_launcher, &progress)?;
+            progress.finish();
+            if let Some(embedded) = appmeta::from_appimage(path) {
+                meta.fill(embedded);
+            }
+            add_metadata(&bundle_root, &meta)?;
             tracing::info!(
-                "Created {} with bin/ (AppImage copied in), config.toml, run.sh, and assets/. Add more AppImages to bin/ or assets/icon.png if desired, then run: dotlnx validate {}",
+                "Created {} with bin/ (AppImage copied in), config.toml, {}and assets/. Add more AppImages to bin/ or assets/icon.png if desired, then run: dotlnx validate {}",
                 bundle_root.display(),
+                if builtin_launcher { "" } else { "run.sh, " },
                 bundle_root.display()
             );
         }
-        (None, Some(path)) => {
-            let bundle_root = create_bin_bundle(appname, path, output_dir)?;
+        (None, Some(path), None, None) => {
+            let bundle_root = create_bin_bundle(appname, path, output_dir, &progress)?;
+            progress.finish();
+            add_metadata(&bundle_root, &meta)?;
             tracing::info!(
                 "Created {} with bin/ (executable copied in), config.toml, and assets/. Add assets/icon.png if desired, then run: dotlnx validate {}",
                 bundle_root.display(),
                 bundle_root.display()
             );
         }
-        (None, None) => anyhow::bail!("specify exactly one of --appimage or --bin"),
-        (Some(_), Some(_)) => anyhow::bail!("specify exactly one of --appimage or --bin"),
+        (None, None, Some(reference), None) => {
+            let bundle_root = create_oci_bundle(appname, reference, output_dir)?;
+            add_metadata(&bundle_root, &meta)?;
+            tracing::info!(
+                "Created {} with rootfs/ (image unpacked), oci.json, run.sh, config.toml, and assets/. Adjust [security] (network, paths) if needed, then run: dotlnx validate {}",
+                bundle_root.display(),
+                bundle_root.display()
+            );
+        }
+        (None, None, None, Some(source)) => {
+            let bundle_root =
+                create_wine_bundle(appname, source, wine_program, output_dir, &progress)?;
+            progress.finish();
+            add_metadata(&bundle_root, &meta)?;
+            tracing::info!(
+                "Created {} with data/prefix (Wine prefix), run.sh, config.toml, and assets/. Check PROGRAM in run.sh and add assets/icon.png if desired, then run: dotlnx validate {}",
+                bundle_root.display(),
+                bundle_root.display()
+            );
+        }
+        _ => anyhow::bail!("specify exactly one of --appimage, --bin, --oci or --wine"),
     }
     Ok(())
 }
@@ -248,11 +697,71 @@ mod tests {
         assert_eq!(derive_appimage_pattern(p), "*.appimage");
     }
 
+    #[test]
+    fn derive_pattern_skips_the_arch_and_keeps_the_extension() {
+        let p = Path::new("Tool-x86_64-2.1.AppImage");
+        assert_eq!(derive_appimage_pattern(p), "Tool-x86_64-*.AppImage");
+        assert_eq!(derive_appimage_pattern(Path::new("tool.bin")), "*.AppImage");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_sh_starts_the_newest_appimage_for_this_machine() {
+        use std::os::unix::fs::PermissionsExt;
+        let Some(host) = Arch::host() else {
+            return;
+        };
+        let other = if host == Arch::Aarch64 {
+            Arch::X86_64
+        } else {
+            Arch::Aarch64
+        };
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("bin")).unwrap();
+        let add = |version: &str, arch: Arch, age: u64| {
+            let p = dir.path().join(format!(
+                "bin/App-{}-{}.AppImage",
+                version,
+                arch.debian_name()
+            ));
+            std::fs::write(&p, format!("#!/bin/sh\necho {} {}\n", version, arch)).unwrap();
+            std::fs::set_permissions(&p, std::fs::Permissions::from_mode(0o755)).unwrap();
+            let mtime = std::time::SystemTime::now() - std::time::Duration::from_secs(age);
+            std::fs::File::options()
+                .write(true)
+                .open(&p)
+                .unwrap()
+                .set_modified(mtime)
+                .unwrap();
+        };
+        add("1.0", host, 100);
+        add("2.0", host, 10);
+        add("3.0", other, 0);
+        let pattern = derive_appimage_pattern(Path::new(&format!(
+            "App-1.0-{}.AppImage",
+            host.debian_name()
+        )));
+        let script = dir.path().join("run.sh");
+        std::fs::write(&script, run_sh_appimage("App", &pattern)).unwrap();
+        let out = std::process::Command::new("sh")
+            .arg(&script)
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&out.stdout),
+            format!("2.0 {}\n", host)
+        );
+    }
+
     #[test]
     fn test_escape_bash_double_quoted() {
         assert_eq!(super::escape_bash_double_quoted("x"), "x");
         assert_eq!(super::escape_bash_double_quoted(r#"a"b"#), r#"a\"b"#);
         assert_eq!(super::escape_bash_double_quoted("a\\b"), "a\\\\b");
+        assert_eq!(
+            super::escape_bash_double_quoted("$HOME`x`"),
+            "\\$HOME\\`x\\`"
+        );
     }
 
     #[test]
@@ -260,12 +769,37 @@ mod tests {
         let out = tempfile::tempdir().unwrap();
         let appimage = out.path().join("fake.appimage");
         std::fs::write(&appimage, b"fake").unwrap();
-        let bundle_root = create_appimage_bundle("MyApp", &appimage, out.path()).unwrap();
+        let bundle_root =
+            create_appimage_bundle("MyApp", &appimage, out.path(), false, &Progress::hidden())
+                .unwrap();
         assert_eq!(
             bundle_root.file_name().and_then(|n| n.to_str()),
             Some("MyApp.lnx")
         );
         assert!(validate::validate_bundle(&bundle_root).is_ok());
+        // Not an ELF file: nothing to record.
+        let cfg = crate::config::load(&bundle_root).unwrap();
+        assert!(cfg.arch.is_empty());
+    }
+
+    #[test]
+    fn builtin_launcher_bundle_has_no_run_sh() {
+        let out = tempfile::tempdir().unwrap();
+        let name = format!("Tool-1.2-{}.AppImage", Arch::host().unwrap_or(Arch::X86_64));
+        let appimage = out.path().join(&name);
+        std::fs::write(&appimage, b"fake").unwrap();
+        let bundle_root =
+            create_appimage_bundle("Tool", &appimage, out.path(), true, &Progress::hidden())
+                .unwrap();
+        assert!(!bundle_root.join("run.sh").exists());
+        let cfg = crate::config::load(&bundle_root).unwrap();
+        assert_eq!(cfg.executable, "bin/Tool-*-*.AppImage");
+        assert!(cfg.needs_runner());
+        validate::validate_config(&bundle_root, &cfg).unwrap();
+        assert_eq!(
+            crate::bundle::resolve_executable(&bundle_root, &cfg.executable),
+            bundle_root.join("bin").join(&name)
+        );
     }
 
     #[test]
@@ -273,12 +807,21 @@ mod tests {
         let out = tempfile::tempdir().unwrap();
         let script = out.path().join("mytool.sh");
         std::fs::write(&script, "#!/bin/sh\nexit 0").unwrap();
-        let bundle_root = create_bin_bundle("MyTool", &script, out.path()).unwrap();
+        let bundle_root =
+            create_bin_bundle("MyTool", &script, out.path(), &Progress::hidden()).unwrap();
         assert_eq!(
             bundle_root.file_name().and_then(|n| n.to_str()),
             Some("MyTool.lnx")
         );
         assert!(validate::validate_bundle(&bundle_root).is_ok());
+
+        // A binary's architecture is recorded.
+        let exe = std::env::current_exe().unwrap();
+        let bundle_root =
+            create_bin_bundle("Native", &exe, out.path(), &Progress::hidden()).unwrap();
+        let cfg = crate::config::load(&bundle_root).unwrap();
+        assert_eq!(cfg.arch, Arch::host().into_iter().collect::<Vec<_>>());
+        assert!(validate::arch_warnings(&bundle_root, &cfg).is_empty());
     }
 
     #[test]
@@ -286,7 +829,8 @@ mod tests {
         let out = tempfile::tempdir().unwrap();
         let script = out.path().join("test.sh");
         std::fs::write(&script, "#!/bin/sh\nexit 0").unwrap();
-        let bundle_root = create_bin_bundle("Test App", &script, out.path()).unwrap();
+        let bundle_root =
+            create_bin_bundle("Test App", &script, out.path(), &Progress::hidden()).unwrap();
         assert_eq!(
             bundle_root.file_name().and_then(|n| n.to_str()),
             Some("Test App.lnx")
@@ -299,7 +843,20 @@ mod tests {
         let out = tempfile::tempdir().unwrap();
         let f = out.path().join("x.appimage");
         std::fs::write(&f, b"x").unwrap();
-        let e = run("", Some(&f), None, out.path()).unwrap_err();
+        let e = run(
+            "",
+            Some(&f),
+            None,
+            None,
+            None,
+            None,
+            false,
+            None,
+            None,
+            out.path(),
+            ProgressArgs::default(),
+        )
+        .unwrap_err();
         assert!(e.to_string().to_lowercase().contains("empty"));
     }
 
@@ -308,7 +865,135 @@ mod tests {
         let out = tempfile::tempdir().unwrap();
         let f = out.path().join("x.appimage");
         std::fs::write(&f, b"x").unwrap();
-        let e = run("bad/name", Some(&f), None, out.path()).unwrap_err();
+        let e = run(
+            "bad/name",
+            Some(&f),
+            None,
+            None,
+            None,
+            None,
+            false,
+            None,
+            None,
+            out.path(),
+            ProgressArgs::default(),
+        )
+        .unwrap_err();
         assert!(e.to_string().contains("name"));
     }
+
+    #[test]
+    fn oci_bundle_files_validate() {
+        let out = tempfile::tempdir().unwrap();
+        let bundle_root = out.path().join("Tool.lnx");
+        std::fs::create_dir_all(bundle_root.join("rootfs")).unwrap();
+        let image = oci::ImageConfig {
+            image: "docker://example.com/tool:1".into(),
+            entrypoint: vec!["/tool".into()],
+            ..Default::default()
+        };
+        write_oci_files("Tool", &image, &bundle_root).unwrap();
+        assert!(validate::validate_bundle(&bundle_root).is_ok());
+        let cfg = crate::config::load(&bundle_root).unwrap();
+        assert!(!cfg.security.unwrap().confine);
+        let stored: oci::ImageConfig = serde_json::from_str(
+            &std::fs::read_to_string(bundle_root.join(oci::IMAGE_FILE)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(stored, image);
+    }
+
+    #[test]
+    fn bin_bundle_imports_desktop_metadata() {
+        let out = tempfile::tempdir().unwrap();
+        let exe = out.path().join("tool");
+        std::fs::write(&exe, "#!/bin/sh\n").unwrap();
+        let desktop = out.path().join("tool.desktop");
+        std::fs::write(
+            &desktop,
+            "[Desktop Entry]\nName=Tool\nName[de]=Werkzeug\nKeywords=fix;repair;\n",
+        )
+        .unwrap();
+        let progress = ProgressArgs::default();
+        run(
+            "Tool",
+            None,
+            Some(&exe),
+            None,
+            None,
+            None,
+            false,
+            Some(&desktop),
+            None,
+            out.path(),
+            progress,
+        )
+        .unwrap();
+        let bundle_root = out.path().join("Tool.lnx");
+        assert!(validate::validate_bundle(&bundle_root).is_ok());
+        let cfg = crate::config::load(&bundle_root).unwrap();
+        assert_eq!(cfg.keywords, ["fix", "repair"]);
+        assert_eq!(cfg.translations["de"].name.as_deref(), Some("Werkzeug"));
+    }
+
+    #[test]
+    fn run_rejects_multiple_sources() {
+        let out = tempfile::tempdir().unwrap();
+        let f = out.path().join("x.appimage");
+        std::fs::write(&f, b"x").unwrap();
+        let e = run(
+            "X",
+            Some(&f),
+            None,
+            Some("alpine"),
+            None,
+            None,
+            false,
+            None,
+            None,
+            out.path(),
+            ProgressArgs::default(),
+        )
+        .unwrap_err();
+        assert!(e.to_string().contains("exactly one"));
+    }
+
+    #[test]
+    fn wine_bundle_from_prefix() {
+        let out = tempfile::tempdir().unwrap();
+        let prefix = out.path().join("pfx");
+        let app_dir = prefix.join("drive_c/Program Files/Tool");
+        std::fs::create_dir_all(&app_dir).unwrap();
+        std::fs::write(app_dir.join("tool.exe"), b"MZ").unwrap();
+        std::fs::write(app_dir.join("unins000.exe"), b"MZ").unwrap();
+        std::fs::create_dir_all(prefix.join("dosdevices")).unwrap();
+        std::os::unix::fs::symlink("../drive_c", prefix.join("dosdevices/c:")).unwrap();
+
+        let bundle_root =
+            create_wine_bundle("Tool", &prefix, None, out.path(), &Progress::hidden()).unwrap();
+        assert!(validate::validate_bundle(&bundle_root).is_ok());
+        let copied = bundle_root.join("data/prefix");
+        assert!(std::fs::symlink_metadata(copied.join("dosdevices/c:"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        let run_sh = std::fs::read_to_string(bundle_root.join("run.sh")).unwrap();
+        assert!(run_sh.contains(r#"PROGRAM="drive_c/Program Files/Tool/tool.exe""#));
+        let sec = crate::config::load(&bundle_root).unwrap().security.unwrap();
+        assert!(sec.confine && sec.gpu && sec.audio);
+        assert_eq!(sec.write_paths, vec!["@{BUNDLE}/data/**".to_string()]);
+    }
+
+    #[test]
+    fn wine_program_path_accepts_windows_paths() {
+        assert_eq!(
+            wine_program_path(r"C:\Program Files\App\app.exe").unwrap(),
+            "drive_c/Program Files/App/app.exe"
+        );
+        assert_eq!(
+            wine_program_path("drive_c/app.exe").unwrap(),
+            "drive_c/app.exe"
+        );
+        assert!(wine_program_path(r"C:\..\..\etc\passwd").is_err());
+    }
 }
@@ -1,33 +1,272 @@
 //! Parse and validate config.toml (run config + optional security + optional desktop).
 
 use serde::Deserialize;
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
 /// Root config.toml structure.
 #[derive(Debug, Deserialize)]
 pub struct Config {
     /// Required: app name (for menu + profile)
     pub name: String,
+    /// Optional: publisher namespace in reverse-DNS form ("com.example"), combined with `name`
+    /// into the app's ID (see `Config::app_id`).
+    pub vendor: Option<String>,
     /// Required: path to executable relative to bundle root
     pub executable: String,
+    /// What the bundle is: a desktop app (menu entry) or a service (systemd unit).
+    #[serde(rename = "type", default)]
+    pub kind: Kind,
     /// Optional: args to pass to executable
     #[serde(default)]
     pub args: Vec<String>,
     /// Optional: env vars (key=value)
     #[serde(default)]
     pub env: Vec<String>,
+    /// Site-wide env vars from [run] env, set before `env` (so the bundle's win). Not read from
+    /// config.toml.
+    #[serde(skip)]
+    pub site_env: Vec<String>,
     /// Optional: working directory (relative to bundle root)
     pub working_dir: Option<String>,
     /// Optional: desktop metadata for generated .desktop
     pub icon: Option<String>,
     pub comment: Option<String>,
     pub categories: Option<Vec<String>>,
+    /// Optional: what kind of app it is ("Web Browser"), shown by some menus next to the name.
+    pub generic_name: Option<String>,
+    /// Optional: extra words menus and launchers match in searches.
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    /// Optional: menu text in other languages, by locale ("de", "pt_BR", "sr@latin").
+    #[serde(default)]
+    pub translations: BTreeMap<String, Translation>,
+    /// Optional (system tier): only these users get a menu entry for the app.
+    #[serde(default)]
+    pub allowed_users: Vec<String>,
+    /// Optional (system tier): only members of these groups get a menu entry for the app.
+    #[serde(default)]
+    pub allowed_groups: Vec<String>,
     /// When true, add Terminal=true so the app is run in a terminal (for CLI apps with no UI).
     #[serde(default)]
     pub terminal: bool,
+    /// Optional: file mode creation mask for the app, as octal (e.g. "077").
+    pub umask: Option<Umask>,
+    /// Optional: CPU niceness for the app, 0 (normal) to 19 (lowest priority).
+    pub nice: Option<i32>,
+    /// Optional: I/O scheduling class for the app.
+    pub ionice: Option<Ionice>,
+    /// Optional: architectures the bundle's binaries are built for ("x86_64", "aarch64", ...).
+    /// Validate warns when this machine is not one of them.
+    #[serde(default)]
+    pub arch: Vec<crate::arch::Arch>,
+    /// Optional: versions of the executable's AppImage kept, the current one included; sync
+    /// removes older ones of each version series (see `bundle::surplus_appimages`).
+    pub keep_versions: Option<usize>,
+    /// Optional: where the app keeps its user data, relative to the home (".config/MyApp") or
+    /// absolute; `[update] snapshot_data` copies them before an update (see `snapshot`).
+    #[serde(default)]
+    pub data_dirs: Vec<String>,
+    /// Optional: bundle paths the app writes itself ("cache/**", "*.log"), left out of generated
+    /// profile rules, the scan fingerprint and `dotlnx pack` (see `bundle::is_excluded`).
+    #[serde(default)]
+    pub exclude: Vec<String>,
     /// Optional: security section for AppArmor
     #[serde(default)]
     pub security: Option<Security>,
+    /// Optional: how a service bundle's unit runs (type = "service" only).
+    #[serde(default)]
+    pub service: Option<Service>,
+    /// Optional: run a service bundle on a schedule (a systemd timer) instead of all the time.
+    #[serde(default)]
+    pub schedule: Option<Schedule>,
+    /// Optional: programs that render file previews for file managers ([[thumbnailers]]).
+    #[serde(default)]
+    pub thumbnailers: Vec<Thumbnailer>,
+    /// Optional: the app's GNOME Shell search provider.
+    #[serde(default)]
+    pub search_provider: Option<SearchProvider>,
+    /// Optional: how `dotlnx update` fetches new versions of the bundle's AppImage.
+    #[serde(default)]
+    pub update: Option<Update>,
+    /// Optional: what the app's toolkits need to work with screen readers.
+    #[serde(default)]
+    pub accessibility: Option<Accessibility>,
+}
+
+/// One [translations.<locale>] section: the desktop keys in that language. Unset keys fall
+/// back to the untranslated ones.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Translation {
+    pub name: Option<String>,
+    pub generic_name: Option<String>,
+    pub comment: Option<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+}
+
+/// One [[thumbnailers]] entry, installed as a .thumbnailer file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Thumbnailer {
+    /// Program relative to the bundle root.
+    pub executable: String,
+    /// Its arguments; `%i` (input path), `%u` (input URI), `%o` (output PNG) and `%s` (size)
+    /// as separate arguments are filled in by the file manager.
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// MIME types it renders previews for.
+    pub mime_types: Vec<String>,
+}
+
+/// [search_provider] section: the D-Bus name and object the app serves
+/// org.gnome.Shell.SearchProvider2 on.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SearchProvider {
+    pub bus_name: String,
+    pub object_path: String,
+    /// Extra arguments when D-Bus starts the app for a search (e.g. "--gapplication-service").
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+/// [schedule] section of a service bundle.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schedule {
+    /// When to run, as systemd's OnCalendar= (e.g. "daily", "Mon *-*-* 03:00").
+    pub on_calendar: String,
+    /// Run at the next start-up when the machine was off at the scheduled time.
+    #[serde(default = "default_persistent")]
+    pub persistent: bool,
+}
+
+fn default_persistent() -> bool {
+    true
+}
+
+/// [update] section of an AppImage bundle.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Update {
+    /// Fetch new AppImages with zsync (downloading only the blocks that changed).
+    #[serde(default)]
+    pub appimage_zsync: bool,
+    /// https:// URL of the .zsync file; default: the update information embedded in the AppImage.
+    pub zsync_url: Option<String>,
+    /// Copy `data_dirs` before each update, so `dotlnx rollback` restores them with the AppImage.
+    #[serde(default)]
+    pub snapshot_data: bool,
+}
+
+/// [accessibility] section. The profile side (the accessibility bus) is `[security] accessibility`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Accessibility {
+    /// Turn the toolkits' AT-SPI bridges on even when the session did not (see
+    /// `runenv::BRIDGE_VARS`). Applied by `dotlnx run`.
+    #[serde(default)]
+    pub force_bridges: bool,
+}
+
+/// Value of type: "app" (default) or "service".
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    #[default]
+    App,
+    Service,
+}
+
+/// [service] section of a service bundle.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Service {
+    /// When systemd restarts the service.
+    #[serde(default)]
+    pub restart: Restart,
+    /// Account a system-tier service runs as (root when unset). User-tier services always run as
+    /// their user.
+    pub user: Option<String>,
+    /// Stream sockets systemd listens on for the service, starting it on the first connection.
+    #[serde(default)]
+    pub listen: Vec<Listen>,
+}
+
+/// Value of [service] listen: a port ("8080"), an address and port ("127.0.0.1:8080",
+/// "[::1]:8080") or an absolute Unix socket path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Listen {
+    Port(u16),
+    Address(String),
+    Path(PathBuf),
+}
+
+impl Listen {
+    /// True for an IP socket (needs network access to accept on).
+    pub fn is_inet(&self) -> bool {
+        !matches!(self, Listen::Path(_))
+    }
+
+    /// As systemd's ListenStream= takes it.
+    pub fn to_systemd(&self) -> String {
+        match self {
+            Listen::Port(p) => p.to_string(),
+            Listen::Address(a) => a.clone(),
+            Listen::Path(p) => p.display().to_string(),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Listen {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        let port = |p: &str| p.parse::<u16>().ok().filter(|&p| p != 0);
+        let host_ok = |h: &str| {
+            let h = h
+                .strip_prefix('[')
+                .and_then(|h| h.strip_suffix(']'))
+                .unwrap_or(h);
+            !h.is_empty() && h.parse::<std::net::IpAddr>().is_ok()
+        };
+        if s.starts_with('/') {
+            if !s.contains(|c: char| c.is_control()) && !s.split('/').any(|c| c == "..") {
+                return Ok(Listen::Path(PathBuf::from(s)));
+            }
+        } else if let Some(p) = port(&s) {
+            return Ok(Listen::Port(p));
+        } else if let Some((host, p)) = s.rsplit_once(':') {
+            if host_ok(host) && port(p).is_some() {
+                return Ok(Listen::Address(s));
+            }
+        }
+        Err(serde::de::Error::custom(format!(
+            "invalid listen {:?} (expected a port, ADDRESS:PORT or an absolute socket path)",
+            s
+        )))
+    }
+}
+
+/// Value of [service] restart, as in systemd's Restart=.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Restart {
+    No,
+    #[default]
+    OnFailure,
+    Always,
+}
+
+impl Restart {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Restart::No => "no",
+            Restart::OnFailure => "on-failure",
+            Restart::Always => "always",
+        }
+    }
 }
 
 /// Security requirements for AppArmor profile generation.
@@ -43,9 +282,183 @@ pub struct Security {
     pub write_paths: Vec<String>,
     #[serde(default)]
     pub network: bool,
+    /// Host programs the app may run under its own profile (e.g. an interpreter or wine).
+    #[serde(default)]
+    pub exec_paths: Vec<String>,
+    /// GPU access: DRM render/card nodes and the driver files Mesa/Vulkan probe.
+    #[serde(default)]
+    pub gpu: bool,
+    /// Sound: PulseAudio/PipeWire sockets in the user's runtime dir and ALSA devices.
+    #[serde(default)]
+    pub audio: bool,
+    /// XDG desktop portals (file chooser, opening URIs, notifications) over the session bus, and
+    /// the files the document portal exports.
+    #[serde(default)]
+    pub portals: bool,
+    /// Video4Linux devices (webcams, capture cards, TV tuners) opened directly.
+    #[serde(default)]
+    pub v4l: bool,
+    /// Cameras: the Camera portal and PipeWire, and the V4L devices as `v4l` grants them.
+    #[serde(default)]
+    pub camera: bool,
+    /// Screen sharing and recording through the ScreenCast portal and PipeWire.
+    #[serde(default)]
+    pub screen_capture: bool,
+    /// Printing: the CUPS socket and client configuration, and the Print portal.
+    #[serde(default)]
+    pub printing: bool,
+    /// Read access to USB drives and other removable media (/media, /run/media) and to gvfs
+    /// mounts such as MTP phones.
+    #[serde(default)]
+    pub removable_media: bool,
+    /// The user's SSH agent (SSH_AUTH_SOCK), so git and ssh in the app can authenticate.
+    #[serde(default)]
+    pub ssh_agent: bool,
+    /// The Secret Service D-Bus API (GNOME Keyring, KeePassXC, KWallet) for stored passwords.
+    #[serde(default)]
+    pub secrets: bool,
+    /// The AT-SPI accessibility bus, so screen readers and other assistive technologies can
+    /// read and drive the app.
+    #[serde(default)]
+    pub accessibility: bool,
     #[serde(default)]
     #[allow(dead_code)] // reserved for future AppArmor capability rules
     pub capabilities: Vec<String>,
+    /// Unprivileged user namespaces (Chromium/Electron sandboxes on AppArmor 4.x hosts such as
+    /// Ubuntu 24.04+). true grants `userns` within the confined profile; "unconfined" loads the
+    /// profile with the unconfined flag so the app keeps its own sandbox.
+    #[serde(default)]
+    pub userns: Userns,
+    /// Helper programs inside the bundle that run under their own, tighter child profile.
+    #[serde(default)]
+    pub helpers: Vec<Helper>,
+    /// Start the app from a minimal environment (plus `env`) instead of the launching session's,
+    /// so variables like LD_PRELOAD cannot be injected into it. The menu entry goes through
+    /// `dotlnx run` to apply this.
+    #[serde(default)]
+    pub clear_env: bool,
+    /// Syscall filter `dotlnx run` installs before exec; None runs without one. The menu entry
+    /// goes through `dotlnx run` to apply it.
+    #[serde(default)]
+    pub seccomp: Option<Seccomp>,
+    /// Set no_new_privs before exec, so setuid/setgid binaries and file capabilities grant the app
+    /// nothing. Applied by `dotlnx run`, like clear_env.
+    #[serde(default)]
+    pub no_new_privs: bool,
+    /// Give the app an empty HOME for each launch that is deleted when it exits. Applied by
+    /// `dotlnx run`, like clear_env.
+    #[serde(default)]
+    pub ephemeral_home: bool,
+    /// Paths relative to the home (e.g. `.config/MyApp`) an ephemeral_home app keeps between
+    /// launches: they are linked to the real home.
+    #[serde(default)]
+    pub persist_paths: Vec<String>,
+}
+
+/// A helper program (e.g. bin/ffmpeg) exec'd by the app. It transitions to a child profile that
+/// only gets its own paths, not the parent's.
+#[derive(Debug, Deserialize)]
+pub struct Helper {
+    /// Path to the helper relative to bundle root.
+    pub path: String,
+    #[serde(default)]
+    pub read_paths: Vec<String>,
+    #[serde(default)]
+    pub write_paths: Vec<String>,
+    #[serde(default)]
+    pub network: bool,
+}
+
+/// Value of [security] userns: false, true, or "unconfined".
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Userns {
+    #[default]
+    Deny,
+    Allow,
+    Unconfined,
+}
+
+impl<'de> Deserialize<'de> for Userns {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Bool(bool),
+            Str(String),
+        }
+        match Raw::deserialize(d)? {
+            Raw::Bool(false) => Ok(Userns::Deny),
+            Raw::Bool(true) => Ok(Userns::Allow),
+            Raw::Str(s) if s == "unconfined" => Ok(Userns::Unconfined),
+            Raw::Str(s) => Err(serde::de::Error::custom(format!(
+                "invalid userns {:?} (expected true, false, or \"unconfined\")",
+                s
+            ))),
+        }
+    }
+}
+
+/// Value of [security] seccomp: "default", "strict", or the path of a filter in seccompiler's JSON
+/// format (absolute, or relative to the bundle).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Seccomp {
+    Default,
+    Strict,
+    Profile(String),
+}
+
+impl<'de> Deserialize<'de> for Seccomp {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        Ok(match s.as_str() {
+            "default" => Seccomp::Default,
+            "strict" => Seccomp::Strict,
+            "" => return Err(serde::de::Error::custom("seccomp must not be empty")),
+            _ => Seccomp::Profile(s),
+        })
+    }
+}
+
+/// Value of umask: an octal string such as "077" or "0027".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Umask(pub u32);
+
+impl<'de> Deserialize<'de> for Umask {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        match u32::from_str_radix(&s, 8) {
+            Ok(m) if !s.is_empty() && m <= 0o777 => Ok(Umask(m)),
+            _ => Err(serde::de::Error::custom(format!(
+                "invalid umask {:?} (expected octal, e.g. \"077\")",
+                s
+            ))),
+        }
+    }
+}
+
+/// Value of ionice: "idle", "best-effort", or "best-effort:N" with N from 0 (highest) to 7.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ionice {
+    Idle,
+    BestEffort(u8),
+}
+
+impl<'de> Deserialize<'de> for Ionice {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        let level = match s.split_once(':') {
+            None if s == "idle" => return Ok(Ionice::Idle),
+            None if s == "best-effort" => Some(4),
+            Some(("best-effort", n)) => n.parse::<u8>().ok().filter(|n| *n <= 7),
+            _ => None,
+        };
+        level.map(Ionice::BestEffort).ok_or_else(|| {
+            serde::de::Error::custom(format!(
+                "invalid ionice {:?} (expected \"idle\", \"best-effort\" or \"best-effort:0\" to \"best-effort:7\")",
+                s
+            ))
+        })
+    }
 }
 
 impl Default for Security {
@@ -55,7 +468,26 @@ impl Default for Security {
             read_paths: Vec::new(),
             write_paths: Vec::new(),
             network: false,
+            exec_paths: Vec::new(),
+            gpu: false,
+            audio: false,
+            portals: false,
+            v4l: false,
+            camera: false,
+            screen_capture: false,
+            printing: false,
+            removable_media: false,
+            ssh_agent: false,
+            secrets: false,
+            accessibility: false,
             capabilities: Vec::new(),
+            userns: Userns::Deny,
+            helpers: Vec::new(),
+            clear_env: false,
+            seccomp: None,
+            no_new_privs: false,
+            ephemeral_home: false,
+            persist_paths: Vec::new(),
         }
     }
 }
@@ -64,13 +496,245 @@ fn default_confine() -> bool {
     true
 }
 
+impl Config {
+    /// The app's ID: `<vendor>.<name>`, or the name alone without a vendor. Profile names, menu
+    /// entry and unit file names, holds and the launch command are derived from it, so apps of
+    /// the same name from different publishers do not collide.
+    pub fn app_id(&self) -> String {
+        match &self.vendor {
+            Some(vendor) => format!("{}.{}", vendor, self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    /// True when `name` refers to this app: its name or its ID.
+    pub fn answers_to(&self, name: &str) -> bool {
+        self.name == name || self.vendor.is_some() && self.app_id() == name
+    }
+
+    /// True when launching the app needs `dotlnx run` (the menu entry cannot exec it directly):
+    /// it has a launch setting only the runner applies (site env included), or users and groups
+    /// the runner checks.
+    pub fn needs_runner(&self) -> bool {
+        self.executable_is_pattern()
+            || !self.allowed_users.is_empty()
+            || !self.allowed_groups.is_empty()
+            || !self.site_env.is_empty()
+            || self.umask.is_some()
+            || self.nice.is_some()
+            || self.ionice.is_some()
+            || self.accessibility.as_ref().is_some_and(|a| a.force_bridges)
+            || self.security.as_ref().is_some_and(|s| {
+                s.clear_env || s.seccomp.is_some() || s.no_new_privs || s.ephemeral_home
+            })
+    }
+
+    /// True when `executable` names its file with a wildcard (`bin/MyApp-*.AppImage`): the
+    /// runner picks the newest match at launch (see `bundle::resolve_executable`).
+    pub fn executable_is_pattern(&self) -> bool {
+        self.executable.contains(['*', '?'])
+    }
+}
+
+/// Largest config.toml dotlnx will read (real configs are well under 4 KiB).
+pub const MAX_CONFIG_BYTES: u64 = 64 * 1024;
+/// Deepest table/array nesting accepted (the schema itself needs 3: [security] → helpers → entry).
+const MAX_DEPTH: usize = 8;
+/// Longest array accepted (args, env, categories, read_paths, helpers, ...).
+const MAX_ARRAY_LEN: usize = 256;
+/// Longest string value or key accepted.
+const MAX_STRING_LEN: usize = 4096;
+/// Most files one config.toml may include.
+const MAX_INCLUDES: usize = 16;
+
 /// Load and parse config.toml from a bundle root directory.
 pub fn load(bundle_root: &Path) -> anyhow::Result<Config> {
+    parse(&read(bundle_root)?)
+}
+
+/// A bundle's config.toml text with its includes merged in (see `expand_includes`).
+pub fn read(bundle_root: &Path) -> anyhow::Result<String> {
+    use std::io::Read;
+    use std::os::unix::fs::OpenOptionsExt;
     let path = bundle_root.join("config.toml");
-    let s = std::fs::read_to_string(&path)
+    // Non-blocking, so opening a FIFO does not wait for a writer.
+    let file = std::fs::File::options()
+        .read(true)
+        .custom_flags(nix::fcntl::OFlag::O_NONBLOCK.bits())
+        .open(&path)
+        .map_err(|e| anyhow::anyhow!("failed to read config.toml: {}", e))?;
+    // Checked on the open file, so it cannot be swapped for a FIFO or a device in between.
+    if !file
+        .metadata()
+        .map_err(|e| anyhow::anyhow!("failed to read config.toml: {}", e))?
+        .is_file()
+    {
+        anyhow::bail!("config.toml is not a regular file");
+    }
+    // The file may grow after it is opened: read no more than the limit allows.
+    let mut s = String::new();
+    file.take(MAX_CONFIG_BYTES + 1)
+        .read_to_string(&mut s)
         .map_err(|e| anyhow::anyhow!("failed to read config.toml: {}", e))?;
-    let config: Config = toml::from_str(&s).map_err(|e| anyhow::anyhow!("invalid config.toml: {}", e))?;
-    Ok(config)
+    if s.len() as u64 > MAX_CONFIG_BYTES {
+        anyhow::bail!("config.toml is too large (over {} bytes)", MAX_CONFIG_BYTES);
+    }
+    expand_includes(&s, |rel| {
+        crate::safefs::SafeDir::open_under(bundle_root, Path::new(""))?
+            .read_nested(rel, MAX_CONFIG_BYTES)
+    })
+}
+
+/// Files a bundle's config.toml includes (the valid entries of its list; none when it cannot be
+/// read).
+pub fn included_files(bundle_root: &Path) -> Vec<String> {
+    let files: Vec<String> = std::fs::read_to_string(bundle_root.join("config.toml"))
+        .ok()
+        .and_then(|s| toml::from_str::<toml::Value>(&s).ok())
+        .and_then(|v| v.get("include").cloned())
+        .and_then(|v| v.try_into().ok())
+        .unwrap_or_default();
+    files.into_iter().filter(|f| is_include_path(f)).collect()
+}
+
+/// An include names a file inside the bundle: relative, no "..", not config.toml itself.
+fn is_include_path(file: &str) -> bool {
+    let rel = Path::new(file);
+    !file.is_empty()
+        && rel != Path::new("config.toml")
+        && rel
+            .components()
+            .all(|c| matches!(c, std::path::Component::Normal(_)))
+}
+
+/// config.toml text with the files its `include` list names merged in, as one document; the text
+/// as it is when it includes nothing. Included files (paths relative to the bundle root, read
+/// with `read`) are applied in order, each over the ones before it, and config.toml over all of
+/// them: tables are merged key by key, any other value replaces the included one. Included
+/// files cannot include others.
+pub fn expand_includes(
+    s: &str,
+    mut read: impl FnMut(&Path) -> anyhow::Result<String>,
+) -> anyhow::Result<String> {
+    let mut value: toml::Value =
+        toml::from_str(s).map_err(|e| anyhow::anyhow!("invalid config.toml: {}", e))?;
+    let Some(include) = value.as_table_mut().and_then(|t| t.remove("include")) else {
+        return Ok(s.to_string());
+    };
+    let files: Vec<String> = include.try_into().map_err(|_| {
+        anyhow::anyhow!("invalid config.toml: include must be a list of file names")
+    })?;
+    if files.len() > MAX_INCLUDES {
+        anyhow::bail!("invalid config.toml: more than {} includes", MAX_INCLUDES);
+    }
+    let mut merged = toml::Value::Table(Default::default());
+    for file in &files {
+        if !is_include_path(file) {
+            anyhow::bail!("invalid config.toml: include {:?} must be a file inside the bundle (relative, no ..)", file);
+        }
+        let text =
+            read(Path::new(file)).map_err(|e| anyhow::anyhow!("include {:?}: {:#}", file, e))?;
+        let included: toml::Value =
+            toml::from_str(&text).map_err(|e| anyhow::anyhow!("invalid {}: {}", file, e))?;
+        if included.get("include").is_some() {
+            anyhow::bail!("invalid {}: included files cannot include others", file);
+        }
+        merge(&mut merged, included);
+    }
+    merge(&mut merged, value);
+    let text =
+        toml::to_string(&merged).map_err(|e| anyhow::anyhow!("invalid config.toml: {}", e))?;
+    if text.len() as u64 > MAX_CONFIG_BYTES {
+        anyhow::bail!(
+            "config.toml with its includes is too large ({} bytes, limit {})",
+            text.len(),
+            MAX_CONFIG_BYTES
+        );
+    }
+    Ok(text)
+}
+
+/// Merge `over` into `base`: tables key by key, anything else replaced.
+fn merge(base: &mut toml::Value, over: toml::Value) {
+    match (base, over) {
+        (toml::Value::Table(base), toml::Value::Table(over)) => {
+            for (key, value) in over {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, over) => *base = over,
+    }
+}
+
+/// Parse config.toml text. The root daemon parses user-supplied files, so size, nesting depth,
+/// array lengths and string lengths are bounded before the text is mapped onto Config.
+pub fn parse(s: &str) -> anyhow::Result<Config> {
+    if s.len() as u64 > MAX_CONFIG_BYTES {
+        anyhow::bail!(
+            "config.toml is too large ({} bytes, limit {})",
+            s.len(),
+            MAX_CONFIG_BYTES
+        );
+    }
+    let value: toml::Value =
+        toml::from_str(s).map_err(|e| anyhow::anyhow!("invalid config.toml: {}", e))?;
+    check_limits(&value, 0)?;
+    value
+        .try_into()
+        .map_err(|e| anyhow::anyhow!("invalid config.toml: {}", e))
+}
+
+/// Reject absurd documents: too deep, too many array entries, or oversized strings.
+fn check_limits(value: &toml::Value, depth: usize) -> anyhow::Result<()> {
+    if depth > MAX_DEPTH {
+        anyhow::bail!(
+            "invalid config.toml: nested deeper than {} levels",
+            MAX_DEPTH
+        );
+    }
+    match value {
+        toml::Value::String(s) if s.len() > MAX_STRING_LEN => {
+            anyhow::bail!(
+                "invalid config.toml: string longer than {} bytes",
+                MAX_STRING_LEN
+            )
+        }
+        toml::Value::Array(items) => {
+            if items.len() > MAX_ARRAY_LEN {
+                anyhow::bail!(
+                    "invalid config.toml: array with more than {} entries",
+                    MAX_ARRAY_LEN
+                );
+            }
+            for item in items {
+                check_limits(item, depth + 1)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            if table.len() > MAX_ARRAY_LEN {
+                anyhow::bail!(
+                    "invalid config.toml: table with more than {} keys",
+                    MAX_ARRAY_LEN
+                );
+            }
+            for (key, item) in table {
+                if key.len() > MAX_STRING_LEN {
+                    anyhow::bail!(
+                        "invalid config.toml: key longer than {} bytes",
+                        MAX_STRING_LEN
+                    );
+                }
+                check_limits(item, depth + 1)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -144,4 +808,211 @@ network = true
         let err = load(dir.path()).unwrap_err();
         assert!(err.to_string().to_lowercase().contains("invalid"));
     }
+
+    #[test]
+    fn load_config_userns_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |v: &str| {
+            std::fs::write(
+                dir.path().join("config.toml"),
+                format!(
+                    "name = \"a\"\nexecutable = \"a\"\n[security]\nuserns = {}\n",
+                    v
+                ),
+            )
+            .unwrap();
+        };
+        write("true");
+        assert_eq!(
+            load(dir.path()).unwrap().security.unwrap().userns,
+            Userns::Allow
+        );
+        write("\"unconfined\"");
+        assert_eq!(
+            load(dir.path()).unwrap().security.unwrap().userns,
+            Userns::Unconfined
+        );
+        write("\"bogus\"");
+        assert!(load(dir.path()).is_err());
+    }
+
+    #[test]
+    fn load_config_seccomp_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let load_with = |v: &str| {
+            std::fs::write(
+                dir.path().join("config.toml"),
+                format!(
+                    "name = \"a\"\nexecutable = \"a\"\n[security]\nseccomp = {}\n",
+                    v
+                ),
+            )
+            .unwrap();
+            load(dir.path()).map(|c| c.security.unwrap().seccomp)
+        };
+        assert_eq!(load_with("\"default\"").unwrap(), Some(Seccomp::Default));
+        assert_eq!(load_with("\"strict\"").unwrap(), Some(Seccomp::Strict));
+        assert_eq!(
+            load_with("\"seccomp.json\"").unwrap(),
+            Some(Seccomp::Profile("seccomp.json".into()))
+        );
+        assert!(load_with("\"\"").is_err());
+        assert!(load_with("true").is_err());
+    }
+
+    #[test]
+    fn parse_umask_and_ionice() {
+        let parse_with =
+            |extra: &str| parse(&format!("name = \"a\"\nexecutable = \"a\"\n{}", extra));
+        let cfg = parse_with("umask = \"0077\"\nionice = \"idle\"\n").unwrap();
+        assert_eq!(cfg.umask, Some(Umask(0o077)));
+        assert_eq!(cfg.ionice, Some(Ionice::Idle));
+        let cfg = parse_with("ionice = \"best-effort\"\n").unwrap();
+        assert_eq!(cfg.ionice, Some(Ionice::BestEffort(4)));
+        for bad in [
+            "umask = \"8\"",
+            "umask = \"\"",
+            "umask = \"1777\"",
+            "umask = 77",
+            "ionice = \"realtime:1\"",
+            "ionice = \"best-effort:8\"",
+        ] {
+            assert!(parse_with(&format!("{}\n", bad)).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn parse_listen_sockets() {
+        let listen = |v: &str| {
+            parse(&format!(
+                "name = \"a\"\nexecutable = \"a\"\n[service]\nlisten = [{}]\n",
+                v
+            ))
+            .map(|c| c.service.unwrap().listen)
+        };
+        assert_eq!(
+            listen("\"8080\", \"127.0.0.1:80\", \"[::1]:8080\", \"/run/a.sock\"").unwrap(),
+            vec![
+                Listen::Port(8080),
+                Listen::Address("127.0.0.1:80".into()),
+                Listen::Address("[::1]:8080".into()),
+                Listen::Path("/run/a.sock".into()),
+            ]
+        );
+        for bad in [
+            "\"0\"",
+            "\"a.sock\"",
+            "\"example.com:80\"",
+            "\"1.2.3.4:99999\"",
+            "\"/run/../a\"",
+            "8080",
+        ] {
+            assert!(listen(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn parse_rejects_oversized_configs() {
+        let base = "name = \"a\"\nexecutable = \"run.sh\"\n";
+        assert!(parse(base).is_ok());
+
+        let too_big = format!(
+            "{}comment = \"{}\"\n",
+            base,
+            "x".repeat(MAX_CONFIG_BYTES as usize)
+        );
+        assert!(parse(&too_big)
+            .unwrap_err()
+            .to_string()
+            .contains("too large"));
+
+        let long_string = format!("{}comment = \"{}\"\n", base, "x".repeat(MAX_STRING_LEN + 1));
+        assert!(parse(&long_string)
+            .unwrap_err()
+            .to_string()
+            .contains("string"));
+
+        let many = vec!["\"x\""; MAX_ARRAY_LEN + 1].join(",");
+        let long_array = format!("{}args = [{}]\n", base, many);
+        assert!(parse(&long_array)
+            .unwrap_err()
+            .to_string()
+            .contains("array"));
+
+        let deep = format!("{}args = {}1{}\n", base, "[".repeat(20), "]".repeat(20));
+        assert!(parse(&deep).unwrap_err().to_string().contains("nested"));
+    }
+
+    #[test]
+    fn read_refuses_oversized_and_special_config_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            format!("comment = \"{}\"\n", "x".repeat(MAX_CONFIG_BYTES as usize)),
+        )
+        .unwrap();
+        assert!(read(dir.path())
+            .unwrap_err()
+            .to_string()
+            .contains("too large"));
+
+        std::fs::remove_file(&path).unwrap();
+        nix::unistd::mkfifo(&path, nix::sys::stat::Mode::from_bits_truncate(0o600)).unwrap();
+        assert!(read(dir.path())
+            .unwrap_err()
+            .to_string()
+            .contains("not a regular file"));
+    }
+
+    #[test]
+    fn includes_are_merged_under_config_toml() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("shared")).unwrap();
+        std::fs::write(
+            dir.path().join("shared/common.toml"),
+            "env = [\"A=1\"]\ncomment = \"family\"\n[security]\nnetwork = false\nread_paths = [\"/srv/data\"]\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("gpu.toml"),
+            "[security]\ngpu = true\nnetwork = true\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("config.toml"),
+            "include = [\"shared/common.toml\", \"gpu.toml\"]\nname = \"a\"\nexecutable = \"run.sh\"\nenv = [\"B=2\"]\n[security]\nnetwork = false\n",
+        )
+        .unwrap();
+        let cfg = load(dir.path()).unwrap();
+        assert_eq!(cfg.env, ["B=2"], "config.toml replaces included arrays");
+        assert_eq!(cfg.comment.as_deref(), Some("family"));
+        let sec = cfg.security.unwrap();
+        assert!(sec.gpu && !sec.network, "tables merge; later files win");
+        assert_eq!(sec.read_paths, ["/srv/data"]);
+        assert_eq!(
+            included_files(dir.path()),
+            ["shared/common.toml", "gpu.toml"]
+        );
+
+        let base = "name = \"a\"\nexecutable = \"run.sh\"\n";
+        for bad in ["../x.toml", "/etc/x.toml", "config.toml", ""] {
+            let text = format!("include = [{:?}]\n{}", bad, base);
+            assert!(
+                expand_includes(&text, |_| Ok(String::new())).is_err(),
+                "{}",
+                bad
+            );
+        }
+        let nested = format!("include = [\"a.toml\"]\n{}", base);
+        assert!(expand_includes(&nested, |_| Ok("include = [\"b.toml\"]\n".into())).is_err());
+        assert_eq!(expand_includes(base, |_| unreachable!()).unwrap(), base);
+        std::os::unix::fs::symlink("/etc/hostname", dir.path().join("link.toml")).unwrap();
+        std::fs::write(
+            dir.path().join("config.toml"),
+            format!("include = [\"link.toml\"]\n{}", base),
+        )
+        .unwrap();
+        assert!(load(dir.path()).is_err(), "symlinked includes are refused");
+    }
 }
@@ -0,0 +1,357 @@
+//! `dotlnx denial-agent`: an opt-in per-user agent that watches for AppArmor denials of the
+//! user's dotlnx apps and asks what to do about each one, like a firewall prompt: allow the path
+//! until reboot (a `dotlnx grant` through pkexec, so an admin password or polkit rule decides),
+//! never ask again for it, or ignore it this time.
+//!
+//! Denials come from the kernel log (`journalctl -k`), which carries the audit records when
+//! auditd is not running; the user must be allowed to read it (systemd-journal or adm group on
+//! most distributions). Only file denials can become a grant; the others are printed.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tracing::{info, warn};
+
+use crate::bundle;
+use crate::config;
+use crate::paths::{self, Paths};
+use crate::state;
+
+/// One AppArmor denial from an audit record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Denial {
+    /// Profile that denied, without a child profile ("//helper").
+    pub profile: String,
+    pub operation: String,
+    /// File the app tried to use; None for network, capability, signal, ... denials.
+    pub name: Option<String>,
+    /// Access asked for ("r", "w", "rw", "x", ...).
+    pub requested: String,
+    /// Uid the app was running as.
+    pub fsuid: Option<u32>,
+}
+
+impl Denial {
+    /// The `dotlnx grant` rule that would allow it; None when a grant cannot (exec, non-file).
+    pub fn rule(&self) -> Option<String> {
+        let name = self.name.as_deref().filter(|n| n.starts_with('/'))?;
+        if self.requested.contains(['x', 'm']) {
+            return None;
+        }
+        let access = match self.requested.contains(['w', 'a', 'c', 'd']) {
+            true => "rw",
+            false if self.requested.contains('r') => "read",
+            false => return None,
+        };
+        Some(format!("{}:{}", access, name))
+    }
+
+    /// Key under which "never ask again" is remembered.
+    fn key(&self) -> String {
+        format!(
+            "{} {} {}",
+            self.profile,
+            self.requested,
+            self.name.as_deref().unwrap_or(&self.operation)
+        )
+    }
+}
+
+/// Parse an audit record (`audit: type=1400 ...: apparmor="DENIED" ...`); None unless it is an
+/// AppArmor denial.
+pub fn parse(line: &str) -> Option<Denial> {
+    let fields = fields(line);
+    if fields.get("apparmor").map(String::as_str) != Some("DENIED") {
+        return None;
+    }
+    let profile = fields.get("profile")?;
+    let profile = profile.split("//").next().unwrap_or(profile).to_string();
+    Some(Denial {
+        profile,
+        operation: fields.get("operation").cloned().unwrap_or_default(),
+        name: fields.get("name").cloned(),
+        requested: fields
+            .get("requested_mask")
+            .or_else(|| fields.get("denied_mask"))
+            .cloned()
+            .unwrap_or_default(),
+        fsuid: fields.get("fsuid").and_then(|u| u.parse().ok()),
+    })
+}
+
+/// key=value pairs of an audit record. Quoted values are taken as is; unquoted `name` and
+/// `profile` values are hex (the kernel encodes names with spaces or odd bytes that way).
+fn fields(line: &str) -> BTreeMap<String, String> {
+    let mut out = BTreeMap::new();
+    let mut rest = line;
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].rsplit(' ').next().unwrap_or("").to_string();
+        let after = &rest[eq + 1..];
+        let (value, next) = match after.strip_prefix('"') {
+            Some(quoted) => match quoted.find('"') {
+                Some(end) => (quoted[..end].to_string(), &quoted[end + 1..]),
+                None => (quoted.to_string(), ""),
+            },
+            None => {
+                let end = after.find(' ').unwrap_or(after.len());
+                let raw = &after[..end];
+                let value = match key.as_str() {
+                    "name" | "profile" => decode_hex(raw).unwrap_or_else(|| raw.to_string()),
+                    _ => raw.to_string(),
+                };
+                (value, &after[end..])
+            }
+        };
+        out.entry(key).or_insert(value);
+        rest = next;
+    }
+    out
+}
+
+fn decode_hex(s: &str) -> Option<String> {
+    if !s.len().is_multiple_of(2) || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let bytes: Vec<u8> = (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16))
+        .collect::<Result<_, _>>()
+        .ok()?;
+    String::from_utf8(bytes).ok()
+}
+
+/// What the user chose in the prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Answer {
+    Allow,
+    Silence,
+    Ignore,
+}
+
+/// Denials the user asked never to be prompted about again.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Silenced {
+    #[serde(default)]
+    denials: BTreeSet<String>,
+}
+
+impl Silenced {
+    fn path() -> Result<PathBuf> {
+        Ok(state::state_dir()?.join("silenced-denials.json"))
+    }
+
+    fn load() -> Self {
+        Self::path()
+            .ok()
+            .and_then(|p| std::fs::read_to_string(p).ok())
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// The user the agent runs for and the profiles of the apps they can launch.
+struct Me {
+    uid: u32,
+    name: String,
+    paths: Paths,
+    /// Profile name -> app name, for the user's bundles and /Applications.
+    apps: BTreeMap<String, String>,
+}
+
+impl Me {
+    /// Re-read the bundles (apps installed since the agent started have no entry yet).
+    fn refresh(&mut self) {
+        self.apps.clear();
+        for (dir, user) in [
+            (&self.paths.user_apps, Some(self.name.as_str())),
+            (&self.paths.system_apps, None),
+        ] {
+            for bundle in bundle::discover_lnx_dirs(dir) {
+                if let Ok(cfg) = config::load(&bundle) {
+                    self.apps
+                        .insert(self.paths.profile_name(user, &cfg.app_id()), cfg.app_id());
+                }
+            }
+        }
+    }
+
+    /// App name of a denial that belongs to this user, else None.
+    fn app_for(&mut self, denial: &Denial) -> Option<String> {
+        let ours = [&self.paths.user_prefix, &self.paths.system_prefix]
+            .iter()
+            .any(|prefix| denial.profile.starts_with(prefix.as_str()));
+        if !ours || denial.fsuid.is_some_and(|uid| uid != self.uid) {
+            return None;
+        }
+        if !self.apps.contains_key(&denial.profile) {
+            self.refresh();
+        }
+        self.apps.get(&denial.profile).cloned()
+    }
+}
+
+/// Entry point for `dotlnx denial-agent`: follow the kernel log until it ends. With `prompt`
+/// false, denials are only printed.
+pub fn run(prompt: bool) -> Result<()> {
+    if bundle::is_root() {
+        anyhow::bail!("run dotlnx denial-agent as a desktop user, not root");
+    }
+    let user = nix::unistd::User::from_uid(nix::unistd::getuid())?
+        .ok_or_else(|| anyhow::anyhow!("current user not found"))?;
+    let mut me = Me {
+        uid: user.uid.as_raw(),
+        name: user.name,
+        paths: paths::load()?,
+        apps: BTreeMap::new(),
+    };
+    me.refresh();
+    let exe = std::env::current_exe().context("locate dotlnx binary")?;
+    let mut silenced = Silenced::load();
+    let mut seen = HashSet::new();
+    let mut child = Command::new("journalctl")
+        .args(["-k", "-f", "-n", "0", "-o", "cat"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("start journalctl (the agent reads denials from the kernel log)")?;
+    let stdout = child.stdout.take().context("journalctl stdout")?;
+    info!("watching for AppArmor denials of {}'s apps", me.name);
+    for line in std::io::BufReader::new(stdout).lines() {
+        let Some(denial) = parse(&line?) else {
+            continue;
+        };
+        let Some(app) = me.app_for(&denial) else {
+            continue;
+        };
+        // One prompt per denial per session: apps retry the same access in a loop.
+        if silenced.denials.contains(&denial.key()) || !seen.insert(denial.key()) {
+            continue;
+        }
+        let what = denial.name.as_deref().unwrap_or(&denial.operation);
+        println!(
+            "{}: denied {} {} ({})",
+            app, denial.requested, what, denial.operation
+        );
+        let Some(rule) = denial.rule().filter(|_| prompt) else {
+            continue;
+        };
+        match ask(&app, &denial, &rule) {
+            Answer::Allow => allow(&exe, &app, &rule),
+            Answer::Silence => {
+                silenced.denials.insert(denial.key());
+                if let Err(e) = silenced.save() {
+                    warn!("could not remember the choice: {:#}", e);
+                }
+            }
+            Answer::Ignore => {}
+        }
+    }
+    let _ = child.wait();
+    Ok(())
+}
+
+/// Ask with a yad dialog; no yad (or a closed dialog) counts as ignore.
+fn ask(app: &str, denial: &Denial, rule: &str) -> Answer {
+    let access = if rule.starts_with("rw:") {
+        "write to"
+    } else {
+        "read"
+    };
+    let text = format!(
+        "<b>{}</b> was not allowed to {}\n<tt>{}</tt>\n\nAllow it until the next reboot? An administrator password may be asked for.",
+        markup(app),
+        access,
+        markup(denial.name.as_deref().unwrap_or_default())
+    );
+    let status = Command::new("yad")
+        .args([
+            "--title=dotlnx",
+            "--image=dialog-password",
+            "--window-icon=dialog-password",
+            "--on-top",
+        ])
+        .arg(format!("--text={}", text))
+        .args([
+            "--button=Ignore:1",
+            "--button=Don't ask again:2",
+            "--button=Allow until reboot:0",
+        ])
+        .status();
+    match status.map(|s| s.code()) {
+        Ok(Some(0)) => Answer::Allow,
+        Ok(Some(2)) => Answer::Silence,
+        Ok(_) => Answer::Ignore,
+        Err(e) => {
+            warn!("cannot show the prompt (needs yad): {}", e);
+            Answer::Ignore
+        }
+    }
+}
+
+/// Grant through pkexec; the app sees the path from its next launch.
+fn allow(exe: &Path, app: &str, rule: &str) {
+    let status = Command::new("pkexec")
+        .arg(exe)
+        .args(["grant", app, rule, "--until", "reboot"])
+        .status();
+    match status {
+        Ok(s) if s.success() => info!(app, rule, "granted until reboot; restart the app to use it"),
+        Ok(s) => warn!(app, rule, "grant failed ({})", s),
+        Err(e) => warn!(
+            app,
+            "cannot run pkexec ({}); ask an administrator to run: sudo dotlnx grant {:?} {}",
+            e,
+            app,
+            rule
+        ),
+    }
+}
+
+/// Escape text for yad's Pango markup.
+fn markup(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_denials_and_suggests_rules() {
+        let line = r#"audit: type=1400 audit(1700000000.123:456): apparmor="DENIED" operation="open" class="file" profile="dotlnx-alice-MyApp" name="/srv/share/report.pdf" pid=4242 comm="myapp" requested_mask="r" denied_mask="r" fsuid=1000 ouid=0"#;
+        let denial = parse(line).unwrap();
+        assert_eq!(denial.profile, "dotlnx-alice-MyApp");
+        assert_eq!(denial.name.as_deref(), Some("/srv/share/report.pdf"));
+        assert_eq!(denial.fsuid, Some(1000));
+        assert_eq!(denial.rule().as_deref(), Some("read:/srv/share/report.pdf"));
+
+        // Names with spaces are hex; child profiles count as their app's.
+        let line = r#"audit: type=1400 audit(1.2:3): apparmor="DENIED" operation="mknod" profile="dotlnx-MyApp//bin/ffmpeg" name=2F746D702F6120622E6D7034 requested_mask="c" denied_mask="c" fsuid=1000"#;
+        let denial = parse(line).unwrap();
+        assert_eq!(denial.profile, "dotlnx-MyApp");
+        assert_eq!(denial.rule().as_deref(), Some("rw:/tmp/a b.mp4"));
+
+        let exec = r#"apparmor="DENIED" operation="exec" profile="dotlnx-MyApp" name="/usr/bin/sh" requested_mask="x""#;
+        assert_eq!(parse(exec).unwrap().rule(), None);
+        let net = r#"apparmor="DENIED" operation="create" profile="dotlnx-MyApp" family="inet" sock_type="stream""#;
+        assert_eq!(parse(net).unwrap().name, None);
+        assert!(
+            parse(r#"apparmor="ALLOWED" operation="open" profile="dotlnx-MyApp" name="/x""#)
+                .is_none()
+        );
+        assert!(parse("usb 1-1: new high-speed USB device").is_none());
+    }
+}
@@ -0,0 +1,446 @@
+//! `dotlnx validate --check-deps`: find the shared libraries a bundle's ELF files need
+//! (DT_NEEDED) and check each resolves the way the dynamic loader would look for it: the file's
+//! RPATH/RUNPATH (with $ORIGIN), the bundle itself (wrapper scripts often set LD_LIBRARY_PATH to
+//! a lib/ dir), then the host's library dirs. Nothing is executed: `ldd` runs the loader on the
+//! binary, which is unsafe for bundles not yet trusted.
+//!
+//! Missing libraries are errors. Libraries found only on this host and outside the set every
+//! desktop system has (glibc, the C++ runtime, graphics, audio and display stacks) are reported
+//! as ones to vendor or document, since the next machine may not have them.
+
+use anyhow::{Context, Result};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Files examined per bundle at most.
+const MAX_FILES: usize = 20_000;
+/// Largest dynamic section and string table read.
+const MAX_TABLE: u64 = 1024 * 1024;
+/// Most program headers accepted.
+const MAX_PHNUM: u16 = 512;
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const DT_NEEDED: u64 = 1;
+const DT_STRTAB: u64 = 5;
+const DT_STRSZ: u64 = 10;
+const DT_RPATH: u64 = 15;
+const DT_RUNPATH: u64 = 29;
+
+/// Libraries every desktop host provides (or must provide itself, like GL drivers): never
+/// suggested for vendoring. Prefixes of file names.
+const BASE_LIBS: &[&str] = &[
+    "ld-linux",
+    "libc.so",
+    "libm.so",
+    "libdl.so",
+    "libpthread.so",
+    "librt.so",
+    "libresolv.so",
+    "libutil.so",
+    "libanl.so",
+    "libcrypt.so",
+    "libstdc++.so",
+    "libgcc_s.so",
+    "libz.so",
+    "libGL",
+    "libEGL",
+    "libOpenGL",
+    "libvulkan.so",
+    "libdrm.so",
+    "libgbm.so",
+    "libX",
+    "libxcb",
+    "libxkbcommon",
+    "libwayland-",
+    "libasound.so",
+    "libpulse",
+    "libpipewire-",
+    "libdbus-1.so",
+    "libudev.so",
+    "libsystemd.so",
+    "libfontconfig.so",
+    "libfreetype.so",
+    "libexpat.so",
+    "libuuid.so",
+    "libselinux.so",
+];
+
+/// What the loader needs to know about one ELF file.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Dynamic {
+    /// ELFCLASS64.
+    pub class64: bool,
+    pub machine: u16,
+    pub needed: Vec<String>,
+    /// RPATH and RUNPATH entries, as written.
+    pub search: Vec<String>,
+}
+
+/// One ELF file's unresolved and host-only libraries.
+#[derive(Debug, Default)]
+pub struct FileReport {
+    /// Relative to the bundle.
+    pub file: PathBuf,
+    pub missing: Vec<String>,
+    pub vendor: Vec<String>,
+}
+
+/// Little ELF reader over a file, for the few fields needed.
+struct Reader {
+    file: File,
+    class64: bool,
+    le: bool,
+}
+
+impl Reader {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(buf)
+    }
+
+    fn u16(&self, b: &[u8]) -> u16 {
+        let b = [b[0], b[1]];
+        if self.le {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        }
+    }
+
+    fn u32(&self, b: &[u8]) -> u32 {
+        let b = [b[0], b[1], b[2], b[3]];
+        if self.le {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        }
+    }
+
+    /// A word: 8 bytes in 64-bit files, 4 in 32-bit ones.
+    fn word(&self, b: &[u8]) -> u64 {
+        if !self.class64 {
+            return self.u32(b) as u64;
+        }
+        let b = [b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]];
+        if self.le {
+            u64::from_le_bytes(b)
+        } else {
+            u64::from_be_bytes(b)
+        }
+    }
+}
+
+/// Class and machine of an ELF file; None when it is not one.
+fn header(path: &Path) -> Option<(bool, u16)> {
+    let mut h = [0u8; 20];
+    File::open(path).ok()?.read_exact(&mut h).ok()?;
+    if h[..4] != *b"\x7fELF" {
+        return None;
+    }
+    let machine = match h[5] {
+        1 => u16::from_le_bytes([h[18], h[19]]),
+        2 => u16::from_be_bytes([h[18], h[19]]),
+        _ => return None,
+    };
+    Some((h[4] == 2, machine))
+}
+
+/// The dynamic linking info of an ELF file: None when `path` is not ELF; empty `needed` for a
+/// static binary.
+pub fn read_dynamic(path: &Path) -> Result<Option<Dynamic>> {
+    let Some((class64, machine)) = header(path) else {
+        return Ok(None);
+    };
+    let mut ident = [0u8; 6];
+    let mut file = File::open(path)?;
+    file.read_exact(&mut ident)?;
+    let mut r = Reader {
+        file,
+        class64,
+        le: ident[5] == 1,
+    };
+    let mut eh = [0u8; 64];
+    let eh_len = if class64 { 64 } else { 52 };
+    r.read_at(0, &mut eh[..eh_len])?;
+    let (phoff, phentsize, phnum) = if class64 {
+        (r.word(&eh[0x20..]), r.u16(&eh[0x36..]), r.u16(&eh[0x38..]))
+    } else {
+        (r.word(&eh[0x1c..]), r.u16(&eh[0x2a..]), r.u16(&eh[0x2c..]))
+    };
+    let min_phentsize = if class64 { 56 } else { 32 };
+    if phnum > MAX_PHNUM || (phnum > 0 && (phentsize as usize) < min_phentsize) {
+        anyhow::bail!("malformed ELF program headers");
+    }
+    // (offset, vaddr, filesz) of PT_LOAD segments, and the dynamic segment.
+    let mut loads = Vec::new();
+    let mut dynamic = None;
+    let mut ph = vec![0u8; phentsize as usize];
+    for i in 0..phnum as u64 {
+        r.read_at(phoff + i * phentsize as u64, &mut ph)?;
+        let p_type = r.u32(&ph);
+        let (offset, vaddr, filesz) = if class64 {
+            (r.word(&ph[8..]), r.word(&ph[16..]), r.word(&ph[32..]))
+        } else {
+            (r.word(&ph[4..]), r.word(&ph[8..]), r.word(&ph[16..]))
+        };
+        match p_type {
+            PT_LOAD => loads.push((offset, vaddr, filesz)),
+            PT_DYNAMIC => dynamic = Some((offset, filesz)),
+            _ => {}
+        }
+    }
+    let mut info = Dynamic {
+        class64,
+        machine,
+        ..Dynamic::default()
+    };
+    let Some((dyn_off, dyn_size)) = dynamic else {
+        return Ok(Some(info));
+    };
+    let mut table = vec![0u8; dyn_size.min(MAX_TABLE) as usize];
+    r.read_at(dyn_off, &mut table)?;
+    let entry = if class64 { 16 } else { 8 };
+    let half = entry / 2;
+    let (mut needed, mut search) = (Vec::new(), Vec::new());
+    let (mut strtab, mut strsz) = (None, 0);
+    for e in table.chunks_exact(entry) {
+        let (tag, val) = (r.word(e), r.word(&e[half..]));
+        match tag {
+            0 => break,
+            DT_NEEDED => needed.push(val),
+            DT_RPATH | DT_RUNPATH => search.push(val),
+            DT_STRTAB => strtab = Some(val),
+            DT_STRSZ => strsz = val,
+            _ => {}
+        }
+    }
+    let strtab = strtab.context("ELF dynamic section without a string table")?;
+    // DT_STRTAB is an address: find the file offset of the segment that maps it.
+    let str_off = loads
+        .iter()
+        .find(|(_, vaddr, filesz)| strtab >= *vaddr && strtab < vaddr + filesz)
+        .map(|(offset, vaddr, _)| offset + (strtab - vaddr))
+        .context("ELF string table outside the loaded segments")?;
+    let mut strings = vec![0u8; strsz.min(MAX_TABLE) as usize];
+    r.read_at(str_off, &mut strings)?;
+    let string = |at: u64| -> Option<String> {
+        let rest = strings.get(at as usize..)?;
+        let end = rest.iter().position(|&b| b == 0)?;
+        Some(String::from_utf8_lossy(&rest[..end]).into_owned())
+    };
+    info.needed = needed.into_iter().filter_map(string).collect();
+    info.search = search
+        .into_iter()
+        .filter_map(string)
+        .flat_map(|s| s.split(':').map(str::to_string).collect::<Vec<_>>())
+        .filter(|s| !s.is_empty())
+        .collect();
+    Ok(Some(info))
+}
+
+/// The host's library directories: the loader's defaults plus /etc/ld.so.conf and its includes.
+pub fn host_lib_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    read_ld_so_conf(Path::new("/etc/ld.so.conf"), &mut dirs, 0);
+    for d in ["/lib64", "/usr/lib64", "/lib", "/usr/lib"] {
+        dirs.push(PathBuf::from(d));
+    }
+    let mut seen = BTreeSet::new();
+    dirs.retain(|d| seen.insert(d.clone()));
+    dirs
+}
+
+fn read_ld_so_conf(path: &Path, dirs: &mut Vec<PathBuf>, depth: usize) {
+    let Ok(text) = std::fs::read_to_string(path) else {
+        return;
+    };
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if let Some(pattern) = line.strip_prefix("include").map(str::trim) {
+            if depth > 4 {
+                continue;
+            }
+            // Only "dir/*.suffix" patterns, which is what distributions ship.
+            let pattern = Path::new(pattern);
+            let (Some(dir), Some(glob)) = (
+                pattern.parent(),
+                pattern.file_name().and_then(|n| n.to_str()),
+            ) else {
+                continue;
+            };
+            let suffix = glob.strip_prefix('*').unwrap_or(glob);
+            let Ok(entries) = std::fs::read_dir(dir) else {
+                continue;
+            };
+            let mut files: Vec<PathBuf> = entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.to_str().is_some_and(|s| s.ends_with(suffix)))
+                .collect();
+            files.sort();
+            for f in files {
+                read_ld_so_conf(&f, dirs, depth + 1);
+            }
+        } else if line.starts_with('/') {
+            dirs.push(PathBuf::from(line));
+        }
+    }
+}
+
+/// True for libraries not worth vendoring (see [`BASE_LIBS`]).
+fn is_base(lib: &str) -> bool {
+    BASE_LIBS.iter().any(|p| lib.starts_with(p))
+}
+
+/// Check every ELF file in `bundle_root` against the bundle and `host_dirs`.
+/// Only files that have a problem are returned.
+pub fn check(bundle_root: &Path, host_dirs: &[PathBuf]) -> Result<Vec<FileReport>> {
+    // Container bundles run against their own root file system, not the host's.
+    let skip = bundle_root
+        .join(crate::oci::IMAGE_FILE)
+        .is_file()
+        .then(|| bundle_root.join(crate::oci::ROOTFS_DIR));
+    let mut elves = Vec::new();
+    // Every library file in the bundle by name, for wrappers that set LD_LIBRARY_PATH.
+    let mut bundled: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    let walk = walkdir::WalkDir::new(bundle_root)
+        .follow_links(false)
+        .into_iter()
+        .filter_entry(|e| skip.as_deref() != Some(e.path()));
+    for entry in walk
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .take(MAX_FILES)
+    {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let is_lib = name.contains(".so");
+        #[cfg(unix)]
+        let executable = {
+            use std::os::unix::fs::PermissionsExt;
+            entry
+                .metadata()
+                .is_ok_and(|m| m.permissions().mode() & 0o111 != 0)
+        };
+        #[cfg(not(unix))]
+        let executable = false;
+        if is_lib {
+            bundled
+                .entry(name)
+                .or_default()
+                .push(entry.path().to_path_buf());
+        }
+        if is_lib || executable {
+            elves.push(entry.into_path());
+        }
+    }
+    let mut reports = Vec::new();
+    for path in elves {
+        let info = match read_dynamic(&path) {
+            Ok(Some(info)) => info,
+            Ok(None) => continue,
+            Err(e) => anyhow::bail!("{}: {}", path.display(), e),
+        };
+        let matches = |candidate: &Path| header(candidate) == Some((info.class64, info.machine));
+        let origin = path.parent().unwrap_or(bundle_root);
+        let mut report = FileReport {
+            file: path
+                .strip_prefix(bundle_root)
+                .unwrap_or(&path)
+                .to_path_buf(),
+            ..FileReport::default()
+        };
+        for lib in &info.needed {
+            if lib.contains('/') {
+                if !matches(&origin.join(lib)) {
+                    report.missing.push(lib.clone());
+                }
+                continue;
+            }
+            let in_rpath = info.search.iter().any(|dir| {
+                let dir = dir
+                    .replace("${ORIGIN}", "$ORIGIN")
+                    .replace("$ORIGIN", &origin.to_string_lossy());
+                matches(&Path::new(&dir).join(lib))
+            });
+            let in_bundle = || {
+                bundled
+                    .get(lib)
+                    .is_some_and(|paths| paths.iter().any(|p| matches(p)))
+            };
+            if in_rpath || in_bundle() {
+                continue;
+            }
+            match host_dirs.iter().any(|d| matches(&d.join(lib))) {
+                true if !is_base(lib) => report.vendor.push(lib.clone()),
+                true => {}
+                false => report.missing.push(lib.clone()),
+            }
+        }
+        if !report.missing.is_empty() || !report.vendor.is_empty() {
+            reports.push(report);
+        }
+    }
+    Ok(reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_needed_libraries_of_a_real_binary() {
+        // The test binary itself links libc dynamically on the usual targets.
+        let exe = std::env::current_exe().unwrap();
+        let info = read_dynamic(&exe).unwrap().unwrap();
+        assert!(
+            info.needed.iter().any(|l| l.starts_with("libc.so")),
+            "{:?}",
+            info.needed
+        );
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("script"), "#!/bin/sh\n").unwrap();
+        assert_eq!(read_dynamic(&dir.path().join("script")).unwrap(), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn check_reports_missing_vendorable_and_bundled_libraries() {
+        use std::os::unix::fs::PermissionsExt;
+        let exe = std::env::current_exe().unwrap();
+        let info = read_dynamic(&exe).unwrap().unwrap();
+        let bundle = tempfile::tempdir().unwrap();
+        std::fs::create_dir(bundle.path().join("bin")).unwrap();
+        std::fs::copy(&exe, bundle.path().join("bin/app")).unwrap();
+        std::fs::set_permissions(
+            bundle.path().join("bin/app"),
+            std::fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+
+        // Real host dirs: everything resolves and only base libraries are needed.
+        assert!(check(bundle.path(), &host_lib_dirs()).unwrap().is_empty());
+
+        // No host dirs: every library is missing, until it is bundled (any ELF of the right
+        // machine stands in for it).
+        let report = check(bundle.path(), &[]).unwrap();
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].file, Path::new("bin/app"));
+        assert_eq!(report[0].missing, info.needed);
+        std::fs::create_dir(bundle.path().join("lib")).unwrap();
+        for lib in &info.needed {
+            std::fs::copy(&exe, bundle.path().join("lib").join(lib)).unwrap();
+        }
+        assert!(check(bundle.path(), &[]).unwrap().is_empty());
+
+        // Found only on the host: fine for base libraries, worth vendoring for the rest.
+        let host = tempfile::tempdir().unwrap();
+        let lib = &info.needed[0];
+        std::fs::rename(bundle.path().join("lib").join(lib), host.path().join(lib)).unwrap();
+        let report = check(bundle.path(), &[host.path().to_path_buf()]).unwrap();
+        assert_eq!(report.is_empty(), is_base(lib), "{:?}", report);
+        assert!(!is_base("libfoo.so.1") && is_base("libc.so.6") && is_base("libX11.so.6"));
+    }
+}
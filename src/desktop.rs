@@ -3,12 +3,14 @@
 //! and special characters cannot break the file or inject keys.
 
 use anyhow::Result;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+use crate::asuser;
 use crate::config::Config;
+use crate::paths::Paths;
 
 #[cfg(unix)]
-use nix::unistd::{chown, User};
+use nix::unistd::User;
 
 /// Escape a string for use as a .desktop key value per the Desktop Entry spec.
 /// Encodes `\` → `\\`, newline → `\n`, tab → `\t`, carriage return → `\r`.
@@ -28,95 +30,109 @@ pub fn escape_desktop_value(s: &str) -> String {
     out
 }
 
+/// A list value (Keywords=): escaped items, each followed by `;`.
+fn desktop_list(items: &[String]) -> String {
+    items
+        .iter()
+        .map(|s| format!("{};", escape_desktop_value(s)))
+        .collect()
+}
+
+/// Characters that force an Exec argument into double quotes (Desktop Entry spec).
+const EXEC_RESERVED: &[char] = &[
+    ' ', '\t', '\n', '"', '\'', '\\', '>', '<', '~', '|', '&', ';', '$', '*', '?', '#', '(', ')',
+    '`',
+];
+
 /// Escape a single argument for the Exec key (Desktop Entry spec).
-/// Escapes `\`, `"`, `` ` ``, `$`; if the result contains space or those chars, wraps in double quotes.
+/// Inside quotes, `"`, `` ` ``, `$` and `\` get a backslash; the backslashes are then doubled
+/// again for the string-value unescaping that runs first, so `\` becomes `\\\\`. A literal `%`
+/// is `%%` (not a field code); control characters become spaces.
 fn escape_exec_argument(s: &str) -> String {
     let mut out = String::with_capacity(s.len());
     for c in s.chars() {
         match c {
-            '\\' => out.push_str("\\\\"),
-            '"' => out.push_str("\\\""),
-            '`' => out.push_str("\\`"),
-            '$' => out.push_str("\\$"),
+            '\\' => out.push_str("\\\\\\\\"),
+            '"' => out.push_str("\\\\\""),
+            '`' => out.push_str("\\\\`"),
+            '$' => out.push_str("\\\\$"),
+            '%' => out.push_str("%%"),
+            c if c.is_control() => out.push(' '),
             c => out.push(c),
         }
     }
     out
 }
 
-/// Format one Exec component: quote and escape if it contains space or special chars.
-fn escape_for_exec_arg(s: &str) -> String {
-    let escaped = escape_exec_argument(s);
-    if escaped
-        .chars()
-        .any(|c| c == ' ' || c == '"' || c == '\\' || c == '`' || c == '$')
-    {
-        format!("\"{}\"", escaped)
+/// Format one Exec component: quote and escape if it contains space or other reserved chars.
+pub fn escape_for_exec_arg(s: &str) -> String {
+    if s.is_empty() || s.contains(EXEC_RESERVED) || s.contains(|c: char| c.is_control()) {
+        format!("\"{}\"", escape_exec_argument(s))
     } else {
-        escaped
+        escape_exec_argument(s)
     }
 }
 
-/// Build the Exec= line for a .desktop file: absolute path to the bundle executable
-/// (or `aa-exec -p PROFILE -- /path` when confined). Uses canonical path when the executable exists.
-fn build_exec_line(
+/// Command line that starts an app: the absolute path to the bundle executable and its args
+/// (behind `aa-exec -p PROFILE --` when confined), or `dotlnx run NAME` when a launch setting
+/// needs the runner. Uses canonical path when the executable exists. Not escaped.
+pub fn launch_argv(
     config: &crate::config::Config,
     bundle_root: &Path,
     profile_name: Option<&str>,
-) -> String {
+) -> Vec<String> {
+    if config.needs_runner() {
+        // The environment, syscall filter and process settings are set up by `dotlnx run`, which
+        // also applies the profile and args.
+        let dotlnx = std::env::current_exe()
+            .ok()
+            .and_then(|p| p.to_str().map(String::from))
+            .unwrap_or_else(|| "dotlnx".into());
+        return vec![dotlnx, "run".into(), config.app_id()];
+    }
     let exec_path = bundle_root.join(&config.executable);
-    let path_str = exec_path
-        .canonicalize()
+    let path_str = crate::fscache::canonicalize(&exec_path)
         .ok()
         .and_then(|p| p.to_str().map(String::from))
         .unwrap_or_else(|| exec_path.display().to_string());
-    let confine = config
-        .security
-        .as_ref()
-        .map(|s| s.confine)
-        .unwrap_or(true);
-    let mut parts: Vec<String> = if profile_name.is_some() && confine {
-        let profile = profile_name.unwrap();
+    let confine = config.security.as_ref().map(|s| s.confine).unwrap_or(true);
+    let mut argv: Vec<String> = if let (Some(profile), true) = (profile_name, confine) {
         vec![
             "aa-exec".into(),
             "-p".into(),
             profile.into(),
             "--".into(),
-            escape_for_exec_arg(&path_str),
+            path_str,
         ]
     } else {
-        vec![escape_for_exec_arg(&path_str)]
+        vec![path_str]
     };
-    for arg in &config.args {
-        parts.push(escape_for_exec_arg(arg));
-    }
-    parts.push("%u".into());
-    parts.join(" ")
-}
-
-/// User applications dir (XDG_DATA_HOME/applications). Used for user-tier .desktop files.
-pub fn user_applications_dir() -> Result<std::path::PathBuf> {
-    let dir = xdg::BaseDirectories::with_prefix("")?
-        .get_data_home()
-        .join("applications");
-    Ok(dir)
+    argv.extend(config.args.iter().cloned());
+    argv
 }
 
-/// System applications dir (/usr/share/applications). Used for system-tier .desktop files; requires root.
-pub fn system_applications_dir() -> std::path::PathBuf {
-    std::path::PathBuf::from("/usr/share/applications")
+/// Build the Exec= line for a .desktop file from [`launch_argv`], with %u for a file or URL.
+fn build_exec_line(
+    config: &crate::config::Config,
+    bundle_root: &Path,
+    profile_name: Option<&str>,
+) -> String {
+    let mut parts: Vec<String> = launch_argv(config, bundle_root, profile_name)
+        .iter()
+        .map(|a| escape_for_exec_arg(a))
+        .collect();
+    parts.push("%u".into());
+    parts.join(" ")
 }
 
 /// Generate .desktop file content for an app. Exec is the absolute path to the bundle executable
 /// (or `aa-exec -p PROFILE -- /path` when confined), so the launcher's process is the app, not dotlnx.
-/// All user-controlled values (name, comment, icon, categories) are escaped.
+/// All user-controlled values (name, comment, keywords, icon, categories, translations) are escaped.
 /// If `icon` is a relative path under the bundle, it is resolved to an absolute path.
 /// When `profile_name` is Some and [security] confine is true, Exec uses aa-exec for AppArmor.
-pub fn generate_desktop(
-    config: &Config,
-    bundle_root: &Path,
-    profile_name: Option<&str>,
-) -> String {
+/// When a launch setting needs the runner (clear_env, seccomp, no_new_privs, ephemeral_home,
+/// umask, nice, ionice, force_bridges), Exec is `dotlnx run NAME` instead, which applies them.
+pub fn generate_desktop(config: &Config, bundle_root: &Path, profile_name: Option<&str>) -> String {
     let name = escape_desktop_value(&config.name);
     let exec = build_exec_line(config, bundle_root, profile_name);
     let mut out = format!(
@@ -130,9 +146,42 @@ pub fn generate_desktop(
         let path_abs = bundle_root.join(workdir).display().to_string();
         out.push_str(&format!("Path={}\n", escape_desktop_value(&path_abs)));
     }
+    if let Some(ref generic_name) = config.generic_name {
+        out.push_str(&format!(
+            "GenericName={}\n",
+            escape_desktop_value(generic_name)
+        ));
+    }
     if let Some(ref comment) = config.comment {
         out.push_str(&format!("Comment={}\n", escape_desktop_value(comment)));
     }
+    if !config.keywords.is_empty() {
+        out.push_str(&format!("Keywords={}\n", desktop_list(&config.keywords)));
+    }
+    for (locale, t) in &config.translations {
+        let keys = [
+            ("Name", &t.name),
+            ("GenericName", &t.generic_name),
+            ("Comment", &t.comment),
+        ];
+        for (key, value) in keys {
+            if let Some(value) = value {
+                out.push_str(&format!(
+                    "{}[{}]={}\n",
+                    key,
+                    locale,
+                    escape_desktop_value(value)
+                ));
+            }
+        }
+        if !t.keywords.is_empty() {
+            out.push_str(&format!(
+                "Keywords[{}]={}\n",
+                locale,
+                desktop_list(&t.keywords)
+            ));
+        }
+    }
     if let Some(ref icon) = config.icon {
         let icon_value = resolve_icon_for_desktop(icon, Some(bundle_root));
         out.push_str(&format!("Icon={}\n", escape_desktop_value(&icon_value)));
@@ -150,7 +199,7 @@ pub fn generate_desktop(
 /// Resolve icon value for the Icon= line. If bundle_root is set and icon is a relative path
 /// pointing to an existing file in the bundle, return its absolute path; otherwise return icon as-is
 /// (theme name or absolute path from config).
-fn resolve_icon_for_desktop(icon: &str, bundle_root: Option<&Path>) -> String {
+pub fn resolve_icon_for_desktop(icon: &str, bundle_root: Option<&Path>) -> String {
     if icon.is_empty() {
         return icon.to_string();
     }
@@ -158,8 +207,8 @@ fn resolve_icon_for_desktop(icon: &str, bundle_root: Option<&Path>) -> String {
         // Relative path: resolve against bundle root so the .desktop file gets an absolute path
         if !icon.starts_with('/') && !icon.starts_with("~/") {
             let resolved = root.join(icon);
-            if resolved.is_file() {
-                if let Ok(abs) = resolved.canonicalize() {
+            if crate::fscache::is_file(&resolved) {
+                if let Ok(abs) = crate::fscache::canonicalize(&resolved) {
                     if let Some(s) = abs.to_str() {
                         return s.to_string();
                     }
@@ -171,135 +220,265 @@ fn resolve_icon_for_desktop(icon: &str, bundle_root: Option<&Path>) -> String {
 }
 
 /// Remove the .directory file from the bundle (inverse of write_bundle_directory_file).
-pub fn remove_bundle_directory_file(bundle_root: &Path) -> Result<()> {
-    let path = bundle_root.join(".directory");
-    if path.is_file() {
-        std::fs::remove_file(&path)?;
-    }
+/// `as_user`: perform the removal as that user (root acting on a user bundle).
+pub fn remove_bundle_directory_file(bundle_root: &Path, as_user: Option<&str>) -> Result<()> {
+    let req = asuser::Request::RemoveFile {
+        dir: bundle_root.to_path_buf(),
+        name: ".directory".to_string(),
+    };
+    asuser::perform(as_user, &req)?;
     Ok(())
 }
 
-/// Write a .directory file inside the bundle so file managers (e.g. Dolphin) show the app icon on the .lnx folder.
-pub fn write_bundle_directory_file(bundle_root: &Path, config: &Config) -> Result<()> {
-    let Some(ref icon) = config.icon else {
-        return Ok(());
-    };
+/// Contents of the bundle's .directory file, or None when the app has no icon.
+pub fn directory_file_content(bundle_root: &Path, config: &Config) -> Option<String> {
+    let icon = config.icon.as_ref()?;
     let icon_value = resolve_icon_for_desktop(icon, Some(bundle_root));
-    let name = escape_desktop_value(&config.name);
-    let content = format!(
+    Some(format!(
         "[Desktop Entry]\n\
          Type=Directory\n\
          Name={}\n\
          Icon={}\n",
-        name,
+        escape_desktop_value(&config.name),
         escape_desktop_value(&icon_value)
-    );
-    std::fs::write(bundle_root.join(".directory"), content)?;
-    Ok(())
+    ))
 }
 
-/// Set GNOME/Nautilus folder icon via gio (metadata::custom-icon). Uses the user's D-Bus session
-/// when run_as_user is Some so gvfsd-metadata receives the write (required when sync runs as root).
-#[cfg(unix)]
-pub fn set_gnome_folder_icon(
+/// Write a .directory file inside the bundle so file managers (e.g. Dolphin) show the app icon on the .lnx folder.
+/// `as_user`: write as that user (root syncing a user bundle), so the file is theirs from the start.
+/// An identical file is left alone (no mtime bump, no file manager rescan). Returns true when written.
+pub fn write_bundle_directory_file(
     bundle_root: &Path,
     config: &Config,
-    run_as_user: Option<&str>,
-) -> Result<()> {
-    let Some(ref icon) = config.icon else {
-        return Ok(());
+    as_user: Option<&str>,
+) -> Result<bool> {
+    let Some(content) = directory_file_content(bundle_root, config) else {
+        return Ok(false);
     };
-    let icon_value = resolve_icon_for_desktop(icon, Some(bundle_root));
-    if !icon_value.starts_with('/') {
-        return Ok(());
+    let existing = asuser::perform(
+        as_user,
+        &asuser::Request::ReadFile {
+            dir: bundle_root.to_path_buf(),
+            name: ".directory".to_string(),
+        },
+    )?;
+    if existing.as_deref() == Some(content.as_str()) {
+        return Ok(false);
     }
-    let file_url = format!("file://{}", icon_value.replace(' ', "%20"));
-    let bundle_str = bundle_root
-        .to_str()
-        .ok_or_else(|| anyhow::anyhow!("bundle path not UTF-8"))?;
-    let gio_path = "/usr/bin/gio";
-    if !std::path::Path::new(gio_path).exists() {
-        return Ok(());
+    let req = asuser::Request::WriteFile {
+        dir: bundle_root.to_path_buf(),
+        name: ".directory".to_string(),
+        contents: content,
+        create_dir: false,
+    };
+    asuser::perform(as_user, &req)?;
+    Ok(true)
+}
+
+/// `file://` URL gio gets for the bundle's folder icon; None unless the icon is a file in the bundle.
+pub fn folder_icon_url(bundle_root: &Path, config: &Config) -> Option<String> {
+    let icon_value = resolve_icon_for_desktop(config.icon.as_ref()?, Some(bundle_root));
+    icon_value
+        .starts_with('/')
+        .then(|| format!("file://{}", icon_value.replace(' ', "%20")))
+}
+
+/// A GNOME folder icon change: set `url` (a `file://` URL) on `bundle`, or clear it (None).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FolderIcon {
+    pub bundle: PathBuf,
+    pub url: Option<String>,
+}
+
+/// Run by [`apply_gnome_folder_icons`] with gio's path, then a bundle and a URL per change (an
+/// empty URL clears the icon). Prints the number of each change gio made.
+#[cfg(unix)]
+const GIO_BATCH: &str = r#"gio=$1; shift; i=0
+while [ $# -ge 2 ]; do
+  if [ -n "$2" ]; then "$gio" set -t string "$1" metadata::custom-icon "$2"; else "$gio" set -t unset "$1" metadata::custom-icon; fi && echo "$i"
+  i=$((i + 1)); shift 2
+done"#;
+
+/// Set or clear GNOME/Nautilus folder icons (metadata::custom-icon) with gio, all in one `sh`:
+/// spawning runuser (a PAM session) per folder made a first sync of many bundles slow. Uses the
+/// user's D-Bus session when run_as_user is Some so gvfsd-metadata receives the writes (required
+/// when sync runs as root). Returns whether gio made each change (none when gio is unavailable).
+///
+/// Built with the `gio` feature, a user's own folders are done through GIO in this process
+/// instead: no gio binary is needed, and each failure is logged with GIO's reason. The root
+/// service's user tiers (run_as_user Some) stay on the gio subprocess: the metadata belongs to
+/// each user's session, which a root process cannot reach through its own GIO.
+#[cfg(unix)]
+pub fn apply_gnome_folder_icons(
+    paths: &Paths,
+    icons: &[FolderIcon],
+    run_as_user: Option<&str>,
+) -> Result<Vec<bool>> {
+    #[cfg(feature = "gio")]
+    if run_as_user.is_none() {
+        return Ok(icons.iter().map(apply_in_process).collect());
+    }
+    run_gio_batch(paths, icons, run_as_user)
+}
+
+/// [`GIO_BATCH`] with the gio binary, as `run_as_user` when given.
+#[cfg(unix)]
+fn run_gio_batch(
+    paths: &Paths,
+    icons: &[FolderIcon],
+    run_as_user: Option<&str>,
+) -> Result<Vec<bool>> {
+    let gio_path = &paths.gio;
+    if icons.is_empty() || !gio_path.exists() {
+        return Ok(vec![false; icons.len()]);
     }
     let mut cmd = if let Some(username) = run_as_user {
-        let uid = User::from_name(username).ok().flatten().map(|u| u.uid.as_raw());
-        let (dbus_addr, xdg_runtime) = uid.map(|uid| {
-            let bus = format!("/run/user/{}/bus", uid);
-            let runtime = format!("/run/user/{}", uid);
-            (
-                std::path::Path::new(&bus).exists().then(|| bus),
-                runtime,
-            )
-        }).unwrap_or((None, String::new()));
+        let uid = User::from_name(username)
+            .ok()
+            .flatten()
+            .map(|u| u.uid.as_raw());
         let mut c = std::process::Command::new("runuser");
         c.args(["-u", username, "--", "env"]);
-        if let Some(ref bus) = dbus_addr {
-            c.arg(format!("DBUS_SESSION_BUS_ADDRESS=unix:path={}", bus));
-            c.arg(format!("XDG_RUNTIME_DIR={}", xdg_runtime));
+        if let Some(uid) = uid.filter(|uid| Path::new(&format!("/run/user/{}/bus", uid)).exists()) {
+            c.arg(format!(
+                "DBUS_SESSION_BUS_ADDRESS=unix:path=/run/user/{}/bus",
+                uid
+            ));
+            c.arg(format!("XDG_RUNTIME_DIR=/run/user/{}", uid));
         }
-        c.arg(gio_path)
-            .args(["set", "-t", "string", bundle_str, "metadata::custom-icon"])
-            .arg(&file_url);
+        c.arg("sh");
         c
     } else {
-        let mut c = std::process::Command::new(gio_path);
-        c.args(["set", "-t", "string", bundle_str, "metadata::custom-icon"])
-            .arg(&file_url);
-        c
+        std::process::Command::new("sh")
     };
-    match cmd.status() {
-        Ok(s) if s.success() => Ok(()),
-        Ok(_) => Ok(()),
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
-        Err(e) => Err(e.into()),
+    cmd.args(["-c", GIO_BATCH, "sh"]).arg(gio_path);
+    for icon in icons {
+        cmd.arg(&icon.bundle)
+            .arg(icon.url.as_deref().unwrap_or_default());
+    }
+    let out = match cmd.stderr(std::process::Stdio::inherit()).output() {
+        Ok(out) => out,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![false; icons.len()]),
+        Err(e) => return Err(e.into()),
+    };
+    let mut done = vec![false; icons.len()];
+    for i in String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .filter_map(|l| l.trim().parse::<usize>().ok())
+    {
+        if let Some(d) = done.get_mut(i) {
+            *d = true;
+        }
+    }
+    Ok(done)
+}
+
+/// One folder icon change through GIO in this process. The metadata goes to this process's
+/// session (gvfsd-metadata), so only a user's own folders can be done this way.
+#[cfg(all(unix, feature = "gio"))]
+fn apply_in_process(icon: &FolderIcon) -> bool {
+    match set_folder_icon_in_process(icon) {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::warn!(bundle = %icon.bundle.display(), "GIO could not change the folder icon: {}", e);
+            false
+        }
+    }
+}
+
+#[cfg(all(unix, feature = "gio"))]
+const CUSTOM_ICON: &str = "metadata::custom-icon";
+
+#[cfg(all(unix, feature = "gio"))]
+fn set_folder_icon_in_process(icon: &FolderIcon) -> std::result::Result<(), gio::glib::Error> {
+    use gio::prelude::*;
+    let file = gio::File::for_path(&icon.bundle);
+    let flags = gio::FileQueryInfoFlags::NONE;
+    if let Some(url) = &icon.url {
+        return file.set_attribute_string(CUSTOM_ICON, url, flags, gio::Cancellable::NONE);
+    }
+    // gio-rs has no safe way to unset an attribute. `gio set -t unset` calls
+    // g_file_set_attribute with type INVALID and no value, which GLib documents as removing the
+    // attribute and which the metadata backends (gvfsd-metadata, GLib's local one) implement as
+    // "unset key"; the value pointer is never read for that type.
+    use gio::glib::translate::{from_glib_full, ToGlibPtr};
+    let mut error = std::ptr::null_mut();
+    let attribute: gio::glib::translate::Stash<*const std::ffi::c_char, str> =
+        CUSTOM_ICON.to_glib_none();
+    // SAFETY: `file` and `attribute` outlive the call (a live GFile, a NUL-terminated name), the
+    // value may be NULL for type INVALID, and `error` is a NULL GError* that GLib fills only on
+    // failure, returning FALSE; we then own it.
+    let ok = unsafe {
+        gio::ffi::g_file_set_attribute(
+            file.to_glib_none().0,
+            attribute.0,
+            gio::ffi::G_FILE_ATTRIBUTE_TYPE_INVALID,
+            std::ptr::null_mut(),
+            flags.bits(),
+            std::ptr::null_mut(),
+            &mut error,
+        )
+    };
+    match ok {
+        0 => Err(unsafe { from_glib_full(error) }),
+        _ => Ok(()),
     }
 }
 
 #[cfg(not(unix))]
-pub fn set_gnome_folder_icon(
-    _bundle_root: &Path,
-    _config: &Config,
+pub fn apply_gnome_folder_icons(
+    _paths: &Paths,
+    icons: &[FolderIcon],
     _run_as_user: Option<&str>,
-) -> Result<()> {
-    Ok(())
+) -> Result<Vec<bool>> {
+    Ok(vec![false; icons.len()])
 }
 
 /// Clear GNOME folder icon (metadata::custom-icon). Uses user's D-Bus session when run_as_user is Some.
+pub fn clear_gnome_folder_icon(
+    paths: &Paths,
+    bundle_root: &Path,
+    run_as_user: Option<&str>,
+) -> Result<()> {
+    let icon = FolderIcon {
+        bundle: bundle_root.to_path_buf(),
+        url: None,
+    };
+    apply_gnome_folder_icons(paths, &[icon], run_as_user).map(|_| ())
+}
+
+/// Show a desktop notification to a user (notify-send in their D-Bus session). Best effort:
+/// missing notify-send or no session bus is not an error.
 #[cfg(unix)]
-pub fn clear_gnome_folder_icon(bundle_root: &Path, run_as_user: Option<&str>) -> Result<()> {
-    let bundle_str = bundle_root
-        .to_str()
-        .ok_or_else(|| anyhow::anyhow!("bundle path not UTF-8"))?;
-    let gio_path = "/usr/bin/gio";
-    if !std::path::Path::new(gio_path).exists() {
+pub fn notify_user(paths: &Paths, username: &str, summary: &str, body: &str) -> Result<()> {
+    let notify_send = &paths.notify_send;
+    if !notify_send.exists() {
         return Ok(());
     }
-    let mut cmd = if let Some(username) = run_as_user {
-        let uid = User::from_name(username).ok().flatten().map(|u| u.uid.as_raw());
-        let (dbus_addr, xdg_runtime) = uid.map(|uid| {
-            let bus = format!("/run/user/{}/bus", uid);
-            let runtime = format!("/run/user/{}", uid);
-            (
-                std::path::Path::new(&bus).exists().then(|| bus),
-                runtime,
-            )
-        }).unwrap_or((None, String::new()));
-        let mut c = std::process::Command::new("runuser");
-        c.args(["-u", username, "--", "env"]);
-        if let Some(ref bus) = dbus_addr {
-            c.arg(format!("DBUS_SESSION_BUS_ADDRESS=unix:path={}", bus));
-            c.arg(format!("XDG_RUNTIME_DIR={}", xdg_runtime));
-        }
-        c.arg(gio_path)
-            .args(["set", "-t", "unset", bundle_str, "metadata::custom-icon"]);
-        c
-    } else {
-        let mut c = std::process::Command::new(gio_path);
-        c.args(["set", "-t", "unset", bundle_str, "metadata::custom-icon"]);
-        c
+    let Some(uid) = User::from_name(username)
+        .ok()
+        .flatten()
+        .map(|u| u.uid.as_raw())
+    else {
+        return Ok(());
     };
+    let bus = format!("/run/user/{}/bus", uid);
+    if !std::path::Path::new(&bus).exists() {
+        return Ok(());
+    }
+    let mut cmd = std::process::Command::new("runuser");
+    cmd.args(["-u", username, "--", "env"])
+        .arg(format!("DBUS_SESSION_BUS_ADDRESS=unix:path={}", bus))
+        .arg(format!("XDG_RUNTIME_DIR=/run/user/{}", uid))
+        .arg(notify_send)
+        .args([
+            "--app-name=dotlnx",
+            "--urgency=critical",
+            "--",
+            summary,
+            body,
+        ]);
     match cmd.status() {
-        Ok(s) if s.success() => Ok(()),
         Ok(_) => Ok(()),
         Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
         Err(e) => Err(e.into()),
@@ -307,36 +486,80 @@ pub fn clear_gnome_folder_icon(bundle_root: &Path, run_as_user: Option<&str>) ->
 }
 
 #[cfg(not(unix))]
-pub fn clear_gnome_folder_icon(_bundle_root: &Path, _run_as_user: Option<&str>) -> Result<()> {
+pub fn notify_user(_paths: &Paths, _username: &str, _summary: &str, _body: &str) -> Result<()> {
     Ok(())
 }
 
-/// Write generated .desktop to the given applications directory.
-/// Returns the path of the created file so the caller can chown when needed.
+/// Write generated .desktop to the given applications directory (created if missing).
+/// Returns the path of the created file.
 /// Exec is the absolute path to the bundle executable (or aa-exec ... when confined).
 /// Pass `profile_name` when AppArmor is in use and [security] confine is true.
+/// `as_user`: write as that user (root installing into a user's ~/.local/share/applications).
 pub fn install_desktop(
+    paths: &Paths,
     apps_dir: &Path,
     config: &Config,
     bundle_root: &Path,
     profile_name: Option<&str>,
+    as_user: Option<&str>,
 ) -> Result<std::path::PathBuf> {
-    let name = format!("dotlnx-{}.desktop", config.name);
-    let path = apps_dir.join(&name);
-    let content = generate_desktop(config, bundle_root, profile_name);
-    std::fs::write(&path, content)?;
+    let contents = generate_desktop(config, bundle_root, profile_name);
+    write_desktop(paths, apps_dir, &config.app_id(), contents, as_user)
+}
+
+/// Write an app's already generated .desktop file into `apps_dir` (created if missing).
+/// `as_user`: write as that user.
+pub fn write_desktop(
+    paths: &Paths,
+    apps_dir: &Path,
+    name: &str,
+    contents: String,
+    as_user: Option<&str>,
+) -> Result<std::path::PathBuf> {
+    crate::validate::validate_app_name(name)?;
+    let file_name = paths.desktop_file_name(apps_dir, name);
+    let path = apps_dir.join(&file_name);
+    let req = asuser::Request::WriteFile {
+        dir: apps_dir.to_path_buf(),
+        name: file_name,
+        contents,
+        create_dir: true,
+    };
+    asuser::perform(as_user, &req)?;
     Ok(path)
 }
 
-/// Change ownership of a path to the given username (uid:gid). Used when root creates
-/// .desktop files in a user's applications dir so the user owns the file.
-#[cfg(unix)]
-pub fn chown_to_user(path: &Path, username: &str) -> Result<()> {
-    let user = User::from_name(username)
-        .map_err(|e| anyhow::anyhow!("lookup user {:?}: {}", username, e))?
-        .ok_or_else(|| anyhow::anyhow!("no such user: {:?}", username))?;
-    chown(path, Some(user.uid), Some(user.gid))
-        .map_err(|e| anyhow::anyhow!("chown {}: {}", path.display(), e))?;
+/// Current contents of an app's .desktop file in `apps_dir` (None when not installed there).
+/// `as_user`: read as that user.
+pub fn read_desktop(
+    paths: &Paths,
+    apps_dir: &Path,
+    name: &str,
+    as_user: Option<&str>,
+) -> Result<Option<String>> {
+    crate::validate::validate_app_name(name)?;
+    let req = asuser::Request::ReadFile {
+        dir: apps_dir.to_path_buf(),
+        name: paths.desktop_file_name(apps_dir, name),
+    };
+    asuser::perform(as_user, &req)
+}
+
+/// Remove .desktop file for an app by name from the given applications directory.
+/// The name is validated and removal goes through the open directory, so it cannot reach
+/// outside apps_dir. `as_user`: remove as that user.
+pub fn uninstall_desktop(
+    paths: &Paths,
+    apps_dir: &Path,
+    name: &str,
+    as_user: Option<&str>,
+) -> Result<()> {
+    crate::validate::validate_app_name(name)?;
+    let req = asuser::Request::RemoveFile {
+        dir: apps_dir.to_path_buf(),
+        name: paths.desktop_file_name(apps_dir, name),
+    };
+    asuser::perform(as_user, &req)?;
     Ok(())
 }
 
@@ -351,12 +574,33 @@ mod tests {
             executable: "bin/myapp".into(),
             args: vec![],
             env: vec![],
+            site_env: vec![],
             working_dir: None,
             icon: None,
             comment: None,
             categories: None,
+            generic_name: None,
+            keywords: vec![],
+            translations: Default::default(),
+            allowed_users: vec![],
+            allowed_groups: vec![],
             security: None,
+            kind: Default::default(),
+            service: None,
+            schedule: None,
+            thumbnailers: Vec::new(),
+            search_provider: None,
+            update: None,
+            accessibility: None,
+            keep_versions: None,
+            data_dirs: vec![],
+            vendor: None,
+            exclude: Vec::new(),
+            arch: vec![],
             terminal: false,
+            umask: None,
+            nice: None,
+            ionice: None,
         }
     }
 
@@ -371,7 +615,11 @@ mod tests {
         assert!(out.contains("[Desktop Entry]"));
         assert!(out.contains("Name=myapp"));
         let exec_line = out.lines().find(|l| l.starts_with("Exec=")).unwrap();
-        assert!(exec_line.contains("bin/myapp"), "Exec should contain bundle path: {}", exec_line);
+        assert!(
+            exec_line.contains("bin/myapp"),
+            "Exec should contain bundle path: {}",
+            exec_line
+        );
         assert!(exec_line.ends_with("%u"));
         assert!(out.contains("Type=Application"));
     }
@@ -389,6 +637,24 @@ mod tests {
         assert!(exec_line.contains("bin/myapp"));
     }
 
+    #[test]
+    fn generate_desktop_with_clear_env_goes_through_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("myapp.lnx");
+        std::fs::create_dir_all(bundle.join("bin")).unwrap();
+        std::fs::write(bundle.join("bin/myapp"), b"").unwrap();
+        let mut cfg = minimal_config();
+        cfg.args = vec!["--flag".into()];
+        cfg.security = Some(crate::config::Security {
+            clear_env: true,
+            ..Default::default()
+        });
+        let out = generate_desktop(&cfg, &bundle, Some("dotlnx-user-myapp"));
+        let exec_line = out.lines().find(|l| l.starts_with("Exec=")).unwrap();
+        assert!(exec_line.ends_with(" run myapp %u"), "{}", exec_line);
+        assert!(!exec_line.contains("aa-exec") && !exec_line.contains("--flag"));
+    }
+
     #[test]
     fn generate_desktop_escapes_exec_args() {
         let dir = tempfile::tempdir().unwrap();
@@ -432,6 +698,31 @@ mod tests {
         assert!(out.contains("Categories=Utility"));
     }
 
+    #[test]
+    fn generate_desktop_keywords_and_translations() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("myapp.lnx");
+        let mut cfg = minimal_config();
+        cfg.generic_name = Some("Web Browser".into());
+        cfg.keywords = vec!["internet".into(), "www".into()];
+        cfg.translations.insert(
+            "de".into(),
+            crate::config::Translation {
+                name: Some("Mein Programm".into()),
+                comment: Some("Im Netz surfen".into()),
+                keywords: vec!["Netz".into()],
+                ..Default::default()
+            },
+        );
+        let out = generate_desktop(&cfg, &bundle, None);
+        assert!(out.contains("\nGenericName=Web Browser\n"));
+        assert!(out.contains("\nKeywords=internet;www;\n"));
+        assert!(out.contains("\nName[de]=Mein Programm\n"));
+        assert!(out.contains("\nComment[de]=Im Netz surfen\n"));
+        assert!(out.contains("\nKeywords[de]=Netz;\n"));
+        assert!(!out.contains("GenericName[de]"));
+    }
+
     #[test]
     fn generate_desktop_resolves_bundle_relative_icon() {
         let dir = tempfile::tempdir().unwrap();
@@ -461,37 +752,362 @@ mod tests {
         std::fs::create_dir_all(bundle.join("bin")).unwrap();
         std::fs::write(bundle.join("bin/myapp"), b"").unwrap();
         let cfg = minimal_config();
-        let desktop_path = install_desktop(apps_dir, &cfg, &bundle, None).unwrap();
+        let paths = Paths::under(dir.path());
+        let desktop_path = install_desktop(&paths, apps_dir, &cfg, &bundle, None, None).unwrap();
         assert!(desktop_path.exists());
         let content = std::fs::read_to_string(&desktop_path).unwrap();
         assert!(content.contains("Name=myapp"));
+        assert_eq!(
+            read_desktop(&paths, apps_dir, "myapp", None).unwrap(),
+            Some(content)
+        );
 
-        uninstall_desktop(apps_dir, "myapp").unwrap();
+        uninstall_desktop(&paths, apps_dir, "myapp", None).unwrap();
         assert!(!desktop_path.exists());
+        assert_eq!(read_desktop(&paths, apps_dir, "myapp", None).unwrap(), None);
+    }
+
+    #[test]
+    fn install_desktop_refuses_symlinked_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let victim = dir.path().join("victim");
+        std::fs::write(&victim, "keep").unwrap();
+        let apps_dir = dir.path().join("applications");
+        std::fs::create_dir_all(&apps_dir).unwrap();
+        std::os::unix::fs::symlink(&victim, apps_dir.join("dotlnx-myapp.desktop")).unwrap();
+        let cfg = minimal_config();
+        assert!(install_desktop(
+            &Paths::under(dir.path()),
+            &apps_dir,
+            &cfg,
+            dir.path(),
+            None,
+            None
+        )
+        .is_err());
+        assert_eq!(std::fs::read_to_string(&victim).unwrap(), "keep");
     }
 
     #[test]
     fn uninstall_desktop_nonexistent_ok() {
         let dir = tempfile::tempdir().unwrap();
-        uninstall_desktop(dir.path(), "nonexistent").unwrap();
+        uninstall_desktop(&Paths::under(dir.path()), dir.path(), "nonexistent", None).unwrap();
     }
-}
 
-/// Remove .desktop file for an app by name from the given applications directory.
-/// Resolved path must stay under apps_dir to prevent path traversal.
-pub fn uninstall_desktop(apps_dir: &Path, name: &str) -> Result<()> {
-    let path = apps_dir.join(format!("dotlnx-{}.desktop", name));
-    if path.exists() {
-        if !apps_dir.exists() {
-            anyhow::bail!("applications dir does not exist");
+    #[test]
+    fn directory_file_written_only_when_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("myapp.lnx");
+        std::fs::create_dir_all(&bundle).unwrap();
+        std::fs::write(bundle.join("icon.png"), b"png").unwrap();
+        let mut cfg = minimal_config();
+        assert!(
+            !write_bundle_directory_file(&bundle, &cfg, None).unwrap(),
+            "no icon, no file"
+        );
+        cfg.icon = Some("icon.png".into());
+        assert!(write_bundle_directory_file(&bundle, &cfg, None).unwrap());
+        assert!(!write_bundle_directory_file(&bundle, &cfg, None).unwrap());
+        cfg.name = "renamed".into();
+        assert!(write_bundle_directory_file(&bundle, &cfg, None).unwrap());
+        assert_eq!(
+            folder_icon_url(&bundle, &cfg),
+            Some(format!("file://{}", bundle.join("icon.png").display()).replace(' ', "%20"))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn folder_icons_are_set_and_cleared_in_one_batch() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::under(dir.path());
+        let log = dir.path().join("gio.log");
+        std::fs::create_dir_all(paths.gio.parent().unwrap()).unwrap();
+        std::fs::write(
+            &paths.gio,
+            format!(
+                "#!/bin/sh\necho \"$*\" >> '{}'\ncase \"$4\" in *Bad*) exit 1;; esac\n",
+                log.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&paths.gio, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let icon = |bundle: &str, url: Option<&str>| FolderIcon {
+            bundle: dir.path().join(bundle),
+            url: url.map(str::to_string),
+        };
+        let icons = [
+            icon("My App.lnx", Some("file:///a%20b.png")),
+            icon("Bad.lnx", Some("file:///x.png")),
+            icon("Old.lnx", None),
+        ];
+        assert_eq!(
+            run_gio_batch(&paths, &icons, None).unwrap(),
+            [true, false, true]
+        );
+        let calls = std::fs::read_to_string(&log).unwrap();
+        let d = dir.path().display();
+        assert_eq!(
+            calls,
+            format!(
+                "set -t string {d}/My App.lnx metadata::custom-icon file:///a%20b.png\n\
+                 set -t string {d}/Bad.lnx metadata::custom-icon file:///x.png\n\
+                 set -t unset {d}/Old.lnx metadata::custom-icon\n"
+            )
+        );
+
+        // Without gio nothing is made and nothing fails.
+        std::fs::remove_file(&paths.gio).unwrap();
+        assert_eq!(
+            run_gio_batch(&paths, &icons, None).unwrap(),
+            [false, false, false]
+        );
+    }
+
+    #[cfg(feature = "gio")]
+    #[test]
+    fn in_process_gio_reports_folders_it_cannot_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("Gone.lnx");
+        let icon = |url: Option<&str>| FolderIcon {
+            bundle: missing.clone(),
+            url: url.map(str::to_string),
+        };
+        assert!(!apply_in_process(&icon(Some("file:///x.png"))));
+        assert!(!apply_in_process(&icon(None)));
+    }
+
+    /// Sets and unsets the icon for real where the session has a metadata store (gvfs; run
+    /// under `dbus-run-session` in CI). Elsewhere GIO refuses both, and the unset path must
+    /// still come back with GIO's error rather than crash.
+    #[cfg(feature = "gio")]
+    #[test]
+    fn in_process_gio_sets_and_unsets_the_folder_icon() {
+        use gio::prelude::*;
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("App.lnx");
+        std::fs::create_dir(&bundle).unwrap();
+        let icon = |url: Option<&str>| FolderIcon {
+            bundle: bundle.clone(),
+            url: url.map(str::to_string),
+        };
+        let current = || {
+            gio::File::for_path(&bundle)
+                .query_info(
+                    CUSTOM_ICON,
+                    gio::FileQueryInfoFlags::NONE,
+                    gio::Cancellable::NONE,
+                )
+                .unwrap()
+                .attribute_string(CUSTOM_ICON)
+                .map(|s| s.to_string())
+        };
+        let url = "file:///usr/share/icons/app.png";
+        match set_folder_icon_in_process(&icon(Some(url))) {
+            Ok(()) => {
+                assert_eq!(current().as_deref(), Some(url));
+                set_folder_icon_in_process(&icon(None)).unwrap();
+                assert_eq!(current(), None);
+            }
+            Err(e) if e.matches(gio::IOErrorEnum::NotSupported) => {
+                eprintln!(
+                    "skipping the round trip: no metadata store in this session ({})",
+                    e
+                );
+                let unset = set_folder_icon_in_process(&icon(None)).unwrap_err();
+                assert!(unset.matches(gio::IOErrorEnum::NotSupported), "{}", unset);
+            }
+            Err(e) => panic!("{}", e),
         }
-        let apps_canon = std::fs::canonicalize(apps_dir)
-            .map_err(|e| anyhow::anyhow!("applications dir: {}", e))?;
-        let path_canon = std::fs::canonicalize(&path).map_err(|e| anyhow::anyhow!("{}", e))?;
-        if !path_canon.starts_with(&apps_canon) || !path_canon.is_file() {
-            anyhow::bail!("refusing to remove path outside applications dir");
+    }
+
+    // Property tests for the escaping above. Failing seeds proptest finds are saved under
+    // proptest-regressions/ and replayed first; inputs that once got through go in the corpus.
+
+    /// Inputs that broke (or nearly broke) escaping at some point.
+    const ESCAPE_CORPUS: &[&str] = &[
+        "x\nExec=/bin/evil",
+        "x\r\n[Desktop Entry]",
+        "ends with backslash\\",
+        "\"",
+        "%u",
+        "100%",
+        "$HOME",
+        "`id`",
+        "a;b",
+        "",
+        "\u{b}\u{85}",
+    ];
+
+    /// Desktop Entry string unescaping (\s \n \t \r \\).
+    fn unescape_value(v: &str) -> String {
+        let mut out = String::new();
+        let mut chars = v.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('s') => out.push(' '),
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('r') => out.push('\r'),
+                Some('\\') => out.push('\\'),
+                Some(c) => {
+                    out.push('\\');
+                    out.push(c)
+                }
+                None => out.push('\\'),
+            }
+        }
+        out
+    }
+
+    /// Split an Exec value into arguments the way a spec-following launcher does; None when the
+    /// value is malformed (unbalanced quotes, unquoted reserved characters, stray field codes).
+    fn parse_exec(value: &str) -> Option<Vec<String>> {
+        let v = unescape_value(value);
+        let mut args = Vec::new();
+        let mut chars = v.chars().peekable();
+        loop {
+            match chars.peek() {
+                None => break,
+                Some(' ') => {
+                    chars.next();
+                    continue;
+                }
+                _ => {}
+            }
+            let mut arg = String::new();
+            if chars.peek() == Some(&'"') {
+                chars.next();
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => match chars.next()? {
+                            c @ ('"' | '`' | '$' | '\\') => arg.push(c),
+                            _ => return None,
+                        },
+                        c => arg.push(c),
+                    }
+                }
+                if chars.peek().is_some_and(|c| *c != ' ') {
+                    return None;
+                }
+            } else {
+                while let Some(&c) = chars.peek() {
+                    if c == ' ' {
+                        break;
+                    }
+                    if EXEC_RESERVED.contains(&c) {
+                        return None;
+                    }
+                    arg.push(c);
+                    chars.next();
+                }
+            }
+            // Field codes: %% is a literal %, %u is the one we add, anything else is injected.
+            let mut literal = String::new();
+            let mut codes = arg.chars();
+            while let Some(c) = codes.next() {
+                if c != '%' {
+                    literal.push(c);
+                    continue;
+                }
+                match codes.next() {
+                    Some('%') => literal.push('%'),
+                    Some('u') if arg == "%u" => literal.push_str("%u"),
+                    _ => return None,
+                }
+            }
+            args.push(literal);
+        }
+        Some(args)
+    }
+
+    fn without_controls(s: &str) -> String {
+        s.chars()
+            .map(|c| if c.is_control() { ' ' } else { c })
+            .collect()
+    }
+
+    fn check_desktop_value(s: &str) {
+        let out = escape_desktop_value(s);
+        assert!(!out.chars().any(|c| c.is_control()), "{:?} -> {:?}", s, out);
+        let keep = |c: char| !c.is_control() || matches!(c, '\n' | '\t' | '\r');
+        let expected: String = s.chars().map(|c| if keep(c) { c } else { ' ' }).collect();
+        assert_eq!(unescape_value(&out), expected);
+    }
+
+    fn check_exec_arg(s: &str) {
+        let out = escape_for_exec_arg(s);
+        assert!(!out.chars().any(|c| c.is_control()), "{:?} -> {:?}", s, out);
+        assert_eq!(
+            parse_exec(&out),
+            Some(vec![without_controls(s)]),
+            "{:?} -> {:?}",
+            s,
+            out
+        );
+    }
+
+    fn check_generated_entry(name: &str, comment: &str, args: &[String]) {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("myapp.lnx");
+        let mut cfg = minimal_config();
+        cfg.name = name.into();
+        cfg.comment = Some(comment.into());
+        cfg.args = args.to_vec();
+        let out = generate_desktop(&cfg, &bundle, Some("dotlnx-myapp"));
+        let keys: Vec<&str> = out
+            .lines()
+            .skip(1)
+            .map(|l| l.split_once('=').map_or(l, |(k, _)| k))
+            .collect();
+        assert_eq!(keys, ["Type", "Name", "Exec", "Comment"], "{}", out);
+        let exec = out.lines().find_map(|l| l.strip_prefix("Exec=")).unwrap();
+        let mut expected = vec![
+            "aa-exec".to_string(),
+            "-p".into(),
+            "dotlnx-myapp".into(),
+            "--".into(),
+            bundle.join("bin/myapp").display().to_string(),
+        ];
+        expected.extend(args.iter().map(|a| without_controls(a)));
+        expected.push("%u".into());
+        assert_eq!(parse_exec(exec), Some(expected), "{}", exec);
+    }
+
+    #[test]
+    fn escaping_corpus_regressions() {
+        for s in ESCAPE_CORPUS {
+            check_desktop_value(s);
+            check_exec_arg(s);
+        }
+        let args: Vec<String> = ESCAPE_CORPUS.iter().map(|s| s.to_string()).collect();
+        check_generated_entry("x\nExec=evil", "y\r\nHidden=true", &args);
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn desktop_value_escaping_round_trips(s in "\\PC*|[\\x00-\\x1f\\\\ %\"]*") {
+            check_desktop_value(&s);
+        }
+
+        #[test]
+        fn exec_arguments_round_trip(s in "\\PC*|[\\x00-\\x1f\\\\ %\"'`$;#*]*") {
+            check_exec_arg(&s);
+        }
+
+        #[test]
+        fn generated_entries_cannot_gain_keys(
+            name in "\\PC*",
+            comment in "[\\x00-\\x1f a-z=\\[\\]]*",
+            args in proptest::collection::vec("\\PC*|[\\x00-\\x1f\\\\ %\"=]*", 0..4),
+        ) {
+            check_generated_entry(&name, &comment, &args);
         }
-        std::fs::remove_file(&path)?;
     }
-    Ok(())
 }
@@ -0,0 +1,345 @@
+//! `dotlnx diff`: what changes between two versions of a bundle, for review before rolling a new
+//! one out. Shows the config.toml keys that differ, the files added, removed or changed (from a
+//! manifest of each bundle: size, CRC-32, executable bit, link target), and how the generated
+//! AppArmor profile and menu entry would change.
+//!
+//! Both versions are rendered as if installed at the first bundle's path, so only real changes
+//! show up, with the site's admin override and [run] defaults applied as sync would.
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::apparmor;
+use crate::bundle;
+use crate::config::{self, Config};
+use crate::desktop;
+use crate::paths::Paths;
+use crate::policy;
+use crate::settings::{self, Settings};
+
+/// One file of a bundle, as compared.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entry {
+    File {
+        size: u64,
+        crc: u32,
+        executable: bool,
+    },
+    Link(PathBuf),
+}
+
+impl std::fmt::Display for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Entry::File {
+                size, executable, ..
+            } => {
+                write!(
+                    f,
+                    "{} bytes{}",
+                    size,
+                    if *executable { ", executable" } else { "" }
+                )
+            }
+            Entry::Link(target) => write!(f, "-> {}", target.display()),
+        }
+    }
+}
+
+/// Files of a bundle by path relative to its root, without its `exclude`d paths.
+pub fn manifest(bundle: &Path, exclude: &[String]) -> Result<BTreeMap<PathBuf, Entry>> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut out = BTreeMap::new();
+    let entries = walkdir::WalkDir::new(bundle)
+        .follow_links(false)
+        .into_iter()
+        .filter_entry(|e| {
+            let relative = e.path().strip_prefix(bundle).unwrap_or(e.path());
+            !bundle::is_excluded(exclude, relative)
+        });
+    for entry in entries {
+        let entry = entry?;
+        let relative = entry.path().strip_prefix(bundle)?.to_path_buf();
+        let file_type = entry.file_type();
+        if file_type.is_symlink() {
+            out.insert(relative, Entry::Link(std::fs::read_link(entry.path())?));
+        } else if file_type.is_file() {
+            let meta = entry.metadata()?;
+            let mut hasher = crc32fast::Hasher::new();
+            let mut file = std::fs::File::open(entry.path())
+                .with_context(|| format!("read {}", entry.path().display()))?;
+            let mut buf = vec![0u8; 64 * 1024];
+            loop {
+                let n = file.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&buf[..n]);
+            }
+            let executable = meta.permissions().mode() & 0o111 != 0;
+            out.insert(
+                relative,
+                Entry::File {
+                    size: meta.len(),
+                    crc: hasher.finalize(),
+                    executable,
+                },
+            );
+        }
+    }
+    Ok(out)
+}
+
+/// `+ path`, `- path` and `~ path` lines between two manifests.
+pub fn diff_manifests(
+    old: &BTreeMap<PathBuf, Entry>,
+    new: &BTreeMap<PathBuf, Entry>,
+) -> Vec<String> {
+    let mut out = Vec::new();
+    for (path, entry) in old {
+        match new.get(path) {
+            None => out.push(format!("- {} ({})", path.display(), entry)),
+            Some(other) if other != entry => {
+                out.push(format!("~ {} ({} => {})", path.display(), entry, other))
+            }
+            Some(_) => {}
+        }
+    }
+    for (path, entry) in new {
+        if !old.contains_key(path) {
+            out.push(format!("+ {} ({})", path.display(), entry));
+        }
+    }
+    out.sort_by(|a, b| a[2..].cmp(&b[2..]));
+    out
+}
+
+/// Leaf values of a TOML document by dotted key ("security.read_paths").
+fn flatten(value: &toml::Value, prefix: &str, out: &mut BTreeMap<String, String>) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten(value, &key, out);
+            }
+        }
+        // Arrays of tables ([[thumbnailers]]) by index; plain arrays as one value.
+        toml::Value::Array(items) if items.iter().any(|v| v.is_table()) => {
+            for (i, value) in items.iter().enumerate() {
+                flatten(value, &format!("{}[{}]", prefix, i), out);
+            }
+        }
+        other => {
+            out.insert(prefix.to_string(), other.to_string());
+        }
+    }
+}
+
+/// Changed config.toml keys: `+ key = value`, `- key = value`, `~ key: old => new`.
+pub fn diff_configs(old: &str, new: &str) -> Result<Vec<String>> {
+    let mut keys = [BTreeMap::new(), BTreeMap::new()];
+    for (text, keys) in [old, new].into_iter().zip(keys.iter_mut()) {
+        let value: toml::Value = toml::from_str(text).context("parse config.toml")?;
+        flatten(&value, "", keys);
+    }
+    let [old, new] = keys;
+    let mut out = Vec::new();
+    for (key, value) in &old {
+        match new.get(key) {
+            None => out.push(format!("- {} = {}", key, value)),
+            Some(other) if other != value => out.push(format!("~ {}: {} => {}", key, value, other)),
+            Some(_) => {}
+        }
+    }
+    for (key, value) in &new {
+        if !old.contains_key(key) {
+            out.push(format!("+ {} = {}", key, value));
+        }
+    }
+    out.sort_by(|a, b| a[2..].cmp(&b[2..]));
+    Ok(out)
+}
+
+/// Line diff (longest common subsequence): changed lines with `-`/`+`, unchanged ones left out.
+pub fn diff_lines(old: &str, new: &str) -> Vec<String> {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    // lcs[i][j]: length of the longest common subsequence of a[i..] and b[j..].
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j, mut out) = (0, 0, Vec::new());
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            i += 1;
+            j += 1;
+        } else if j < b.len() && (i == a.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            out.push(format!("+{}", b[j]));
+            j += 1;
+        } else {
+            out.push(format!("-{}", a[i]));
+            i += 1;
+        }
+    }
+    out
+}
+
+/// A bundle's config as sync would use it (admin override and [run] defaults applied).
+fn effective_config(settings: &Settings, bundle: &Path) -> Result<Config> {
+    let mut cfg = config::load(bundle)?;
+    if let Err(e) = crate::validate::validate_config(bundle, &cfg) {
+        println!("note: {} does not validate: {:#}", bundle.display(), e);
+    }
+    policy::apply_override(&mut cfg, &settings.apparmor.overrides_dir)?;
+    settings.run.apply(&mut cfg);
+    Ok(cfg)
+}
+
+/// Generated profile (None when unconfined) and menu entry (None for services) of `cfg`,
+/// installed at `at`.
+fn generated(
+    cfg: &Config,
+    at: &Path,
+    profile: &str,
+    opts: &apparmor::ProfileOptions,
+) -> (Option<String>, Option<String>) {
+    let confine = cfg.security.as_ref().map(|s| s.confine).unwrap_or(true);
+    let profile_text = confine.then(|| apparmor::generate_profile(at, cfg, profile, opts));
+    let vars = crate::templates::Vars {
+        name: &cfg.name,
+        bundle: at,
+        profile: confine.then_some(profile),
+    };
+    let desktop_text = (cfg.kind == config::Kind::App).then(|| {
+        opts.templates.apply_desktop(
+            desktop::generate_desktop(cfg, at, confine.then_some(profile)),
+            &vars,
+        )
+    });
+    (profile_text, desktop_text)
+}
+
+/// Print one section: its title and lines, or that nothing changed.
+fn section(title: &str, lines: &[String]) {
+    println!("{}:", title);
+    if lines.is_empty() {
+        println!("  (no changes)");
+    }
+    for line in lines {
+        println!("  {}", line);
+    }
+}
+
+/// Entry point for `dotlnx diff <old> <new>` and `dotlnx diff <new> --against-installed`.
+/// Returns whether anything differs.
+pub fn run(first: &Path, second: Option<&Path>, against_installed: bool) -> Result<bool> {
+    let settings = settings::load()?;
+    let paths = Paths::from_settings(&settings.paths);
+    let (old, new, username) = match (second, against_installed) {
+        (Some(second), false) => (first.to_path_buf(), second.to_path_buf(), None),
+        (None, true) => {
+            let name = config::load(first)?.app_id();
+            let Some((installed, _, is_user_tier)) = bundle::resolve_bundle_by_name(&paths, &name)?
+            else {
+                anyhow::bail!("{} is not installed; nothing to compare against", name);
+            };
+            let username = is_user_tier
+                .then(|| bundle::username_from_bundle_path(&installed))
+                .flatten();
+            (installed, first.to_path_buf(), username)
+        }
+        _ => anyhow::bail!("give two bundles, or one bundle and --against-installed"),
+    };
+    for dir in [&old, &new] {
+        if !dir.join("config.toml").is_file() {
+            anyhow::bail!("not a .lnx bundle (no config.toml): {}", dir.display());
+        }
+    }
+    println!("--- {}\n+++ {}", old.display(), new.display());
+    let text = |dir: &Path| config::read(dir);
+    let config_lines = diff_configs(&text(&old)?, &text(&new)?)?;
+    let old_cfg = effective_config(&settings, &old)?;
+    let new_cfg = effective_config(&settings, &new)?;
+    let file_lines = diff_manifests(
+        &manifest(&old, &old_cfg.exclude)?,
+        &manifest(&new, &new_cfg.exclude)?,
+    );
+
+    let opts = apparmor::ProfileOptions::from_settings(&settings, &paths)?;
+    let profile = paths.profile_name(username.as_deref(), &old_cfg.app_id());
+    let (old_profile, old_desktop) = generated(&old_cfg, &old, &profile, &opts);
+    let (new_profile, new_desktop) = generated(&new_cfg, &old, &profile, &opts);
+    let text_diff = |a: &Option<String>, b: &Option<String>| {
+        diff_lines(a.as_deref().unwrap_or(""), b.as_deref().unwrap_or(""))
+    };
+    let profile_lines = text_diff(&old_profile, &new_profile);
+    let desktop_lines = text_diff(&old_desktop, &new_desktop);
+
+    section("config.toml", &config_lines);
+    section("files", &file_lines);
+    section(&format!("AppArmor profile {}", profile), &profile_lines);
+    section("menu entry", &desktop_lines);
+    Ok([&config_lines, &file_lines, &profile_lines, &desktop_lines]
+        .iter()
+        .any(|l| !l.is_empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffs_configs_files_and_lines() {
+        let old = "name = \"app\"\nexecutable = \"bin/app\"\n[security]\nnetwork = false\nread_paths = [\"/srv\"]\n";
+        let new = "name = \"app\"\nexecutable = \"bin/app\"\ncomment = \"x\"\n[security]\nnetwork = true\n";
+        assert_eq!(
+            diff_configs(old, new).unwrap(),
+            [
+                "+ comment = \"x\"",
+                "~ security.network: false => true",
+                "- security.read_paths = [\"/srv\"]"
+            ]
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a.lnx"), dir.path().join("b.lnx"));
+        for (root, files) in [
+            (&a, [("bin/app", "v1"), ("gone", "x"), ("cache/x", "1")]),
+            (&b, [("bin/app", "v2"), ("new", "y"), ("cache/x", "2")]),
+        ] {
+            for (name, data) in files {
+                std::fs::create_dir_all(root.join(name).parent().unwrap()).unwrap();
+                std::fs::write(root.join(name), data).unwrap();
+            }
+        }
+        let exclude = ["cache/**".to_string()];
+        let lines = diff_manifests(
+            &manifest(&a, &exclude).unwrap(),
+            &manifest(&b, &exclude).unwrap(),
+        );
+        assert_eq!(
+            lines,
+            [
+                "~ bin/app (2 bytes => 2 bytes)",
+                "- gone (1 bytes)",
+                "+ new (1 bytes)"
+            ]
+        );
+
+        assert_eq!(diff_lines("a\nb\nc\n", "a\nc\nd\n"), ["-b", "+d"]);
+        assert!(diff_lines("same\n", "same\n").is_empty());
+    }
+}
@@ -0,0 +1,163 @@
+//! `[security] ephemeral_home`: `dotlnx run` gives the app an empty HOME of its own for each
+//! launch, under the user's runtime directory (a tmpfs), and deletes it when the app exits.
+//! `persist_paths` lists what the app keeps between launches: those paths in the scratch home are
+//! symlinks to the same paths in the real home. For demo stations and for trying apps out without
+//! letting them near the user's files.
+//!
+//! HOME and the XDG base directories point into the scratch home. That is all an unconfined app
+//! gets; one that looks its home up in the password database still finds the real one. A confined
+//! app's profile grants the scratch home and the persisted paths, and nothing else of the home
+//! beyond what the bundle lists.
+
+use anyhow::{Context, Result};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+
+/// Directory in the runtime dir that holds the scratch homes, one `<app>.<pid>` per launch.
+pub const DIR_NAME: &str = "dotlnx-home";
+
+/// A per-launch home. Removed by [`ScratchHome::remove`]; `dotlnx run` exits through
+/// `std::process::exit`, so it is not left to Drop.
+#[derive(Debug)]
+pub struct ScratchHome {
+    path: PathBuf,
+}
+
+impl ScratchHome {
+    /// Make `app`'s scratch home for this launch in the session's runtime dir, with
+    /// `persist` linked to the real home.
+    pub fn create(app: &str, persist: &[String]) -> Result<Self> {
+        let runtime = std::env::var_os("XDG_RUNTIME_DIR")
+            .filter(|d| Path::new(d).is_absolute())
+            .context("ephemeral_home needs XDG_RUNTIME_DIR (the session's runtime directory)")?;
+        let home = std::env::var_os("HOME")
+            .filter(|d| Path::new(d).is_absolute())
+            .context("ephemeral_home needs HOME")?;
+        Self::create_in(
+            &Path::new(&runtime).join(DIR_NAME),
+            app,
+            Path::new(&home),
+            persist,
+        )
+    }
+
+    fn create_in(base: &Path, app: &str, real_home: &Path, persist: &[String]) -> Result<Self> {
+        crate::validate::validate_app_name(app)?;
+        match std::fs::DirBuilder::new().mode(0o700).create(base) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e).with_context(|| format!("create {}", base.display())),
+        }
+        sweep(base);
+        let path = base.join(format!("{}.{}", app, std::process::id()));
+        // Left behind by an earlier dotlnx with this pid.
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::DirBuilder::new()
+            .mode(0o700)
+            .create(&path)
+            .with_context(|| format!("create {}", path.display()))?;
+        let scratch = Self { path };
+        if let Err(e) = scratch.link(real_home, persist) {
+            scratch.remove();
+            return Err(e);
+        }
+        Ok(scratch)
+    }
+
+    /// Link each persisted path into the scratch home. A path the real home does not have yet is
+    /// created there as a directory, so what the app writes into it is kept.
+    fn link(&self, real_home: &Path, persist: &[String]) -> Result<()> {
+        for p in persist {
+            let real = real_home.join(p);
+            if std::fs::symlink_metadata(&real).is_err() {
+                std::fs::create_dir_all(&real)
+                    .with_context(|| format!("create {}", real.display()))?;
+            }
+            let link = self.path.join(p);
+            if let Some(parent) = link.parent() {
+                std::fs::create_dir_all(parent)
+                    .with_context(|| format!("create {}", parent.display()))?;
+            }
+            std::os::unix::fs::symlink(&real, &link)
+                .with_context(|| format!("link {}", link.display()))?;
+        }
+        Ok(())
+    }
+
+    /// HOME and the XDG base directories for the app, all inside the scratch home.
+    pub fn vars(&self) -> Vec<(String, String)> {
+        let home = self.path.display().to_string();
+        let mut vars = vec![("HOME".to_string(), home.clone())];
+        for (key, dir) in [
+            ("XDG_CONFIG_HOME", ".config"),
+            ("XDG_DATA_HOME", ".local/share"),
+            ("XDG_STATE_HOME", ".local/state"),
+            ("XDG_CACHE_HOME", ".cache"),
+        ] {
+            vars.push((key.to_string(), format!("{}/{}", home, dir)));
+        }
+        vars
+    }
+
+    /// Delete the scratch home. Persisted paths are symlinks and removing them leaves what they
+    /// point to alone.
+    pub fn remove(self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.path) {
+            tracing::warn!("could not remove {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Remove scratch homes whose dotlnx is gone (killed before it could clean up).
+fn sweep(base: &Path) {
+    let Ok(entries) = std::fs::read_dir(base) else {
+        return;
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let name = entry.file_name();
+        let Some(pid) = name
+            .to_str()
+            .and_then(|n| n.rsplit_once('.'))
+            .and_then(|(_, pid)| pid.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        if !Path::new("/proc").join(pid.to_string()).exists() {
+            let _ = std::fs::remove_dir_all(entry.path());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scratch_home_is_discarded_but_persisted_paths_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let (base, real) = (dir.path().join("run"), dir.path().join("home"));
+        std::fs::create_dir(&real).unwrap();
+        std::fs::create_dir(&base).unwrap();
+        // A dead launch's leftovers.
+        std::fs::create_dir(base.join(format!("Notes.{}", u32::MAX))).unwrap();
+
+        let persist = vec![".config/Notes".to_string()];
+        let scratch = ScratchHome::create_in(&base, "Notes", &real, &persist).unwrap();
+        let home = PathBuf::from(&scratch.vars()[0].1);
+        assert_eq!(home, base.join(format!("Notes.{}", std::process::id())));
+        assert!(!base.join(format!("Notes.{}", u32::MAX)).exists());
+        std::fs::write(home.join("scratch.txt"), "gone").unwrap();
+        std::fs::write(home.join(".config/Notes/settings.ini"), "kept").unwrap();
+        assert!(scratch.vars().contains(&(
+            "XDG_CONFIG_HOME".into(),
+            format!("{}/.config", home.display())
+        )));
+
+        scratch.remove();
+        assert!(!home.exists());
+        assert_eq!(
+            std::fs::read_to_string(real.join(".config/Notes/settings.ini")).unwrap(),
+            "kept"
+        );
+    }
+}
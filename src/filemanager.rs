@@ -0,0 +1,233 @@
+//! `dotlnx integrate-filemanager`: right-click actions for .lnx folders in Nautilus (Files) and
+//! Dolphin. "Install with dotlnx" runs `dotlnx install`, "Validate bundle" runs `dotlnx validate`;
+//! both report the result as a desktop notification.
+//!
+//! Everything is per user under XDG_DATA_HOME: one helper script both file managers call,
+//! Nautilus scripts (right-click > Scripts), and a Dolphin service menu (KF6 and KF5 locations).
+//! Every generated file carries MARKER so `--remove` never deletes files it did not write.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::bundle;
+
+const MARKER: &str = "Generated by dotlnx integrate-filemanager";
+
+/// A file to install: path, contents, mode.
+#[derive(Debug)]
+struct Integration {
+    path: PathBuf,
+    contents: String,
+    mode: u32,
+}
+
+/// Entry point for `dotlnx integrate-filemanager [--remove]`.
+pub fn run(remove: bool) -> Result<()> {
+    if bundle::is_root() {
+        anyhow::bail!("run integrate-filemanager as the desktop user (the menus are per user)");
+    }
+    let data_home = xdg::BaseDirectories::with_prefix("")?.get_data_home();
+    let exe = std::env::current_exe().context("locate dotlnx binary")?;
+    let files = integrations(&data_home, &exe)?;
+    if remove {
+        remove_files(&files)
+    } else {
+        install_files(&files)
+    }
+}
+
+/// The files dotlnx installs for `data_home`, calling `exe`.
+fn integrations(data_home: &Path, exe: &Path) -> Result<Vec<Integration>> {
+    let helper = data_home.join("dotlnx/filemanager-action");
+    let helper_str = helper.to_string_lossy();
+    if helper_str.contains(['"', '`', '$', '\\', '%', '\n']) {
+        anyhow::bail!(
+            "data directory {} contains characters a .desktop Exec line cannot hold",
+            data_home.display()
+        );
+    }
+    let nautilus = data_home.join("nautilus/scripts");
+    let mut files = vec![Integration {
+        path: helper.clone(),
+        contents: helper_script(exe),
+        mode: 0o755,
+    }];
+    for (label, action) in [
+        ("Install with dotlnx", "install"),
+        ("Validate bundle (dotlnx)", "validate"),
+    ] {
+        files.push(Integration {
+            path: nautilus.join(label),
+            contents: format!(
+                "#!/bin/sh\n# {}\nexec {} {} \"$@\"\n",
+                MARKER,
+                sh_quote(&helper_str),
+                action
+            ),
+            mode: 0o755,
+        });
+    }
+    let service_menu = dolphin_service_menu(&helper_str);
+    // KF6 only loads executable service menus; KF5 reads its own directory.
+    for dir in ["kio/servicemenus", "kservices5/ServiceMenus"] {
+        files.push(Integration {
+            path: data_home.join(dir).join("dotlnx.desktop"),
+            contents: service_menu.clone(),
+            mode: 0o755,
+        });
+    }
+    Ok(files)
+}
+
+/// Shell script run by both file managers: `filemanager-action install|validate <path>...`.
+fn helper_script(exe: &Path) -> String {
+    format!(
+        r#"#!/bin/sh
+# {marker}
+# Remove with: dotlnx integrate-filemanager --remove
+dotlnx={exe}
+action="$1"
+shift
+notify() {{
+    if command -v notify-send >/dev/null 2>&1; then
+        notify-send --app-name=dotlnx --icon=system-software-install "$1" "$2"
+    else
+        printf '%s\n%s\n' "$1" "$2" >&2
+    fi
+}}
+for f in "$@"; do
+    name=$(basename "$f")
+    if out=$(NO_COLOR=1 "$dotlnx" "$action" -- "$f" 2>&1); then
+        case "$action" in
+            install) notify "Installed $name" "It will appear in your applications menu in a moment." ;;
+            *) notify "$name is valid" "" ;;
+        esac
+    else
+        notify "dotlnx $action failed: $name" "$(printf '%s\n' "$out" | tail -n 3)"
+    fi
+done
+"#,
+        marker = MARKER,
+        exe = sh_quote(&exe.to_string_lossy()),
+    )
+}
+
+/// Dolphin service menu offering both actions on folders (Dolphin cannot match a name suffix,
+/// so the actions show for every folder; dotlnx rejects anything that is not a .lnx bundle).
+fn dolphin_service_menu(helper: &str) -> String {
+    format!(
+        "[Desktop Entry]\n\
+         Type=Service\n\
+         X-KDE-ServiceTypes=KonqPopupMenu/Plugin\n\
+         MimeType=inode/directory;\n\
+         Actions=dotlnxInstall;dotlnxValidate;\n\
+         X-KDE-Submenu=dotlnx\n\
+         Comment={marker}\n\
+         \n\
+         [Desktop Action dotlnxInstall]\n\
+         Name=Install with dotlnx\n\
+         Icon=system-software-install\n\
+         Exec=\"{helper}\" install %F\n\
+         \n\
+         [Desktop Action dotlnxValidate]\n\
+         Name=Validate bundle\n\
+         Icon=dialog-ok\n\
+         Exec=\"{helper}\" validate %F\n",
+        marker = MARKER,
+        helper = helper,
+    )
+}
+
+/// Single-quote for sh.
+fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+fn install_files(files: &[Integration]) -> Result<()> {
+    for f in files {
+        if let Ok(existing) = std::fs::read_to_string(&f.path) {
+            if !existing.contains(MARKER) {
+                warn!(path = %f.path.display(), "exists and was not written by dotlnx; leaving it alone");
+                continue;
+            }
+        }
+        if let Some(parent) = f.path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("create {}", parent.display()))?;
+        }
+        std::fs::write(&f.path, &f.contents)
+            .with_context(|| format!("write {}", f.path.display()))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&f.path, std::fs::Permissions::from_mode(f.mode))?;
+        }
+        info!(path = %f.path.display(), "installed");
+    }
+    info!("file manager actions installed; restart Nautilus (nautilus -q) or Dolphin to see them");
+    Ok(())
+}
+
+fn remove_files(files: &[Integration]) -> Result<()> {
+    for f in files {
+        match std::fs::read_to_string(&f.path) {
+            Ok(existing) if existing.contains(MARKER) => {
+                std::fs::remove_file(&f.path)
+                    .with_context(|| format!("remove {}", f.path.display()))?;
+                info!(path = %f.path.display(), "removed");
+            }
+            Ok(_) => {
+                warn!(path = %f.path.display(), "not written by dotlnx; leaving it alone")
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("read {}", f.path.display())),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn install_and_remove_only_touch_marked_files() {
+        let data = tempfile::tempdir().unwrap();
+        let files = integrations(data.path(), Path::new("/usr/bin/dotlnx")).unwrap();
+        let foreign = data.path().join("kservices5/ServiceMenus/dotlnx.desktop");
+        std::fs::create_dir_all(foreign.parent().unwrap()).unwrap();
+        std::fs::write(&foreign, "[Desktop Entry]\n").unwrap();
+
+        install_files(&files).unwrap();
+        let helper =
+            std::fs::read_to_string(data.path().join("dotlnx/filemanager-action")).unwrap();
+        assert!(helper.contains("dotlnx='/usr/bin/dotlnx'"));
+        let script =
+            std::fs::read_to_string(data.path().join("nautilus/scripts/Install with dotlnx"))
+                .unwrap();
+        assert!(script.ends_with("filemanager-action' install \"$@\"\n"));
+        let menu =
+            std::fs::read_to_string(data.path().join("kio/servicemenus/dotlnx.desktop")).unwrap();
+        assert!(menu.contains("filemanager-action\" validate %F"));
+        assert_eq!(
+            std::fs::read_to_string(&foreign).unwrap(),
+            "[Desktop Entry]\n"
+        );
+
+        remove_files(&files).unwrap();
+        assert!(!data.path().join("dotlnx/filemanager-action").exists());
+        assert!(!data.path().join("kio/servicemenus/dotlnx.desktop").exists());
+        assert!(foreign.exists());
+    }
+
+    #[test]
+    fn sh_quote_escapes_single_quotes() {
+        assert_eq!(sh_quote("/opt/it's/dotlnx"), r"'/opt/it'\''s/dotlnx'");
+    }
+
+    #[test]
+    fn unsafe_data_dir_is_rejected() {
+        assert!(integrations(Path::new("/home/a/$x"), Path::new("/usr/bin/dotlnx")).is_err());
+    }
+}
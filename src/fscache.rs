@@ -0,0 +1,131 @@
+//! Per-sync memo of canonicalize/stat results. Validation, the trust checks, Exec= and Icon=
+//! resolution each canonicalize or stat the same bundle paths (bundle root, executable, helpers,
+//! icon); during a sync the answers are cached so each path hits the filesystem once.
+//!
+//! The cache only exists while a [`Scope`] is alive (one per sync pass) and is dropped with it, so
+//! the long-running watcher never serves results from a previous pass. Outside a scope every call
+//! goes straight to the filesystem. Only use it for bundle contents, which sync never modifies.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A failed lookup, kept so a missing path is not retried within the pass.
+type Cached<T> = Result<T, (io::ErrorKind, String)>;
+
+#[derive(Default)]
+struct Cache {
+    canonical: HashMap<PathBuf, Cached<PathBuf>>,
+    metadata: HashMap<PathBuf, Cached<Metadata>>,
+}
+
+thread_local! {
+    static CACHE: RefCell<Option<Cache>> = const { RefCell::new(None) };
+}
+
+/// Caching is on while this is alive; dropping it discards everything cached.
+pub struct Scope {
+    outer: bool,
+}
+
+/// Start caching for the current thread. Nested scopes share the outermost one's cache.
+pub fn scope() -> Scope {
+    CACHE.with(|c| {
+        let mut c = c.borrow_mut();
+        let outer = c.is_none();
+        if outer {
+            *c = Some(Cache::default());
+        }
+        Scope { outer }
+    })
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        if self.outer {
+            CACHE.with(|c| *c.borrow_mut() = None);
+        }
+    }
+}
+
+fn memo<T: Clone>(
+    path: &Path,
+    table: fn(&mut Cache) -> &mut HashMap<PathBuf, Cached<T>>,
+    lookup: fn(&Path) -> io::Result<T>,
+) -> io::Result<T> {
+    let hit = CACHE.with(|c| {
+        c.borrow_mut()
+            .as_mut()
+            .map(|cache| table(cache).get(path).cloned())
+    });
+    let result = match hit {
+        // No scope: plain filesystem call.
+        None => return lookup(path),
+        Some(Some(cached)) => cached,
+        Some(None) => {
+            let fresh = lookup(path).map_err(|e| (e.kind(), e.to_string()));
+            CACHE.with(|c| {
+                if let Some(cache) = c.borrow_mut().as_mut() {
+                    table(cache).insert(path.to_path_buf(), fresh.clone());
+                }
+            });
+            fresh
+        }
+    };
+    result.map_err(|(kind, msg)| io::Error::new(kind, msg))
+}
+
+/// [`std::fs::canonicalize`], memoized within a scope.
+pub fn canonicalize(path: &Path) -> io::Result<PathBuf> {
+    memo(path, |c| &mut c.canonical, |p| std::fs::canonicalize(p))
+}
+
+/// [`std::fs::metadata`] (follows symlinks), memoized within a scope.
+pub fn metadata(path: &Path) -> io::Result<Metadata> {
+    memo(path, |c| &mut c.metadata, |p| std::fs::metadata(p))
+}
+
+/// Like [`Path::exists`].
+pub fn exists(path: &Path) -> bool {
+    metadata(path).is_ok()
+}
+
+/// Like [`Path::is_file`].
+pub fn is_file(path: &Path) -> bool {
+    metadata(path).is_ok_and(|m| m.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_are_cached_only_inside_a_scope() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("app");
+        std::fs::write(&file, "x").unwrap();
+        {
+            let _scope = scope();
+            assert!(is_file(&file));
+            let canon = canonicalize(&file).unwrap();
+            let missing = dir.path().join("missing");
+            assert!(!exists(&missing));
+            std::fs::remove_file(&file).unwrap();
+            std::fs::write(&missing, "").unwrap();
+            // Answers from the first lookup stand for the rest of the pass.
+            assert!(is_file(&file));
+            assert_eq!(canonicalize(&file).unwrap(), canon);
+            let err = metadata(&missing).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::NotFound);
+            {
+                let _nested = scope();
+                assert!(is_file(&file));
+            }
+            assert!(is_file(&file), "a nested scope keeps the outer cache");
+        }
+        assert!(!is_file(&file));
+        assert!(exists(&dir.path().join("missing")));
+    }
+}
@@ -0,0 +1,106 @@
+//! Undo leftovers of earlier syncs that nothing else will: .directory files and GNOME folder
+//! icons in bundle folders that are no longer installed (the app was uninstalled, renamed or
+//! moved to another tier while the folder stayed).
+
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+use crate::paths;
+use crate::state::{self, Decoration, State};
+use crate::sync::{self, Action, Change};
+
+/// Clean up after bundles the last sync did not install. Returns one change per folder cleaned
+/// (or, with `check`, that would be), without touching anything when `check` is set.
+pub fn run(check: bool) -> Result<Vec<Change>> {
+    let paths = paths::load()?;
+    let dir = state::state_dir()?;
+    let mut st = State::load(&dir)?;
+    let mut leftovers = take_leftovers(&mut st);
+    let changes: Vec<Change> = leftovers.iter().filter_map(|(b, d)| change(b, d)).collect();
+    if check || leftovers.is_empty() {
+        return Ok(changes);
+    }
+    sync::clear_decorations(&paths, leftovers.iter_mut().map(|(b, d)| (b.as_path(), d)));
+    sync::keep_uncleared(&mut st, leftovers);
+    st.save(&dir)?;
+    Ok(changes)
+}
+
+/// Decorations of bundles missing from the name index the last sync built.
+fn take_leftovers(st: &mut State) -> Vec<(PathBuf, Decoration)> {
+    let keys: Vec<String> = st
+        .decorations
+        .keys()
+        .filter(|k| !st.is_installed(Path::new(k)))
+        .cloned()
+        .collect();
+    keys.into_iter()
+        .filter_map(|k| st.decorations.remove_entry(&k))
+        .map(|(k, d)| (PathBuf::from(k), d))
+        .collect()
+}
+
+/// What cleaning a folder removes; None when the folder is gone (only state is forgotten).
+fn change(bundle: &Path, deco: &Decoration) -> Option<Change> {
+    if !bundle.is_dir() {
+        return None;
+    }
+    let mut paths = Vec::new();
+    if deco.directory_file {
+        paths.push(bundle.join(".directory"));
+    }
+    if deco.folder_icon.is_some() {
+        paths.push(bundle.to_path_buf());
+    }
+    (!paths.is_empty()).then(|| Change {
+        action: Action::Remove,
+        app: deco.app.clone(),
+        paths,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_bundles_missing_from_the_index_are_leftovers() {
+        let tmp = tempfile::tempdir().unwrap();
+        let kept = tmp.path().join("Kept.lnx");
+        let left = tmp.path().join("Left.lnx");
+        let gone = tmp.path().join("Gone.lnx");
+        std::fs::create_dir_all(&kept).unwrap();
+        std::fs::create_dir_all(&left).unwrap();
+        let mut st = State::default();
+        st.names
+            .insert("Kept".into(), vec![kept.display().to_string()]);
+        for (b, app) in [(&kept, "Kept"), (&left, "Left"), (&gone, "Gone")] {
+            let deco = Decoration {
+                app: app.into(),
+                directory_file: true,
+                ..Decoration::default()
+            };
+            st.decorations.insert(b.display().to_string(), deco);
+        }
+
+        let mut leftovers = take_leftovers(&mut st);
+        leftovers.sort_by(|a, b| a.0.cmp(&b.0));
+        let bundles: Vec<&PathBuf> = leftovers.iter().map(|(b, _)| b).collect();
+        assert_eq!(bundles, [&gone, &left]);
+        assert_eq!(st.decorations.len(), 1);
+
+        let changes: Vec<Change> = leftovers.iter().filter_map(|(b, d)| change(b, d)).collect();
+        assert_eq!(
+            changes.len(),
+            1,
+            "a folder that is gone has nothing to clean"
+        );
+        assert_eq!(changes[0].app, "Left");
+        assert_eq!(changes[0].paths, [left.join(".directory")]);
+
+        // Nothing undone yet: the folder that is still there stays recorded, the other is dropped.
+        sync::keep_uncleared(&mut st, leftovers);
+        assert!(st.decorations.contains_key(&left.display().to_string()));
+        assert!(!st.decorations.contains_key(&gone.display().to_string()));
+    }
+}
@@ -0,0 +1,245 @@
+//! Host GPU driver layout for `[security] gpu`. Mesa lives under /usr/lib, which every profile
+//! may map, but proprietary drivers (NVIDIA) and some distributions put their GL/EGL/Vulkan
+//! libraries elsewhere (/usr/lib64/nvidia, /opt/...). Sync finds them the way the loaders do
+//! (the vendor files of GLVND, EGL external platforms and Vulkan, and the dynamic loader's
+//! directories) and adds them to the profiles of GPU apps; `dotlnx validate` warns about a
+//! layout no profile can cover.
+
+use std::path::{Path, PathBuf};
+
+/// Rules for NVIDIA's device nodes, driver state and caches. Added with every `gpu = true`
+/// profile, so one profile works on hosts with and without the driver.
+pub const NVIDIA_RULES: &[&str] = &[
+    "# gpu: nvidia",
+    "/dev/nvidiactl rw,",
+    "/dev/nvidia[0-9]* rw,",
+    "/dev/nvidia-modeset rw,",
+    "/dev/nvidia-uvm rw,",
+    "/dev/nvidia-uvm-tools rw,",
+    "/dev/nvidia-caps/ r,",
+    "/dev/nvidia-caps/* rw,",
+    "/proc/driver/nvidia/** r,",
+    "/sys/module/nvidia/** r,",
+    "/usr/share/nvidia/** r,",
+    "/usr/share/egl/** r,",
+    "/etc/glvnd/** r,",
+    "/etc/egl/** r,",
+    "owner @{HOME}/.nv/** rwk,",
+    "owner @{HOME}/.cache/nvidia/** rwk,",
+];
+
+/// Directories holding the vendor files (JSON naming a driver library) of GLVND, EGL external
+/// platforms and Vulkan ICDs.
+const VENDOR_DIRS: &[&str] = &[
+    "usr/share/glvnd/egl_vendor.d",
+    "etc/glvnd/egl_vendor.d",
+    "usr/share/egl/egl_external_platform.d",
+    "etc/egl/egl_external_platform.d",
+    "usr/share/vulkan/icd.d",
+    "etc/vulkan/icd.d",
+];
+
+/// Where driver packages put their libraries outside the loader's configuration.
+const KNOWN_DIRS: &[&str] = &[
+    "usr/lib64/nvidia",
+    "usr/lib32/nvidia",
+    "usr/lib/nvidia",
+    "opt/nvidia/lib64",
+];
+
+/// Library directories every profile may already map (`/usr/lib/** rm`, `/lib/** rm`).
+const COVERED: &[&str] = &["/usr/lib/", "/lib/"];
+
+/// Directories users can write; libraries there are never added to a profile.
+const UNSAFE: &[&str] = &[
+    "/home/",
+    "/root/",
+    "/tmp/",
+    "/var/tmp/",
+    "/dev/shm/",
+    "/run/user/",
+];
+
+/// What sync and validate found on the host.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Layout {
+    /// Driver library directories profiles do not cover yet, added to GPU apps' profiles.
+    pub lib_dirs: Vec<PathBuf>,
+    /// Driver library directories in places users can write, left out of profiles.
+    pub unsafe_dirs: Vec<PathBuf>,
+    /// The NVIDIA kernel driver is loaded.
+    pub nvidia_driver: bool,
+    /// NVIDIA's user-space libraries were found.
+    pub nvidia_libs: bool,
+}
+
+impl Layout {
+    /// Profile rules for the driver libraries found, at `indent`.
+    pub fn rules(&self, indent: &str) -> Vec<String> {
+        if self.lib_dirs.is_empty() {
+            return Vec::new();
+        }
+        let mut out = vec![format!("{}# gpu: driver libraries on this host", indent)];
+        for dir in &self.lib_dirs {
+            let dir = crate::apparmor::quote_path_for_apparmor(&format!("{}/**", dir.display()));
+            out.push(format!("{}{} rm,", indent, dir));
+        }
+        out
+    }
+
+    /// Why a confined GPU app may still fail on this host.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut out: Vec<String> = self
+            .unsafe_dirs
+            .iter()
+            .map(|d| {
+                format!(
+                    "GPU driver libraries in {} are not added to profiles (users can write there); confined GPU apps cannot load them",
+                    d.display()
+                )
+            })
+            .collect();
+        if self.nvidia_driver && !self.nvidia_libs {
+            out.push(
+                "the NVIDIA driver is loaded but its libraries were not found where the loaders look; \
+                 add their directory to read_paths or confined GPU apps may fail"
+                    .into(),
+            );
+        }
+        out
+    }
+}
+
+/// Find the host's GPU driver layout under `root` ("/" for the live host). The dynamic loader's
+/// configuration is only read for the live host.
+pub fn detect(root: &Path) -> Layout {
+    let live = root == Path::new("/");
+    let mut search: Vec<PathBuf> = match live {
+        true => crate::deps::host_lib_dirs(),
+        false => Vec::new(),
+    };
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    for vendor_dir in VENDOR_DIRS {
+        let Ok(entries) = std::fs::read_dir(root.join(vendor_dir)) else {
+            continue;
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            let Some(library) = std::fs::read_to_string(entry.path())
+                .ok()
+                .and_then(|t| library_path(&t))
+            else {
+                continue;
+            };
+            // A bare file name is looked up in the loader's directories, already listed.
+            if let Some(dir) = Path::new(&library).parent().filter(|d| d.is_absolute()) {
+                dirs.push(dir.to_path_buf());
+            }
+        }
+    }
+    dirs.extend(
+        search
+            .iter()
+            .filter(|d| d.to_string_lossy().contains("nvidia"))
+            .cloned(),
+    );
+    dirs.extend(
+        KNOWN_DIRS
+            .iter()
+            .map(|d| Path::new("/").join(d))
+            .filter(|d| root.join(d.strip_prefix("/").unwrap_or(d)).is_dir()),
+    );
+    dirs.sort();
+    dirs.dedup();
+    search.extend(dirs.iter().cloned());
+    let in_root = |d: &Path| root.join(d.strip_prefix("/").unwrap_or(d));
+    let nvidia_libs = search.iter().any(|d| {
+        std::fs::read_dir(in_root(d)).is_ok_and(|entries| {
+            entries.filter_map(|e| e.ok()).any(|e| {
+                let name = e.file_name();
+                let name = name.to_string_lossy();
+                name.starts_with("libGLX_nvidia") || name.starts_with("libnvidia-glcore")
+            })
+        })
+    });
+    let mut layout = Layout {
+        nvidia_driver: in_root(Path::new("/proc/driver/nvidia")).exists()
+            || in_root(Path::new("/dev/nvidiactl")).exists(),
+        nvidia_libs,
+        ..Layout::default()
+    };
+    for dir in dirs {
+        let text = format!("{}/", dir.display());
+        if UNSAFE.iter().any(|u| text.starts_with(u)) {
+            layout.unsafe_dirs.push(dir);
+        } else if !COVERED.iter().any(|c| text.starts_with(c)) {
+            layout.lib_dirs.push(dir);
+        }
+    }
+    layout
+}
+
+/// `ICD.library_path` of a GLVND / EGL platform / Vulkan vendor file.
+fn library_path(json: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(json).ok()?;
+    value["ICD"]["library_path"].as_str().map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_driver_libraries_outside_usr_lib() {
+        let root = tempfile::tempdir().unwrap();
+        let write = |rel: &str, text: &str| {
+            let path = root.path().join(rel);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text).unwrap();
+        };
+        let icd = |lib: &str| {
+            format!(
+                "{{\"file_format_version\": \"1.0.0\", \"ICD\": {{\"library_path\": \"{}\"}}}}",
+                lib
+            )
+        };
+        write(
+            "usr/share/vulkan/icd.d/nvidia_icd.json",
+            &icd("/usr/lib64/nvidia/libGLX_nvidia.so.0"),
+        );
+        write(
+            "usr/share/glvnd/egl_vendor.d/50_mesa.json",
+            &icd("libEGL_mesa.so.0"),
+        );
+        write(
+            "etc/vulkan/icd.d/custom.json",
+            &icd("/home/dev/vk/libvk_custom.so"),
+        );
+        write(
+            "usr/share/egl/egl_external_platform.d/10_nvidia_wayland.json",
+            &icd("/usr/lib/x86_64-linux-gnu/libnvidia-egl-wayland.so.1"),
+        );
+        write("usr/lib64/nvidia/libGLX_nvidia.so.0", "");
+        write("proc/driver/nvidia/version", "");
+
+        let layout = detect(root.path());
+        assert_eq!(layout.lib_dirs, [PathBuf::from("/usr/lib64/nvidia")]);
+        assert_eq!(layout.unsafe_dirs, [PathBuf::from("/home/dev/vk")]);
+        assert!(layout.nvidia_driver && layout.nvidia_libs);
+        assert_eq!(
+            layout.rules("  "),
+            [
+                "  # gpu: driver libraries on this host",
+                "  /usr/lib64/nvidia/** rm,"
+            ]
+        );
+        assert_eq!(layout.warnings().len(), 1);
+
+        std::fs::remove_file(root.path().join("usr/lib64/nvidia/libGLX_nvidia.so.0")).unwrap();
+        let layout = detect(root.path());
+        assert!(!layout.nvidia_libs);
+        assert!(layout
+            .warnings()
+            .iter()
+            .any(|w| w.contains("NVIDIA driver is loaded")));
+    }
+}
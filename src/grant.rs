@@ -0,0 +1,378 @@
+//! `dotlnx grant` / `dotlnx revoke`: temporary exceptions to an app's AppArmor profile. An admin
+//! lets one app read or write a path it was not given (to open a file on a share, say) without
+//! touching the bundle's config.toml or the site overrides; sync adds the paths to the profile it
+//! generates and reloads it.
+//!
+//! Grants live in grants.json next to state.json, keyed by profile name, and are only written by
+//! these commands (a sync in the daemon cannot lose one it did not see). A grant lasts until the
+//! next reboot (the default) or until it is revoked.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::config::{Config, Security};
+
+/// Kernel's id of the current boot; changes on every reboot.
+const BOOT_ID: &str = "/proc/sys/kernel/random/boot_id";
+
+/// What a grant allows on its path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    Read,
+    Rw,
+}
+
+/// How long a grant lasts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Until {
+    /// Until the machine restarts: dropped once the boot id differs.
+    Reboot { boot_id: String },
+    /// Until `dotlnx revoke`.
+    Revoke,
+}
+
+/// `--until` on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Lifetime {
+    Reboot,
+    Revoke,
+}
+
+/// One temporary rule for one profile.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Grant {
+    pub access: Access,
+    /// AppArmor path (globs allowed), as given.
+    pub path: String,
+    pub until: Until,
+    /// Who granted it (the user behind sudo or pkexec, else USER).
+    pub by: String,
+    /// Unix time it was granted.
+    pub since: u64,
+}
+
+impl Grant {
+    /// False once a reboot-scoped grant belongs to an earlier boot. `boot_id` None (no /proc)
+    /// keeps every grant.
+    fn is_live(&self, boot_id: Option<&str>) -> bool {
+        match (&self.until, boot_id) {
+            (Until::Reboot { boot_id: granted }, Some(current)) => granted == current,
+            _ => true,
+        }
+    }
+
+    /// The rule as typed on the command line ("read:/srv/share/**").
+    pub fn rule(&self) -> String {
+        let access = match self.access {
+            Access::Read => "read",
+            Access::Rw => "rw",
+        };
+        format!("{}:{}", access, self.path)
+    }
+}
+
+/// Parse "read:/path" or "rw:/path".
+pub fn parse_rule(rule: &str) -> Result<(Access, String)> {
+    let (access, path) = rule
+        .split_once(':')
+        .with_context(|| format!("invalid rule {:?} (expected read:/path or rw:/path)", rule))?;
+    let access = match access {
+        "read" | "r" => Access::Read,
+        "rw" | "write" => Access::Rw,
+        _ => anyhow::bail!(
+            "invalid access {:?} in {:?} (expected read or rw)",
+            access,
+            rule
+        ),
+    };
+    if !path.starts_with('/') {
+        anyhow::bail!("grant path must be absolute: {:?}", path);
+    }
+    crate::validate::validate_security_path("grant", path)?;
+    Ok((access, path.to_string()))
+}
+
+/// Grants by profile name.
+pub type Grants = BTreeMap<String, Vec<Grant>>;
+
+/// Load grants.json from the state dir. Missing means none.
+pub fn load(state_dir: &Path) -> Result<Grants> {
+    let path = state_dir.join("grants.json");
+    match std::fs::read_to_string(&path) {
+        Ok(s) => serde_json::from_str(&s).with_context(|| format!("parse {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Grants::new()),
+        Err(e) => Err(e).with_context(|| format!("read {}", path.display())),
+    }
+}
+
+/// Write grants.json atomically (temp file + rename).
+fn save(state_dir: &Path, grants: &Grants) -> Result<()> {
+    std::fs::create_dir_all(state_dir)?;
+    let tmp = state_dir.join(".grants.json.tmp");
+    std::fs::write(&tmp, serde_json::to_string_pretty(grants)?)?;
+    std::fs::rename(&tmp, state_dir.join("grants.json"))?;
+    Ok(())
+}
+
+/// Current boot id, None without /proc.
+fn boot_id() -> Option<String> {
+    std::fs::read_to_string(BOOT_ID)
+        .ok()
+        .map(|s| s.trim().to_string())
+}
+
+/// Drop grants from earlier boots (and profiles left with none).
+fn expire(grants: &mut Grants, boot_id: Option<&str>) {
+    for list in grants.values_mut() {
+        list.retain(|g| g.is_live(boot_id));
+    }
+    grants.retain(|_, list| !list.is_empty());
+}
+
+/// Add the live grants of `profile` to the config its profile is generated from.
+pub fn apply(cfg: &mut Config, grants: &Grants, profile: &str) {
+    let Some(list) = grants.get(profile) else {
+        return;
+    };
+    let boot_id = boot_id();
+    let live: Vec<&Grant> = list
+        .iter()
+        .filter(|g| g.is_live(boot_id.as_deref()))
+        .collect();
+    if live.is_empty() {
+        return;
+    }
+    let security = cfg.security.get_or_insert_with(Security::default);
+    for grant in live {
+        let paths = match grant.access {
+            Access::Read => &mut security.read_paths,
+            Access::Rw => &mut security.write_paths,
+        };
+        if !paths.contains(&grant.path) {
+            paths.push(grant.path.clone());
+        }
+    }
+}
+
+/// The app called `name` for an admin command: its profile name (which identifies it in its
+/// tier), bundle and config. User-tier apps are looked up in the home of whoever ran sudo or
+/// pkexec, not root's.
+pub fn resolve_app(name: &str) -> Result<(String, PathBuf, Config)> {
+    crate::validate::validate_app_name(name)?;
+    let mut paths = crate::paths::load()?;
+    if let Some(user) = invoking_user().filter(|u| u != "root") {
+        paths.user_apps = paths.home_dirs.join(user).join("Applications");
+    }
+    let Some((bundle_path, config, is_user_tier)) =
+        crate::bundle::resolve_bundle_by_name(&paths, name)?
+    else {
+        anyhow::bail!(crate::i18n::Message::new(
+            "app-not-found",
+            &[("name", name.to_string())]
+        ));
+    };
+    let username = is_user_tier.then(|| {
+        crate::bundle::username_from_bundle_path(&bundle_path)
+            .unwrap_or_else(|| std::env::var("USER").unwrap_or_else(|_| "unknown".into()))
+    });
+    Ok((
+        paths.profile_name(username.as_deref(), &config.app_id()),
+        bundle_path,
+        config,
+    ))
+}
+
+/// Profile name of the app called `name`, refusing apps that run unconfined.
+fn resolve_profile(name: &str) -> Result<String> {
+    let (profile, _, config) = resolve_app(name)?;
+    if !config.security.as_ref().map(|s| s.confine).unwrap_or(true) {
+        anyhow::bail!(
+            "{} runs unconfined (confine = false): it has no profile to grant paths in",
+            config.name
+        );
+    }
+    Ok(profile)
+}
+
+/// The user behind sudo or pkexec (the denial agent grants through pkexec), when there is one.
+fn invoking_user() -> Option<String> {
+    if let Ok(user) = std::env::var("SUDO_USER") {
+        return Some(user);
+    }
+    let uid = std::env::var("PKEXEC_UID").ok()?.parse().ok()?;
+    let user = nix::unistd::User::from_uid(nix::unistd::Uid::from_raw(uid)).ok()??;
+    Some(user.name)
+}
+
+/// Who is running the command, for the record.
+pub fn granted_by() -> String {
+    invoking_user()
+        .or_else(|| std::env::var("USER").ok())
+        .unwrap_or_else(|| "unknown".into())
+}
+
+pub fn require_root(command: &str) -> Result<()> {
+    if !crate::bundle::is_root() {
+        anyhow::bail!(
+            "{} changes what sync installs; run it as root (sudo dotlnx {} ...)",
+            command,
+            command
+        );
+    }
+    Ok(())
+}
+
+/// Print the grants of one profile.
+fn print(profile: &str, list: &[Grant]) {
+    if list.is_empty() {
+        println!("{}: no grants", profile);
+        return;
+    }
+    for grant in list {
+        let until = match grant.until {
+            Until::Reboot { .. } => "reboot",
+            Until::Revoke => "revoke",
+        };
+        println!(
+            "{}: {} (until {}, by {} at {})",
+            profile,
+            grant.rule(),
+            until,
+            grant.by,
+            grant.since
+        );
+    }
+}
+
+/// `dotlnx grant <name> [rules...]`: add the rules and reload the profile; without rules, list
+/// the app's grants.
+pub fn grant(name: &str, rules: &[String], until: Lifetime) -> Result<()> {
+    require_root("grant")?;
+    let profile = resolve_profile(name)?;
+    let state_dir = crate::state::state_dir()?;
+    let mut grants = load(&state_dir)?;
+    let boot_id = boot_id();
+    expire(&mut grants, boot_id.as_deref());
+    if rules.is_empty() {
+        print(
+            &profile,
+            grants.get(&profile).map(Vec::as_slice).unwrap_or_default(),
+        );
+        return Ok(());
+    }
+    let until = match (until, boot_id) {
+        (Lifetime::Reboot, Some(boot_id)) => Until::Reboot { boot_id },
+        (Lifetime::Reboot, None) => anyhow::bail!(
+            "cannot read {} to scope the grant to this boot; use --until revoke",
+            BOOT_ID
+        ),
+        (Lifetime::Revoke, _) => Until::Revoke,
+    };
+    let by = granted_by();
+    let list = grants.entry(profile.clone()).or_default();
+    for rule in rules {
+        let (access, path) = parse_rule(rule)?;
+        list.retain(|g| !(g.access == access && g.path == path));
+        let grant = Grant {
+            access,
+            path,
+            until: until.clone(),
+            by: by.clone(),
+            since: crate::state::now(),
+        };
+        info!(profile = %profile, by = %by, "granted {}", grant.rule());
+        list.push(grant);
+    }
+    save(&state_dir, &grants)?;
+    crate::sync::run(false)?;
+    Ok(())
+}
+
+/// `dotlnx revoke <name> [rules...]`: remove the given rules (all of the app's grants when none
+/// are given) and reload the profile.
+pub fn revoke(name: &str, rules: &[String]) -> Result<()> {
+    require_root("revoke")?;
+    let profile = resolve_profile(name)?;
+    let state_dir = crate::state::state_dir()?;
+    let mut grants = load(&state_dir)?;
+    expire(&mut grants, boot_id().as_deref());
+    let wanted = rules
+        .iter()
+        .map(|r| parse_rule(r))
+        .collect::<Result<Vec<_>>>()?;
+    let list = grants.entry(profile.clone()).or_default();
+    let before = list.len();
+    list.retain(|g| {
+        !wanted.is_empty() && !wanted.iter().any(|(a, p)| g.access == *a && g.path == *p)
+    });
+    let removed = before - list.len();
+    if removed == 0 {
+        println!("{}: nothing to revoke", profile);
+        return Ok(());
+    }
+    info!(profile = %profile, by = %granted_by(), "revoked {} grant(s)", removed);
+    grants.retain(|_, list| !list.is_empty());
+    save(&state_dir, &grants)?;
+    crate::sync::run(false)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_apply_and_expire() {
+        assert_eq!(
+            parse_rule("read:/srv/share/**").unwrap(),
+            (Access::Read, "/srv/share/**".into())
+        );
+        assert_eq!(parse_rule("rw:/tmp/out").unwrap().0, Access::Rw);
+        assert!(parse_rule("/tmp/out").is_err());
+        assert!(parse_rule("exec:/bin/sh").is_err());
+        assert!(parse_rule("read:relative").is_err());
+        assert!(parse_rule("rw:/a/../etc").is_err());
+
+        let grant = |access, path: &str, until| Grant {
+            access,
+            path: path.into(),
+            until,
+            by: "admin".into(),
+            since: 1,
+        };
+        let old_boot = Until::Reboot {
+            boot_id: "not-this-boot".into(),
+        };
+        let mut grants = Grants::new();
+        grants.insert(
+            "dotlnx-alice-app".into(),
+            vec![
+                grant(Access::Read, "/srv/share/**", Until::Revoke),
+                grant(Access::Rw, "/tmp/out", old_boot.clone()),
+            ],
+        );
+        grants.insert(
+            "dotlnx-other".into(),
+            vec![grant(Access::Rw, "/tmp/gone", old_boot)],
+        );
+
+        let mut cfg: Config = toml::from_str("name = \"app\"\nexecutable = \"bin/app\"\n").unwrap();
+        apply(&mut cfg, &grants, "dotlnx-alice-app");
+        let security = cfg.security.as_ref().unwrap();
+        assert!(security.confine);
+        assert_eq!(security.read_paths, ["/srv/share/**"]);
+        if boot_id().is_some() {
+            assert!(security.write_paths.is_empty());
+        }
+
+        expire(&mut grants, Some("this-boot"));
+        assert_eq!(grants.len(), 1);
+        assert_eq!(grants["dotlnx-alice-app"].len(), 1);
+        assert_eq!(grants["dotlnx-alice-app"][0].rule(), "read:/srv/share/**");
+    }
+}
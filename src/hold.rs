@@ -0,0 +1,157 @@
+//! `dotlnx hold` / `dotlnx unhold`: keep an app on the version that was validated. While an app is
+//! held, sync leaves its menu entry, profile and units as they are when its bundle changes (a new
+//! AppImage dropped in, an edited config.toml) until `dotlnx sync --force`, and `dotlnx import
+//! --force` does not overwrite its bundle.
+//!
+//! Holds live in holds.json next to state.json, keyed by profile name like grants, with a
+//! fingerprint of the bundle taken when it was held; only these commands write it.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::grant;
+use crate::scan;
+
+/// One held app.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hold {
+    pub app: String,
+    pub bundle: PathBuf,
+    /// `scan::fingerprint` of the bundle when it was held (names, sizes, mtimes, modes).
+    pub fingerprint: String,
+    /// Who held it (the user behind sudo or pkexec, else USER).
+    pub by: String,
+    /// Unix time it was held.
+    pub since: u64,
+}
+
+/// Holds by profile name.
+pub type Holds = BTreeMap<String, Hold>;
+
+/// Fingerprint of a bundle's files, the same whatever the [scan] settings.
+pub fn fingerprint(bundle: &Path, exclude: &[String]) -> String {
+    scan::fingerprint(&Default::default(), bundle, exclude)
+}
+
+impl Hold {
+    /// True when `bundle` is the held bundle and has changed since it was held.
+    pub fn blocks(&self, bundle: &Path, exclude: &[String]) -> bool {
+        self.bundle == bundle && self.fingerprint != fingerprint(bundle, exclude)
+    }
+}
+
+/// Load holds.json from the state dir. Missing means none.
+pub fn load(state_dir: &Path) -> Result<Holds> {
+    let path = state_dir.join("holds.json");
+    match std::fs::read_to_string(&path) {
+        Ok(s) => serde_json::from_str(&s).with_context(|| format!("parse {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Holds::new()),
+        Err(e) => Err(e).with_context(|| format!("read {}", path.display())),
+    }
+}
+
+/// Write holds.json atomically (temp file + rename).
+fn save(state_dir: &Path, holds: &Holds) -> Result<()> {
+    std::fs::create_dir_all(state_dir)?;
+    let tmp = state_dir.join(".holds.json.tmp");
+    std::fs::write(&tmp, serde_json::to_string_pretty(holds)?)?;
+    std::fs::rename(&tmp, state_dir.join("holds.json"))?;
+    Ok(())
+}
+
+/// `dotlnx hold [name]`: hold the app at its current bundle; without a name, list held apps.
+pub fn hold(name: Option<&str>) -> Result<()> {
+    let state_dir = crate::state::state_dir()?;
+    let mut holds = load(&state_dir)?;
+    let Some(name) = name else {
+        if holds.is_empty() {
+            println!("no apps held");
+        }
+        for (profile, h) in &holds {
+            let changed = if h.fingerprint != fingerprint(&h.bundle, &exclude_of(&h.bundle)) {
+                ", bundle changed since"
+            } else {
+                ""
+            };
+            println!(
+                "{} ({}): {} (by {} at {}{})",
+                h.app,
+                profile,
+                h.bundle.display(),
+                h.by,
+                h.since,
+                changed
+            );
+        }
+        return Ok(());
+    };
+    grant::require_root("hold")?;
+    let (profile, bundle, config) = grant::resolve_app(name)?;
+    let hold = Hold {
+        app: config.app_id(),
+        fingerprint: fingerprint(&bundle, &config.exclude),
+        bundle,
+        by: grant::granted_by(),
+        since: crate::state::now(),
+    };
+    info!(app = %hold.app, bundle = %hold.bundle.display(), by = %hold.by, "held");
+    holds.insert(profile, hold);
+    save(&state_dir, &holds)
+}
+
+/// `dotlnx unhold <name>`: release the app; the next sync brings it up to date with its bundle.
+pub fn unhold(name: &str) -> Result<()> {
+    grant::require_root("unhold")?;
+    let state_dir = crate::state::state_dir()?;
+    let mut holds = load(&state_dir)?;
+    let (profile, _, _) = grant::resolve_app(name)?;
+    if holds.remove(&profile).is_none() {
+        println!("{} is not held", name);
+        return Ok(());
+    }
+    info!(app = %name, by = %grant::granted_by(), "released hold");
+    save(&state_dir, &holds)?;
+    crate::sync::run(false)?;
+    Ok(())
+}
+
+/// A bundle's `exclude` patterns; none when its config cannot be read.
+fn exclude_of(bundle: &Path) -> Vec<String> {
+    crate::config::load(bundle)
+        .map(|c| c.exclude)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hold_blocks_only_a_changed_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("App.lnx");
+        std::fs::create_dir_all(bundle.join("cache")).unwrap();
+        std::fs::write(
+            bundle.join("config.toml"),
+            "name = \"App\"\nexecutable = \"run.sh\"\n",
+        )
+        .unwrap();
+        let exclude = ["cache/**".to_string()];
+        let hold = Hold {
+            app: "App".into(),
+            bundle: bundle.clone(),
+            fingerprint: fingerprint(&bundle, &exclude),
+            by: "admin".into(),
+            since: 1,
+        };
+        assert!(!hold.blocks(&bundle, &exclude));
+        std::fs::write(bundle.join("cache/x"), "written by the app").unwrap();
+        assert!(!hold.blocks(&bundle, &exclude));
+        assert!(!hold.blocks(&dir.path().join("Other.lnx"), &exclude));
+        std::fs::write(bundle.join("run.sh"), "#!/bin/sh\n").unwrap();
+        assert!(hold.blocks(&bundle, &exclude));
+    }
+}
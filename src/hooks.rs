@@ -0,0 +1,201 @@
+//! Site hooks (`/etc/dotlnx/hooks`, or [hooks] dir): executables dotlnx runs on events, so sites
+//! can update a CMDB, refresh an icon store or send their own notifications without patching
+//! dotlnx. `pre-sync.d/` runs before every sync, `post-install.d/` after an app is installed or
+//! updated and `post-remove.d/` after one is removed (by sync or `dotlnx uninstall`).
+//!
+//! Each executable in the event's directory runs in file name order with the event as JSON on
+//! stdin. Hooks only observe: a hook that fails or outlives [`HOOK_TIMEOUT`] is logged (and
+//! killed) and dotlnx carries on. As root, hooks that are not owned by root or that others may
+//! modify are skipped, so they cannot be used to run code as root.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::sync::{Action, Change};
+
+/// Where hooks are read from unless [hooks] dir says otherwise.
+pub const DEFAULT_DIR: &str = "/etc/dotlnx/hooks";
+
+/// How long one hook may run before it is killed.
+const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// When hooks run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Event {
+    PreSync,
+    PostInstall,
+    PostRemove,
+}
+
+impl Event {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Event::PreSync => "pre-sync",
+            Event::PostInstall => "post-install",
+            Event::PostRemove => "post-remove",
+        }
+    }
+
+    /// The event that follows `action`.
+    pub fn after(action: Action) -> Self {
+        match action {
+            Action::Install | Action::Update => Event::PostInstall,
+            Action::Remove => Event::PostRemove,
+        }
+    }
+}
+
+/// What a hook gets on stdin.
+#[derive(Debug, Serialize)]
+pub struct Payload<'a> {
+    pub event: Event,
+    /// Unix time.
+    pub time: u64,
+    /// The user running dotlnx (root for the service).
+    pub user: String,
+    /// The app changed; absent for pre-sync.
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub change: Option<&'a Change>,
+}
+
+impl<'a> Payload<'a> {
+    pub fn new(event: Event, change: Option<&'a Change>) -> Self {
+        let user = match crate::bundle::is_root() {
+            true => "root".to_string(),
+            false => std::env::var("USER").unwrap_or_default(),
+        };
+        Self {
+            event,
+            time: crate::state::now(),
+            user,
+            change,
+        }
+    }
+}
+
+/// Executables for `event` under `dir`, in file name order. As root, hooks others could have
+/// written are left out with a warning.
+pub fn hooks(dir: &Path, event: Event, as_root: bool) -> Vec<PathBuf> {
+    let event_dir = dir.join(format!("{}.d", event.as_str()));
+    let Ok(entries) = std::fs::read_dir(&event_dir) else {
+        return Vec::new();
+    };
+    let mut out: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .filter(|e| !e.file_name().to_string_lossy().starts_with('.'))
+        .filter_map(|e| {
+            let path = e.path();
+            let meta = std::fs::metadata(&path).ok().filter(|m| m.is_file() && m.mode() & 0o111 != 0)?;
+            if as_root && (meta.uid() != 0 || meta.mode() & 0o022 != 0) {
+                warn!(hook = %path.display(), "skipping hook: not owned by root, or writable by others");
+                return None;
+            }
+            Some(path)
+        })
+        .collect();
+    out.sort();
+    out
+}
+
+/// Run the hooks for `payload`'s event. Failures are logged, never returned.
+pub fn fire(dir: &Path, payload: &Payload) {
+    let hooks = hooks(dir, payload.event, crate::bundle::is_root());
+    if hooks.is_empty() {
+        return;
+    }
+    let json = match serde_json::to_string(payload) {
+        Ok(json) => json + "\n",
+        Err(e) => {
+            warn!("hook payload: {}", e);
+            return;
+        }
+    };
+    for hook in hooks {
+        match run_hook(&hook, &json) {
+            Ok(()) => info!(hook = %hook.display(), event = payload.event.as_str(), "ran hook"),
+            Err(e) => {
+                warn!(hook = %hook.display(), event = payload.event.as_str(), "hook failed: {:#}", e)
+            }
+        }
+    }
+}
+
+/// Run one hook with `json` on stdin, at most [`HOOK_TIMEOUT`].
+fn run_hook(hook: &Path, json: &str) -> Result<()> {
+    let mut child = Command::new(hook)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .with_context(|| format!("start {}", hook.display()))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A hook that does not read its payload is fine.
+        let _ = stdin.write_all(json.as_bytes());
+    }
+    let deadline = Instant::now() + HOOK_TIMEOUT;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            anyhow::bail!("timed out after {}s", HOOK_TIMEOUT.as_secs());
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    if !status.success() {
+        anyhow::bail!("{}", status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn hooks_get_the_event_on_stdin() {
+        let dir = tempfile::tempdir().unwrap();
+        let event_dir = dir.path().join("post-install.d");
+        std::fs::create_dir(&event_dir).unwrap();
+        let out = dir.path().join("payload.json");
+        let script = format!("#!/bin/sh\ncat >> {:?}\n", out);
+        for name in ["20-cmdb", "10-icons", ".hidden"] {
+            std::fs::write(event_dir.join(name), &script).unwrap();
+            std::fs::set_permissions(event_dir.join(name), std::fs::Permissions::from_mode(0o755))
+                .unwrap();
+        }
+        std::fs::write(event_dir.join("README"), "not executable").unwrap();
+        assert_eq!(
+            hooks(dir.path(), Event::PostInstall, false),
+            [event_dir.join("10-icons"), event_dir.join("20-cmdb")]
+        );
+        assert!(hooks(dir.path(), Event::PreSync, false).is_empty());
+
+        let change = Change {
+            action: Action::Update,
+            app: "Notes".into(),
+            paths: vec!["/x/dotlnx-Notes.desktop".into()],
+        };
+        fire(
+            dir.path(),
+            &Payload::new(Event::after(change.action), Some(&change)),
+        );
+        let written = std::fs::read_to_string(&out).unwrap();
+        assert_eq!(written.lines().count(), 2);
+        let first: serde_json::Value =
+            serde_json::from_str(written.lines().next().unwrap()).unwrap();
+        assert_eq!(first["event"], "post-install");
+        assert_eq!(first["action"], "update");
+        assert_eq!(first["app"], "Notes");
+        assert_eq!(first["paths"][0], "/x/dotlnx-Notes.desktop");
+    }
+}
@@ -0,0 +1,314 @@
+//! Translated user-facing messages: CLI errors people read, the dialog shown when a menu launch
+//! fails and the notifications the service sends. Each message has a stable ID (`app-not-found`);
+//! the text comes from a Fluent catalog for the user's language (`locale/<lang>.ftl`, built in,
+//! or one installed in /usr/share/dotlnx/locale), falling back to English. Errors are logged with
+//! their ID (`message_id="app-not-found"`), so scripts can match it whatever the language.
+//!
+//! Only the part of Fluent the catalogs use is read: `id = text` messages, `{ $name }`
+//! placeables, indented continuation lines and comments. JSON output, the lines `dotlnx logs`
+//! reads back from launch logs and the service's own log stay in English.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::asuser;
+
+/// Catalogs compiled into dotlnx.
+const BUILT_IN: &[(&str, &str)] = &[
+    ("en", include_str!("../locale/en.ftl")),
+    ("de", include_str!("../locale/de.ftl")),
+];
+
+/// Where packages and administrators add catalogs (`<lang>.ftl`), which take precedence.
+const CATALOG_DIR: &str = "/usr/share/dotlnx/locale";
+
+/// A user's language preference: catalog names to try, best first (`de_AT`, `de`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lang(Vec<String>);
+
+impl Lang {
+    /// English, for text that is parsed back or shared (logs).
+    pub fn english() -> Self {
+        Lang(vec!["en".into()])
+    }
+
+    /// The language of this process: LANGUAGE (a colon-separated list), else LC_ALL,
+    /// LC_MESSAGES or LANG, as gettext picks it.
+    pub fn from_env() -> Self {
+        let var = |k: &str| std::env::var(k).ok().filter(|v| !v.is_empty());
+        let locale = var("LC_ALL")
+            .or_else(|| var("LC_MESSAGES"))
+            .or_else(|| var("LANG"));
+        // LANGUAGE is ignored under the C locale, like gettext does.
+        let language = var("LANGUAGE").filter(|_| locale.as_deref().is_some_and(|l| !is_c(l)));
+        Self::parse(language.or(locale).as_deref().unwrap_or("C"))
+    }
+
+    /// The language `username` set for their session: `~/.config/locale.conf`, else
+    /// AccountsService, else the system locale. For notifications the service sends them.
+    /// The file in their home is read as them (see `asuser`), so a symlink there cannot show root
+    /// another file.
+    pub fn of_user(username: &str) -> Self {
+        let home = nix::unistd::User::from_name(username)
+            .ok()
+            .flatten()
+            .map(|u| u.dir);
+        let own = home.and_then(|h| {
+            let req = asuser::Request::ReadFile {
+                dir: h.join(".config"),
+                name: "locale.conf".into(),
+            };
+            asuser::perform(Some(username), &req).ok().flatten()
+        });
+        let sources = [
+            (
+                Path::new("/var/lib/AccountsService/users").join(username),
+                &["Language"][..],
+            ),
+            (
+                Path::new("/etc/locale.conf").to_path_buf(),
+                &["LC_MESSAGES", "LANG"][..],
+            ),
+            (
+                Path::new("/etc/default/locale").to_path_buf(),
+                &["LC_MESSAGES", "LANG"][..],
+            ),
+        ];
+        let texts = own
+            .map(|text| (text, &["LC_MESSAGES", "LANG"][..]))
+            .into_iter()
+            .chain(
+                sources
+                    .into_iter()
+                    .filter_map(|(file, keys)| Some((std::fs::read_to_string(file).ok()?, keys))),
+            );
+        for (text, keys) in texts {
+            if let Some(value) = keys.iter().find_map(|k| assignment(&text, k)) {
+                return Self::parse(&value);
+            }
+        }
+        Self::english()
+    }
+
+    /// `de_AT.UTF-8@euro:fr` -> de_AT, de, fr; C and POSIX mean English.
+    fn parse(value: &str) -> Self {
+        let mut tags = Vec::new();
+        for locale in value.split(':').filter(|l| !l.is_empty()) {
+            if is_c(locale) {
+                tags.push("en".to_string());
+                continue;
+            }
+            let name = locale.split(['.', '@']).next().unwrap_or_default();
+            tags.push(name.to_string());
+            if let Some((language, _)) = name.split_once('_') {
+                tags.push(language.to_string());
+            }
+        }
+        tags.retain(|t| !t.is_empty() && t.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'));
+        tags.dedup();
+        Lang(tags)
+    }
+}
+
+fn is_c(locale: &str) -> bool {
+    matches!(locale.split('.').next(), Some("C" | "POSIX"))
+}
+
+/// The value of `key=value` in a locale.conf or AccountsService file (`[User]` section).
+fn assignment(text: &str, key: &str) -> Option<String> {
+    text.lines().find_map(|line| {
+        let (k, v) = line.trim().split_once('=')?;
+        (k.trim() == key)
+            .then(|| v.trim().trim_matches('"').to_string())
+            .filter(|v| !v.is_empty())
+    })
+}
+
+/// A message catalog: ID -> text with `{ $name }` placeables.
+type Catalog = HashMap<String, String>;
+
+/// Read a Fluent catalog. Lines that are not messages (terms, attributes, selectors) are skipped.
+fn parse_catalog(text: &str) -> Catalog {
+    let mut catalog = Catalog::new();
+    let mut current: Option<String> = None;
+    for line in text.lines() {
+        if line.starts_with([' ', '\t']) && !line.trim().is_empty() {
+            if let Some(value) = current.as_ref().and_then(|id| catalog.get_mut(id)) {
+                if !value.is_empty() {
+                    value.push('\n');
+                }
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+        current = None;
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let Some((id, value)) = line.split_once('=') else {
+            continue;
+        };
+        let id = id.trim();
+        if id.is_empty()
+            || !id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            continue;
+        }
+        catalog.insert(id.to_string(), value.trim().to_string());
+        current = Some(id.to_string());
+    }
+    catalog
+}
+
+/// The catalog for `tag`: an installed one, else the built-in one.
+fn catalog(tag: &str) -> Option<&'static Catalog> {
+    static CACHE: OnceLock<std::sync::Mutex<HashMap<String, Option<&'static Catalog>>>> =
+        OnceLock::new();
+    let mut cache = CACHE
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    *cache.entry(tag.to_string()).or_insert_with(|| {
+        let text = std::fs::read_to_string(Path::new(CATALOG_DIR).join(format!("{}.ftl", tag)))
+            .ok()
+            .or_else(|| {
+                BUILT_IN
+                    .iter()
+                    .find(|(t, _)| *t == tag)
+                    .map(|(_, text)| text.to_string())
+            })?;
+        Some(Box::leak(Box::new(parse_catalog(&text))))
+    })
+}
+
+/// Replace `{ $name }` with its value; unknown names are left as they are.
+fn format(pattern: &str, args: &[(&str, String)]) -> String {
+    let mut out = String::new();
+    let mut rest = pattern;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        let inner = rest[start + 1..start + len].trim();
+        out.push_str(&rest[..start]);
+        match inner
+            .strip_prefix('$')
+            .and_then(|name| args.iter().find(|(k, _)| *k == name))
+        {
+            Some((_, value)) => out.push_str(value),
+            None => out.push_str(&rest[start..=start + len]),
+        }
+        rest = &rest[start + len + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Message `id` in `lang`, falling back to English and then to the ID itself.
+pub fn tr_in(lang: &Lang, id: &str, args: &[(&str, String)]) -> String {
+    let pattern = lang
+        .0
+        .iter()
+        .map(String::as_str)
+        .chain(["en"])
+        .filter_map(|tag| catalog(tag))
+        .find_map(|c| c.get(id));
+    match pattern {
+        Some(p) => format(p, args),
+        None => id.to_string(),
+    }
+}
+
+/// This process's language.
+pub fn lang() -> &'static Lang {
+    static LANG: OnceLock<Lang> = OnceLock::new();
+    LANG.get_or_init(Lang::from_env)
+}
+
+/// Message `id` in this process's language.
+pub fn tr(id: &str, args: &[(&str, String)]) -> String {
+    tr_in(lang(), id, args)
+}
+
+/// An error whose text is a translated message. `main` logs its ID along with the text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub id: &'static str,
+    args: Vec<(&'static str, String)>,
+}
+
+impl Message {
+    pub fn new(id: &'static str, args: &[(&'static str, String)]) -> Self {
+        Message {
+            id,
+            args: args.to_vec(),
+        }
+    }
+
+    /// The text in English, for logs.
+    pub fn english(&self) -> String {
+        tr_in(&Lang::english(), self.id, &self.args)
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&tr(self.id, &self.args))
+    }
+}
+
+impl std::error::Error for Message {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locale_picks_the_catalog_and_falls_back_to_english() {
+        assert_eq!(Lang::parse("de_AT.UTF-8@euro:fr").0, ["de_AT", "de", "fr"]);
+        assert_eq!(Lang::parse("C.UTF-8").0, ["en"]);
+        let args = [("name", "Nope".to_string())];
+        assert_eq!(
+            tr_in(&Lang::english(), "app-not-found", &args),
+            "app not found: Nope"
+        );
+        assert_eq!(
+            tr_in(&Lang::parse("de_AT.UTF-8"), "app-not-found", &args),
+            "App nicht gefunden: Nope"
+        );
+        assert_eq!(
+            tr_in(&Lang::parse("xx_YY"), "app-not-found", &args),
+            "app not found: Nope"
+        );
+        assert_eq!(
+            tr_in(&Lang::english(), "no-such-message", &args),
+            "no-such-message"
+        );
+        assert_eq!(
+            assignment("[User]\nLanguage=pt_BR.UTF-8\n", "Language").as_deref(),
+            Some("pt_BR.UTF-8")
+        );
+    }
+
+    #[test]
+    fn catalogs_parse_and_only_translate_english_ids() {
+        let english = parse_catalog(BUILT_IN[0].1);
+        let continued = parse_catalog("a = one { $x }\n    two\n# note\nb = {$y}!\n");
+        assert_eq!(format(&continued["a"], &[("x", "1".into())]), "one 1\ntwo");
+        assert_eq!(format(&continued["b"], &[]), "{$y}!");
+        for (tag, text) in BUILT_IN {
+            for id in parse_catalog(text).keys() {
+                assert!(
+                    english.contains_key(id),
+                    "{}.ftl: {} is not in en.ftl",
+                    tag,
+                    id
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! `dotlnx info <name>`: everything about one app in one place, for working out why it does not
+//! launch, or does not launch confined. The bundle is found the way `dotlnx run` finds it, and
+//! the launch settings shown are the effective ones (admin override and site `[run]` applied).
+
+use anyhow::Result;
+use serde::Serialize;
+use std::path::PathBuf;
+
+use crate::apparmor;
+use crate::bundle;
+use crate::desktop;
+use crate::i18n;
+use crate::open;
+use crate::output::{self, Style};
+use crate::paths::Paths;
+use crate::runenv;
+use crate::settings;
+use crate::validate;
+
+/// One app, as `dotlnx run` would start it.
+#[derive(Debug, Serialize)]
+pub struct Info {
+    pub name: String,
+    /// App ID (`vendor.name`, or the name).
+    pub id: String,
+    /// "user" or "system".
+    pub tier: String,
+    pub bundle: PathBuf,
+    /// The executable `executable` resolves to (the newest match for a pattern).
+    pub executable: PathBuf,
+    pub executable_exists: bool,
+    pub args: Vec<String>,
+    /// Variables dotlnx sets for the app (site `[run] env`, the bundle's `env`, accessibility
+    /// bridges), in the order they are set; PATH is left out.
+    pub env: Vec<String>,
+    /// The app starts from a minimal environment ([security] clear_env).
+    pub clear_env: bool,
+    /// The command the menu entry runs.
+    pub launch: Vec<String>,
+    /// The menu entry goes through `dotlnx run` (a launch setting only the runner applies).
+    pub via_runner: bool,
+    /// An admin override in the overrides dir applies.
+    pub overridden: bool,
+    pub confined: bool,
+    /// Profile name; None when the app runs unconfined.
+    pub profile: Option<String>,
+    pub profile_path: Option<PathBuf>,
+    pub profile_installed: bool,
+    pub profile_loaded: bool,
+    /// aa-exec is installed, so menu entries can start apps confined.
+    pub apparmor_available: bool,
+    pub desktop_file: PathBuf,
+    pub desktop_installed: bool,
+    /// The installed menu entry, or the one sync would write when it is not installed.
+    pub desktop: String,
+    /// None when the bundle is valid, else why `dotlnx validate` rejects it.
+    pub validation_error: Option<String>,
+    /// What stands in the way of a confined launch, in plain words.
+    pub problems: Vec<String>,
+}
+
+pub fn run(name: &str, json: bool) -> Result<()> {
+    let settings = settings::load()?;
+    let paths = Paths::from_settings(&settings.paths);
+    let info = collect(&settings, &paths, name)?;
+    if json {
+        return output::json(&info);
+    }
+    print(&info);
+    Ok(())
+}
+
+fn collect(settings: &settings::Settings, paths: &Paths, name: &str) -> Result<Info> {
+    let Some((dir, mut cfg, is_user_tier)) = bundle::resolve_bundle_by_name(paths, name)? else {
+        anyhow::bail!(i18n::Message::new(
+            "app-not-found",
+            &[("name", name.to_string())]
+        ));
+    };
+    let overridden = crate::policy::apply_override(&mut cfg, &settings.apparmor.overrides_dir)?;
+    settings.run.apply(&mut cfg);
+    let id = cfg.app_id();
+    // The user sync names the profile after: the owner of the Applications dir it found.
+    let username = match is_user_tier {
+        true => bundle::user_tier_entries(paths)?
+            .into_iter()
+            .find(|(apps, _, _)| dir.parent() == Some(apps.as_path()))
+            .map(|(_, _, user)| user)
+            .or_else(|| bundle::username_from_bundle_path(&dir)),
+        false => None,
+    };
+    let confined = cfg.security.as_ref().is_none_or(|s| s.confine);
+    let profile = confined.then(|| paths.profile_name(username.as_deref(), &id));
+    let executable = bundle::resolve_executable(&dir, &cfg.executable);
+    let env = runenv::LaunchEnv::build(&cfg, &dir, std::iter::empty());
+    let desktop_file = open::desktop_file(paths, &cfg, is_user_tier)?;
+    let installed_entry = std::fs::read_to_string(&desktop_file).ok();
+    let validation_error = validate::validate_bundle(&dir)
+        .err()
+        .map(|e| format!("{:#}", e));
+    let apparmor_available = apparmor::is_available();
+    let mut info = Info {
+        name: cfg.name.clone(),
+        tier: if is_user_tier { "user" } else { "system" }.into(),
+        executable_exists: executable.exists(),
+        args: cfg.args.clone(),
+        env: env
+            .vars
+            .iter()
+            .filter(|(k, _)| k != "PATH")
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect(),
+        clear_env: env.clear,
+        launch: desktop::launch_argv(&cfg, &dir, profile.as_deref()),
+        via_runner: cfg.needs_runner(),
+        overridden,
+        confined,
+        profile_path: profile.as_ref().map(|p| paths.profile_path(p)),
+        profile_installed: profile
+            .as_ref()
+            .is_some_and(|p| paths.profile_path(p).is_file()),
+        profile_loaded: profile
+            .as_ref()
+            .is_some_and(|p| apparmor::is_loaded(paths, p)),
+        apparmor_available,
+        desktop_installed: installed_entry.is_some(),
+        desktop: installed_entry
+            .unwrap_or_else(|| desktop::generate_desktop(&cfg, &dir, profile.as_deref())),
+        problems: Vec::new(),
+        validation_error,
+        desktop_file,
+        executable,
+        profile,
+        bundle: dir,
+        id,
+    };
+    info.problems = problems(&info);
+    Ok(info)
+}
+
+/// Why the app may not launch, or not confined, judged from what [`collect`] found.
+fn problems(info: &Info) -> Vec<String> {
+    let mut out = Vec::new();
+    if let Some(ref e) = info.validation_error {
+        out.push(format!(
+            "the bundle is invalid, so sync does not install it: {}",
+            e
+        ));
+    }
+    if !info.executable_exists {
+        out.push(format!(
+            "the executable {} does not exist",
+            info.executable.display()
+        ));
+    }
+    if !info.desktop_installed {
+        out.push(
+            "the menu entry is not installed: run `dotlnx sync`, or check the service log".into(),
+        );
+    }
+    if !info.confined {
+        out.push("[security] confine = false: the app runs without an AppArmor profile".into());
+        return out;
+    }
+    if !info.apparmor_available {
+        out.push("aa-exec is not installed, so menu entries start the app unconfined".into());
+    }
+    if !info.profile_installed {
+        out.push("the profile is not installed: sync writes it (as root for the system tier, or with the service running)".into());
+    } else if !info.profile_loaded {
+        out.push(
+            "the profile is not loaded in the kernel (or this user cannot tell): check `sudo aa-status` and the service log".into(),
+        );
+    }
+    if info.desktop_installed
+        && !info.via_runner
+        && !info.desktop.lines().any(|l| l.starts_with("Exec=aa-exec "))
+    {
+        out.push("the installed menu entry does not start the app through aa-exec: it was written when AppArmor was unavailable; run `dotlnx sync`".into());
+    }
+    out
+}
+
+fn print(info: &Info) {
+    let flag = |b: bool, yes: &str, no: &str| {
+        if b {
+            (yes.to_string(), Style::Good)
+        } else {
+            (no.to_string(), Style::Bad)
+        }
+    };
+    let confinement = match &info.profile {
+        None => ("unconfined".to_string(), Style::Warn),
+        Some(p) => (
+            format!(
+                "apparmor, profile {} ({}, {})",
+                p,
+                flag(info.profile_installed, "installed", "not installed").0,
+                flag(info.profile_loaded, "loaded", "not loaded").0
+            ),
+            if info.profile_loaded {
+                Style::Good
+            } else {
+                Style::Warn
+            },
+        ),
+    };
+    let executable = flag(info.executable_exists, "found", "missing");
+    let entry = flag(info.desktop_installed, "installed", "not installed");
+    let mut rows = vec![
+        ("Name", info.name.clone(), Style::Plain),
+        ("ID", info.id.clone(), Style::Plain),
+        ("Tier", info.tier.clone(), Style::Plain),
+        ("Bundle", info.bundle.display().to_string(), Style::Plain),
+        (
+            "Executable",
+            format!("{} ({})", info.executable.display(), executable.0),
+            executable.1,
+        ),
+        ("Args", info.args.join(" "), Style::Plain),
+        ("Environment", info.env.join(" "), Style::Plain),
+        (
+            "Clear env",
+            if info.clear_env { "yes" } else { "no" }.into(),
+            Style::Plain,
+        ),
+        ("Launch", info.launch.join(" "), Style::Plain),
+        ("Confinement", confinement.0, confinement.1),
+    ];
+    if let Some(ref path) = info.profile_path {
+        rows.push(("Profile file", path.display().to_string(), Style::Plain));
+    }
+    rows.extend([
+        (
+            "Override",
+            if info.overridden { "yes" } else { "no" }.into(),
+            Style::Plain,
+        ),
+        match info.validation_error {
+            Some(ref e) => ("Validation", e.clone(), Style::Bad),
+            None => ("Validation", "ok".into(), Style::Good),
+        },
+        (
+            "Menu entry",
+            format!("{} ({})", info.desktop_file.display(), entry.0),
+            entry.1,
+        ),
+    ]);
+    output::fields(&rows);
+    match info.desktop_installed {
+        true => println!(
+            "\n{}",
+            output::paint(&format!("{}:", info.desktop_file.display()), Style::Heading)
+        ),
+        false => println!(
+            "\n{}",
+            output::paint("Menu entry sync would write:", Style::Heading)
+        ),
+    }
+    print!("{}", info.desktop);
+    if !info.problems.is_empty() {
+        println!("\n{}", output::paint("Problems:", Style::Heading));
+        for p in &info.problems {
+            println!("  - {}", output::paint(p, Style::Warn));
+        }
+    }
+}
@@ -0,0 +1,379 @@
+//! `dotlnx install <bundle.lnx>...`: copy bundles (e.g. from Downloads) into the Applications
+//! folder, or unpack packed ones (`.lnx.tar.zst`, see `pack`). Each bundle is validated first; the watcher then installs it like any dropped-in
+//! folder. Without a running service, a sync is run here so the menu entry appears right away.
+//! Installing a bundle that is already there with the same files changes nothing, so `--check`
+//! and config management can call it again and again.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::bundle;
+use crate::bundler;
+use crate::config;
+use crate::kiosk;
+use crate::pack;
+use crate::paths;
+use crate::progress::{self, Progress, ProgressArgs};
+use crate::scan;
+use crate::settings::{self, Settings};
+use crate::sync::{self, Action, Change};
+use crate::validate;
+use crate::watch;
+
+/// Entry point for `dotlnx install`. As root, bundles go to /Applications (system tier). With
+/// `check`, nothing is copied: the bundles are only checked. Returns the bundles that were (or
+/// would be) installed; one already installed with the same files is not a change.
+pub fn run(bundles: &[PathBuf], check: bool, progress_args: ProgressArgs) -> Result<Vec<Change>> {
+    let paths = paths::load()?;
+    let apps_dir = if bundle::is_root() {
+        paths.system_apps
+    } else {
+        paths.user_apps
+    };
+    let settings = settings::load()?;
+    let mut changes = Vec::new();
+    for path in bundles {
+        let progress = Progress::new(progress_args, "install", progress::tree_size(path));
+        progress.set_item(&path.file_name().unwrap_or_default().to_string_lossy());
+        changes.extend(install_one(path, &apps_dir, &settings, check, &progress)?);
+        progress.finish();
+    }
+    if !check && !changes.is_empty() && !watch::service_active() {
+        sync::run(false)?;
+    }
+    Ok(changes)
+}
+
+fn installed(cfg: &config::Config, dest: PathBuf) -> Change {
+    Change {
+        action: Action::Install,
+        app: cfg.app_id(),
+        paths: vec![dest],
+    }
+}
+
+/// Validate (and with [scan] set, scan) `src` and copy it into `apps_dir`, unless `check`; a
+/// packed bundle is unpacked there instead. None when the same bundle is already there.
+fn install_one(
+    src: &Path,
+    apps_dir: &Path,
+    settings: &Settings,
+    check: bool,
+    progress: &Progress,
+) -> Result<Option<Change>> {
+    if pack::is_packed_bundle(src) && src.is_file() {
+        return install_archive(src, apps_dir, settings, check);
+    }
+    if !bundle::is_lnx_bundle(src) {
+        anyhow::bail!("not a .lnx bundle: {}", src.display());
+    }
+    let Some((dest, cfg)) = self::check(src, apps_dir, settings)? else {
+        return Ok(None);
+    };
+    if check {
+        return Ok(Some(installed(&cfg, dest)));
+    }
+    std::fs::create_dir_all(apps_dir).with_context(|| format!("create {}", apps_dir.display()))?;
+    let dir_name = dest.file_name().unwrap_or_default();
+    // Copy under a name the watcher ignores, then rename: sync never sees a half-copied bundle.
+    let tmp = apps_dir.join(format!(".{}.dotlnx-tmp", dir_name.to_string_lossy()));
+    if tmp.exists() {
+        std::fs::remove_dir_all(&tmp)?;
+    }
+    let copied =
+        bundler::copy_tree(src, &tmp, progress).and_then(|_| Ok(std::fs::rename(&tmp, &dest)?));
+    if let Err(e) = copied {
+        let _ = std::fs::remove_dir_all(&tmp);
+        return Err(e).with_context(|| format!("copy {} to {}", src.display(), dest.display()));
+    }
+    info!(app = %cfg.name, bundle = %dest.display(), "installed");
+    Ok(Some(installed(&cfg, dest)))
+}
+
+/// Unpack a packed bundle into a hidden dir of `apps_dir` (a temp dir with `check`), check it
+/// like a bundle folder, then rename it into place.
+fn install_archive(
+    src: &Path,
+    apps_dir: &Path,
+    settings: &Settings,
+    check: bool,
+) -> Result<Option<Change>> {
+    let temp = check
+        .then(|| tempfile::Builder::new().prefix("dotlnx-install-").tempdir())
+        .transpose()?;
+    let staging = match &temp {
+        Some(temp) => temp.path().to_path_buf(),
+        None => {
+            let staging = apps_dir.join(format!(
+                ".{}.dotlnx-tmp",
+                src.file_name().unwrap_or_default().to_string_lossy()
+            ));
+            if staging.exists() {
+                std::fs::remove_dir_all(&staging)?;
+            }
+            std::fs::create_dir_all(&staging)
+                .with_context(|| format!("create {}", staging.display()))?;
+            staging
+        }
+    };
+    let installed = pack::unpack(src, &staging).and_then(|bundle| {
+        let Some((dest, cfg)) = self::check(&bundle, apps_dir, settings)? else {
+            return Ok(None);
+        };
+        if !check {
+            std::fs::rename(&bundle, &dest)?;
+            info!(app = %cfg.name, bundle = %dest.display(), archive = %src.display(), "installed");
+        }
+        Ok(Some(installed(&cfg, dest)))
+    });
+    let _ = std::fs::remove_dir_all(&staging);
+    installed
+}
+
+/// Everything that must hold before `src` goes into `apps_dir`: a valid bundle the site allows,
+/// clean by [scan], with no name or profile clash. Returns where it goes and its config; None
+/// when a bundle with the same files is already there.
+fn check(
+    src: &Path,
+    apps_dir: &Path,
+    settings: &Settings,
+) -> Result<Option<(PathBuf, config::Config)>> {
+    validate::validate_bundle(src)?;
+    let cfg = config::load(src)?;
+    if let Some(reason) = kiosk::refusal(&settings.kiosk, bundle::is_root(), &cfg) {
+        anyhow::bail!("refusing to install {}: {}", src.display(), reason);
+    }
+    if scan::enabled(&settings.scan) {
+        let findings = scan::scan(&settings.scan, src)?;
+        if !findings.is_empty() {
+            anyhow::bail!(
+                "refusing to install {}: {}",
+                src.display(),
+                findings.join("; ")
+            );
+        }
+    }
+    let dir_name = src
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("bad bundle path: {}", src.display()))?;
+    let dest = apps_dir.join(dir_name);
+    if dest.exists() {
+        if same_files(src, &dest).unwrap_or(false) {
+            info!(app = %cfg.name, bundle = %dest.display(), "already installed");
+            return Ok(None);
+        }
+        anyhow::bail!("{} already exists; remove it first", dest.display());
+    }
+    let existing: Vec<(PathBuf, config::Config)> = bundle::discover_lnx_dirs(apps_dir)
+        .into_iter()
+        .filter_map(|d| config::load(&d).ok().map(|c| (d, c)))
+        .collect();
+    if let Some((dir, _)) = existing.iter().find(|(_, c)| c.app_id() == cfg.app_id()) {
+        anyhow::bail!(
+            "an app named {:?} is already installed from {}",
+            cfg.app_id(),
+            dir.display()
+        );
+    }
+    // A different name can still clash on the AppArmor profile name.
+    let mut claims = validate::NameClaims::default();
+    for (dir, c) in &existing {
+        let _ = claims.claim(&c.app_id(), dir);
+    }
+    claims.claim(&cfg.app_id(), &dest)?;
+    Ok(Some((dest, cfg)))
+}
+
+/// True when `a` and `b` hold the same names, contents, symlink targets and execute bits.
+fn same_files(a: &Path, b: &Path) -> Result<bool> {
+    let walk = |root: &Path| {
+        walkdir::WalkDir::new(root)
+            .follow_links(false)
+            .sort_by_file_name()
+            .into_iter()
+    };
+    let (mut left, mut right) = (walk(a), walk(b));
+    loop {
+        let (x, y) = match (left.next().transpose()?, right.next().transpose()?) {
+            (None, None) => return Ok(true),
+            (Some(x), Some(y)) => (x, y),
+            _ => return Ok(false),
+        };
+        let (tx, ty) = (x.file_type(), y.file_type());
+        if x.path().strip_prefix(a)? != y.path().strip_prefix(b)?
+            || (tx.is_dir(), tx.is_file(), tx.is_symlink())
+                != (ty.is_dir(), ty.is_file(), ty.is_symlink())
+        {
+            return Ok(false);
+        }
+        let same = if tx.is_symlink() {
+            std::fs::read_link(x.path())? == std::fs::read_link(y.path())?
+        } else if tx.is_file() {
+            let (mx, my) = (x.metadata()?, y.metadata()?);
+            is_executable(&mx) == is_executable(&my)
+                && mx.len() == my.len()
+                && std::fs::read(x.path())? == std::fs::read(y.path())?
+        } else {
+            true
+        };
+        if !same {
+            return Ok(false);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(meta: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_meta: &std::fs::Metadata) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_bundle(root: &Path, dir: &str, name: &str) -> PathBuf {
+        let b = root.join(dir);
+        std::fs::create_dir_all(b.join("bin")).unwrap();
+        std::fs::write(b.join("bin/app"), "#!/bin/sh\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(b.join("bin/app"), std::fs::Permissions::from_mode(0o755))
+                .unwrap();
+        }
+        std::fs::write(
+            b.join("config.toml"),
+            format!("name = \"{}\"\nexecutable = \"bin/app\"\n", name),
+        )
+        .unwrap();
+        b
+    }
+
+    #[test]
+    fn install_copies_and_refuses_duplicates() {
+        let downloads = tempfile::tempdir().unwrap();
+        let apps = tempfile::tempdir().unwrap();
+        let src = make_bundle(downloads.path(), "Tool.lnx", "Tool");
+        let install = |src: &Path, check| {
+            install_one(
+                src,
+                apps.path(),
+                &Settings::default(),
+                check,
+                &Progress::hidden(),
+            )
+        };
+        let planned = install(&src, true).unwrap().unwrap();
+        assert_eq!(
+            std::fs::read_dir(apps.path()).unwrap().count(),
+            0,
+            "--check copies nothing"
+        );
+        let change = install(&src, false).unwrap().unwrap();
+        assert_eq!(
+            (change.action, &change.app),
+            (Action::Install, &"Tool".to_string())
+        );
+        assert_eq!(change.paths, planned.paths);
+        let dest = &change.paths[0];
+        assert_eq!(dest, &apps.path().join("Tool.lnx"));
+        assert!(dest.join("bin/app").is_file());
+        assert!(src.is_dir(), "the original is kept");
+        assert!(!apps.path().join(".Tool.lnx.dotlnx-tmp").exists());
+        // The same bundle again is not a change; a different one of that name is refused.
+        assert!(install(&src, true).unwrap().is_none());
+        assert!(install(&src, false).unwrap().is_none());
+        std::fs::write(src.join("README"), "new").unwrap();
+        let err = install(&src, false).unwrap_err();
+        assert!(err.to_string().contains("already exists"));
+        let renamed = make_bundle(downloads.path(), "Tool2.lnx", "Tool");
+        let err = install(&renamed, false).unwrap_err();
+        assert!(err.to_string().contains("already installed"));
+    }
+
+    #[test]
+    fn install_unpacks_packed_bundles() {
+        let downloads = tempfile::tempdir().unwrap();
+        let apps = tempfile::tempdir().unwrap();
+        let src = make_bundle(downloads.path(), "Tool.lnx", "Tool");
+        let archive = downloads.path().join("Tool.lnx.tar.gz");
+        let out = std::fs::File::create(&archive).unwrap();
+        pack::write_archive(
+            &src,
+            &[],
+            0,
+            pack::Compression::Gzip,
+            out,
+            &Progress::hidden(),
+        )
+        .unwrap();
+        std::fs::remove_dir_all(&src).unwrap();
+        let install = |check| {
+            install_one(
+                &archive,
+                apps.path(),
+                &Settings::default(),
+                check,
+                &Progress::hidden(),
+            )
+        };
+        assert!(install(true).unwrap().is_some());
+        assert_eq!(
+            std::fs::read_dir(apps.path()).unwrap().count(),
+            0,
+            "--check unpacks elsewhere"
+        );
+        let dest = install(false).unwrap().unwrap().paths.remove(0);
+        assert_eq!(dest, apps.path().join("Tool.lnx"));
+        assert!(dest.join("bin/app").is_file());
+        assert_eq!(
+            std::fs::read_dir(apps.path()).unwrap().count(),
+            1,
+            "nothing left but the bundle"
+        );
+        assert!(install(false).unwrap().is_none());
+        std::fs::write(dest.join("README"), "edited").unwrap();
+        let err = install(false).unwrap_err();
+        assert!(err.to_string().contains("already exists"));
+        assert_eq!(std::fs::read_dir(apps.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn install_rejects_non_bundles() {
+        let downloads = tempfile::tempdir().unwrap();
+        let apps = tempfile::tempdir().unwrap();
+        let plain = downloads.path().join("notes");
+        std::fs::create_dir(&plain).unwrap();
+        assert!(install_one(
+            &plain,
+            apps.path(),
+            &Settings::default(),
+            false,
+            &Progress::hidden()
+        )
+        .is_err());
+        let broken = downloads.path().join("Broken.lnx");
+        std::fs::create_dir(&broken).unwrap();
+        std::fs::write(
+            broken.join("config.toml"),
+            "name = \"Broken\"\nexecutable = \"bin/missing\"\n",
+        )
+        .unwrap();
+        assert!(install_one(
+            &broken,
+            apps.path(),
+            &Settings::default(),
+            false,
+            &Progress::hidden()
+        )
+        .is_err());
+        assert!(!apps.path().join("Broken.lnx").exists());
+    }
+}
@@ -0,0 +1,483 @@
+//! Files an app installs into the desktop's shared data directories besides its menu entry:
+//!
+//! - `share/glib-2.0/schemas/*.gschema.xml` (and `*.gschema.override`) shipped in the bundle's
+//!   own `share/` tree: GSettings schemas, which many GNOME apps abort without. Installed into the
+//!   tier's `glib-2.0/schemas` and compiled.
+//! - `[[thumbnailers]]` in config.toml: a `.thumbnailer` entry in `thumbnailers/`, so file
+//!   managers preview the app's file types with a program from the bundle.
+//! - `[search_provider]`: a GNOME Shell search provider in `gnome-shell/search-providers/`, and
+//!   a D-Bus service file in `dbus-1/services/` that starts the app when Shell searches.
+//!
+//! Files go into the tier's data dir (the parent of its .desktop dir: ~/.local/share or
+//! /usr/share), named `<prefix><app>.<file>`, and are recorded in state (`integrations`) so
+//! removing the app removes exactly what sync wrote. As root, a user bundle's files are read and
+//! written as that user (see `asuser`).
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::asuser::{self, Request};
+use crate::config::Config;
+use crate::desktop;
+use crate::paths::Paths;
+
+/// GSettings schemas, under a data dir and under a bundle's `share/`.
+pub const SCHEMAS_DIR: &str = "glib-2.0/schemas";
+/// Thumbnailer entries, under a data dir.
+const THUMBNAILERS_DIR: &str = "thumbnailers";
+/// GNOME Shell search providers, under a data dir.
+const SEARCH_PROVIDERS_DIR: &str = "gnome-shell/search-providers";
+/// D-Bus session services (activation files), under a data dir.
+const DBUS_SERVICES_DIR: &str = "dbus-1/services";
+
+/// A file to install: where, and what it holds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct File {
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+/// State key of an app's integration files in a tier: the path of its .desktop file.
+pub fn key(paths: &Paths, desktop_dir: &Path, app: &str) -> String {
+    desktop_dir
+        .join(paths.desktop_file_name(desktop_dir, app))
+        .display()
+        .to_string()
+}
+
+/// Files the app asks for in the tier whose .desktop dir is `desktop_dir`: its config's
+/// thumbnailers and search provider, and what its bundle's `share/` holds. Programs are started
+/// under `profile` when set, as the menu entry starts the app.
+pub fn wanted(
+    paths: &Paths,
+    cfg: &Config,
+    bundle: &Path,
+    desktop_dir: &Path,
+    profile: Option<&str>,
+    as_user: Option<&str>,
+) -> Result<Vec<File>> {
+    let Some(data_dir) = desktop_dir.parent() else {
+        return Ok(Vec::new());
+    };
+    let prefix = format!("{}{}.", paths.desktop_prefix(desktop_dir), cfg.app_id());
+    let mut files = Vec::new();
+    for (i, thumbnailer) in cfg.thumbnailers.iter().enumerate() {
+        let program = bundle.join(&thumbnailer.executable);
+        let program = crate::fscache::canonicalize(&program).unwrap_or(program);
+        let mut exec: Vec<String> = profile_argv(&program, profile)
+            .iter()
+            .chain(&thumbnailer.args)
+            .map(|a| match a.as_str() {
+                "%i" | "%u" | "%o" | "%s" => a.clone(),
+                _ => desktop::escape_for_exec_arg(a),
+            })
+            .collect();
+        if thumbnailer.args.is_empty() {
+            exec.extend(["%i", "%o", "%s"].map(String::from));
+        }
+        let mut mime_types = thumbnailer.mime_types.join(";");
+        mime_types.push(';');
+        files.push(File {
+            path: data_dir
+                .join(THUMBNAILERS_DIR)
+                .join(format!("{}{}.thumbnailer", prefix, i)),
+            contents: format!(
+                "[Thumbnailer Entry]\nTryExec={}\nExec={}\nMimeType={}\n",
+                desktop::escape_desktop_value(&program.display().to_string()),
+                exec.join(" "),
+                desktop::escape_desktop_value(&mime_types)
+            ),
+        });
+    }
+    if let Some(ref provider) = cfg.search_provider {
+        files.push(File {
+            path: data_dir
+                .join(SEARCH_PROVIDERS_DIR)
+                .join(format!("{}search-provider.ini", prefix)),
+            contents: format!(
+                "[Shell Search Provider]\nDesktopId={}\nBusName={}\nObjectPath={}\nVersion=2\n",
+                paths.desktop_file_name(desktop_dir, &cfg.app_id()),
+                provider.bus_name,
+                provider.object_path
+            ),
+        });
+        let mut argv = desktop::launch_argv(cfg, bundle, profile);
+        argv.extend(provider.args.iter().cloned());
+        let exec: Vec<String> = argv.iter().map(|a| dbus_quote(a)).collect();
+        files.push(File {
+            path: data_dir
+                .join(DBUS_SERVICES_DIR)
+                .join(format!("{}service", prefix)),
+            contents: format!(
+                "[D-BUS Service]\nName={}\nExec={}\n",
+                provider.bus_name,
+                exec.join(" ")
+            ),
+        });
+    }
+    let schemas = bundle.join("share").join(SCHEMAS_DIR);
+    for name in list(&schemas, as_user)? {
+        if name.ends_with(".gschema.xml") || name.ends_with(".gschema.override") {
+            files.push(File {
+                path: data_dir
+                    .join(SCHEMAS_DIR)
+                    .join(format!("{}{}", prefix, name)),
+                contents: read(&schemas, &name, as_user)?.unwrap_or_default(),
+            });
+        }
+    }
+    Ok(files)
+}
+
+/// `program`, behind `aa-exec -p PROFILE --` when there is a profile. Not escaped.
+fn profile_argv(program: &Path, profile: Option<&str>) -> Vec<String> {
+    let program = program.display().to_string();
+    match profile {
+        Some(p) => vec![
+            "aa-exec".into(),
+            "-p".into(),
+            p.into(),
+            "--".into(),
+            program,
+        ],
+        None => vec![program],
+    }
+}
+
+/// One word of a D-Bus service file's Exec, which D-Bus splits like a shell: single-quoted
+/// unless it is plainly safe.
+fn dbus_quote(s: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "/._-+=:,@".contains(c);
+    if !s.is_empty() && s.chars().all(safe) {
+        return s.to_string();
+    }
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Files to write or remove so that what is installed matches `wanted`; `installed` is what
+/// state recorded for the app.
+pub fn stale(
+    wanted: &[File],
+    installed: &[PathBuf],
+    as_user: Option<&str>,
+) -> Result<Vec<PathBuf>> {
+    let mut out = Vec::new();
+    for file in wanted {
+        let (dir, name) = split(&file.path)?;
+        if read(dir, name, as_user)?.as_deref() != Some(file.contents.as_str()) {
+            out.push(file.path.clone());
+        }
+    }
+    out.extend(
+        installed
+            .iter()
+            .filter(|p| !wanted.iter().any(|f| &f.path == *p))
+            .cloned(),
+    );
+    Ok(out)
+}
+
+/// Write `wanted`, remove the rest of `installed`, and refresh the caches of the directories
+/// touched (compile schemas). Schemas are checked before they are installed: one broken schema
+/// would stop glib-compile-schemas for every app in the directory.
+pub fn apply(
+    paths: &Paths,
+    bundle: &Path,
+    wanted: &[File],
+    installed: &[PathBuf],
+    as_user: Option<&str>,
+) -> Result<()> {
+    if wanted
+        .iter()
+        .any(|f| f.path.parent().is_some_and(|d| d.ends_with(SCHEMAS_DIR)))
+    {
+        let schemas = bundle.join("share").join(SCHEMAS_DIR);
+        compile_schemas(paths, &schemas, true, as_user).context("bundle's GSettings schemas")?;
+    }
+    for file in wanted {
+        let (dir, name) = split(&file.path)?;
+        let req = Request::WriteFile {
+            dir: dir.to_path_buf(),
+            name: name.to_string(),
+            contents: file.contents.clone(),
+            create_dir: true,
+        };
+        asuser::perform(as_user, &req)?;
+    }
+    let unwanted: Vec<PathBuf> = installed
+        .iter()
+        .filter(|p| !wanted.iter().any(|f| &f.path == *p))
+        .cloned()
+        .collect();
+    remove_files(&unwanted, as_user)?;
+    let written: Vec<PathBuf> = wanted.iter().map(|f| f.path.clone()).collect();
+    if let Err(e) = refresh(paths, written.iter().chain(&unwanted), as_user) {
+        // Take the app's files back out so the directory works for everyone else.
+        remove_files(&written, as_user)?;
+        let _ = refresh(paths, written.iter(), as_user);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Remove an app's installed files and refresh their directories.
+pub fn remove(paths: &Paths, installed: &[PathBuf], as_user: Option<&str>) -> Result<()> {
+    remove_files(installed, as_user)?;
+    refresh(paths, installed.iter(), as_user)
+}
+
+fn remove_files(files: &[PathBuf], as_user: Option<&str>) -> Result<()> {
+    for path in files {
+        let (dir, name) = split(path)?;
+        asuser::perform(
+            as_user,
+            &Request::RemoveFile {
+                dir: dir.to_path_buf(),
+                name: name.to_string(),
+            },
+        )?;
+    }
+    Ok(())
+}
+
+/// Rebuild the caches of the directories `files` are in.
+fn refresh<'a>(
+    paths: &Paths,
+    files: impl Iterator<Item = &'a PathBuf>,
+    as_user: Option<&str>,
+) -> Result<()> {
+    let mut dirs: Vec<&Path> = files.filter_map(|p| p.parent()).collect();
+    dirs.sort();
+    dirs.dedup();
+    for dir in dirs {
+        if dir.ends_with(SCHEMAS_DIR) {
+            let left = list(dir, as_user)?;
+            if left.iter().any(|n| n.ends_with(".gschema.xml")) {
+                compile_schemas(paths, dir, false, as_user)?;
+            } else {
+                // glib-compile-schemas does nothing for an empty directory: drop the stale cache.
+                let req = Request::RemoveFile {
+                    dir: dir.to_path_buf(),
+                    name: "gschemas.compiled".into(),
+                };
+                asuser::perform(as_user, &req)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run glib-compile-schemas on `dir` (with `check`, only check it: --strict --dry-run).
+fn compile_schemas(paths: &Paths, dir: &Path, check: bool, as_user: Option<&str>) -> Result<()> {
+    let tool = &paths.glib_compile_schemas;
+    if !tool.exists() {
+        anyhow::bail!(
+            "{} not found (install glib2 / libglib2.0-bin)",
+            tool.display()
+        );
+    }
+    let mut cmd = match as_user {
+        Some(user) if crate::bundle::is_root() => {
+            let mut c = Command::new("runuser");
+            c.args(["-u", user, "--"]).arg(tool);
+            c
+        }
+        _ => Command::new(tool),
+    };
+    if check {
+        cmd.args(["--strict", "--dry-run"]);
+    }
+    let out = cmd
+        .arg(dir)
+        .output()
+        .with_context(|| format!("run {}", tool.display()))?;
+    if !out.status.success() {
+        anyhow::bail!(
+            "glib-compile-schemas {} failed: {}",
+            dir.display(),
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+fn list(dir: &Path, as_user: Option<&str>) -> Result<Vec<String>> {
+    let listing = asuser::perform(
+        as_user,
+        &Request::ListFiles {
+            dir: dir.to_path_buf(),
+        },
+    )?;
+    Ok(listing
+        .unwrap_or_default()
+        .lines()
+        .map(str::to_string)
+        .collect())
+}
+
+fn read(dir: &Path, name: &str, as_user: Option<&str>) -> Result<Option<String>> {
+    asuser::perform(
+        as_user,
+        &Request::ReadFile {
+            dir: dir.to_path_buf(),
+            name: name.to_string(),
+        },
+    )
+}
+
+fn split(path: &Path) -> Result<(&Path, &str)> {
+    match (path.parent(), path.file_name().and_then(|n| n.to_str())) {
+        (Some(dir), Some(name)) => Ok((dir, name)),
+        _ => anyhow::bail!("bad integration file path {}", path.display()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn schemas_are_installed_and_removed() {
+        use std::os::unix::fs::PermissionsExt;
+        let root = tempfile::tempdir().unwrap();
+        let bundle = root.path().join("App.lnx");
+        let schemas = bundle.join("share").join(SCHEMAS_DIR);
+        std::fs::create_dir_all(&schemas).unwrap();
+        std::fs::write(schemas.join("org.example.App.gschema.xml"), "<schemalist/>").unwrap();
+        std::fs::write(schemas.join("README"), "not a schema").unwrap();
+        let desktop_dir = root.path().join("share/applications");
+        let mut paths = Paths::under(root.path());
+        // A stand-in compiler that records what it was asked to do.
+        let log = root.path().join("compile.log");
+        let tool = root.path().join("glib-compile-schemas");
+        std::fs::write(
+            &tool,
+            format!("#!/bin/sh\necho \"$@\" >> {}\n", log.display()),
+        )
+        .unwrap();
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+        paths.glib_compile_schemas = tool;
+
+        let cfg = crate::config::parse("name = \"App\"\nexecutable = \"bin/app\"\n").unwrap();
+        let wanted = wanted(&paths, &cfg, &bundle, &desktop_dir, None, None).unwrap();
+        let target = root
+            .path()
+            .join("share/glib-2.0/schemas/dotlnx-App.org.example.App.gschema.xml");
+        assert_eq!(
+            wanted.iter().map(|f| &f.path).collect::<Vec<_>>(),
+            [&target]
+        );
+        assert_eq!(
+            stale(&wanted, &[], None).unwrap(),
+            std::slice::from_ref(&target)
+        );
+        apply(&paths, &bundle, &wanted, &[], None).unwrap();
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "<schemalist/>");
+        assert!(stale(&wanted, std::slice::from_ref(&target), None)
+            .unwrap()
+            .is_empty());
+        let calls = std::fs::read_to_string(&log).unwrap();
+        assert!(
+            calls.starts_with(&format!("--strict --dry-run {}\n", schemas.display())),
+            "{}",
+            calls
+        );
+        assert!(calls.ends_with(&format!("{}\n", target.parent().unwrap().display())));
+
+        // Gone from the bundle: removed, and the compiled cache with it.
+        let compiled = target.parent().unwrap().join("gschemas.compiled");
+        std::fs::write(&compiled, "").unwrap();
+        assert_eq!(
+            stale(&[], std::slice::from_ref(&target), None).unwrap(),
+            std::slice::from_ref(&target)
+        );
+        remove(&paths, std::slice::from_ref(&target), None).unwrap();
+        assert!(!target.exists() && !compiled.exists());
+    }
+
+    #[test]
+    fn thumbnailers_and_search_provider() {
+        let root = tempfile::tempdir().unwrap();
+        let bundle = root.path().join("My App.lnx");
+        std::fs::create_dir_all(bundle.join("bin")).unwrap();
+        let cfg = crate::config::parse(
+            r#"
+name = "My App"
+executable = "bin/app"
+
+[[thumbnailers]]
+executable = "bin/thumb"
+args = ["--size", "%s", "%i", "%o", "50%"]
+mime_types = ["image/x-example", "application/x-example"]
+
+[[thumbnailers]]
+executable = "bin/thumb"
+mime_types = ["text/x-example"]
+
+[search_provider]
+bus_name = "org.example.App.SearchProvider"
+object_path = "/org/example/App/SearchProvider"
+args = ["--search-provider"]
+"#,
+        )
+        .unwrap();
+        let desktop_dir = root.path().join("share/applications");
+        let paths = Paths::under(root.path());
+        let files = wanted(
+            &paths,
+            &cfg,
+            &bundle,
+            &desktop_dir,
+            Some("dotlnx-me-My_App"),
+            None,
+        )
+        .unwrap();
+        let share = root.path().join("share");
+        let names: Vec<_> = files
+            .iter()
+            .map(|f| f.path.strip_prefix(&share).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "thumbnailers/dotlnx-My App.0.thumbnailer",
+                "thumbnailers/dotlnx-My App.1.thumbnailer",
+                "gnome-shell/search-providers/dotlnx-My App.search-provider.ini",
+                "dbus-1/services/dotlnx-My App.service",
+            ]
+            .map(PathBuf::from)
+        );
+        let thumb = bundle.join("bin/thumb").display().to_string();
+        assert_eq!(
+            files[0].contents,
+            format!(
+                "[Thumbnailer Entry]\nTryExec={t}\nExec=aa-exec -p dotlnx-me-My_App -- \"{t}\" --size %s %i %o 50%%\n\
+                 MimeType=image/x-example;application/x-example;\n",
+                t = thumb
+            )
+        );
+        assert!(
+            files[1]
+                .contents
+                .contains(" %i %o %s\nMimeType=text/x-example;\n"),
+            "{}",
+            files[1].contents
+        );
+        assert_eq!(
+            files[2].contents,
+            "[Shell Search Provider]\nDesktopId=dotlnx-My App.desktop\nBusName=org.example.App.SearchProvider\n\
+             ObjectPath=/org/example/App/SearchProvider\nVersion=2\n"
+        );
+        assert_eq!(
+            files[3].contents,
+            format!(
+                "[D-BUS Service]\nName=org.example.App.SearchProvider\n\
+                 Exec=aa-exec -p dotlnx-me-My_App -- '{}' --search-provider\n",
+                bundle.join("bin/app").display()
+            )
+        );
+        assert_eq!(dbus_quote("it's"), r"'it'\''s'");
+    }
+}
@@ -0,0 +1,161 @@
+//! Write-ahead journal for sync. Installing or uninstalling an app touches several outputs (the
+//! AppArmor profile and the .desktop entry); if sync dies between them (power loss, OOM kill) the
+//! menu can point at a profile that was never loaded. Before touching an app's outputs sync
+//! records what it is about to do in `journal.json` (state dir) and clears it when done. The next
+//! sync finds a leftover entry and removes that app's outputs, which rolls back a half-done
+//! install (the normal pass then installs it cleanly) and finishes a half-done uninstall.
+//! Sync loads several apps' profiles at once, so one journal can hold a batch of entries.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const JOURNAL_FILE: &str = "journal.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Op {
+    Install,
+    Uninstall,
+}
+
+/// One in-flight operation: everything needed to undo or finish it without the bundle.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub op: Op,
+    pub app: String,
+    /// Directory holding the app's dotlnx-<app>.desktop.
+    pub desktop_dir: PathBuf,
+    /// Owning user when root acts on a user tier (file operations run as them).
+    pub user: Option<String>,
+    /// AppArmor profile name (root only).
+    pub profile: Option<String>,
+    /// Unix time the operation started.
+    pub started: u64,
+}
+
+/// The journal file in a state directory.
+pub struct Journal {
+    dir: PathBuf,
+}
+
+impl Journal {
+    pub fn new(state_dir: &Path) -> Self {
+        Self {
+            dir: state_dir.to_path_buf(),
+        }
+    }
+
+    fn path(&self) -> PathBuf {
+        self.dir.join(JOURNAL_FILE)
+    }
+
+    /// Record `entry` durably (fsync + atomic rename) before the operation starts.
+    pub fn begin(&self, entry: &Entry) -> Result<()> {
+        self.write(&serde_json::to_string(entry)?)
+    }
+
+    /// Record several operations that run together (e.g. profiles loaded in parallel).
+    pub fn begin_batch(&self, entries: &[Entry]) -> Result<()> {
+        self.write(&serde_json::to_string(entries)?)
+    }
+
+    fn write(&self, json: &str) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let tmp = self.dir.join(".journal.json.tmp");
+        let mut f = std::fs::File::create(&tmp)?;
+        f.write_all(json.as_bytes())?;
+        f.sync_all()?;
+        std::fs::rename(&tmp, self.path())?;
+        Ok(())
+    }
+
+    /// The operation finished: forget it.
+    pub fn commit(&self) -> Result<()> {
+        match std::fs::remove_file(self.path()) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The operations a previous sync started but never finished (empty if none). An unreadable
+    /// journal is logged and dropped (nothing can be done with it).
+    pub fn pending(&self) -> Result<Vec<Entry>> {
+        let s = match std::fs::read_to_string(self.path()) {
+            Ok(s) => s,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        match serde_json::from_str(&s) {
+            Ok(Stored::One(entry)) => Ok(vec![entry]),
+            Ok(Stored::Batch(entries)) => Ok(entries),
+            Err(e) => {
+                tracing::warn!("ignoring corrupt sync journal: {}", e);
+                self.commit()?;
+                Ok(Vec::new())
+            }
+        }
+    }
+}
+
+/// On-disk journal: one entry from [`Journal::begin`], a list from [`Journal::begin_batch`].
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Stored {
+    One(Entry),
+    Batch(Vec<Entry>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry() -> Entry {
+        Entry {
+            op: Op::Install,
+            app: "myapp".into(),
+            desktop_dir: PathBuf::from("/home/alice/.local/share/applications"),
+            user: Some("alice".into()),
+            profile: Some("dotlnx-alice-myapp".into()),
+            started: 1,
+        }
+    }
+
+    #[test]
+    fn begin_commit_cycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let j = Journal::new(dir.path());
+        assert!(j.pending().unwrap().is_empty());
+        j.begin(&entry()).unwrap();
+        assert_eq!(j.pending().unwrap(), vec![entry()]);
+        j.commit().unwrap();
+        assert!(j.pending().unwrap().is_empty());
+        j.commit().unwrap();
+    }
+
+    #[test]
+    fn batch_entries_are_all_pending() {
+        let dir = tempfile::tempdir().unwrap();
+        let j = Journal::new(dir.path());
+        let other = Entry {
+            app: "other".into(),
+            profile: Some("dotlnx-alice-other".into()),
+            ..entry()
+        };
+        j.begin_batch(&[entry(), other.clone()]).unwrap();
+        assert_eq!(j.pending().unwrap(), vec![entry(), other]);
+        j.commit().unwrap();
+        assert!(j.pending().unwrap().is_empty());
+    }
+
+    #[test]
+    fn corrupt_journal_is_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(JOURNAL_FILE), "{oops").unwrap();
+        let j = Journal::new(dir.path());
+        assert!(j.pending().unwrap().is_empty());
+        assert!(!dir.path().join(JOURNAL_FILE).exists());
+    }
+}
@@ -0,0 +1,339 @@
+//! Kiosk mode ([kiosk] in the site config): the machine runs one system-tier app full screen and
+//! nothing else. While `app` is set, sync installs only that app, from /Applications, and only
+//! when it is a confined desktop app; every other bundle (including users' own) is refused, as is
+//! `dotlnx install` of anything else.
+//!
+//! For the app, sync writes two units to the system unit dir: dotlnx-kiosk.service, which logs the
+//! kiosk user in on the seat's terminal and starts the app under the session command (a kiosk
+//! compositor such as cage) through `dotlnx run`, restarting it whenever it exits, and
+//! dotlnx-kiosk.target, a minimal boot target pulling in only that service. The target becomes
+//! the default one, so the session starts at every boot; the previous default is recorded in the
+//! target and restored when kiosk mode is turned off. An update of the app restarts the session.
+
+use anyhow::{Context, Result};
+use std::path::Path;
+use tracing::{debug, info, warn};
+
+use crate::asuser;
+use crate::config::{self, Config};
+use crate::paths::Paths;
+use crate::settings::KioskSettings;
+use crate::sync::{Action, Change};
+use crate::systemd::{self, quote, value};
+
+pub const SERVICE: &str = "dotlnx-kiosk.service";
+pub const TARGET: &str = "dotlnx-kiosk.target";
+/// First line of both units, followed by the app name. Unlike app units' header, so the
+/// tier's unit reconcile never takes them for an app's.
+const HEADER: &str = "# dotlnx kiosk session for ";
+/// Line of the target recording the default target before kiosk mode.
+const PREVIOUS: &str = "# Default target before kiosk mode: ";
+
+/// Why kiosk mode refuses to install a bundle; None when it is the kiosk app and may be.
+pub fn refusal(settings: &KioskSettings, system_tier: bool, cfg: &Config) -> Option<String> {
+    let app = settings.app.as_deref()?;
+    if !cfg.answers_to(app) {
+        return Some(format!("kiosk mode runs only {}", app));
+    }
+    if !system_tier {
+        return Some(format!(
+            "kiosk mode installs {} from the system Applications dir only",
+            app
+        ));
+    }
+    if cfg.kind != config::Kind::App {
+        return Some("the kiosk app must be a desktop app, not a service".into());
+    }
+    if !cfg.security.as_ref().map(|s| s.confine).unwrap_or(true) {
+        return Some("the kiosk app must be confined (confine = false is refused)".into());
+    }
+    None
+}
+
+/// The service and target running `app` as [kiosk] says. `dotlnx` is the binary to start it
+/// with; `previous` the default target to restore when kiosk mode ends.
+pub fn units(
+    settings: &KioskSettings,
+    app: &str,
+    dotlnx: &str,
+    previous: Option<&str>,
+) -> Result<[(String, String); 2]> {
+    let tty = value(&settings.tty)?;
+    let header = format!(
+        "{}{}\n# Changes are overwritten by the next sync.\n",
+        HEADER, app
+    );
+    let mut argv: Vec<String> = settings.session.iter().map(|a| quote(a)).collect();
+    argv.extend([quote(dotlnx), quote("run"), quote(app)]);
+
+    let mut service = header.clone();
+    service.push_str("[Unit]\n");
+    service.push_str(&format!("Description=Kiosk session: {}\n", value(app)?));
+    service.push_str(&format!(
+        "After=systemd-user-sessions.service plymouth-quit-wait.service getty@{}.service\n",
+        tty
+    ));
+    service.push_str(&format!("Conflicts=getty@{}.service\n", tty));
+    // Never give up restarting: a signage box has nobody to reset a failed unit.
+    service.push_str("StartLimitIntervalSec=0\n");
+    service.push_str("\n[Service]\n");
+    service.push_str(&format!("User={}\n", value(&settings.user)?));
+    service.push_str("PAMName=login\n");
+    service.push_str(&format!("TTYPath=/dev/{}\n", tty));
+    service.push_str("StandardInput=tty\nStandardOutput=journal\nStandardError=journal\n");
+    service.push_str(&format!("UtmpIdentifier={}\nUtmpMode=user\n", tty));
+    service.push_str(&format!("ExecStart={}\n", argv.join(" ")));
+    service.push_str("Restart=always\n");
+    service.push_str(&format!("RestartSec={}\n", settings.restart_sec));
+
+    let mut target = header;
+    if let Some(previous) = previous {
+        target.push_str(&format!("{}{}\n", PREVIOUS, value(previous)?));
+    }
+    target.push_str("[Unit]\n");
+    target.push_str(&format!("Description=Kiosk: {}\n", value(app)?));
+    target.push_str("Requires=multi-user.target\nAfter=multi-user.target\n");
+    target.push_str("Conflicts=display-manager.service rescue.service rescue.target\n");
+    target.push_str(&format!("Wants={}\nAllowIsolate=yes\n", SERVICE));
+    Ok([(SERVICE.to_string(), service), (TARGET.to_string(), target)])
+}
+
+/// A kiosk unit in `dir` as written by sync; None when missing. Refuses a file dotlnx did not write.
+fn read_ours(dir: &Path, name: &str) -> Result<Option<String>> {
+    let path = dir.join(name);
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("read {}", path.display())),
+    };
+    if !text.starts_with(HEADER) {
+        anyhow::bail!(
+            "{} exists and was not written by dotlnx; leaving it alone",
+            path.display()
+        );
+    }
+    Ok(Some(text))
+}
+
+/// App named in a kiosk unit's header.
+fn app_of(text: &str) -> Option<&str> {
+    text.lines().next()?.strip_prefix(HEADER)
+}
+
+/// Default target recorded in a kiosk target.
+fn previous_default(target: &str) -> Option<&str> {
+    target.lines().find_map(|l| l.strip_prefix(PREVIOUS))
+}
+
+/// `systemctl get-default`; None without systemctl or when it fails.
+fn default_target(paths: &Paths) -> Option<String> {
+    if !paths.systemctl.exists() {
+        return None;
+    }
+    let out = std::process::Command::new(&paths.systemctl)
+        .arg("get-default")
+        .output()
+        .ok()?;
+    let name = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    (out.status.success() && !name.is_empty()).then_some(name)
+}
+
+/// Stop the kiosk session, put back the default target from before and delete the units.
+fn remove(
+    paths: &Paths,
+    unit_dir: &systemd::UnitDir,
+    existing: &[Option<String>; 2],
+    app: String,
+    dry_run: bool,
+) -> Result<Change> {
+    let change = Change {
+        action: Action::Remove,
+        app,
+        paths: [SERVICE, TARGET]
+            .iter()
+            .zip(existing)
+            .filter(|(_, e)| e.is_some())
+            .map(|(n, _)| unit_dir.dir.join(n))
+            .collect(),
+    };
+    if dry_run {
+        return Ok(change);
+    }
+    if default_target(paths).as_deref() == Some(TARGET) {
+        let previous = existing[1]
+            .as_deref()
+            .and_then(previous_default)
+            .unwrap_or("graphical.target");
+        unit_dir.systemctl(paths, &["set-default", previous]);
+    }
+    unit_dir.systemctl(paths, &["stop", SERVICE]);
+    for name in [SERVICE, TARGET] {
+        let req = asuser::Request::RemoveFile {
+            dir: unit_dir.dir.clone(),
+            name: name.to_string(),
+        };
+        asuser::perform(None, &req)?;
+    }
+    unit_dir.systemctl(paths, &["daemon-reload"]);
+    info!(app = %change.app, "kiosk mode off");
+    Ok(change)
+}
+
+/// Bring the kiosk units in line with [kiosk]: written for the kiosk app when `installed` (this
+/// sync installed it), removed when kiosk mode is off or names another app. `updated`: the app's
+/// outputs changed, so a running session restarts into the new version. Returns the change to
+/// report.
+pub fn sync(
+    paths: &Paths,
+    settings: &KioskSettings,
+    installed: bool,
+    updated: bool,
+    dry_run: bool,
+) -> Result<Option<Change>> {
+    let dir = &paths.systemd_system_dir;
+    let unit_dir = systemd::UnitDir {
+        dir: dir.clone(),
+        scope: systemd::Scope::System,
+        prefix: paths.system_prefix.clone(),
+    };
+    let existing = [read_ours(dir, SERVICE)?, read_ours(dir, TARGET)?];
+    let current = existing
+        .iter()
+        .flatten()
+        .next()
+        .and_then(|t| app_of(t))
+        .map(str::to_string);
+    let app = match settings.app.as_deref() {
+        Some(app) if installed => app,
+        // Not installed now (invalid, quarantined, not there yet): a session set up for it stays
+        // and keeps restarting until the app is back.
+        Some(app) if current.as_deref().is_none_or(|c| c == app) => {
+            if current.is_none() {
+                warn!(app = %app, "kiosk app is not installed; no kiosk session yet");
+            }
+            return Ok(None);
+        }
+        _ => {
+            let Some(current) = current else {
+                return Ok(None);
+            };
+            return remove(paths, &unit_dir, &existing, current, dry_run).map(Some);
+        }
+    };
+    let previous = match existing[1].as_deref() {
+        Some(target) => previous_default(target).map(str::to_string),
+        None => default_target(paths).filter(|t| t != TARGET),
+    };
+    let dotlnx = std::env::current_exe()
+        .ok()
+        .and_then(|p| p.to_str().map(String::from))
+        .unwrap_or_else(|| "dotlnx".into());
+    let wanted = units(settings, app, &dotlnx, previous.as_deref())?;
+    let stale: Vec<&(String, String)> = wanted
+        .iter()
+        .zip(&existing)
+        .filter(|((_, text), old)| old.as_deref() != Some(text.as_str()))
+        .map(|(w, _)| w)
+        .collect();
+    if stale.is_empty() {
+        if updated && !dry_run {
+            unit_dir.systemctl(paths, &["try-restart", SERVICE]);
+        }
+        return Ok(None);
+    }
+    let first = existing.iter().all(Option::is_none);
+    let change = Change {
+        action: if first {
+            Action::Install
+        } else {
+            Action::Update
+        },
+        app: app.to_string(),
+        paths: stale.iter().map(|(name, _)| dir.join(name)).collect(),
+    };
+    if dry_run {
+        return Ok(Some(change));
+    }
+    for (name, text) in stale {
+        let req = asuser::Request::WriteFile {
+            dir: dir.clone(),
+            name: name.clone(),
+            contents: text.clone(),
+            create_dir: true,
+        };
+        asuser::perform(None, &req)?;
+    }
+    unit_dir.systemctl(paths, &["daemon-reload"]);
+    if first {
+        // Takes over at the next boot (or now: systemctl isolate dotlnx-kiosk.target).
+        unit_dir.systemctl(paths, &["set-default", TARGET]);
+        info!(app = %app, "kiosk mode on: {} is the default target", TARGET);
+    } else {
+        debug!(app = %app, "kiosk units updated");
+        unit_dir.systemctl(paths, &["try-restart", SERVICE]);
+    }
+    Ok(Some(change))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_confined_kiosk_app_is_installed() {
+        let settings: KioskSettings = toml::from_str("app = \"Sign\"\n").unwrap();
+        let cfg = |extra: &str| -> Config {
+            toml::from_str(&format!(
+                "name = \"Sign\"\nexecutable = \"bin/sign\"\n{}",
+                extra
+            ))
+            .unwrap()
+        };
+        assert_eq!(refusal(&settings, true, &cfg("")), None);
+        assert!(refusal(&settings, false, &cfg(""))
+            .unwrap()
+            .contains("system Applications"));
+        assert!(
+            refusal(&settings, true, &cfg("[security]\nconfine = false\n"))
+                .unwrap()
+                .contains("confined")
+        );
+        assert!(refusal(&settings, true, &cfg("type = \"service\"\n")).is_some());
+        let other: Config = toml::from_str("name = \"Other\"\nexecutable = \"a\"\n").unwrap();
+        assert_eq!(
+            refusal(&settings, true, &other).unwrap(),
+            "kiosk mode runs only Sign"
+        );
+        assert_eq!(refusal(&KioskSettings::default(), false, &other), None);
+    }
+
+    #[test]
+    fn units_start_the_app_on_the_seat_and_restart_it() {
+        let settings: KioskSettings =
+            toml::from_str("app = \"Sign\"\ntty = \"tty2\"\nrestart_sec = 5\n").unwrap();
+        let [(service_name, service), (target_name, target)] = units(
+            &settings,
+            "Sign",
+            "/usr/bin/dotlnx",
+            Some("graphical.target"),
+        )
+        .unwrap();
+        assert_eq!(
+            (service_name.as_str(), target_name.as_str()),
+            (SERVICE, TARGET)
+        );
+        assert!(service.contains(
+            "ExecStart=\"/usr/bin/cage\" \"-s\" \"--\" \"/usr/bin/dotlnx\" \"run\" \"Sign\"\n"
+        ));
+        assert!(service.contains("User=kiosk\n") && service.contains("TTYPath=/dev/tty2\n"));
+        assert!(service.contains("Conflicts=getty@tty2.service\n"));
+        assert!(service.contains("Restart=always\nRestartSec=5\n"));
+        assert_eq!(app_of(&service), Some("Sign"));
+        assert_eq!(previous_default(&target), Some("graphical.target"));
+        assert!(target.contains("Wants=dotlnx-kiosk.service\nAllowIsolate=yes\n"));
+
+        let direct: KioskSettings = toml::from_str("app = \"Sign\"\nsession = []\n").unwrap();
+        let [(_, service), _] = units(&direct, "Sign", "/usr/bin/dotlnx", None).unwrap();
+        assert!(service.contains("ExecStart=\"/usr/bin/dotlnx\" \"run\" \"Sign\"\n"));
+    }
+}
@@ -0,0 +1,341 @@
+//! Menu launches and `dotlnx logs`. A menu entry's output goes nowhere, so when `dotlnx run` is
+//! started from a graphical session without a terminal, the app's stdout and stderr go to
+//! `<state dir>/logs/<app>.log`, and a launch that fails early (dotlnx refuses it, or the app
+//! exits with an error within [`EARLY_EXIT`]) is shown in an error dialog (zenity, kdialog, or a
+//! notification) with the reason and a pointer to `dotlnx logs`, instead of failing silently.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{IsTerminal, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::i18n::{tr, Message};
+
+/// An error exit this soon after starting counts as a failed launch.
+pub const EARLY_EXIT: Duration = Duration::from_secs(5);
+/// A log bigger than this is moved to `<app>.log.1` when the app is next launched.
+const MAX_LOG_BYTES: u64 = 1024 * 1024;
+/// Lines of the app's output quoted in the error dialog.
+const DIALOG_LINES: usize = 6;
+/// Lines `dotlnx logs NAME` prints by default.
+pub const DEFAULT_LINES: usize = 50;
+
+/// True when `dotlnx run` was started the way a menu entry starts it: no terminal to report to,
+/// but a graphical session to show a dialog in.
+pub fn from_menu() -> bool {
+    !std::io::stdin().is_terminal()
+        && !std::io::stderr().is_terminal()
+        && (std::env::var_os("DISPLAY").is_some() || std::env::var_os("WAYLAND_DISPLAY").is_some())
+}
+
+/// Where this user's launch logs are.
+pub fn logs_dir() -> Result<PathBuf> {
+    Ok(crate::state::state_dir()?.join("logs"))
+}
+
+/// Open `app`'s log for one more launch, headed by `what`. Returns the log, open for appending,
+/// and where this launch's part of it starts.
+fn open_log(app: &str, what: &str) -> Result<(PathBuf, File, u64)> {
+    crate::validate::validate_app_name(app)?;
+    let dir = logs_dir()?;
+    std::fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+    let path = dir.join(format!("{}.log", app));
+    if std::fs::metadata(&path).is_ok_and(|m| m.len() > MAX_LOG_BYTES) {
+        let _ = std::fs::rename(&path, dir.join(format!("{}.log.1", app)));
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(&path)
+        .with_context(|| format!("open {}", path.display()))?;
+    writeln!(file, "=== {} {}", timestamp(crate::state::now()), what)?;
+    let start = file.metadata()?.len();
+    Ok((path, file, start))
+}
+
+/// One menu launch of an app.
+pub struct Launch {
+    app: String,
+    path: PathBuf,
+    file: File,
+    start: u64,
+    started: Instant,
+}
+
+impl Launch {
+    /// Start logging a menu launch of `app`. None when the log cannot be opened; the app is
+    /// launched all the same.
+    pub fn begin(app: &str) -> Option<Launch> {
+        match open_log(app, &format!("dotlnx run {}", app)) {
+            Ok((path, file, start)) => Some(Launch {
+                app: app.to_string(),
+                path,
+                file,
+                start,
+                started: Instant::now(),
+            }),
+            Err(e) => {
+                tracing::warn!("{}: no launch log: {:#}", app, e);
+                None
+            }
+        }
+    }
+
+    /// Send `cmd`'s stdout and stderr to the log.
+    pub fn attach(&self, cmd: &mut Command) {
+        if let (Ok(out), Ok(err)) = (self.file.try_clone(), self.file.try_clone()) {
+            cmd.stdout(Stdio::from(out)).stderr(Stdio::from(err));
+        }
+    }
+
+    /// Report the launch when the app exited with an error within [`EARLY_EXIT`].
+    pub fn finish(mut self, status: ExitStatus) {
+        if status.success() || self.started.elapsed() >= EARLY_EXIT {
+            return;
+        }
+        let how = match (status.code(), status.signal()) {
+            (Some(code), _) => Message::new("launch-exited", &[("code", code.to_string())]),
+            (None, Some(signal)) => {
+                Message::new("launch-killed", &[("signal", signal.to_string())])
+            }
+            _ => Message::new("launch-stopped", &[]),
+        };
+        // The log stays in English: `events` reads these lines back.
+        let _ = writeln!(
+            self.file,
+            "=== {} {}",
+            timestamp(crate::state::now()),
+            how.english()
+        );
+        let mut output = String::new();
+        let _ = File::open(&self.path).and_then(|mut f| {
+            f.seek(SeekFrom::Start(self.start))
+                .and_then(|_| f.read_to_string(&mut output))
+        });
+        let output: Vec<&str> = output.lines().filter(|l| !l.starts_with("=== ")).collect();
+        let tail = output[output.len().saturating_sub(DIALOG_LINES)..].join("\n");
+        show(&self.app, &how.to_string(), &tail);
+    }
+}
+
+/// Report a launch dotlnx refused or could not start (app not found, failed checks, missing
+/// executable): log it under `name` when that is a valid app name, and show the dialog.
+pub fn refused(name: &str, error: &anyhow::Error) {
+    let reason = format!("{:#}", error);
+    let _ = open_log(
+        name,
+        &format!("dotlnx run {}: not started: {}", name, reason),
+    );
+    show(name, &reason, "");
+}
+
+/// Show the error dialog for `name`: the first of zenity, kdialog and notify-send that is
+/// installed (kdialog first on KDE). Not waited for, so dotlnx can exit while it is open.
+fn show(name: &str, reason: &str, output: &str) {
+    let title = tr("launch-failed-title", &[("app", name.to_string())]);
+    let mut text = reason.to_string();
+    if !output.is_empty() {
+        text.push_str(&format!("\n\n{}", output));
+    }
+    text.push_str(&format!(
+        "\n\n{}",
+        tr("launch-failed-logs", &[("app", name.to_string())])
+    ));
+    let zenity = [
+        "zenity",
+        "--error",
+        "--no-markup",
+        "--title",
+        &title,
+        "--text",
+        &text,
+    ];
+    let kdialog = ["kdialog", "--title", &title, "--error", &text];
+    let notify = [
+        "notify-send",
+        "--app-name=dotlnx",
+        "--urgency=critical",
+        "--",
+        &title,
+        &text,
+    ];
+    let kde = std::env::var("XDG_CURRENT_DESKTOP").is_ok_and(|d| d.split(':').any(|d| d == "KDE"));
+    let order: [&[&str]; 3] = match kde {
+        true => [&kdialog, &zenity, &notify],
+        false => [&zenity, &kdialog, &notify],
+    };
+    for argv in order {
+        let spawned = Command::new(argv[0])
+            .args(&argv[1..])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        match spawned {
+            Ok(_) => return,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                tracing::warn!("could not show launch error: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+/// One launch recorded in a launch log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Event {
+    /// When it was launched (UTC, as written in the log).
+    pub time: String,
+    /// Why it was not started or stopped right away; None for a launch that went fine.
+    pub failure: Option<String>,
+}
+
+/// The launches in a launch log, oldest first.
+pub fn events(log: &str) -> Vec<Event> {
+    let mut out: Vec<Event> = Vec::new();
+    for line in log.lines() {
+        let Some(header) = line.strip_prefix("=== ") else {
+            continue;
+        };
+        // "YYYY-MM-DD HH:MM:SS what"
+        let (Some(time), Some(what)) = (header.get(..19), header.get(20..)) else {
+            continue;
+        };
+        match what.strip_prefix("dotlnx run ") {
+            Some(launch) => out.push(Event {
+                time: time.to_string(),
+                failure: launch
+                    .split_once(": not started: ")
+                    .map(|(_, why)| why.to_string()),
+            }),
+            None => {
+                if let Some(last) = out.last_mut().filter(|e| e.failure.is_none()) {
+                    last.failure = Some(what.to_string());
+                }
+            }
+        }
+    }
+    out
+}
+
+/// `secs` since the epoch as `YYYY-MM-DD HH:MM:SS` UTC.
+pub fn timestamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    // Civil date from days since 1970-01-01 (proleptic Gregorian calendar).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
+
+/// `dotlnx logs`: the last `lines` lines of an app's launch log, or with no name the apps that
+/// have one.
+pub fn run(name: Option<&str>, lines: usize) -> Result<()> {
+    let dir = logs_dir()?;
+    let Some(name) = name else {
+        let mut apps: Vec<String> = std::fs::read_dir(&dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|e| e.ok())
+                    .filter_map(|e| {
+                        e.file_name()
+                            .to_str()?
+                            .strip_suffix(".log")
+                            .map(String::from)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        apps.sort();
+        if apps.is_empty() {
+            println!(
+                "No launch logs yet (apps started from the menu through `dotlnx run` are logged)."
+            );
+        }
+        for app in apps {
+            println!("{}", app);
+        }
+        return Ok(());
+    };
+    // Logs are kept under the app ID; accept the name the user knows the app by.
+    let settings = crate::settings::load()?;
+    let paths = crate::paths::Paths::from_settings(&settings.paths);
+    let app = match crate::bundle::resolve_bundle_by_name(&paths, name) {
+        Ok(Some((_, config, _))) => config.app_id(),
+        _ => name.to_string(),
+    };
+    crate::validate::validate_app_name(&app)?;
+    let path = dir.join(format!("{}.log", app));
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            anyhow::bail!(Message::new("no-launch-log", &[("app", app.to_string())]))
+        }
+        Err(e) => return Err(e).with_context(|| format!("read {}", path.display())),
+    };
+    let all: Vec<&str> = text.lines().collect();
+    for line in &all[all.len().saturating_sub(lines)..] {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps_are_utc_calendar_dates() {
+        assert_eq!(timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(timestamp(951_782_400), "2000-02-29 00:00:00");
+        assert_eq!(timestamp(1_792_151_999), "2026-10-16 11:59:59");
+    }
+
+    #[test]
+    fn events_pair_launches_with_their_failures() {
+        let log = "=== 2026-10-01 08:00:00 dotlnx run Notes\nhello\n\
+                   === 2026-10-02 09:00:00 dotlnx run Notes\ncannot open libfoo.so\n\
+                   === 2026-10-02 09:00:01 exited with status 3 right after starting\n\
+                   === 2026-10-03 10:00:00 dotlnx run Notes: not started: executable not found: /x\n";
+        let events = events(log);
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events[0],
+            Event {
+                time: "2026-10-01 08:00:00".into(),
+                failure: None
+            }
+        );
+        assert_eq!(
+            events[1].failure.as_deref(),
+            Some("exited with status 3 right after starting")
+        );
+        assert_eq!(events[2].time, "2026-10-03 10:00:00");
+        assert_eq!(
+            events[2].failure.as_deref(),
+            Some("executable not found: /x")
+        );
+    }
+}
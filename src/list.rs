@@ -0,0 +1,145 @@
+//! `dotlnx list`: the apps dotlnx manages, one line each: name, tier, bundle, whether it runs
+//! confined, and whether its menu entry and profile are in place right now (on disk and, for the
+//! profile, loaded in the kernel). Reads the bundles and the installed files, not state.json, so
+//! it also shows what a sync has not caught up with yet.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::path::PathBuf;
+
+use crate::apparmor;
+use crate::bundle;
+use crate::output::{self, Style, Table};
+use crate::paths::Paths;
+use crate::permissions;
+use crate::settings;
+
+/// One app.
+#[derive(Debug, Serialize)]
+pub struct Listed {
+    pub name: String,
+    /// App ID (`vendor.name`, or the name).
+    pub id: String,
+    /// "user:<name>" or "system".
+    pub tier: String,
+    pub bundle: PathBuf,
+    pub confined: bool,
+    /// Profile name; None when the app runs unconfined.
+    pub profile: Option<String>,
+    /// The menu entry is in the tier's applications dir.
+    pub desktop_installed: bool,
+    /// The generated profile is in the AppArmor dir.
+    pub profile_installed: bool,
+    /// The profile is loaded in the kernel.
+    pub profile_loaded: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct List {
+    pub apps: Vec<Listed>,
+    pub errors: Vec<permissions::Unreadable>,
+}
+
+/// Print every bundle this user can see: their own and the system tier's (all users' as root).
+pub fn run(json: bool) -> Result<()> {
+    let settings = settings::load()?;
+    let paths = Paths::from_settings(&settings.paths);
+    let list = collect(&settings, &paths)?;
+    if json {
+        return output::json(&list);
+    }
+    print(&list);
+    Ok(())
+}
+
+fn collect(settings: &settings::Settings, paths: &Paths) -> Result<List> {
+    let is_root = bundle::is_root();
+    let mut tiers: Vec<(PathBuf, PathBuf, Option<String>)> = bundle::user_tier_entries(paths)?
+        .into_iter()
+        .map(|(apps, desktop, user)| (apps, desktop, Some(user)))
+        .collect();
+    tiers.push((
+        paths.system_apps.clone(),
+        paths.system_desktop.clone(),
+        None,
+    ));
+    let mut list = List::default();
+    for (apps_dir, desktop_dir, user) in tiers {
+        for dir in bundle::discover_lnx_dirs(&apps_dir) {
+            let owner = user.as_deref().filter(|_| is_root);
+            let cfg = match permissions::resolve(settings, owner, &dir, is_root) {
+                Ok((cfg, _)) => cfg,
+                Err(e) => {
+                    list.errors.push(permissions::Unreadable {
+                        bundle: dir,
+                        error: format!("{:#}", e),
+                    });
+                    continue;
+                }
+            };
+            let id = cfg.app_id();
+            let confined = cfg.security.as_ref().is_none_or(|s| s.confine);
+            let profile = confined.then(|| paths.profile_name(user.as_deref(), &id));
+            let entry = desktop_dir.join(paths.desktop_file_name(&desktop_dir, &id));
+            list.apps.push(Listed {
+                name: cfg.name.clone(),
+                tier: user
+                    .as_ref()
+                    .map(|u| format!("user:{}", u))
+                    .unwrap_or_else(|| "system".into()),
+                bundle: dir,
+                confined,
+                desktop_installed: entry.is_file(),
+                profile_installed: profile
+                    .as_ref()
+                    .is_some_and(|p| paths.profile_path(p).is_file()),
+                profile_loaded: profile
+                    .as_ref()
+                    .is_some_and(|p| apparmor::is_loaded(paths, p)),
+                profile,
+                id,
+            });
+        }
+    }
+    Ok(list)
+}
+
+fn print(list: &List) {
+    if list.apps.is_empty() && list.errors.is_empty() {
+        println!(
+            "No apps. Put .lnx bundles in ~/Applications (or /Applications) and run `dotlnx sync`."
+        );
+        return;
+    }
+    let mut table = Table::new(&["NAME", "TIER", "CONFINEMENT", "MENU", "PROFILE", "BUNDLE"]);
+    for app in &list.apps {
+        let profile = match (&app.profile, app.profile_installed, app.profile_loaded) {
+            (None, _, _) => ("-", Style::Plain),
+            (Some(_), _, true) => ("loaded", Style::Good),
+            (Some(_), true, false) => ("not loaded", Style::Warn),
+            (Some(_), false, false) => ("missing", Style::Bad),
+        };
+        table.row(vec![
+            (app.id.clone(), Style::Plain),
+            (app.tier.clone(), Style::Plain),
+            match app.confined {
+                true => ("apparmor".into(), Style::Good),
+                false => ("unconfined".into(), Style::Warn),
+            },
+            match app.desktop_installed {
+                true => ("yes".into(), Style::Good),
+                false => ("missing".into(), Style::Bad),
+            },
+            (profile.0.into(), profile.1),
+            (app.bundle.display().to_string(), Style::Plain),
+        ]);
+    }
+    table.print();
+    for e in &list.errors {
+        println!(
+            "{}: {}",
+            e.bundle.display(),
+            output::paint(&e.error, Style::Bad)
+        );
+    }
+}
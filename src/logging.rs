@@ -0,0 +1,113 @@
+//! Diagnostics: tracing to stderr at the level RUST_LOG asks for, moved by `-v`/`-q`, and with
+//! `--log-file` a full debug log besides, whatever the console shows, for attaching to bug
+//! reports.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+/// Global `-v`, `-q` and `--log-file`.
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct LogArgs {
+    /// More detail on stderr: -v for debug, -vv for trace (over RUST_LOG's level)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+    /// Less on stderr: -q for warnings and errors only (and no progress bars), -qq for errors only
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub quiet: u8,
+    /// Also write a full debug log to this file (appended), whatever -v/-q and RUST_LOG say
+    #[arg(long, global = true, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+}
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// `-q` was given: commands leave out progress too.
+pub fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Filter directives for stderr: RUST_LOG (info when unset), with `-v`/`-q` replacing its
+/// default level but keeping its per-module directives.
+fn console_directives(verbose: u8, quiet: u8, rust_log: Option<&str>) -> String {
+    let level = match (verbose, quiet) {
+        (0, 0) => None,
+        (1, _) => Some("debug"),
+        (_, 0) => Some("trace"),
+        (_, 1) => Some("warn"),
+        _ => Some("error"),
+    };
+    let rust_log = rust_log.filter(|s| !s.trim().is_empty());
+    match (rust_log, level) {
+        (Some(env), Some(level)) => format!("{},{}", env, level),
+        (Some(env), None) => env.to_string(),
+        (None, level) => level.unwrap_or("info").to_string(),
+    }
+}
+
+/// Open the log file for appending, readable by its owner only (logs name users and paths).
+fn open(path: &Path) -> Result<std::fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("cannot open log file {}", path.display()))
+}
+
+/// Install the subscriber. A log file that cannot be opened is an error, returned after stderr
+/// logging is set up so it can be reported.
+pub fn init(args: &LogArgs, color: bool) -> Result<()> {
+    QUIET.store(args.quiet > 0, Ordering::Relaxed);
+    let rust_log = std::env::var(EnvFilter::DEFAULT_ENV).ok();
+    let console = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_ansi(color)
+        .with_filter(EnvFilter::new(console_directives(
+            args.verbose,
+            args.quiet,
+            rust_log.as_deref(),
+        )));
+    let (file, error) = match args.log_file.as_deref().map(open) {
+        Some(Ok(f)) => (Some(f), None),
+        Some(Err(e)) => (None, Some(e)),
+        None => (None, None),
+    };
+    let file = file.map(|f| {
+        tracing_subscriber::fmt::layer()
+            .with_writer(std::sync::Mutex::new(f))
+            .with_ansi(false)
+            .with_filter(EnvFilter::new("debug"))
+    });
+    tracing_subscriber::registry()
+        .with(console)
+        .with(file)
+        .init();
+    if let Some(e) = error {
+        return Err(e);
+    }
+    tracing::debug!(version = env!("CARGO_PKG_VERSION"), args = ?std::env::args().collect::<Vec<_>>(), "dotlnx started");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_replace_the_default_level_but_keep_module_directives() {
+        assert_eq!(console_directives(0, 0, None), "info");
+        assert_eq!(console_directives(0, 0, Some("warn")), "warn");
+        assert_eq!(console_directives(1, 0, None), "debug");
+        assert_eq!(console_directives(3, 0, Some("")), "trace");
+        assert_eq!(console_directives(0, 1, None), "warn");
+        assert_eq!(
+            console_directives(0, 2, Some("dotlnx::sync=trace,info")),
+            "dotlnx::sync=trace,info,error"
+        );
+    }
+}
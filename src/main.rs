@@ -13,24 +13,88 @@
 //! You should have received a copy of the GNU General Public License
 //! along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+mod access;
+mod adopt;
 mod apparmor;
+mod appmeta;
+mod arch;
+mod asuser;
+mod backup;
 mod bundle;
 mod bundler;
 mod config;
+mod denials;
+mod deps;
 mod desktop;
+mod diff;
+mod ephemeral;
+mod filemanager;
+mod fscache;
+mod gc;
+mod gpu;
+mod grant;
+mod hold;
+mod hooks;
+mod i18n;
+mod info;
+mod install;
+mod integration;
+mod journal;
+mod kiosk;
+mod launchlog;
+mod list;
+mod logging;
+mod oci;
+mod open;
+mod output;
+mod pack;
+mod paths;
+mod permissions;
+mod policy;
+mod progress;
+mod report;
+mod runenv;
+mod runner;
+mod safefs;
+mod scan;
+mod sdnotify;
+mod search;
+mod seccomp;
+mod settings;
+mod snapshot;
+mod state;
+mod status;
+mod steam;
 mod sync;
+mod systemd;
+mod templates;
+mod tray;
+#[cfg(feature = "tui")]
+mod tui;
 mod uninstall;
+mod update;
 mod validate;
 mod watch;
+mod writemon;
+mod zipfile;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
-use tracing_subscriber::EnvFilter;
 
 #[derive(Parser)]
 #[command(name = "dotlnx")]
-#[command(about = "Drop .lnx folders in the app folder to install; watcher syncs to menu + AppArmor")]
+#[command(
+    about = "Drop .lnx folders in the app folder to install; watcher syncs to menu + AppArmor"
+)]
 struct Cli {
+    /// Use as little memory as possible (small devices); same as [resources] low_memory
+    #[arg(long, global = true)]
+    low_memory: bool,
+    /// When to colour output: auto (on a terminal, unless NO_COLOR is set), always or never
+    #[arg(long, global = true, value_enum, value_name = "WHEN", default_value_t = output::Color::Auto)]
+    color: output::Color,
+    #[command(flatten)]
+    log: logging::LogArgs,
     #[command(subcommand)]
     command: Commands,
 }
@@ -42,6 +106,19 @@ enum Commands {
         /// Only print what would be done
         #[arg(long)]
         dry_run: bool,
+        /// Change nothing; exit 2 if a sync would change anything, 0 if already in sync
+        #[arg(long)]
+        check: bool,
+        #[command(flatten)]
+        out: output::OutputArgs,
+        /// Also update held apps whose bundle changed (see `dotlnx hold`)
+        #[arg(long)]
+        force: bool,
+    },
+    /// Show whether the service is running and how the last sync of each tier went: bundles found, installed, skipped and quarantined, and why any were not installed
+    Status {
+        #[command(flatten)]
+        out: output::OutputArgs,
     },
     /// Watch app folders and auto-sync on change. Default behavior; package starts this.
     Watch {
@@ -51,6 +128,44 @@ enum Commands {
     },
     /// Launch an app by name from the CLI (diagnostics/scripting). .desktop files use the direct executable path, not this.
     Run {
+        /// Watch the bundle while the app runs and list what it wrote inside it (for bundle authors)
+        #[arg(long)]
+        report_writes: bool,
+        /// Stop the app if it still runs after SECS seconds (exit status 124 then, like timeout(1))
+        #[arg(long, value_name = "SECS")]
+        wait: Option<u64>,
+        /// Smoke test: pass (exit 0) if the app is still running after --wait (default 10s) or exited 0, else fail (exit 1)
+        #[arg(long)]
+        check: bool,
+        /// Write the app's stdout and stderr to FILE
+        #[arg(long, value_name = "FILE")]
+        output: Option<std::path::PathBuf>,
+        /// App name (from config.toml)
+        name: String,
+        /// Passed to the app after its configured args (e.g. a file or URL from the menu entry)
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Show the output of an app's menu launches (`dotlnx run` from the menu); with no name, list the apps logged
+    Logs {
+        /// App name (from config.toml)
+        name: Option<String>,
+        /// Number of lines to show
+        #[arg(short = 'n', long, default_value_t = launchlog::DEFAULT_LINES)]
+        lines: usize,
+    },
+    /// Launch an app in the background, the same way its menu entry does
+    Open {
+        /// App name (from config.toml)
+        name: String,
+    },
+    /// Show an app's .lnx bundle folder in the file manager
+    Reveal {
+        /// App name (from config.toml)
+        name: String,
+    },
+    /// Edit an app's config.toml in $VISUAL/$EDITOR, then validate and sync
+    Edit {
         /// App name (from config.toml)
         name: String,
     },
@@ -58,13 +173,104 @@ enum Commands {
     Validate {
         /// Path to .lnx directory or directory containing .lnx dirs
         path: std::path::PathBuf,
+        /// Also check that the shared libraries of ELF files in the bundle are present
+        #[arg(long)]
+        check_deps: bool,
+        /// Strip setuid/setgid bits and file capabilities instead of failing on them
+        #[arg(long)]
+        fix: bool,
+        #[command(flatten)]
+        out: output::OutputArgs,
     },
     /// Remove app from dotlnx (used by watch when folder removed; or admins). End users just remove the folder.
     Uninstall {
         /// App name (from config.toml)
         name: String,
+        /// Change nothing; exit 2 if the app is installed (would be removed), 0 if not
+        #[arg(long)]
+        check: bool,
+        #[command(flatten)]
+        out: output::OutputArgs,
+    },
+    /// Let an app read or write a path its profile does not allow, until reboot or revoke (root). Without rules, list its grants.
+    Grant {
+        /// App name (from config.toml)
+        name: String,
+        /// read:/path or rw:/path (AppArmor globs allowed)
+        rules: Vec<String>,
+        /// How long the grant lasts
+        #[arg(long, value_enum, default_value = "reboot")]
+        until: grant::Lifetime,
+    },
+    /// Remove grants made with `dotlnx grant` (all of the app's when no rules are given) and reload its profile (root)
+    Revoke {
+        /// App name (from config.toml)
+        name: String,
+        /// Rules to remove, as given to grant
+        rules: Vec<String>,
+    },
+    /// Keep an app at its installed version: sync stops applying changes to its bundle until unhold or `sync --force` (root). Without a name, list held apps.
+    Hold {
+        /// App name (from config.toml)
+        name: Option<String>,
+    },
+    /// Release a hold and bring the app up to date with its bundle (root)
+    Unhold {
+        /// App name (from config.toml)
+        name: String,
+    },
+    /// Show everything about one app: bundle, executable, env, launch command, profile and menu entry, and what keeps it from launching confined
+    Info {
+        /// App name (from config.toml)
+        name: String,
+        #[command(flatten)]
+        out: output::OutputArgs,
+    },
+    /// List the apps dotlnx manages: tier, bundle, confinement, and whether the menu entry and profile are installed
+    List {
+        #[command(flatten)]
+        out: output::OutputArgs,
+    },
+    /// Review what every app may access (files, network, devices) after overrides and grants, flagging broad grants
+    Permissions {
+        #[command(flatten)]
+        out: output::OutputArgs,
+    },
+    /// Summarize installed apps, versions, confinement, last launches and recent failures (local only, nothing is sent)
+    Report {
+        #[command(flatten)]
+        out: output::OutputArgs,
+    },
+    /// Find installed apps by name, comment, keywords or categories (all when no query is given)
+    Search {
+        /// Words that must all match
+        query: Vec<String>,
+        #[command(flatten)]
+        out: output::OutputArgs,
     },
-    /// Create a .lnx bundle scaffold. Use exactly one of --appimage or --bin.
+    /// Review what a new version of a bundle changes: config keys, files, generated profile and menu entry
+    Diff {
+        /// Old bundle (with --against-installed: the new bundle)
+        bundle: std::path::PathBuf,
+        /// New bundle
+        #[arg(required_unless_present = "against_installed")]
+        other: Option<std::path::PathBuf>,
+        /// Compare the bundle with the installed app of the same name
+        #[arg(long, conflicts_with = "other")]
+        against_installed: bool,
+        /// Exit 2 if anything differs, 0 if not
+        #[arg(long)]
+        check: bool,
+    },
+    /// Clean up folder icons (.directory, GNOME metadata) left in bundles that are no longer installed
+    Gc {
+        /// Change nothing; exit 2 if anything would be cleaned, 0 if not
+        #[arg(long)]
+        check: bool,
+        #[command(flatten)]
+        out: output::OutputArgs,
+    },
+    /// Create a .lnx bundle scaffold. Use exactly one of --appimage, --bin, --oci or --wine.
     Bundle {
         /// Application name (menu and bundle folder name)
         #[arg(long)]
@@ -75,58 +281,372 @@ enum Commands {
         /// Bin bundle: copy script or binary into bin/, config.toml + assets/; that file is the executable
         #[arg(long)]
         bin: Option<std::path::PathBuf>,
+        /// Container bundle: pull an image with skopeo (e.g. docker://registry/app:tag) into rootfs/; run.sh starts it with bubblewrap
+        #[arg(long)]
+        oci: Option<String>,
+        /// Wine bundle: run a Windows installer (.exe/.msi) into a prefix in data/, or copy an existing WINEPREFIX directory
+        #[arg(long)]
+        wine: Option<std::path::PathBuf>,
+        /// Program the Wine bundle starts (e.g. "C:\Program Files\App\app.exe"); guessed when omitted
+        #[arg(long)]
+        wine_program: Option<String>,
+        /// AppImage bundle without run.sh: `dotlnx run` launches the newest matching AppImage itself
+        #[arg(long)]
+        builtin_launcher: bool,
+        /// Copy GenericName, Comment, Keywords, Categories and translated names from this .desktop file into config.toml (AppImages: read from the AppImage when omitted)
+        #[arg(long)]
+        desktop_file: Option<std::path::PathBuf>,
+        /// Same from an AppStream metainfo file; the .desktop file wins where both set a key
+        #[arg(long)]
+        metainfo: Option<std::path::PathBuf>,
         /// Directory to create the .lnx folder in
         #[arg(long, default_value = ".")]
         output_dir: std::path::PathBuf,
+        #[command(flatten)]
+        progress: progress::ProgressArgs,
+    },
+    /// Archive a .lnx bundle for distribution (reproducible: same files, same bytes)
+    Pack {
+        /// .lnx bundle directory
+        path: std::path::PathBuf,
+        /// Archive to write (default: <bundle>.lnx.tar.zst in the current directory; a .tar.gz name packs with gzip)
+        #[arg(long, short)]
+        output: Option<std::path::PathBuf>,
+        /// Pack a second time and fail unless both archives are byte-identical
+        #[arg(long)]
+        verify_reproducible: bool,
+        #[command(flatten)]
+        progress: progress::ProgressArgs,
+    },
+    /// Archive all managed bundles, generated files and state (move apps to another machine).
+    Export {
+        /// Archive to write (.tar.gz)
+        output: std::path::PathBuf,
+        /// Only include each bundle's config.toml, not its files
+        #[arg(long)]
+        configs_only: bool,
+        #[command(flatten)]
+        progress: progress::ProgressArgs,
+    },
+    /// Restore bundles from an `export` archive, then sync to regenerate menu entries and profiles.
+    Import {
+        /// Archive written by `dotlnx export`
+        archive: std::path::PathBuf,
+        /// Overwrite bundles that already exist
+        #[arg(long)]
+        force: bool,
+        #[command(flatten)]
+        progress: progress::ProgressArgs,
+    },
+    /// Turn menu entries installed by hand into bundles in your Applications folder and hide the originals
+    Adopt {
+        /// Entries to adopt, by .desktop file name or Name (default: all, asking about each)
+        entries: Vec<String>,
+        /// Adopt without asking
+        #[arg(long)]
+        yes: bool,
+        /// Only list what would be adopted
+        #[arg(long)]
+        dry_run: bool,
     },
+    /// Fetch new AppImages for bundles with [update] appimage_zsync = true (as root: system tier)
+    Update {
+        /// App names (default: every opted-in bundle)
+        names: Vec<String>,
+        /// Only print what would be updated, and from where
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Go back to the AppImage (and, with [update] snapshot_data, the data) an app had before its last update
+    Rollback {
+        /// App name
+        name: String,
+        /// List the updates that can be rolled back instead
+        #[arg(long)]
+        list: bool,
+    },
+    /// Copy .lnx bundles (e.g. from Downloads) into your Applications folder after validating them
+    Install {
+        /// .lnx bundle directories, or bundles packed with `dotlnx pack` (.lnx.tar.zst, .lnx.tar.gz)
+        #[arg(required = true)]
+        paths: Vec<std::path::PathBuf>,
+        /// Change nothing; exit 2 if a bundle would be installed, 0 if all are already there
+        #[arg(long)]
+        check: bool,
+        #[command(flatten)]
+        out: output::OutputArgs,
+        #[command(flatten)]
+        progress: progress::ProgressArgs,
+    },
+    /// Add "Install with dotlnx" / "Validate bundle" to the Nautilus and Dolphin right-click menu
+    IntegrateFilemanager {
+        /// Remove the menu entries again
+        #[arg(long)]
+        remove: bool,
+    },
+    /// Add bundles to Steam as non-Steam game shortcuts (Big Picture). Run as the Steam user; restart Steam afterwards.
+    SteamExport {
+        /// App names to export (default: all bundles; dotlnx shortcuts for removed bundles are dropped)
+        names: Vec<String>,
+    },
+    /// Show a tray icon with installed apps, recent sync results and quick actions (needs yad).
+    Tray {
+        /// Run one menu action instead of the icon (used by the tray menu)
+        #[arg(long, value_enum, hide = true)]
+        action: Option<tray::Action>,
+    },
+    /// Prompt when AppArmor blocks one of your apps: allow the path until reboot (via pkexec) or stop asking. Run as the desktop user.
+    DenialAgent {
+        /// Only print denials, do not prompt
+        #[arg(long)]
+        no_prompt: bool,
+    },
+    /// Interactive terminal console: list apps across tiers; validate, inspect, run, uninstall, view logs
+    #[cfg(feature = "tui")]
+    Tui,
+    /// Internal: start a container bundle's image in its bubblewrap sandbox (called by its run.sh).
+    #[command(name = "__oci-run", hide = true)]
+    OciRun {
+        bundle: std::path::PathBuf,
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Internal: perform one file operation as the invoking (unprivileged) user. Spawned by the root daemon.
+    #[command(name = "__as-user", hide = true)]
+    AsUser,
 }
 
 fn main() {
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
-        )
-        .with_writer(std::io::stderr)
-        .init();
+    let cli = Cli::parse();
+    let logged = logging::init(
+        &cli.log,
+        cli.color
+            .enabled(std::io::IsTerminal::is_terminal(&std::io::stderr())),
+    );
+    output::init(cli.color);
 
-    if let Err(e) = run() {
-        tracing::error!("{}", e);
+    if let Err(e) = logged.and_then(|()| run(cli)) {
+        match e.downcast_ref::<i18n::Message>() {
+            Some(m) => tracing::error!(message_id = m.id, "{}", e),
+            None => tracing::error!("{}", e),
+        }
         std::process::exit(1);
     }
 }
 
-fn run() -> Result<()> {
-    let cli = Cli::parse();
+fn run(cli: Cli) -> Result<()> {
+    if cli.low_memory {
+        // Read by settings::load, here and in the helper processes dotlnx starts.
+        std::env::set_var("DOTLNX_LOW_MEMORY", "1");
+    }
     match cli.command {
-        Commands::Sync { dry_run } => crate::sync::run(dry_run),
+        Commands::Sync {
+            dry_run,
+            check,
+            out,
+            force,
+        } => {
+            let summary = crate::sync::run_forcing(dry_run || check, force)?;
+            if out.json() {
+                output::json(&summary)?;
+            }
+            exit_for_check(check, summary.changed)
+        }
+        Commands::Status { out } => status::run(out.json()),
         Commands::Watch { once } => crate::watch::run(once),
-        Commands::Run { name } => run_app(&name),
-        Commands::Validate { path } => crate::validate::run(&path),
-        Commands::Uninstall { name } => uninstall::run(&name),
+        Commands::Run {
+            name,
+            args,
+            report_writes,
+            wait,
+            check,
+            output,
+        } => {
+            // Nobody sees stderr of a menu launch: show why it failed in a dialog instead.
+            let menu = launchlog::from_menu();
+            let limit = match (wait, check) {
+                (Some(secs), _) => Some(std::time::Duration::from_secs(secs)),
+                (None, true) => Some(DEFAULT_CHECK_WAIT),
+                (None, false) => None,
+            };
+            let opts = RunOptions {
+                report_writes,
+                limit,
+                check,
+                output,
+                menu,
+            };
+            run_app(&name, &args, &opts).inspect_err(|e| {
+                if menu {
+                    launchlog::refused(&name, e);
+                }
+            })
+        }
+        Commands::Logs { name, lines } => launchlog::run(name.as_deref(), lines),
+        Commands::Open { name } => open::open(&name),
+        Commands::Reveal { name } => open::reveal(&name),
+        Commands::Edit { name } => open::edit(&name),
+        Commands::Validate {
+            path,
+            check_deps,
+            fix,
+            out,
+        } => crate::validate::run(&path, check_deps, fix, out.json()),
+        Commands::Uninstall { name, check, out } => {
+            let change = uninstall::run(&name, check)?;
+            if out.json() {
+                output::json(&output::Changes::new(change.as_slice()))?;
+            }
+            exit_for_check(check, change.is_some())
+        }
+        Commands::Grant { name, rules, until } => grant::grant(&name, &rules, until),
+        Commands::Revoke { name, rules } => grant::revoke(&name, &rules),
+        Commands::Hold { name } => hold::hold(name.as_deref()),
+        Commands::Unhold { name } => hold::unhold(&name),
+        Commands::Info { name, out } => info::run(&name, out.json()),
+        Commands::List { out } => list::run(out.json()),
+        Commands::Permissions { out } => permissions::run(out.json()),
+        Commands::Report { out } => report::run(out.json()),
+        Commands::Search { query, out } => search::run(&query, out.json()),
+        Commands::Diff {
+            bundle,
+            other,
+            against_installed,
+            check,
+        } => {
+            let changed = diff::run(&bundle, other.as_deref(), against_installed)?;
+            exit_for_check(check, changed)
+        }
+        Commands::Gc { check, out } => {
+            let changes = gc::run(check)?;
+            if out.json() {
+                output::json(&output::Changes::new(&changes))?;
+            }
+            exit_for_check(check, !changes.is_empty())
+        }
         Commands::Bundle {
             appname,
             appimage,
             bin,
+            oci,
+            wine,
+            wine_program,
+            builtin_launcher,
+            desktop_file,
+            metainfo,
             output_dir,
-        } => bundler::run(&appname, appimage.as_deref(), bin.as_deref(), &output_dir),
+            progress,
+        } => bundler::run(
+            &appname,
+            appimage.as_deref(),
+            bin.as_deref(),
+            oci.as_deref(),
+            wine.as_deref(),
+            wine_program.as_deref(),
+            builtin_launcher,
+            desktop_file.as_deref(),
+            metainfo.as_deref(),
+            &output_dir,
+            progress,
+        ),
+        Commands::Pack {
+            path,
+            output,
+            verify_reproducible,
+            progress,
+        } => pack::run(&path, output.as_deref(), verify_reproducible, progress),
+        Commands::Export {
+            output,
+            configs_only,
+            progress,
+        } => backup::export(&output, configs_only, progress),
+        Commands::Import {
+            archive,
+            force,
+            progress,
+        } => backup::import(&archive, force, progress),
+        Commands::Adopt {
+            entries,
+            yes,
+            dry_run,
+        } => adopt::run(&entries, yes, dry_run),
+        Commands::Update { names, dry_run } => update::run(&names, dry_run),
+        Commands::Rollback { name, list } => snapshot::run(&name, list),
+        Commands::Install {
+            paths,
+            check,
+            out,
+            progress,
+        } => {
+            let changes = install::run(&paths, check, progress)?;
+            if out.json() {
+                output::json(&output::Changes::new(&changes))?;
+            }
+            exit_for_check(check, !changes.is_empty())
+        }
+        Commands::IntegrateFilemanager { remove } => filemanager::run(remove),
+        Commands::SteamExport { names } => steam::run(&names),
+        Commands::Tray { action } => tray::run(action),
+        Commands::DenialAgent { no_prompt } => denials::run(!no_prompt),
+        #[cfg(feature = "tui")]
+        Commands::Tui => tui::run(),
+        Commands::OciRun { bundle, args } => oci::run(&bundle, &args),
+        Commands::AsUser => asuser::serve(),
     }
 }
 
-fn run_app(name: &str) -> Result<()> {
-    let (bundle_path, config, is_user_tier) = match crate::bundle::resolve_bundle_by_name(name)? {
-        Some(t) => t,
-        None => anyhow::bail!("app not found: {}", name),
-    };
-    let profile = if is_user_tier {
-        let username = crate::bundle::username_from_bundle_path(&bundle_path)
-            .unwrap_or_else(|| std::env::var("USER").unwrap_or_else(|_| "unknown".into()));
-        crate::apparmor::profile_name_safe(&username, &config.name)
-    } else {
-        crate::apparmor::profile_name_safe_system(&config.name)
-    };
-    let exec_path = bundle_path.join(&config.executable);
+/// `--check` exit status: 2 when something would change, so config management tools can report
+/// "changed" without parsing output (0 means already in the desired state).
+fn exit_for_check(check: bool, changed: bool) -> Result<()> {
+    if check && changed {
+        std::process::exit(2);
+    }
+    Ok(())
+}
+
+/// How long `dotlnx run --check` lets an app run when --wait is not given.
+const DEFAULT_CHECK_WAIT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Flags of `dotlnx run` beyond the app and its args.
+struct RunOptions {
+    report_writes: bool,
+    /// --wait (or the --check default): stop the app after this long.
+    limit: Option<std::time::Duration>,
+    check: bool,
+    /// --output: where the app's stdout and stderr go.
+    output: Option<std::path::PathBuf>,
+    /// Started from the menu (see `launchlog::from_menu`).
+    menu: bool,
+}
+
+fn run_app(name: &str, extra_args: &[String], opts: &RunOptions) -> Result<()> {
+    let settings = crate::settings::load()?;
+    let paths = crate::paths::Paths::from_settings(&settings.paths);
+    let (bundle_path, mut config, is_user_tier) =
+        match crate::bundle::resolve_bundle_by_name(&paths, name)? {
+            Some(t) => t,
+            None => {
+                return Err(
+                    i18n::Message::new("app-not-found", &[("name", name.to_string())]).into(),
+                )
+            }
+        };
+    crate::policy::apply_override(&mut config, &settings.apparmor.overrides_dir)?;
+    if !is_user_tier {
+        crate::access::check_launch(&config)?;
+    }
+    settings.run.apply(&mut config);
+    let username = is_user_tier.then(|| {
+        crate::bundle::username_from_bundle_path(&bundle_path)
+            .unwrap_or_else(|| std::env::var("USER").unwrap_or_else(|_| "unknown".into()))
+    });
+    let profile = paths.profile_name(username.as_deref(), &config.app_id());
+    let exec_path = crate::bundle::resolve_executable(&bundle_path, &config.executable);
     if !exec_path.exists() {
-        anyhow::bail!("executable not found: {}", exec_path.display());
+        anyhow::bail!(i18n::Message::new(
+            "executable-not-found",
+            &[("path", exec_path.display().to_string())]
+        ));
     }
     crate::validate::path_under_bundle(&exec_path, &bundle_path)?;
     let cwd = config
@@ -140,29 +660,97 @@ fn run_app(name: &str) -> Result<()> {
             crate::validate::path_under_bundle(&cwd_resolved, &bundle_path)?;
         }
     }
-    let mut env: Vec<(String, String)> = config
-        .env
-        .iter()
-        .filter_map(|s| {
-            let (k, v) = s.split_once('=')?;
-            Some((k.trim().into(), v.trim().into()))
-        })
-        .collect();
-    // Ensure PATH includes bundle bin if present
-    let bin_dir = bundle_path.join("bin");
-    if bin_dir.exists() {
-        let path = std::env::var_os("PATH")
-            .and_then(|p| p.into_string().ok())
-            .unwrap_or_default();
-        let new_path = format!("{}:{}", bin_dir.display(), path);
-        env.push(("PATH".into(), new_path));
-    }
+    let env = runenv::LaunchEnv::build(&config, &bundle_path, std::env::vars());
+    let filter = match config.security.as_ref().and_then(|s| s.seccomp.as_ref()) {
+        Some(mode) => Some(seccomp::compile(mode, &bundle_path)?),
+        None => None,
+    };
+    let process = runner::ProcessSettings::from_config(&config);
+    let log = opts
+        .menu
+        .then(|| launchlog::Launch::begin(&config.app_id()))
+        .flatten();
+    let output = match opts.output {
+        Some(ref path) => Some(
+            std::fs::File::create(path).with_context(|| format!("create {}", path.display()))?,
+        ),
+        None => None,
+    };
+    let home = match config.security.as_ref().filter(|s| s.ephemeral_home) {
+        Some(sec) => Some(ephemeral::ScratchHome::create(
+            &config.app_id(),
+            &sec.persist_paths,
+        )?),
+        None => None,
+    };
+    let prepare = |cmd: &mut std::process::Command| {
+        if let Some(ref log) = log {
+            log.attach(cmd);
+        }
+        if let Some(ref file) = output {
+            if let (Ok(out), Ok(err)) = (file.try_clone(), file.try_clone()) {
+                cmd.stdout(out).stderr(err);
+            }
+        }
+        env.apply(cmd);
+        if let Some(ref home) = home {
+            cmd.envs(home.vars());
+        }
+        process.install(cmd);
+        if let Some(ref f) = filter {
+            seccomp::install(cmd, f.clone());
+        }
+    };
+    let args: Vec<String> = config.args.iter().chain(extra_args).cloned().collect();
     let confine = config.security.as_ref().map(|s| s.confine).unwrap_or(true);
-    let status = if confine {
-        run_with_profile(&profile, &exec_path, &config.args, &cwd, &env)?
+    let monitor = match opts.report_writes {
+        true => Some(
+            writemon::WriteMonitor::start(&bundle_path)
+                .with_context(|| format!("watch {} for writes", bundle_path.display()))?,
+        ),
+        false => None,
+    };
+    let started = std::time::Instant::now();
+    let ended = if confine {
+        run_with_profile(&profile, &exec_path, &args, &cwd, &prepare, opts.limit)
     } else {
-        run_unconfined(&exec_path, &config.args, &cwd, &env)?
+        run_unconfined(&exec_path, &args, &cwd, &prepare, opts.limit)
     };
+    if let Some(home) = home {
+        home.remove();
+    }
+    let ended = ended?;
+    if let Some(monitor) = monitor {
+        writemon::report(&config.name, &monitor.finish(&config.exclude));
+    }
+    let status = match ended {
+        runner::Ended::Exited(status) => status,
+        runner::Ended::Stopped => {
+            if opts.check {
+                let limit = opts.limit.unwrap_or_default();
+                println!(
+                    "ok: {} still running after {}s",
+                    config.app_id(),
+                    limit.as_secs()
+                );
+            }
+            std::process::exit(if opts.check { 0 } else { 124 });
+        }
+    };
+    if let Some(log) = log {
+        log.finish(status);
+    }
+    if opts.check {
+        let verdict = if status.success() { "ok" } else { "failed" };
+        println!(
+            "{}: {} exited ({}) after {:.1}s",
+            verdict,
+            config.app_id(),
+            status,
+            started.elapsed().as_secs_f64()
+        );
+        std::process::exit(if status.success() { 0 } else { 1 });
+    }
     std::process::exit(status.code().unwrap_or(1));
 }
 
@@ -171,14 +759,13 @@ fn run_unconfined(
     exec_path: &std::path::Path,
     args: &[String],
     cwd: &std::path::Path,
-    env: &[(String, String)],
-) -> Result<std::process::ExitStatus> {
+    prepare: &dyn Fn(&mut std::process::Command),
+    limit: Option<std::time::Duration>,
+) -> Result<runner::Ended> {
     let mut cmd = std::process::Command::new(exec_path);
     cmd.args(args).current_dir(cwd);
-    for (k, v) in env {
-        cmd.env(k, v);
-    }
-    Ok(cmd.status()?)
+    prepare(&mut cmd);
+    Ok(launch(&mut cmd, limit)?)
 }
 
 /// Run executable under AppArmor profile via aa-exec; if aa-exec is unavailable, run without confinement.
@@ -187,16 +774,15 @@ fn run_with_profile(
     exec_path: &std::path::Path,
     args: &[String],
     cwd: &std::path::Path,
-    env: &[(String, String)],
-) -> Result<std::process::ExitStatus> {
+    prepare: &dyn Fn(&mut std::process::Command),
+    limit: Option<std::time::Duration>,
+) -> Result<runner::Ended> {
     let mut cmd = std::process::Command::new("aa-exec");
     cmd.args(["-p", profile, "--"]);
     cmd.arg(exec_path).args(args);
     cmd.current_dir(cwd);
-    for (k, v) in env {
-        cmd.env(k, v);
-    }
-    match cmd.status() {
+    prepare(&mut cmd);
+    match launch(&mut cmd, limit) {
         Ok(s) => return Ok(s),
         Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
         Err(e) => return Err(e.into()),
@@ -204,9 +790,23 @@ fn run_with_profile(
     // aa-exec not found (e.g. non-Linux or AppArmor not installed); run without confinement
     let mut fallback = std::process::Command::new(exec_path);
     fallback.args(args).current_dir(cwd);
-    for (k, v) in env {
-        fallback.env(k, v);
-    }
-    Ok(fallback.status()?)
+    prepare(&mut fallback);
+    Ok(launch(&mut fallback, limit)?)
 }
 
+/// Start `cmd` and wait for it (at most `limit`, see [`runner::run_bounded`]). When systemd
+/// passed this process listen sockets (a socket-activated service), the app replaces dotlnx
+/// instead: LISTEN_PID names the process the sockets are for.
+fn launch(
+    cmd: &mut std::process::Command,
+    limit: Option<std::time::Duration>,
+) -> std::io::Result<runner::Ended> {
+    use std::os::unix::process::CommandExt;
+    if std::env::var("LISTEN_PID").is_ok_and(|pid| pid == std::process::id().to_string()) {
+        return Err(cmd.exec());
+    }
+    match limit {
+        Some(limit) => runner::run_bounded(cmd, limit),
+        None => cmd.status().map(runner::Ended::Exited),
+    }
+}
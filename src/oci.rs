@@ -0,0 +1,806 @@
+//! Container images as bundles. `dotlnx bundle --oci <ref>` pulls the image with skopeo into an
+//! OCI layout, applies its layers into the bundle's rootfs/ and records the image's entrypoint,
+//! env and working dir in oci.json. The bundle's run.sh calls `dotlnx __oci-run`, which starts
+//! the entrypoint in a bubblewrap sandbox built from the bundle's [security] section at launch
+//! time, so editing config.toml changes the sandbox without regenerating anything.
+
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use crate::config::{self, Security};
+
+/// Image metadata file inside the bundle.
+pub const IMAGE_FILE: &str = "oci.json";
+/// Extracted image filesystem inside the bundle.
+pub const ROOTFS_DIR: &str = "rootfs";
+
+/// Transports skopeo understands; anything else is treated as a registry reference.
+const TRANSPORTS: &[&str] = &[
+    "docker://",
+    "docker-archive:",
+    "docker-daemon:",
+    "oci:",
+    "oci-archive:",
+    "containers-storage:",
+    "dir:",
+];
+
+/// What the bundle needs from the image config to start it.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageConfig {
+    /// Reference the image was pulled from.
+    pub image: String,
+    #[serde(default)]
+    pub entrypoint: Vec<String>,
+    #[serde(default)]
+    pub cmd: Vec<String>,
+    /// KEY=VALUE pairs from the image (PATH in particular).
+    #[serde(default)]
+    pub env: Vec<String>,
+    #[serde(default)]
+    pub working_dir: Option<String>,
+}
+
+/// Add the docker:// transport to plain registry references ("alpine:3", "ghcr.io/x/y:tag").
+pub fn normalize_reference(reference: &str) -> String {
+    if TRANSPORTS.iter().any(|t| reference.starts_with(t)) {
+        reference.to_string()
+    } else {
+        format!("docker://{}", reference)
+    }
+}
+
+/// Pull `reference` and unpack it into `bundle_root`/rootfs. Returns the image config to store.
+pub fn pull_into(reference: &str, bundle_root: &Path) -> Result<ImageConfig> {
+    let reference = normalize_reference(reference);
+    // A fresh private dir (removed on drop), so nobody can plant a layout at a guessable path.
+    let tmp = tempfile::Builder::new()
+        .prefix("dotlnx-oci-")
+        .tempdir()
+        .context("create temporary dir for the image")?;
+    let mut image = pull_via(&reference, &tmp.path().join("layout"), bundle_root)?;
+    image.image = reference;
+    Ok(image)
+}
+
+fn pull_via(reference: &str, layout: &Path, bundle_root: &Path) -> Result<ImageConfig> {
+    let dest = format!("oci:{}:image", layout.display());
+    tracing::info!(image = %reference, "pulling with skopeo");
+    let status = std::process::Command::new("skopeo")
+        .args(["copy", reference, &dest])
+        .status()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                anyhow::anyhow!("skopeo not found; install it to bundle container images")
+            }
+            _ => anyhow::anyhow!("run skopeo: {}", e),
+        })?;
+    if !status.success() {
+        anyhow::bail!("skopeo copy {} failed ({})", reference, status);
+    }
+    unpack_layout(layout, &bundle_root.join(ROOTFS_DIR))
+}
+
+#[derive(Deserialize)]
+struct Index {
+    manifests: Vec<Descriptor>,
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    config: Descriptor,
+    layers: Vec<Descriptor>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    #[serde(default)]
+    media_type: String,
+    digest: String,
+}
+
+#[derive(Deserialize)]
+struct ConfigBlob {
+    #[serde(default)]
+    config: RuntimeConfig,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RuntimeConfig {
+    #[serde(default)]
+    entrypoint: Option<Vec<String>>,
+    #[serde(default)]
+    cmd: Option<Vec<String>>,
+    #[serde(default)]
+    env: Option<Vec<String>>,
+    #[serde(default)]
+    working_dir: Option<String>,
+}
+
+fn blob_path(layout: &Path, digest: &str) -> Result<PathBuf> {
+    let (algo, hex) = digest
+        .split_once(':')
+        .ok_or_else(|| anyhow::anyhow!("malformed digest {:?}", digest))?;
+    if !algo.chars().all(|c| c.is_ascii_alphanumeric())
+        || !hex.chars().all(|c| c.is_ascii_hexdigit())
+    {
+        anyhow::bail!("malformed digest {:?}", digest);
+    }
+    Ok(layout.join("blobs").join(algo).join(hex))
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+    serde_json::from_str(&text).with_context(|| format!("parse {}", path.display()))
+}
+
+/// Apply every layer of the single image in an OCI layout to `rootfs`, in order.
+fn unpack_layout(layout: &Path, rootfs: &Path) -> Result<ImageConfig> {
+    let index: Index = read_json(&layout.join("index.json"))?;
+    let [ref desc] = index.manifests[..] else {
+        anyhow::bail!(
+            "expected one image in the OCI layout, found {}",
+            index.manifests.len()
+        );
+    };
+    let manifest: Manifest = read_json(&blob_path(layout, &desc.digest)?)?;
+    let blob: ConfigBlob = read_json(&blob_path(layout, &manifest.config.digest)?)?;
+    std::fs::create_dir_all(rootfs)?;
+    for layer in &manifest.layers {
+        let file = std::fs::File::open(blob_path(layout, &layer.digest)?)?;
+        let reader: Box<dyn Read> =
+            if layer.media_type.ends_with("+gzip") || layer.media_type.ends_with(".tar.gzip") {
+                Box::new(GzDecoder::new(file))
+            } else if layer.media_type.ends_with(".tar") {
+                Box::new(file)
+            } else {
+                anyhow::bail!(
+                    "unsupported layer type {} (only tar and tar+gzip layers can be unpacked)",
+                    layer.media_type
+                );
+            };
+        apply_layer(reader, rootfs).with_context(|| format!("apply layer {}", layer.digest))?;
+    }
+    let rc = blob.config;
+    Ok(ImageConfig {
+        image: String::new(),
+        entrypoint: rc.entrypoint.unwrap_or_default(),
+        cmd: rc.cmd.unwrap_or_default(),
+        env: rc.env.unwrap_or_default(),
+        working_dir: rc.working_dir.filter(|d| !d.is_empty()),
+    })
+}
+
+/// Unpack one layer tarball over `rootfs`, honouring OCI whiteouts (`.wh.<name>` deletes a file
+/// from lower layers, `.wh..wh..opq` empties its directory). Device nodes are skipped (the sandbox
+/// provides /dev) and setuid/setgid and group/other write bits are dropped.
+fn apply_layer(reader: impl Read, rootfs: &Path) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    archive.set_preserve_permissions(false);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let rel = relative_entry_path(&entry.path()?)?;
+        let Some(file_name) = rel.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let parent = rel.parent().unwrap_or(Path::new(""));
+        if file_name == ".wh..wh..opq" {
+            let dir = rootfs.join(parent);
+            crate::safefs::refuse_symlinked_parents(rootfs, &dir.join("x"))?;
+            if dir.is_dir() {
+                for child in std::fs::read_dir(&dir)? {
+                    remove_any(&child?.path())?;
+                }
+            }
+            continue;
+        }
+        if let Some(hidden) = file_name.strip_prefix(".wh.") {
+            let target = rootfs.join(parent).join(hidden);
+            crate::safefs::refuse_symlinked_parents(rootfs, &target)?;
+            remove_any(&target)?;
+            continue;
+        }
+        let kind = entry.header().entry_type();
+        if kind.is_character_special() || kind.is_block_special() || kind.is_fifo() {
+            continue;
+        }
+        let dest = rootfs.join(&rel);
+        crate::safefs::refuse_symlinked_parents(rootfs, &dest)?;
+        // A later layer may replace a directory with a file or the other way round.
+        if let Ok(meta) = std::fs::symlink_metadata(&dest) {
+            if meta.is_dir() != kind.is_dir() || meta.file_type().is_symlink() {
+                remove_any(&dest)?;
+            }
+        }
+        let mode = entry.header().mode().unwrap_or(0o644);
+        entry
+            .unpack_in(rootfs)
+            .with_context(|| format!("unpack {}", rel.display()))?;
+        #[cfg(unix)]
+        if kind.is_file() || kind.is_dir() {
+            use std::os::unix::fs::PermissionsExt;
+            let owner_bits = if kind.is_dir() { 0o700 } else { 0o600 };
+            std::fs::set_permissions(
+                &dest,
+                std::fs::Permissions::from_mode((mode & 0o755) | owner_bits),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Layer paths are relative to the image root; "./" and a leading "/" are accepted, ".." is not.
+fn relative_entry_path(path: &Path) -> Result<PathBuf> {
+    let mut rel = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::Normal(p) => rel.push(p),
+            Component::CurDir | Component::RootDir => {}
+            _ => anyhow::bail!("refusing layer entry {}", path.display()),
+        }
+    }
+    Ok(rel)
+}
+
+fn remove_any(path: &Path) -> Result<()> {
+    let meta = match std::fs::symlink_metadata(path) {
+        Ok(m) => m,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    if meta.is_dir() {
+        std::fs::remove_dir_all(path)?;
+    } else {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Host session details passed into the sandbox.
+#[derive(Debug, Default)]
+pub struct HostEnv {
+    pub home: Option<String>,
+    pub runtime_dir: Option<String>,
+    pub display: Option<String>,
+    pub wayland_display: Option<String>,
+    pub lang: Option<String>,
+    pub term: Option<String>,
+    pub ssh_auth_sock: Option<String>,
+    /// The app's `.flatpak-info` shim on the host (see [`write_portal_info`]).
+    pub portal_info: Option<String>,
+}
+
+impl HostEnv {
+    fn from_env() -> Self {
+        let var = |k: &str| std::env::var(k).ok().filter(|v| !v.is_empty());
+        Self {
+            home: var("HOME"),
+            runtime_dir: var("XDG_RUNTIME_DIR"),
+            display: var("DISPLAY"),
+            wayland_display: var("WAYLAND_DISPLAY"),
+            lang: var("LANG"),
+            term: var("TERM"),
+            ssh_auth_sock: var("SSH_AUTH_SOCK"),
+            portal_info: None,
+        }
+    }
+}
+
+/// Application ID portals see for an app: `dotlnx.` and its name, with anything a D-Bus name
+/// element cannot hold replaced by `_`.
+fn portal_app_id(name: &str) -> String {
+    let mut id: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if id.starts_with(|c: char| c.is_ascii_digit()) {
+        id.insert(0, '_');
+    }
+    format!("dotlnx.{}", id)
+}
+
+/// Write the `.flatpak-info` file bound at the sandbox's root when [security] portals is set.
+/// xdg-desktop-portal reads it from the app's root to tell a sandboxed app from a host one: a
+/// sandboxed app gets the files the user picks through the document portal
+/// ($XDG_RUNTIME_DIR/doc), instead of a host path its sandbox cannot reach.
+fn write_portal_info(runtime_dir: &str, name: &str) -> Result<String> {
+    let dir = Path::new(runtime_dir).join("dotlnx");
+    std::fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+    let id = portal_app_id(name);
+    let path = dir.join(format!("{}.flatpak-info", id));
+    std::fs::write(&path, format!("[Application]\nname={}\n", id))
+        .with_context(|| format!("write {}", path.display()))?;
+    Ok(path.display().to_string())
+}
+
+/// The host's Video4Linux device nodes (/dev/video*, /dev/media*).
+fn video_devices() -> Vec<String> {
+    let mut devices: Vec<String> = std::fs::read_dir("/dev")
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().into_string().ok())
+        .filter(|n| n.starts_with("video") || n.starts_with("media"))
+        .map(|n| format!("/dev/{}", n))
+        .collect();
+    devices.sort();
+    devices
+}
+
+/// Host path for a [security] path entry, or None for patterns bwrap cannot bind (globs).
+fn bind_path(pattern: &str, bundle_root: &Path, home: Option<&str>) -> Option<String> {
+    let expanded = crate::apparmor::expand_bundle_var(pattern, bundle_root);
+    let mut p = expanded
+        .trim_end_matches("/**")
+        .trim_end_matches("/*")
+        .to_string();
+    if let Some(home) = home {
+        if let Some(rest) = p.strip_prefix("@{HOME}") {
+            p = format!("{}{}", home, rest);
+        } else if let Some(rest) = p.strip_prefix('~') {
+            p = format!("{}{}", home, rest);
+        }
+    }
+    (p.starts_with('/') && !p.contains(['*', '?', '[', '{'])).then_some(p)
+}
+
+/// bwrap arguments for starting the image: read-only rootfs, private /tmp, a fresh HOME, no
+/// network unless [security] network = true, read_paths/write_paths bound from the host,
+/// GPU/sound/video devices and sockets when gpu/audio/v4l/camera/screen_capture are set, the
+/// CUPS socket for printing, removable media read-only, the SSH agent socket for ssh_agent, the
+/// session bus for portals and secrets (and accessibility, with the accessibility bus's socket
+/// directory), and the document portal and the app's `.flatpak-info`
+/// when portals is set.
+/// `bundle_env` (config.toml env) is applied after the image's own env.
+pub fn bwrap_args(
+    bundle_root: &Path,
+    image: &ImageConfig,
+    security: Option<&Security>,
+    bundle_env: &[String],
+    host: &HostEnv,
+    user_args: &[String],
+) -> Vec<OsString> {
+    let mut args: Vec<OsString> = Vec::new();
+    let mut push = |items: &[&str]| args.extend(items.iter().map(OsString::from));
+    let rootfs = bundle_root.join(ROOTFS_DIR).display().to_string();
+    push(&["--die-with-parent", "--unshare-all"]);
+    if security.is_some_and(|s| s.network) {
+        push(&[
+            "--share-net",
+            "--ro-bind-try",
+            "/etc/resolv.conf",
+            "/etc/resolv.conf",
+        ]);
+    }
+    push(&["--ro-bind", &rootfs, "/"]);
+    push(&[
+        "--proc", "/proc", "--dev", "/dev", "--tmpfs", "/tmp", "--tmpfs", "/run",
+    ]);
+    push(&["--clearenv"]);
+    let home = host.home.as_deref().unwrap_or("/home/app");
+    push(&["--tmpfs", home, "--setenv", "HOME", home]);
+
+    // GUI sockets: X11 and Wayland.
+    if let Some(display) = host.display.as_deref() {
+        push(&["--ro-bind-try", "/tmp/.X11-unix", "/tmp/.X11-unix"]);
+        push(&["--setenv", "DISPLAY", display]);
+    }
+    if let Some(runtime_dir) = host.runtime_dir.as_deref() {
+        push(&[
+            "--dir",
+            runtime_dir,
+            "--setenv",
+            "XDG_RUNTIME_DIR",
+            runtime_dir,
+        ]);
+        if let Some(wayland) = host.wayland_display.as_deref() {
+            let socket = format!("{}/{}", runtime_dir, wayland);
+            push(&["--ro-bind-try", &socket, &socket]);
+            push(&["--setenv", "WAYLAND_DISPLAY", wayland]);
+        }
+    }
+    for (key, value) in [("LANG", &host.lang), ("TERM", &host.term)] {
+        if let Some(v) = value.as_deref() {
+            push(&["--setenv", key, v]);
+        }
+    }
+
+    if let Some(sec) = security {
+        let bind = |p: &String| bind_path(p, bundle_root, host.home.as_deref());
+        for p in sec.read_paths.iter().filter_map(bind) {
+            push(&["--ro-bind-try", &p, &p]);
+        }
+        for p in sec.write_paths.iter().filter_map(bind) {
+            push(&["--bind-try", &p, &p]);
+        }
+        if sec.gpu {
+            push(&["--dev-bind-try", "/dev/dri", "/dev/dri"]);
+            push(&["--ro-bind-try", "/sys/dev/char", "/sys/dev/char"]);
+            push(&["--ro-bind-try", "/sys/devices", "/sys/devices"]);
+        }
+        if sec.audio {
+            push(&["--dev-bind-try", "/dev/snd", "/dev/snd"]);
+            if let Some(runtime_dir) = host.runtime_dir.as_deref() {
+                for socket in ["pulse/native", "pipewire-0"] {
+                    let path = format!("{}/{}", runtime_dir, socket);
+                    push(&["--ro-bind-try", &path, &path]);
+                }
+            }
+        }
+        if sec.v4l || sec.camera {
+            for device in video_devices() {
+                push(&["--dev-bind-try", &device, &device]);
+            }
+            push(&[
+                "--ro-bind-try",
+                "/sys/class/video4linux",
+                "/sys/class/video4linux",
+            ]);
+        }
+        if sec.camera || sec.screen_capture {
+            if let Some(runtime_dir) = host.runtime_dir.as_deref() {
+                let path = format!("{}/pipewire-0", runtime_dir);
+                push(&["--ro-bind-try", &path, &path]);
+            }
+        }
+        if sec.printing {
+            push(&[
+                "--ro-bind-try",
+                "/run/cups/cups.sock",
+                "/run/cups/cups.sock",
+            ]);
+            push(&[
+                "--ro-bind-try",
+                "/etc/cups/client.conf",
+                "/etc/cups/client.conf",
+            ]);
+        }
+        if sec.removable_media {
+            push(&["--ro-bind-try", "/media", "/media"]);
+            push(&["--ro-bind-try", "/run/media", "/run/media"]);
+            if let Some(runtime_dir) = host.runtime_dir.as_deref() {
+                let gvfs = format!("{}/gvfs", runtime_dir);
+                push(&["--ro-bind-try", &gvfs, &gvfs]);
+            }
+        }
+        if let (Some(sock), true) = (host.ssh_auth_sock.as_deref(), sec.ssh_agent) {
+            push(&[
+                "--ro-bind-try",
+                sock,
+                sock,
+                "--setenv",
+                "SSH_AUTH_SOCK",
+                sock,
+            ]);
+        }
+        if let (Some(runtime_dir), true) = (
+            host.runtime_dir.as_deref(),
+            sec.portals || sec.secrets || sec.accessibility,
+        ) {
+            let bus = format!("{}/bus", runtime_dir);
+            push(&["--ro-bind-try", &bus, &bus]);
+            push(&[
+                "--setenv",
+                "DBUS_SESSION_BUS_ADDRESS",
+                &format!("unix:path={}", bus),
+            ]);
+        }
+        if let (Some(runtime_dir), true) = (host.runtime_dir.as_deref(), sec.accessibility) {
+            let at_spi = format!("{}/at-spi", runtime_dir);
+            push(&["--ro-bind-try", &at_spi, &at_spi]);
+        }
+        if sec.portals {
+            if let Some(runtime_dir) = host.runtime_dir.as_deref() {
+                let doc = format!("{}/doc", runtime_dir);
+                push(&["--bind-try", &doc, &doc]);
+            }
+            if let Some(info) = host.portal_info.as_deref() {
+                push(&["--ro-bind", info, "/.flatpak-info"]);
+            }
+        }
+    }
+
+    let mut has_path = false;
+    for kv in image.env.iter().chain(bundle_env) {
+        if let Some((k, v)) = kv.split_once('=') {
+            let k = k.trim();
+            has_path |= k == "PATH";
+            push(&["--setenv", k, v.trim()]);
+        }
+    }
+    if !has_path {
+        push(&[
+            "--setenv",
+            "PATH",
+            "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin",
+        ]);
+    }
+    push(&["--chdir", image.working_dir.as_deref().unwrap_or("/")]);
+    push(&["--"]);
+    // Docker semantics: arguments replace Cmd, Entrypoint stays.
+    let cmd = if user_args.is_empty() {
+        &image.cmd
+    } else {
+        user_args
+    };
+    args.extend(image.entrypoint.iter().chain(cmd).map(OsString::from));
+    args
+}
+
+/// Entry point for `dotlnx __oci-run <bundle> [args...]` (called by the bundle's run.sh).
+pub fn run(bundle_root: &Path, user_args: &[String]) -> Result<()> {
+    let cfg = config::load(bundle_root)?;
+    let image: ImageConfig = read_json(&bundle_root.join(IMAGE_FILE))?;
+    if image.entrypoint.is_empty() && image.cmd.is_empty() && user_args.is_empty() {
+        anyhow::bail!("image {} has no entrypoint or command", image.image);
+    }
+    let mut host = HostEnv::from_env();
+    if let (Some(runtime_dir), true) = (
+        &host.runtime_dir,
+        cfg.security.as_ref().is_some_and(|s| s.portals),
+    ) {
+        host.portal_info = Some(write_portal_info(runtime_dir, &cfg.name)?);
+    }
+    let args = bwrap_args(
+        bundle_root,
+        &image,
+        cfg.security.as_ref(),
+        &cfg.env,
+        &host,
+        user_args,
+    );
+    let status = std::process::Command::new("bwrap")
+        .args(&args)
+        .status()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                anyhow::anyhow!("bwrap not found; install bubblewrap to run container bundles")
+            }
+            _ => anyhow::anyhow!("run bwrap: {}", e),
+        })?;
+    std::process::exit(status.code().unwrap_or(1));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(entries: &[(&str, Option<&[u8]>)]) -> Vec<u8> {
+        let mut b = tar::Builder::new(Vec::new());
+        for (path, data) in entries {
+            let mut h = tar::Header::new_gnu();
+            match data {
+                Some(d) => {
+                    h.set_entry_type(tar::EntryType::Regular);
+                    h.set_size(d.len() as u64);
+                    h.set_mode(0o4755);
+                    b.append_data(&mut h, path, *d).unwrap();
+                }
+                None => {
+                    h.set_entry_type(tar::EntryType::Directory);
+                    h.set_size(0);
+                    h.set_mode(0o777);
+                    b.append_data(&mut h, path, std::io::empty()).unwrap();
+                }
+            }
+        }
+        b.into_inner().unwrap()
+    }
+
+    #[test]
+    fn normalize_reference_adds_docker_transport() {
+        assert_eq!(normalize_reference("alpine:3"), "docker://alpine:3");
+        assert_eq!(normalize_reference("docker://x/y:1"), "docker://x/y:1");
+        assert_eq!(
+            normalize_reference("oci-archive:/tmp/x.tar"),
+            "oci-archive:/tmp/x.tar"
+        );
+    }
+
+    #[test]
+    fn layers_apply_with_whiteouts() {
+        let tmp = tempfile::tempdir().unwrap();
+        let rootfs = tmp.path().join("rootfs");
+        std::fs::create_dir_all(&rootfs).unwrap();
+        let lower = layer(&[
+            ("etc", None),
+            ("etc/keep", Some(b"k")),
+            ("etc/gone", Some(b"g")),
+            ("opt", None),
+            ("opt/old", Some(b"o")),
+        ]);
+        apply_layer(lower.as_slice(), &rootfs).unwrap();
+        let upper = layer(&[
+            ("etc/.wh.gone", Some(b"")),
+            ("opt/.wh..wh..opq", Some(b"")),
+            ("opt/new", Some(b"n")),
+        ]);
+        apply_layer(upper.as_slice(), &rootfs).unwrap();
+        assert!(rootfs.join("etc/keep").exists());
+        assert!(!rootfs.join("etc/gone").exists());
+        assert!(!rootfs.join("opt/old").exists());
+        assert!(rootfs.join("opt/new").exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |p: &str| {
+                std::fs::metadata(rootfs.join(p))
+                    .unwrap()
+                    .permissions()
+                    .mode()
+            };
+            assert_eq!(mode("etc/keep") & 0o7777, 0o755);
+            assert_eq!(mode("etc") & 0o7777, 0o755);
+        }
+    }
+
+    #[test]
+    fn unpack_layout_reads_manifest_and_config() {
+        use std::io::Write;
+        let tmp = tempfile::tempdir().unwrap();
+        let layout = tmp.path().join("layout");
+        let blobs = layout.join("blobs/sha256");
+        std::fs::create_dir_all(&blobs).unwrap();
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(&layer(&[("bin", None), ("bin/tool", Some(b"#!/bin/sh"))]))
+            .unwrap();
+        std::fs::write(blobs.join("aa"), gz.finish().unwrap()).unwrap();
+        std::fs::write(
+            blobs.join("bb"),
+            r#"{"config":{"Entrypoint":["/bin/tool"],"Env":["PATH=/bin"],"WorkingDir":""}}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            blobs.join("cc"),
+            r#"{"config":{"digest":"sha256:bb"},"layers":[{"mediaType":"application/vnd.oci.image.layer.v1.tar+gzip","digest":"sha256:aa"}]}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            layout.join("index.json"),
+            r#"{"manifests":[{"mediaType":"application/vnd.oci.image.manifest.v1+json","digest":"sha256:cc"}]}"#,
+        )
+        .unwrap();
+        let rootfs = tmp.path().join("rootfs");
+        let image = unpack_layout(&layout, &rootfs).unwrap();
+        assert_eq!(image.entrypoint, vec!["/bin/tool".to_string()]);
+        assert_eq!(image.working_dir, None);
+        assert!(rootfs.join("bin/tool").is_file());
+        assert!(blob_path(&layout, "sha256:../../x").is_err());
+    }
+
+    #[test]
+    fn whiteout_through_symlink_is_refused() {
+        let tmp = tempfile::tempdir().unwrap();
+        let rootfs = tmp.path().join("rootfs");
+        let outside = tmp.path().join("outside");
+        std::fs::create_dir_all(&rootfs).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(outside.join("victim"), "x").unwrap();
+        std::os::unix::fs::symlink(&outside, rootfs.join("etc")).unwrap();
+        let upper = layer(&[("etc/.wh.victim", Some(b""))]);
+        assert!(apply_layer(upper.as_slice(), &rootfs).is_err());
+        assert!(outside.join("victim").exists());
+    }
+
+    #[test]
+    fn bwrap_args_follow_security() {
+        let image = ImageConfig {
+            image: "docker://x".into(),
+            entrypoint: vec!["/app".into()],
+            cmd: vec!["--serve".into()],
+            env: vec!["PATH=/bin".into()],
+            working_dir: Some("/work".into()),
+        };
+        let host = HostEnv {
+            home: Some("/home/alice".into()),
+            ..Default::default()
+        };
+        let strs = |a: Vec<OsString>| -> Vec<String> {
+            a.into_iter().map(|s| s.into_string().unwrap()).collect()
+        };
+        let args = strs(bwrap_args(
+            Path::new("/b.lnx"),
+            &image,
+            None,
+            &[],
+            &host,
+            &[],
+        ));
+        assert!(!args.contains(&"--share-net".to_string()));
+        assert!(args
+            .windows(3)
+            .any(|w| w == ["--ro-bind", "/b.lnx/rootfs", "/"]));
+        assert!(args.ends_with(&["--".into(), "/app".into(), "--serve".into()]));
+
+        let sec = Security {
+            network: true,
+            gpu: true,
+            write_paths: vec!["@{HOME}/Documents/**".into(), "/srv/*.log".into()],
+            ..Default::default()
+        };
+        let args = strs(bwrap_args(
+            Path::new("/b.lnx"),
+            &image,
+            Some(&sec),
+            &[],
+            &host,
+            &["x".into()],
+        ));
+        assert!(args.contains(&"--share-net".to_string()));
+        assert!(args
+            .windows(3)
+            .any(|w| w == ["--dev-bind-try", "/dev/dri", "/dev/dri"]));
+        assert!(args.windows(3).any(|w| w
+            == [
+                "--bind-try",
+                "/home/alice/Documents",
+                "/home/alice/Documents"
+            ]));
+        assert!(!args.iter().any(|a| a.contains("*.log")));
+        assert!(args.ends_with(&["--".into(), "/app".into(), "x".into()]));
+        assert!(!args.iter().any(|a| a.contains("bus")));
+
+        let sec = Security {
+            portals: true,
+            ..Default::default()
+        };
+        let host = HostEnv {
+            runtime_dir: Some("/run/user/1000".into()),
+            portal_info: Some("/run/user/1000/dotlnx/dotlnx.My_App.flatpak-info".into()),
+            ..host
+        };
+        let args = strs(bwrap_args(
+            Path::new("/b.lnx"),
+            &image,
+            Some(&sec),
+            &[],
+            &host,
+            &[],
+        ));
+        assert!(args
+            .windows(3)
+            .any(|w| w == ["--ro-bind-try", "/run/user/1000/bus", "/run/user/1000/bus"]));
+        assert!(args
+            .windows(3)
+            .any(|w| w == ["--bind-try", "/run/user/1000/doc", "/run/user/1000/doc"]));
+        assert!(args.windows(3).any(|w| w
+            == [
+                "--ro-bind",
+                "/run/user/1000/dotlnx/dotlnx.My_App.flatpak-info",
+                "/.flatpak-info"
+            ]));
+        assert!(!args.iter().any(|a| a.contains("pipewire")));
+        let sec = Security {
+            screen_capture: true,
+            ..Default::default()
+        };
+        let args = strs(bwrap_args(
+            Path::new("/b.lnx"),
+            &image,
+            Some(&sec),
+            &[],
+            &host,
+            &[],
+        ));
+        assert!(args.windows(3).any(|w| w
+            == [
+                "--ro-bind-try",
+                "/run/user/1000/pipewire-0",
+                "/run/user/1000/pipewire-0"
+            ]));
+        assert_eq!(portal_app_id("My App"), "dotlnx.My_App");
+        assert_eq!(portal_app_id("7zip"), "dotlnx._7zip");
+    }
+}
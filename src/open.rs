@@ -0,0 +1,172 @@
+//! `dotlnx open`, `dotlnx reveal`, `dotlnx edit`: desktop conveniences around a bundle found by
+//! app name (same lookup as `dotlnx run`: user tier first, then /Applications).
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tracing::{error, info};
+
+use crate::access;
+use crate::bundle;
+use crate::config;
+use crate::paths::{self, Paths};
+use crate::sync;
+use crate::validate;
+use crate::watch;
+
+fn resolve(paths: &Paths, name: &str) -> Result<(PathBuf, config::Config, bool)> {
+    bundle::resolve_bundle_by_name(paths, name)?.ok_or_else(|| {
+        crate::i18n::Message::new("app-not-found", &[("name", name.to_string())]).into()
+    })
+}
+
+/// The generated menu entry for a resolved bundle.
+pub(crate) fn desktop_file(
+    paths: &Paths,
+    cfg: &config::Config,
+    is_user_tier: bool,
+) -> Result<PathBuf> {
+    let dir = if is_user_tier {
+        paths.user_desktop_dir()?
+    } else if access::is_restricted(cfg) {
+        paths.user_desktop_dir()?.join(access::MEMBER_MENU_DIR)
+    } else {
+        paths.system_desktop.clone()
+    };
+    Ok(dir.join(paths.desktop_file_name(&dir, &cfg.app_id())))
+}
+
+/// Start `cmd` without waiting for it or tying it to this terminal's output.
+fn spawn_detached(cmd: &mut Command) -> std::io::Result<()> {
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map(|_| ())
+}
+
+/// Entry point for `dotlnx open <name>`: launch the app the way the menu does (through its
+/// .desktop entry, so confinement and startup notification match) and return right away.
+/// Falls back to a background `dotlnx run` when the entry is not installed yet.
+pub fn open(name: &str) -> Result<()> {
+    let paths = paths::load()?;
+    let (_, mut cfg, is_user_tier) = resolve(&paths, name)?;
+    if !is_user_tier {
+        // Say why here: the launched app's own errors go nowhere.
+        let settings = crate::settings::load()?;
+        crate::policy::apply_override(&mut cfg, &settings.apparmor.overrides_dir)?;
+        access::check_launch(&cfg)?;
+    }
+    let entry = desktop_file(&paths, &cfg, is_user_tier)?;
+    if entry.is_file() && spawn_detached(Command::new("gio").arg("launch").arg(&entry)).is_ok() {
+        info!(app = %cfg.name, "launched");
+        return Ok(());
+    }
+    let exe = std::env::current_exe().context("locate dotlnx binary")?;
+    spawn_detached(Command::new(exe).arg("run").arg(cfg.app_id()))
+        .with_context(|| format!("launch {}", cfg.name))?;
+    info!(app = %cfg.name, "launched");
+    Ok(())
+}
+
+/// Entry point for `dotlnx reveal <name>`: show the bundle folder in the file manager, selected in
+/// its Applications folder when the file manager supports org.freedesktop.FileManager1.
+pub fn reveal(name: &str) -> Result<()> {
+    let (bundle_path, _, _) = resolve(&paths::load()?, name)?;
+    let shown = Command::new("gdbus")
+        .args([
+            "call",
+            "--session",
+            "--dest",
+            "org.freedesktop.FileManager1",
+            "--object-path",
+            "/org/freedesktop/FileManager1",
+            "--method",
+            "org.freedesktop.FileManager1.ShowItems",
+        ])
+        .arg(format!("['{}']", file_uri(&bundle_path)))
+        .arg("")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success());
+    if !shown {
+        spawn_detached(Command::new("xdg-open").arg(&bundle_path))
+            .context("open the bundle folder (is xdg-open installed?)")?;
+    }
+    info!(bundle = %bundle_path.display(), "revealed");
+    Ok(())
+}
+
+/// `file://` URI for an absolute path, percent-encoding everything but unreserved characters
+/// and `/` (also keeps the URI free of quotes for the GVariant string above).
+fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for b in path.as_os_str().as_encoded_bytes() {
+        match *b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(*b as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    uri
+}
+
+/// Entry point for `dotlnx edit <name>`: open config.toml in $VISUAL/$EDITOR, then validate and
+/// resync so the menu entry and profile follow the edit.
+pub fn edit(name: &str) -> Result<()> {
+    let (bundle_path, _, _) = resolve(&paths::load()?, name)?;
+    let config_path = bundle_path.join("config.toml");
+    let editor = editor();
+    // Through sh, like git: EDITOR may carry arguments ("code --wait").
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(&config_path)
+        .status()
+        .with_context(|| format!("run editor {:?}", editor))?;
+    if !status.success() {
+        anyhow::bail!("editor {:?} exited with {}; not syncing", editor, status);
+    }
+    if let Err(e) = validate::validate_bundle(&bundle_path) {
+        error!(
+            "{} is not valid after the edit; fix it with `dotlnx edit {}`",
+            config_path.display(),
+            name
+        );
+        return Err(e);
+    }
+    if bundle::is_root() || !watch::service_active() {
+        sync::run(false)?;
+    } else {
+        // The service watches each bundle's config.toml and re-syncs by itself.
+        info!("saved; the dotlnx service picks up the change");
+    }
+    Ok(())
+}
+
+/// $VISUAL, then $EDITOR, then vi.
+fn editor() -> String {
+    ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|k| std::env::var(k).ok())
+        .find(|v| !v.trim().is_empty())
+        .unwrap_or_else(|| "vi".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_uri_percent_encodes() {
+        assert_eq!(
+            file_uri(Path::new("/home/a/Applications/My App.lnx")),
+            "file:///home/a/Applications/My%20App.lnx"
+        );
+        assert_eq!(file_uri(Path::new("/x/it's")), "file:///x/it%27s");
+        assert_eq!(file_uri(Path::new("/x/é")), "file:///x/%C3%A9");
+    }
+}
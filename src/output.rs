@@ -0,0 +1,234 @@
+//! Results printed for people and scripts, as opposed to tracing's diagnostics on stderr. Text
+//! results are aligned tables and label/value blocks, coloured when stdout is a terminal (and
+//! NO_COLOR is unset); `--format json` prints the same result as JSON instead. `--color` and
+//! `--format` mean the same on every subcommand that prints a result. (`--output` is left to
+//! the subcommands that write a file: `run --output FILE`, `pack --output PATH`.)
+
+use anyhow::Result;
+use serde::Serialize;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// `--color`: when to colour text output and diagnostics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Color {
+    /// On a terminal, unless NO_COLOR is set or TERM is dumb
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl Color {
+    /// Whether to colour a stream that is (or is not) a terminal.
+    pub fn enabled(self, terminal: bool) -> bool {
+        match self {
+            Color::Always => true,
+            Color::Never => false,
+            Color::Auto => {
+                terminal
+                    && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                    && std::env::var_os("TERM").is_none_or(|t| t != "dumb")
+            }
+        }
+    }
+}
+
+/// `--format`: the format of a subcommand's result.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+}
+
+/// `--format` and its `--json` shorthand, for each subcommand that prints a result.
+#[derive(Debug, Clone, Copy, Default, clap::Args)]
+pub struct OutputArgs {
+    /// Result format: text (aligned, coloured on a terminal) or json
+    #[arg(long = "format", value_enum, value_name = "FORMAT", default_value_t = Format::Text)]
+    pub format: Format,
+    /// Print the result as JSON (same as --format json)
+    #[arg(long)]
+    pub json: bool,
+}
+
+impl OutputArgs {
+    pub fn json(&self) -> bool {
+        self.json || self.format == Format::Json
+    }
+}
+
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Decide once, at startup, whether stdout gets colours.
+pub fn init(color: Color) {
+    COLOR.store(
+        color.enabled(std::io::stdout().is_terminal()),
+        Ordering::Relaxed,
+    );
+}
+
+/// What a value means, for its colour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Plain,
+    /// In place, working.
+    Good,
+    /// Works, but not as it should (not loaded, unconfined).
+    Warn,
+    /// Missing or failed.
+    Bad,
+    /// Headers and labels.
+    Heading,
+}
+
+impl Style {
+    fn code(self) -> Option<&'static str> {
+        match self {
+            Style::Plain => None,
+            Style::Good => Some("32"),
+            Style::Warn => Some("33"),
+            Style::Bad => Some("31"),
+            Style::Heading => Some("1"),
+        }
+    }
+}
+
+/// `text` in `style` when stdout gets colours.
+pub fn paint(text: &str, style: Style) -> String {
+    match style.code() {
+        Some(code) if COLOR.load(Ordering::Relaxed) && !text.is_empty() => {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        }
+        _ => text.to_string(),
+    }
+}
+
+/// Print `value` as pretty JSON.
+pub fn json<T: Serialize>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// The `--json` result of a subcommand that installs or removes apps (or would, with
+/// `--check`): the `changed` and `changes` fields of `sync --json`.
+#[derive(Debug, Serialize)]
+pub struct Changes<'a, T> {
+    pub changed: bool,
+    pub changes: &'a [T],
+}
+
+impl<'a, T> Changes<'a, T> {
+    pub fn new(changes: &'a [T]) -> Self {
+        Changes {
+            changed: !changes.is_empty(),
+            changes,
+        }
+    }
+}
+
+/// Rows under a header, each column as wide as its widest cell. The last column is not padded,
+/// so long paths do not push trailing spaces into the output.
+#[derive(Debug)]
+pub struct Table {
+    rows: Vec<Vec<(String, Style)>>,
+}
+
+impl Table {
+    pub fn new(header: &[&str]) -> Self {
+        Table {
+            rows: vec![header
+                .iter()
+                .map(|h| (h.to_string(), Style::Heading))
+                .collect()],
+        }
+    }
+
+    pub fn row(&mut self, cells: Vec<(String, Style)>) {
+        self.rows.push(cells);
+    }
+
+    /// The lines of the table, coloured when stdout gets colours.
+    pub fn lines(&self) -> Vec<String> {
+        let columns = self.rows.iter().map(Vec::len).max().unwrap_or(0);
+        let widths: Vec<usize> = (0..columns)
+            .map(|i| {
+                self.rows
+                    .iter()
+                    .filter_map(|r| r.get(i))
+                    .map(|(c, _)| c.chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        self.rows
+            .iter()
+            .map(|row| {
+                let mut line = String::new();
+                for (i, (cell, style)) in row.iter().enumerate() {
+                    line.push_str(&paint(cell, *style));
+                    if i + 1 < row.len() {
+                        line.push_str(&" ".repeat(widths[i] - cell.chars().count() + 2));
+                    }
+                }
+                line
+            })
+            .collect()
+    }
+
+    pub fn print(&self) {
+        for line in self.lines() {
+            println!("{}", line);
+        }
+    }
+}
+
+/// Print `Label: value` lines with the values lined up.
+pub fn fields(rows: &[(&str, String, Style)]) {
+    let width = rows
+        .iter()
+        .map(|(label, _, _)| label.chars().count() + 1)
+        .max()
+        .unwrap_or(0);
+    for (label, value, style) in rows {
+        let label = format!("{}:", label);
+        let pad = " ".repeat(width - label.chars().count() + 1);
+        println!(
+            "{}{}{}",
+            paint(&label, Style::Heading),
+            pad,
+            paint(value, *style)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tables_align_columns_but_not_the_last() {
+        let mut table = Table::new(&["NAME", "TIER", "BUNDLE"]);
+        table.row(vec![
+            ("Editor".into(), Style::Plain),
+            ("user".into(), Style::Good),
+            ("/a/Editor.lnx".into(), Style::Plain),
+        ]);
+        table.row(vec![
+            ("Go".into(), Style::Plain),
+            ("system".into(), Style::Bad),
+            ("/b".into(), Style::Plain),
+        ]);
+        assert_eq!(
+            table.lines(),
+            [
+                "NAME    TIER    BUNDLE",
+                "Editor  user    /a/Editor.lnx",
+                "Go      system  /b"
+            ]
+        );
+        assert!(!Color::Auto.enabled(false));
+        assert!(Color::Always.enabled(false) && !Color::Never.enabled(true));
+    }
+}
@@ -0,0 +1,974 @@
+//! `dotlnx pack`: archive one .lnx bundle (`<Name>.lnx.tar.zst`) for distribution, and
+//! [`unpack`], which turns such an archive (or a zipped bundle, `<Name>.lnx.zip`) back into a
+//! bundle folder. `dotlnx install` takes archives too, and sync unpacks the ones dropped into an
+//! Applications dir ([`extract_dropped`]).
+//!
+//! The archive is reproducible: packing the same files gives the same bytes, whatever their
+//! mtimes, owners, permission bits beyond "executable or not", or the order the file system lists
+//! them in. So a release can be rebuilt and checked against its signature or attestation.
+//!
+//! - entries are sorted by name, the bundle directory first (`MyApp.lnx/...`)
+//! - mtime is `$SOURCE_DATE_EPOCH` when set, otherwise 0
+//! - owner is 0:0 without names; mode is 0755 for directories and executables, 0644 otherwise
+//! - the tar stream goes through the `zstd` tool, or gzip (with no name or time in its header)
+//!   when the archive is named `.tar.gz`
+//! - paths matching the config's `exclude` patterns (the app's own cache or logs) are left out
+//!
+//! Bytes may still differ between dotlnx (and zstd) versions, so verify with the versions that
+//! packed the release. `--verify-reproducible` packs twice and compares.
+
+use anyhow::{Context, Result};
+use flate2::write::GzEncoder;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use tracing::{debug, info, warn};
+
+use crate::progress::{self, Progress, ProgressArgs};
+use crate::validate;
+use crate::zipfile;
+
+/// zstd level for packed bundles: most of the gain of the high levels at a speed that suits
+/// multi-gigabyte AppImages.
+const ZSTD_LEVEL: &str = "-12";
+/// How long a dropped archive's size and mtime must hold still before sync unpacks it.
+const SETTLE_CHECK: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// How an archive is compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Zstd,
+    Gzip,
+}
+
+impl Compression {
+    /// From the archive's name: `.tar.gz`/`.tgz` is gzip, anything else zstd.
+    pub fn from_name(path: &Path) -> Self {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            true => Compression::Gzip,
+            false => Compression::Zstd,
+        }
+    }
+
+    /// From the archive's first bytes.
+    fn sniff(path: &Path) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        std::fs::File::open(path)
+            .and_then(|mut f| f.read_exact(&mut magic))
+            .with_context(|| format!("read {}", path.display()))?;
+        match magic {
+            [0x28, 0xb5, 0x2f, 0xfd] => Ok(Compression::Zstd),
+            [0x1f, 0x8b, ..] => Ok(Compression::Gzip),
+            _ => anyhow::bail!("{} is not a zstd or gzip archive", path.display()),
+        }
+    }
+}
+
+/// True when `path` is named like a packed bundle (`<Name>.lnx.tar.zst` or `.lnx.tar.gz`) or a
+/// zipped one (`<Name>.lnx.zip`).
+pub fn is_packed_bundle(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    [".lnx.tar.zst", ".lnx.tar.gz", ".lnx.tgz", ".lnx.zip"]
+        .iter()
+        .any(|ext| name.ends_with(ext))
+}
+
+/// Packed bundles dropped straight into `apps_dir` (regular files, not hidden), for sync to
+/// unpack with [`extract_dropped`]. Archives whose size or mtime changes over [`SETTLE_CHECK`]
+/// are still being copied in and are left for a later sync.
+pub fn dropped_archives(apps_dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(apps_dir) else {
+        return Vec::new();
+    };
+    let out: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+        .map(|e| e.path())
+        .filter(|p| {
+            is_packed_bundle(p)
+                && !p
+                    .file_name()
+                    .is_some_and(|n| n.to_string_lossy().starts_with('.'))
+        })
+        .collect();
+    if out.is_empty() {
+        return out;
+    }
+    let stamp = |p: &Path| {
+        std::fs::metadata(p)
+            .ok()
+            .map(|m| (m.len(), m.modified().ok()))
+    };
+    let before: Vec<_> = out.iter().map(|p| stamp(p)).collect();
+    std::thread::sleep(SETTLE_CHECK);
+    let mut out: Vec<PathBuf> = out
+        .into_iter()
+        .zip(before)
+        .filter(|(p, before)| {
+            let stable = before.is_some() && stamp(p) == *before;
+            if !stable {
+                debug!(archive = %p.display(), "archive is still being written; leaving it for the next sync");
+            }
+            stable
+        })
+        .map(|(p, _)| p)
+        .collect();
+    out.sort();
+    out
+}
+
+/// Turn a packed bundle dropped into its Applications dir into a bundle folder next to it:
+/// unpack it, validate the bundle, then delete the archive. When that fails, nothing is left
+/// unpacked and the archive is renamed to `<file>.failed`, so later syncs do not retry it; one
+/// that ends early ([`is_truncated`]) is left as it is for the next sync to try again.
+pub fn extract_dropped(archive: &Path) -> Result<PathBuf> {
+    let apps_dir = archive
+        .parent()
+        .context("archive has no parent directory")?;
+    let file_name = archive
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let staging = apps_dir.join(format!(".{}.dotlnx-tmp", file_name));
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging)?;
+    }
+    let extracted = std::fs::create_dir(&staging)
+        .with_context(|| format!("create {}", staging.display()))
+        .and_then(|()| unpack(archive, &staging))
+        .and_then(|bundle| {
+            validate::validate_bundle(&bundle)?;
+            let dest = apps_dir.join(bundle.file_name().unwrap_or_default());
+            if dest.exists() {
+                anyhow::bail!("{} already exists; remove it first", dest.display());
+            }
+            std::fs::rename(&bundle, &dest)?;
+            Ok(dest)
+        });
+    let _ = std::fs::remove_dir_all(&staging);
+    match extracted {
+        Ok(dest) => {
+            std::fs::remove_file(archive)
+                .with_context(|| format!("remove {}", archive.display()))?;
+            Ok(dest)
+        }
+        // Cut short (or still arriving despite the settle check): try again on the next sync
+        // rather than setting it aside for good.
+        Err(e) if is_truncated(&e) => Err(e),
+        Err(e) => {
+            let failed = apps_dir.join(format!("{}.failed", file_name));
+            if let Err(re) = std::fs::rename(archive, &failed) {
+                warn!(archive = %archive.display(), "cannot rename to {}: {}", failed.display(), re);
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Entry point for `dotlnx pack <bundle>`.
+pub fn run(
+    bundle: &Path,
+    output: Option<&Path>,
+    verify_reproducible: bool,
+    progress_args: ProgressArgs,
+) -> Result<()> {
+    validate::validate_bundle(bundle)?;
+    let exclude = crate::config::load(bundle)?.exclude;
+    let dir_name = bundle
+        .file_name()
+        .and_then(|n| n.to_str())
+        .context("bundle path has no name")?;
+    let output = match output {
+        Some(p) => p.to_path_buf(),
+        None => PathBuf::from(format!("{}.tar.zst", dir_name)),
+    };
+    if std::path::absolute(&output)?.starts_with(std::path::absolute(bundle)?) {
+        anyhow::bail!("the archive must be written outside the bundle");
+    }
+    let compression = Compression::from_name(&output);
+    let mtime = source_date_epoch()?;
+    let size = progress::tree_size(bundle);
+    let progress = Progress::new(
+        progress_args,
+        "pack",
+        if verify_reproducible { 2 * size } else { size },
+    );
+    progress.set_item(dir_name);
+
+    let file =
+        std::fs::File::create(&output).with_context(|| format!("create {}", output.display()))?;
+    write_archive(bundle, &exclude, mtime, compression, file, &progress)?.sync_all()?;
+    if verify_reproducible {
+        let written = std::fs::File::open(&output)?;
+        let expected = Compare::new(std::io::BufReader::new(written));
+        let compare = write_archive(bundle, &exclude, mtime, compression, expected, &progress)?;
+        if let Some(offset) = compare.finish()? {
+            progress.finish();
+            anyhow::bail!(
+                "packing {} twice gave different archives (first difference at byte {}); \
+                 is something writing into the bundle?",
+                bundle.display(),
+                offset
+            );
+        }
+    }
+    progress.finish();
+    info!(archive = %output.display(), verified = verify_reproducible, "packed");
+    Ok(())
+}
+
+/// `$SOURCE_DATE_EPOCH` (https://reproducible-builds.org/specs/source-date-epoch/), or 0.
+fn source_date_epoch() -> Result<u64> {
+    match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(s) if !s.is_empty() => s
+            .trim()
+            .parse()
+            .with_context(|| format!("SOURCE_DATE_EPOCH is not a number of seconds: {:?}", s)),
+        _ => Ok(0),
+    }
+}
+
+/// Write the archive of `bundle` (without `exclude`d paths) to `out`; returns `out` once the
+/// compressed stream is finished.
+pub fn write_archive<W: Write + Send + 'static>(
+    bundle: &Path,
+    exclude: &[String],
+    mtime: u64,
+    compression: Compression,
+    out: W,
+    progress: &Progress,
+) -> Result<W> {
+    let dir_name = bundle.file_name().context("bundle path has no name")?;
+    let encoder = match compression {
+        // GzEncoder::new writes a header without file name or mtime.
+        Compression::Gzip => Encoder::Gzip(GzEncoder::new(out, flate2::Compression::default())),
+        Compression::Zstd => Encoder::Zstd(ZstdWriter::new(out)?),
+    };
+    let mut tar = tar::Builder::new(encoder);
+    let entries = walkdir::WalkDir::new(bundle)
+        .follow_links(false)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| {
+            let relative = e.path().strip_prefix(bundle).unwrap_or(e.path());
+            !crate::bundle::is_excluded(exclude, relative)
+        });
+    for entry in entries {
+        let entry = entry?;
+        let relative = entry.path().strip_prefix(bundle)?;
+        let name = match relative.as_os_str().is_empty() {
+            true => PathBuf::from(dir_name),
+            false => Path::new(dir_name).join(relative),
+        };
+        let meta = entry.path().symlink_metadata()?;
+        let mut header = tar::Header::new_gnu();
+        header.set_mtime(mtime);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_username("")?;
+        header.set_groupname("")?;
+        let file_type = meta.file_type();
+        if file_type.is_dir() {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_mode(0o755);
+            header.set_size(0);
+            tar.append_data(&mut header, &name, std::io::empty())?;
+        } else if file_type.is_symlink() {
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_mode(0o777);
+            header.set_size(0);
+            let target = std::fs::read_link(entry.path())?;
+            tar.append_link(&mut header, &name, &target)?;
+        } else if file_type.is_file() {
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_mode(if is_executable(&meta) { 0o755 } else { 0o644 });
+            header.set_size(meta.len());
+            let file = std::fs::File::open(entry.path())
+                .with_context(|| format!("read {}", entry.path().display()))?;
+            // A file that changes size while being read would corrupt the archive; tar fails on
+            // short reads, and the take() stops long ones.
+            tar.append_data(&mut header, &name, progress.reader(file).take(meta.len()))?;
+        } else {
+            anyhow::bail!(
+                "cannot pack {} (not a file, directory or symlink)",
+                entry.path().display()
+            );
+        }
+    }
+    tar.into_inner()?.finish()
+}
+
+/// Compressor behind the tar stream.
+enum Encoder<W: Write + Send + 'static> {
+    Gzip(GzEncoder<W>),
+    Zstd(ZstdWriter<W>),
+}
+
+impl<W: Write + Send + 'static> Encoder<W> {
+    fn finish(self) -> Result<W> {
+        match self {
+            Encoder::Gzip(gz) => Ok(gz.finish()?),
+            Encoder::Zstd(zstd) => zstd.finish(),
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Encoder::Gzip(gz) => gz.write(buf),
+            Encoder::Zstd(zstd) => zstd.stdin.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Encoder::Gzip(gz) => gz.flush(),
+            Encoder::Zstd(zstd) => zstd.stdin.flush(),
+        }
+    }
+}
+
+/// Compresses through the `zstd` tool: what is written goes to its stdin, and a thread copies
+/// its output to the wrapped writer.
+struct ZstdWriter<W> {
+    child: Child,
+    stdin: ChildStdin,
+    copier: std::thread::JoinHandle<std::io::Result<W>>,
+}
+
+impl<W: Write + Send + 'static> ZstdWriter<W> {
+    fn new(mut out: W) -> Result<Self> {
+        let mut child = Command::new("zstd")
+            .args(["-q", "-c", ZSTD_LEVEL])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("run zstd (install the zstd package, or pack to a .tar.gz archive)")?;
+        let stdin = child.stdin.take().context("zstd stdin")?;
+        let mut stdout = child.stdout.take().context("zstd stdout")?;
+        let copier = std::thread::spawn(move || std::io::copy(&mut stdout, &mut out).map(|_| out));
+        Ok(Self {
+            child,
+            stdin,
+            copier,
+        })
+    }
+
+    fn finish(mut self) -> Result<W> {
+        drop(self.stdin);
+        let out = self
+            .copier
+            .join()
+            .map_err(|_| anyhow::anyhow!("zstd output thread panicked"))??;
+        let status = self.child.wait()?;
+        if !status.success() {
+            anyhow::bail!("zstd failed ({})", status);
+        }
+        Ok(out)
+    }
+}
+
+/// Unpack a packed bundle (or a zipped one) into `into`; returns the new bundle folder. The
+/// archive must hold one `<Name>.lnx` folder of files, directories and symlinks; group and other
+/// write bits (and setuid bits) are dropped. The bundle is unpacked under a hidden name and
+/// renamed into place, so a watcher on `into` never sees it half-written.
+pub fn unpack(archive: &Path, into: &Path) -> Result<PathBuf> {
+    if zipfile::is_zip(archive)? {
+        let mut staging = Staging::new(archive, into);
+        let unpacked = unzip(archive, &mut staging);
+        return staging.finish(unpacked);
+    }
+    let mut zstd = None;
+    let reader: Box<dyn Read> = match Compression::sniff(archive)? {
+        Compression::Gzip => Box::new(flate2::read::GzDecoder::new(std::fs::File::open(archive)?)),
+        Compression::Zstd => {
+            let child = zstd.insert(
+                Command::new("zstd")
+                    .args(["-q", "-d", "-c", "--"])
+                    .arg(archive)
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()
+                    .context("run zstd (install the zstd package)")?,
+            );
+            Box::new(child.stdout.take().context("zstd stdout")?)
+        }
+    };
+    let mut tar = tar::Archive::new(reader);
+    tar.set_preserve_permissions(false);
+    tar.set_mask(0o022);
+    let mut staging = Staging::new(archive, into);
+    let unpacked = (|| -> Result<()> {
+        for entry in tar.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.into_owned();
+            let kind = entry.header().entry_type();
+            if !(kind.is_file() || kind.is_dir() || kind.is_symlink()) {
+                anyhow::bail!(
+                    "{}: {} is not a file, directory or symlink",
+                    archive.display(),
+                    path.display()
+                );
+            }
+            if let Some(dir) = staging.enter(&path)? {
+                entry
+                    .unpack_in(dir)
+                    .with_context(|| format!("unpack {}", path.display()))?;
+            }
+        }
+        Ok(())
+    })();
+    let mut reader = tar.into_inner();
+    // Read on past the end-of-archive blocks so gzip checks its trailer and zstd its frame.
+    let drained = match unpacked {
+        Ok(()) => std::io::copy(&mut reader, &mut std::io::sink())
+            .map(|_| ())
+            .context("read archive"),
+        Err(_) => Ok(()),
+    };
+    drop(reader);
+    let finished = match zstd {
+        Some(mut child) => {
+            if unpacked.is_err() {
+                // Stopped partway: zstd may be blocked writing to a pipe nobody reads any more.
+                let _ = child.kill();
+            }
+            child
+                .wait()
+                .map_err(anyhow::Error::from)
+                .and_then(|status| match status.success() {
+                    true => Ok(()),
+                    false => {
+                        let mut stderr = String::new();
+                        if let Some(mut pipe) = child.stderr.take() {
+                            let _ = pipe.read_to_string(&mut stderr);
+                        }
+                        Err(zstd_failed(status, &stderr))
+                    }
+                })
+        }
+        None => Ok(()),
+    };
+    // zstd running out of input explains whatever tar made of the cut-off stream.
+    let result = match finished {
+        Err(e) if is_truncated(&e) => Err(e),
+        finished => unpacked.and(drained).and(finished),
+    };
+    staging.finish(result)
+}
+
+/// zstd's failure; an archive that ends early gives an UnexpectedEof error ([`is_truncated`]).
+fn zstd_failed(status: std::process::ExitStatus, stderr: &str) -> anyhow::Error {
+    if stderr.contains("premature end") {
+        return std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "the archive ends early (zstd)",
+        )
+        .into();
+    }
+    match stderr.trim() {
+        "" => anyhow::anyhow!("zstd failed ({})", status),
+        message => anyhow::anyhow!("zstd failed ({}): {}", status, message),
+    }
+}
+
+/// True when unpacking failed because the archive ends early: cut short, or still being written.
+pub fn is_truncated(e: &anyhow::Error) -> bool {
+    e.chain()
+        .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+        .any(|io| io.kind() == std::io::ErrorKind::UnexpectedEof)
+}
+
+/// Write a zip archive's entries under the staging dir, with the modes `pack` would give them.
+fn unzip(archive: &Path, staging: &mut Staging) -> Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+    let mut file = std::fs::File::open(archive)?;
+    for entry in
+        zipfile::entries(&mut file).with_context(|| format!("read {}", archive.display()))?
+    {
+        let path = PathBuf::from(entry.name.trim_end_matches('/'));
+        let Some(dir) = staging.enter(&path)? else {
+            continue;
+        };
+        let dest = dir.join(&path);
+        match entry.kind {
+            zipfile::Kind::Dir => crate::safefs::create_dirs(&dest)?,
+            zipfile::Kind::Symlink => {
+                let mut target = Vec::new();
+                entry.copy_to(&mut file, &mut target)?;
+                let target = String::from_utf8(target)
+                    .with_context(|| format!("{}: symlink target is not UTF-8", entry.name))?;
+                std::os::unix::fs::symlink(target, &dest)
+                    .with_context(|| format!("unpack {}", entry.name))?;
+            }
+            zipfile::Kind::File => {
+                if let Some(parent) = dest.parent() {
+                    crate::safefs::create_dirs(parent)?;
+                }
+                let mut out = std::fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .mode(if entry.executable { 0o755 } else { 0o644 })
+                    .open(&dest)
+                    .with_context(|| format!("unpack {}", entry.name))?;
+                entry.copy_to(&mut file, &mut out)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The hidden dir next to the final bundle that an archive is unpacked into, created for its
+/// first entry.
+struct Staging<'a> {
+    archive: &'a Path,
+    into: &'a Path,
+    /// The bundle folder's name and the staging dir, once the first entry is seen.
+    top: Option<(String, PathBuf)>,
+}
+
+impl<'a> Staging<'a> {
+    fn new(archive: &'a Path, into: &'a Path) -> Self {
+        Self {
+            archive,
+            into,
+            top: None,
+        }
+    }
+
+    /// Check that `path` is inside the archive's one bundle folder and that nothing on the way to
+    /// it is a symlink. Returns the dir to unpack it under, or None for the bundle folder itself.
+    fn enter(&mut self, path: &Path) -> Result<Option<&Path>> {
+        let mut components = path.components();
+        let Some(std::path::Component::Normal(first)) = components.next() else {
+            anyhow::bail!(
+                "{}: entry {} is not inside a bundle folder",
+                self.archive.display(),
+                path.display()
+            );
+        };
+        let first = first.to_string_lossy().into_owned();
+        let name = self
+            .top
+            .as_ref()
+            .map_or(first.as_str(), |(name, _)| name.as_str());
+        if name != first || !name.ends_with(".lnx") || name.starts_with('.') {
+            anyhow::bail!(
+                "{} does not hold one .lnx bundle folder (found {})",
+                self.archive.display(),
+                first
+            );
+        }
+        if components.any(|c| !matches!(c, std::path::Component::Normal(_))) {
+            anyhow::bail!(
+                "{}: entry {} leaves the bundle folder",
+                self.archive.display(),
+                path.display()
+            );
+        }
+        if self.top.is_none() {
+            // Left over from an unpack that was interrupted.
+            let staging = self.into.join(format!(".{}.dotlnx-unpack", first));
+            if staging.exists() {
+                std::fs::remove_dir_all(&staging)?;
+            }
+            std::fs::create_dir_all(staging.join(&first))
+                .with_context(|| format!("create {}", staging.display()))?;
+            self.top = Some((first, staging));
+        }
+        let Some((_, staging)) = &self.top else {
+            unreachable!()
+        };
+        if path.components().count() == 1 {
+            return Ok(None);
+        }
+        crate::safefs::refuse_symlinked_parents(staging, &staging.join(path))?;
+        Ok(Some(staging))
+    }
+
+    /// Move the bundle into place when unpacking went well; remove the staging dir either way.
+    fn finish(self, unpacked: Result<()>) -> Result<PathBuf> {
+        let Some((name, staging)) = self.top else {
+            unpacked?;
+            anyhow::bail!("{} is empty", self.archive.display());
+        };
+        let moved = unpacked.and_then(|()| {
+            let bundle = self.into.join(&name);
+            if bundle.exists() {
+                anyhow::bail!("{} already exists", bundle.display());
+            }
+            std::fs::rename(staging.join(&name), &bundle)?;
+            Ok(bundle)
+        });
+        let _ = std::fs::remove_dir_all(&staging);
+        moved
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(meta: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_meta: &std::fs::Metadata) -> bool {
+    false
+}
+
+/// A writer that compares what is written with an existing stream, for --verify-reproducible.
+struct Compare<R> {
+    expected: R,
+    offset: u64,
+    first_difference: Option<u64>,
+}
+
+impl<R: Read> Compare<R> {
+    fn new(expected: R) -> Self {
+        Self {
+            expected,
+            offset: 0,
+            first_difference: None,
+        }
+    }
+
+    /// Offset of the first byte that differed, or where one stream ended before the other.
+    fn finish(mut self) -> std::io::Result<Option<u64>> {
+        if self.first_difference.is_none() && self.expected.read(&mut [0u8; 1])? != 0 {
+            self.first_difference = Some(self.offset);
+        }
+        Ok(self.first_difference)
+    }
+}
+
+impl<R: Read> Write for Compare<R> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.first_difference.is_none() {
+            let mut expected = vec![0u8; buf.len()];
+            let mut got = 0;
+            while got < buf.len() {
+                match self.expected.read(&mut expected[got..])? {
+                    0 => break,
+                    n => got += n,
+                }
+            }
+            match buf.iter().zip(&expected[..got]).position(|(a, b)| a != b) {
+                Some(i) => self.first_difference = Some(self.offset + i as u64),
+                None if got < buf.len() => self.first_difference = Some(self.offset + got as u64),
+                None => {}
+            }
+        }
+        self.offset += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn same_files_give_the_same_archive() {
+        use std::os::unix::fs::PermissionsExt;
+        let make = |root: &Path, created_in_reverse: bool| {
+            let bundle = root.join("App.lnx");
+            std::fs::create_dir_all(bundle.join("bin")).unwrap();
+            let mut files = vec![
+                ("config.toml", 0o600),
+                ("bin/app", 0o700),
+                ("bin/lib.so", 0o640),
+            ];
+            if created_in_reverse {
+                files.reverse();
+            }
+            for (name, mode) in files {
+                std::fs::write(bundle.join(name), name).unwrap();
+                std::fs::set_permissions(bundle.join(name), std::fs::Permissions::from_mode(mode))
+                    .unwrap();
+            }
+            std::os::unix::fs::symlink("app", bundle.join("bin/link")).unwrap();
+            bundle
+        };
+        let (a, b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let (bundle_a, bundle_b) = (make(a.path(), false), make(b.path(), true));
+        let pack = |bundle: &Path| {
+            write_archive(
+                bundle,
+                &["*.log".into()],
+                0,
+                Compression::Gzip,
+                Vec::new(),
+                &Progress::hidden(),
+            )
+            .unwrap()
+        };
+        let bytes = pack(&bundle_a);
+        std::fs::write(bundle_b.join("bin/app.log"), "started").unwrap();
+        assert_eq!(bytes, pack(&bundle_b));
+
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(&bytes[..]));
+        let entries: Vec<(String, u32)> = archive
+            .entries()
+            .unwrap()
+            .map(|e| {
+                let e = e.unwrap();
+                (
+                    e.path().unwrap().display().to_string(),
+                    e.header().mode().unwrap(),
+                )
+            })
+            .collect();
+        let expected = [
+            ("App.lnx", 0o755),
+            ("App.lnx/bin", 0o755),
+            ("App.lnx/bin/app", 0o755),
+            ("App.lnx/bin/lib.so", 0o644),
+            ("App.lnx/bin/link", 0o777),
+            ("App.lnx/config.toml", 0o644),
+        ];
+        let expected: Vec<(String, u32)> =
+            expected.iter().map(|(p, m)| (p.to_string(), *m)).collect();
+        assert_eq!(entries, expected);
+
+        // Compare spots a changed byte and a stream of a different length.
+        let check = |written: &[u8]| {
+            let mut c = Compare::new(&bytes[..]);
+            c.write_all(written).unwrap();
+            c.finish().unwrap()
+        };
+        assert_eq!(check(&bytes), None);
+        let mut changed = bytes.clone();
+        changed[40] ^= 1;
+        assert_eq!(check(&changed), Some(40));
+        assert_eq!(check(&bytes[..100]), Some(100));
+        assert_eq!(
+            check(&[bytes.clone(), vec![0]].concat()),
+            Some(bytes.len() as u64)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unpack_restores_what_pack_archived() {
+        use std::os::unix::fs::PermissionsExt;
+        let src = tempfile::tempdir().unwrap();
+        let bundle = src.path().join("App.lnx");
+        std::fs::create_dir_all(bundle.join("bin")).unwrap();
+        std::fs::write(
+            bundle.join("config.toml"),
+            "name = \"App\"\nexecutable = \"bin/app\"\n",
+        )
+        .unwrap();
+        std::fs::write(bundle.join("bin/app"), "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(
+            bundle.join("bin/app"),
+            std::fs::Permissions::from_mode(0o775),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink("app", bundle.join("bin/link")).unwrap();
+        let zstd = Command::new("zstd")
+            .arg("--version")
+            .output()
+            .is_ok_and(|o| o.status.success());
+        if !zstd {
+            eprintln!("skipping the zstd round trip: zstd is not installed");
+        }
+        let formats = [("App.lnx.tar.gz", true), ("App.lnx.tar.zst", zstd)];
+        for (file, _) in formats.iter().filter(|(_, available)| *available) {
+            let archive = src.path().join(file);
+            assert!(is_packed_bundle(&archive));
+            let out = std::fs::File::create(&archive).unwrap();
+            write_archive(
+                &bundle,
+                &[],
+                0,
+                Compression::from_name(&archive),
+                out,
+                &Progress::hidden(),
+            )
+            .unwrap();
+            let apps = tempfile::tempdir().unwrap();
+            let unpacked = unpack(&archive, apps.path()).unwrap();
+            assert_eq!(unpacked, apps.path().join("App.lnx"));
+            assert_eq!(
+                std::fs::read_to_string(unpacked.join("config.toml")).unwrap(),
+                std::fs::read_to_string(bundle.join("config.toml")).unwrap()
+            );
+            let mode =
+                |p: &Path| std::fs::symlink_metadata(p).unwrap().permissions().mode() & 0o7777;
+            assert_eq!(
+                (
+                    mode(&unpacked.join("bin/app")),
+                    mode(&unpacked.join("config.toml"))
+                ),
+                (0o755, 0o644)
+            );
+            assert_eq!(
+                std::fs::read_link(unpacked.join("bin/link")).unwrap(),
+                Path::new("app")
+            );
+            // Only the bundle is left: no staging dir, and a second unpack does not overwrite it.
+            assert_eq!(std::fs::read_dir(apps.path()).unwrap().count(), 1);
+            assert!(unpack(&archive, apps.path())
+                .unwrap_err()
+                .to_string()
+                .contains("already exists"));
+            assert_eq!(std::fs::read_dir(apps.path()).unwrap().count(), 1);
+        }
+
+        // An archive of anything but one bundle folder is refused.
+        let stray = src.path().join("Stray.lnx.tar.gz");
+        let mut tar = tar::Builder::new(GzEncoder::new(
+            std::fs::File::create(&stray).unwrap(),
+            flate2::Compression::default(),
+        ));
+        tar.append_path_with_name(bundle.join("config.toml"), "config.toml")
+            .unwrap();
+        tar.into_inner().unwrap().finish().unwrap();
+        let apps = tempfile::tempdir().unwrap();
+        assert!(unpack(&stray, apps.path())
+            .unwrap_err()
+            .to_string()
+            .contains("one .lnx bundle folder"));
+        assert_eq!(std::fs::read_dir(apps.path()).unwrap().count(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn zstd_archive_rejected_partway_does_not_hang() {
+        if !Command::new("zstd")
+            .arg("--version")
+            .output()
+            .is_ok_and(|o| o.status.success())
+        {
+            eprintln!("skipping: zstd is not installed");
+            return;
+        }
+        // A second bundle folder, then far more data than a pipe holds: tar stops reading at the
+        // second folder while zstd still has megabytes to write.
+        let src = tempfile::tempdir().unwrap();
+        let archive = src.path().join("Two.lnx.tar.zst");
+        let zstd = ZstdWriter::new(std::fs::File::create(&archive).unwrap()).unwrap();
+        let mut tar = tar::Builder::new(Encoder::Zstd(zstd));
+        for (name, size) in [("A.lnx/run", 0), ("B.lnx/run", 0), ("B.lnx/big", 8 << 20)] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(size);
+            header.set_mode(0o644);
+            tar.append_data(&mut header, name, std::io::repeat(0).take(size))
+                .unwrap();
+        }
+        tar.into_inner().unwrap().finish().unwrap();
+
+        let apps = tempfile::tempdir().unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let (archive_path, apps_path) = (archive.clone(), apps.path().to_path_buf());
+        std::thread::spawn(move || {
+            tx.send(unpack(&archive_path, &apps_path).map_err(|e| e.to_string()))
+        });
+        let outcome = rx
+            .recv_timeout(std::time::Duration::from_secs(30))
+            .expect("unpack hung");
+        assert!(outcome.unwrap_err().contains("one .lnx bundle folder"));
+        assert_eq!(std::fs::read_dir(apps.path()).unwrap().count(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dropped_archives_become_bundles_or_are_set_aside() {
+        use crate::zipfile::tests::write_zip;
+        let apps = tempfile::tempdir().unwrap();
+        let config = b"name = \"App\"\nexecutable = \"bin/app\"\n";
+        write_zip(
+            &apps.path().join("App.lnx.zip"),
+            &[
+                ("App.lnx/", 0o040755, b""),
+                ("App.lnx/config.toml", 0o100644, config),
+                ("App.lnx/bin/app", 0o100775, b"#!/bin/sh\n"),
+            ],
+        );
+        // No config.toml: fails validation.
+        write_zip(
+            &apps.path().join("Broken.lnx.zip"),
+            &[("Broken.lnx/bin/app", 0o100755, b"#!/bin/sh\n")],
+        );
+        // Escapes the bundle folder.
+        write_zip(
+            &apps.path().join("Evil.lnx.zip"),
+            &[("Evil.lnx/../evil", 0o100644, b"x")],
+        );
+        // Cut off before its central directory, as if still being copied.
+        let cut = apps.path().join("Cut.lnx.zip");
+        write_zip(&cut, &[("Cut.lnx/config.toml", 0o100644, config)]);
+        let bytes = std::fs::read(&cut).unwrap();
+        std::fs::write(&cut, &bytes[..bytes.len() - 30]).unwrap();
+        std::fs::write(apps.path().join(".Hidden.lnx.zip"), "").unwrap();
+        let dropped = dropped_archives(apps.path());
+        let names: Vec<String> = dropped
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "App.lnx.zip",
+                "Broken.lnx.zip",
+                "Cut.lnx.zip",
+                "Evil.lnx.zip"
+            ]
+        );
+
+        let bundle = extract_dropped(&dropped[0]).unwrap();
+        assert_eq!(bundle, apps.path().join("App.lnx"));
+        assert!(crate::bundle::is_lnx_bundle(&bundle));
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(bundle.join("bin/app"))
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o7777;
+        assert_eq!(mode, 0o755);
+        assert!(extract_dropped(&dropped[1])
+            .unwrap_err()
+            .to_string()
+            .contains("config.toml"));
+        assert!(is_truncated(&extract_dropped(&dropped[2]).unwrap_err()));
+        assert!(extract_dropped(&dropped[3])
+            .unwrap_err()
+            .to_string()
+            .contains("leaves the bundle folder"));
+        assert!(!apps.path().join("evil").exists());
+
+        // The good archive is gone, the bad ones are renamed so the next sync leaves them alone,
+        // the cut-off one stays to be retried, and nothing half-unpacked is left.
+        let mut left: Vec<String> = std::fs::read_dir(apps.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        left.sort();
+        assert_eq!(
+            left,
+            [
+                ".Hidden.lnx.zip",
+                "App.lnx",
+                "Broken.lnx.zip.failed",
+                "Cut.lnx.zip",
+                "Evil.lnx.zip.failed"
+            ]
+        );
+        assert_eq!(dropped_archives(apps.path()), [cut]);
+    }
+}
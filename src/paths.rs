@@ -0,0 +1,292 @@
+//! Where dotlnx reads and writes outside the bundles: Applications dirs, menu dirs, the AppArmor
+//! profile dir, kernel interfaces and external tools. Resolved once per command from the site
+//! config's [paths] section and passed down, so the same code can run against a re-rooted tree
+//! (image builds, containers, tests) instead of the live host.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+use crate::settings::{PathSettings, Precedence};
+
+/// System-tier Applications dir.
+const SYSTEM_APPLICATIONS: &str = "/Applications";
+/// System-tier .desktop files.
+const SYSTEM_DESKTOP_DIR: &str = "/usr/share/applications";
+/// Generated profiles. Requires root to write.
+const APPARMOR_DIR: &str = "/etc/apparmor.d/dotlnx.d";
+/// Policy ABI files (abi/3.0, abi/4.0) shipped with apparmor_parser.
+const APPARMOR_ABI_DIR: &str = "/etc/apparmor.d/abi";
+/// Kernel list of loaded profiles, one "name (mode)" per line.
+const LOADED_PROFILES: &str = "/sys/kernel/security/apparmor/profiles";
+/// System-tier service units.
+const SYSTEMD_SYSTEM_DIR: &str = "/etc/systemd/system";
+/// Start of generated profile and .desktop file names.
+const DEFAULT_PREFIX: &str = "dotlnx-";
+/// Locations to check for apparmor_parser (root/sudo/systemd often have minimal PATH without /usr/sbin).
+const APPARMOR_PARSER_CANDIDATES: &[&str] = &["/usr/sbin/apparmor_parser", "/sbin/apparmor_parser"];
+
+/// Resolved locations. Fields are plain paths so tests can build one by hand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Paths {
+    /// System-tier Applications dir (/Applications).
+    pub system_apps: PathBuf,
+    /// System-tier .desktop files (/usr/share/applications).
+    pub system_desktop: PathBuf,
+    /// root's home, scanned by the daemon (/root).
+    pub root_home: PathBuf,
+    /// Parent of the other users' homes, scanned by the daemon (/home).
+    pub home_dirs: PathBuf,
+    /// Generated AppArmor profiles (/etc/apparmor.d/dotlnx.d).
+    pub apparmor_dir: PathBuf,
+    /// Host policy ABI files, probed for abi = "auto".
+    pub apparmor_abi_dir: PathBuf,
+    /// Kernel list of loaded profiles.
+    pub loaded_profiles: PathBuf,
+    /// apparmor_parser, None when it is not installed.
+    pub apparmor_parser: Option<PathBuf>,
+    /// Where apparmor_parser caches compiled profiles; None for its own default.
+    pub apparmor_cache: Option<PathBuf>,
+    /// gio, for folder icons.
+    pub gio: PathBuf,
+    /// notify-send, for quarantine notifications.
+    pub notify_send: PathBuf,
+    /// glib-compile-schemas, for GSettings schemas bundles install.
+    pub glib_compile_schemas: PathBuf,
+    /// System-tier service units (/etc/systemd/system).
+    pub systemd_system_dir: PathBuf,
+    /// systemctl, to enable and start service units.
+    pub systemctl: PathBuf,
+    /// The invoking user's Applications dir (DOTLNX_APPLICATIONS or ~/Applications).
+    pub user_apps: PathBuf,
+    /// The invoking user's .desktop dir (XDG_DATA_HOME/applications), None without a home.
+    pub user_desktop: Option<PathBuf>,
+    /// The invoking user's service units (XDG_CONFIG_HOME/systemd/user), None without a home.
+    pub user_units: Option<PathBuf>,
+    /// Start of user-tier profile and .desktop file names ("dotlnx-").
+    pub user_prefix: String,
+    /// Start of system-tier profile and .desktop file names ("dotlnx-").
+    pub system_prefix: String,
+    /// Which tier wins when both have an app by the same name.
+    pub precedence: Precedence,
+}
+
+impl Paths {
+    /// Standard locations under `root` ("/" for the live host). The user-tier dirs always come
+    /// from the environment: they belong to whoever runs dotlnx, not to the tree being managed.
+    #[cfg(test)]
+    pub fn under(root: &Path) -> Self {
+        Self::from_settings(&PathSettings {
+            root: Some(root.to_path_buf()),
+            ..PathSettings::default()
+        })
+    }
+
+    /// Locations from the [paths] section. Explicit keys are used as given; the rest are the
+    /// standard locations under `root`. DOTLNX_SYSTEM_APPLICATIONS still overrides the system
+    /// Applications dir.
+    pub fn from_settings(settings: &PathSettings) -> Self {
+        let root = settings.root.as_deref().unwrap_or(Path::new("/"));
+        let rooted = |p: &str| root.join(p.trim_start_matches('/'));
+        let pick =
+            |set: &Option<PathBuf>, default: &str| set.clone().unwrap_or_else(|| rooted(default));
+        let system_apps = std::env::var_os("DOTLNX_SYSTEM_APPLICATIONS")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| pick(&settings.system_applications, SYSTEM_APPLICATIONS));
+        let apparmor_parser = match &settings.apparmor_parser {
+            Some(p) => Some(p.clone()),
+            None => find_apparmor_parser(&rooted, settings.root.is_none()),
+        };
+        let home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
+        let prefix = settings.prefix.as_deref().unwrap_or(DEFAULT_PREFIX);
+        Self {
+            system_apps,
+            system_desktop: pick(&settings.system_desktop_dir, SYSTEM_DESKTOP_DIR),
+            root_home: rooted("/root"),
+            home_dirs: pick(&settings.home_dirs, "/home"),
+            apparmor_dir: pick(&settings.apparmor_dir, APPARMOR_DIR),
+            apparmor_abi_dir: rooted(APPARMOR_ABI_DIR),
+            loaded_profiles: rooted(LOADED_PROFILES),
+            apparmor_parser,
+            apparmor_cache: settings.apparmor_cache.clone(),
+            gio: rooted("/usr/bin/gio"),
+            notify_send: rooted("/usr/bin/notify-send"),
+            glib_compile_schemas: rooted("/usr/bin/glib-compile-schemas"),
+            systemd_system_dir: pick(&settings.systemd_system_dir, SYSTEMD_SYSTEM_DIR),
+            systemctl: pick(&settings.systemctl, "/usr/bin/systemctl"),
+            user_apps: std::env::var_os("DOTLNX_APPLICATIONS")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| home.join("Applications")),
+            user_desktop: xdg::BaseDirectories::with_prefix("")
+                .ok()
+                .map(|x| x.get_data_home().join("applications")),
+            user_units: xdg::BaseDirectories::with_prefix("")
+                .ok()
+                .map(|x| x.get_config_home().join("systemd/user")),
+            user_prefix: settings
+                .user_prefix
+                .clone()
+                .unwrap_or_else(|| prefix.to_string()),
+            system_prefix: settings
+                .system_prefix
+                .clone()
+                .unwrap_or_else(|| prefix.to_string()),
+            precedence: settings.precedence,
+        }
+    }
+
+    /// Path of a generated profile on disk (whether or not it exists).
+    pub fn profile_path(&self, profile_name: &str) -> PathBuf {
+        self.apparmor_dir.join(profile_name)
+    }
+
+    /// Profile name of an app: user tier when `username` is set, system tier otherwise.
+    pub fn profile_name(&self, username: Option<&str>, app: &str) -> String {
+        match username {
+            Some(u) => crate::apparmor::profile_name_user(&self.user_prefix, u, app),
+            None => crate::apparmor::profile_name_system(&self.system_prefix, app),
+        }
+    }
+
+    /// Prefix of menu entries in `desktop_dir`: the system tier's in the system desktop dir, the
+    /// user tier's anywhere else.
+    pub fn desktop_prefix(&self, desktop_dir: &Path) -> &str {
+        if desktop_dir == self.system_desktop {
+            &self.system_prefix
+        } else {
+            &self.user_prefix
+        }
+    }
+
+    /// File name of an app's menu entry in `desktop_dir` (e.g. dotlnx-MyApp.desktop).
+    pub fn desktop_file_name(&self, desktop_dir: &Path, app: &str) -> String {
+        format!("{}{}.desktop", self.desktop_prefix(desktop_dir), app)
+    }
+
+    /// The invoking user's .desktop dir, or an error when there is no home to put it in.
+    pub fn user_desktop_dir(&self) -> Result<PathBuf> {
+        self.user_desktop
+            .clone()
+            .context("cannot determine XDG data directory (is HOME set?)")
+    }
+
+    /// Applications dirs in the order an app name is looked up in, each with whether it is the
+    /// user tier.
+    pub fn tiers(&self) -> [(&Path, bool); 2] {
+        let user = (self.user_apps.as_path(), true);
+        let system = (self.system_apps.as_path(), false);
+        match self.precedence {
+            Precedence::System => [system, user],
+            Precedence::User | Precedence::Error => [user, system],
+        }
+    }
+
+    /// apparmor_parser, or an error naming where it was looked for.
+    pub fn apparmor_parser(&self) -> Result<&Path> {
+        self.apparmor_parser.as_deref().with_context(|| {
+            "apparmor_parser not found (checked /usr/sbin, /sbin, and PATH; set [paths] apparmor_parser)"
+        })
+    }
+}
+
+/// Locations for this host from the site config (/etc/dotlnx/config.toml).
+pub fn load() -> Result<Paths> {
+    Ok(Paths::from_settings(&crate::settings::load()?.paths))
+}
+
+/// apparmor_parser: /usr/sbin and /sbin under the root first, then PATH (only for the live host;
+/// a re-rooted tree has its own parser or none).
+fn find_apparmor_parser(rooted: &dyn Fn(&str) -> PathBuf, search_path: bool) -> Option<PathBuf> {
+    for p in APPARMOR_PARSER_CANDIDATES {
+        let path = rooted(p);
+        if path.is_file() {
+            return Some(path);
+        }
+    }
+    if !search_path {
+        return None;
+    }
+    let path_env = std::env::var_os("PATH")?;
+    std::env::split_paths(&path_env)
+        .map(|dir| dir.join("apparmor_parser"))
+        .find(|candidate| candidate.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_re_rooted() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = Paths::under(root.path());
+        assert_eq!(
+            paths.apparmor_dir,
+            root.path().join("etc/apparmor.d/dotlnx.d")
+        );
+        assert_eq!(
+            paths.system_desktop,
+            root.path().join("usr/share/applications")
+        );
+        assert_eq!(paths.home_dirs, root.path().join("home"));
+        assert_eq!(
+            paths.profile_path("dotlnx-a"),
+            root.path().join("etc/apparmor.d/dotlnx.d/dotlnx-a")
+        );
+        // No parser in the tree and the host PATH is not consulted.
+        assert_eq!(paths.apparmor_parser, None);
+        assert!(paths.apparmor_parser().is_err());
+
+        std::fs::create_dir_all(root.path().join("sbin")).unwrap();
+        std::fs::write(root.path().join("sbin/apparmor_parser"), "").unwrap();
+        let paths = Paths::under(root.path());
+        assert_eq!(
+            paths.apparmor_parser,
+            Some(root.path().join("sbin/apparmor_parser"))
+        );
+    }
+
+    #[test]
+    fn explicit_settings_are_not_re_rooted() {
+        let settings = PathSettings {
+            root: Some(PathBuf::from("/srv/img")),
+            apparmor_dir: Some(PathBuf::from("/var/lib/profiles")),
+            apparmor_parser: Some(PathBuf::from("/opt/aa/parser")),
+            ..PathSettings::default()
+        };
+        let paths = Paths::from_settings(&settings);
+        assert_eq!(paths.apparmor_dir, Path::new("/var/lib/profiles"));
+        assert_eq!(
+            paths.apparmor_parser.as_deref(),
+            Some(Path::new("/opt/aa/parser"))
+        );
+        assert_eq!(
+            paths.loaded_profiles,
+            Path::new("/srv/img/sys/kernel/security/apparmor/profiles")
+        );
+        assert_eq!(paths.root_home, Path::new("/srv/img/root"));
+    }
+
+    #[test]
+    fn prefixes_name_profiles_and_menu_entries_per_tier() {
+        let default = Paths::under(Path::new("/srv/img"));
+        assert_eq!(default.profile_name(Some("jane"), "App"), "dotlnx-jane-App");
+        assert_eq!(default.profile_name(None, "App"), "dotlnx-App");
+
+        let settings = PathSettings {
+            prefix: Some("corp-".into()),
+            system_prefix: Some("corp-sys-".into()),
+            ..PathSettings::default()
+        };
+        let paths = Paths::from_settings(&settings);
+        assert_eq!(paths.profile_name(Some("jane"), "App"), "corp-jane-App");
+        assert_eq!(paths.profile_name(None, "App"), "corp-sys-App");
+        assert_eq!(
+            paths.desktop_file_name(&paths.system_desktop, "App"),
+            "corp-sys-App.desktop"
+        );
+        assert_eq!(
+            paths.desktop_file_name(Path::new("/home/jane/.local/share/applications"), "App"),
+            "corp-App.desktop"
+        );
+    }
+}
@@ -0,0 +1,394 @@
+//! `dotlnx permissions`: what every app may touch, for security reviews. Each bundle's config is
+//! resolved the way sync resolves it before generating the profile (admin override, [run]
+//! defaults, temporary grants), then summarized as file, network and device access, with grants
+//! that reach much further than an app usually needs called out.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::asuser;
+use crate::bundle;
+use crate::config::{Config, Seccomp, Userns};
+use crate::grant;
+use crate::output::{self, Style};
+use crate::paths::Paths;
+use crate::policy;
+use crate::settings;
+use crate::state;
+
+/// Capabilities that amount to (or easily lead to) root.
+const BROAD_CAPABILITIES: &[&str] = &[
+    "sys_admin",
+    "sys_ptrace",
+    "sys_module",
+    "sys_rawio",
+    "dac_override",
+    "dac_read_search",
+    "setuid",
+    "setgid",
+    "net_admin",
+    "bpf",
+];
+
+/// Directories whose whole tree is too much for one app.
+const BROAD_TREES: &[&str] = &[
+    "", "/home", "/home/*", "@{HOME}", "/root", "/etc", "/usr", "/var", "/opt", "/srv", "/mnt",
+    "/media", "/run", "/dev", "/proc", "/sys",
+];
+
+/// Path parts that hold credentials.
+const SECRETS: &[&str] = &[
+    ".ssh",
+    ".gnupg",
+    ".pki",
+    ".password-store",
+    "keyrings",
+    "/etc/shadow",
+    "/etc/sudoers",
+];
+
+/// Interpreters that run anything they are given.
+const SHELLS: &[&str] = &[
+    "sh", "bash", "dash", "zsh", "fish", "python", "python3", "perl", "ruby", "node", "env",
+];
+
+/// One app's effective access.
+#[derive(Debug, Serialize)]
+pub struct AppPermissions {
+    pub name: String,
+    /// "user:<name>" or "system".
+    pub tier: String,
+    pub bundle: PathBuf,
+    /// Profile name; None when the app runs unconfined.
+    pub profile: Option<String>,
+    /// An admin override in /etc/dotlnx/overrides applies.
+    pub overridden: bool,
+    pub network: bool,
+    pub read_paths: Vec<String>,
+    pub write_paths: Vec<String>,
+    pub exec_paths: Vec<String>,
+    /// Presets that open devices or desktop services (gpu, audio, camera, ...).
+    pub presets: Vec<&'static str>,
+    pub capabilities: Vec<String>,
+    /// "deny", "allow" or "unconfined".
+    pub userns: &'static str,
+    pub helpers: Vec<String>,
+    pub seccomp: Option<String>,
+    /// Access reaching much further than an app usually needs.
+    pub warnings: Vec<String>,
+}
+
+/// A bundle whose config could not be resolved.
+#[derive(Debug, Serialize)]
+pub struct Unreadable {
+    pub bundle: PathBuf,
+    pub error: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub apps: Vec<AppPermissions>,
+    pub errors: Vec<Unreadable>,
+}
+
+/// Print each app's effective file, network and device access, broad grants flagged, for every
+/// bundle this user can read (all users and the system tier as root).
+pub fn run(json: bool) -> Result<()> {
+    let settings = settings::load()?;
+    let paths = Paths::from_settings(&settings.paths);
+    let report = collect(&settings, &paths)?;
+    if json {
+        return output::json(&report);
+    }
+    print(&report);
+    Ok(())
+}
+
+fn collect(settings: &settings::Settings, paths: &Paths) -> Result<Report> {
+    let is_root = bundle::is_root();
+    let grants = match is_root {
+        true => grant::load(&state::state_dir()?).unwrap_or_default(),
+        false => Default::default(),
+    };
+    let mut tiers: Vec<(PathBuf, Option<String>)> = bundle::user_tier_entries(paths)?
+        .into_iter()
+        .map(|(apps, _, user)| (apps, Some(user)))
+        .collect();
+    tiers.push((paths.system_apps.clone(), None));
+    let mut report = Report::default();
+    for (apps_dir, user) in tiers {
+        for dir in bundle::discover_lnx_dirs(&apps_dir) {
+            let owner = user.as_deref().filter(|_| is_root);
+            match resolve(settings, owner, &dir, is_root) {
+                Ok((mut cfg, overridden)) => {
+                    let profile = paths.profile_name(user.as_deref(), &cfg.app_id());
+                    grant::apply(&mut cfg, &grants, &profile);
+                    let tier = user
+                        .as_ref()
+                        .map(|u| format!("user:{}", u))
+                        .unwrap_or_else(|| "system".into());
+                    report
+                        .apps
+                        .push(summarize(&cfg, &dir, tier, profile, overridden));
+                }
+                Err(e) => report.errors.push(Unreadable {
+                    bundle: dir,
+                    error: format!("{:#}", e),
+                }),
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// The config sync generates the profile from: validated, with the override and [run] applied.
+pub fn resolve(
+    settings: &settings::Settings,
+    owner: Option<&str>,
+    dir: &Path,
+    is_root: bool,
+) -> Result<(Config, bool)> {
+    let mut cfg = asuser::load_config(owner, dir, is_root)?;
+    crate::validate::validate_config(dir, &cfg)?;
+    let overridden = policy::apply_override(&mut cfg, &settings.apparmor.overrides_dir)?;
+    settings.run.apply(&mut cfg);
+    Ok((cfg, overridden))
+}
+
+/// Effective access of one app and what stands out about it.
+pub fn summarize(
+    cfg: &Config,
+    bundle: &Path,
+    tier: String,
+    profile: String,
+    overridden: bool,
+) -> AppPermissions {
+    let default = crate::config::Security::default();
+    let sec = cfg.security.as_ref().unwrap_or(&default);
+    let presets = [
+        ("gpu", sec.gpu),
+        ("audio", sec.audio),
+        ("portals", sec.portals),
+        ("v4l", sec.v4l),
+        ("camera", sec.camera),
+        ("screen_capture", sec.screen_capture),
+        ("printing", sec.printing),
+        ("removable_media", sec.removable_media),
+        ("ssh_agent", sec.ssh_agent),
+        ("secrets", sec.secrets),
+        ("accessibility", sec.accessibility),
+    ]
+    .into_iter()
+    .filter_map(|(name, on)| on.then_some(name))
+    .collect();
+    let userns = match sec.userns {
+        Userns::Deny => "deny",
+        Userns::Allow => "allow",
+        Userns::Unconfined => "unconfined",
+    };
+    let seccomp = sec.seccomp.as_ref().map(|s| match s {
+        Seccomp::Default => "default".to_string(),
+        Seccomp::Strict => "strict".to_string(),
+        Seccomp::Profile(p) => p.clone(),
+    });
+    AppPermissions {
+        name: cfg.name.clone(),
+        tier,
+        bundle: bundle.to_path_buf(),
+        profile: sec.confine.then_some(profile),
+        overridden,
+        network: sec.network,
+        read_paths: sec.read_paths.clone(),
+        write_paths: sec.write_paths.clone(),
+        exec_paths: sec.exec_paths.clone(),
+        presets,
+        capabilities: sec.capabilities.clone(),
+        userns,
+        helpers: sec.helpers.iter().map(|h| h.path.clone()).collect(),
+        seccomp,
+        warnings: warnings(cfg),
+    }
+}
+
+/// Grants that reach much further than an app usually needs.
+fn warnings(cfg: &Config) -> Vec<String> {
+    let Some(sec) = cfg.security.as_ref() else {
+        return Vec::new();
+    };
+    let mut out = Vec::new();
+    if !sec.confine {
+        out.push("not confined: no AppArmor profile limits it".to_string());
+        return out;
+    }
+    if sec.userns == Userns::Unconfined {
+        out.push(
+            "profile is unconfined (userns = \"unconfined\"): only its name is attached".into(),
+        );
+    }
+    for (paths, verb) in [(&sec.write_paths, "write"), (&sec.read_paths, "read")] {
+        for path in paths {
+            if is_broad_tree(path) {
+                out.push(format!("may {} all of {}", verb, path));
+            } else if SECRETS.iter().any(|s| path.contains(s)) {
+                out.push(format!("may {} credentials: {}", verb, path));
+            }
+        }
+    }
+    for path in &sec.exec_paths {
+        let base = path.rsplit('/').next().unwrap_or(path);
+        if is_broad_tree(path)
+            || SHELLS
+                .iter()
+                .any(|s| base == *s || (s.starts_with("python") && base.starts_with(s)))
+        {
+            out.push(format!(
+                "may run {} under its profile (can execute arbitrary code)",
+                path
+            ));
+        }
+    }
+    for cap in &sec.capabilities {
+        if BROAD_CAPABILITIES.contains(&cap.as_str()) {
+            out.push(format!("capability {}", cap));
+        }
+    }
+    out
+}
+
+/// True for a whole top-level tree ("/home/**", "@{HOME}/", "/**").
+fn is_broad_tree(path: &str) -> bool {
+    let mut base = path;
+    for suffix in ["**", "*", "/"] {
+        base = base.strip_suffix(suffix).unwrap_or(base);
+    }
+    let base = base.trim_end_matches('/');
+    BROAD_TREES.contains(&base)
+}
+
+fn print(report: &Report) {
+    if report.apps.is_empty() && report.errors.is_empty() {
+        println!("no apps");
+    }
+    let list = |items: &[String]| match items.is_empty() {
+        true => "-".to_string(),
+        false => items.join(", "),
+    };
+    for app in &report.apps {
+        let profile = match &app.profile {
+            Some(p) => format!("profile {}", p),
+            None => "unconfined".to_string(),
+        };
+        let overridden = if app.overridden {
+            ", admin override"
+        } else {
+            ""
+        };
+        let title = format!("{} ({}, {}{})", app.name, app.tier, profile, overridden);
+        println!("{}", output::paint(&title, Style::Heading));
+        let presets: Vec<String> = app.presets.iter().map(|p| p.to_string()).collect();
+        let mut rows = vec![
+            ("Read", list(&app.read_paths), Style::Plain),
+            ("Write", list(&app.write_paths), Style::Plain),
+        ];
+        if !app.exec_paths.is_empty() {
+            rows.push(("Exec", list(&app.exec_paths), Style::Plain));
+        }
+        rows.push((
+            "Network",
+            if app.network { "yes" } else { "no" }.into(),
+            Style::Plain,
+        ));
+        rows.push(("Presets", list(&presets), Style::Plain));
+        if !app.capabilities.is_empty() {
+            rows.push(("Capabilities", list(&app.capabilities), Style::Warn));
+        }
+        if app.userns != "deny" {
+            rows.push(("User namespaces", app.userns.to_string(), Style::Warn));
+        }
+        if !app.helpers.is_empty() {
+            rows.push(("Helpers", list(&app.helpers), Style::Plain));
+        }
+        if let Some(s) = &app.seccomp {
+            rows.push(("Seccomp", s.clone(), Style::Plain));
+        }
+        output::fields(&rows);
+        for warning in &app.warnings {
+            println!("  ! {}", output::paint(warning, Style::Warn));
+        }
+        println!();
+    }
+    for e in &report.errors {
+        println!(
+            "{}: {}",
+            e.bundle.display(),
+            output::paint(&e.error, Style::Bad)
+        );
+    }
+    let flagged = report
+        .apps
+        .iter()
+        .filter(|a| !a.warnings.is_empty())
+        .count();
+    println!("{} app(s), {} flagged", report.apps.len(), flagged);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_broad_access() {
+        let cfg: Config = toml::from_str(
+            r#"
+name = "app"
+executable = "bin/app"
+[security]
+network = true
+gpu = true
+read_paths = ["/srv/media/**", "@{HOME}/.ssh/id_ed25519"]
+write_paths = ["@{HOME}/**", "/tmp/app/"]
+exec_paths = ["/usr/bin/python3.12", "/usr/bin/ffmpeg"]
+capabilities = ["sys_ptrace", "net_bind_service"]
+"#,
+        )
+        .unwrap();
+        let app = summarize(
+            &cfg,
+            Path::new("/Applications/app.lnx"),
+            "system".into(),
+            "dotlnx-app".into(),
+            false,
+        );
+        assert_eq!(app.profile.as_deref(), Some("dotlnx-app"));
+        assert_eq!(app.presets, ["gpu"]);
+        assert_eq!(
+            app.warnings,
+            [
+                "may write all of @{HOME}/**",
+                "may read credentials: @{HOME}/.ssh/id_ed25519",
+                "may run /usr/bin/python3.12 under its profile (can execute arbitrary code)",
+                "capability sys_ptrace",
+            ]
+        );
+
+        let cfg: Config = toml::from_str(
+            "name = \"app\"\nexecutable = \"bin/app\"\n[security]\nconfine = false\n",
+        )
+        .unwrap();
+        let app = summarize(
+            &cfg,
+            Path::new("/Applications/app.lnx"),
+            "system".into(),
+            "dotlnx-app".into(),
+            true,
+        );
+        assert_eq!(app.profile, None);
+        assert_eq!(app.warnings.len(), 1);
+        assert!(
+            is_broad_tree("/**")
+                && is_broad_tree("/home/*/")
+                && !is_broad_tree("/home/alice/Music/**")
+        );
+    }
+}
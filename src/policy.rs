@@ -0,0 +1,382 @@
+//! Site policy applied on top of what bundles request: base profile fragments from
+//! /etc/dotlnx/policy.d that are merged into every generated profile, and per-app overrides from
+//! /etc/dotlnx/overrides that take precedence over the bundle's [security] section.
+
+use anyhow::Result;
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::config::{Config, Seccomp, Security, Userns};
+
+/// Default directory for site base policy fragments (`*.conf`, merged in file name order).
+pub const DEFAULT_POLICY_DIR: &str = "/etc/dotlnx/policy.d";
+
+/// Default directory for per-app overrides (`<name>.toml`).
+pub const DEFAULT_OVERRIDES_DIR: &str = "/etc/dotlnx/overrides";
+
+/// Admin override file (/etc/dotlnx/overrides/<name>.toml). Every key that is set replaces the
+/// bundle's value; unset keys leave the bundle's choice alone.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Override {
+    /// Who gets a menu entry (system tier); `[]` opens the app to everyone again.
+    pub allowed_users: Option<Vec<String>>,
+    pub allowed_groups: Option<Vec<String>>,
+    #[serde(default)]
+    pub security: Option<SecurityOverride>,
+}
+
+/// Pinned [security] keys.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SecurityOverride {
+    pub confine: Option<bool>,
+    pub network: Option<bool>,
+    pub userns: Option<Userns>,
+    pub read_paths: Option<Vec<String>>,
+    pub write_paths: Option<Vec<String>>,
+    pub exec_paths: Option<Vec<String>>,
+    pub gpu: Option<bool>,
+    pub audio: Option<bool>,
+    pub portals: Option<bool>,
+    pub v4l: Option<bool>,
+    pub camera: Option<bool>,
+    pub screen_capture: Option<bool>,
+    pub printing: Option<bool>,
+    pub removable_media: Option<bool>,
+    pub ssh_agent: Option<bool>,
+    pub secrets: Option<bool>,
+    pub accessibility: Option<bool>,
+    pub clear_env: Option<bool>,
+    pub seccomp: Option<Seccomp>,
+    pub no_new_privs: Option<bool>,
+    pub ephemeral_home: Option<bool>,
+    pub persist_paths: Option<Vec<String>>,
+}
+
+/// Load the override for `app_name` from `dir`, if any.
+pub fn load_override(dir: &Path, app_name: &str) -> Result<Option<Override>> {
+    crate::validate::validate_app_name(app_name)?;
+    let path = dir.join(format!("{}.toml", app_name));
+    let s = match std::fs::read_to_string(&path) {
+        Ok(s) => s,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => anyhow::bail!("failed to read {}: {}", path.display(), e),
+    };
+    let ov: Override =
+        toml::from_str(&s).map_err(|e| anyhow::anyhow!("invalid {}: {}", path.display(), e))?;
+    for name in ov.allowed_users.iter().chain(&ov.allowed_groups).flatten() {
+        if !crate::validate::is_account_name(name) {
+            anyhow::bail!(
+                "invalid {}: {:?} is not a valid user or group name",
+                path.display(),
+                name
+            );
+        }
+    }
+    if let Some(ref sec) = ov.security {
+        for (i, p) in sec.read_paths.iter().flatten().enumerate() {
+            crate::validate::validate_security_path(&format!("read_paths[{}]", i), p)?;
+        }
+        for (i, p) in sec.write_paths.iter().flatten().enumerate() {
+            crate::validate::validate_security_path(&format!("write_paths[{}]", i), p)?;
+        }
+        for (i, p) in sec.exec_paths.iter().flatten().enumerate() {
+            crate::validate::validate_security_path(&format!("exec_paths[{}]", i), p)?;
+        }
+        for (i, p) in sec.persist_paths.iter().flatten().enumerate() {
+            crate::validate::validate_persist_path(&format!("persist_paths[{}]", i), p)?;
+        }
+    }
+    Ok(Some(ov))
+}
+
+/// Apply the admin override for this app (if one exists) to a loaded bundle config.
+/// Returns true when an override was applied. Must run at both sync and run time so editing the
+/// bundle in a user's home cannot weaken what the admin pinned.
+pub fn apply_override(config: &mut Config, dir: &Path) -> Result<bool> {
+    let Some(ov) = load_override(dir, &config.name)? else {
+        return Ok(false);
+    };
+    if let Some(v) = ov.allowed_users {
+        config.allowed_users = v;
+    }
+    if let Some(v) = ov.allowed_groups {
+        config.allowed_groups = v;
+    }
+    if let Some(pinned) = ov.security {
+        let sec = config.security.get_or_insert_with(Security::default);
+        if let Some(v) = pinned.confine {
+            sec.confine = v;
+        }
+        if let Some(v) = pinned.network {
+            sec.network = v;
+        }
+        if let Some(v) = pinned.userns {
+            sec.userns = v;
+        }
+        if let Some(v) = pinned.read_paths {
+            sec.read_paths = v;
+        }
+        if let Some(v) = pinned.write_paths {
+            sec.write_paths = v;
+        }
+        if let Some(v) = pinned.exec_paths {
+            sec.exec_paths = v;
+        }
+        if let Some(v) = pinned.gpu {
+            sec.gpu = v;
+        }
+        if let Some(v) = pinned.audio {
+            sec.audio = v;
+        }
+        if let Some(v) = pinned.portals {
+            sec.portals = v;
+        }
+        if let Some(v) = pinned.v4l {
+            sec.v4l = v;
+        }
+        if let Some(v) = pinned.camera {
+            sec.camera = v;
+        }
+        if let Some(v) = pinned.screen_capture {
+            sec.screen_capture = v;
+        }
+        if let Some(v) = pinned.printing {
+            sec.printing = v;
+        }
+        if let Some(v) = pinned.removable_media {
+            sec.removable_media = v;
+        }
+        if let Some(v) = pinned.ssh_agent {
+            sec.ssh_agent = v;
+        }
+        if let Some(v) = pinned.secrets {
+            sec.secrets = v;
+        }
+        if let Some(v) = pinned.accessibility {
+            sec.accessibility = v;
+        }
+        if let Some(v) = pinned.clear_env {
+            sec.clear_env = v;
+        }
+        if let Some(v) = pinned.seccomp {
+            sec.seccomp = Some(v);
+        }
+        if let Some(v) = pinned.no_new_privs {
+            sec.no_new_privs = v;
+        }
+        if let Some(v) = pinned.ephemeral_home {
+            sec.ephemeral_home = v;
+        }
+        if let Some(v) = pinned.persist_paths {
+            sec.persist_paths = v;
+        }
+    }
+    Ok(true)
+}
+
+/// A base policy fragment: file name (for the provenance comment) and its rule lines.
+#[derive(Debug, Clone)]
+pub struct Fragment {
+    pub source: String,
+    pub rules: Vec<String>,
+}
+
+/// Load all `*.conf` fragments from `dir`, sorted by file name. A missing directory means no
+/// base policy. Fragments are rule bodies (e.g. `deny @{HOME}/.ssh/** rw,`), not whole profiles.
+pub fn load_base_policy(dir: &Path) -> Result<Vec<Fragment>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(rd) => rd,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => anyhow::bail!("failed to read {}: {}", dir.display(), e),
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            p.is_file()
+                && p.extension().and_then(|e| e.to_str()) == Some("conf")
+                && !p
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| n.starts_with('.'))
+        })
+        .collect();
+    paths.sort();
+    let mut out = Vec::new();
+    for path in paths {
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+        let source = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default()
+            .to_string();
+        out.push(parse_fragment(&source, &text)?);
+    }
+    Ok(out)
+}
+
+/// Parse one fragment: keep non-empty lines, reject text that could close or escape the profile
+/// block it is pasted into (unbalanced braces) or pull in a whole new profile.
+pub fn parse_fragment(source: &str, text: &str) -> Result<Fragment> {
+    let mut depth: i32 = 0;
+    let mut rules = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        let code = trimmed.split('#').next().unwrap_or("").trim_start();
+        if code.starts_with("profile ") || code.starts_with("abi ") {
+            anyhow::bail!(
+                "{}:{}: policy fragments must contain rules only (no profile or abi lines)",
+                source,
+                i + 1
+            );
+        }
+        for c in code.chars() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ => {}
+            }
+            if depth < 0 {
+                anyhow::bail!("{}:{}: unbalanced '}}' in policy fragment", source, i + 1);
+            }
+        }
+        rules.push(trimmed.to_string());
+    }
+    if depth != 0 {
+        anyhow::bail!("{}: unbalanced '{{' in policy fragment", source);
+    }
+    Ok(Fragment {
+        source: source.to_string(),
+        rules,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_base_policy_missing_dir_is_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(load_base_policy(&dir.path().join("missing"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn load_base_policy_sorted_conf_only() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("20-shadow.conf"), "deny /etc/shadow r,\n").unwrap();
+        std::fs::write(
+            dir.path().join("10-ssh.conf"),
+            "\n  deny @{HOME}/.ssh/** rw,\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("README"), "not a fragment").unwrap();
+        std::fs::write(dir.path().join(".hidden.conf"), "deny /x r,").unwrap();
+        let frags = load_base_policy(dir.path()).unwrap();
+        assert_eq!(frags.len(), 2);
+        assert_eq!(frags[0].source, "10-ssh.conf");
+        assert_eq!(frags[0].rules, ["deny @{HOME}/.ssh/** rw,"]);
+        assert_eq!(frags[1].source, "20-shadow.conf");
+    }
+
+    #[test]
+    fn parse_fragment_allows_alternation() {
+        let f = parse_fragment("a.conf", "deny /etc/{shadow,gshadow} r,").unwrap();
+        assert_eq!(f.rules.len(), 1);
+    }
+
+    #[test]
+    fn parse_fragment_rejects_profile_escape() {
+        assert!(parse_fragment("a.conf", "}\nprofile evil {").is_err());
+        assert!(parse_fragment("a.conf", "/x r, {").is_err());
+        assert!(parse_fragment("a.conf", "profile x { }").is_err());
+    }
+
+    fn config_with_network() -> Config {
+        toml::from_str(
+            "name = \"myapp\"\nexecutable = \"bin/myapp\"\n[security]\nconfine = false\nnetwork = true\nread_paths = [\"/srv\"]\n",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn apply_override_pins_security_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("myapp.toml"),
+            "[security]\nconfine = true\nnetwork = false\n",
+        )
+        .unwrap();
+        let mut cfg = config_with_network();
+        assert!(apply_override(&mut cfg, dir.path()).unwrap());
+        let sec = cfg.security.unwrap();
+        assert!(sec.confine);
+        assert!(!sec.network);
+        // Keys the admin did not set stay as the bundle declared them.
+        assert_eq!(sec.read_paths, ["/srv"]);
+    }
+
+    #[test]
+    fn apply_override_creates_security_section() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("myapp.toml"),
+            "[security]\nnetwork = false\n",
+        )
+        .unwrap();
+        let mut cfg: Config = toml::from_str("name = \"myapp\"\nexecutable = \"a\"\n").unwrap();
+        apply_override(&mut cfg, dir.path()).unwrap();
+        assert!(cfg.security.unwrap().confine);
+    }
+
+    #[test]
+    fn apply_override_absent_is_noop() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cfg = config_with_network();
+        assert!(!apply_override(&mut cfg, dir.path()).unwrap());
+        assert!(cfg.security.unwrap().network);
+    }
+
+    #[test]
+    fn load_override_rejects_unknown_keys_and_bad_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.toml"), "[security]\nconfined = true\n").unwrap();
+        assert!(load_override(dir.path(), "a").is_err());
+        std::fs::write(
+            dir.path().join("b.toml"),
+            "[security]\nread_paths = [\"/x#y\"]\n",
+        )
+        .unwrap();
+        assert!(load_override(dir.path(), "b").is_err());
+        std::fs::write(
+            dir.path().join("c.toml"),
+            "allowed_groups = [\"Lab Staff\"]\n",
+        )
+        .unwrap();
+        assert!(load_override(dir.path(), "c").is_err());
+    }
+
+    #[test]
+    fn apply_override_sets_who_gets_the_app() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("myapp.toml"),
+            "allowed_groups = [\"physics\"]\n",
+        )
+        .unwrap();
+        let mut cfg: Config =
+            toml::from_str("name = \"myapp\"\nexecutable = \"a\"\nallowed_users = [\"alice\"]\n")
+                .unwrap();
+        apply_override(&mut cfg, dir.path()).unwrap();
+        assert_eq!(cfg.allowed_users, ["alice"]);
+        assert_eq!(cfg.allowed_groups, ["physics"]);
+    }
+}
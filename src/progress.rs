@@ -0,0 +1,245 @@
+//! Progress output for commands that move large payloads (install, bundle, export, import): a
+//! bar on stderr when it is a terminal, nothing with --quiet, or JSON lines on stdout with
+//! --json-progress for front ends:
+//!
+//! ```text
+//! {"done":1048576,"item":"Big.lnx","task":"install","total":4294967296}
+//! {"done":4294967296,"finished":true,"item":"Big.lnx","task":"install","total":4294967296}
+//! ```
+//!
+//! `done` and `total` are bytes. `total` is an estimate: taken before the work starts, or (export)
+//! grown as bundles are found.
+
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::cell::{Cell, RefCell};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Least time between two JSON progress lines.
+const JSON_INTERVAL: Duration = Duration::from_millis(250);
+
+/// --json-progress, shared by the commands that report progress. The global -q/--quiet hides
+/// progress too.
+#[derive(clap::Args, Debug, Clone, Copy, Default)]
+pub struct ProgressArgs {
+    /// Do not show progress (set by -q)
+    #[arg(skip)]
+    pub quiet: bool,
+    /// Report progress as JSON lines on stdout instead of a progress bar
+    #[arg(long)]
+    pub json_progress: bool,
+}
+
+enum Output {
+    Hidden,
+    Bar(ProgressBar),
+    Json { last: Cell<Option<Instant>> },
+}
+
+/// Progress of one task, counted in bytes.
+pub struct Progress {
+    task: &'static str,
+    item: RefCell<String>,
+    done: Cell<u64>,
+    total: Cell<u64>,
+    output: Output,
+}
+
+impl Progress {
+    /// Start reporting `task` (e.g. "install") of about `total` bytes.
+    pub fn new(args: ProgressArgs, task: &'static str, total: u64) -> Self {
+        let output = if args.json_progress {
+            Output::Json {
+                last: Cell::new(None),
+            }
+        } else if args.quiet || crate::logging::quiet() {
+            Output::Hidden
+        } else {
+            // Hidden by indicatif itself when stderr is not a terminal (service logs, pipes).
+            let bar = ProgressBar::with_draw_target(Some(total), ProgressDrawTarget::stderr());
+            bar.set_style(
+                ProgressStyle::with_template(
+                    "{msg} [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec} eta {eta}",
+                )
+                .expect("valid template")
+                .progress_chars("=> "),
+            );
+            Output::Bar(bar)
+        };
+        Self {
+            task,
+            item: RefCell::new(String::new()),
+            done: Cell::new(0),
+            total: Cell::new(total),
+            output,
+        }
+    }
+
+    /// No output at all (tests, callers without progress flags).
+    pub fn hidden() -> Self {
+        Self::new(
+            ProgressArgs {
+                quiet: true,
+                json_progress: false,
+            },
+            "",
+            0,
+        )
+    }
+
+    /// Name what is being worked on (a bundle, a file).
+    pub fn set_item(&self, item: &str) {
+        *self.item.borrow_mut() = item.to_string();
+        if let Output::Bar(bar) = &self.output {
+            bar.set_message(item.to_string());
+        }
+    }
+
+    /// Add to the expected total (work found after starting).
+    pub fn add_total(&self, bytes: u64) {
+        self.total.set(self.total.get() + bytes);
+        if let Output::Bar(bar) = &self.output {
+            bar.set_length(self.total.get());
+        }
+    }
+
+    /// Count `bytes` as done.
+    pub fn inc(&self, bytes: u64) {
+        self.done.set(self.done.get() + bytes);
+        match &self.output {
+            Output::Hidden => {}
+            Output::Bar(bar) => bar.inc(bytes),
+            Output::Json { last } => {
+                if last.get().is_none_or(|t| t.elapsed() >= JSON_INTERVAL) {
+                    last.set(Some(Instant::now()));
+                    self.emit(false);
+                }
+            }
+        }
+    }
+
+    /// The task is complete: clear the bar or print the final JSON line.
+    pub fn finish(&self) {
+        match &self.output {
+            Output::Hidden => {}
+            Output::Bar(bar) => bar.finish_and_clear(),
+            Output::Json { .. } => self.emit(true),
+        }
+    }
+
+    fn emit(&self, finished: bool) {
+        let mut line = serde_json::json!({
+            "task": self.task,
+            "item": *self.item.borrow(),
+            "done": self.done.get(),
+            // The estimate can be short (archive headers, files that grew); never report less
+            // total than done.
+            "total": self.total.get().max(self.done.get()),
+        });
+        if finished {
+            line["finished"] = true.into();
+        }
+        let mut out = std::io::stdout().lock();
+        let _ = writeln!(out, "{}", line);
+        let _ = out.flush();
+    }
+
+    /// Wrap a reader so bytes read from it count as done.
+    pub fn reader<R: Read>(&self, inner: R) -> Counted<'_, R> {
+        Counted {
+            inner,
+            progress: self,
+        }
+    }
+
+    /// Wrap a writer so bytes written to it count as done.
+    pub fn writer<W: Write>(&self, inner: W) -> Counted<'_, W> {
+        Counted {
+            inner,
+            progress: self,
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        // A task that fails part way must not leave a half-drawn bar behind the error message.
+        if let Output::Bar(bar) = &self.output {
+            if !bar.is_finished() {
+                bar.abandon();
+            }
+        }
+    }
+}
+
+/// A reader or writer whose traffic is counted by a [`Progress`].
+pub struct Counted<'a, T> {
+    inner: T,
+    progress: &'a Progress,
+}
+
+impl<T> Counted<'_, T> {
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for Counted<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.progress.inc(n as u64);
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Counted<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.progress.inc(n as u64);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Bytes in the regular files under `path` (or of `path` itself), symlinks not followed.
+pub fn tree_size(path: &Path) -> u64 {
+    walkdir::WalkDir::new(path)
+        .follow_links(false)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.metadata().ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counted_io_and_tree_size() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("a"), [0u8; 10]).unwrap();
+        std::fs::write(dir.path().join("sub/b"), [0u8; 5]).unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("/usr/bin", dir.path().join("link")).unwrap();
+        assert_eq!(tree_size(dir.path()), 15);
+
+        let progress = Progress::hidden();
+        let mut out = Vec::new();
+        std::io::copy(
+            &mut progress.reader(&[1u8; 7][..]),
+            &mut progress.writer(&mut out),
+        )
+        .unwrap();
+        assert_eq!(out.len(), 7);
+        assert_eq!(progress.done.get(), 14);
+        progress.finish();
+    }
+}
@@ -0,0 +1,397 @@
+//! `dotlnx report`: a local summary of what dotlnx manages, for fleet inventory runs. Installed
+//! apps with their version and confinement, when they were last launched and why recent launches
+//! failed (from the launch logs, see `launchlog`), quarantined bundles and the changes sync made
+//! lately (state.json). Nothing is sent anywhere: the report is printed for whatever collects it.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::asuser;
+use crate::bundle;
+use crate::config::Seccomp;
+use crate::hold;
+use crate::launchlog;
+use crate::output::{self, Style, Table};
+use crate::paths::Paths;
+use crate::permissions;
+use crate::settings;
+use crate::state;
+
+/// Failed launches listed per app, newest last.
+const MAX_FAILURES: usize = 5;
+/// Changes by sync listed, newest last.
+const MAX_CHANGES: usize = 20;
+
+/// One app.
+#[derive(Debug, Serialize)]
+pub struct AppReport {
+    /// App ID (`vendor.name`, or the name).
+    pub id: String,
+    pub name: String,
+    /// "user:<name>" or "system".
+    pub tier: String,
+    pub bundle: PathBuf,
+    /// What the wildcard of `executable` matched (`1.2.3` for `bin/App-*-x86_64.AppImage`).
+    pub version: Option<String>,
+    /// "apparmor" or "unconfined".
+    pub confinement: &'static str,
+    pub profile: Option<String>,
+    pub seccomp: Option<String>,
+    /// Installed by the last sync (menu entry and profile in place).
+    pub installed: bool,
+    /// Held with `dotlnx hold`.
+    pub held: bool,
+    /// Last launch through `dotlnx run` from the menu (UTC).
+    pub last_launch: Option<String>,
+    pub recent_failures: Vec<Failure>,
+}
+
+/// A launch that failed.
+#[derive(Debug, Serialize)]
+pub struct Failure {
+    /// UTC.
+    pub time: String,
+    /// Who launched it, when root reads every user's logs.
+    pub user: Option<String>,
+    pub reason: String,
+}
+
+/// A quarantined bundle.
+#[derive(Debug, Serialize)]
+pub struct Quarantined {
+    pub bundle: String,
+    #[serde(flatten)]
+    pub entry: state::Quarantine,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub host: String,
+    /// Unix time the report was made.
+    pub generated: u64,
+    pub dotlnx_version: &'static str,
+    pub last_sync: Option<state::SyncRecord>,
+    pub apps: Vec<AppReport>,
+    pub quarantined: Vec<Quarantined>,
+    pub recent_changes: Vec<state::RecordedChange>,
+    pub errors: Vec<permissions::Unreadable>,
+}
+
+/// Print the inventory: installed apps with version, confinement and last launch, failed
+/// launches, quarantined bundles and recent sync changes (all users and the system tier as root).
+pub fn run(json: bool) -> Result<()> {
+    let settings = settings::load()?;
+    let paths = Paths::from_settings(&settings.paths);
+    let report = collect(&settings, &paths)?;
+    if json {
+        return output::json(&report);
+    }
+    print(&report);
+    Ok(())
+}
+
+fn collect(settings: &settings::Settings, paths: &Paths) -> Result<Report> {
+    let is_root = bundle::is_root();
+    let state_dir = state::state_dir()?;
+    let state = state::State::load(&state_dir)?;
+    let holds = hold::load(&state_dir).unwrap_or_default();
+    let users = bundle::user_tier_entries(paths)?;
+    let sources = log_sources(is_root, &users)?;
+    let mut tiers: Vec<(PathBuf, Option<String>)> = users
+        .into_iter()
+        .map(|(apps, _, user)| (apps, Some(user)))
+        .collect();
+    tiers.push((paths.system_apps.clone(), None));
+    let mut apps = Vec::new();
+    let mut errors = Vec::new();
+    for (apps_dir, user) in tiers {
+        for dir in bundle::discover_lnx_dirs(&apps_dir) {
+            let owner = user.as_deref().filter(|_| is_root);
+            let cfg = match permissions::resolve(settings, owner, &dir, is_root) {
+                Ok((cfg, _)) => cfg,
+                Err(e) => {
+                    errors.push(permissions::Unreadable {
+                        bundle: dir,
+                        error: format!("{:#}", e),
+                    });
+                    continue;
+                }
+            };
+            let id = cfg.app_id();
+            let profile = paths.profile_name(user.as_deref(), &id);
+            let confined = cfg.security.as_ref().is_none_or(|s| s.confine);
+            let seccomp = cfg
+                .security
+                .as_ref()
+                .and_then(|s| s.seccomp.as_ref())
+                .map(|s| match s {
+                    Seccomp::Default => "default".to_string(),
+                    Seccomp::Strict => "strict".to_string(),
+                    Seccomp::Profile(p) => p.clone(),
+                });
+            // A user's app is only launched by them; a system app by anyone.
+            let launches: Vec<(Option<String>, launchlog::Event)> = sources
+                .iter()
+                .filter(|(by, _)| {
+                    user.as_deref()
+                        .is_none_or(|u| !is_root || by.as_deref().unwrap_or("root") == u)
+                })
+                .flat_map(|(by, logs)| {
+                    read_events(by.as_deref(), logs, &id)
+                        .into_iter()
+                        .map(move |e| (by.clone(), e))
+                })
+                .collect();
+            apps.push(AppReport {
+                name: cfg.name.clone(),
+                tier: user
+                    .as_ref()
+                    .map(|u| format!("user:{}", u))
+                    .unwrap_or_else(|| "system".into()),
+                version: version(&dir, &cfg.executable),
+                confinement: if confined { "apparmor" } else { "unconfined" },
+                profile: confined.then(|| profile.clone()),
+                seccomp,
+                installed: state.is_installed(&dir),
+                held: holds.contains_key(&profile),
+                last_launch: launches.iter().map(|(_, e)| e.time.clone()).max(),
+                recent_failures: failures(launches),
+                bundle: dir,
+                id,
+            });
+        }
+    }
+    let changes = &state.recent_changes;
+    Ok(Report {
+        host: std::fs::read_to_string("/proc/sys/kernel/hostname")
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+        generated: state::now(),
+        dotlnx_version: env!("CARGO_PKG_VERSION"),
+        last_sync: state.last_sync.clone(),
+        apps,
+        quarantined: state
+            .quarantined
+            .iter()
+            .map(|(bundle, entry)| Quarantined {
+                bundle: bundle.clone(),
+                entry: entry.clone(),
+            })
+            .collect(),
+        recent_changes: changes[changes.len().saturating_sub(MAX_CHANGES)..].to_vec(),
+        errors,
+    })
+}
+
+/// Launch logs to read: who writes them and where. Root reads every user's (as that user) and
+/// its own; anyone else only their own.
+fn log_sources(
+    is_root: bool,
+    users: &[(PathBuf, PathBuf, String)],
+) -> Result<Vec<(Option<String>, PathBuf)>> {
+    let mut sources = vec![(None, launchlog::logs_dir()?)];
+    if is_root {
+        for (apps, _, user) in users.iter().filter(|(_, _, user)| user != "root") {
+            let Some(home) = apps.parent() else { continue };
+            sources.push((Some(user.clone()), home.join(".local/state/dotlnx/logs")));
+        }
+    }
+    Ok(sources)
+}
+
+/// Launches in `app`'s log in `logs`, read as `user` (see `asuser::perform`). Unreadable logs
+/// count as none.
+fn read_events(user: Option<&str>, logs: &Path, app: &str) -> Vec<launchlog::Event> {
+    let req = asuser::Request::ReadFile {
+        dir: logs.to_path_buf(),
+        name: format!("{}.log", app),
+    };
+    match asuser::perform(user, &req) {
+        Ok(Some(text)) => launchlog::events(&text),
+        _ => Vec::new(),
+    }
+}
+
+/// The newest [`MAX_FAILURES`] failed launches, oldest first.
+fn failures(launches: Vec<(Option<String>, launchlog::Event)>) -> Vec<Failure> {
+    let mut failed: Vec<Failure> = launches
+        .into_iter()
+        .filter_map(|(user, e)| {
+            Some(Failure {
+                reason: e.failure?,
+                time: e.time,
+                user,
+            })
+        })
+        .collect();
+    failed.sort_by(|a, b| a.time.cmp(&b.time));
+    failed.split_off(failed.len().saturating_sub(MAX_FAILURES))
+}
+
+/// What the wildcard in the executable's file name matched in the file launched, e.g. `1.2.3` for
+/// `bin/App-*-x86_64.AppImage`. None without a single `*` or a match.
+fn version(bundle: &Path, executable: &str) -> Option<String> {
+    let pattern = Path::new(executable).file_name()?.to_str()?;
+    let (prefix, suffix) = pattern.split_once('*')?;
+    if suffix.contains(['*', '?']) || prefix.contains('?') {
+        return None;
+    }
+    let resolved = bundle::resolve_executable(bundle, executable);
+    let name = resolved.file_name()?.to_str()?;
+    let matched = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+    (!matched.is_empty() && resolved.exists()).then(|| matched.to_string())
+}
+
+fn print(report: &Report) {
+    let last_sync = match &report.last_sync {
+        Some(s) => format!(
+            "{} UTC ({} installed, {} skipped, {} quarantined)",
+            launchlog::timestamp(s.time),
+            s.installed,
+            s.skipped,
+            s.quarantined
+        ),
+        None => "never".into(),
+    };
+    output::fields(&[
+        ("Host", report.host.clone(), Style::Plain),
+        (
+            "Generated",
+            format!("{} UTC", launchlog::timestamp(report.generated)),
+            Style::Plain,
+        ),
+        ("dotlnx", report.dotlnx_version.to_string(), Style::Plain),
+        ("Last sync", last_sync, Style::Plain),
+    ]);
+    if !report.apps.is_empty() {
+        println!();
+        let mut table = Table::new(&[
+            "APP",
+            "TIER",
+            "VERSION",
+            "CONFINEMENT",
+            "SECCOMP",
+            "STATE",
+            "LAST LAUNCH (UTC)",
+        ]);
+        for app in &report.apps {
+            let state = match (app.installed, app.held) {
+                (true, false) => ("installed".into(), Style::Good),
+                (true, true) => ("installed, held".into(), Style::Warn),
+                (false, held) => (
+                    if held {
+                        "not installed, held"
+                    } else {
+                        "not installed"
+                    }
+                    .into(),
+                    Style::Bad,
+                ),
+            };
+            table.row(vec![
+                (app.id.clone(), Style::Plain),
+                (app.tier.clone(), Style::Plain),
+                (
+                    app.version.clone().unwrap_or_else(|| "-".into()),
+                    Style::Plain,
+                ),
+                match &app.profile {
+                    Some(p) => (format!("apparmor {}", p), Style::Good),
+                    None => ("unconfined".into(), Style::Warn),
+                },
+                (
+                    app.seccomp.clone().unwrap_or_else(|| "-".into()),
+                    Style::Plain,
+                ),
+                state,
+                (
+                    app.last_launch.clone().unwrap_or_else(|| "-".into()),
+                    Style::Plain,
+                ),
+            ]);
+        }
+        table.print();
+    }
+    let failed: Vec<(&AppReport, &Failure)> = report
+        .apps
+        .iter()
+        .flat_map(|a| a.recent_failures.iter().map(move |f| (a, f)))
+        .collect();
+    if !failed.is_empty() {
+        println!("\n{}", output::paint("Failed launches:", Style::Heading));
+        for (app, f) in failed {
+            let by = f
+                .user
+                .as_ref()
+                .map(|u| format!(" ({})", u))
+                .unwrap_or_default();
+            println!(
+                "  {} {}{}: {}",
+                app.id,
+                f.time,
+                by,
+                output::paint(&f.reason, Style::Bad)
+            );
+        }
+    }
+    if !report.quarantined.is_empty() {
+        println!("\n{}", output::paint("Quarantined:", Style::Heading));
+        for q in &report.quarantined {
+            println!(
+                "  {}: {}",
+                q.bundle,
+                output::paint(&q.entry.reasons.join("; "), Style::Bad)
+            );
+        }
+    }
+    if !report.recent_changes.is_empty() {
+        println!();
+        let mut table = Table::new(&["CHANGED (UTC)", "ACTION", "APP"]);
+        for c in &report.recent_changes {
+            table.row(vec![
+                (launchlog::timestamp(c.time), Style::Plain),
+                (c.change.action.as_str().to_string(), Style::Plain),
+                (c.change.app.clone(), Style::Plain),
+            ]);
+        }
+        table.print();
+    }
+    for e in &report.errors {
+        println!(
+            "{}: {}",
+            e.bundle.display(),
+            output::paint(&e.error, Style::Bad)
+        );
+    }
+    let failing = report
+        .apps
+        .iter()
+        .filter(|a| !a.recent_failures.is_empty())
+        .count();
+    println!(
+        "\n{} app(s), {} with failed launches, {} quarantined",
+        report.apps.len(),
+        failing,
+        report.quarantined.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_is_what_the_wildcard_matched() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("bin")).unwrap();
+        std::fs::write(dir.path().join("bin/App-1.4.2-x86_64.AppImage"), "").unwrap();
+        assert_eq!(
+            version(dir.path(), "bin/App-*-x86_64.AppImage").as_deref(),
+            Some("1.4.2")
+        );
+        assert_eq!(version(dir.path(), "bin/Other-*.AppImage"), None);
+        assert_eq!(version(dir.path(), "bin/App-1.4.2-x86_64.AppImage"), None);
+    }
+}
@@ -0,0 +1,242 @@
+//! Environment `dotlnx run` starts an app with. By default the app inherits the launching
+//! session's environment plus the site's `[run] env` and the bundle's `env` entries (the bundle's
+//! win). With `[security] clear_env = true` it gets only a short list of session variables
+//! (display, session bus, locale, identity, and SSH_AUTH_SOCK with ssh_agent), a fixed PATH and
+//! the site's and bundle's `env`, so LD_PRELOAD, LD_LIBRARY_PATH, GTK_MODULES and the like set
+//! in the session never reach a confined app. `[accessibility] force_bridges` adds the variables
+//! that turn the toolkits' screen reader support on, under the site's and bundle's `env`.
+
+use std::path::Path;
+use std::process::Command;
+
+use crate::config::Config;
+
+/// Session variables a clear_env app still gets (plus every LC_* locale variable).
+const KEPT: &[&str] = &[
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "LANG",
+    "LANGUAGE",
+    "TZ",
+    "TERM",
+    "DISPLAY",
+    "WAYLAND_DISPLAY",
+    "XAUTHORITY",
+    "XDG_RUNTIME_DIR",
+    "XDG_SESSION_TYPE",
+    "XDG_CURRENT_DESKTOP",
+    "DBUS_SESSION_BUS_ADDRESS",
+    // Socket activation: the sockets systemd passed to a service.
+    "LISTEN_FDS",
+    "LISTEN_PID",
+    "LISTEN_FDNAMES",
+];
+
+/// Variables that make GTK 2, Qt, Chromium/Electron and the Java ATK wrapper start their AT-SPI
+/// bridge whatever the session says (GTK 3 and 4 start it unless NO_AT_BRIDGE=1).
+pub const BRIDGE_VARS: &[(&str, &str)] = &[
+    ("NO_AT_BRIDGE", "0"),
+    ("GTK_MODULES", "gail:atk-bridge"),
+    ("GNOME_ACCESSIBILITY", "1"),
+    ("QT_ACCESSIBILITY", "1"),
+    ("QT_LINUX_ACCESSIBILITY_ALWAYS_ON", "1"),
+    ("ACCESSIBILITY_ENABLED", "1"),
+];
+
+/// PATH of a clear_env app (before the bundle's bin/).
+const CLEAN_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+/// What to change in the inherited environment before exec.
+#[derive(Debug, PartialEq, Eq)]
+pub struct LaunchEnv {
+    /// Start from an empty environment instead of the session's.
+    pub clear: bool,
+    /// Variables to set, in order (later entries win).
+    pub vars: Vec<(String, String)>,
+}
+
+impl LaunchEnv {
+    /// Environment for `config`'s app in `bundle_root`, given the launching session's variables.
+    pub fn build(
+        config: &Config,
+        bundle_root: &Path,
+        session: impl IntoIterator<Item = (String, String)>,
+    ) -> Self {
+        let clear = config.security.as_ref().is_some_and(|s| s.clear_env);
+        let ssh_agent = config.security.as_ref().is_some_and(|s| s.ssh_agent);
+        let a11y_bus = config.security.as_ref().is_some_and(|s| s.accessibility);
+        let session: Vec<(String, String)> = session.into_iter().collect();
+        let mut vars: Vec<(String, String)> = if clear {
+            session
+                .iter()
+                .filter(|(k, _)| {
+                    KEPT.contains(&k.as_str())
+                        || k.starts_with("LC_")
+                        || (ssh_agent && k == "SSH_AUTH_SOCK")
+                        || (a11y_bus && k == "AT_SPI_BUS_ADDRESS")
+                })
+                .cloned()
+                .collect()
+        } else {
+            Vec::new()
+        };
+        if config
+            .accessibility
+            .as_ref()
+            .is_some_and(|a| a.force_bridges)
+        {
+            vars.extend(
+                BRIDGE_VARS
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string())),
+            );
+        }
+        vars.extend(config.site_env.iter().chain(&config.env).filter_map(|s| {
+            let (k, v) = s.split_once('=')?;
+            Some((k.trim().into(), v.trim().into()))
+        }));
+        // Ensure PATH includes bundle bin if present
+        let bin_dir = bundle_root.join("bin");
+        if bin_dir.exists() {
+            let path = if clear {
+                CLEAN_PATH.to_string()
+            } else {
+                session
+                    .iter()
+                    .find(|(k, _)| k == "PATH")
+                    .map(|(_, v)| v.clone())
+                    .unwrap_or_default()
+            };
+            vars.push(("PATH".into(), format!("{}:{}", bin_dir.display(), path)));
+        } else if clear {
+            vars.push(("PATH".into(), CLEAN_PATH.into()));
+        }
+        Self { clear, vars }
+    }
+
+    pub fn apply(&self, cmd: &mut Command) {
+        if self.clear {
+            cmd.env_clear();
+        }
+        for (k, v) in &self.vars {
+            cmd.env(k, v);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> Config {
+        toml::from_str(&format!("name = \"a\"\nexecutable = \"bin/a\"\n{}", toml)).unwrap()
+    }
+
+    fn session() -> Vec<(String, String)> {
+        [
+            ("PATH", "/home/u/bin:/usr/bin"),
+            ("HOME", "/home/u"),
+            ("LC_TIME", "de_DE.UTF-8"),
+            ("LD_PRELOAD", "/tmp/evil.so"),
+            ("WAYLAND_DISPLAY", "wayland-0"),
+            ("SSH_AUTH_SOCK", "/run/user/1000/keyring/ssh"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.into(), v.into()))
+        .collect()
+    }
+
+    fn names(env: &LaunchEnv) -> Vec<&str> {
+        env.vars.iter().map(|(k, _)| k.as_str()).collect()
+    }
+
+    #[test]
+    fn inherits_the_session_by_default() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("bin")).unwrap();
+        let env = LaunchEnv::build(&config("env = [\"FOO=bar\"]\n"), dir.path(), session());
+        assert!(!env.clear);
+        assert_eq!(names(&env), ["FOO", "PATH"]);
+        assert_eq!(
+            env.vars[1].1,
+            format!("{}:/home/u/bin:/usr/bin", dir.path().join("bin").display())
+        );
+    }
+
+    #[test]
+    fn site_env_comes_under_the_bundle_env() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cfg = config("env = [\"LANG=C.UTF-8\"]\n");
+        cfg.site_env = vec![
+            "HTTP_PROXY=http://proxy:3128".into(),
+            "LANG=de_DE.UTF-8".into(),
+        ];
+        let env = LaunchEnv::build(&cfg, dir.path(), session());
+        assert_eq!(names(&env), ["HTTP_PROXY", "LANG", "LANG"]);
+        assert_eq!(
+            env.vars[2].1, "C.UTF-8",
+            "set last, the bundle's value wins"
+        );
+    }
+
+    #[test]
+    fn clear_env_keeps_only_session_basics_and_declared_vars() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg =
+            config("env = [\"FOO=bar\", \"LD_LIBRARY_PATH=lib\"]\n[security]\nclear_env = true\n");
+        let env = LaunchEnv::build(&cfg, dir.path(), session());
+        assert!(env.clear);
+        assert_eq!(
+            names(&env),
+            [
+                "HOME",
+                "LC_TIME",
+                "WAYLAND_DISPLAY",
+                "FOO",
+                "LD_LIBRARY_PATH",
+                "PATH"
+            ]
+        );
+        assert_eq!(env.vars.last().unwrap().1, CLEAN_PATH);
+
+        let mut cmd = Command::new("true");
+        env.apply(&mut cmd);
+        let set: Vec<_> = cmd
+            .get_envs()
+            .map(|(k, _)| k.to_string_lossy().into_owned())
+            .collect();
+        assert!(!set.contains(&"LD_PRELOAD".to_string()));
+
+        let cfg = config("[security]\nclear_env = true\nssh_agent = true\n");
+        let env = LaunchEnv::build(&cfg, dir.path(), session());
+        assert_eq!(
+            names(&env),
+            [
+                "HOME",
+                "LC_TIME",
+                "WAYLAND_DISPLAY",
+                "SSH_AUTH_SOCK",
+                "PATH"
+            ]
+        );
+    }
+
+    #[test]
+    fn force_bridges_turns_toolkit_accessibility_on_under_the_bundle_env() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = config("env = [\"QT_ACCESSIBILITY=0\"]\n[accessibility]\nforce_bridges = true\n");
+        assert!(cfg.needs_runner());
+        let env = LaunchEnv::build(&cfg, dir.path(), session());
+        assert!(env
+            .vars
+            .contains(&("GTK_MODULES".into(), "gail:atk-bridge".into())));
+        let qt: Vec<&str> = env
+            .vars
+            .iter()
+            .filter(|(k, _)| k == "QT_ACCESSIBILITY")
+            .map(|(_, v)| v.as_str())
+            .collect();
+        assert_eq!(qt, ["1", "0"], "the bundle's value is set last");
+    }
+}
@@ -0,0 +1,163 @@
+//! Process settings `dotlnx run` applies to an app between fork and exec: no_new_privs, umask,
+//! nice and ionice. They are inherited through exec, so they cover aa-exec, the app and whatever
+//! the app starts. Also the bounded runs of `dotlnx run --wait`.
+
+use nix::libc;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, ExitStatus};
+use std::time::{Duration, Instant};
+
+use crate::config::{Config, Ionice};
+
+/// ioprio_set(2): which = IOPRIO_WHO_PROCESS, classes and the shift that packs them.
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+const IOPRIO_CLASS_BE: libc::c_int = 2;
+const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+/// After SIGTERM, how long a stopped app gets to quit before SIGKILL.
+const STOP_GRACE: Duration = Duration::from_secs(5);
+
+/// What to set in the child, resolved from the config before fork.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessSettings {
+    pub no_new_privs: bool,
+    pub umask: Option<u32>,
+    pub nice: Option<i32>,
+    pub ionice: Option<Ionice>,
+}
+
+impl ProcessSettings {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            no_new_privs: config.security.as_ref().is_some_and(|s| s.no_new_privs),
+            umask: config.umask.map(|u| u.0),
+            nice: config.nice,
+            ionice: config.ionice,
+        }
+    }
+
+    /// Apply in the process `cmd` starts, just before exec. A setting the kernel refuses makes
+    /// the launch fail rather than start the app without it.
+    pub fn install(self, cmd: &mut Command) {
+        if self == Self::default() {
+            return;
+        }
+        // SAFETY: the closure only makes async-signal-safe syscalls (no allocation) and runs in
+        // the forked child.
+        unsafe {
+            cmd.pre_exec(move || self.apply());
+        }
+    }
+
+    fn apply(&self) -> std::io::Result<()> {
+        let check = |rc: libc::c_int| {
+            if rc < 0 {
+                Err(std::io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        };
+        if let Some(mask) = self.umask {
+            // SAFETY: umask cannot fail.
+            unsafe { libc::umask(mask as libc::mode_t) };
+        }
+        if let Some(nice) = self.nice {
+            // SAFETY: plain syscall on the calling process.
+            check(unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) })?;
+        }
+        if let Some(ionice) = self.ionice {
+            let prio = match ionice {
+                Ionice::Idle => IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+                Ionice::BestEffort(level) => {
+                    (IOPRIO_CLASS_BE << IOPRIO_CLASS_SHIFT) | level as libc::c_int
+                }
+            };
+            // SAFETY: plain syscall on the calling process.
+            let rc = unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, prio) };
+            check(rc as libc::c_int)?;
+        }
+        if self.no_new_privs {
+            // SAFETY: plain prctl on the calling process.
+            check(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })?;
+        }
+        Ok(())
+    }
+}
+
+/// How a run bounded by `dotlnx run --wait` ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ended {
+    /// The app exited by itself.
+    Exited(ExitStatus),
+    /// The app was still running at the limit and has been stopped.
+    Stopped,
+}
+
+/// Start `cmd` in a process group of its own and wait at most `limit` for it. An app still
+/// running then is stopped with everything it started: SIGTERM to the group, SIGKILL after
+/// [`STOP_GRACE`].
+pub fn run_bounded(cmd: &mut Command, limit: Duration) -> std::io::Result<Ended> {
+    let mut child = cmd.process_group(0).spawn()?;
+    let group = -(child.id() as libc::pid_t);
+    let deadline = Instant::now() + limit;
+    while Instant::now() < deadline {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Ended::Exited(status));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    // SAFETY: plain kill(2) of the group the child leads.
+    unsafe { libc::kill(group, libc::SIGTERM) };
+    let grace = Instant::now() + STOP_GRACE;
+    while child.try_wait()?.is_none() && Instant::now() < grace {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    // SAFETY: as above; also catches group members that outlived the app.
+    unsafe { libc::kill(group, libc::SIGKILL) };
+    child.wait()?;
+    Ok(Ended::Stopped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_come_from_the_config() {
+        let cfg = crate::config::parse(
+            "name = \"a\"\nexecutable = \"a\"\numask = \"027\"\nnice = 10\nionice = \"best-effort:6\"\n[security]\nno_new_privs = true\n",
+        )
+        .unwrap();
+        let s = ProcessSettings::from_config(&cfg);
+        assert_eq!(
+            s,
+            ProcessSettings {
+                no_new_privs: true,
+                umask: Some(0o027),
+                nice: Some(10),
+                ionice: Some(Ionice::BestEffort(6)),
+            }
+        );
+        let plain = crate::config::parse("name = \"a\"\nexecutable = \"a\"\n").unwrap();
+        assert_eq!(
+            ProcessSettings::from_config(&plain),
+            ProcessSettings::default()
+        );
+    }
+
+    #[test]
+    fn bounded_runs_stop_apps_that_outlive_the_limit() {
+        let limit = Duration::from_millis(300);
+        let quick = run_bounded(Command::new("sh").args(["-c", "exit 3"]), limit).unwrap();
+        assert!(matches!(quick, Ended::Exited(s) if s.code() == Some(3)));
+        let started = Instant::now();
+        let slow = run_bounded(
+            Command::new("sh").args(["-c", "sleep 30 & sleep 30"]),
+            limit,
+        )
+        .unwrap();
+        assert_eq!(slow, Ended::Stopped);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
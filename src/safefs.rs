@@ -0,0 +1,437 @@
+//! Symlink-safe file access for directories users control (bundles, ~/.local/share/applications).
+//! A directory is opened once and everything after goes through its fd with O_NOFOLLOW, so a user
+//! cannot swap in a symlink between root's validation and root's reads/writes.
+
+use anyhow::{Context, Result};
+use nix::fcntl::{openat, OFlag};
+use nix::sys::stat::Mode;
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Component, Path, PathBuf};
+
+/// Mode of every file dotlnx writes in a home or bundle, whatever the umask.
+pub const FILE_MODE: u32 = 0o644;
+/// Mode of every directory dotlnx creates there.
+pub const DIR_MODE: u32 = 0o755;
+
+/// An open directory; all operations are relative to its fd and never follow a final symlink.
+pub struct SafeDir {
+    fd: OwnedFd,
+    path: PathBuf,
+}
+
+fn dir_flags() -> OFlag {
+    OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC
+}
+
+/// Reject anything but a single, plain path component (no separators, no "..").
+fn single_component(name: &str) -> Result<()> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(()),
+        _ => anyhow::bail!("not a plain file name: {:?}", name),
+    }
+}
+
+impl SafeDir {
+    /// Open `base` (following symlinks: it is trusted, e.g. a home dir), then walk `rel` one
+    /// component at a time refusing symlinks. `rel` must be relative and free of "..".
+    pub fn open_under(base: &Path, rel: &Path) -> Result<Self> {
+        let fd = nix::fcntl::open(
+            base,
+            OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )
+        .with_context(|| format!("open {}", base.display()))?;
+        // SAFETY: fd was just returned by open(2) and is owned by nobody else.
+        let mut dir = SafeDir {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            path: base.to_path_buf(),
+        };
+        for component in rel.components() {
+            match component {
+                Component::Normal(name) => {
+                    let name = name
+                        .to_str()
+                        .ok_or_else(|| anyhow::anyhow!("path not UTF-8: {}", rel.display()))?;
+                    dir = dir.subdir(name)?;
+                }
+                Component::CurDir => {}
+                _ => anyhow::bail!("refusing non-relative path {}", rel.display()),
+            }
+        }
+        Ok(dir)
+    }
+
+    /// Open a directory whose final component must not be a symlink (parents are followed).
+    pub fn open(path: &Path) -> Result<Self> {
+        let parent = path.parent().unwrap_or(Path::new("/"));
+        let name = path
+            .file_name()
+            .ok_or_else(|| anyhow::anyhow!("no file name in {}", path.display()))?;
+        Self::open_under(parent, Path::new(name))
+    }
+
+    /// Open a child directory of this one (no symlink following).
+    pub fn subdir(&self, name: &str) -> Result<Self> {
+        single_component(name)?;
+        let fd = openat(Some(self.fd.as_raw_fd()), name, dir_flags(), Mode::empty()).with_context(
+            || format!("open {} (symlinks refused)", self.path.join(name).display()),
+        )?;
+        Ok(SafeDir {
+            // SAFETY: fd was just returned by openat(2) and is owned by nobody else.
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            path: self.path.join(name),
+        })
+    }
+
+    /// Open a child directory, creating it first (DIR_MODE, not whatever the umask leaves) when
+    /// missing. Returns it and whether it was created.
+    pub fn create_subdir(&self, name: &str) -> Result<(Self, bool)> {
+        single_component(name)?;
+        let created = match nix::sys::stat::mkdirat(
+            Some(self.fd.as_raw_fd()),
+            name,
+            Mode::from_bits_truncate(DIR_MODE),
+        ) {
+            Ok(()) => true,
+            Err(nix::errno::Errno::EEXIST) => false,
+            Err(e) => {
+                return Err(anyhow::anyhow!(
+                    "create {}: {}",
+                    self.path.join(name).display(),
+                    e
+                ))
+            }
+        };
+        let dir = self.subdir(name)?;
+        if created {
+            nix::sys::stat::fchmod(dir.fd.as_raw_fd(), Mode::from_bits_truncate(DIR_MODE))
+                .with_context(|| format!("chmod {}", dir.path.display()))?;
+        }
+        Ok((dir, created))
+    }
+
+    /// Owner (uid, gid) of this directory.
+    pub fn owner(&self) -> Result<(u32, u32)> {
+        let st = nix::sys::stat::fstat(self.fd.as_raw_fd())
+            .with_context(|| format!("stat {}", self.path.display()))?;
+        Ok((st.st_uid, st.st_gid))
+    }
+
+    /// Give this directory to `uid`/`gid`. Goes through the open fd, so it changes the directory
+    /// that was opened, never a symlink swapped in since.
+    pub fn chown(&self, uid: u32, gid: u32) -> Result<()> {
+        std::os::unix::fs::fchown(&self.fd, Some(uid), Some(gid))
+            .with_context(|| format!("chown {}", self.path.display()))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read a regular file in this directory, refusing symlinks and files over `max_len` bytes.
+    pub fn read_to_string(&self, name: &str, max_len: u64) -> Result<String> {
+        let mut file = self.open_file(name, OFlag::O_RDONLY, Mode::empty())?;
+        let meta = file.metadata()?;
+        if !meta.is_file() {
+            anyhow::bail!("{} is not a regular file", self.path.join(name).display());
+        }
+        if meta.len() > max_len {
+            anyhow::bail!(
+                "{} is too large ({} bytes, limit {})",
+                self.path.join(name).display(),
+                meta.len(),
+                max_len
+            );
+        }
+        let mut s = String::new();
+        (&mut file).take(max_len + 1).read_to_string(&mut s)?;
+        if s.len() as u64 > max_len {
+            anyhow::bail!("{} grew while reading", self.path.join(name).display());
+        }
+        Ok(s)
+    }
+
+    /// Read a regular file below this directory (`rel` is relative and free of ".."), refusing
+    /// symlinks anywhere on the way.
+    pub fn read_nested(&self, rel: &Path, max_len: u64) -> Result<String> {
+        let mut names = Vec::new();
+        for component in rel.components() {
+            match component {
+                Component::Normal(name) => names.push(
+                    name.to_str()
+                        .ok_or_else(|| anyhow::anyhow!("path not UTF-8: {}", rel.display()))?,
+                ),
+                Component::CurDir => {}
+                _ => anyhow::bail!("refusing non-relative path {}", rel.display()),
+            }
+        }
+        let Some((file, dirs)) = names.split_last() else {
+            anyhow::bail!("empty path");
+        };
+        let mut dir: Option<SafeDir> = None;
+        for name in dirs {
+            dir = Some(dir.as_ref().unwrap_or(self).subdir(name)?);
+        }
+        dir.as_ref().unwrap_or(self).read_to_string(file, max_len)
+    }
+
+    /// Create or truncate a regular file in this directory, leaving it at FILE_MODE (a file an
+    /// earlier umask left private is fixed too). Refuses to write through a symlink or into
+    /// anything that is not a regular file.
+    pub fn write(&self, name: &str, contents: &[u8]) -> Result<std::fs::File> {
+        use std::os::unix::fs::PermissionsExt;
+        let mut file = self.open_file(
+            name,
+            OFlag::O_WRONLY | OFlag::O_CREAT,
+            Mode::from_bits_truncate(FILE_MODE),
+        )?;
+        let meta = file.metadata()?;
+        if !meta.is_file() {
+            anyhow::bail!("{} is not a regular file", self.path.join(name).display());
+        }
+        if meta.permissions().mode() & 0o7777 != FILE_MODE {
+            file.set_permissions(std::fs::Permissions::from_mode(FILE_MODE))?;
+        }
+        file.set_len(0)?;
+        file.write_all(contents)?;
+        Ok(file)
+    }
+
+    /// Remove a file in this directory if present (a symlink is removed itself, not its target).
+    pub fn remove(&self, name: &str) -> Result<()> {
+        single_component(name)?;
+        match nix::unistd::unlinkat(
+            Some(self.fd.as_raw_fd()),
+            name,
+            nix::unistd::UnlinkatFlags::NoRemoveDir,
+        ) {
+            Ok(()) | Err(nix::errno::Errno::ENOENT) => Ok(()),
+            Err(e) => Err(anyhow::anyhow!(
+                "remove {}: {}",
+                self.path.join(name).display(),
+                e
+            )),
+        }
+    }
+
+    fn open_file(&self, name: &str, flags: OFlag, mode: Mode) -> Result<std::fs::File> {
+        single_component(name)?;
+        let fd = openat(
+            Some(self.fd.as_raw_fd()),
+            name,
+            flags | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC | OFlag::O_NONBLOCK,
+            mode,
+        )
+        .with_context(|| format!("open {} (symlinks refused)", self.path.join(name).display()))?;
+        // SAFETY: fd was just returned by openat(2) and is owned by nobody else.
+        Ok(std::fs::File::from(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+}
+
+/// Create `path` and any missing parents at DIR_MODE (like create_dir_all, but independent of
+/// the umask). Only the missing part is walked without following symlinks.
+pub fn create_dirs(path: &Path) -> Result<()> {
+    let mut existing = path;
+    let mut missing = Vec::new();
+    while std::fs::symlink_metadata(existing).is_err() {
+        let (Some(parent), Some(name)) = (existing.parent(), existing.file_name()) else {
+            break;
+        };
+        missing.push(name);
+        existing = parent;
+    }
+    let mut dir = SafeDir::open_under(existing, Path::new(""))?;
+    for name in missing.into_iter().rev() {
+        let name = name
+            .to_str()
+            .ok_or_else(|| anyhow::anyhow!("path not UTF-8: {}", path.display()))?;
+        dir = dir.create_subdir(name)?.0;
+    }
+    Ok(())
+}
+
+/// Walk `rel` under `home` (refusing symlinks), creating missing directories and handing ones
+/// root owns to `uid`/`gid`: after a `sudo` mistake ~/.local/share can end up root's, and the
+/// user can then no longer write there. Directories owned by another user are an error, not
+/// something to take over. Returns what was repaired, for the log.
+pub fn ensure_owned_dirs(home: &Path, rel: &Path, uid: u32, gid: u32) -> Result<Vec<String>> {
+    let mut dir = SafeDir::open_under(home, Path::new(""))?;
+    let mut repaired = Vec::new();
+    for component in rel.components() {
+        let Component::Normal(name) = component else {
+            anyhow::bail!("refusing non-relative path {}", rel.display());
+        };
+        let name = name
+            .to_str()
+            .ok_or_else(|| anyhow::anyhow!("path not UTF-8: {}", rel.display()))?;
+        let (sub, created) = dir.create_subdir(name)?;
+        let (owner, _) = sub.owner()?;
+        if created || (owner == 0 && uid != 0) {
+            sub.chown(uid, gid)?;
+            let what = if created {
+                "created"
+            } else {
+                "was owned by root"
+            };
+            repaired.push(format!("{} ({})", sub.path().display(), what));
+        } else if owner != uid {
+            anyhow::bail!(
+                "{} belongs to uid {}, not {}",
+                sub.path().display(),
+                owner,
+                uid
+            );
+        }
+        dir = sub;
+    }
+    Ok(repaired)
+}
+
+/// Make sure no directory between `root` and `dest` is a symlink, so an archive entry cannot be
+/// redirected outside `root` by a link created earlier (in the archive or on disk). Path-based:
+/// for trees the caller is populating itself, not ones another user can change concurrently.
+pub fn refuse_symlinked_parents(root: &Path, dest: &Path) -> Result<()> {
+    let rel = dest
+        .strip_prefix(root)
+        .map_err(|_| anyhow::anyhow!("{} escapes {}", dest.display(), root.display()))?;
+    let mut cur = root.to_path_buf();
+    let mut components = rel.components().peekable();
+    while let Some(c) = components.next() {
+        if components.peek().is_none() {
+            break;
+        }
+        cur.push(c);
+        if let Ok(meta) = std::fs::symlink_metadata(&cur) {
+            if meta.file_type().is_symlink() {
+                anyhow::bail!("refusing to write through symlink {}", cur.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuse_symlinked_parents_detects_links() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("Applications");
+        std::fs::create_dir_all(root.join("a.lnx")).unwrap();
+        assert!(refuse_symlinked_parents(&root, &root.join("a.lnx/bin/x")).is_ok());
+        std::os::unix::fs::symlink(tmp.path(), root.join("a.lnx/bin")).unwrap();
+        assert!(refuse_symlinked_parents(&root, &root.join("a.lnx/bin/x")).is_err());
+    }
+
+    #[test]
+    fn read_and_write_regular_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(tmp.path().join("a.lnx")).unwrap();
+        let dir = SafeDir::open_under(tmp.path(), Path::new("a.lnx")).unwrap();
+        dir.write("config.toml", b"name = \"a\"").unwrap();
+        assert_eq!(
+            dir.read_to_string("config.toml", 1024).unwrap(),
+            "name = \"a\""
+        );
+        dir.remove("config.toml").unwrap();
+        assert!(!tmp.path().join("a.lnx/config.toml").exists());
+        dir.remove("config.toml").unwrap();
+    }
+
+    #[test]
+    fn refuses_symlinked_file_and_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let outside = tmp.path().join("outside");
+        std::fs::write(&outside, "secret").unwrap();
+        std::fs::create_dir_all(tmp.path().join("a.lnx")).unwrap();
+        std::os::unix::fs::symlink(&outside, tmp.path().join("a.lnx/config.toml")).unwrap();
+        let dir = SafeDir::open_under(tmp.path(), Path::new("a.lnx")).unwrap();
+        assert!(dir.read_to_string("config.toml", 1024).is_err());
+        assert!(dir.write("config.toml", b"x").is_err());
+        assert_eq!(std::fs::read_to_string(&outside).unwrap(), "secret");
+
+        std::os::unix::fs::symlink(tmp.path().join("a.lnx"), tmp.path().join("b.lnx")).unwrap();
+        assert!(SafeDir::open_under(tmp.path(), Path::new("b.lnx")).is_err());
+        assert!(SafeDir::open(&tmp.path().join("b.lnx")).is_err());
+    }
+
+    #[test]
+    fn refuses_parent_and_multi_component_names() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = SafeDir::open_under(tmp.path(), Path::new("")).unwrap();
+        assert!(dir.write("../x", b"x").is_err());
+        assert!(dir.write("a/b", b"x").is_err());
+        assert!(SafeDir::open_under(tmp.path(), Path::new("../x")).is_err());
+    }
+
+    #[test]
+    fn ensure_owned_dirs_creates_and_refuses_links() {
+        let tmp = tempfile::tempdir().unwrap();
+        let home = tmp.path().join("home");
+        std::fs::create_dir_all(home.join(".local")).unwrap();
+        let (uid, gid) = (
+            nix::unistd::getuid().as_raw(),
+            nix::unistd::getgid().as_raw(),
+        );
+        let repaired =
+            ensure_owned_dirs(&home, Path::new(".local/share/applications"), uid, gid).unwrap();
+        assert_eq!(repaired.len(), 2, "{:?}", repaired);
+        assert!(repaired[0].ends_with(".local/share (created)"));
+        assert!(home.join(".local/share/applications").is_dir());
+        assert!(
+            ensure_owned_dirs(&home, Path::new(".local/share/applications"), uid, gid)
+                .unwrap()
+                .is_empty()
+        );
+
+        std::fs::remove_dir(home.join(".local/share/applications")).unwrap();
+        std::os::unix::fs::symlink(tmp.path(), home.join(".local/share/applications")).unwrap();
+        assert!(
+            ensure_owned_dirs(&home, Path::new(".local/share/applications"), uid, gid).is_err()
+        );
+    }
+
+    #[test]
+    fn written_files_and_created_dirs_get_standard_modes() {
+        use std::os::unix::fs::PermissionsExt;
+        let tmp = tempfile::tempdir().unwrap();
+        let apps = tmp.path().join("share/applications");
+        create_dirs(&apps).unwrap();
+        create_dirs(&apps).unwrap();
+        let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode(&tmp.path().join("share")), DIR_MODE);
+        assert_eq!(mode(&apps), DIR_MODE);
+        std::fs::write(apps.join("a.desktop"), "old").unwrap();
+        std::fs::set_permissions(
+            apps.join("a.desktop"),
+            std::fs::Permissions::from_mode(0o600),
+        )
+        .unwrap();
+        SafeDir::open(&apps)
+            .unwrap()
+            .write("a.desktop", b"new")
+            .unwrap();
+        SafeDir::open(&apps)
+            .unwrap()
+            .write("b.desktop", b"new")
+            .unwrap();
+        assert_eq!(mode(&apps.join("a.desktop")), FILE_MODE);
+        assert_eq!(mode(&apps.join("b.desktop")), FILE_MODE);
+        assert_eq!(
+            std::fs::read_to_string(apps.join("a.desktop")).unwrap(),
+            "new"
+        );
+    }
+
+    #[test]
+    fn read_respects_size_limit() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("big"), vec![b'a'; 100]).unwrap();
+        let dir = SafeDir::open_under(tmp.path(), Path::new("")).unwrap();
+        assert!(dir.read_to_string("big", 10).is_err());
+        assert_eq!(dir.read_to_string("big", 100).unwrap().len(), 100);
+    }
+}
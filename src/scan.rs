@@ -0,0 +1,315 @@
+//! Optional scanning of bundles before they are installed ([scan] in the site config): built-in
+//! heuristics and/or an external scanner such as clamscan. A bundle with findings is quarantined
+//! by sync (not installed, recorded in state, its user notified) and refused by `dotlnx install`.
+//!
+//! Scanning reads every file of a bundle, so verdicts are cached in state by a fingerprint of the
+//! bundle's file list (names, sizes, mtimes, modes) and the scan settings: a bundle is scanned
+//! again only when it or the scanner configuration changes. Paths the bundle's config excludes
+//! (the app's own cache or logs) are scanned but left out of the fingerprint, so an app writing
+//! them does not trigger a rescan on every sync.
+
+use anyhow::{Context, Result};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::settings::ScanSettings;
+
+/// Files looked at per bundle by the built-in checks (and the fingerprint).
+const MAX_FILES: usize = 50_000;
+/// Bytes of an executable read for the entropy check.
+const ENTROPY_SAMPLE: usize = 1024 * 1024;
+/// Executables smaller than this are too small for entropy to mean anything.
+const ENTROPY_MIN: usize = 4096;
+/// Bits per byte above which an executable looks packed or encrypted. Compiled code and data
+/// sit around 5-6.5; compressed or encrypted bytes near 8.
+const ENTROPY_LIMIT: f64 = 7.5;
+/// Lines of scanner output kept as the quarantine reason.
+const MAX_REPORT_LINES: usize = 5;
+
+/// True when [scan] asks for any scanning.
+pub fn enabled(settings: &ScanSettings) -> bool {
+    settings.checks || !settings.command.is_empty()
+}
+
+/// Scan a bundle: what is wrong with it, empty when clean. An error means the scan itself failed
+/// (scanner missing, crashed or timed out); callers treat that as not clean.
+pub fn scan(settings: &ScanSettings, bundle: &Path) -> Result<Vec<String>> {
+    let mut findings = Vec::new();
+    if settings.checks {
+        findings.extend(builtin_findings(bundle));
+    }
+    if !settings.command.is_empty() {
+        findings.extend(run_command(
+            &settings.command,
+            bundle,
+            Duration::from_secs(settings.timeout),
+        )?);
+    }
+    Ok(findings)
+}
+
+/// Entries of a bundle (symlinks not followed), relative path first.
+fn files(bundle: &Path) -> impl Iterator<Item = (PathBuf, walkdir::DirEntry)> + '_ {
+    walkdir::WalkDir::new(bundle)
+        .follow_links(false)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .take(MAX_FILES)
+        .map(move |e| {
+            (
+                e.path()
+                    .strip_prefix(bundle)
+                    .unwrap_or(e.path())
+                    .to_path_buf(),
+                e,
+            )
+        })
+}
+
+/// Built-in checks: executables hidden in dot-directories or dot-files, and executables whose
+/// contents look packed or encrypted (a common way to hide a payload). Setuid files are refused
+/// by validation whether or not scanning is on.
+#[cfg(unix)]
+pub fn builtin_findings(bundle: &Path) -> Vec<String> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut findings = Vec::new();
+    for (relative, entry) in files(bundle) {
+        let Ok(meta) = entry.metadata() else { continue };
+        if !meta.is_file() || meta.permissions().mode() & 0o111 == 0 {
+            continue;
+        }
+        let Ok(head) = read_head(entry.path(), ENTROPY_SAMPLE) else {
+            continue;
+        };
+        let is_elf = head.starts_with(b"\x7fELF");
+        if !is_elf && !head.starts_with(b"#!") {
+            continue;
+        }
+        let hidden = relative
+            .components()
+            .any(|c| c.as_os_str().to_str().is_some_and(|s| s.starts_with('.')));
+        if hidden {
+            findings.push(format!("hidden executable {}", relative.display()));
+        }
+        // AppImages (type 2: "AI\x02" after the ELF magic) carry a compressed file system.
+        let appimage = head.get(8..11) == Some(b"AI\x02");
+        if is_elf && !appimage && head.len() >= ENTROPY_MIN {
+            let bits = entropy(&head);
+            if bits > ENTROPY_LIMIT {
+                findings.push(format!(
+                    "packed or encrypted executable {} ({:.1} bits/byte)",
+                    relative.display(),
+                    bits
+                ));
+            }
+        }
+    }
+    findings
+}
+
+#[cfg(not(unix))]
+pub fn builtin_findings(_bundle: &Path) -> Vec<String> {
+    Vec::new()
+}
+
+/// Up to `limit` bytes from the start of a file.
+fn read_head(path: &Path, limit: usize) -> std::io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    std::fs::File::open(path)?
+        .take(limit as u64)
+        .read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Shannon entropy of `data`, in bits per byte (0 to 8).
+fn entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &b in data {
+        counts[b as usize] += 1;
+    }
+    let len = data.len() as f64;
+    counts
+        .iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Run the external scanner with the bundle path as its last argument. Exit status 0 is clean
+/// and 1 is "found something" (clamscan's convention): its output becomes the finding. Anything
+/// else, or running longer than `timeout`, is an error.
+fn run_command(argv: &[String], bundle: &Path, timeout: Duration) -> Result<Vec<String>> {
+    let mut child = Command::new(&argv[0])
+        .args(&argv[1..])
+        .arg(bundle)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("run scanner {}", argv[0]))?;
+    // Read output on a thread so a chatty scanner cannot block on a full pipe.
+    let mut stdout = child.stdout.take().expect("piped stdout");
+    let reader = std::thread::spawn(move || {
+        let mut out = Vec::new();
+        let _ = (&mut stdout).take(64 * 1024).read_to_end(&mut out);
+        let _ = std::io::copy(&mut stdout, &mut std::io::sink());
+        out
+    });
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            anyhow::bail!("scanner {} timed out after {}s", argv[0], timeout.as_secs());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    };
+    let out = reader.join().unwrap_or_default();
+    match status.code() {
+        Some(0) => Ok(Vec::new()),
+        Some(1) => {
+            let out = String::from_utf8_lossy(&out);
+            let mut lines: Vec<String> = out
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+                .take(MAX_REPORT_LINES)
+                .map(|l| format!("{}: {}", argv[0], l))
+                .collect();
+            if lines.is_empty() {
+                lines.push(format!("flagged by {}", argv[0]));
+            }
+            Ok(lines)
+        }
+        _ => anyhow::bail!("scanner {} failed ({})", argv[0], status),
+    }
+}
+
+/// Fingerprint of a bundle's files (except `exclude`d ones) and the scan settings: a cached
+/// verdict is reused while this stays the same.
+#[cfg(unix)]
+pub fn fingerprint(settings: &ScanSettings, bundle: &Path, exclude: &[String]) -> String {
+    use std::os::unix::fs::MetadataExt;
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(format!("{:?}", settings).as_bytes());
+    let mut count = 0u64;
+    for (relative, entry) in files(bundle) {
+        if crate::bundle::is_excluded(exclude, &relative) {
+            continue;
+        }
+        let Ok(meta) = entry.metadata() else { continue };
+        hasher.update(relative.as_os_str().as_encoded_bytes());
+        hasher.update(&[0]);
+        // A directory's size and mtime only say its list of names changed, which the names
+        // already show; skipping them keeps excluded files from changing the fingerprint.
+        let (len, mtime, mtime_nsec) = match meta.is_dir() {
+            true => (0, 0, 0),
+            false => (meta.len(), meta.mtime() as u64, meta.mtime_nsec() as u64),
+        };
+        for n in [len, mtime, mtime_nsec, meta.mode() as u64, meta.ino()] {
+            hasher.update(&n.to_le_bytes());
+        }
+        count += 1;
+    }
+    format!("{:08x}-{}", hasher.finalize(), count)
+}
+
+#[cfg(not(unix))]
+pub fn fingerprint(settings: &ScanSettings, bundle: &Path, exclude: &[String]) -> String {
+    let count = files(bundle)
+        .filter(|(relative, _)| !crate::bundle::is_excluded(exclude, relative))
+        .count();
+    format!("{:?}-{}", settings, count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn builtin_checks_and_scanner_command() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("App.lnx");
+        std::fs::create_dir_all(bundle.join("bin")).unwrap();
+        std::fs::create_dir_all(bundle.join(".cache")).unwrap();
+        let write = |rel: &str, bytes: &[u8], mode: u32| {
+            std::fs::write(bundle.join(rel), bytes).unwrap();
+            std::fs::set_permissions(bundle.join(rel), std::fs::Permissions::from_mode(mode))
+                .unwrap();
+        };
+        // Code-like bytes (few distinct values) and pseudo-random ones.
+        let plain: Vec<u8> = b"\x7fELF"
+            .iter()
+            .copied()
+            .chain((0..8192).map(|i| (i % 40) as u8))
+            .collect();
+        let mut x = 12345u32;
+        let random: Vec<u8> = b"\x7fELF"
+            .iter()
+            .copied()
+            .chain((0..65536).map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            }))
+            .collect();
+        write("bin/app", &plain, 0o755);
+        write("bin/data.bin", &random, 0o644);
+        let settings = ScanSettings {
+            checks: true,
+            ..ScanSettings::default()
+        };
+        assert!(scan(&settings, &bundle).unwrap().is_empty());
+        let clean = fingerprint(&settings, &bundle, &[]);
+        write("bin/app.log", b"started", 0o644);
+        assert_eq!(fingerprint(&settings, &bundle, &["*.log".into()]), clean);
+        assert_ne!(fingerprint(&settings, &bundle, &[]), clean);
+        std::fs::remove_file(bundle.join("bin/app.log")).unwrap();
+
+        write(".cache/run", b"#!/bin/sh\n", 0o755);
+        write("bin/packed", &random, 0o755);
+        let findings = scan(&settings, &bundle).unwrap();
+        assert_eq!(findings.len(), 2, "{:?}", findings);
+        assert_eq!(findings[0], "hidden executable .cache/run");
+        assert!(findings[1].starts_with("packed or encrypted executable bin/packed"));
+        assert_ne!(fingerprint(&settings, &bundle, &[]), clean);
+
+        let command = |script: &str| ScanSettings {
+            command: vec!["sh".into(), "-c".into(), script.into(), "sh".into()],
+            ..ScanSettings::default()
+        };
+        assert!(scan(&command("test -d \"$1\""), &bundle)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            scan(
+                &command("echo \"$1/bin/packed: Eicar FOUND\"; exit 1"),
+                &bundle
+            )
+            .unwrap(),
+            [format!("sh: {}/bin/packed: Eicar FOUND", bundle.display())]
+        );
+        assert!(scan(&command("exit 2"), &bundle).is_err());
+        let slow = ScanSettings {
+            timeout: 0,
+            ..command("sleep 5")
+        };
+        assert!(scan(&slow, &bundle)
+            .unwrap_err()
+            .to_string()
+            .contains("timed out"));
+        assert!(!enabled(&ScanSettings::default()));
+    }
+}
@@ -0,0 +1,116 @@
+//! systemd notification protocol (sd_notify): readiness, status text and watchdog pings for
+//! `Type=notify` units. Every call is a no-op when NOTIFY_SOCKET is unset (not under systemd).
+
+use std::time::{Duration, Instant};
+
+/// Send one notification (e.g. "READY=1", "STATUS=Idle"). Errors are logged, never fatal:
+/// losing a status update must not take the watcher down.
+pub fn notify(state: &str) {
+    let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(e) = send(&socket, state) {
+        tracing::debug!("sd_notify {:?}: {}", state, e);
+    }
+}
+
+/// Set the status line shown by `systemctl status`.
+pub fn status(text: &str) {
+    notify(&format!("STATUS={}", text.replace('\n', " ")));
+}
+
+#[cfg(target_os = "linux")]
+fn send(socket: &std::ffi::OsStr, state: &str) -> std::io::Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+    let bytes = socket.as_bytes();
+    // "@name" is an abstract-namespace socket.
+    let addr = match bytes.strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(std::path::Path::new(socket))?,
+    };
+    let sock = UnixDatagram::unbound()?;
+    sock.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send(_socket: &std::ffi::OsStr, _state: &str) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Watchdog pinger driven by the main loop: when the loop hangs, pings stop and systemd restarts
+/// the unit. Enabled only when systemd set WATCHDOG_USEC (and WATCHDOG_PID is ours, if set).
+pub struct Watchdog {
+    interval: Option<Duration>,
+    next: Option<Instant>,
+}
+
+impl Watchdog {
+    pub fn from_env() -> Self {
+        let pid_ok = std::env::var("WATCHDOG_PID")
+            .ok()
+            .and_then(|p| p.parse::<u32>().ok())
+            .is_none_or(|pid| pid == std::process::id());
+        let usec = std::env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|u| u.parse::<u64>().ok())
+            .filter(|u| *u > 0 && pid_ok);
+        Self::with_timeout(usec.map(Duration::from_micros))
+    }
+
+    /// Ping at half the systemd timeout, as sd_watchdog_enabled(3) recommends.
+    fn with_timeout(timeout: Option<Duration>) -> Self {
+        let interval = timeout.map(|t| t / 2);
+        Self {
+            interval,
+            next: interval.map(|i| Instant::now() + i),
+        }
+    }
+
+    /// When the next ping is due (None when the watchdog is off).
+    pub fn deadline(&self) -> Option<Instant> {
+        self.next
+    }
+
+    /// Ping if due.
+    pub fn tick(&mut self) {
+        let (Some(interval), Some(next)) = (self.interval, self.next) else {
+            return;
+        };
+        if Instant::now() >= next {
+            notify("WATCHDOG=1");
+            self.next = Some(Instant::now() + interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn send_reaches_datagram_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify.sock");
+        let server = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+        send(path.as_os_str(), "READY=1").unwrap();
+        let mut buf = [0u8; 64];
+        let n = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+    }
+
+    #[test]
+    fn watchdog_pings_at_half_timeout() {
+        let wd = Watchdog::with_timeout(Some(Duration::from_secs(10)));
+        let due = wd
+            .deadline()
+            .unwrap()
+            .saturating_duration_since(Instant::now());
+        assert!(due <= Duration::from_secs(5) && due > Duration::from_secs(4));
+        assert!(Watchdog::with_timeout(None).deadline().is_none());
+    }
+}
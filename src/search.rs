@@ -0,0 +1,210 @@
+//! `dotlnx search`: find apps by what they are for. Every word of the query must appear in an
+//! app's name, generic name, comment, keywords or categories (translations included); hits are
+//! ranked by where they matched, name first.
+//!
+//! Only installed bundles are searched: this tree has no bundle repositories yet. Each hit
+//! carries a `source` so repository results can join the same JSON later.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::path::PathBuf;
+
+use crate::asuser;
+use crate::bundle;
+use crate::config::Config;
+use crate::output::{self, Style, Table};
+use crate::paths::Paths;
+
+/// One app that matched.
+#[derive(Debug, Serialize)]
+pub struct Hit {
+    pub name: String,
+    /// Where it was found: "installed".
+    pub source: &'static str,
+    /// "user:<name>" or "system".
+    pub tier: String,
+    pub bundle: PathBuf,
+    pub generic_name: Option<String>,
+    pub comment: Option<String>,
+    pub keywords: Vec<String>,
+    pub categories: Vec<String>,
+    /// Fields the query matched in, best first.
+    pub matched: Vec<&'static str>,
+    /// Higher is a better match.
+    pub score: u32,
+}
+
+/// Searchable fields and what a match in each is worth.
+const FIELDS: &[(&str, u32)] = &[
+    ("name", 100),
+    ("generic_name", 40),
+    ("keywords", 30),
+    ("categories", 20),
+    ("comment", 10),
+];
+
+/// Lowercased text of each field of `cfg`, translations folded into their field.
+fn field_texts(cfg: &Config) -> Vec<(&'static str, String)> {
+    let mut name = vec![cfg.name.clone()];
+    let mut generic = cfg.generic_name.iter().cloned().collect::<Vec<_>>();
+    let mut comment = cfg.comment.iter().cloned().collect::<Vec<_>>();
+    let mut keywords = cfg.keywords.clone();
+    for t in cfg.translations.values() {
+        name.extend(t.name.clone());
+        generic.extend(t.generic_name.clone());
+        comment.extend(t.comment.clone());
+        keywords.extend(t.keywords.iter().cloned());
+    }
+    let categories = cfg.categories.clone().unwrap_or_default();
+    [
+        ("name", name),
+        ("generic_name", generic),
+        ("keywords", keywords),
+        ("categories", categories),
+        ("comment", comment),
+    ]
+    .into_iter()
+    .map(|(field, values)| (field, values.join("\n").to_lowercase()))
+    .collect()
+}
+
+/// Score of `cfg` for the lowercased query words; None unless every word matches somewhere.
+/// An empty query matches everything.
+pub fn score(cfg: &Config, words: &[String]) -> Option<(u32, Vec<&'static str>)> {
+    let texts = field_texts(cfg);
+    let mut total = 0;
+    let mut matched: Vec<&'static str> = Vec::new();
+    for word in words {
+        let mut best = None;
+        for ((field, text), (_, weight)) in texts.iter().zip(FIELDS) {
+            if text.contains(word.as_str()) {
+                best = best.max(Some(*weight));
+                if !matched.contains(field) {
+                    matched.push(field);
+                }
+            }
+        }
+        total += best?;
+    }
+    // A name that is the query (or starts with it) beats one that merely contains it.
+    let name = cfg.name.to_lowercase();
+    let query = words.join(" ");
+    if !query.is_empty() && name == query {
+        total += 100;
+    } else if !query.is_empty() && name.starts_with(&query) {
+        total += 50;
+    }
+    matched.sort_by_key(|f| FIELDS.iter().position(|(name, _)| name == f));
+    Some((total, matched))
+}
+
+/// Hits for `query` among the bundles this user can see (all users' as root).
+fn collect(paths: &Paths, words: &[String]) -> Result<Vec<Hit>> {
+    let is_root = bundle::is_root();
+    let mut tiers: Vec<(PathBuf, Option<String>)> = bundle::user_tier_entries(paths)?
+        .into_iter()
+        .map(|(apps, _, user)| (apps, Some(user)))
+        .collect();
+    tiers.push((paths.system_apps.clone(), None));
+    let mut hits = Vec::new();
+    for (apps_dir, user) in tiers {
+        for dir in bundle::discover_lnx_dirs(&apps_dir) {
+            let owner = user.as_deref().filter(|_| is_root);
+            let cfg = match asuser::load_config(owner, &dir, is_root) {
+                Ok(cfg) => cfg,
+                Err(e) => {
+                    tracing::debug!("{}: {:#}", dir.display(), e);
+                    continue;
+                }
+            };
+            let Some((score, matched)) = score(&cfg, words) else {
+                continue;
+            };
+            hits.push(Hit {
+                tier: user
+                    .as_ref()
+                    .map(|u| format!("user:{}", u))
+                    .unwrap_or_else(|| "system".into()),
+                source: "installed",
+                bundle: dir,
+                generic_name: cfg.generic_name,
+                comment: cfg.comment,
+                keywords: cfg.keywords,
+                categories: cfg.categories.unwrap_or_default(),
+                matched,
+                score,
+                name: cfg.name,
+            });
+        }
+    }
+    hits.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.name.cmp(&b.name)));
+    Ok(hits)
+}
+
+/// Entry point for `dotlnx search [query...] [--json]`.
+pub fn run(query: &[String], json: bool) -> Result<()> {
+    let paths = crate::paths::load()?;
+    let words: Vec<String> = query
+        .iter()
+        .flat_map(|q| q.split_whitespace())
+        .map(str::to_lowercase)
+        .collect();
+    let hits = collect(&paths, &words)?;
+    if json {
+        return output::json(&hits);
+    }
+    if hits.is_empty() {
+        println!("no apps match");
+        return Ok(());
+    }
+    let mut table = Table::new(&["NAME", "TIER", "DESCRIPTION"]);
+    for hit in &hits {
+        let about = hit
+            .generic_name
+            .as_deref()
+            .or(hit.comment.as_deref())
+            .unwrap_or("");
+        table.row(vec![
+            (hit.name.clone(), Style::Plain),
+            (hit.tier.clone(), Style::Plain),
+            (about.to_string(), Style::Plain),
+        ]);
+    }
+    table.print();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranks_by_field_and_needs_every_word() {
+        let browser: Config = toml::from_str(
+            r#"
+name = "Browser"
+executable = "bin/browser"
+generic_name = "Web Browser"
+comment = "Browse the web"
+keywords = ["internet", "www"]
+categories = ["Network"]
+[translations.de]
+keywords = ["Netz"]
+"#,
+        )
+        .unwrap();
+        let words = |q: &str| {
+            q.split_whitespace()
+                .map(str::to_lowercase)
+                .collect::<Vec<_>>()
+        };
+        let (exact, matched) = score(&browser, &words("browser")).unwrap();
+        assert_eq!(matched, ["name", "generic_name"]);
+        let (by_keyword, matched) = score(&browser, &words("WWW")).unwrap();
+        assert_eq!(matched, ["keywords"]);
+        assert!(exact > by_keyword);
+        assert!(score(&browser, &words("netz network")).is_some());
+        assert!(score(&browser, &words("web editor")).is_none());
+        assert_eq!(score(&browser, &[]).unwrap().0, 0);
+    }
+}
@@ -0,0 +1,199 @@
+//! Syscall filters for [security] seccomp. `dotlnx run` compiles the filter before starting the
+//! app and installs it in the child just before exec, so it covers the app and everything the app
+//! starts. Installing a filter also sets no_new_privs: setuid programs the app runs gain nothing.
+//!
+//! "default" refuses (EPERM) syscalls that no desktop app needs and that widen the kernel's attack
+//! surface: kernel modules and kexec, keyrings, bpf, perf, ptrace and cross-process memory access,
+//! clock and swap control. "strict" also refuses namespaces and mounts, chroot and io_uring, which
+//! breaks apps that sandbox themselves (Chromium/Electron, bubblewrap) but suits network-facing
+//! services. A path names a filter in seccompiler's JSON format for the host architecture.
+
+use anyhow::{Context, Result};
+use nix::libc;
+use seccompiler::{BpfProgram, SeccompAction, SeccompFilter, TargetArch};
+use std::collections::BTreeMap;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+
+use crate::config::Seccomp;
+
+/// Largest filter file dotlnx will read.
+const MAX_PROFILE_BYTES: u64 = 1024 * 1024;
+
+/// Refused by "default" and "strict".
+const DEFAULT_DENY: &[i64] = &[
+    libc::SYS_acct,
+    libc::SYS_add_key,
+    libc::SYS_adjtimex,
+    libc::SYS_bpf,
+    libc::SYS_clock_adjtime,
+    libc::SYS_clock_settime,
+    libc::SYS_delete_module,
+    libc::SYS_finit_module,
+    libc::SYS_init_module,
+    libc::SYS_kexec_file_load,
+    libc::SYS_kexec_load,
+    libc::SYS_keyctl,
+    libc::SYS_lookup_dcookie,
+    libc::SYS_open_by_handle_at,
+    libc::SYS_perf_event_open,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_ptrace,
+    libc::SYS_quotactl,
+    libc::SYS_reboot,
+    libc::SYS_request_key,
+    libc::SYS_settimeofday,
+    libc::SYS_swapoff,
+    libc::SYS_swapon,
+    libc::SYS_syslog,
+    libc::SYS_userfaultfd,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_ioperm,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_iopl,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_uselib,
+];
+
+/// Refused by "strict" on top of DEFAULT_DENY.
+const STRICT_DENY: &[i64] = &[
+    libc::SYS_chroot,
+    libc::SYS_fsconfig,
+    libc::SYS_fsmount,
+    libc::SYS_fsopen,
+    libc::SYS_fspick,
+    libc::SYS_io_uring_enter,
+    libc::SYS_io_uring_register,
+    libc::SYS_io_uring_setup,
+    libc::SYS_mknodat,
+    libc::SYS_mount,
+    libc::SYS_move_mount,
+    libc::SYS_name_to_handle_at,
+    libc::SYS_open_tree,
+    libc::SYS_pivot_root,
+    libc::SYS_setns,
+    libc::SYS_umount2,
+    libc::SYS_unshare,
+    libc::SYS_vhangup,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_mknod,
+];
+
+fn target_arch() -> Result<TargetArch> {
+    TargetArch::try_from(std::env::consts::ARCH).map_err(|_| {
+        anyhow::anyhow!(
+            "seccomp filters are not supported on {}",
+            std::env::consts::ARCH
+        )
+    })
+}
+
+/// Compile the filter `mode` selects for the bundle at `bundle_root`.
+pub fn compile(mode: &Seccomp, bundle_root: &Path) -> Result<BpfProgram> {
+    let arch = target_arch()?;
+    let deny: Vec<i64> = match mode {
+        Seccomp::Default => DEFAULT_DENY.to_vec(),
+        Seccomp::Strict => DEFAULT_DENY.iter().chain(STRICT_DENY).copied().collect(),
+        Seccomp::Profile(p) => return compile_profile(p, bundle_root, arch),
+    };
+    let rules: BTreeMap<i64, Vec<_>> = deny.into_iter().map(|nr| (nr, Vec::new())).collect();
+    let filter = SeccompFilter::new(
+        rules,
+        SeccompAction::Allow,
+        SeccompAction::Errno(libc::EPERM as u32),
+        arch,
+    )?;
+    Ok(filter.try_into()?)
+}
+
+/// A filter file: absolute, or relative to the bundle (and then inside it). The file may define
+/// several filters; the one named "main" is used, or the only one.
+fn compile_profile(p: &str, bundle_root: &Path, arch: TargetArch) -> Result<BpfProgram> {
+    let path = if Path::new(p).is_absolute() {
+        Path::new(p).to_path_buf()
+    } else {
+        crate::validate::path_stays_in_bundle(p)?;
+        bundle_root.join(p)
+    };
+    let meta =
+        std::fs::metadata(&path).with_context(|| format!("seccomp filter {}", path.display()))?;
+    if meta.len() > MAX_PROFILE_BYTES {
+        anyhow::bail!(
+            "seccomp filter {} is larger than {} bytes",
+            path.display(),
+            MAX_PROFILE_BYTES
+        );
+    }
+    let json = std::fs::read(&path).with_context(|| format!("read {}", path.display()))?;
+    let mut filters = seccompiler::compile_from_json(json.as_slice(), arch)
+        .with_context(|| format!("seccomp filter {}", path.display()))?;
+    if let Some(main) = filters.remove("main") {
+        return Ok(main);
+    }
+    if filters.len() == 1 {
+        return Ok(filters.into_values().next().unwrap());
+    }
+    let mut names: Vec<_> = filters.into_keys().collect();
+    names.sort();
+    anyhow::bail!(
+        "seccomp filter {} defines {} filters ({}); name the one to use \"main\"",
+        path.display(),
+        names.len(),
+        names.join(", ")
+    )
+}
+
+/// Install `program` in the process `cmd` starts, just before exec.
+pub fn install(cmd: &mut Command, program: BpfProgram) {
+    // SAFETY: the closure only makes the prctl/seccomp syscalls (no allocation on success) and
+    // runs in the forked child.
+    unsafe {
+        cmd.pre_exec(move || {
+            seccompiler::apply_filter(&program).map_err(|e| match e {
+                seccompiler::Error::Prctl(e) | seccompiler::Error::Seccomp(e) => e,
+                _ => std::io::Error::from_raw_os_error(libc::EINVAL),
+            })
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn supported() -> bool {
+        target_arch().is_ok()
+    }
+
+    #[test]
+    fn builtin_filters_compile() {
+        if !supported() {
+            return;
+        }
+        let default = compile(&Seccomp::Default, Path::new("/")).unwrap();
+        let strict = compile(&Seccomp::Strict, Path::new("/")).unwrap();
+        assert!(strict.len() > default.len());
+    }
+
+    #[test]
+    fn profile_is_read_from_the_bundle() {
+        if !supported() {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let json = r#"{"main": {"mismatch_action": "allow", "filter_action": {"errno": 1}, "filter": [{"syscall": "ptrace"}]}}"#;
+        std::fs::write(dir.path().join("filter.json"), json).unwrap();
+        let prog = compile(&Seccomp::Profile("filter.json".into()), dir.path()).unwrap();
+        assert!(!prog.is_empty());
+
+        let two = r#"{"a": {"mismatch_action": "allow", "filter_action": "trap", "filter": []},
+                      "b": {"mismatch_action": "allow", "filter_action": "trap", "filter": []}}"#;
+        std::fs::write(dir.path().join("two.json"), two).unwrap();
+        let err = compile(&Seccomp::Profile("two.json".into()), dir.path()).unwrap_err();
+        assert!(err.to_string().contains("(a, b)"), "{}", err);
+        assert!(compile(&Seccomp::Profile("../filter.json".into()), dir.path()).is_err());
+        assert!(compile(&Seccomp::Profile("missing.json".into()), dir.path()).is_err());
+    }
+}
@@ -0,0 +1,593 @@
+//! Site-wide settings (/etc/dotlnx/config.toml). Distinct from a bundle's config.toml: these are
+//! admin-controlled knobs that apply to every bundle on the host. Missing file means all defaults.
+
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// Default location of the site config. Override with DOTLNX_CONFIG (tests, cross-host generation).
+pub const DEFAULT_SETTINGS_PATH: &str = "/etc/dotlnx/config.toml";
+
+/// Root of /etc/dotlnx/config.toml.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    #[serde(default)]
+    pub apparmor: AppArmorSettings,
+    #[serde(default)]
+    pub watch: WatchSettings,
+    #[serde(default)]
+    pub paths: PathSettings,
+    #[serde(default)]
+    pub run: RunSettings,
+    #[serde(default)]
+    pub resources: ResourceSettings,
+    #[serde(default)]
+    pub scan: ScanSettings,
+    #[serde(default)]
+    pub kiosk: KioskSettings,
+    #[serde(default)]
+    pub templates: TemplateSettings,
+    #[serde(default)]
+    pub hooks: HookSettings,
+}
+
+/// [templates] section: snippets added to every generated menu entry and profile (see
+/// `templates`).
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateSettings {
+    /// Directory of `*.desktop` and `*.apparmor` templates.
+    #[serde(default = "default_templates_dir")]
+    pub dir: PathBuf,
+}
+
+impl Default for TemplateSettings {
+    fn default() -> Self {
+        Self {
+            dir: default_templates_dir(),
+        }
+    }
+}
+
+fn default_templates_dir() -> PathBuf {
+    PathBuf::from(crate::templates::DEFAULT_DIR)
+}
+
+/// [hooks] section: executables run on sync events (see `hooks`).
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HookSettings {
+    /// Directory holding `pre-sync.d`, `post-install.d` and `post-remove.d`.
+    #[serde(default = "default_hooks_dir")]
+    pub dir: PathBuf,
+}
+
+impl Default for HookSettings {
+    fn default() -> Self {
+        Self {
+            dir: default_hooks_dir(),
+        }
+    }
+}
+
+fn default_hooks_dir() -> PathBuf {
+    PathBuf::from(crate::hooks::DEFAULT_DIR)
+}
+
+/// [kiosk] section: run one system-tier app full screen and nothing else (see `kiosk`).
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KioskSettings {
+    /// The app (its config.toml `name`) the machine runs. Unset: no kiosk mode.
+    pub app: Option<String>,
+    /// Account the kiosk session runs as.
+    #[serde(default = "default_kiosk_user")]
+    pub user: String,
+    /// Virtual terminal of the seat the session takes over.
+    #[serde(default = "default_kiosk_tty")]
+    pub tty: String,
+    /// Command the app is started under, e.g. a kiosk compositor; [] starts the app directly.
+    #[serde(default = "default_kiosk_session")]
+    pub session: Vec<String>,
+    /// Seconds to wait before starting the app again after it exits or crashes.
+    #[serde(default = "default_kiosk_restart_sec")]
+    pub restart_sec: u64,
+}
+
+impl Default for KioskSettings {
+    fn default() -> Self {
+        Self {
+            app: None,
+            user: default_kiosk_user(),
+            tty: default_kiosk_tty(),
+            session: default_kiosk_session(),
+            restart_sec: default_kiosk_restart_sec(),
+        }
+    }
+}
+
+fn default_kiosk_user() -> String {
+    "kiosk".into()
+}
+
+fn default_kiosk_tty() -> String {
+    "tty1".into()
+}
+
+fn default_kiosk_session() -> Vec<String> {
+    vec!["/usr/bin/cage".into(), "-s".into(), "--".into()]
+}
+
+fn default_kiosk_restart_sec() -> u64 {
+    2
+}
+
+impl KioskSettings {
+    fn check(&self) -> anyhow::Result<()> {
+        if let Some(ref app) = self.app {
+            crate::validate::validate_app_name(app)
+                .map_err(|e| anyhow::anyhow!("[kiosk] app: {}", e))?;
+        }
+        if !crate::validate::is_account_name(&self.user) {
+            anyhow::bail!("[kiosk] user {:?} is not a valid account name", self.user);
+        }
+        let vt = self.tty.strip_prefix("tty").unwrap_or("");
+        if vt.is_empty() || !vt.chars().all(|c| c.is_ascii_digit()) {
+            anyhow::bail!(
+                "[kiosk] tty {:?} must name a virtual terminal (e.g. \"tty1\")",
+                self.tty
+            );
+        }
+        if self.session.first().is_some_and(|c| c.is_empty()) {
+            anyhow::bail!("[kiosk] session must start with the program to run");
+        }
+        Ok(())
+    }
+}
+
+/// [scan] section: checks a bundle must pass before sync installs it (see `scan`).
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScanSettings {
+    /// Built-in heuristics: hidden executables, packed executables.
+    #[serde(default)]
+    pub checks: bool,
+    /// External scanner, run with the bundle path appended (e.g. ["clamscan", "-r", "-i",
+    /// "--no-summary"]). Exit 0 is clean, 1 quarantines the bundle, anything else is a failed scan.
+    #[serde(default)]
+    pub command: Vec<String>,
+    /// Seconds the scanner may take per bundle.
+    #[serde(default = "default_scan_timeout")]
+    pub timeout: u64,
+}
+
+impl Default for ScanSettings {
+    fn default() -> Self {
+        Self {
+            checks: false,
+            command: Vec::new(),
+            timeout: default_scan_timeout(),
+        }
+    }
+}
+
+fn default_scan_timeout() -> u64 {
+    300
+}
+
+/// [resources] section: how much of the machine dotlnx may use.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResourceSettings {
+    /// For small devices (1 GB kiosks): one apparmor_parser at a time, each bundle's outputs
+    /// written before the next bundle is read, and freed memory returned after every sync.
+    /// Also set by `dotlnx --low-memory` (DOTLNX_LOW_MEMORY=1).
+    #[serde(default)]
+    pub low_memory: bool,
+    /// AppImage versions kept in each bundle's bin/ when the bundle sets no keep_versions.
+    pub keep_versions: Option<usize>,
+}
+
+/// [run] section: settings `dotlnx run` applies to every app it starts.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RunSettings {
+    /// Start every app with no_new_privs, whatever its [security] no_new_privs says.
+    #[serde(default)]
+    pub no_new_privs: bool,
+    /// `KEY=value` variables every app starts with (proxies, locale, theme), under the
+    /// bundle's own `env`.
+    #[serde(default)]
+    pub env: Vec<String>,
+}
+
+impl RunSettings {
+    /// Apply the site's launch requirements to an app's config. Runs at both sync time (so menu
+    /// entries go through `dotlnx run`) and launch time, after the admin override.
+    pub fn apply(&self, config: &mut crate::config::Config) {
+        if self.no_new_privs {
+            config
+                .security
+                .get_or_insert_with(crate::config::Security::default)
+                .no_new_privs = true;
+        }
+        config.site_env = self.env.clone();
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        for var in &self.env {
+            let valid = var.split_once('=').is_some_and(|(key, value)| {
+                key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                    && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                    && !value.contains(|c: char| c.is_control())
+            });
+            if !valid {
+                anyhow::bail!("[run] env: {:?} is not KEY=value", var);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// [paths] section: where dotlnx looks outside the bundles. Unset keys use the standard
+/// locations, re-rooted under `root` when that is set (images, containers, test roots).
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PathSettings {
+    /// Prefix for every default system location below.
+    pub root: Option<PathBuf>,
+    /// System-tier Applications dir (default /Applications; DOTLNX_SYSTEM_APPLICATIONS wins).
+    pub system_applications: Option<PathBuf>,
+    /// Where system-tier menu entries go (default /usr/share/applications).
+    pub system_desktop_dir: Option<PathBuf>,
+    /// Where generated AppArmor profiles go (default /etc/apparmor.d/dotlnx.d).
+    pub apparmor_dir: Option<PathBuf>,
+    /// apparmor_parser to run (default: /usr/sbin, /sbin, then PATH).
+    pub apparmor_parser: Option<PathBuf>,
+    /// Where apparmor_parser caches compiled profiles (default: its own, from parser.conf).
+    pub apparmor_cache: Option<PathBuf>,
+    /// Directory holding users' homes, scanned by the daemon (default /home).
+    pub home_dirs: Option<PathBuf>,
+    /// Where system-tier service units go (default /etc/systemd/system).
+    pub systemd_system_dir: Option<PathBuf>,
+    /// systemctl to run (default /usr/bin/systemctl).
+    pub systemctl: Option<PathBuf>,
+    /// Start of generated profile and .desktop file names (default "dotlnx-").
+    pub prefix: Option<String>,
+    /// `prefix` for the user tier only.
+    pub user_prefix: Option<String>,
+    /// `prefix` for the system tier only.
+    pub system_prefix: Option<String>,
+    /// Which tier's app wins when both Applications dirs have one by the same name.
+    #[serde(default)]
+    pub precedence: Precedence,
+}
+
+/// Resolution of an app name found in both the user and the system Applications dir.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Precedence {
+    /// The user's copy wins (it shadows the system one).
+    #[default]
+    User,
+    /// The system copy wins; the user's copy is not installed.
+    System,
+    /// Neither wins: the user's copy is not installed and launching the name fails.
+    Error,
+}
+
+impl PathSettings {
+    /// Reject prefixes that would make invalid or ambiguous file and profile names.
+    fn check(&self) -> anyhow::Result<()> {
+        for (key, value) in [
+            ("prefix", &self.prefix),
+            ("user_prefix", &self.user_prefix),
+            ("system_prefix", &self.system_prefix),
+        ] {
+            let Some(p) = value else { continue };
+            let valid = !p.is_empty()
+                && !p.starts_with('-')
+                && p.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid {
+                anyhow::bail!(
+                    "[paths] {} = {:?}: use letters, digits, - and _ (not empty, not starting with -)",
+                    key,
+                    p
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// [apparmor] section: how generated profiles are written.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AppArmorSettings {
+    /// Policy ABI to target: "auto" (detect from host), "4.0", "3.0", or "none" (legacy syntax, no abi line).
+    /// Set explicitly when generating profiles for a different host than the one running dotlnx.
+    #[serde(default)]
+    pub abi: AbiSetting,
+    /// Directory of site base policy fragments merged into every generated profile.
+    #[serde(default = "default_policy_dir")]
+    pub policy_dir: PathBuf,
+    /// Directory of per-app overrides (<name>.toml) that pin [security] keys over the bundle's.
+    #[serde(default = "default_overrides_dir")]
+    pub overrides_dir: PathBuf,
+    /// apparmor_parser processes sync runs at once; 0 picks the CPU count (at most 4).
+    #[serde(default)]
+    pub jobs: usize,
+    /// How profiles grant access to the bundle's own files: "recursive", "directories" or "files".
+    #[serde(default)]
+    pub bundle_rules: crate::apparmor::BundleRules,
+    /// Largest number of bundle rules "directories"/"files" may emit before a profile falls
+    /// back to the recursive rule; 0 is no limit.
+    #[serde(default = "default_max_bundle_rules")]
+    pub max_bundle_rules: usize,
+}
+
+impl Default for AppArmorSettings {
+    fn default() -> Self {
+        Self {
+            abi: AbiSetting::default(),
+            policy_dir: default_policy_dir(),
+            overrides_dir: default_overrides_dir(),
+            jobs: 0,
+            bundle_rules: Default::default(),
+            max_bundle_rules: default_max_bundle_rules(),
+        }
+    }
+}
+
+fn default_max_bundle_rules() -> usize {
+    1000
+}
+
+fn default_policy_dir() -> PathBuf {
+    PathBuf::from(crate::policy::DEFAULT_POLICY_DIR)
+}
+
+fn default_overrides_dir() -> PathBuf {
+    PathBuf::from(crate::policy::DEFAULT_OVERRIDES_DIR)
+}
+
+/// [watch] section: how `dotlnx watch` behaves.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatchSettings {
+    /// Seconds between full syncs even without file events (catches missed events and manual
+    /// edits of generated files). 0 disables the timer.
+    #[serde(default = "default_reconcile_interval")]
+    pub reconcile_interval: u64,
+    /// Seconds between scans of Applications dirs on network filesystems (NFS/CIFS), which are
+    /// polled because inotify does not see changes made on other hosts.
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval: u64,
+}
+
+impl Default for WatchSettings {
+    fn default() -> Self {
+        Self {
+            reconcile_interval: default_reconcile_interval(),
+            poll_interval: default_poll_interval(),
+        }
+    }
+}
+
+fn default_reconcile_interval() -> u64 {
+    15 * 60
+}
+
+fn default_poll_interval() -> u64 {
+    30
+}
+
+/// Requested ABI: detect from the host or use a fixed one.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AbiSetting {
+    #[default]
+    Auto,
+    Fixed(crate::apparmor::Abi),
+}
+
+impl<'de> Deserialize<'de> for AbiSetting {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        use crate::apparmor::Abi;
+        let s = String::deserialize(d)?;
+        match s.as_str() {
+            "auto" => Ok(AbiSetting::Auto),
+            "none" => Ok(AbiSetting::Fixed(Abi::Legacy)),
+            "3.0" => Ok(AbiSetting::Fixed(Abi::V3)),
+            "4.0" => Ok(AbiSetting::Fixed(Abi::V4)),
+            other => Err(serde::de::Error::custom(format!(
+                "unknown abi {:?} (expected \"auto\", \"4.0\", \"3.0\", or \"none\")",
+                other
+            ))),
+        }
+    }
+}
+
+/// Path of the site config: DOTLNX_CONFIG or /etc/dotlnx/config.toml.
+pub fn settings_path() -> PathBuf {
+    std::env::var_os("DOTLNX_CONFIG")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_SETTINGS_PATH))
+}
+
+/// Load site settings. A missing file yields defaults; an unreadable or invalid file is an error
+/// so a typo in admin policy is never silently ignored.
+pub fn load() -> anyhow::Result<Settings> {
+    let path = settings_path();
+    let s = match std::fs::read_to_string(&path) {
+        Ok(s) => s,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let mut settings = Settings::default();
+            settings.resources.low_memory = low_memory_requested();
+            return Ok(settings);
+        }
+        Err(e) => anyhow::bail!("failed to read {}: {}", path.display(), e),
+    };
+    let mut settings =
+        parse(&s).map_err(|e| anyhow::anyhow!("invalid {}: {}", path.display(), e))?;
+    settings.resources.low_memory |= low_memory_requested();
+    Ok(settings)
+}
+
+/// DOTLNX_LOW_MEMORY set to anything but "" or "0" (`dotlnx --low-memory` sets it, so helper
+/// processes inherit it).
+fn low_memory_requested() -> bool {
+    std::env::var_os("DOTLNX_LOW_MEMORY").is_some_and(|v| !v.is_empty() && v != "0")
+}
+
+/// Parse site settings from TOML text.
+pub fn parse(s: &str) -> anyhow::Result<Settings> {
+    let settings: Settings = toml::from_str(s)?;
+    settings.paths.check()?;
+    settings.kiosk.check()?;
+    settings.run.check()?;
+    if settings
+        .resources
+        .keep_versions
+        .is_some_and(|k| !(1..=crate::validate::MAX_KEEP_VERSIONS).contains(&k))
+    {
+        anyhow::bail!(
+            "[resources] keep_versions must be 1 to {}",
+            crate::validate::MAX_KEEP_VERSIONS
+        );
+    }
+    if settings.scan.command.first().is_some_and(|c| c.is_empty()) {
+        anyhow::bail!("[scan] command must start with the scanner to run");
+    }
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apparmor::Abi;
+
+    #[test]
+    fn empty_settings_are_defaults() {
+        let s = parse("").unwrap();
+        assert_eq!(s.apparmor.abi, AbiSetting::Auto);
+        assert!(!s.resources.low_memory);
+        assert!(
+            parse("[resources]\nlow_memory = true\n")
+                .unwrap()
+                .resources
+                .low_memory
+        );
+        assert_eq!(
+            parse("[resources]\nkeep_versions = 2\n")
+                .unwrap()
+                .resources
+                .keep_versions,
+            Some(2)
+        );
+        assert!(parse("[resources]\nkeep_versions = 0\n").is_err());
+        assert!(!s.scan.checks && s.scan.command.is_empty());
+        assert_eq!(s.scan.timeout, 300);
+    }
+
+    #[test]
+    fn parse_run_env() {
+        let s = parse("[run]\nenv = [\"HTTP_PROXY=http://proxy:3128\", \"LANG=de_DE.UTF-8\"]\n")
+            .unwrap();
+        assert_eq!(
+            s.run.env,
+            ["HTTP_PROXY=http://proxy:3128", "LANG=de_DE.UTF-8"]
+        );
+        assert!(parse("[run]\nenv = [\"NO_EQUALS\"]\n").is_err());
+        assert!(parse("[run]\nenv = [\"1BAD=x\"]\n").is_err());
+        assert!(parse("[run]\nenv = [\"A=b\\nc\"]\n").is_err());
+        let mut cfg = crate::config::parse("name = \"a\"\nexecutable = \"a\"\n").unwrap();
+        s.run.apply(&mut cfg);
+        assert_eq!(cfg.site_env, s.run.env);
+        assert!(
+            cfg.needs_runner(),
+            "menu entries go through dotlnx run to set it"
+        );
+    }
+
+    #[test]
+    fn parse_fixed_abi() {
+        let s = parse("[apparmor]\nabi = \"3.0\"\n").unwrap();
+        assert_eq!(s.apparmor.abi, AbiSetting::Fixed(Abi::V3));
+        let s = parse("[apparmor]\nabi = \"none\"\n").unwrap();
+        assert_eq!(s.apparmor.abi, AbiSetting::Fixed(Abi::Legacy));
+    }
+
+    #[test]
+    fn parse_unknown_abi_err() {
+        let err = parse("[apparmor]\nabi = \"9.9\"\n").unwrap_err();
+        assert!(err.to_string().contains("abi"));
+    }
+
+    #[test]
+    fn parse_watch_interval() {
+        assert_eq!(parse("").unwrap().watch.reconcile_interval, 900);
+        let s = parse("[watch]\nreconcile_interval = 0\n").unwrap();
+        assert_eq!(s.watch.reconcile_interval, 0);
+        assert_eq!(s.watch.poll_interval, 30);
+    }
+
+    #[test]
+    fn parse_paths() {
+        let s =
+            parse("[paths]\nroot = \"/srv/img\"\napparmor_parser = \"/opt/aa/parser\"\n").unwrap();
+        assert_eq!(s.paths.root, Some(PathBuf::from("/srv/img")));
+        assert_eq!(
+            s.paths.apparmor_parser,
+            Some(PathBuf::from("/opt/aa/parser"))
+        );
+        assert!(s.paths.apparmor_dir.is_none());
+        assert_eq!(s.paths.precedence, Precedence::User);
+        assert!(parse("[paths]\nbogus = \"/x\"\n").is_err());
+        assert_eq!(
+            parse("[paths]\nprecedence = \"system\"\n")
+                .unwrap()
+                .paths
+                .precedence,
+            Precedence::System
+        );
+        assert!(parse("[paths]\nprecedence = \"newest\"\n").is_err());
+    }
+
+    #[test]
+    fn parse_prefixes() {
+        let s = parse("[paths]\nprefix = \"corp-apps-\"\n").unwrap();
+        assert_eq!(s.paths.prefix.as_deref(), Some("corp-apps-"));
+        for bad in ["", "-x", "a/b", "a b", "a.b"] {
+            let toml = format!("[paths]\nsystem_prefix = {:?}\n", bad);
+            assert!(parse(&toml).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn parse_bundle_rules() {
+        use crate::apparmor::BundleRules;
+        let s = parse("").unwrap();
+        assert_eq!(s.apparmor.bundle_rules, BundleRules::Recursive);
+        assert_eq!(s.apparmor.max_bundle_rules, 1000);
+        let s = parse("[apparmor]\nbundle_rules = \"files\"\nmax_bundle_rules = 0\n").unwrap();
+        assert_eq!(s.apparmor.bundle_rules, BundleRules::Files);
+        assert_eq!(s.apparmor.max_bundle_rules, 0);
+        assert!(parse("[apparmor]\nbundle_rules = \"some\"\n").is_err());
+    }
+
+    #[test]
+    fn run_no_new_privs_is_forced_on_every_app() {
+        let s = parse("[run]\nno_new_privs = true\n").unwrap();
+        let mut cfg = crate::config::parse("name = \"a\"\nexecutable = \"a\"\n").unwrap();
+        s.run.apply(&mut cfg);
+        assert!(cfg.security.as_ref().unwrap().no_new_privs);
+        assert!(cfg.needs_runner());
+        assert!(!parse("").unwrap().run.no_new_privs);
+    }
+
+    #[test]
+    fn parse_unknown_key_err() {
+        assert!(parse("bogus = 1\n").is_err());
+    }
+}
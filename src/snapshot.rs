@@ -0,0 +1,529 @@
+//! Snapshots `dotlnx update` takes before it replaces an app's AppImage, and `dotlnx rollback`,
+//! which goes back to the version before the last update. Each update records which AppImage it
+//! installed and where the one it replaced is kept; with `[update] snapshot_data = true` it also
+//! copies the app's `data_dirs` first (a read-only btrfs snapshot when the directory is a btrfs
+//! subvolume, else a tar archive), so a rollback restores the old version together with the data
+//! it wrote, not data a newer version may have migrated.
+//!
+//! Snapshots live in `<state dir>/snapshots/<app>/<time>/`; the newest [`KEPT`] are kept per app.
+//! Data dirs relative to the home are the home of whoever runs the update; as root (system tier)
+//! only absolute ones are copied, which validation limits to an app's own directory below
+//! /var/lib, /srv or /opt.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{info, warn};
+
+use crate::bundle;
+use crate::config::{self, Config};
+use crate::launchlog;
+use crate::paths::Paths;
+use crate::settings;
+use crate::state;
+use crate::sync;
+use crate::watch;
+
+/// Directory in the state dir that holds the snapshots.
+pub const DIR: &str = "snapshots";
+
+/// Snapshots kept per app, the newest.
+const KEPT: usize = 3;
+
+/// Inode number of a btrfs subvolume's root.
+const BTRFS_SUBVOLUME_INODE: u64 = 256;
+
+/// How one data dir was saved.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "lowercase")]
+pub enum Saved {
+    /// The directory did not exist: rollback moves away what the update created.
+    Missing,
+    Tar {
+        file: String,
+    },
+    Btrfs {
+        subvolume: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DataCopy {
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub saved: Saved,
+}
+
+/// What one update changed, as `snapshot.json` records it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub app: String,
+    /// Unix time the snapshot was taken.
+    pub time: u64,
+    pub bundle: PathBuf,
+    /// The AppImage the update installed.
+    pub installed: PathBuf,
+    /// Where the AppImage it replaced is now.
+    pub previous: PathBuf,
+    /// Where that AppImage goes back to: itself, or the fixed name it was moved aside from.
+    pub restore_as: PathBuf,
+    pub data: Vec<DataCopy>,
+}
+
+/// A snapshot taken before an update, recorded once the update is in place.
+#[derive(Debug)]
+pub struct Pending {
+    dir: PathBuf,
+    app: String,
+    bundle: PathBuf,
+    data: Vec<DataCopy>,
+}
+
+impl Pending {
+    /// Record the update: `installed` replaced what is now at `previous` (moved aside from
+    /// `restore_as`). Keeps the newest [`KEPT`] snapshots of the app.
+    pub fn finish(self, installed: &Path, previous: &Path, restore_as: &Path) {
+        let snapshot = Snapshot {
+            app: self.app,
+            time: state::now(),
+            bundle: self.bundle,
+            installed: installed.to_path_buf(),
+            previous: previous.to_path_buf(),
+            restore_as: restore_as.to_path_buf(),
+            data: self.data,
+        };
+        let written = serde_json::to_string_pretty(&snapshot)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(std::fs::write(self.dir.join("snapshot.json"), json + "\n")?));
+        if let Err(e) = written {
+            warn!("{}: could not record snapshot: {:#}", snapshot.app, e);
+            remove(&self.dir);
+            return;
+        }
+        if let Some(store) = self.dir.parent() {
+            let all = list(store);
+            for (dir, _) in &all[..all.len().saturating_sub(KEPT)] {
+                remove(dir);
+            }
+        }
+    }
+
+    /// Throw the snapshot away (the update did not go in).
+    pub fn discard(self) {
+        remove(&self.dir);
+    }
+}
+
+/// Directories `cfg` declares as its data, resolved for whoever runs the update. As root,
+/// relative ones (in users' homes) are left out. Ones validation refuses are never used.
+fn data_paths(cfg: &Config) -> Vec<PathBuf> {
+    let home = std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .filter(|h| h.is_absolute());
+    let is_root = bundle::is_root();
+    cfg.data_dirs
+        .iter()
+        .filter_map(|d| {
+            if let Err(e) = crate::validate::validate_data_dir("data_dirs", d) {
+                warn!(app = %cfg.name, "not snapshotted: {:#}", e);
+                return None;
+            }
+            let d = Path::new(d);
+            match (d.is_absolute(), is_root, &home) {
+                (true, _, _) => Some(d.to_path_buf()),
+                (false, false, Some(home)) => Some(home.join(d)),
+                _ => {
+                    info!(app = %cfg.name, dir = %d.display(), "data dir in users' homes not snapshotted as root");
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Take the snapshot for an update of `cfg`'s app in `bundle_root`, in `store`: the data dirs
+/// with `[update] snapshot_data`, else only the record.
+pub fn take(store: &Path, bundle_root: &Path, cfg: &Config) -> Result<Pending> {
+    let app = cfg.app_id();
+    crate::validate::validate_app_name(&app)?;
+    let app_store = store.join(&app);
+    std::fs::create_dir_all(&app_store)
+        .with_context(|| format!("create {}", app_store.display()))?;
+    let dir = app_store.join(state::now().to_string());
+    std::fs::create_dir(&dir).with_context(|| format!("create {}", dir.display()))?;
+    let mut pending = Pending {
+        dir,
+        app,
+        bundle: bundle_root.to_path_buf(),
+        data: Vec::new(),
+    };
+    if !cfg.update.as_ref().is_some_and(|u| u.snapshot_data) {
+        return Ok(pending);
+    }
+    for (i, path) in data_paths(cfg).into_iter().enumerate() {
+        match copy_data(&pending.dir, i, &path) {
+            Ok(saved) => pending.data.push(DataCopy { path, saved }),
+            Err(e) => {
+                pending.discard();
+                return Err(e.context(format!("snapshot {}", path.display())));
+            }
+        }
+    }
+    Ok(pending)
+}
+
+/// Save `path` as the `i`th copy in the snapshot `dir`.
+fn copy_data(dir: &Path, i: usize, path: &Path) -> Result<Saved> {
+    let Ok(meta) = std::fs::symlink_metadata(path) else {
+        return Ok(Saved::Missing);
+    };
+    if is_subvolume(path, &meta) {
+        let subvolume = i.to_string();
+        let status = Command::new("btrfs")
+            .args(["subvolume", "snapshot", "-r"])
+            .arg(path)
+            .arg(dir.join(&subvolume))
+            .stdout(std::process::Stdio::null())
+            .status();
+        match status {
+            Ok(s) if s.success() => return Ok(Saved::Btrfs { subvolume }),
+            // Another filesystem than the state dir's, or no btrfs tool: tar it instead.
+            other => {
+                info!(dir = %path.display(), "btrfs snapshot not possible ({:?}), using tar", other.map(|s| s.to_string()))
+            }
+        }
+    }
+    let (parent, name) = parent_and_name(path)?;
+    let file = format!("{}.tar.gz", i);
+    let status = Command::new("tar")
+        .arg("-C")
+        .arg(parent)
+        .arg("-czf")
+        .arg(dir.join(&file))
+        .arg(Path::new(".").join(name))
+        .status()
+        .context("run tar")?;
+    if !status.success() {
+        anyhow::bail!("tar failed ({})", status);
+    }
+    Ok(Saved::Tar { file })
+}
+
+fn is_subvolume(path: &Path, meta: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    meta.is_dir()
+        && meta.ino() == BTRFS_SUBVOLUME_INODE
+        && nix::sys::statfs::statfs(path)
+            .is_ok_and(|s| s.filesystem_type() == nix::sys::statfs::BTRFS_SUPER_MAGIC)
+}
+
+fn parent_and_name(path: &Path) -> Result<(&Path, &std::ffi::OsStr)> {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => Ok((parent, name)),
+        _ => anyhow::bail!("{} has no parent directory", path.display()),
+    }
+}
+
+/// The app's snapshots in `app_store`, oldest first.
+pub fn list(app_store: &Path) -> Vec<(PathBuf, Snapshot)> {
+    let Ok(entries) = std::fs::read_dir(app_store) else {
+        return Vec::new();
+    };
+    let mut out: Vec<(PathBuf, Snapshot)> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let text = std::fs::read_to_string(e.path().join("snapshot.json")).ok()?;
+            Some((e.path(), serde_json::from_str(&text).ok()?))
+        })
+        .collect();
+    out.sort_by_key(|(_, s)| s.time);
+    out
+}
+
+/// Delete a snapshot directory, btrfs snapshots in it included.
+fn remove(dir: &Path) {
+    if let Ok(entries) = std::fs::read_dir(dir) {
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if entry.metadata().is_ok_and(|m| is_subvolume(&path, &m)) {
+                let _ = Command::new("btrfs")
+                    .args(["subvolume", "delete"])
+                    .arg(&path)
+                    .stdout(std::process::Stdio::null())
+                    .status();
+            }
+        }
+    }
+    if let Err(e) = std::fs::remove_dir_all(dir) {
+        warn!("could not remove snapshot {}: {}", dir.display(), e);
+    }
+}
+
+/// Put back what `snapshot` (in `dir`) saved: each data dir, then the AppImage. What the update
+/// wrote to a data dir is moved aside (`<dir>.dotlnx-rollback-<time>`), not deleted.
+pub fn restore(dir: &Path, snapshot: &Snapshot) -> Result<()> {
+    if !snapshot.previous.is_file() {
+        anyhow::bail!(
+            "{} is gone (removed by keep_versions?); cannot go back to it",
+            snapshot.previous.display()
+        );
+    }
+    for data in &snapshot.data {
+        restore_data(dir, data).with_context(|| format!("restore {}", data.path.display()))?;
+    }
+    if snapshot.installed != snapshot.previous && snapshot.installed.exists() {
+        std::fs::remove_file(&snapshot.installed)
+            .with_context(|| format!("remove {}", snapshot.installed.display()))?;
+    }
+    if snapshot.previous != snapshot.restore_as {
+        std::fs::rename(&snapshot.previous, &snapshot.restore_as)
+            .with_context(|| format!("move {} back", snapshot.previous.display()))?;
+    }
+    // Launchers run the newest AppImage.
+    std::fs::File::options()
+        .write(true)
+        .open(&snapshot.restore_as)
+        .and_then(|f| f.set_modified(std::time::SystemTime::now()))
+        .with_context(|| format!("touch {}", snapshot.restore_as.display()))?;
+    Ok(())
+}
+
+fn restore_data(dir: &Path, data: &DataCopy) -> Result<()> {
+    let (parent, name) = parent_and_name(&data.path)?;
+    let aside = match std::fs::symlink_metadata(&data.path) {
+        Ok(_) => {
+            let aside = parent.join(format!(
+                "{}.dotlnx-rollback-{}",
+                name.to_string_lossy(),
+                state::now()
+            ));
+            std::fs::rename(&data.path, &aside)
+                .with_context(|| format!("move {} aside", data.path.display()))?;
+            Some(aside)
+        }
+        Err(_) => None,
+    };
+    let restored = match &data.saved {
+        Saved::Missing => Ok(()),
+        Saved::Tar { file } => Command::new("tar")
+            .arg("-C")
+            .arg(parent)
+            .arg("-xzf")
+            .arg(dir.join(file))
+            .status()
+            .context("run tar")
+            .and_then(|s| {
+                if s.success() {
+                    Ok(())
+                } else {
+                    anyhow::bail!("tar failed ({})", s)
+                }
+            }),
+        Saved::Btrfs { subvolume } => Command::new("btrfs")
+            .args(["subvolume", "snapshot"])
+            .arg(dir.join(subvolume))
+            .arg(&data.path)
+            .stdout(std::process::Stdio::null())
+            .status()
+            .context("run btrfs")
+            .and_then(|s| {
+                if s.success() {
+                    Ok(())
+                } else {
+                    anyhow::bail!("btrfs failed ({})", s)
+                }
+            }),
+    };
+    match (restored, aside) {
+        (Ok(()), Some(aside)) => {
+            println!(
+                "{}: what the update left is in {}",
+                data.path.display(),
+                aside.display()
+            );
+            Ok(())
+        }
+        (Ok(()), None) => Ok(()),
+        (Err(e), aside) => {
+            let _ = std::fs::remove_dir_all(&data.path);
+            if let Some(aside) = aside {
+                let _ = std::fs::rename(&aside, &data.path);
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Entry point for `dotlnx rollback <name> [--list]`: list the app's snapshots, or go back to
+/// the version before its last update, then sync when no service is running.
+pub fn run(name: &str, list_only: bool) -> Result<()> {
+    let settings = settings::load()?;
+    let paths = Paths::from_settings(&settings.paths);
+    let apps_dir = if bundle::is_root() {
+        &paths.system_apps
+    } else {
+        &paths.user_apps
+    };
+    let Some((dir, cfg)) = bundle::discover_lnx_dirs(apps_dir)
+        .into_iter()
+        .filter_map(|d| config::load(&d).ok().map(|c| (d, c)))
+        .find(|(_, c)| c.answers_to(name))
+    else {
+        anyhow::bail!("no app named {:?} in {}", name, apps_dir.display());
+    };
+    let snapshots = list(&state::state_dir()?.join(DIR).join(cfg.app_id()));
+    let file_name = |p: &Path| {
+        p.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    };
+    if list_only {
+        if snapshots.is_empty() {
+            println!("{}: no updates to roll back", cfg.name);
+        }
+        for (_, s) in &snapshots {
+            let data = match s.data.len() {
+                0 => String::new(),
+                n => format!(" (with {} data dir(s))", n),
+            };
+            println!(
+                "{} UTC  {} -> {}{}",
+                launchlog::timestamp(s.time),
+                file_name(&s.restore_as),
+                file_name(&s.installed),
+                data
+            );
+        }
+        return Ok(());
+    }
+    let Some((snapshot_dir, snapshot)) = snapshots.last() else {
+        anyhow::bail!(
+            "{}: no update to roll back (dotlnx update records one before each update)",
+            cfg.name
+        );
+    };
+    if snapshot.bundle != dir {
+        anyhow::bail!(
+            "the last update of {} was of {}, not {}",
+            cfg.name,
+            snapshot.bundle.display(),
+            dir.display()
+        );
+    }
+    restore(snapshot_dir, snapshot)?;
+    remove(snapshot_dir);
+    info!(app = %cfg.name, to = %snapshot.restore_as.display(), "rolled back");
+    println!(
+        "{}: rolled back to {}; the next `dotlnx update` installs {} again unless the app is held",
+        cfg.name,
+        file_name(&snapshot.restore_as),
+        file_name(&snapshot.installed)
+    );
+    if !watch::service_active() {
+        sync::run(false)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A bundle that was updated from App-1 to App-2, with the snapshot for that update.
+    fn updated(tmp: &Path, data: Vec<DataCopy>) -> Snapshot {
+        let bin = tmp.join("App.lnx/bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        std::fs::write(bin.join("App-1.AppImage"), "v1").unwrap();
+        std::fs::write(bin.join("App-2.AppImage"), "v2").unwrap();
+        Snapshot {
+            app: "App".into(),
+            time: 1,
+            bundle: tmp.join("App.lnx"),
+            installed: bin.join("App-2.AppImage"),
+            previous: bin.join("App-1.AppImage"),
+            restore_as: bin.join("App-1.AppImage"),
+            data,
+        }
+    }
+
+    fn aside(data: &Path) -> Vec<PathBuf> {
+        let name = format!(
+            "{}.dotlnx-rollback-",
+            data.file_name().unwrap().to_string_lossy()
+        );
+        std::fs::read_dir(data.parent().unwrap())
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.file_name().unwrap().to_string_lossy().starts_with(&name))
+            .collect()
+    }
+
+    #[test]
+    fn rollback_restores_a_tarred_data_dir_and_keeps_the_newer_one_aside() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (store, data) = (tmp.path().join("snapshot"), tmp.path().join("data/App"));
+        std::fs::create_dir_all(&store).unwrap();
+        std::fs::create_dir_all(data.join("db")).unwrap();
+        std::fs::write(data.join("db/settings"), "v1").unwrap();
+
+        let saved = copy_data(&store, 0, &data).unwrap();
+        assert_eq!(
+            saved,
+            Saved::Tar {
+                file: "0.tar.gz".into()
+            }
+        );
+        // What the new version does to its data.
+        std::fs::write(data.join("db/settings"), "v2").unwrap();
+        std::fs::write(data.join("migrated"), "").unwrap();
+
+        let snapshot = updated(
+            tmp.path(),
+            vec![DataCopy {
+                path: data.clone(),
+                saved,
+            }],
+        );
+        restore(&store, &snapshot).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(data.join("db/settings")).unwrap(),
+            "v1"
+        );
+        assert!(!data.join("migrated").exists());
+        let aside = aside(&data);
+        assert_eq!(aside.len(), 1);
+        assert_eq!(
+            std::fs::read_to_string(aside[0].join("db/settings")).unwrap(),
+            "v2"
+        );
+        assert!(!snapshot.installed.exists() && snapshot.restore_as.is_file());
+    }
+
+    #[test]
+    fn rollback_moves_away_a_data_dir_the_update_created() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (store, data) = (tmp.path().join("snapshot"), tmp.path().join("data/App"));
+        std::fs::create_dir_all(&store).unwrap();
+
+        let saved = copy_data(&store, 0, &data).unwrap();
+        assert_eq!(saved, Saved::Missing);
+        std::fs::create_dir_all(&data).unwrap();
+        std::fs::write(data.join("created"), "").unwrap();
+
+        restore(
+            &store,
+            &updated(
+                tmp.path(),
+                vec![DataCopy {
+                    path: data.clone(),
+                    saved,
+                }],
+            ),
+        )
+        .unwrap();
+        assert!(!data.exists());
+        assert!(aside(&data)[0].join("created").is_file());
+    }
+}
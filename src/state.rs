@@ -0,0 +1,298 @@
+//! Persistent dotlnx state (JSON): what sync decided about bundles between runs.
+//! Root/daemon: /var/lib/dotlnx/state.json. Non-root: $XDG_STATE_HOME/dotlnx/state.json.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::sync::{Change, Summary};
+
+/// How many past changes `recent_changes` keeps.
+const RECENT_CHANGES: usize = 50;
+
+/// State directory used when running as root (the daemon).
+pub const SYSTEM_STATE_DIR: &str = "/var/lib/dotlnx";
+
+/// Directory holding state.json: DOTLNX_STATE_DIR, else /var/lib/dotlnx (root) or XDG state home.
+pub fn state_dir() -> Result<PathBuf> {
+    if let Some(dir) = std::env::var_os("DOTLNX_STATE_DIR") {
+        return Ok(PathBuf::from(dir));
+    }
+    if crate::bundle::is_root() {
+        return Ok(PathBuf::from(SYSTEM_STATE_DIR));
+    }
+    Ok(xdg::BaseDirectories::with_prefix("dotlnx")?.get_state_home())
+}
+
+/// Everything dotlnx remembers between runs. Unknown fields are ignored so older binaries can
+/// read state written by newer ones.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    /// Bundles refused by sync, keyed by bundle path.
+    #[serde(default)]
+    pub quarantined: BTreeMap<String, Quarantine>,
+    /// Outcome of the most recent (non-dry-run) sync, for `dotlnx tray`.
+    #[serde(default)]
+    pub last_sync: Option<SyncRecord>,
+    /// Menu entries/profiles sync installed, updated or removed, oldest first (capped).
+    #[serde(default)]
+    pub recent_changes: Vec<RecordedChange>,
+    /// What sync changed inside bundle folders (folder icons), keyed by bundle path, so it can
+    /// be undone when the app is uninstalled or loses its icon.
+    #[serde(default)]
+    pub decorations: BTreeMap<String, Decoration>,
+    /// App name -> bundle paths installed under that name, rebuilt by every sync, so `dotlnx run`
+    /// can find a bundle without parsing every config.toml.
+    #[serde(default)]
+    pub names: BTreeMap<String, Vec<String>>,
+    /// Files sync installed for an app besides its menu entry (GSettings schemas, ...), keyed by
+    /// the app's .desktop path, so removing the app removes exactly those.
+    #[serde(default)]
+    pub integrations: BTreeMap<String, Vec<PathBuf>>,
+    /// Verdicts of [scan], keyed by bundle path, reused while the bundle's fingerprint holds.
+    #[serde(default)]
+    pub scans: BTreeMap<String, ScanRecord>,
+}
+
+/// A bundle's last completed scan.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanRecord {
+    /// `scan::fingerprint` of the bundle when it was scanned.
+    pub fingerprint: String,
+    /// Empty when the bundle was clean.
+    pub findings: Vec<String>,
+}
+
+/// Counts from one sync pass.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncRecord {
+    /// Unix time the sync finished.
+    pub time: u64,
+    pub installed: usize,
+    pub skipped: usize,
+    pub quarantined: usize,
+}
+
+/// A change made by sync and when it was made.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedChange {
+    pub time: u64,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+/// Side effects of sync inside one bundle folder.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Decoration {
+    pub app: String,
+    /// User the changes were made as (root syncing a user bundle); None for the running user.
+    #[serde(default)]
+    pub user: Option<String>,
+    /// dotlnx wrote the bundle's .directory file (folder icon for KDE and others).
+    #[serde(default)]
+    pub directory_file: bool,
+    /// GNOME folder icon (file:// URL) last set with gio. gio metadata is not cheap to read back,
+    /// so this is what sync compares against to skip redundant sets.
+    #[serde(default)]
+    pub folder_icon: Option<String>,
+}
+
+impl Decoration {
+    /// True when nothing is left to undo.
+    pub fn is_empty(&self) -> bool {
+        !self.directory_file && self.folder_icon.is_none()
+    }
+}
+
+/// Why and since when a bundle is quarantined (not installed, not launchable from the menu).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quarantine {
+    /// App name from config.toml, when it could be read.
+    pub app: Option<String>,
+    /// Owning user (user tier) or None (system tier).
+    pub user: Option<String>,
+    pub reasons: Vec<String>,
+    /// Unix time the bundle was first quarantined.
+    pub since: u64,
+}
+
+impl State {
+    /// Load state from `dir`/state.json. Missing file means empty state; a corrupt file is
+    /// logged and treated as empty so one bad write cannot wedge the daemon.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join("state.json");
+        let s = match std::fs::read_to_string(&path) {
+            Ok(s) => s,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => anyhow::bail!("failed to read {}: {}", path.display(), e),
+        };
+        match serde_json::from_str(&s) {
+            Ok(state) => Ok(state),
+            Err(e) => {
+                tracing::warn!(path = %path.display(), "ignoring corrupt state file: {}", e);
+                Ok(Self::default())
+            }
+        }
+    }
+
+    /// Write state atomically (temp file + rename) to `dir`/state.json.
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join("state.json");
+        let tmp = dir.join(".state.json.tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    /// Record a quarantine. Returns true when the bundle was not already quarantined for the
+    /// same reasons (i.e. the user should be notified).
+    pub fn quarantine(&mut self, bundle: &Path, entry: Quarantine) -> bool {
+        let key = bundle.display().to_string();
+        match self.quarantined.get(&key) {
+            Some(existing) if existing.reasons == entry.reasons => false,
+            Some(existing) => {
+                let since = existing.since;
+                self.quarantined.insert(key, Quarantine { since, ..entry });
+                true
+            }
+            None => {
+                self.quarantined.insert(key, entry);
+                true
+            }
+        }
+    }
+
+    /// Remember a finished sync: its counts and the changes it made.
+    pub fn record_sync(&mut self, summary: &Summary) {
+        let time = now();
+        self.last_sync = Some(SyncRecord {
+            time,
+            installed: summary.installed,
+            skipped: summary.skipped,
+            quarantined: summary.quarantined,
+        });
+        self.recent_changes.extend(
+            summary
+                .changes
+                .iter()
+                .cloned()
+                .map(|change| RecordedChange { time, change }),
+        );
+        let excess = self.recent_changes.len().saturating_sub(RECENT_CHANGES);
+        self.recent_changes.drain(..excess);
+    }
+
+    /// True when the last sync installed `bundle` (it is in the name index).
+    pub fn is_installed(&self, bundle: &Path) -> bool {
+        self.names
+            .values()
+            .flatten()
+            .any(|b| Path::new(b) == bundle)
+    }
+
+    /// Lift a quarantine (bundle is trustworthy again). Returns true if one was lifted.
+    pub fn release(&mut self, bundle: &Path) -> bool {
+        self.quarantined
+            .remove(&bundle.display().to_string())
+            .is_some()
+    }
+}
+
+/// Current Unix time in seconds.
+pub fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(reason: &str) -> Quarantine {
+        Quarantine {
+            app: Some("myapp".into()),
+            user: Some("alice".into()),
+            reasons: vec![reason.into()],
+            since: 100,
+        }
+    }
+
+    #[test]
+    fn load_missing_is_default() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(State::load(dir.path()).unwrap().quarantined.is_empty());
+    }
+
+    #[test]
+    fn save_and_load_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = State::default();
+        state.quarantine(
+            Path::new("/home/alice/Applications/a.lnx"),
+            entry("world-writable"),
+        );
+        state.save(dir.path()).unwrap();
+        let loaded = State::load(dir.path()).unwrap();
+        assert_eq!(loaded.quarantined.len(), 1);
+        assert!(!dir.path().join(".state.json.tmp").exists());
+    }
+
+    #[test]
+    fn corrupt_state_is_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("state.json"), "{not json").unwrap();
+        assert!(State::load(dir.path()).unwrap().quarantined.is_empty());
+    }
+
+    #[test]
+    fn quarantine_reports_new_and_changed_only() {
+        let mut state = State::default();
+        let p = Path::new("/x.lnx");
+        assert!(state.quarantine(p, entry("a")));
+        assert!(!state.quarantine(
+            p,
+            Quarantine {
+                since: 999,
+                ..entry("a")
+            }
+        ));
+        assert!(state.quarantine(
+            p,
+            Quarantine {
+                since: 999,
+                ..entry("b")
+            }
+        ));
+        // First-seen time is kept across reason changes.
+        assert_eq!(state.quarantined["/x.lnx"].since, 100);
+        assert!(state.release(p));
+        assert!(!state.release(p));
+    }
+
+    #[test]
+    fn record_sync_keeps_latest_changes() {
+        let mut state = State::default();
+        let mut summary = Summary {
+            installed: 3,
+            ..Summary::default()
+        };
+        for i in 0..RECENT_CHANGES + 5 {
+            summary.record(Change {
+                action: crate::sync::Action::Install,
+                app: format!("app{}", i),
+                paths: vec![],
+            });
+        }
+        state.record_sync(&summary);
+        assert_eq!(state.last_sync.as_ref().unwrap().installed, 3);
+        assert_eq!(state.recent_changes.len(), RECENT_CHANGES);
+        assert_eq!(state.recent_changes[0].change.app, "app5");
+        let json = serde_json::to_string(&state).unwrap();
+        let loaded: State = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.recent_changes, state.recent_changes);
+    }
+}
@@ -0,0 +1,223 @@
+//! `dotlnx status`: is the service running, and how did the last sync of each tier go. Every
+//! completed (non-dry-run) sync writes status.json next to state.json: per tier, when it was
+//! synced, how many bundles it found, installed, skipped and quarantined, and why each skipped
+//! bundle was not installed. A failed or cancelled sync leaves the previous file, so its times
+//! are those of the last successful sync.
+//!
+//! A user sees their own sync's file and the system's (/var/lib/dotlnx), limited to the system
+//! tier and their own; root sees every tier the service synced.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::bundle;
+use crate::launchlog;
+use crate::output::{self, Style, Table};
+use crate::paths::Paths;
+use crate::settings;
+use crate::state;
+
+/// A bundle a sync did not install.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Invalid {
+    pub bundle: PathBuf,
+    pub error: String,
+}
+
+/// One tier's part of the last sync.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TierStatus {
+    /// "user:<name>" or "system".
+    pub tier: String,
+    pub apps_dir: PathBuf,
+    /// Unix time the tier was synced.
+    pub time: u64,
+    /// .lnx bundles found in the Applications dir.
+    pub discovered: usize,
+    pub installed: usize,
+    /// Bundles not installed: invalid config, failed validation, overrides, name clashes.
+    pub skipped: usize,
+    pub quarantined: usize,
+    /// The skipped bundles and why.
+    #[serde(default)]
+    pub invalid: Vec<Invalid>,
+}
+
+/// Contents of status.json.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Status {
+    /// By tier.
+    pub tiers: BTreeMap<String, TierStatus>,
+}
+
+impl Status {
+    pub fn new(tiers: Vec<TierStatus>) -> Self {
+        Status {
+            tiers: tiers.into_iter().map(|t| (t.tier.clone(), t)).collect(),
+        }
+    }
+}
+
+/// Load status.json from the state dir. Missing means no sync has completed yet.
+pub fn load(state_dir: &Path) -> Result<Status> {
+    let path = state_dir.join("status.json");
+    match std::fs::read_to_string(&path) {
+        Ok(s) => serde_json::from_str(&s).with_context(|| format!("parse {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Status::default()),
+        Err(e) => Err(e).with_context(|| format!("read {}", path.display())),
+    }
+}
+
+/// Write status.json atomically (temp file + rename).
+pub fn save(state_dir: &Path, status: &Status) -> Result<()> {
+    std::fs::create_dir_all(state_dir)?;
+    let tmp = state_dir.join(".status.json.tmp");
+    std::fs::write(&tmp, serde_json::to_string_pretty(status)?)?;
+    std::fs::rename(&tmp, state_dir.join("status.json"))?;
+    Ok(())
+}
+
+/// What `dotlnx status` reports.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    /// `systemctl is-active dotlnx.service` (active, inactive, failed, ...); None without systemctl.
+    pub service: Option<String>,
+    pub tiers: Vec<TierStatus>,
+}
+
+pub fn run(json: bool) -> Result<()> {
+    let settings = settings::load()?;
+    let paths = Paths::from_settings(&settings.paths);
+    let report = Report {
+        service: service_state(),
+        tiers: collect(&paths)?,
+    };
+    if json {
+        return output::json(&report);
+    }
+    print(&report);
+    Ok(())
+}
+
+fn service_state() -> Option<String> {
+    let out = std::process::Command::new("systemctl")
+        .args(["is-active", "dotlnx.service"])
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    Some(String::from_utf8_lossy(&out.stdout).trim().to_string()).filter(|s| !s.is_empty())
+}
+
+/// Tiers from this user's status.json and, for a user, the system's; the newer wins.
+fn collect(paths: &Paths) -> Result<Vec<TierStatus>> {
+    let own = state::state_dir()?;
+    let mut tiers = load(&own)?.tiers;
+    let system = Path::new(state::SYSTEM_STATE_DIR);
+    if !bundle::is_root() && std::env::var_os("DOTLNX_STATE_DIR").is_none() && own != system {
+        let mine: Vec<String> = bundle::user_tier_entries(paths)?
+            .into_iter()
+            .map(|(_, _, user)| format!("user:{}", user))
+            .chain(["system".to_string()])
+            .collect();
+        // Another user's status is not readable for most users, and not theirs to see.
+        let others = load(system).map(|s| s.tiers).unwrap_or_default();
+        for (label, t) in others.into_iter().filter(|(label, _)| mine.contains(label)) {
+            if tiers.get(&label).is_none_or(|own| own.time < t.time) {
+                tiers.insert(label, t);
+            }
+        }
+    }
+    Ok(ordered(tiers))
+}
+
+/// The system tier first, then the users'.
+fn ordered(tiers: BTreeMap<String, TierStatus>) -> Vec<TierStatus> {
+    let mut out: Vec<TierStatus> = tiers.into_values().collect();
+    out.sort_by_key(|t| (t.tier != "system", t.tier.clone()));
+    out
+}
+
+fn print(report: &Report) {
+    let service = match report.service.as_deref() {
+        Some("active") => ("running".to_string(), Style::Good),
+        Some(state) => (format!("not running ({})", state), Style::Warn),
+        None => ("unknown (no systemctl)".to_string(), Style::Warn),
+    };
+    output::fields(&[("Service", service.0, service.1)]);
+    println!();
+    if report.tiers.is_empty() {
+        println!("No completed sync yet. Run `dotlnx sync`, or start the service.");
+        return;
+    }
+    let mut table = Table::new(&[
+        "TIER",
+        "LAST SYNC (UTC)",
+        "FOUND",
+        "INSTALLED",
+        "SKIPPED",
+        "QUARANTINED",
+        "APPLICATIONS",
+    ]);
+    let count = |n: usize, bad: Style| (n.to_string(), if n == 0 { Style::Plain } else { bad });
+    for t in &report.tiers {
+        table.row(vec![
+            (t.tier.clone(), Style::Plain),
+            (launchlog::timestamp(t.time), Style::Plain),
+            (t.discovered.to_string(), Style::Plain),
+            (t.installed.to_string(), Style::Good),
+            count(t.skipped, Style::Warn),
+            count(t.quarantined, Style::Bad),
+            (t.apps_dir.display().to_string(), Style::Plain),
+        ]);
+    }
+    table.print();
+    let invalid: Vec<&Invalid> = report.tiers.iter().flat_map(|t| &t.invalid).collect();
+    if !invalid.is_empty() {
+        println!("\n{}", output::paint("Not installed:", Style::Heading));
+        for i in invalid {
+            println!(
+                "  {}: {}",
+                i.bundle.display(),
+                output::paint(&i.error, Style::Warn)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tier(label: &str, time: u64) -> TierStatus {
+        TierStatus {
+            tier: label.into(),
+            apps_dir: "/Applications".into(),
+            time,
+            discovered: 2,
+            installed: 1,
+            skipped: 1,
+            quarantined: 0,
+            invalid: vec![Invalid {
+                bundle: "/Applications/Bad.lnx".into(),
+                error: "name is empty".into(),
+            }],
+        }
+    }
+
+    #[test]
+    fn status_round_trips_and_lists_the_system_tier_first() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(load(dir.path()).unwrap().tiers.is_empty());
+        save(
+            dir.path(),
+            &Status::new(vec![tier("user:alice", 2), tier("system", 1)]),
+        )
+        .unwrap();
+        let loaded = load(dir.path()).unwrap();
+        assert_eq!(loaded.tiers["system"], tier("system", 1));
+        let order: Vec<String> = ordered(loaded.tiers).into_iter().map(|t| t.tier).collect();
+        assert_eq!(order, ["system", "user:alice"]);
+    }
+}
@@ -0,0 +1,403 @@
+//! `dotlnx steam-export`: add bundles to Steam as non-Steam game shortcuts, so games and
+//! emulators installed with dotlnx show up in Big Picture mode. Shortcuts live in each Steam
+//! account's userdata/<id>/config/shortcuts.vdf (binary VDF). Entries dotlnx writes carry a
+//! "dotlnx" tag; re-exporting replaces them and never touches shortcuts added by hand.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::bundle;
+use crate::config;
+use crate::desktop;
+use crate::paths;
+
+/// Tag that marks shortcuts managed by dotlnx.
+const TAG: &str = "dotlnx";
+
+/// A binary VDF value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Map(Vec<(String, Value)>),
+    Str(String),
+    Int(u32),
+}
+
+const T_MAP: u8 = 0x00;
+const T_STR: u8 = 0x01;
+const T_INT: u8 = 0x02;
+const T_END: u8 = 0x08;
+
+/// Parse binary VDF into its top-level entries.
+pub fn parse(data: &[u8]) -> Result<Vec<(String, Value)>> {
+    let mut pos = 0;
+    let map = parse_map(data, &mut pos, 0)?;
+    Ok(map)
+}
+
+fn read_cstr(data: &[u8], pos: &mut usize) -> Result<String> {
+    let rest = &data[*pos..];
+    let end = rest
+        .iter()
+        .position(|b| *b == 0)
+        .ok_or_else(|| anyhow::anyhow!("unterminated string at byte {}", *pos))?;
+    let s = String::from_utf8_lossy(&rest[..end]).into_owned();
+    *pos += end + 1;
+    Ok(s)
+}
+
+fn parse_map(data: &[u8], pos: &mut usize, depth: usize) -> Result<Vec<(String, Value)>> {
+    if depth > 16 {
+        anyhow::bail!("shortcuts.vdf nested too deeply");
+    }
+    let mut out = Vec::new();
+    while *pos < data.len() {
+        let kind = data[*pos];
+        *pos += 1;
+        if kind == T_END {
+            return Ok(out);
+        }
+        let key = read_cstr(data, pos)?;
+        let value = match kind {
+            T_MAP => Value::Map(parse_map(data, pos, depth + 1)?),
+            T_STR => Value::Str(read_cstr(data, pos)?),
+            T_INT => {
+                let bytes: [u8; 4] = data
+                    .get(*pos..*pos + 4)
+                    .and_then(|b| b.try_into().ok())
+                    .ok_or_else(|| anyhow::anyhow!("truncated integer at byte {}", *pos))?;
+                *pos += 4;
+                Value::Int(u32::from_le_bytes(bytes))
+            }
+            other => anyhow::bail!("unsupported VDF type {:#04x} at byte {}", other, *pos - 1),
+        };
+        out.push((key, value));
+    }
+    Ok(out)
+}
+
+/// Serialize top-level entries to binary VDF (the inverse of `parse`).
+pub fn write(entries: &[(String, Value)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_map(&mut out, entries);
+    out.push(T_END);
+    out
+}
+
+fn write_map(out: &mut Vec<u8>, entries: &[(String, Value)]) {
+    for (key, value) in entries {
+        let kind = match value {
+            Value::Map(_) => T_MAP,
+            Value::Str(_) => T_STR,
+            Value::Int(_) => T_INT,
+        };
+        out.push(kind);
+        out.extend_from_slice(key.as_bytes());
+        out.push(0);
+        match value {
+            Value::Map(m) => {
+                write_map(out, m);
+                out.push(T_END);
+            }
+            Value::Str(s) => {
+                out.extend_from_slice(s.as_bytes());
+                out.push(0);
+            }
+            Value::Int(i) => out.extend_from_slice(&i.to_le_bytes()),
+        }
+    }
+}
+
+/// Steam's id for a non-Steam shortcut (also names its artwork in config/grid).
+pub fn shortcut_appid(exe: &str, app_name: &str) -> u32 {
+    let mut h = crc32fast::Hasher::new();
+    h.update(exe.as_bytes());
+    h.update(app_name.as_bytes());
+    h.finalize() | 0x8000_0000
+}
+
+/// One bundle to export.
+#[derive(Debug, Clone)]
+pub struct Game {
+    pub name: String,
+    pub bundle: PathBuf,
+    pub icon: String,
+}
+
+fn quoted(s: &str) -> String {
+    format!("\"{}\"", s)
+}
+
+/// A shortcut entry launching `dotlnx run <name>` (so the app gets its profile like any launch).
+fn shortcut(game: &Game, dotlnx: &Path) -> Value {
+    let exe = quoted(&dotlnx.display().to_string());
+    let s = |v: &str| Value::Str(v.to_string());
+    Value::Map(vec![
+        ("appid".into(), Value::Int(shortcut_appid(&exe, &game.name))),
+        ("AppName".into(), s(&game.name)),
+        ("Exe".into(), Value::Str(exe)),
+        (
+            "StartDir".into(),
+            Value::Str(quoted(&game.bundle.display().to_string())),
+        ),
+        ("icon".into(), s(&game.icon)),
+        ("ShortcutPath".into(), s("")),
+        (
+            "LaunchOptions".into(),
+            Value::Str(format!("run {}", quoted(&game.name))),
+        ),
+        ("IsHidden".into(), Value::Int(0)),
+        ("AllowDesktopConfig".into(), Value::Int(1)),
+        ("AllowOverlay".into(), Value::Int(1)),
+        ("OpenVR".into(), Value::Int(0)),
+        ("Devkit".into(), Value::Int(0)),
+        ("DevkitGameID".into(), s("")),
+        ("DevkitOverrideAppID".into(), Value::Int(0)),
+        ("LastPlayTime".into(), Value::Int(0)),
+        ("FlatpakAppID".into(), s("")),
+        ("tags".into(), Value::Map(vec![("0".into(), s(TAG))])),
+    ])
+}
+
+fn field<'a>(entry: &'a [(String, Value)], key: &str) -> Option<&'a Value> {
+    entry
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(key))
+        .map(|(_, v)| v)
+}
+
+/// App name of a dotlnx-managed shortcut, None for shortcuts added some other way.
+fn managed_name(entry: &Value) -> Option<&str> {
+    let Value::Map(fields) = entry else {
+        return None;
+    };
+    let Some(Value::Map(tags)) = field(fields, "tags") else {
+        return None;
+    };
+    if !tags.iter().any(|(_, v)| *v == Value::Str(TAG.into())) {
+        return None;
+    }
+    match field(fields, "AppName") {
+        Some(Value::Str(name)) => Some(name),
+        _ => None,
+    }
+}
+
+/// Merge `games` into a parsed shortcuts.vdf: replace dotlnx shortcuts for the same apps (and,
+/// when `replace_all`, drop every other dotlnx shortcut), keep everything else, renumber.
+fn merge(
+    existing: Vec<(String, Value)>,
+    games: &[Game],
+    dotlnx: &Path,
+    replace_all: bool,
+) -> Vec<(String, Value)> {
+    let mut shortcuts: Vec<Value> = existing
+        .into_iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case("shortcuts"))
+        .and_then(|(_, v)| match v {
+            Value::Map(m) => Some(m.into_iter().map(|(_, v)| v).collect()),
+            _ => None,
+        })
+        .unwrap_or_default();
+    shortcuts.retain(|s| match managed_name(s) {
+        Some(name) => !replace_all && !games.iter().any(|g| g.name == name),
+        None => true,
+    });
+    shortcuts.extend(games.iter().map(|g| shortcut(g, dotlnx)));
+    let numbered = shortcuts
+        .into_iter()
+        .enumerate()
+        .map(|(i, v)| (i.to_string(), v))
+        .collect();
+    vec![("shortcuts".into(), Value::Map(numbered))]
+}
+
+/// Steam account config dirs (userdata/<id>/config) for the current user.
+fn steam_config_dirs(home: &Path) -> Vec<PathBuf> {
+    let mut out = Vec::new();
+    for root in [".steam/steam", ".local/share/Steam"] {
+        let userdata = home.join(root).join("userdata");
+        let Ok(rd) = std::fs::read_dir(&userdata) else {
+            continue;
+        };
+        for e in rd.filter_map(|e| e.ok()) {
+            let id = e.file_name().to_string_lossy().into_owned();
+            if id == "0" || !id.chars().all(|c| c.is_ascii_digit()) {
+                continue;
+            }
+            let dir = e.path().join("config");
+            // ~/.steam/steam is usually a symlink to ~/.local/share/Steam: count each dir once.
+            let canon = dir.canonicalize().unwrap_or_else(|_| dir.clone());
+            if !out
+                .iter()
+                .any(|d: &PathBuf| d.canonicalize().ok().as_ref() == Some(&canon))
+            {
+                out.push(dir);
+            }
+        }
+    }
+    out
+}
+
+/// True when a Steam client process is running (it rewrites shortcuts.vdf on exit).
+fn steam_running() -> bool {
+    let Ok(rd) = std::fs::read_dir("/proc") else {
+        return false;
+    };
+    rd.filter_map(|e| e.ok()).any(|e| {
+        std::fs::read_to_string(e.path().join("comm"))
+            .is_ok_and(|c| matches!(c.trim(), "steam" | "steamwebhelper"))
+    })
+}
+
+/// Installed bundles (user tier, then system tier) with valid configs, optionally filtered.
+fn collect_games(names: &[String]) -> Result<Vec<Game>> {
+    let paths = paths::load()?;
+    let mut roots: Vec<PathBuf> = bundle::user_tier_entries(&paths)?
+        .into_iter()
+        .map(|(apps, _, _)| apps)
+        .collect();
+    if paths.precedence == crate::settings::Precedence::System {
+        roots.insert(0, paths.system_apps);
+    } else {
+        roots.push(paths.system_apps);
+    }
+    let mut games: Vec<Game> = Vec::new();
+    for root in roots {
+        for dir in bundle::discover_lnx_dirs(&root) {
+            let Ok(cfg) = config::load(&dir) else {
+                continue;
+            };
+            if games.iter().any(|g| g.name == cfg.name) {
+                continue; // first tier wins, as in `dotlnx run`
+            }
+            if !names.is_empty() && !names.contains(&cfg.name) {
+                continue;
+            }
+            let icon = cfg
+                .icon
+                .as_deref()
+                .map(|i| desktop::resolve_icon_for_desktop(i, Some(&dir)))
+                .unwrap_or_default();
+            games.push(Game {
+                name: cfg.name,
+                bundle: dir,
+                icon,
+            });
+        }
+    }
+    for name in names {
+        if !games.iter().any(|g| &g.name == name) {
+            anyhow::bail!(crate::i18n::Message::new(
+                "app-not-found",
+                &[("name", name.to_string())]
+            ));
+        }
+    }
+    Ok(games)
+}
+
+/// Entry point for `dotlnx steam-export [name...]`. Without names, every bundle is exported and
+/// dotlnx shortcuts for bundles that no longer exist are removed.
+pub fn run(names: &[String]) -> Result<()> {
+    if bundle::is_root() {
+        anyhow::bail!("run steam-export as the user whose Steam library should get the shortcuts");
+    }
+    let home = dirs::home_dir().ok_or_else(|| anyhow::anyhow!("no home directory"))?;
+    let config_dirs = steam_config_dirs(&home);
+    if config_dirs.is_empty() {
+        anyhow::bail!("no Steam account found (log in to Steam once first)");
+    }
+    if steam_running() {
+        warn!("Steam is running and may overwrite the shortcuts when it exits; restart Steam to see them");
+    }
+    let games = collect_games(names)?;
+    let dotlnx = std::env::current_exe().context("locate dotlnx binary")?;
+    for dir in config_dirs {
+        let path = dir.join("shortcuts.vdf");
+        let existing = match std::fs::read(&path) {
+            Ok(data) => parse(&data).with_context(|| format!("parse {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).with_context(|| format!("read {}", path.display())),
+        };
+        let merged = merge(existing, &games, &dotlnx, names.is_empty());
+        std::fs::create_dir_all(&dir)?;
+        let tmp = dir.join(".shortcuts.vdf.dotlnx-tmp");
+        std::fs::write(&tmp, write(&merged))?;
+        std::fs::rename(&tmp, &path)?;
+        info!(file = %path.display(), shortcuts = games.len(), "exported to Steam");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game(name: &str) -> Game {
+        Game {
+            name: name.into(),
+            bundle: PathBuf::from(format!("/home/a/Applications/{}.lnx", name)),
+            icon: String::new(),
+        }
+    }
+
+    #[test]
+    fn vdf_roundtrip() {
+        let entries = vec![(
+            "shortcuts".to_string(),
+            Value::Map(vec![(
+                "0".into(),
+                Value::Map(vec![
+                    ("appid".into(), Value::Int(0x8000_0001)),
+                    ("AppName".into(), Value::Str("Game".into())),
+                ]),
+            )]),
+        )];
+        let bytes = write(&entries);
+        assert_eq!(&bytes[..11], b"\x00shortcuts\x00");
+        assert_eq!(&bytes[bytes.len() - 2..], b"\x08\x08");
+        assert_eq!(parse(&bytes).unwrap(), entries);
+        assert!(parse(b"\x01key\x00unterminated").is_err());
+    }
+
+    #[test]
+    fn appid_has_high_bit() {
+        assert!(shortcut_appid("\"/usr/bin/dotlnx\"", "Game") & 0x8000_0000 != 0);
+        assert_ne!(
+            shortcut_appid("\"/usr/bin/dotlnx\"", "A"),
+            shortcut_appid("\"/usr/bin/dotlnx\"", "B")
+        );
+    }
+
+    #[test]
+    fn merge_keeps_manual_shortcuts_and_replaces_managed() {
+        let dotlnx = Path::new("/usr/bin/dotlnx");
+        let manual = Value::Map(vec![("AppName".into(), Value::Str("Manual".into()))]);
+        let existing = vec![(
+            "shortcuts".to_string(),
+            Value::Map(vec![
+                ("0".into(), manual.clone()),
+                ("1".into(), shortcut(&game("Old"), dotlnx)),
+                ("2".into(), shortcut(&game("Kept"), dotlnx)),
+            ]),
+        )];
+        // Named export: replaces Old, keeps other managed entries.
+        let merged = merge(existing.clone(), &[game("Old")], dotlnx, false);
+        let Value::Map(ref list) = merged[0].1 else {
+            panic!()
+        };
+        let names: Vec<_> = list.iter().map(|(_, v)| managed_name(v)).collect();
+        assert_eq!(names, vec![None, Some("Kept"), Some("Old")]);
+        assert_eq!(list[2].0, "2");
+
+        // Full export: drops managed entries for bundles that are gone.
+        let merged = merge(existing, &[game("New")], dotlnx, true);
+        let Value::Map(ref list) = merged[0].1 else {
+            panic!()
+        };
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].1, manual);
+        assert_eq!(managed_name(&list[1].1), Some("New"));
+    }
+}
@@ -1,47 +1,407 @@
 //! One-shot sync: scan app folders, validate, generate AppArmor, generate .desktop.
 //! Used by the watch service and for scripts/CI.
+//!
+//! Each Applications dir is synced in two stages: bundles are read and compared one by one, then
+//! the AppArmor profiles that need (re)loading are compiled a few at a time (apparmor_parser is
+//! slow), and only after that are the menu entries written.
 
 use anyhow::Result;
-use std::collections::HashSet;
-use std::path::Path;
-use tracing::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use tracing::{debug, info, warn};
 
+use crate::access;
 use crate::apparmor;
+use crate::asuser;
 use crate::bundle;
 use crate::config;
 use crate::desktop;
+use crate::fscache;
+use crate::grant;
+use crate::hold;
+use crate::hooks;
+use crate::i18n;
+use crate::integration;
+use crate::journal;
+use crate::kiosk;
+use crate::pack;
+use crate::paths::Paths;
+use crate::policy;
+use crate::safefs;
+use crate::scan;
+use crate::settings;
+use crate::state;
+use crate::status;
+use crate::systemd;
+use crate::templates;
 use crate::validate;
 
 /// Run full sync: make installed state match folders (add/update .lnx → install; remove .lnx → uninstall).
 /// When root + SUDO_USER: sync invoking user only. When root (daemon): sync all users. When non-root: current user only.
-pub fn run(dry_run: bool) -> Result<()> {
+/// Returns per-run counts (for status reporting).
+pub fn run(dry_run: bool) -> Result<Summary> {
+    run_forcing(dry_run, false)
+}
+
+/// Like `run`; with `force`, held apps whose bundle changed are updated too (`sync --force`).
+pub fn run_forcing(dry_run: bool, force: bool) -> Result<Summary> {
+    let summary = sync_once(dry_run, force, &Control::default())?;
+    Ok(summary.expect("sync without a cancel source cannot be cancelled"))
+}
+
+/// Lets the watcher follow a sync running on another thread and stop it early, as a whole or for
+/// single bundles.
+#[derive(Debug, Default)]
+pub struct Control {
+    cancelled: AtomicBool,
+    /// Bundles whose config.toml this sync has already read.
+    visited: Mutex<HashSet<PathBuf>>,
+    /// Visited bundles that changed again since: their outputs are not written by this sync.
+    superseded: Mutex<HashSet<PathBuf>>,
+}
+
+impl Control {
+    /// Ask the sync to stop at the next bundle boundary.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// `bundle` changed while this sync runs. When the sync already read it, its work on that
+    /// bundle is dropped (the bundle is left as it was) and true is returned: the caller has to
+    /// sync again for it. Other bundles are not affected.
+    pub fn supersede(&self, bundle: &Path) -> bool {
+        let Ok(visited) = self.visited.lock() else {
+            return false;
+        };
+        if !visited.contains(bundle) {
+            return false;
+        }
+        if let Ok(mut s) = self.superseded.lock() {
+            s.insert(bundle.to_path_buf());
+        }
+        true
+    }
+
+    fn is_superseded(&self, bundle: &Path) -> bool {
+        self.superseded.lock().is_ok_and(|s| s.contains(bundle))
+    }
+
+    fn visit(&self, bundle: &Path) {
+        if let Ok(mut v) = self.visited.lock() {
+            v.insert(bundle.to_path_buf());
+        }
+    }
+}
+
+/// [`run`], stoppable through `control`. Returns None when cancelled: every app is left either
+/// fully installed or as it was, and state is saved, but apps not reached are not reconciled.
+pub fn run_with(dry_run: bool, control: &Control) -> Result<Option<Summary>> {
+    sync_once(dry_run, false, control)
+}
+
+fn sync_once(dry_run: bool, force: bool, control: &Control) -> Result<Option<Summary>> {
+    let settings = settings::load()?;
+    if !dry_run {
+        hooks::fire(
+            &settings.hooks.dir,
+            &hooks::Payload::new(hooks::Event::PreSync, None),
+        );
+    }
+    let outcome = run_pass(dry_run, force, control, &settings);
+    if let (false, Ok(Some(summary))) = (dry_run, &outcome) {
+        for change in &summary.changes {
+            hooks::fire(
+                &settings.hooks.dir,
+                &hooks::Payload::new(hooks::Event::after(change.action), Some(change)),
+            );
+        }
+    }
+    if settings.resources.low_memory {
+        release_memory();
+    }
+    outcome
+}
+
+/// Hand heap pages freed by a sync back to the kernel, so the idle watcher does not keep its
+/// peak size (glibc keeps freed memory for reuse otherwise).
+fn release_memory() {
+    #[cfg(target_env = "gnu")]
+    // SAFETY: malloc_trim only returns free pages; it touches no memory still in use.
+    unsafe {
+        nix::libc::malloc_trim(0);
+    }
+}
+
+fn run_pass(
+    dry_run: bool,
+    force: bool,
+    control: &Control,
+    settings: &settings::Settings,
+) -> Result<Option<Summary>> {
     let is_root = bundle::is_root();
+    let paths = Paths::from_settings(&settings.paths);
+    let profile_opts = apparmor::ProfileOptions::from_settings(settings, &paths)?;
+    let state_dir = state::state_dir()?;
+    let _cache = fscache::scope();
+    let mut ctx = SyncContext {
+        dry_run,
+        force,
+        is_root,
+        settings,
+        paths: &paths,
+        profile_opts: &profile_opts,
+        state: state::State::load(&state_dir)?,
+        grants: grant::load(&state_dir).unwrap_or_else(|e| {
+            warn!("ignoring grants: {:#}", e);
+            Default::default()
+        }),
+        holds: hold::load(&state_dir).unwrap_or_else(|e| {
+            warn!("ignoring holds: {:#}", e);
+            Default::default()
+        }),
+        member_menus: Vec::new(),
+        system_names: system_app_names(&paths),
+        kiosk_app: None,
+        summary: Summary::default(),
+        tiers: Vec::new(),
+        invalid: Vec::new(),
+        journal: journal::Journal::new(&state_dir),
+        control,
+        jobs: parser_jobs(settings),
+    };
+    let mut previous_names = Default::default();
+    if !dry_run {
+        recover_interrupted(&paths, &ctx.journal)?;
+        previous_names = std::mem::take(&mut ctx.state.names);
+    }
 
-    for (apps_dir, desktop_dir, username) in bundle::user_tier_entries()? {
-        if apps_dir.exists() {
-            sync_dir(
-                &apps_dir,
-                &desktop_dir,
-                Tier::User(username),
-                dry_run,
-                is_root,
-            )?;
+    for (apps_dir, desktop_dir, username) in bundle::user_tier_entries(&paths)? {
+        if apps_dir.exists() && !control.is_cancelled() {
+            sync_dir(&apps_dir, &desktop_dir, Tier::User(username), &mut ctx)?;
         }
     }
 
-    if is_root {
-        let system_apps = bundle::system_applications_dir();
-        if system_apps.exists() {
-            sync_dir(
-                &system_apps,
-                &desktop::system_applications_dir(),
-                Tier::System,
-                dry_run,
-                true,
+    if is_root && !control.is_cancelled() && paths.system_apps.exists() {
+        sync_dir(
+            &paths.system_apps,
+            &paths.system_desktop,
+            Tier::System,
+            &mut ctx,
+        )?;
+        if !control.is_cancelled() {
+            sync_member_menus(&mut ctx)?;
+        }
+    }
+    if is_root && !control.is_cancelled() {
+        let (installed, updated) = (ctx.kiosk_app.is_some(), ctx.kiosk_app == Some(true));
+        match kiosk::sync(&paths, &settings.kiosk, installed, updated, dry_run) {
+            Ok(Some(change)) => ctx.summary.record(change),
+            Ok(None) => {}
+            Err(e) => warn!("kiosk session: {:#}", e),
+        }
+    }
+    let cancelled = control.is_cancelled();
+    if !dry_run {
+        if cancelled {
+            // A partial index would hide apps not reached; keep the last complete one.
+            ctx.state.names = previous_names;
+        } else {
+            ctx.state.record_sync(&ctx.summary);
+            ctx.state
+                .scans
+                .retain(|bundle, _| Path::new(bundle).is_dir());
+        }
+        ctx.state.save(&state_dir)?;
+        if !cancelled {
+            status::save(
+                &state_dir,
+                &status::Status::new(std::mem::take(&mut ctx.tiers)),
             )?;
         }
     }
-    Ok(())
+    if cancelled {
+        info!("sync cancelled");
+        return Ok(None);
+    }
+    Ok(Some(ctx.summary))
+}
+
+/// Names of the system tier's apps when they win over the user tier's (`[paths] precedence` other
+/// than "user"), read from the bundles themselves since the user tiers are synced first.
+fn system_app_names(paths: &Paths) -> HashSet<String> {
+    if paths.precedence == settings::Precedence::User {
+        return HashSet::new();
+    }
+    bundle::discover_lnx_dirs(&paths.system_apps)
+        .iter()
+        .filter_map(|dir| config::load(dir).ok())
+        .map(|cfg| cfg.app_id())
+        .collect()
+}
+
+/// How many apparmor_parser processes run at once: [apparmor] jobs, or the CPU count up to 4;
+/// always one in low-memory mode (each parser can take hundreds of MB on a large profile).
+fn parser_jobs(settings: &settings::Settings) -> usize {
+    if settings.resources.low_memory {
+        return 1;
+    }
+    match settings.apparmor.jobs {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get().min(4)),
+        n => n,
+    }
+}
+
+/// Run `f` over `items` on up to `jobs` threads. Items not yet started when `control` is
+/// cancelled are skipped.
+///
+/// Scoped threads rather than an async runtime: every item is blocking work (apparmor_parser,
+/// file writes), which tokio would only hand to its blocking pool anyway, and the daemon has
+/// no runtime to share.
+fn for_each_bounded<T: Sync>(items: &[T], jobs: usize, control: &Control, f: impl Fn(&T) + Sync) {
+    let next = AtomicUsize::new(0);
+    std::thread::scope(|s| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            s.spawn(|| {
+                while !control.is_cancelled() {
+                    let Some(item) = items.get(next.fetch_add(1, Ordering::SeqCst)) else {
+                        break;
+                    };
+                    f(item);
+                }
+            });
+        }
+    });
+}
+
+/// What one sync pass found.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Summary {
+    /// True when any menu entry or profile was (or, in dry-run, would be) written or removed.
+    pub changed: bool,
+    /// Bundles installed (or that would be, in dry-run).
+    pub installed: usize,
+    /// Bundles skipped as invalid (bad config, failed validation or override).
+    pub skipped: usize,
+    /// Bundles refused by the trust checks or the [scan] checks.
+    pub quarantined: usize,
+    /// Apps whose generated outputs differ from what is on disk.
+    pub changes: Vec<Change>,
+    /// Installed apps whose outputs already matched (nothing written).
+    pub unchanged: Vec<String>,
+    /// Held apps whose bundle changed, left at the installed version.
+    pub held: Vec<String>,
+}
+
+impl Summary {
+    pub fn record(&mut self, change: Change) {
+        self.changed = true;
+        self.changes.push(change);
+    }
+}
+
+/// One app whose outputs a sync (or uninstall) changes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change {
+    pub action: Action,
+    pub app: String,
+    /// Generated files written or removed (.desktop entry, AppArmor profile).
+    pub paths: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Install,
+    Update,
+    Remove,
+}
+
+impl Action {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Install => "install",
+            Action::Update => "update",
+            Action::Remove => "remove",
+        }
+    }
+}
+
+/// Per-run inputs shared by every directory synced in one pass.
+struct SyncContext<'a> {
+    dry_run: bool,
+    /// Update held apps whose bundle changed (`sync --force`).
+    force: bool,
+    is_root: bool,
+    settings: &'a settings::Settings,
+    paths: &'a Paths,
+    profile_opts: &'a apparmor::ProfileOptions,
+    state: state::State,
+    /// Temporary profile exceptions from `dotlnx grant`.
+    grants: grant::Grants,
+    /// Apps kept at a validated version by `dotlnx hold`.
+    holds: hold::Holds,
+    /// System apps pinned to some users, collected by the system tier's pass.
+    member_menus: Vec<MemberMenu>,
+    /// Apps in the system Applications dir, when the site gives them precedence over the user
+    /// tier's (empty otherwise).
+    system_names: HashSet<String>,
+    /// The [kiosk] app, when the system tier's pass installed it: whether its outputs changed.
+    kiosk_app: Option<bool>,
+    summary: Summary,
+    /// What each tier's pass found, for `dotlnx status`.
+    tiers: Vec<status::TierStatus>,
+    /// Bundles the current tier's pass skipped, and why.
+    invalid: Vec<status::Invalid>,
+    journal: journal::Journal,
+    control: &'a Control,
+    /// Profiles compiled at once.
+    jobs: usize,
+}
+
+impl SyncContext<'_> {
+    /// Count a bundle the pass did not install, keeping why for `dotlnx status`.
+    fn skip(&mut self, dir: &Path, error: String) {
+        self.summary.skipped += 1;
+        self.invalid.push(status::Invalid {
+            bundle: dir.to_path_buf(),
+            error,
+        });
+    }
+}
+
+/// Outputs one bundle needs written, collected so profiles can be loaded in parallel.
+struct Install {
+    cfg: config::Config,
+    dir: PathBuf,
+    profile_name: Option<String>,
+    profile_content: Option<String>,
+    /// Profile for aa-exec in the menu entry (None: unconfined or no AppArmor).
+    desktop_profile: Option<String>,
+    /// Service units the bundle needs (none for an app).
+    units: Vec<systemd::Unit>,
+    /// Files from the bundle's share/ (schemas, ...) and what state says is installed.
+    integration: Vec<integration::File>,
+    installed_integration: Vec<PathBuf>,
+    stale: Stale,
+    load_profile: bool,
+    /// The app has a menu entry in the tier's applications dir (not a service or pinned app).
+    menu: bool,
+}
+
+/// A system app only some users get a menu entry for (see `access`).
+struct MemberMenu {
+    app: String,
+    allowed_users: Vec<String>,
+    allowed_groups: Vec<String>,
+    /// The entry to write; None for a held app, whose entries are left as they are.
+    content: Option<String>,
 }
 
 enum Tier {
@@ -49,98 +409,422 @@ enum Tier {
     System,
 }
 
+impl Tier {
+    /// The user of a user tier; None for the system tier.
+    fn username(&self) -> Option<&str> {
+        match self {
+            Tier::User(u) => Some(u),
+            Tier::System => None,
+        }
+    }
+
+    /// Start of this tier's profile names.
+    fn prefix<'p>(&self, paths: &'p Paths) -> &'p str {
+        match self {
+            Tier::User(_) => &paths.user_prefix,
+            Tier::System => &paths.system_prefix,
+        }
+    }
+
+    /// Tier as recorded in profile metadata.
+    fn label(&self) -> String {
+        match self {
+            Tier::User(u) => format!("user:{}", u),
+            Tier::System => "system".into(),
+        }
+    }
+}
+
+/// Make sure a user's .desktop dir (and its parents in the home) exists and belongs to the user.
+/// `sudo` mistakes leave ~/.local/share root-owned, and every menu entry for that user would then
+/// fail to install. Root repairs it; a user sync can only say what is wrong.
+fn heal_desktop_dir(ctx: &SyncContext, apps_root: &Path, desktop_dir: &Path, tier: &Tier) {
+    let Tier::User(username) = tier else { return };
+    let Some(home) = apps_root.parent() else {
+        return;
+    };
+    let Ok(rel) = desktop_dir.strip_prefix(home) else {
+        return;
+    };
+    if ctx.dry_run {
+        return;
+    }
+    if !ctx.is_root {
+        use std::os::unix::fs::MetadataExt;
+        let mut dir = home.to_path_buf();
+        for component in rel.components() {
+            dir.push(component);
+            if std::fs::metadata(&dir).is_ok_and(|m| m.uid() == 0) {
+                warn!(
+                    path = %dir.display(),
+                    "owned by root: menu entries cannot be written there; fix with `sudo chown -R {}: {}` or run `sudo dotlnx sync`",
+                    username,
+                    dir.display()
+                );
+                return;
+            }
+        }
+        return;
+    }
+    let Some(user) = nix::unistd::User::from_name(username).ok().flatten() else {
+        return;
+    };
+    match safefs::ensure_owned_dirs(home, rel, user.uid.as_raw(), user.gid.as_raw()) {
+        Ok(repaired) => {
+            for what in repaired {
+                info!(user = %username, "repaired desktop dir: {}", what);
+            }
+        }
+        Err(e) => warn!(user = %username, "cannot repair {}: {:#}", desktop_dir.display(), e),
+    }
+}
+
 /// Sync a single Applications directory: discover .lnx, validate, install (desktop + AppArmor), then reconcile (uninstall removed).
 fn sync_dir(
     apps_root: &Path,
     target_desktop_dir: &Path,
     tier: Tier,
-    dry_run: bool,
-    is_root: bool,
+    ctx: &mut SyncContext,
 ) -> Result<()> {
+    let (dry_run, is_root) = (ctx.dry_run, ctx.is_root);
+    let counts = (
+        ctx.summary.installed,
+        ctx.summary.skipped,
+        ctx.summary.quarantined,
+    );
+    let owner = match &tier {
+        Tier::User(u) if is_root => Some(u.as_str()),
+        _ => None,
+    };
+    extract_dropped(ctx, apps_root, owner);
     let dirs = bundle::discover_lnx_dirs(apps_root);
+    debug!(tier = %tier.label(), apps = %apps_root.display(), bundles = dirs.len(), "syncing tier");
     let mut current_names = HashSet::new();
+    if !dirs.is_empty() {
+        heal_desktop_dir(ctx, apps_root, target_desktop_dir, &tier);
+    }
+    let units_dir = unit_dir(ctx, apps_root, &tier);
+    let mut installs = Vec::new();
+    let mut folder_icons = Vec::new();
+    let mut installed_dirs = HashSet::new();
+    let mut claims = validate::NameClaims::default();
+    // Pre-hash profile names of installed apps, removed once nothing else maps to them.
+    let mut legacy_profiles = Vec::new();
 
     for dir in &dirs {
-        if let Err(e) = validate::validate_bundle(dir) {
-            warn!(bundle = %dir.display(), "skipping invalid bundle: {}", e);
-            continue;
+        if ctx.control.is_cancelled() {
+            return Ok(());
         }
-        let cfg = match config::load(dir) {
+        ctx.control.visit(dir);
+        debug!(bundle = %dir.display(), "reading bundle");
+        // Read config.toml once (as the owning user when root syncs a user bundle) and validate
+        // and install from that same copy. As root, a symlinked bundle dir is refused outright:
+        // the user could repoint it between our checks and our writes.
+        let mut cfg = match asuser::load_config(owner, dir, is_root) {
             Ok(c) => c,
             Err(e) => {
-                warn!(bundle = %dir.display(), "skipping bundle (config error): {}", e);
+                warn!(bundle = %dir.display(), "skipping bundle (config error): {:#}", e);
+                ctx.skip(dir, format!("config error: {:#}", e));
                 continue;
             }
         };
-        current_names.insert(cfg.name.clone());
-
-        if dry_run {
-            info!(
-                app = %cfg.name,
-                desktop = %target_desktop_dir.join(format!("dotlnx-{}.desktop", cfg.name)).display(),
-                "would install"
+        if let Err(e) = validate::validate_config(dir, &cfg) {
+            warn!(bundle = %dir.display(), "skipping invalid bundle: {}", e);
+            ctx.skip(dir, e.to_string());
+            continue;
+        }
+        // Fail closed: a broken admin override must not fall back to the bundle's own policy.
+        if let Err(e) = policy::apply_override(&mut cfg, &ctx.settings.apparmor.overrides_dir) {
+            warn!(bundle = %dir.display(), "skipping bundle (override error): {}", e);
+            ctx.skip(dir, format!("override error: {}", e));
+            continue;
+        }
+        ctx.settings.run.apply(&mut cfg);
+        let system_tier = matches!(tier, Tier::System);
+        if let Some(reason) = kiosk::refusal(&ctx.settings.kiosk, system_tier, &cfg) {
+            warn!(bundle = %dir.display(), "not installed: {}", reason);
+            ctx.skip(dir, reason);
+            continue;
+        }
+        // Outputs are named after the app's ID (vendor and name); logs and summaries use it too.
+        let app = cfg.app_id();
+        if !system_tier && ctx.system_names.contains(&app) {
+            let (why, setting) = match ctx.paths.precedence {
+                settings::Precedence::Error => ("conflicts with", "error"),
+                _ => ("is shadowed by", "system"),
+            };
+            let reason = format!(
+                "{} the system app of the same name in {} ([paths] precedence = \"{}\")",
+                why,
+                ctx.paths.system_apps.display(),
+                setting
             );
+            warn!(bundle = %dir.display(), "not installed: {}", reason);
+            ctx.skip(dir, reason);
             continue;
         }
-
-        std::fs::create_dir_all(target_desktop_dir)?;
-        let confine = cfg.security.as_ref().map(|s| s.confine).unwrap_or(true);
-        let profile_name = is_root.then(|| match &tier {
-            Tier::User(u) => apparmor::profile_name_user(u, &cfg.name),
-            Tier::System => apparmor::profile_name_system(&cfg.name),
-        });
-        // Only use aa-exec in .desktop when AppArmor is actually available; otherwise the launcher would fail.
-        let desktop_profile = (is_root && confine && apparmor::is_available())
-            .then(|| profile_name.as_ref().unwrap().as_str());
-        let desktop_path =
-            desktop::install_desktop(target_desktop_dir, &cfg, dir, desktop_profile)?;
-        #[cfg(unix)]
+        // Root installing from a user's home grants the bundle system trust (profile, menu entry):
+        // refuse bundles the user does not own or that other users can modify.
+        let mut issues = Vec::new();
+        let mut hint = "quarantine-hint-ownership";
         if is_root {
             if let Tier::User(ref username) = tier {
-                if let Err(e) = desktop::chown_to_user(&desktop_path, username) {
-                    warn!(path = %desktop_path.display(), user = %username, "chown desktop to user: {}", e);
-                }
+                issues = match bundle::user_uid(username) {
+                    Some(uid) => validate::bundle_trust_issues(apps_root, dir, &cfg, uid),
+                    None => vec![format!("no such user: {}", username)],
+                };
             }
         }
-
-        if let Err(e) = desktop::write_bundle_directory_file(dir, &cfg) {
-            warn!(bundle = %dir.display(), "could not write .directory for folder icon: {}", e);
+        if issues.is_empty() {
+            issues = validate::privileged_files(dir)
+                .iter()
+                .map(|f| f.to_string())
+                .collect();
+            hint = "quarantine-hint-privileged";
         }
-        #[cfg(unix)]
-        if is_root && cfg.icon.is_some() {
-            if let Tier::User(ref username) = tier {
-                let dir_file = dir.join(".directory");
-                if dir_file.exists() {
-                    if let Err(e) = desktop::chown_to_user(&dir_file, username) {
-                        warn!(path = %dir_file.display(), user = %username, "chown .directory to user: {}", e);
+        if issues.is_empty() && scan::enabled(&ctx.settings.scan) {
+            issues = scan_bundle(ctx, dir, &cfg.exclude);
+            hint = "quarantine-hint-scan";
+        }
+        if !issues.is_empty() {
+            quarantine_bundle(ctx, dir, &app, tier.username(), issues, hint);
+            ctx.summary.quarantined += 1;
+            continue;
+        }
+        if !dry_run && ctx.state.release(dir) {
+            info!(bundle = %dir.display(), "bundle no longer quarantined");
+        }
+        let confine = cfg.security.as_ref().map(|s| s.confine).unwrap_or(true);
+        let profile_name = profile_name_for(ctx.paths, &tier, &app, is_root);
+        if let Some(p) = &profile_name {
+            grant::apply(&mut cfg, &ctx.grants, p);
+        }
+        // Only use aa-exec in .desktop when AppArmor is actually available; otherwise the launcher would fail.
+        let desktop_profile =
+            (is_root && confine && apparmor::is_available()).then(|| profile_name.clone().unwrap());
+        let units = match &units_dir {
+            Some(ud) => {
+                let argv = desktop::launch_argv(&cfg, dir, desktop_profile.as_deref());
+                match systemd::generate(&cfg, dir, &argv, ud) {
+                    Ok(units) => units,
+                    Err(e) => {
+                        warn!(bundle = %dir.display(), "skipping bundle (service unit): {}", e);
+                        ctx.skip(dir, format!("service unit: {}", e));
+                        continue;
                     }
                 }
             }
+            None => Vec::new(),
+        };
+        // Two bundles of a tier with one name would overwrite each other's menu entry and profile.
+        if let Err(e) = claims.claim(&app, dir) {
+            warn!(bundle = %dir.display(), "skipping bundle: {}", e);
+            ctx.skip(dir, e.to_string());
+            continue;
+        }
+        current_names.insert(app.clone());
+        installed_dirs.insert(dir.clone());
+        if !dry_run {
+            // Indexed by ID and plain name, so `dotlnx run` finds the app by either.
+            for key in std::iter::once(&app).chain(cfg.vendor.is_some().then_some(&cfg.name)) {
+                ctx.state
+                    .names
+                    .entry(key.clone())
+                    .or_default()
+                    .push(dir.display().to_string());
+            }
         }
-        let run_as_user = match &tier {
-            Tier::User(u) if is_root => Some(u.as_str()),
+        ctx.summary.installed += 1;
+
+        if is_root {
+            let legacy =
+                apparmor::legacy_profile_name(tier.prefix(ctx.paths), tier.username(), &app);
+            legacy_profiles.extend(legacy.map(|p| (p, dir.clone())));
+        }
+        let profile_content = match &profile_name {
+            Some(p) if confine => Some(apparmor::generate_profile(dir, &cfg, p, ctx.profile_opts)),
             _ => None,
         };
-        if let Err(e) = desktop::set_gnome_folder_icon(dir, &cfg, run_as_user) {
-            warn!(bundle = %dir.display(), "could not set GNOME folder icon: {}", e);
+        // Services get a unit instead of a menu entry; system apps pinned to some users get one
+        // in each member's home instead of the shared one.
+        let pinned = system_tier && access::is_restricted(&cfg);
+        let menu = cfg.kind == config::Kind::App && !pinned;
+        let template_vars = templates::Vars {
+            name: &cfg.name,
+            bundle: dir,
+            profile: desktop_profile.as_deref(),
+        };
+        let entry = || {
+            ctx.profile_opts.templates.apply_desktop(
+                desktop::generate_desktop(&cfg, dir, desktop_profile.as_deref()),
+                &template_vars,
+            )
+        };
+        let desktop_content = menu.then(entry);
+        let member_menu = (pinned && cfg.kind == config::Kind::App).then(|| MemberMenu {
+            app: app.clone(),
+            allowed_users: cfg.allowed_users.clone(),
+            allowed_groups: cfg.allowed_groups.clone(),
+            content: None,
+        });
+        let mut stale = stale_outputs(
+            ctx.paths,
+            target_desktop_dir,
+            &app,
+            owner,
+            desktop_content.as_deref(),
+            profile_name.as_deref(),
+            profile_content.as_deref(),
+        )?;
+        if let Some(ud) = &units_dir {
+            stale.compare_units(ud, &app, &units)?;
         }
+        let installed_integration = ctx
+            .state
+            .integrations
+            .get(&integration::key(ctx.paths, target_desktop_dir, &app))
+            .cloned()
+            .unwrap_or_default();
+        // Unreadable share/ files leave what is installed alone.
+        let integration = match integration::wanted(
+            ctx.paths,
+            &cfg,
+            dir,
+            target_desktop_dir,
+            desktop_profile.as_deref(),
+            owner,
+        ) {
+            Ok(files) => {
+                stale.integration = integration::stale(&files, &installed_integration, owner)?;
+                files
+            }
+            Err(e) => {
+                warn!(bundle = %dir.display(), "cannot read the bundle's share/ files: {:#}", e);
+                Vec::new()
+            }
+        };
+        let change = stale.change(ctx.paths, target_desktop_dir, &app, profile_name.as_deref());
+        // A held app keeps the outputs of the bundle it was held at until `sync --force`.
+        let hold_key = ctx.paths.profile_name(tier.username(), &app);
+        if change.is_some()
+            && !ctx.force
+            && ctx
+                .holds
+                .get(&hold_key)
+                .is_some_and(|h| h.blocks(dir, &cfg.exclude))
+        {
+            warn!(
+                app = %app,
+                bundle = %dir.display(),
+                "held: the bundle changed; keeping the installed version (dotlnx sync --force or dotlnx unhold to update)"
+            );
+            ctx.summary.held.push(app.clone());
+            ctx.member_menus.extend(member_menu);
+            if system_tier && ctx.settings.kiosk.app.is_some() {
+                ctx.kiosk_app = Some(false);
+            }
+            continue;
+        }
+        if system_tier && ctx.settings.kiosk.app.is_some() {
+            ctx.kiosk_app = Some(change.is_some());
+        }
+        if let Some(mut m) = member_menu {
+            m.content = Some(entry());
+            ctx.member_menus.push(m);
+        }
+        // A profile whose file is current may still need loading (e.g. after an AppArmor restart,
+        // or a reload that failed after the file was written); one the kernel already has as
+        // written is not reloaded, which would only cost a compile and an audit record.
+        let load_profile = match (&profile_name, &profile_content) {
+            (Some(p), Some(content)) => {
+                stale.profile || !apparmor::is_current(ctx.paths, p, content)
+            }
+            _ => false,
+        };
 
-        if is_root {
-            let profile_name = profile_name.as_ref().unwrap();
-            if confine {
-                let profile_content = apparmor::generate_profile(dir, &cfg, profile_name);
-                if let Err(e) = apparmor::load_profile(profile_name, &profile_content) {
-                    warn!(profile = %profile_name, "could not load AppArmor profile: {}", e);
-                }
-            } else {
-                // App runs unconfined; remove profile if it existed (e.g. switched from confined)
-                let _ = apparmor::unload_profile(profile_name);
+        if dry_run {
+            match &change {
+                Some(c) => info!(app = %app, paths = ?c.paths, "would {}", c.action.as_str()),
+                None => info!(app = %app, "unchanged"),
+            }
+        }
+        match change {
+            Some(c) => ctx.summary.record(c),
+            None => ctx.summary.unchanged.push(app.clone()),
+        }
+        if dry_run {
+            continue;
+        }
+
+        decorate(ctx, dir, &cfg, owner, &mut folder_icons);
+        // A held bundle stays as it was validated, old versions included.
+        if ctx.holds.get(&hold_key).is_none_or(|h| h.bundle != *dir) {
+            prune_versions(ctx, dir, &cfg, owner);
+        }
+        if stale.desktop
+            || stale.profile
+            || !stale.units.is_empty()
+            || !stale.integration.is_empty()
+            || load_profile
+        {
+            installs.push(Install {
+                cfg,
+                dir: dir.clone(),
+                profile_name,
+                profile_content,
+                desktop_profile,
+                units,
+                integration,
+                installed_integration,
+                stale,
+                load_profile,
+                menu,
+            });
+            // Low-memory mode does not hold the tier's generated outputs: write them now.
+            if ctx.settings.resources.low_memory {
+                install_outputs(
+                    &installs,
+                    target_desktop_dir,
+                    &tier,
+                    owner,
+                    units_dir.as_ref(),
+                    ctx,
+                )?;
+                installs.clear();
             }
         }
     }
+    if !installs.is_empty() {
+        install_outputs(
+            &installs,
+            target_desktop_dir,
+            &tier,
+            owner,
+            units_dir.as_ref(),
+            ctx,
+        )?;
+    }
+    apply_folder_icons(ctx, folder_icons, owner);
+    if ctx.control.is_cancelled() {
+        // Bundles not reached must not be mistaken for removed ones.
+        return Ok(());
+    }
+
+    if !dry_run {
+        remove_legacy_profiles(ctx, &tier, &current_names, &legacy_profiles);
+    }
+
+    // Folders decorated by an earlier sync that are no longer installed from here (removed,
+    // renamed, invalid, quarantined): undone below, with the app's uninstall when it has one.
+    let mut orphaned = if dry_run {
+        Vec::new()
+    } else {
+        take_decorations(&mut ctx.state, apps_root, &installed_dirs)
+    };
 
     // Reconcile: uninstall desktops (and profiles) for apps no longer in the folder
-    if !dry_run && target_desktop_dir.exists() {
+    let mut removed = HashSet::new();
+    if target_desktop_dir.exists() {
         for entry in std::fs::read_dir(target_desktop_dir)? {
             let entry = entry?;
             let path = entry.path();
@@ -148,39 +832,1026 @@ fn sync_dir(
                 continue;
             }
             let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
-            if !stem.starts_with("dotlnx-") {
+            let Some(name) = stem.strip_prefix(ctx.paths.desktop_prefix(target_desktop_dir)) else {
                 continue;
-            }
-            let name = stem.strip_prefix("dotlnx-").unwrap_or(stem);
+            };
             if current_names.contains(name) {
                 continue;
             }
             if validate::validate_app_name(name).is_err() {
                 continue;
             }
-            if let Err(e) = uninstall_one(target_desktop_dir, name, &tier, is_root) {
-                warn!(app = %name, "uninstall failed: {}", e);
+            removed.insert(name.to_string());
+            remove_app(
+                ctx,
+                target_desktop_dir,
+                name,
+                &tier,
+                units_dir.as_ref(),
+                &mut orphaned,
+            );
+        }
+    }
+    // Services have no menu entry: their units say which app they belong to.
+    if let Some(ud) = &units_dir {
+        for name in ud.apps()? {
+            if current_names.contains(&name) || removed.contains(&name) {
+                continue;
             }
+            remove_app(
+                ctx,
+                target_desktop_dir,
+                &name,
+                &tier,
+                Some(ud),
+                &mut orphaned,
+            );
         }
     }
+    // The rest belong to apps still installed from another folder, or to none at all.
+    clear_decorations(
+        ctx.paths,
+        orphaned.iter_mut().map(|(b, d)| (b.as_path(), d)),
+    );
+    keep_uncleared(&mut ctx.state, orphaned);
+    ctx.tiers.push(status::TierStatus {
+        tier: tier.label(),
+        apps_dir: apps_root.to_path_buf(),
+        time: state::now(),
+        discovered: dirs.len(),
+        installed: ctx.summary.installed - counts.0,
+        skipped: ctx.summary.skipped - counts.1,
+        quarantined: ctx.summary.quarantined - counts.2,
+        invalid: std::mem::take(&mut ctx.invalid),
+    });
 
     Ok(())
 }
 
-/// Uninstall a single app from a tier: remove desktop and (when root) AppArmor profile.
+/// Unpack packed bundles dropped into `apps_root` (as the owning user when root syncs a user
+/// tier), so this pass installs them. One that fails is counted as skipped and left renamed to
+/// `<file>.failed`, unless it ends early: that one stays for the next sync.
+fn extract_dropped(ctx: &mut SyncContext, apps_root: &Path, owner: Option<&str>) {
+    for archive in pack::dropped_archives(apps_root) {
+        if ctx.dry_run {
+            info!(archive = %archive.display(), "would unpack dropped archive");
+            continue;
+        }
+        match asuser::perform(
+            owner,
+            &asuser::Request::ExtractArchive {
+                archive: archive.clone(),
+            },
+        ) {
+            Ok(bundle) => {
+                info!(archive = %archive.display(), bundle = bundle.as_deref().unwrap_or_default(), "unpacked dropped archive")
+            }
+            Err(e) => {
+                warn!(archive = %archive.display(), "cannot unpack dropped archive: {:#}", e);
+                ctx.skip(&archive, format!("archive error: {:#}", e));
+            }
+        }
+    }
+}
+
+/// Remove profiles installed apps had under their pre-hash names (see
+/// [`apparmor::legacy_profile_name`]), unless an app of the tier now has that name or another
+/// bundle wrote the profile.
+fn remove_legacy_profiles(
+    ctx: &SyncContext,
+    tier: &Tier,
+    current_names: &HashSet<String>,
+    legacy: &[(String, PathBuf)],
+) {
+    let in_use: HashSet<String> = current_names
+        .iter()
+        .filter_map(|n| profile_name_for(ctx.paths, tier, n, ctx.is_root))
+        .collect();
+    for (profile, dir) in legacy {
+        if in_use.contains(profile) || apparmor::installed_profile(ctx.paths, profile).is_none() {
+            continue;
+        }
+        let Ok(lock) = apparmor::lock_profile(ctx.paths, profile) else {
+            continue;
+        };
+        if lock.meta().is_some_and(|m| m.bundle != *dir) {
+            continue;
+        }
+        match lock.unload() {
+            Ok(()) => info!(profile = %profile, "removed profile under its old name"),
+            Err(e) => warn!(profile = %profile, "could not remove old profile: {:#}", e),
+        }
+    }
+}
+
+/// Remove older versions of the bundle's AppImage beyond its keep_versions (or the site's
+/// default), as the bundle's owner when root syncs a user's bundle.
+fn prune_versions(ctx: &SyncContext, dir: &Path, cfg: &config::Config, owner: Option<&str>) {
+    let Some(keep) = cfg.keep_versions.or(ctx.settings.resources.keep_versions) else {
+        return;
+    };
+    for old in bundle::surplus_appimages(dir, &cfg.executable, keep) {
+        let name = old
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let bin = old.parent().unwrap_or(dir).to_path_buf();
+        let removed = match owner {
+            Some(user) => {
+                asuser::perform(Some(user), &asuser::Request::RemoveFile { dir: bin, name })
+                    .map(|_| ())
+            }
+            None => std::fs::remove_file(&old).map_err(Into::into),
+        };
+        match removed {
+            Ok(()) => {
+                info!(app = %cfg.app_id(), removed = %old.display(), "removed old AppImage version")
+            }
+            Err(e) => {
+                warn!(app = %cfg.app_id(), "cannot remove old AppImage {}: {:#}", old.display(), e)
+            }
+        }
+    }
+}
+
+/// Bring the changes sync makes inside a bundle folder (.directory file, GNOME folder icon) in
+/// line with its config, recording them in state so they can be undone later. Folder icon
+/// changes are added to `icons`, for [`apply_folder_icons`] to make for the whole tier at once.
+fn decorate(
+    ctx: &mut SyncContext,
+    dir: &Path,
+    cfg: &config::Config,
+    owner: Option<&str>,
+    icons: &mut Vec<desktop::FolderIcon>,
+) {
+    let key = dir.display().to_string();
+    let mut deco = ctx.state.decorations.remove(&key).unwrap_or_default();
+    deco.app = cfg.app_id();
+    deco.user = owner.map(str::to_string);
+    if desktop::directory_file_content(dir, cfg).is_some() {
+        match desktop::write_bundle_directory_file(dir, cfg, owner) {
+            Ok(_) => deco.directory_file = true,
+            Err(e) => {
+                warn!(bundle = %dir.display(), "could not write .directory for folder icon: {}", e)
+            }
+        }
+    } else if deco.directory_file {
+        // The app lost its icon.
+        match desktop::remove_bundle_directory_file(dir, owner) {
+            Ok(()) => deco.directory_file = false,
+            Err(e) => warn!(bundle = %dir.display(), "could not remove .directory: {}", e),
+        }
+    }
+    let url = desktop::folder_icon_url(dir, cfg);
+    let changed = url != deco.folder_icon;
+    if changed {
+        icons.push(desktop::FolderIcon {
+            bundle: dir.to_path_buf(),
+            url,
+        });
+    }
+    if !deco.is_empty() || changed {
+        ctx.state.decorations.insert(key, deco);
+    }
+}
+
+/// Make a tier's folder icon changes (one gio batch for the user) and record those made. An icon
+/// gio could not set (or gio missing) is tried again by the next sync; a clear is not.
+fn apply_folder_icons(ctx: &mut SyncContext, icons: Vec<desktop::FolderIcon>, owner: Option<&str>) {
+    if icons.is_empty() {
+        return;
+    }
+    let done = match desktop::apply_gnome_folder_icons(ctx.paths, &icons, owner) {
+        Ok(done) => done,
+        Err(e) => {
+            warn!(
+                count = icons.len(),
+                "could not set GNOME folder icons: {}", e
+            );
+            return;
+        }
+    };
+    for (icon, done) in icons.into_iter().zip(done) {
+        let key = icon.bundle.display().to_string();
+        let Some(deco) = ctx.state.decorations.get_mut(&key) else {
+            continue;
+        };
+        match icon.url {
+            Some(url) if done => deco.folder_icon = Some(url),
+            Some(_) => {}
+            None => deco.folder_icon = None,
+        }
+        if deco.is_empty() {
+            ctx.state.decorations.remove(&key);
+        }
+    }
+}
+
+/// Remove from state the decorations of bundles under `apps_root` that are not in `installed`.
+fn take_decorations(
+    state: &mut state::State,
+    apps_root: &Path,
+    installed: &HashSet<PathBuf>,
+) -> Vec<(PathBuf, state::Decoration)> {
+    let keys: Vec<String> = state
+        .decorations
+        .keys()
+        .filter(|k| Path::new(k).starts_with(apps_root) && !installed.contains(Path::new(k)))
+        .cloned()
+        .collect();
+    keys.into_iter()
+        .filter_map(|k| state.decorations.remove_entry(&k))
+        .map(|(k, d)| (PathBuf::from(k), d))
+        .collect()
+}
+
+/// Put back decorations that could not be undone, so the next sync (or `dotlnx gc`) retries.
+/// Those of folders that are gone are forgotten: there is nothing left to undo.
+pub(crate) fn keep_uncleared(
+    state: &mut state::State,
+    decorations: Vec<(PathBuf, state::Decoration)>,
+) {
+    for (bundle, deco) in decorations {
+        if !deco.is_empty() && bundle.is_dir() {
+            state.decorations.insert(bundle.display().to_string(), deco);
+        }
+    }
+}
+
+/// Undo what sync changed inside bundle folders, as the user each was changed as (the folder
+/// icons in one gio batch per user). Whatever could not be undone is left set in its decoration.
+/// A folder that no longer exists has nothing to undo.
+pub(crate) fn clear_decorations<'a>(
+    paths: &Paths,
+    decorations: impl IntoIterator<Item = (&'a Path, &'a mut state::Decoration)>,
+) {
+    let mut icons: BTreeMap<Option<String>, Vec<(&Path, &mut state::Decoration)>> = BTreeMap::new();
+    for (bundle, deco) in decorations {
+        if !bundle.is_dir() {
+            continue;
+        }
+        if deco.directory_file {
+            match desktop::remove_bundle_directory_file(bundle, deco.user.as_deref()) {
+                Ok(()) => deco.directory_file = false,
+                Err(e) => warn!(bundle = %bundle.display(), "could not remove .directory: {}", e),
+            }
+        }
+        if deco.folder_icon.is_some() {
+            icons
+                .entry(deco.user.clone())
+                .or_default()
+                .push((bundle, deco));
+        }
+    }
+    for (user, decos) in icons {
+        let clears: Vec<desktop::FolderIcon> = decos
+            .iter()
+            .map(|(bundle, _)| desktop::FolderIcon {
+                bundle: bundle.to_path_buf(),
+                url: None,
+            })
+            .collect();
+        match desktop::apply_gnome_folder_icons(paths, &clears, user.as_deref()) {
+            Ok(_) => decos
+                .into_iter()
+                .for_each(|(_, deco)| deco.folder_icon = None),
+            Err(e) => {
+                warn!(user = ?user, count = clears.len(), "could not clear GNOME folder icons: {}", e)
+            }
+        }
+    }
+}
+
+/// Write the outputs of every changed bundle in a directory. All of them are journaled first, then
+/// the profiles are loaded `ctx.jobs` at a time, then the menu entries are written: profile before
+/// menu entry, so an entry never launches into a profile that isn't loaded. If the sync is
+/// cancelled while profiles load, no entries are written (the next sync finds them stale); a bundle
+/// superseded meanwhile ([`Control::supersede`]) gets none of its outputs.
+fn install_outputs(
+    installs: &[Install],
+    target_desktop_dir: &Path,
+    tier: &Tier,
+    owner: Option<&str>,
+    units_dir: Option<&systemd::UnitDir>,
+    ctx: &mut SyncContext,
+) -> Result<()> {
+    let control = ctx.control;
+    // A bundle that changed again since it was read is left as it was; the watcher syncs it again.
+    let installs: Vec<&Install> = installs
+        .iter()
+        .filter(|i| !control.is_superseded(&i.dir))
+        .collect();
+    let entries: Vec<journal::Entry> = installs
+        .iter()
+        .map(|i| journal::Entry {
+            op: journal::Op::Install,
+            app: i.cfg.app_id(),
+            desktop_dir: target_desktop_dir.to_path_buf(),
+            user: owner.map(str::to_string),
+            profile: i.profile_name.clone(),
+            started: state::now(),
+        })
+        .collect();
+    ctx.journal.begin_batch(&entries)?;
+
+    let loads: Vec<(&Install, &str, &str)> = installs
+        .iter()
+        .copied()
+        .filter(|i| i.load_profile)
+        .filter_map(|i| Some((i, i.profile_name.as_deref()?, i.profile_content.as_deref()?)))
+        .collect();
+    let (paths, names, tier_label) = (ctx.paths, &ctx.state.names, tier.label());
+    // Another bundle of this tier with the same name keeps a profile it wrote first.
+    let live = |app: &str, bundle: &Path| {
+        names
+            .get(app)
+            .is_some_and(|dirs| dirs.iter().any(|d| Path::new(d) == bundle))
+    };
+    for_each_bounded(
+        &loads,
+        ctx.jobs,
+        control,
+        |(i, profile_name, profile_content)| {
+            if control.is_superseded(&i.dir) {
+                return;
+            }
+            let loaded = apparmor::lock_profile(paths, profile_name).and_then(|lock| {
+            if let Some(meta) = lock.meta() {
+                if meta.belongs_elsewhere(&i.dir, |b| live(&i.cfg.app_id(), b)) {
+                    anyhow::bail!("in use by {}", meta.bundle.display());
+                }
+            }
+            if lock.modified_outside() {
+                warn!(profile = %profile_name, "profile was edited outside dotlnx; replacing it");
+            }
+            let owner = apparmor::ProfileOwner {
+                app: &i.cfg.app_id(),
+                tier: tier_label.clone(),
+                bundle: &i.dir,
+            };
+            lock.load(profile_content, &owner)
+        });
+            if let Err(e) = loaded {
+                warn!(profile = %profile_name, "could not load AppArmor profile: {:#}", e);
+            }
+        },
+    );
+    if ctx.control.is_cancelled() {
+        return ctx.journal.commit();
+    }
+
+    let mut integrations = Vec::new();
+    for i in installs {
+        if control.is_superseded(&i.dir) {
+            continue;
+        }
+        if let (Some(profile_name), None, true) =
+            (&i.profile_name, &i.profile_content, i.stale.profile)
+        {
+            // App runs unconfined; remove profile if it existed (e.g. switched from confined)
+            if let Ok(lock) = apparmor::lock_profile(paths, profile_name) {
+                if !lock
+                    .meta()
+                    .is_some_and(|m| m.belongs_elsewhere(&i.dir, |b| live(&i.cfg.app_id(), b)))
+                {
+                    let _ = lock.unload();
+                }
+            }
+        }
+        if i.stale.desktop {
+            if i.menu {
+                desktop::install_desktop(
+                    paths,
+                    target_desktop_dir,
+                    &i.cfg,
+                    &i.dir,
+                    i.desktop_profile.as_deref(),
+                    owner,
+                )?;
+            } else {
+                desktop::uninstall_desktop(paths, target_desktop_dir, &i.cfg.app_id(), owner)?;
+            }
+        }
+        if let (Some(ud), false) = (units_dir, i.stale.units.is_empty()) {
+            systemd::apply(paths, ud, &i.cfg.app_id(), &i.units)?;
+        }
+        if !i.stale.integration.is_empty() {
+            let mut recorded: Vec<PathBuf> = i.integration.iter().map(|f| f.path.clone()).collect();
+            if let Err(e) = integration::apply(
+                paths,
+                &i.dir,
+                &i.integration,
+                &i.installed_integration,
+                owner,
+            ) {
+                warn!(app = %i.cfg.app_id(), "could not install the bundle's share/ files: {:#}", e);
+                // Whatever got written is removed by the next sync.
+                for path in &i.installed_integration {
+                    if !recorded.contains(path) {
+                        recorded.push(path.clone());
+                    }
+                }
+            }
+            integrations.push((
+                integration::key(paths, target_desktop_dir, &i.cfg.app_id()),
+                recorded,
+            ));
+        }
+    }
+    for (key, files) in integrations {
+        match files.is_empty() {
+            true => ctx.state.integrations.remove(&key),
+            false => ctx.state.integrations.insert(key, files),
+        };
+    }
+    ctx.journal.commit()
+}
+
+/// Give every user the menu entries of the system apps pinned to them (`allowed_users`,
+/// `allowed_groups`) and remove the ones they no longer get, as that user, in
+/// [`access::MEMBER_MENU_DIR`] under their applications dir.
+fn sync_member_menus(ctx: &mut SyncContext) -> Result<()> {
+    let menus = std::mem::take(&mut ctx.member_menus);
+    for (_, desktop_dir, username) in bundle::user_tier_entries(ctx.paths)? {
+        if nix::unistd::User::from_name(&username)
+            .ok()
+            .flatten()
+            .is_none()
+        {
+            continue;
+        }
+        let dir = desktop_dir.join(access::MEMBER_MENU_DIR);
+        let groups = access::groups_of(&username);
+        let mut wanted = HashSet::new();
+        for menu in &menus {
+            let member = access::is_listed(
+                &username,
+                &groups,
+                &menu.allowed_users,
+                &menu.allowed_groups,
+            );
+            if !member && menu.content.is_some() {
+                continue;
+            }
+            wanted.insert(menu.app.as_str());
+            let Some(content) = &menu.content else {
+                continue;
+            };
+            if let Err(e) = sync_member_menu(ctx, &dir, &username, &menu.app, content) {
+                warn!(app = %menu.app, user = %username, "could not write menu entry: {:#}", e);
+            }
+        }
+        let listing = match asuser::perform(
+            Some(&username),
+            &asuser::Request::ListFiles { dir: dir.clone() },
+        ) {
+            Ok(listing) => listing.unwrap_or_default(),
+            Err(e) => {
+                warn!(user = %username, "cannot list {}: {:#}", dir.display(), e);
+                continue;
+            }
+        };
+        for file in listing.lines() {
+            let prefix = ctx.paths.desktop_prefix(&dir);
+            let Some(name) = file
+                .strip_suffix(".desktop")
+                .and_then(|f| f.strip_prefix(prefix))
+            else {
+                continue;
+            };
+            if wanted.contains(name) || validate::validate_app_name(name).is_err() {
+                continue;
+            }
+            ctx.summary.record(Change {
+                action: Action::Remove,
+                app: name.to_string(),
+                paths: vec![dir.join(file)],
+            });
+            if ctx.dry_run {
+                info!(app = %name, user = %username, "would remove menu entry");
+            } else if let Err(e) =
+                desktop::uninstall_desktop(ctx.paths, &dir, name, Some(&username))
+            {
+                warn!(app = %name, user = %username, "could not remove menu entry: {:#}", e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write one member's entry for a pinned app when it differs from what they have.
+fn sync_member_menu(
+    ctx: &mut SyncContext,
+    dir: &Path,
+    username: &str,
+    app: &str,
+    content: &str,
+) -> Result<()> {
+    let existing = desktop::read_desktop(ctx.paths, dir, app, Some(username))?;
+    if existing.as_deref() == Some(content) {
+        return Ok(());
+    }
+    let action = if existing.is_some() {
+        Action::Update
+    } else {
+        Action::Install
+    };
+    ctx.summary.record(Change {
+        action,
+        app: app.to_string(),
+        paths: vec![dir.join(ctx.paths.desktop_file_name(dir, app))],
+    });
+    if ctx.dry_run {
+        info!(app = %app, user = %username, "would {} menu entry", action.as_str());
+        return Ok(());
+    }
+    desktop::write_desktop(ctx.paths, dir, app, content.to_string(), Some(username))?;
+    Ok(())
+}
+
+/// Report and (unless dry-run) uninstall an app that is no longer in the folder.
+fn remove_app(
+    ctx: &mut SyncContext,
+    target_desktop_dir: &Path,
+    name: &str,
+    tier: &Tier,
+    units_dir: Option<&systemd::UnitDir>,
+    orphaned: &mut [(PathBuf, state::Decoration)],
+) {
+    let profile_name = profile_name_for(ctx.paths, tier, name, ctx.is_root);
+    let mut paths: Vec<PathBuf> =
+        Some(target_desktop_dir.join(ctx.paths.desktop_file_name(target_desktop_dir, name)))
+            .filter(|p| p.exists())
+            .into_iter()
+            .collect();
+    if let Some(ud) = units_dir {
+        paths.extend(
+            ud.installed(name)
+                .unwrap_or_default()
+                .iter()
+                .map(|u| ud.dir.join(&u.name)),
+        );
+    }
+    paths.extend(
+        profile_name
+            .as_deref()
+            .map(|p| ctx.paths.profile_path(p))
+            .filter(|p| p.exists()),
+    );
+    let integration_key = integration::key(ctx.paths, target_desktop_dir, name);
+    paths.extend(
+        ctx.state
+            .integrations
+            .get(&integration_key)
+            .into_iter()
+            .flatten()
+            .cloned(),
+    );
+    ctx.summary.record(Change {
+        action: Action::Remove,
+        app: name.to_string(),
+        paths,
+    });
+    if ctx.dry_run {
+        info!(app = %name, "would remove");
+        return;
+    }
+    let integration = ctx
+        .state
+        .integrations
+        .remove(&integration_key)
+        .unwrap_or_default();
+    if let Err(e) = uninstall_one(
+        ctx,
+        target_desktop_dir,
+        name,
+        tier,
+        units_dir,
+        &integration,
+        orphaned,
+    ) {
+        warn!(app = %name, "uninstall failed: {}", e);
+    }
+}
+
+/// Uninstall a single app from a tier: remove desktop, service units, `integration` files and
+/// (when root) AppArmor profile, and undo the app's entries in `orphaned` (folders it decorated
+/// that are still there).
 fn uninstall_one(
+    ctx: &SyncContext,
     target_desktop_dir: &Path,
     name: &str,
     tier: &Tier,
-    is_root: bool,
+    units_dir: Option<&systemd::UnitDir>,
+    integration: &[PathBuf],
+    orphaned: &mut [(PathBuf, state::Decoration)],
 ) -> Result<()> {
-    desktop::uninstall_desktop(target_desktop_dir, name)?;
-    if is_root {
-        let profile_name = match tier {
-            Tier::User(u) => apparmor::profile_name_user(u, name),
-            Tier::System => apparmor::profile_name_system(name),
+    let (paths, journal) = (ctx.paths, &ctx.journal);
+    let as_user = match tier {
+        Tier::User(u) if ctx.is_root => Some(u.as_str()),
+        _ => None,
+    };
+    let profile_name = profile_name_for(paths, tier, name, ctx.is_root);
+    journal.begin(&journal::Entry {
+        op: journal::Op::Uninstall,
+        app: name.to_string(),
+        desktop_dir: target_desktop_dir.to_path_buf(),
+        user: as_user.map(str::to_string),
+        profile: profile_name.clone(),
+        started: state::now(),
+    })?;
+    if let Some(ud) = units_dir {
+        // Stop the service before its profile goes.
+        systemd::remove(paths, ud, name)?;
+    }
+    remove_outputs(
+        paths,
+        target_desktop_dir,
+        name,
+        as_user,
+        profile_name.as_deref(),
+    )?;
+    integration::remove(paths, integration, as_user)?;
+    clear_decorations(
+        paths,
+        orphaned
+            .iter_mut()
+            .filter(|(_, d)| d.app == name)
+            .map(|(b, d)| (b.as_path(), d)),
+    );
+    journal.commit()
+}
+
+/// Where a tier's service units go; None when the invoking user has no home.
+fn unit_dir(ctx: &SyncContext, apps_root: &Path, tier: &Tier) -> Option<systemd::UnitDir> {
+    let (dir, scope) = match tier {
+        Tier::System => (ctx.paths.systemd_system_dir.clone(), systemd::Scope::System),
+        Tier::User(u) if ctx.is_root => (
+            apps_root.parent()?.join(".config/systemd/user"),
+            systemd::Scope::UserOf(u.clone()),
+        ),
+        Tier::User(_) => (ctx.paths.user_units.clone()?, systemd::Scope::User),
+    };
+    Some(systemd::UnitDir {
+        dir,
+        scope,
+        prefix: tier.prefix(ctx.paths).to_string(),
+    })
+}
+
+/// Profile name for an app in a tier; None when not root (profiles are root-only).
+fn profile_name_for(paths: &Paths, tier: &Tier, name: &str, is_root: bool) -> Option<String> {
+    is_root.then(|| paths.profile_name(tier.username(), name))
+}
+
+/// Which of an app's generated outputs differ from what is on disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Stale {
+    desktop: bool,
+    profile: bool,
+    /// A menu entry exists already (the change is an update, not an install).
+    had_desktop: bool,
+    /// Service unit files to write or remove.
+    units: Vec<PathBuf>,
+    /// Service units exist already.
+    had_units: bool,
+    /// Files from the bundle's share/ to write or remove.
+    integration: Vec<PathBuf>,
+}
+
+/// Compare an app's generated outputs with what is installed. `desktop_content` is None for a
+/// service and `profile_content` None when the app runs unconfined (an existing entry or profile
+/// would be removed).
+fn stale_outputs(
+    paths: &Paths,
+    desktop_dir: &Path,
+    app: &str,
+    as_user: Option<&str>,
+    desktop_content: Option<&str>,
+    profile_name: Option<&str>,
+    profile_content: Option<&str>,
+) -> Result<Stale> {
+    let existing = desktop::read_desktop(paths, desktop_dir, app, as_user)?;
+    Ok(Stale {
+        desktop: existing.as_deref() != desktop_content,
+        profile: profile_name
+            .is_some_and(|p| apparmor::installed_profile(paths, p).as_deref() != profile_content),
+        had_desktop: existing.is_some(),
+        units: Vec::new(),
+        had_units: false,
+        integration: Vec::new(),
+    })
+}
+
+impl Stale {
+    /// Compare the app's service units in `dir` with `wanted`.
+    fn compare_units(
+        &mut self,
+        dir: &systemd::UnitDir,
+        app: &str,
+        wanted: &[systemd::Unit],
+    ) -> Result<()> {
+        let installed = dir.installed(app)?;
+        self.had_units = !installed.is_empty();
+        let changed = wanted.iter().filter(|u| !installed.contains(u));
+        let unwanted = installed
+            .iter()
+            .filter(|u| !wanted.iter().any(|w| w.name == u.name));
+        self.units = changed
+            .chain(unwanted)
+            .map(|u| dir.dir.join(&u.name))
+            .collect();
+        Ok(())
+    }
+
+    /// The change to report; None when every output is current.
+    fn change(
+        &self,
+        locations: &Paths,
+        desktop_dir: &Path,
+        app: &str,
+        profile_name: Option<&str>,
+    ) -> Option<Change> {
+        let mut paths = Vec::new();
+        if self.desktop {
+            paths.push(desktop_dir.join(locations.desktop_file_name(desktop_dir, app)));
+        }
+        paths.extend(self.units.iter().cloned());
+        paths.extend(self.integration.iter().cloned());
+        if self.profile {
+            paths.extend(profile_name.map(|p| locations.profile_path(p)));
+        }
+        if paths.is_empty() {
+            return None;
+        }
+        let action = if self.had_desktop || self.had_units {
+            Action::Update
+        } else {
+            Action::Install
         };
-        apparmor::unload_profile(&profile_name)?;
+        Some(Change {
+            action,
+            app: app.to_string(),
+            paths,
+        })
+    }
+}
+
+/// Remove an app's menu entry, then its profile (reverse of install order).
+fn remove_outputs(
+    paths: &Paths,
+    desktop_dir: &Path,
+    name: &str,
+    as_user: Option<&str>,
+    profile_name: Option<&str>,
+) -> Result<()> {
+    desktop::uninstall_desktop(paths, desktop_dir, name, as_user)?;
+    if let Some(profile_name) = profile_name {
+        apparmor::unload_profile(paths, profile_name)?;
     }
     Ok(())
 }
+
+/// Clean up after a sync that died mid-operation: remove the outputs of the app it was working
+/// on. A half-done install is rolled back (this pass reinstalls it if the bundle is still there);
+/// a half-done uninstall is finished.
+fn recover_interrupted(paths: &Paths, journal: &journal::Journal) -> Result<()> {
+    for entry in journal.pending()? {
+        warn!(
+            app = %entry.app,
+            op = ?entry.op,
+            "previous sync was interrupted; cleaning up its partial changes"
+        );
+        // Best effort: a cleanup that keeps failing must not block every future sync.
+        if let Err(e) = remove_outputs(
+            paths,
+            &entry.desktop_dir,
+            &entry.app,
+            entry.user.as_deref(),
+            entry.profile.as_deref(),
+        ) {
+            warn!(app = %entry.app, "could not clean up interrupted sync: {}", e);
+        }
+    }
+    journal.commit()
+}
+
+/// Findings of the [scan] checks for a bundle, from the cached verdict while the bundle is
+/// unchanged. A scan that fails counts as a finding (fail closed) and is retried next sync.
+fn scan_bundle(ctx: &mut SyncContext, dir: &Path, exclude: &[String]) -> Vec<String> {
+    let key = dir.display().to_string();
+    let fingerprint = scan::fingerprint(&ctx.settings.scan, dir, exclude);
+    if let Some(record) = ctx
+        .state
+        .scans
+        .get(&key)
+        .filter(|r| r.fingerprint == fingerprint)
+    {
+        return record.findings.clone();
+    }
+    match scan::scan(&ctx.settings.scan, dir) {
+        Ok(findings) => {
+            if !ctx.dry_run {
+                let record = state::ScanRecord {
+                    fingerprint,
+                    findings: findings.clone(),
+                };
+                ctx.state.scans.insert(key, record);
+            }
+            findings
+        }
+        Err(e) => {
+            warn!(bundle = %dir.display(), "scan failed: {:#}", e);
+            vec![format!("scan failed: {:#}", e)]
+        }
+    }
+}
+
+/// Refuse a bundle: record it in state and tell its user once (not on every sync).
+fn quarantine_bundle(
+    ctx: &mut SyncContext,
+    dir: &Path,
+    app: &str,
+    username: Option<&str>,
+    reasons: Vec<String>,
+    hint: &'static str,
+) {
+    if ctx.dry_run {
+        info!(bundle = %dir.display(), reasons = %reasons.join("; "), "would quarantine");
+        return;
+    }
+    warn!(bundle = %dir.display(), reasons = %reasons.join("; "), "quarantined bundle (not installed)");
+    let entry = state::Quarantine {
+        app: Some(app.to_string()),
+        user: username.map(str::to_string),
+        reasons: reasons.clone(),
+        since: state::now(),
+    };
+    // Notifications go through runuser, so only the root daemon can send them.
+    let notify = ctx.state.quarantine(dir, entry) && ctx.is_root;
+    if let (true, Some(username)) = (notify, username) {
+        // In the user's language, not the service's.
+        let lang = i18n::Lang::of_user(username);
+        let body = i18n::tr_in(
+            &lang,
+            "quarantine-body",
+            &[
+                ("bundle", dir.display().to_string()),
+                ("reasons", reasons.join("; ")),
+                ("hint", i18n::tr_in(&lang, hint, &[])),
+            ],
+        );
+        let title = i18n::tr_in(&lang, "quarantine-title", &[("app", app.to_string())]);
+        if let Err(e) = desktop::notify_user(ctx.paths, username, &title, &body) {
+            warn!(user = %username, "could not notify user: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recover_interrupted_rolls_back_half_install() {
+        let state_dir = tempfile::tempdir().unwrap();
+        let desktop_dir = tempfile::tempdir().unwrap();
+        let entry_path = desktop_dir.path().join("dotlnx-myapp.desktop");
+        std::fs::write(&entry_path, "[Desktop Entry]\n").unwrap();
+        let j = journal::Journal::new(state_dir.path());
+        j.begin(&journal::Entry {
+            op: journal::Op::Install,
+            app: "myapp".into(),
+            desktop_dir: desktop_dir.path().to_path_buf(),
+            user: None,
+            profile: None,
+            started: 0,
+        })
+        .unwrap();
+        recover_interrupted(&Paths::under(state_dir.path()), &j).unwrap();
+        assert!(!entry_path.exists());
+        assert!(j.pending().unwrap().is_empty());
+    }
+
+    #[test]
+    fn bounded_jobs_limit_concurrency_and_stop_on_cancel() {
+        let items: Vec<usize> = (0..20).collect();
+        let (active, peak, done) = (
+            AtomicUsize::new(0),
+            AtomicUsize::new(0),
+            AtomicUsize::new(0),
+        );
+        for_each_bounded(&items, 3, &Control::default(), |_| {
+            let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            active.fetch_sub(1, Ordering::SeqCst);
+            done.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!(done.load(Ordering::SeqCst), 20);
+        assert!(peak.load(Ordering::SeqCst) <= 3);
+
+        let control = Control::default();
+        let started = AtomicUsize::new(0);
+        for_each_bounded(&items, 2, &control, |_| {
+            if started.fetch_add(1, Ordering::SeqCst) == 4 {
+                control.cancel();
+            }
+        });
+        assert!(started.load(Ordering::SeqCst) < 20);
+    }
+
+    #[test]
+    fn control_supersedes_only_visited_bundles() {
+        let control = Control::default();
+        let (x, y) = (Path::new("/a/x.lnx"), Path::new("/a/y.lnx"));
+        // Not read yet: the running sync picks up the change by itself.
+        assert!(!control.supersede(x));
+        assert!(!control.is_superseded(x));
+        control.visit(x);
+        control.visit(y);
+        assert!(control.supersede(x));
+        assert!(control.is_superseded(x));
+        assert!(!control.is_superseded(y));
+        assert!(!control.is_cancelled());
+        control.cancel();
+        assert!(control.is_cancelled());
+    }
+
+    fn diff_outputs(
+        paths: &Paths,
+        desktop_dir: &Path,
+        app: &str,
+        as_user: Option<&str>,
+        desktop_content: &str,
+        profile_name: Option<&str>,
+        profile_content: Option<&str>,
+    ) -> Result<Option<Change>> {
+        let stale = stale_outputs(
+            paths,
+            desktop_dir,
+            app,
+            as_user,
+            Some(desktop_content),
+            profile_name,
+            profile_content,
+        )?;
+        Ok(stale.change(paths, desktop_dir, app, profile_name))
+    }
+
+    #[test]
+    fn diff_outputs_reports_install_update_and_unchanged() {
+        let desktop_dir = tempfile::tempdir().unwrap();
+        let paths = Paths::under(desktop_dir.path());
+        let change = diff_outputs(&paths, desktop_dir.path(), "myapp", None, "A", None, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(change.action, Action::Install);
+        assert_eq!(
+            change.paths,
+            vec![desktop_dir.path().join("dotlnx-myapp.desktop")]
+        );
+
+        std::fs::write(desktop_dir.path().join("dotlnx-myapp.desktop"), "A").unwrap();
+        assert!(
+            diff_outputs(&paths, desktop_dir.path(), "myapp", None, "A", None, None)
+                .unwrap()
+                .is_none()
+        );
+        let change = diff_outputs(&paths, desktop_dir.path(), "myapp", None, "B", None, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(change.action, Action::Update);
+    }
+
+    #[test]
+    fn stale_profile_alone_is_an_update_of_the_profile() {
+        let stale = Stale {
+            desktop: false,
+            profile: true,
+            had_desktop: true,
+            units: Vec::new(),
+            had_units: false,
+            integration: Vec::new(),
+        };
+        let paths = Paths::under(Path::new("/srv/img"));
+        let change = stale
+            .change(&paths, Path::new("/apps"), "myapp", Some("dotlnx-myapp"))
+            .unwrap();
+        assert_eq!(change.action, Action::Update);
+        assert_eq!(
+            change.paths,
+            vec![PathBuf::from(
+                "/srv/img/etc/apparmor.d/dotlnx.d/dotlnx-myapp"
+            )]
+        );
+        let current = Stale {
+            profile: false,
+            ..stale
+        };
+        assert!(current
+            .change(&paths, Path::new("/apps"), "myapp", Some("dotlnx-myapp"))
+            .is_none());
+    }
+}
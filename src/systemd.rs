@@ -0,0 +1,526 @@
+//! systemd units for service bundles (`type = "service"`). Sync writes a unit for each service
+//! bundle instead of a menu entry: in /etc/systemd/system for the system tier, in the user's
+//! ~/.config/systemd/user for a user tier (written as that user). The unit runs the same command
+//! line a menu entry would (under `aa-exec` when confined, or `dotlnx run` when a launch setting
+//! needs the runner), and sync enables and (re)starts it with systemctl. When the bundle goes
+//! away, its units are stopped, disabled and deleted.
+//!
+//! A service with a [schedule] is a periodic job instead: its .service runs once per start
+//! (Type=oneshot) and is not enabled itself; a .timer with the schedule is enabled and started.
+//! Likewise a service with [service] listen sockets gets a .socket unit that is enabled instead,
+//! so systemd starts the service on the first connection and nothing of it runs while idle.
+//!
+//! Every unit starts with a header naming its app. That is how sync tells its own units from
+//! others' with the same prefix, and finds the app of a unit whose bundle is gone.
+
+use anyhow::Result;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+use crate::asuser;
+use crate::config::{Config, Kind};
+use crate::paths::Paths;
+
+/// First line of every generated unit, followed by the app name.
+const HEADER: &str = "# Generated by dotlnx for ";
+/// Unit types sync generates, in the order they are written.
+const SUFFIXES: &[&str] = &["service", "timer", "socket"];
+
+/// Which service manager reads a unit directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Scope {
+    /// The system manager (system tier).
+    System,
+    /// The invoking user's manager.
+    User,
+    /// A user's manager, driven by root for that user's tier.
+    UserOf(String),
+}
+
+/// A tier's unit directory.
+#[derive(Debug, Clone)]
+pub struct UnitDir {
+    pub dir: PathBuf,
+    pub scope: Scope,
+    /// Start of unit names (the tier's prefix).
+    pub prefix: String,
+}
+
+/// One generated unit file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unit {
+    pub name: String,
+    pub contents: String,
+    /// Enabled and started by sync (the others are pulled in by it).
+    pub enable: bool,
+}
+
+impl UnitDir {
+    fn as_user(&self) -> Option<&str> {
+        match &self.scope {
+            Scope::UserOf(u) => Some(u),
+            _ => None,
+        }
+    }
+
+    /// File name of an app's unit of type `suffix` (e.g. dotlnx-MyApp.service).
+    pub fn unit_name(&self, app: &str, suffix: &str) -> String {
+        format!(
+            "{}.{}",
+            crate::apparmor::profile_name_system(&self.prefix, app),
+            suffix
+        )
+    }
+
+    fn read(&self, name: &str) -> Result<Option<String>> {
+        let req = asuser::Request::ReadFile {
+            dir: self.dir.clone(),
+            name: name.to_string(),
+        };
+        asuser::perform(self.as_user(), &req)
+    }
+
+    /// The app's units present now (only ones dotlnx generated).
+    pub fn installed(&self, app: &str) -> Result<Vec<Unit>> {
+        let mut units = Vec::new();
+        for suffix in SUFFIXES {
+            let name = self.unit_name(app, suffix);
+            if let Some(contents) = self.read(&name)? {
+                if app_of(&contents) == Some(app) {
+                    let enable = contents.contains("\n[Install]\n");
+                    units.push(Unit {
+                        name,
+                        contents,
+                        enable,
+                    });
+                }
+            }
+        }
+        Ok(units)
+    }
+
+    /// Apps with generated units in this directory.
+    pub fn apps(&self) -> Result<BTreeSet<String>> {
+        let mut apps = BTreeSet::new();
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return Ok(apps);
+        };
+        for entry in entries {
+            let name = entry?.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            let ours = name.starts_with(&self.prefix)
+                && SUFFIXES
+                    .iter()
+                    .any(|s| name.strip_suffix(s).is_some_and(|n| n.ends_with('.')));
+            if !ours {
+                continue;
+            }
+            if let Some(app) = self.read(name)?.as_deref().and_then(app_of) {
+                apps.insert(app.to_string());
+            }
+        }
+        Ok(apps)
+    }
+
+    /// Run `systemctl ARGS` against this directory's manager. Best effort: without systemctl
+    /// (images, containers) or a reachable manager the units take effect at the next boot or
+    /// login, so failures are logged, not returned.
+    pub fn systemctl(&self, paths: &Paths, args: &[&str]) {
+        if !paths.systemctl.exists() {
+            debug!(systemctl = %paths.systemctl.display(), "systemctl not found; not reloading units");
+            return;
+        }
+        let mut cmd = std::process::Command::new(&paths.systemctl);
+        match &self.scope {
+            Scope::System => {}
+            Scope::User => {
+                cmd.arg("--user");
+            }
+            Scope::UserOf(u) => {
+                cmd.args(["--user", "--machine", &format!("{}@.host", u)]);
+            }
+        }
+        cmd.args(args);
+        match cmd.output() {
+            Ok(out) if out.status.success() => {}
+            Ok(out) => warn!(
+                args = ?args,
+                "systemctl failed ({}): {}",
+                out.status,
+                String::from_utf8_lossy(&out.stderr).trim()
+            ),
+            Err(e) => warn!(args = ?args, "could not run systemctl: {}", e),
+        }
+    }
+}
+
+/// App named in a generated unit's header.
+fn app_of(contents: &str) -> Option<&str> {
+    contents.lines().next()?.strip_prefix(HEADER)
+}
+
+/// Units a bundle needs in `dir`: none for an app, a .service for a service, plus a .timer for a
+/// scheduled one or a .socket for one with listen sockets. `argv` is the command that starts it (see [`crate::desktop::launch_argv`]).
+pub fn generate(
+    config: &Config,
+    bundle_root: &Path,
+    argv: &[String],
+    dir: &UnitDir,
+) -> Result<Vec<Unit>> {
+    if config.kind != Kind::Service {
+        return Ok(Vec::new());
+    }
+    let bundle = value(&bundle_root.display().to_string())?;
+    let cwd = config
+        .working_dir
+        .as_ref()
+        .map(|d| bundle_root.join(d))
+        .unwrap_or_else(|| bundle_root.to_path_buf());
+    let service = config.service.as_ref();
+    let header = format!(
+        "{}{}\n# From {}. Changes are overwritten by the next sync.\n",
+        HEADER,
+        config.app_id(),
+        bundle
+    );
+    let description = value(config.comment.as_deref().unwrap_or(&config.name))?;
+    let mut s = header.clone();
+    s.push_str("[Unit]\n");
+    s.push_str(&format!("Description={}\n", description));
+    s.push_str("\n[Service]\n");
+    if config.schedule.is_some() {
+        s.push_str("Type=oneshot\n");
+    }
+    let exec: Vec<String> = argv.iter().map(|a| quote(a)).collect();
+    s.push_str(&format!("ExecStart={}\n", exec.join(" ")));
+    s.push_str(&format!(
+        "WorkingDirectory={}\n",
+        value(&cwd.display().to_string())?
+    ));
+    for var in config.site_env.iter().chain(&config.env) {
+        s.push_str(&format!("Environment={}\n", quote(var)));
+    }
+    if dir.scope == Scope::System {
+        if let Some(user) = service.and_then(|svc| svc.user.as_deref()) {
+            s.push_str(&format!("User={}\n", value(user)?));
+        }
+    }
+    let service_name = dir.unit_name(&config.app_id(), "service");
+    let listen = service.map(|svc| &svc.listen[..]).unwrap_or_default();
+    let Some(schedule) = &config.schedule else {
+        let restart = service.map(|svc| svc.restart).unwrap_or_default();
+        s.push_str(&format!("Restart={}\n", restart.as_str()));
+        if !listen.is_empty() {
+            let mut socket = header;
+            socket.push_str("[Unit]\n");
+            socket.push_str(&format!("Description=Sockets of {}\n", description));
+            socket.push_str("\n[Socket]\n");
+            for l in listen {
+                socket.push_str(&format!("ListenStream={}\n", value(&l.to_systemd())?));
+            }
+            socket.push_str("\n[Install]\nWantedBy=sockets.target\n");
+            return Ok(vec![
+                Unit {
+                    name: service_name,
+                    contents: s,
+                    enable: false,
+                },
+                Unit {
+                    name: dir.unit_name(&config.app_id(), "socket"),
+                    contents: socket,
+                    enable: true,
+                },
+            ]);
+        }
+        let target = match dir.scope {
+            Scope::System => "multi-user.target",
+            Scope::User | Scope::UserOf(_) => "default.target",
+        };
+        s.push_str(&format!("\n[Install]\nWantedBy={}\n", target));
+        return Ok(vec![Unit {
+            name: service_name,
+            contents: s,
+            enable: true,
+        }]);
+    };
+    let mut timer = header;
+    timer.push_str("[Unit]\n");
+    timer.push_str(&format!("Description=Schedule of {}\n", description));
+    timer.push_str("\n[Timer]\n");
+    timer.push_str(&format!(
+        "OnCalendar={}\n",
+        value(schedule.on_calendar.trim())?
+    ));
+    timer.push_str(&format!("Persistent={}\n", schedule.persistent));
+    timer.push_str("\n[Install]\nWantedBy=timers.target\n");
+    Ok(vec![
+        Unit {
+            name: service_name,
+            contents: s,
+            enable: false,
+        },
+        Unit {
+            name: dir.unit_name(&config.app_id(), "timer"),
+            contents: timer,
+            enable: true,
+        },
+    ])
+}
+
+/// A setting value written as is: specifiers escaped, no control characters.
+pub fn value(s: &str) -> Result<String> {
+    if s.contains(|c: char| c.is_control()) {
+        anyhow::bail!(
+            "{:?} contains control characters and cannot be written to a unit",
+            s
+        );
+    }
+    Ok(s.replace('%', "%%"))
+}
+
+/// One ExecStart/Environment word: double-quoted with C escapes, specifiers and variables escaped.
+pub fn quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '%' => out.push_str("%%"),
+            '$' => out.push_str("$$"),
+            '\n' => out.push_str("\\n"),
+            c if c.is_control() => out.push_str(&format!("\\x{:02x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Make an app's units in `dir` match `units`: stop and delete the ones no longer wanted, write
+/// changed ones, reload the manager, then enable and restart the ones to enable. A changed
+/// socket-activated service that is running is restarted too, so it picks up the new bundle.
+pub fn apply(paths: &Paths, dir: &UnitDir, app: &str, units: &[Unit]) -> Result<()> {
+    let installed = dir.installed(app)?;
+    let unwanted: Vec<&str> = installed
+        .iter()
+        .filter(|u| !units.iter().any(|w| w.name == u.name))
+        .map(|u| u.name.as_str())
+        .collect();
+    // Units that were enabled and no longer should be (a service that got a schedule).
+    let demoted: Vec<&str> = installed
+        .iter()
+        .filter(|u| u.enable && units.iter().any(|w| w.name == u.name && !w.enable))
+        .map(|u| u.name.as_str())
+        .collect();
+    if !unwanted.is_empty() || !demoted.is_empty() {
+        let mut args = vec!["disable", "--now"];
+        args.extend(unwanted.iter().chain(&demoted));
+        dir.systemctl(paths, &args);
+        for name in &unwanted {
+            remove_file(dir, name)?;
+        }
+    }
+    let activated = units.iter().any(|u| u.name.ends_with(".socket"));
+    let changed: Vec<&Unit> = units.iter().filter(|u| !installed.contains(u)).collect();
+    for unit in &changed {
+        let req = asuser::Request::WriteFile {
+            dir: dir.dir.clone(),
+            name: unit.name.clone(),
+            contents: unit.contents.clone(),
+            create_dir: true,
+        };
+        asuser::perform(dir.as_user(), &req)?;
+    }
+    dir.systemctl(paths, &["daemon-reload"]);
+    for unit in units.iter().filter(|u| u.enable) {
+        dir.systemctl(paths, &["enable", &unit.name]);
+        dir.systemctl(paths, &["restart", &unit.name]);
+    }
+    if activated {
+        for unit in changed
+            .iter()
+            .filter(|u| !u.enable && u.name.ends_with(".service"))
+        {
+            dir.systemctl(paths, &["try-restart", &unit.name]);
+        }
+    }
+    Ok(())
+}
+
+/// Stop, disable and delete all of an app's units in `dir`. Returns the files removed.
+pub fn remove(paths: &Paths, dir: &UnitDir, app: &str) -> Result<Vec<PathBuf>> {
+    let installed = dir.installed(app)?;
+    if installed.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut args = vec!["disable", "--now"];
+    args.extend(installed.iter().map(|u| u.name.as_str()));
+    dir.systemctl(paths, &args);
+    for unit in &installed {
+        remove_file(dir, &unit.name)?;
+    }
+    dir.systemctl(paths, &["daemon-reload"]);
+    Ok(installed.iter().map(|u| dir.dir.join(&u.name)).collect())
+}
+
+fn remove_file(dir: &UnitDir, name: &str) -> Result<()> {
+    let req = asuser::Request::RemoveFile {
+        dir: dir.dir.clone(),
+        name: name.to_string(),
+    };
+    asuser::perform(dir.as_user(), &req)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_dir(scope: Scope) -> UnitDir {
+        UnitDir {
+            dir: PathBuf::from("/etc/systemd/system"),
+            scope,
+            prefix: "dotlnx-".into(),
+        }
+    }
+
+    fn service(extra: &str) -> Config {
+        crate::config::parse(&format!(
+            "name = \"My Svc\"\nexecutable = \"bin/svc\"\ntype = \"service\"\nenv = [\"A=1 2\"]\n{}",
+            extra
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn service_unit_runs_the_launch_command() {
+        let cfg = service("[service]\nuser = \"svc\"\nrestart = \"always\"\n");
+        let argv = vec![
+            "aa-exec".into(),
+            "-p".into(),
+            "dotlnx-My_Svc".into(),
+            "--".into(),
+            "/Applications/S.lnx/bin/svc".into(),
+            "100%".into(),
+        ];
+        let units = generate(
+            &cfg,
+            Path::new("/Applications/S.lnx"),
+            &argv,
+            &unit_dir(Scope::System),
+        )
+        .unwrap();
+        assert_eq!(units.len(), 1);
+        let unit = &units[0];
+        assert!(
+            unit.name.starts_with("dotlnx-My_Svc.") && unit.name.ends_with(".service"),
+            "{}",
+            unit.name
+        );
+        assert_eq!(app_of(&unit.contents), Some("My Svc"));
+        assert!(unit.contents.contains(
+            "ExecStart=\"aa-exec\" \"-p\" \"dotlnx-My_Svc\" \"--\" \"/Applications/S.lnx/bin/svc\" \"100%%\"\n"
+        ));
+        assert!(unit.contents.contains("Environment=\"A=1 2\"\n"));
+        assert!(unit.contents.contains("User=svc\nRestart=always\n"));
+        assert!(unit
+            .contents
+            .ends_with("[Install]\nWantedBy=multi-user.target\n"));
+
+        // User managers cannot switch users.
+        let units = generate(
+            &cfg,
+            Path::new("/home/u/Applications/S.lnx"),
+            &argv,
+            &unit_dir(Scope::User),
+        )
+        .unwrap();
+        assert!(!units[0].contents.contains("User="));
+        assert!(units[0].contents.ends_with("WantedBy=default.target\n"));
+    }
+
+    #[test]
+    fn scheduled_service_is_started_by_a_timer() {
+        let cfg = service("[schedule]\non_calendar = \"Mon *-*-* 03:00\"\npersistent = false\n");
+        let units = generate(
+            &cfg,
+            Path::new("/home/u/Applications/S.lnx"),
+            &["/x".into()],
+            &unit_dir(Scope::User),
+        )
+        .unwrap();
+        let [service, timer] = &units[..] else {
+            panic!("{:?}", units);
+        };
+        assert!(!service.enable && timer.enable);
+        assert!(
+            timer.name.ends_with(".timer")
+                && timer
+                    .name
+                    .starts_with(service.name.trim_end_matches("service"))
+        );
+        assert!(service.contents.contains("Type=oneshot\n"));
+        assert!(!service.contents.contains("[Install]") && !service.contents.contains("Restart="));
+        assert_eq!(app_of(&timer.contents), Some("My Svc"));
+        assert!(timer
+            .contents
+            .contains("OnCalendar=Mon *-*-* 03:00\nPersistent=false\n"));
+        assert!(timer.contents.ends_with("WantedBy=timers.target\n"));
+    }
+
+    #[test]
+    fn listen_sockets_activate_the_service() {
+        let cfg = service("[service]\nlisten = [\"127.0.0.1:8080\", \"/run/my%.sock\"]\n");
+        let units = generate(
+            &cfg,
+            Path::new("/Applications/S.lnx"),
+            &["/x".into()],
+            &unit_dir(Scope::System),
+        )
+        .unwrap();
+        let [service, socket] = &units[..] else {
+            panic!("{:?}", units);
+        };
+        assert!(!service.enable && socket.enable);
+        assert!(
+            socket.name.ends_with(".socket")
+                && socket
+                    .name
+                    .starts_with(service.name.trim_end_matches("service"))
+        );
+        assert!(
+            !service.contents.contains("[Install]")
+                && service.contents.contains("Restart=on-failure\n")
+        );
+        assert_eq!(app_of(&socket.contents), Some("My Svc"));
+        assert!(socket
+            .contents
+            .contains("ListenStream=127.0.0.1:8080\nListenStream=/run/my%%.sock\n"));
+        assert!(socket.contents.ends_with("WantedBy=sockets.target\n"));
+    }
+
+    #[test]
+    fn apps_get_no_units() {
+        let cfg = crate::config::parse("name = \"a\"\nexecutable = \"a\"\n").unwrap();
+        assert!(generate(
+            &cfg,
+            Path::new("/x"),
+            &["/x/a".into()],
+            &unit_dir(Scope::System)
+        )
+        .unwrap()
+        .is_empty());
+    }
+
+    #[test]
+    fn quoting_escapes_specifiers_and_variables() {
+        assert_eq!(
+            quote("a \"b\" $HOME %h\\"),
+            "\"a \\\"b\\\" $$HOME %%h\\\\\""
+        );
+        assert!(value("a\nb").is_err());
+    }
+}
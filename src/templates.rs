@@ -0,0 +1,247 @@
+//! Site templates (`/etc/dotlnx/templates.d`, or [templates] dir): snippets added to every
+//! generated menu entry and profile, so fleet tooling (asset inventories, monitoring agents) can
+//! recognise dotlnx-managed apps the same way on every machine.
+//!
+//! `*.desktop` files hold `X-` keys for the [Desktop Entry] group; `*.apparmor` files hold rules
+//! (or comments) for the app's profile, checked like base policy fragments. Files are applied in
+//! file name order. `%{name}`, `%{bundle}` and `%{profile}` in either are replaced with the app's
+//! name, bundle path and AppArmor profile name (empty when the app is unconfined).
+
+use anyhow::{Context, Result};
+use std::path::Path;
+
+use crate::desktop;
+use crate::policy;
+
+/// Where templates are read from unless [templates] dir says otherwise.
+pub const DEFAULT_DIR: &str = "/etc/dotlnx/templates.d";
+
+/// Placeholders a template may use.
+const PLACEHOLDERS: &[&str] = &["name", "bundle", "profile"];
+
+/// One template file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snippet {
+    /// File name, tagged on what it adds.
+    pub source: String,
+    /// Non-empty lines, placeholders not yet filled in.
+    pub lines: Vec<String>,
+}
+
+/// All templates of the site.
+#[derive(Debug, Clone, Default)]
+pub struct Templates {
+    pub desktop: Vec<Snippet>,
+    pub profile: Vec<Snippet>,
+}
+
+/// What the placeholders stand for in one app's outputs.
+pub struct Vars<'a> {
+    pub name: &'a str,
+    pub bundle: &'a Path,
+    pub profile: Option<&'a str>,
+}
+
+impl Vars<'_> {
+    /// `line` with the placeholders filled in.
+    fn fill(&self, line: &str) -> String {
+        let bundle = self.bundle.display().to_string();
+        line.replace("%{name}", self.name)
+            .replace("%{bundle}", &bundle)
+            .replace("%{profile}", self.profile.unwrap_or(""))
+    }
+}
+
+/// Refuse placeholders other than `PLACEHOLDERS`.
+fn check_placeholders(source: &str, number: usize, line: &str) -> Result<()> {
+    let mut rest = line;
+    while let Some(at) = rest.find("%{") {
+        let after = &rest[at + 2..];
+        let Some(end) = after.find('}') else {
+            anyhow::bail!("{}:{}: unterminated placeholder", source, number);
+        };
+        if !PLACEHOLDERS.contains(&&after[..end]) {
+            anyhow::bail!(
+                "{}:{}: unknown placeholder %{{{}}} (use %{{name}}, %{{bundle}} or %{{profile}})",
+                source,
+                number,
+                &after[..end]
+            );
+        }
+        rest = &after[end + 1..];
+    }
+    Ok(())
+}
+
+/// Parse a `*.desktop` template: `X-` keys and comments only, so a template cannot change how
+/// the app is launched or shown.
+pub fn parse_desktop(source: &str, text: &str) -> Result<Snippet> {
+    let mut lines = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        check_placeholders(source, i + 1, line)?;
+        if !line.starts_with('#') {
+            let key = line
+                .split_once('=')
+                .map(|(k, _)| k.trim())
+                .unwrap_or_default();
+            let valid = key.len() > 2
+                && key.starts_with("X-")
+                && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+            if !valid {
+                anyhow::bail!(
+                    "{}:{}: templates may only add X- keys (X-Name=value) and comments",
+                    source,
+                    i + 1
+                );
+            }
+        }
+        lines.push(line.to_string());
+    }
+    Ok(Snippet {
+        source: source.to_string(),
+        lines,
+    })
+}
+
+/// Parse a `*.apparmor` template: rules held to the same checks as base policy fragments.
+pub fn parse_profile(source: &str, text: &str) -> Result<Snippet> {
+    for (i, line) in text.lines().enumerate() {
+        check_placeholders(source, i + 1, line)?;
+    }
+    let fragment = policy::parse_fragment(source, text)?;
+    Ok(Snippet {
+        source: fragment.source,
+        lines: fragment.rules,
+    })
+}
+
+/// Load the templates in `dir`. A missing directory means none.
+pub fn load(dir: &Path) -> Result<Templates> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Templates::default()),
+        Err(e) => return Err(e).with_context(|| format!("read {}", dir.display())),
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            p.is_file()
+                && !p
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| n.starts_with('.'))
+        })
+        .collect();
+    paths.sort();
+    let mut templates = Templates::default();
+    for path in paths {
+        let source = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default()
+            .to_string();
+        let (parse, list): (fn(&str, &str) -> Result<Snippet>, _) =
+            match path.extension().and_then(|e| e.to_str()) {
+                Some("desktop") => (parse_desktop, &mut templates.desktop),
+                Some("apparmor") => (parse_profile, &mut templates.profile),
+                _ => continue,
+            };
+        let text =
+            std::fs::read_to_string(&path).with_context(|| format!("read {}", path.display()))?;
+        list.push(parse(&source, &text)?);
+    }
+    Ok(templates)
+}
+
+impl Templates {
+    /// A generated menu entry with the desktop templates' keys appended (the generated entry is a
+    /// single [Desktop Entry] group). Values are escaped like the generated ones.
+    pub fn apply_desktop(&self, entry: String, vars: &Vars) -> String {
+        let mut out = entry;
+        for snippet in &self.desktop {
+            out.push_str(&format!("# site template: {}\n", snippet.source));
+            for line in &snippet.lines {
+                match line.split_once('=') {
+                    Some((key, value)) if !line.starts_with('#') => {
+                        out.push_str(&format!(
+                            "{}={}\n",
+                            key.trim(),
+                            desktop::escape_desktop_value(&vars.fill(value.trim()))
+                        ));
+                    }
+                    _ => out.push_str(&format!("{}\n", vars.fill(line).replace(['\n', '\r'], " "))),
+                }
+            }
+        }
+        out
+    }
+
+    /// Profile template lines for one app at `indent`, each file tagged with its source.
+    pub fn profile_lines(&self, vars: &Vars, indent: &str) -> Vec<String> {
+        let mut out = Vec::new();
+        for snippet in &self.profile {
+            out.push(format!("{}# site template: {}", indent, snippet.source));
+            out.extend(
+                snippet
+                    .lines
+                    .iter()
+                    .map(|line| format!("{}{}", indent, vars.fill(line))),
+            );
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_tag_entries_and_profiles() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("10-asset.desktop"),
+            "# fleet inventory\nX-Corp-Asset=dotlnx:%{name}\nX-Corp-Bundle = %{bundle}\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("20-audit.apparmor"),
+            "# agent tag: %{profile}\n/opt/agent/hook rix,\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("README"), "not a template").unwrap();
+        let templates = load(dir.path()).unwrap();
+        let vars = Vars {
+            name: "My App",
+            bundle: Path::new("/Applications/My App.lnx"),
+            profile: Some("dotlnx-my-app"),
+        };
+
+        let entry = templates.apply_desktop("[Desktop Entry]\nName=My App\n".into(), &vars);
+        assert_eq!(
+            entry,
+            "[Desktop Entry]\nName=My App\n# site template: 10-asset.desktop\n# fleet inventory\nX-Corp-Asset=dotlnx:My App\nX-Corp-Bundle=/Applications/My App.lnx\n"
+        );
+        assert_eq!(
+            templates.profile_lines(&vars, "  "),
+            [
+                "  # site template: 20-audit.apparmor",
+                "  # agent tag: dotlnx-my-app",
+                "  /opt/agent/hook rix,"
+            ]
+        );
+        assert!(load(&dir.path().join("missing"))
+            .unwrap()
+            .desktop
+            .is_empty());
+
+        assert!(parse_desktop("bad.desktop", "Exec=/bin/sh\n").is_err());
+        assert!(parse_desktop("bad.desktop", "X-Tag=%{user}\n").is_err());
+        assert!(parse_profile("bad.apparmor", "}\nprofile evil {\n").is_err());
+    }
+}
@@ -0,0 +1,534 @@
+//! `dotlnx tray`: a notification-area icon for desktop users, drawn by yad.
+//!
+//! The icon's tooltip shows how many apps are installed and when the last sync ran; the menu
+//! launches apps and offers quick actions (recent activity, sync now, open ~/Applications, logs).
+//! Status comes from the state.json the daemon (and user syncs) write after every sync. "Sync now"
+//! asks a running daemon for a sync by creating the watcher's request file in ~/Applications;
+//! without a daemon it syncs the user's bundles directly.
+
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::apparmor;
+use crate::bundle;
+use crate::config;
+use crate::paths;
+use crate::state::{self, Quarantine, RecordedChange, SyncRecord};
+use crate::sync;
+use crate::watch;
+
+/// How often the tray re-reads state and bundles.
+const POLL: Duration = Duration::from_secs(5);
+/// Recent changes shown in the activity window.
+const SHOWN_CHANGES: usize = 20;
+const ICON: &str = "system-software-install";
+const ICON_WARNING: &str = "dialog-warning";
+
+/// Actions run by the tray's menu entries (each in its own process, like any yad menu command).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Action {
+    /// Window with the last sync, recent changes and quarantined bundles.
+    Details,
+    /// Window with the daemon's journal.
+    Logs,
+    /// Ask the daemon for a sync (or sync directly when no daemon runs).
+    Sync,
+}
+
+/// An installed app as shown in the menu.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct App {
+    name: String,
+    system: bool,
+}
+
+/// What the tray shows for the current user.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Status {
+    apps: Vec<App>,
+    last_sync: Option<SyncRecord>,
+    /// This user's (and the system tier's) changes, oldest first.
+    changes: Vec<RecordedChange>,
+    /// (bundle path, quarantine) for this user's bundles.
+    quarantined: Vec<(String, Quarantine)>,
+}
+
+/// The user the tray runs for.
+struct Me {
+    name: String,
+    home: PathBuf,
+    apps_dir: PathBuf,
+    system_apps_dir: PathBuf,
+    /// Start of this user's profile names.
+    profile_prefix: String,
+}
+
+impl Me {
+    fn current() -> Result<Self> {
+        let user = nix::unistd::User::from_uid(nix::unistd::getuid())?
+            .ok_or_else(|| anyhow::anyhow!("current user not found"))?;
+        let paths = paths::load()?;
+        Ok(Me {
+            name: user.name,
+            home: user.dir,
+            apps_dir: paths.user_apps,
+            system_apps_dir: paths.system_apps,
+            profile_prefix: paths.user_prefix,
+        })
+    }
+}
+
+/// Entry point for `dotlnx tray`.
+pub fn run(action: Option<Action>) -> Result<()> {
+    if bundle::is_root() {
+        anyhow::bail!("run dotlnx tray as a desktop user, not root");
+    }
+    let me = Me::current()?;
+    match action {
+        None => serve(&me),
+        Some(Action::Details) => show_text(
+            "dotlnx: recent activity",
+            &details_text(&collect_status(&me)?, state::now()),
+        ),
+        Some(Action::Logs) => show_text("dotlnx: log", &daemon_log()),
+        Some(Action::Sync) => request_sync(&me),
+    }
+}
+
+/// Run the icon until yad exits (its Quit entry or the session ending).
+fn serve(me: &Me) -> Result<()> {
+    let exe = std::env::current_exe().context("locate dotlnx binary")?;
+    let exe = exe.display().to_string();
+    let details = quote(&exe).map(|e| format!("{} tray --action details", e));
+    let mut cmd = Command::new("yad");
+    cmd.args(["--notification", "--listen", "--text=dotlnx"])
+        .arg(format!("--image={}", ICON))
+        .stdin(Stdio::piped());
+    if let Some(ref details) = details {
+        cmd.arg(format!("--command={}", details));
+    }
+    let mut child = match cmd.spawn() {
+        Ok(c) => c,
+        Err(e) if yad_missing(&e) => {
+            anyhow::bail!("dotlnx tray needs yad (e.g. apt install yad / dnf install yad)")
+        }
+        Err(e) => return Err(e).context("start yad"),
+    };
+    let mut stdin = child.stdin.take().context("yad stdin")?;
+    let mut shown: Option<(String, String, String)> = None;
+    loop {
+        if child.try_wait()?.is_some() {
+            return Ok(());
+        }
+        let status = match collect_status(me) {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("could not read dotlnx status: {}", e);
+                Status::default()
+            }
+        };
+        let now = state::now();
+        let next = (
+            icon(&status).to_string(),
+            tooltip(&status, now),
+            menu(&status, &exe, &me.apps_dir),
+        );
+        // Relative times in the tooltip change every minute; only rewrite what differs.
+        if shown.as_ref() != Some(&next) {
+            let (icon, tooltip, menu) = &next;
+            let sent = writeln!(stdin, "icon:{}\ntooltip:{}\nmenu:{}", icon, tooltip, menu)
+                .and_then(|_| stdin.flush());
+            if sent.is_err() {
+                // yad went away between try_wait and the write.
+                return Ok(());
+            }
+            shown = Some(next);
+        }
+        std::thread::sleep(POLL);
+    }
+}
+
+/// Bundles, last sync and quarantines for `me`, from the daemon's state and the user's own.
+fn collect_status(me: &Me) -> Result<Status> {
+    let mut apps: Vec<App> = Vec::new();
+    for (root, system) in [
+        (me.apps_dir.clone(), false),
+        (me.system_apps_dir.clone(), true),
+    ] {
+        for dir in bundle::discover_lnx_dirs(&root) {
+            if let Ok(cfg) = config::load(&dir) {
+                if !apps.iter().any(|a| a.name == cfg.app_id()) {
+                    apps.push(App {
+                        name: cfg.app_id(),
+                        system,
+                    });
+                }
+            }
+        }
+    }
+    apps.sort_by_key(|a| a.name.to_lowercase());
+
+    let mut dirs = vec![PathBuf::from(state::SYSTEM_STATE_DIR)];
+    let own = state::state_dir()?;
+    if !dirs.contains(&own) {
+        dirs.push(own);
+    }
+    let system_names: Vec<&str> = apps
+        .iter()
+        .filter(|a| a.system)
+        .map(|a| a.name.as_str())
+        .collect();
+    let mut status = Status::default();
+    for dir in dirs {
+        // The daemon's state may be unreadable for users on locked-down hosts.
+        let Ok(st) = state::State::load(&dir) else {
+            continue;
+        };
+        if let Some(rec) = st.last_sync {
+            if status.last_sync.as_ref().is_none_or(|l| l.time < rec.time) {
+                status.last_sync = Some(rec);
+            }
+        }
+        status.changes.extend(
+            st.recent_changes
+                .into_iter()
+                .filter(|c| concerns(&c.change, me, &system_names)),
+        );
+        status
+            .quarantined
+            .extend(st.quarantined.into_iter().filter(|(path, q)| {
+                q.user.as_deref() == Some(&me.name) || Path::new(path).starts_with(&me.home)
+            }));
+    }
+    status.changes.sort_by_key(|c| c.time);
+    status.apps = apps;
+    Ok(status)
+}
+
+/// True when a change recorded by the daemon (which syncs every user) is about `me`:
+/// it touched their home, their profile, or a system-tier app they can see.
+fn concerns(change: &sync::Change, me: &Me, system_names: &[&str]) -> bool {
+    let profile = apparmor::profile_name_user(&me.profile_prefix, &me.name, &change.app);
+    system_names.contains(&change.app.as_str())
+        || change.paths.iter().any(|p| {
+            p.starts_with(&me.home) || p.file_name().is_some_and(|n| n == profile.as_str())
+        })
+}
+
+fn icon(status: &Status) -> &'static str {
+    if status.quarantined.is_empty() {
+        ICON
+    } else {
+        ICON_WARNING
+    }
+}
+
+/// One-line tooltip (yad's listen protocol is line based).
+fn tooltip(status: &Status, now: u64) -> String {
+    let mut parts = vec![format!("dotlnx: {} apps", status.apps.len())];
+    match &status.last_sync {
+        Some(rec) => parts.push(format!("last sync {}", ago(now, rec.time))),
+        None => parts.push("no sync yet".into()),
+    }
+    if !status.quarantined.is_empty() {
+        parts.push(format!("{} quarantined", status.quarantined.len()));
+    }
+    parts.join(" · ")
+}
+
+/// yad menu spec: `label!command!icon` entries separated by `|`; an empty entry is a separator.
+fn menu(status: &Status, exe: &str, apps_dir: &Path) -> String {
+    let mut items = Vec::new();
+    let exe = quote(exe);
+    for app in &status.apps {
+        let (Some(exe), Some(name)) = (&exe, quote(&app.name)) else {
+            continue; // `!` or `|` cannot be expressed in a yad menu entry
+        };
+        items.push(format!("{}!{} run {}", app.name, exe, name));
+    }
+    if !items.is_empty() {
+        items.push(String::new());
+    }
+    if let Some(exe) = &exe {
+        items.push(format!(
+            "Recent activity!{} tray --action details!document-open-recent",
+            exe
+        ));
+        items.push(format!("Sync now!{} tray --action sync!view-refresh", exe));
+    }
+    if let Some(dir) = quote(&apps_dir.display().to_string()) {
+        items.push(format!("Open Applications folder!xdg-open {}!folder", dir));
+    }
+    if let Some(exe) = &exe {
+        items.push(format!(
+            "Show log!{} tray --action logs!text-x-generic",
+            exe
+        ));
+    }
+    items.push(String::new());
+    items.push("Quit!quit!application-exit".into());
+    items.join("|")
+}
+
+/// Single-quote `s` for yad's command parsing (GLib shell rules). None when `s` contains a yad
+/// menu separator.
+fn quote(s: &str) -> Option<String> {
+    if s.contains(['!', '|', '\n']) {
+        return None;
+    }
+    Some(format!("'{}'", s.replace('\'', r"'\''")))
+}
+
+/// "just now", "5 min ago", "3 h ago", "2 days ago".
+fn ago(now: u64, then: u64) -> String {
+    let secs = now.saturating_sub(then);
+    match secs {
+        0..=59 => "just now".into(),
+        60..=3599 => format!("{} min ago", secs / 60),
+        3600..=86_399 => format!("{} h ago", secs / 3600),
+        _ => format!("{} days ago", secs / 86_400),
+    }
+}
+
+fn details_text(status: &Status, now: u64) -> String {
+    let mut out = String::new();
+    match &status.last_sync {
+        Some(rec) => out.push_str(&format!(
+            "Last sync {}: {} installed, {} skipped, {} quarantined\n",
+            ago(now, rec.time),
+            rec.installed,
+            rec.skipped,
+            rec.quarantined
+        )),
+        None => out.push_str("No sync has run yet.\n"),
+    }
+    out.push_str("\nRecent changes:\n");
+    if status.changes.is_empty() {
+        out.push_str("  none\n");
+    }
+    for c in status.changes.iter().rev().take(SHOWN_CHANGES) {
+        out.push_str(&format!(
+            "  {:<12} {:<8} {}\n",
+            ago(now, c.time),
+            c.change.action.as_str(),
+            c.change.app
+        ));
+    }
+    if !status.quarantined.is_empty() {
+        out.push_str("\nQuarantined (not installed until fixed):\n");
+        for (path, q) in &status.quarantined {
+            out.push_str(&format!("  {}: {}\n", path, q.reasons.join("; ")));
+        }
+    }
+    out
+}
+
+/// Show `text` in a yad window (stdout when yad is missing, e.g. run from a terminal).
+fn show_text(title: &str, text: &str) -> Result<()> {
+    let spawned = Command::new("yad")
+        .args([
+            "--text-info",
+            "--width=700",
+            "--height=450",
+            "--button=Close:0",
+        ])
+        .arg(format!("--title={}", title))
+        .stdin(Stdio::piped())
+        .spawn();
+    let mut child = match spawned {
+        Ok(c) => c,
+        Err(e) if yad_missing(&e) => {
+            print!("{}", text);
+            return Ok(());
+        }
+        Err(e) => return Err(e).context("start yad"),
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // yad may be closed before reading everything; that is not an error.
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    child.wait()?;
+    Ok(())
+}
+
+/// Spawning yad failed because it is not installed (PATH search ends in ENOENT, or EACCES when
+/// PATH holds a directory the user cannot search).
+fn yad_missing(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied
+    )
+}
+
+/// The daemon's recent journal, or a hint when it cannot be read.
+fn daemon_log() -> String {
+    let out = Command::new("journalctl")
+        .args([
+            "-u",
+            "dotlnx.service",
+            "-n",
+            "200",
+            "--no-pager",
+            "-o",
+            "short-iso",
+        ])
+        .output();
+    match out {
+        Ok(o) if o.status.success() && !o.stdout.is_empty() => {
+            String::from_utf8_lossy(&o.stdout).into_owned()
+        }
+        _ => "No dotlnx log available. Reading the service journal (journalctl -u dotlnx) needs \
+              membership in the systemd-journal or adm group.\n"
+            .into(),
+    }
+}
+
+/// Sync now: poke the daemon's watcher, or run a user sync when no daemon is running.
+fn request_sync(me: &Me) -> Result<()> {
+    if !watch::service_active() {
+        let summary = sync::run(false)?;
+        info!(
+            installed = summary.installed,
+            changes = summary.changes.len(),
+            "synced"
+        );
+        return Ok(());
+    }
+    if !me.apps_dir.is_dir() {
+        info!(dir = %me.apps_dir.display(), "no Applications folder; nothing to sync");
+        return Ok(());
+    }
+    let request = me.apps_dir.join(watch::SYNC_REQUEST);
+    std::fs::File::create(&request).with_context(|| format!("create {}", request.display()))?;
+    std::fs::remove_file(&request)?;
+    info!("sync requested from the dotlnx service");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::{Action as ChangeAction, Change};
+
+    fn me() -> Me {
+        Me {
+            name: "alice".into(),
+            home: PathBuf::from("/home/alice"),
+            apps_dir: PathBuf::from("/home/alice/Applications"),
+            system_apps_dir: PathBuf::from("/Applications"),
+            profile_prefix: "dotlnx-".into(),
+        }
+    }
+
+    fn change(app: &str, path: &str) -> Change {
+        Change {
+            action: ChangeAction::Install,
+            app: app.into(),
+            paths: vec![PathBuf::from(path)],
+        }
+    }
+
+    #[test]
+    fn quote_escapes_and_rejects_separators() {
+        assert_eq!(quote("My App").as_deref(), Some("'My App'"));
+        assert_eq!(quote("it's").as_deref(), Some(r"'it'\''s'"));
+        assert!(quote("a|b").is_none());
+        assert!(quote("hey!").is_none());
+    }
+
+    #[test]
+    fn menu_lists_apps_and_actions() {
+        let status = Status {
+            apps: vec![
+                App {
+                    name: "Editor".into(),
+                    system: false,
+                },
+                App {
+                    name: "Bad|Name".into(),
+                    system: true,
+                },
+            ],
+            ..Status::default()
+        };
+        let spec = menu(
+            &status,
+            "/usr/bin/dotlnx",
+            Path::new("/home/alice/Applications"),
+        );
+        let items: Vec<&str> = spec.split('|').collect();
+        assert_eq!(items[0], "Editor!'/usr/bin/dotlnx' run 'Editor'");
+        assert_eq!(items[1], "");
+        assert!(
+            items.contains(&"Open Applications folder!xdg-open '/home/alice/Applications'!folder")
+        );
+        assert_eq!(items.last(), Some(&"Quit!quit!application-exit"));
+        assert!(!spec.contains("Bad"));
+    }
+
+    #[test]
+    fn tooltip_and_icon_reflect_status() {
+        let mut status = Status {
+            apps: vec![App {
+                name: "A".into(),
+                system: false,
+            }],
+            last_sync: Some(SyncRecord {
+                time: 1000,
+                installed: 1,
+                skipped: 0,
+                quarantined: 0,
+            }),
+            ..Status::default()
+        };
+        assert_eq!(
+            tooltip(&status, 1300),
+            "dotlnx: 1 apps · last sync 5 min ago"
+        );
+        assert_eq!(icon(&status), ICON);
+        status.quarantined.push((
+            "/home/alice/Applications/B.lnx".into(),
+            Quarantine {
+                app: Some("B".into()),
+                user: Some("alice".into()),
+                reasons: vec!["world-writable".into()],
+                since: 0,
+            },
+        ));
+        assert!(tooltip(&status, 1000).ends_with("1 quarantined"));
+        assert_eq!(icon(&status), ICON_WARNING);
+        assert!(details_text(&status, 1000).contains("world-writable"));
+    }
+
+    #[test]
+    fn concerns_filters_other_users() {
+        let me = me();
+        assert!(concerns(
+            &change(
+                "A",
+                "/home/alice/.local/share/applications/dotlnx-A.desktop"
+            ),
+            &me,
+            &[]
+        ));
+        assert!(concerns(
+            &change("A", "/etc/apparmor.d/dotlnx.d/dotlnx-alice-A"),
+            &me,
+            &[]
+        ));
+        assert!(!concerns(
+            &change("A", "/home/bob/.local/share/applications/dotlnx-A.desktop"),
+            &me,
+            &[]
+        ));
+        assert!(concerns(
+            &change("Sys", "/usr/share/applications/dotlnx-Sys.desktop"),
+            &me,
+            &["Sys"]
+        ));
+    }
+}
@@ -0,0 +1,504 @@
+//! `dotlnx tui`: interactive terminal console for admins (e.g. over SSH on a kiosk).
+//!
+//! Lists every bundle dotlnx can see (user tier and /Applications) with its status, and shows
+//! the generated .desktop entry, AppArmor profile, validation result or service log in a side
+//! pane. Actions that change things (uninstall, sync) run the regular `dotlnx` subcommands as
+//! child processes, so they behave exactly like the CLI and their log lands in the pane instead
+//! of scribbling over the screen.
+
+use anyhow::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::Frame;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use crate::apparmor;
+use crate::bundle;
+use crate::config;
+use crate::desktop;
+use crate::paths::{self, Paths};
+use crate::state;
+
+const HELP: &str =
+    "↑↓ select  v validate  d desktop  p profile  r run  u uninstall  s sync  l log  \
+                    PgUp/PgDn scroll  R refresh  q quit";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Tier {
+    User(String),
+    System,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Installed,
+    NotInstalled,
+    Invalid,
+    Quarantined,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Status::Installed => "installed",
+            Status::NotInstalled => "not installed",
+            Status::Invalid => "invalid",
+            Status::Quarantined => "quarantined",
+        }
+    }
+}
+
+/// One bundle row.
+#[derive(Debug, Clone)]
+struct Entry {
+    /// App name from config.toml, or the bundle dir name when the config does not load.
+    name: String,
+    tier: Tier,
+    bundle: PathBuf,
+    desktop_dir: PathBuf,
+    status: Status,
+    /// Config error or quarantine reasons.
+    problem: Option<String>,
+}
+
+impl Entry {
+    fn tier_label(&self) -> String {
+        match &self.tier {
+            Tier::User(u) => format!("user:{}", u),
+            Tier::System => "system".into(),
+        }
+    }
+
+    fn profile_name(&self, paths: &Paths) -> String {
+        match &self.tier {
+            Tier::User(u) => paths.profile_name(Some(u), &self.name),
+            Tier::System => paths.profile_name(None, &self.name),
+        }
+    }
+
+    /// Read the user's files as that user when root (same rule as sync).
+    fn as_user(&self) -> Option<&str> {
+        match &self.tier {
+            Tier::User(u) if bundle::is_root() => Some(u.as_str()),
+            _ => None,
+        }
+    }
+}
+
+/// Every bundle in the tiers this user can manage (all users + system as root).
+fn collect_entries(paths: &Paths) -> Result<Vec<Entry>> {
+    let state = state::State::load(&state::state_dir()?).unwrap_or_default();
+    let mut tiers: Vec<(PathBuf, PathBuf, Tier)> = bundle::user_tier_entries(paths)?
+        .into_iter()
+        .map(|(apps, desktop_dir, user)| (apps, desktop_dir, Tier::User(user)))
+        .collect();
+    tiers.push((
+        paths.system_apps.clone(),
+        paths.system_desktop.clone(),
+        Tier::System,
+    ));
+    let mut entries = Vec::new();
+    for (apps_dir, desktop_dir, tier) in tiers {
+        for dir in bundle::discover_lnx_dirs(&apps_dir) {
+            let (name, mut status, mut problem) = match config::load(&dir) {
+                Ok(cfg) => (cfg.app_id(), Status::NotInstalled, None),
+                Err(e) => {
+                    let name = dir
+                        .file_stem()
+                        .map(|s| s.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                    (name, Status::Invalid, Some(e.to_string()))
+                }
+            };
+            if let Some(q) = state.quarantined.get(&dir.display().to_string()) {
+                status = Status::Quarantined;
+                problem = Some(q.reasons.join("; "));
+            } else if status == Status::NotInstalled
+                && desktop_dir
+                    .join(paths.desktop_file_name(&desktop_dir, &name))
+                    .exists()
+            {
+                status = Status::Installed;
+            }
+            entries.push(Entry {
+                name,
+                tier: tier.clone(),
+                bundle: dir,
+                desktop_dir: desktop_dir.clone(),
+                status,
+                problem,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// Console state: the bundle list and what the side pane shows.
+struct App {
+    paths: Paths,
+    entries: Vec<Entry>,
+    list: ListState,
+    pane_title: String,
+    pane: String,
+    scroll: u16,
+    /// Waiting for y/n before uninstalling the selected app.
+    confirm_uninstall: bool,
+    quit: bool,
+}
+
+impl App {
+    fn new(paths: Paths, entries: Vec<Entry>) -> Self {
+        let mut list = ListState::default();
+        list.select((!entries.is_empty()).then_some(0));
+        let mut app = App {
+            paths,
+            entries,
+            list,
+            pane_title: String::new(),
+            pane: String::new(),
+            scroll: 0,
+            confirm_uninstall: false,
+            quit: false,
+        };
+        app.show_summary();
+        app
+    }
+
+    fn selected(&self) -> Option<&Entry> {
+        self.list.selected().and_then(|i| self.entries.get(i))
+    }
+
+    fn set_pane(&mut self, title: impl Into<String>, text: impl Into<String>) {
+        self.pane_title = title.into();
+        self.pane = text.into();
+        self.scroll = 0;
+    }
+
+    /// Default pane: facts about the selected bundle.
+    fn show_summary(&mut self) {
+        let Some(e) = self.selected() else {
+            self.set_pane("dotlnx", "No .lnx bundles found.");
+            return;
+        };
+        let mut text = format!(
+            "Name:    {}\nTier:    {}\nBundle:  {}\nStatus:  {}\nProfile: {}\n",
+            e.name,
+            e.tier_label(),
+            e.bundle.display(),
+            e.status.as_str(),
+            e.profile_name(&self.paths)
+        );
+        if let Some(ref p) = e.problem {
+            text.push_str(&format!("\nProblem: {}\n", p));
+        }
+        let title = e.name.clone();
+        self.set_pane(title, text);
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        if self.entries.is_empty() {
+            return;
+        }
+        let len = self.entries.len() as isize;
+        let current = self.list.selected().unwrap_or(0) as isize;
+        self.list
+            .select(Some((current + delta).rem_euclid(len) as usize));
+        self.show_summary();
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        let keep = self.selected().map(|e| e.bundle.clone());
+        self.entries = collect_entries(&self.paths)?;
+        let index = keep
+            .and_then(|b| self.entries.iter().position(|e| e.bundle == b))
+            .or((!self.entries.is_empty()).then_some(0));
+        self.list.select(index);
+        Ok(())
+    }
+
+    fn handle_key(&mut self, key: KeyCode) -> Result<()> {
+        if self.confirm_uninstall {
+            self.confirm_uninstall = false;
+            if key == KeyCode::Char('y') {
+                if let Some(name) = self.selected().map(|e| e.name.clone()) {
+                    let out = run_dotlnx(&["uninstall", &name]);
+                    self.set_pane(format!("uninstall {}", name), out);
+                    self.refresh()?;
+                }
+            } else {
+                self.show_summary();
+            }
+            return Ok(());
+        }
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_add(10),
+            KeyCode::PageUp => self.scroll = self.scroll.saturating_sub(10),
+            KeyCode::Char('R') => {
+                self.refresh()?;
+                self.show_summary();
+            }
+            KeyCode::Char('s') => {
+                let out = run_dotlnx(&["sync"]);
+                self.set_pane("sync", out);
+                self.refresh()?;
+            }
+            KeyCode::Char('l') => self.set_pane("dotlnx.service log", service_log()),
+            KeyCode::Char(c) => {
+                let Some(e) = self.selected().cloned() else {
+                    return Ok(());
+                };
+                match c {
+                    'v' => {
+                        let bundle = e.bundle.display().to_string();
+                        let out = run_dotlnx(&["validate", &bundle]);
+                        self.set_pane(format!("validate {}", e.name), out);
+                    }
+                    'd' => {
+                        let text = match desktop::read_desktop(
+                            &self.paths,
+                            &e.desktop_dir,
+                            &e.name,
+                            e.as_user(),
+                        ) {
+                            Ok(Some(t)) => t,
+                            Ok(None) => "No menu entry installed.".into(),
+                            Err(err) => format!("could not read menu entry: {}", err),
+                        };
+                        self.set_pane(self.paths.desktop_file_name(&e.desktop_dir, &e.name), text);
+                    }
+                    'p' => {
+                        let profile = e.profile_name(&self.paths);
+                        let text = apparmor::installed_profile(&self.paths, &profile)
+                            .unwrap_or_else(|| "No AppArmor profile installed.".into());
+                        self.set_pane(profile, text);
+                    }
+                    'r' => {
+                        let text = match spawn_detached(&["run", &e.name]) {
+                            Ok(()) => format!("Started {}.", e.name),
+                            Err(err) => format!("could not start {}: {}", e.name, err),
+                        };
+                        self.set_pane(format!("run {}", e.name), text);
+                    }
+                    'u' => {
+                        self.confirm_uninstall = true;
+                        self.set_pane(
+                            format!("uninstall {}", e.name),
+                            format!(
+                                "Remove the menu entry and AppArmor profile for {}? (y/n)\n\n\
+                                 The bundle folder is kept; the next sync installs it again \
+                                 unless it is moved out of the Applications folder.",
+                                e.name
+                            ),
+                        );
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, help] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+        let [left, right] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(main);
+        let items: Vec<ListItem> = self
+            .entries
+            .iter()
+            .map(|e| {
+                ListItem::new(format!(
+                    "{:<24} {:<14} {}",
+                    e.name,
+                    e.tier_label(),
+                    e.status.as_str()
+                ))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::bordered().title(" Apps "))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, left, &mut self.list);
+        let pane = Paragraph::new(self.pane.as_str())
+            .block(Block::bordered().title(format!(" {} ", self.pane_title)))
+            .wrap(Wrap { trim: false })
+            .scroll((self.scroll, 0));
+        frame.render_widget(pane, right);
+        frame.render_widget(Line::from(HELP), help);
+    }
+}
+
+/// Entry point for `dotlnx tui`.
+pub fn run() -> Result<()> {
+    let paths = paths::load()?;
+    let entries = collect_entries(&paths)?;
+    let mut app = App::new(paths, entries);
+    let mut terminal = ratatui::init();
+    let result = (|| -> Result<()> {
+        while !app.quit {
+            terminal.draw(|f| app.draw(f))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    app.handle_key(key.code)?;
+                }
+            }
+        }
+        Ok(())
+    })();
+    ratatui::restore();
+    result
+}
+
+/// Run `dotlnx <args>` and return its combined output (log lines included).
+fn run_dotlnx(args: &[&str]) -> String {
+    let exe = match std::env::current_exe() {
+        Ok(e) => e,
+        Err(e) => return format!("locate dotlnx binary: {}", e),
+    };
+    let out = Command::new(exe)
+        .args(args)
+        .env("NO_COLOR", "1")
+        .stdin(Stdio::null())
+        .output();
+    match out {
+        Ok(o) => {
+            let mut text = strip_ansi(&String::from_utf8_lossy(&o.stdout));
+            text.push_str(&strip_ansi(&String::from_utf8_lossy(&o.stderr)));
+            text.push_str(&match o.status.code() {
+                Some(0) => "\nDone.".to_string(),
+                Some(code) => format!("\nFailed (exit {}).", code),
+                None => "\nKilled.".to_string(),
+            });
+            text
+        }
+        Err(e) => format!("could not run dotlnx: {}", e),
+    }
+}
+
+/// Start `dotlnx <args>` in the background, detached from the console's terminal.
+fn spawn_detached(args: &[&str]) -> Result<()> {
+    Command::new(std::env::current_exe()?)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    Ok(())
+}
+
+fn service_log() -> String {
+    match Command::new("journalctl")
+        .args([
+            "-u",
+            "dotlnx.service",
+            "-n",
+            "300",
+            "--no-pager",
+            "-o",
+            "short-iso",
+        ])
+        .output()
+    {
+        Ok(o) if o.status.success() => String::from_utf8_lossy(&o.stdout).into_owned(),
+        Ok(o) => strip_ansi(&String::from_utf8_lossy(&o.stderr)),
+        Err(e) => format!("could not run journalctl: {}", e),
+    }
+}
+
+/// Drop terminal escape sequences (CSI ... letter) so child output renders as plain text.
+fn strip_ansi(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+            continue;
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+    use std::path::Path;
+
+    fn entry(name: &str, tier: Tier, status: Status) -> Entry {
+        Entry {
+            name: name.into(),
+            tier,
+            bundle: PathBuf::from(format!("/Applications/{}.lnx", name)),
+            desktop_dir: PathBuf::from("/usr/share/applications"),
+            status,
+            problem: None,
+        }
+    }
+
+    #[test]
+    fn strip_ansi_removes_escapes() {
+        assert_eq!(strip_ansi("\x1b[32m INFO\x1b[0m synced"), " INFO synced");
+        assert_eq!(strip_ansi("plain"), "plain");
+    }
+
+    #[test]
+    fn selection_wraps_and_updates_pane() {
+        let mut app = App::new(
+            Paths::under(Path::new("/")),
+            vec![
+                entry("Alpha", Tier::User("alice".into()), Status::Installed),
+                entry("Beta", Tier::System, Status::Quarantined),
+            ],
+        );
+        assert!(app.pane.contains("user:alice"));
+        app.handle_key(KeyCode::Up).unwrap();
+        assert_eq!(app.selected().unwrap().name, "Beta");
+        assert!(app.pane.contains("dotlnx-Beta"));
+        app.handle_key(KeyCode::Char('j')).unwrap();
+        assert_eq!(app.selected().unwrap().name, "Alpha");
+        app.handle_key(KeyCode::Char('u')).unwrap();
+        assert!(app.confirm_uninstall);
+        app.handle_key(KeyCode::Char('n')).unwrap();
+        assert!(!app.confirm_uninstall);
+        assert_eq!(app.pane_title, "Alpha");
+        app.handle_key(KeyCode::Char('q')).unwrap();
+        assert!(app.quit);
+    }
+
+    #[test]
+    fn draw_lists_entries() {
+        let mut app = App::new(
+            Paths::under(Path::new("/")),
+            vec![entry("Alpha", Tier::System, Status::NotInstalled)],
+        );
+        let mut terminal = Terminal::new(TestBackend::new(100, 10)).unwrap();
+        terminal.draw(|f| app.draw(f)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|c| c.symbol())
+            .collect();
+        assert!(screen.contains("Alpha"));
+        assert!(screen.contains("not installed"));
+        assert!(screen.contains("v validate"));
+    }
+}
@@ -1,68 +1,174 @@
-//! Remove app from dotlnx: desktop entries and AppArmor profiles. Does not delete the .lnx folder.
+//! Remove app from dotlnx: desktop entries, service units, files from its share/ and AppArmor
+//! profiles. Does not delete the .lnx folder.
 
 use anyhow::Result;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use tracing::warn;
 
 use crate::apparmor;
 use crate::desktop;
+use crate::hooks;
+use crate::integration;
+use crate::paths::{self, Paths};
+use crate::state;
+use crate::sync::{Action, Change};
+use crate::systemd::{self, Scope, UnitDir};
 use crate::validate;
 
 /// When root + SUDO_USER: use invoking user's desktop dir; when root alone: root's; when non-root: XDG.
-fn user_desktop_dir_and_username() -> Result<(PathBuf, String)> {
+/// Also returns that user's service unit dir (None without a home).
+fn user_desktop_dir_and_username(paths: &Paths) -> Result<(PathBuf, String, Option<UnitDir>)> {
     if crate::bundle::is_root() {
         let (username, home) = if let Ok(sudo_user) = std::env::var("SUDO_USER") {
             let home = if sudo_user == "root" {
-                PathBuf::from("/root")
+                paths.root_home.clone()
             } else {
-                PathBuf::from("/home").join(&sudo_user)
+                paths.home_dirs.join(&sudo_user)
             };
             (sudo_user, home)
         } else {
-            (String::from("root"), PathBuf::from("/root"))
+            (String::from("root"), paths.root_home.clone())
         };
         let desktop_dir = home.join(".local/share/applications");
-        Ok((desktop_dir, username))
+        let units = UnitDir {
+            dir: home.join(".config/systemd/user"),
+            scope: Scope::UserOf(username.clone()),
+            prefix: paths.user_prefix.clone(),
+        };
+        Ok((desktop_dir, username, Some(units)))
     } else {
-        let desktop_dir = desktop::user_applications_dir()?;
+        let desktop_dir = paths.user_desktop_dir()?;
         let username = std::env::var("USER").unwrap_or_else(|_| "unknown".into());
-        Ok((desktop_dir, username))
+        let units = paths.user_units.clone().map(|dir| UnitDir {
+            dir,
+            scope: Scope::User,
+            prefix: paths.user_prefix.clone(),
+        });
+        Ok((desktop_dir, username, units))
     }
 }
 
 /// Remove desktop from user dir and (when root) system dir; remove AppArmor profile(s).
 /// Does not delete the .lnx bundle folder. Clears GNOME folder icon and removes .directory when found.
 /// If the given name is not found exactly, tries with underscores replaced by spaces (same as run).
-pub fn run(name: &str) -> Result<()> {
+/// Returns what was removed (None when nothing was installed); with `check`, what would be
+/// removed, without removing it.
+pub fn run(name: &str, check: bool) -> Result<Option<Change>> {
     validate::validate_app_name(name)?;
-    let resolved = crate::bundle::resolve_bundle_by_name(name)?;
+    let paths = paths::load()?;
+    let resolved = crate::bundle::resolve_bundle_by_name(&paths, name)?;
     let canonical_name = resolved
         .as_ref()
-        .map(|(_, cfg, _)| cfg.name.clone())
+        .map(|(_, cfg, _)| cfg.app_id())
         .unwrap_or_else(|| name.to_string());
     let bundle_path = resolved.as_ref().map(|(path, _, _)| path.clone());
     let is_root = crate::bundle::is_root();
-    let (user_desktop, current_user) = user_desktop_dir_and_username()?;
-
-    desktop::uninstall_desktop(&user_desktop, &canonical_name)?;
-    let user_profile = apparmor::profile_name_user(&current_user, &canonical_name);
-    let _ = apparmor::unload_profile(&user_profile);
+    let (user_desktop, current_user, user_units) = user_desktop_dir_and_username(&paths)?;
 
+    let run_as_user = is_root.then_some(current_user.as_str());
+    let mut targets = vec![(
+        user_desktop,
+        run_as_user,
+        paths.profile_name(Some(&current_user), &canonical_name),
+        user_units,
+    )];
     if is_root {
-        let system_desktop = desktop::system_applications_dir();
-        desktop::uninstall_desktop(&system_desktop, &canonical_name)?;
-        let system_profile = apparmor::profile_name_system(&canonical_name);
-        let _ = apparmor::unload_profile(&system_profile);
+        let system_units = UnitDir {
+            dir: paths.systemd_system_dir.clone(),
+            scope: Scope::System,
+            prefix: paths.system_prefix.clone(),
+        };
+        targets.push((
+            paths.system_desktop.clone(),
+            None,
+            paths.profile_name(None, &canonical_name),
+            Some(system_units),
+        ));
     }
 
-    if let Some(ref path) = bundle_path {
-        let run_as_user = if is_root {
-            Some(current_user.as_str())
+    // A profile written for another bundle of the same name that is still there stays.
+    let kept = |profile: &str| {
+        apparmor::profile_meta(&paths, profile).is_some_and(|meta| {
+            meta.belongs_elsewhere(bundle_path.as_deref().unwrap_or(Path::new("")), |b| {
+                crate::bundle::is_lnx_bundle(b)
+            })
+        })
+    };
+    let state_dir = state::state_dir()?;
+    let mut st = state::State::load(&state_dir)?;
+    let mut removed = Vec::new();
+    for (desktop_dir, as_user, profile, units) in &targets {
+        if desktop::read_desktop(&paths, desktop_dir, &canonical_name, *as_user)?.is_some() {
+            removed.push(desktop_dir.join(paths.desktop_file_name(desktop_dir, &canonical_name)));
+        }
+        if let Some(ud) = units {
+            removed.extend(
+                ud.installed(&canonical_name)?
+                    .iter()
+                    .map(|u| ud.dir.join(&u.name)),
+            );
+        }
+        if apparmor::installed_profile(&paths, profile).is_some() && !kept(profile) {
+            removed.push(paths.profile_path(profile));
+        }
+        let key = integration::key(&paths, desktop_dir, &canonical_name);
+        removed.extend(st.integrations.get(&key).into_iter().flatten().cloned());
+    }
+    let change = (!removed.is_empty()).then(|| Change {
+        action: Action::Remove,
+        app: canonical_name.clone(),
+        paths: removed,
+    });
+    if check {
+        return Ok(change);
+    }
+
+    for (desktop_dir, as_user, profile, units) in &targets {
+        desktop::uninstall_desktop(&paths, desktop_dir, &canonical_name, *as_user)?;
+        if let Some(ud) = units {
+            systemd::remove(&paths, ud, &canonical_name)?;
+        }
+        if kept(profile) {
+            warn!(profile = %profile, "keeping AppArmor profile: it belongs to another bundle");
         } else {
-            None
-        };
-        let _ = desktop::clear_gnome_folder_icon(path, run_as_user);
-        let _ = desktop::remove_bundle_directory_file(path);
+            let _ = apparmor::unload_profile(&paths, profile);
+        }
+        if let Some(files) =
+            st.integrations
+                .remove(&integration::key(&paths, desktop_dir, &canonical_name))
+        {
+            integration::remove(&paths, &files, *as_user)?;
+            st.save(&state_dir)?;
+        }
     }
 
+    if let Some(ref path) = bundle_path {
+        let _ = desktop::clear_gnome_folder_icon(&paths, path, run_as_user);
+        let _ = desktop::remove_bundle_directory_file(path, run_as_user);
+        // Otherwise the next sync would take the folder icon as still set.
+        forget_decoration(path)?;
+    }
+    if let Some(ref change) = change {
+        let settings = crate::settings::load()?;
+        hooks::fire(
+            &settings.hooks.dir,
+            &hooks::Payload::new(hooks::Event::PostRemove, Some(change)),
+        );
+    }
+
+    Ok(change)
+}
+
+/// Drop the bundle's folder icon record from state (its .directory and GNOME icon are gone).
+fn forget_decoration(bundle: &Path) -> Result<()> {
+    let dir = state::state_dir()?;
+    let mut st = state::State::load(&dir)?;
+    if st
+        .decorations
+        .remove(&bundle.display().to_string())
+        .is_some()
+    {
+        st.save(&dir)?;
+    }
     Ok(())
 }
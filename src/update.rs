@@ -0,0 +1,666 @@
+//! `dotlnx update`: fetch new versions of AppImage bundles that opt in with
+//! `[update] appimage_zsync = true`, the way AppImageUpdate does, but under dotlnx: zsync2 (or
+//! zsync) builds the new AppImage from the current one plus the blocks that changed, checking it
+//! against the .zsync file's checksums; dotlnx then puts it into bin/ next to the old ones,
+//! validates (and with [scan] set, scans) the bundle again, and removes versions beyond
+//! `keep_versions`.
+//! A new AppImage that fails any check is removed again. Each update is recorded (with the app's
+//! data dirs, when it asks for that) so `dotlnx rollback` can undo it; see `snapshot`.
+//!
+//! The .zsync URL is `[update] zsync_url`, or else the AppImage's embedded update information
+//! (its `.upd_info` ELF section) when that is a plain `zsync|https://...` URL. Held apps are
+//! skipped. As root, system-tier bundles are updated; users update their own.
+
+use anyhow::{Context, Result};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::info;
+
+use crate::arch::Arch;
+use crate::bundle;
+use crate::config::{self, Config};
+use crate::hold;
+use crate::paths;
+use crate::scan;
+use crate::settings::{self, Settings};
+use crate::snapshot;
+use crate::state;
+use crate::sync;
+use crate::validate;
+use crate::watch;
+
+/// Programs tried in turn to apply an update.
+const ZSYNC_TOOLS: &[&str] = &["zsync2", "zsync"];
+
+/// AppImage versions kept after an update when neither the bundle nor the site sets
+/// keep_versions: the new one and two to go back to.
+const DEFAULT_KEEP_VERSIONS: usize = 3;
+
+/// Staging dir in bin/ the new AppImage is built in.
+const STAGING: &str = ".dotlnx-update";
+
+/// What an update did.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    UpToDate,
+    Updated { from: PathBuf, to: PathBuf },
+}
+
+/// True when `name` is an AppImage's file name.
+fn is_appimage_name(name: &str) -> bool {
+    name.len() > ".appimage".len() && name.to_ascii_lowercase().ends_with(".appimage")
+}
+
+/// Pattern the bundle's AppImages match in bin/: the executable's file name when it is a
+/// pattern (builtin launcher), else any AppImage.
+fn appimage_pattern(cfg: &Config) -> String {
+    let exe = Path::new(&cfg.executable);
+    match exe.file_name().and_then(|n| n.to_str()) {
+        Some(name) if name.contains(['*', '?']) && is_appimage_name(name) => name.to_string(),
+        _ => "*.AppImage".to_string(),
+    }
+}
+
+/// The AppImage the app runs now: the newest match in bin/, as at launch.
+fn current_appimage(bundle_root: &Path, cfg: &Config) -> Result<PathBuf> {
+    let exe = if is_appimage_name(&cfg.executable) && !cfg.executable.contains(['*', '?']) {
+        bundle_root.join(&cfg.executable)
+    } else {
+        bundle::resolve_executable(bundle_root, &format!("bin/{}", appimage_pattern(cfg)))
+    };
+    if !exe.is_file() {
+        anyhow::bail!("no AppImage in {}", bundle_root.join("bin").display());
+    }
+    Ok(exe)
+}
+
+/// The AppImage's embedded update information (the `.upd_info` ELF section), if any.
+pub fn update_information(appimage: &Path) -> Option<String> {
+    use std::io::{Seek, SeekFrom};
+    let mut file = std::fs::File::open(appimage).ok()?;
+    let mut header = [0u8; 64];
+    file.read_exact(&mut header).ok()?;
+    if &header[..4] != b"\x7fELF" {
+        return None;
+    }
+    let little = header[5] == 1;
+    let int = |bytes: &[u8]| {
+        let fold = |acc: u64, b: &u8| (acc << 8) | u64::from(*b);
+        match little {
+            true => bytes.iter().rev().fold(0, fold),
+            false => bytes.iter().fold(0, fold),
+        }
+    };
+    // Where the section headers are, and where name, offset and size are in one.
+    let (shoff, shentsize, shnum, shstrndx, name_at, offset_at, width) = match header[4] {
+        1 => (
+            int(&header[0x20..0x24]),
+            int(&header[0x2e..0x30]),
+            int(&header[0x30..0x32]),
+            int(&header[0x32..0x34]),
+            0,
+            0x10,
+            4,
+        ),
+        2 => (
+            int(&header[0x28..0x30]),
+            int(&header[0x3a..0x3c]),
+            int(&header[0x3c..0x3e]),
+            int(&header[0x3e..0x40]),
+            0,
+            0x18,
+            8,
+        ),
+        _ => return None,
+    };
+    if shnum == 0 || shnum > 4096 || shentsize < (offset_at + 2 * width) as u64 || shstrndx >= shnum
+    {
+        return None;
+    }
+    let mut table = vec![0u8; (shentsize * shnum) as usize];
+    file.seek(SeekFrom::Start(shoff)).ok()?;
+    file.read_exact(&mut table).ok()?;
+    let section = |i: u64| {
+        let entry = &table[(i * shentsize) as usize..((i + 1) * shentsize) as usize];
+        let name = int(&entry[name_at..name_at + 4]);
+        let offset = int(&entry[offset_at..offset_at + width]);
+        let size = int(&entry[offset_at + width..offset_at + 2 * width]);
+        (name, offset, size)
+    };
+    let mut read_at = |offset: u64, size: u64| -> Option<Vec<u8>> {
+        let mut buf = vec![0u8; size.min(64 * 1024) as usize];
+        file.seek(SeekFrom::Start(offset)).ok()?;
+        file.read_exact(&mut buf).ok()?;
+        Some(buf)
+    };
+    let (_, names_offset, names_size) = section(shstrndx);
+    let names = read_at(names_offset, names_size)?;
+    let (_, offset, size) = (0..shnum).map(section).find(|(name, _, _)| {
+        names
+            .get(*name as usize..)
+            .is_some_and(|rest| rest.starts_with(b".upd_info\0"))
+    })?;
+    let data = read_at(offset, size)?;
+    let text =
+        String::from_utf8_lossy(&data[..data.iter().position(|&b| b == 0).unwrap_or(data.len())])
+            .trim()
+            .to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+/// URL of the .zsync file for the bundle's AppImage.
+fn zsync_url(cfg: &Config, appimage: &Path) -> Result<String> {
+    if let Some(url) = cfg.update.as_ref().and_then(|u| u.zsync_url.clone()) {
+        return Ok(url);
+    }
+    let Some(info) = update_information(appimage) else {
+        anyhow::bail!(
+            "{} has no update information; set [update] zsync_url",
+            appimage.display()
+        );
+    };
+    match info.split_once('|') {
+        Some(("zsync", url)) if url.starts_with("https://") => Ok(url.to_string()),
+        Some(("zsync", url)) => anyhow::bail!("update information {:?} is not an https:// URL; set [update] zsync_url", url),
+        _ => anyhow::bail!("update information {:?} is not a zsync URL (only zsync|https://... is supported); set [update] zsync_url", info),
+    }
+}
+
+/// True when both files have the same contents.
+fn same_contents(a: &Path, b: &Path) -> Result<bool> {
+    let (mut a, mut b) = (std::fs::File::open(a)?, std::fs::File::open(b)?);
+    if a.metadata()?.len() != b.metadata()?.len() {
+        return Ok(false);
+    }
+    let (mut x, mut y) = (vec![0u8; 64 * 1024], vec![0u8; 64 * 1024]);
+    loop {
+        let n = a.read(&mut x)?;
+        if n == 0 {
+            return Ok(true);
+        }
+        b.read_exact(&mut y[..n])?;
+        if x[..n] != y[..n] {
+            return Ok(false);
+        }
+    }
+}
+
+/// Run zsync in `dir`, seeded with `seed`. Returns the one file it wrote.
+fn fetch(tool: Option<&Path>, seed: &Path, url: &str, dir: &Path) -> Result<PathBuf> {
+    let tools: Vec<PathBuf> = match tool {
+        Some(tool) => vec![tool.to_path_buf()],
+        None => ZSYNC_TOOLS.iter().map(PathBuf::from).collect(),
+    };
+    let mut status = None;
+    for tool in &tools {
+        match Command::new(tool)
+            .arg("-i")
+            .arg(seed)
+            .arg(url)
+            .current_dir(dir)
+            .status()
+        {
+            Ok(s) => {
+                status = Some(s);
+                break;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("run {}", tool.display())),
+        }
+    }
+    let Some(status) = status else {
+        anyhow::bail!("updating AppImages needs zsync2 (or zsync)");
+    };
+    if !status.success() {
+        anyhow::bail!("zsync failed ({}) for {}", status, url);
+    }
+    let mut written: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            p.is_file()
+                && !p.to_string_lossy().ends_with(".part")
+                && !p.to_string_lossy().ends_with(".zs-old")
+        })
+        .collect();
+    match (written.pop(), written.is_empty()) {
+        (Some(file), true) => Ok(file),
+        (None, _) => anyhow::bail!("zsync wrote no file for {}", url),
+        (Some(_), false) => anyhow::bail!("zsync wrote more than one file for {}", url),
+    }
+}
+
+/// Update one bundle, recording a snapshot in `snapshots` when given (see `snapshot`). `tool`
+/// overrides the zsync program (tests).
+pub fn update_bundle(
+    bundle_root: &Path,
+    cfg: &Config,
+    settings: &Settings,
+    snapshots: Option<&Path>,
+    tool: Option<&Path>,
+) -> Result<Outcome> {
+    use std::os::unix::fs::PermissionsExt;
+    let keep = cfg
+        .keep_versions
+        .or(settings.resources.keep_versions)
+        .unwrap_or(DEFAULT_KEEP_VERSIONS);
+    let current = current_appimage(bundle_root, cfg)?;
+    let url = zsync_url(cfg, &current)?;
+    let bin = current
+        .parent()
+        .context("AppImage has no parent dir")?
+        .to_path_buf();
+    let staging = bin.join(STAGING);
+    let _ = std::fs::remove_dir_all(&staging);
+    std::fs::create_dir(&staging).with_context(|| format!("create {}", staging.display()))?;
+    let fetched = fetch(tool, &current, &url, &staging);
+    let result = fetched.and_then(|new| {
+        if same_contents(&new, &current)? {
+            return Ok(Outcome::UpToDate);
+        }
+        let name = new
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default()
+            .to_string();
+        if !is_appimage_name(&name) {
+            anyhow::bail!("zsync wrote {:?}, not an AppImage", name);
+        }
+        let (was, is) = (Arch::of_file(&current), Arch::of_file(&new));
+        if is.is_none() || (was.is_some() && was != is) {
+            anyhow::bail!(
+                "{} is not an AppImage for the same machine as {}",
+                name,
+                current.display()
+            );
+        }
+        std::fs::set_permissions(&new, std::fs::Permissions::from_mode(0o755))?;
+        let pending = match snapshots {
+            Some(store) => Some(snapshot::take(store, bundle_root, cfg)?),
+            None => None,
+        };
+        match place(bundle_root, &bin, &new, settings) {
+            Ok((to, aside)) => {
+                if let Some(pending) = pending {
+                    pending.finish(&to, aside.as_deref().unwrap_or(&current), &current);
+                }
+                Ok(Outcome::Updated {
+                    from: current.clone(),
+                    to,
+                })
+            }
+            Err(e) => {
+                if let Some(pending) = pending {
+                    pending.discard();
+                }
+                Err(e)
+            }
+        }
+    });
+    let _ = std::fs::remove_dir_all(&staging);
+    let outcome = result?;
+    if let Outcome::Updated { to, .. } = &outcome {
+        for old in bundle::surplus_appimages(bundle_root, &cfg.executable, keep)
+            .into_iter()
+            .filter(|old| old != to)
+        {
+            std::fs::remove_file(&old).with_context(|| format!("remove {}", old.display()))?;
+            info!(removed = %old.display(), "removed old AppImage version");
+        }
+    }
+    Ok(outcome)
+}
+
+/// Move `new` into `bin` as the newest AppImage and check the bundle again; on failure, take it
+/// out and put back what it replaced. Returns where it went and, for a fixed name, where the
+/// AppImage it replaced was moved.
+fn place(
+    bundle_root: &Path,
+    bin: &Path,
+    new: &Path,
+    settings: &Settings,
+) -> Result<(PathBuf, Option<PathBuf>)> {
+    let dest = bin.join(new.file_name().context("no file name")?);
+    // A fixed name (MyApp-latest.AppImage) is moved aside, so it can be kept like a versioned
+    // one; the mark keeps it out of launchers' *.AppImage patterns.
+    let aside = dest.exists().then(|| {
+        let name = dest.file_name().unwrap_or_default().to_string_lossy();
+        bin.join(format!("{}.old-{}", name, state::now()))
+    });
+    if let Some(aside) = &aside {
+        std::fs::rename(&dest, aside).with_context(|| format!("move {} aside", dest.display()))?;
+    }
+    std::fs::rename(new, &dest)
+        .with_context(|| format!("move new AppImage to {}", dest.display()))?;
+    // zsync gives the file the upstream build time; launchers pick the newest, so mark it now.
+    let checked = std::fs::File::options()
+        .write(true)
+        .open(&dest)
+        .and_then(|f| f.set_modified(std::time::SystemTime::now()))
+        .map_err(anyhow::Error::from)
+        .and_then(|_| validate::validate_bundle(bundle_root))
+        .and_then(|_| {
+            if !scan::enabled(&settings.scan) {
+                return Ok(());
+            }
+            let findings = scan::scan(&settings.scan, bundle_root)?;
+            match findings.is_empty() {
+                true => Ok(()),
+                false => anyhow::bail!("{}", findings.join("; ")),
+            }
+        });
+    if let Err(e) = checked {
+        let _ = std::fs::remove_file(&dest);
+        if let Some(aside) = &aside {
+            let _ = std::fs::rename(aside, &dest);
+        }
+        return Err(e.context(format!("rejected update {}", dest.display())));
+    }
+    Ok((dest, aside))
+}
+
+/// Entry point for `dotlnx update [name...] [--dry-run]`: update every opted-in bundle of this
+/// tier (or the named ones), then sync when no service is running to pick the change up.
+pub fn run(names: &[String], dry_run: bool) -> Result<()> {
+    let settings = settings::load()?;
+    let paths = paths::Paths::from_settings(&settings.paths);
+    let is_root = bundle::is_root();
+    let apps_dir = if is_root {
+        &paths.system_apps
+    } else {
+        &paths.user_apps
+    };
+    let mut holds = hold::load(&state::state_dir()?).unwrap_or_default();
+    if !is_root {
+        holds.extend(hold::load(Path::new(state::SYSTEM_STATE_DIR)).unwrap_or_default());
+    }
+    let snapshots = state::state_dir()?.join(snapshot::DIR);
+    let mut seen = Vec::new();
+    let (mut updated, mut failed) = (0, 0);
+    for dir in bundle::discover_lnx_dirs(apps_dir) {
+        let Ok(cfg) = config::load(&dir) else {
+            continue;
+        };
+        if !names.is_empty() && !names.iter().any(|n| cfg.answers_to(n)) {
+            continue;
+        }
+        seen.extend(names.iter().filter(|n| cfg.answers_to(n)));
+        if !cfg.update.as_ref().is_some_and(|u| u.appimage_zsync) {
+            if !names.is_empty() {
+                println!(
+                    "{}: not updated by dotlnx (no [update] appimage_zsync = true)",
+                    cfg.name
+                );
+            }
+            continue;
+        }
+        let username = (!is_root)
+            .then(|| bundle::username_from_bundle_path(&dir))
+            .flatten();
+        if holds
+            .get(&paths.profile_name(username.as_deref(), &cfg.app_id()))
+            .is_some_and(|h| h.bundle == dir)
+        {
+            println!("{}: held, not updated", cfg.name);
+            continue;
+        }
+        if dry_run {
+            match current_appimage(&dir, &cfg).and_then(|a| zsync_url(&cfg, &a)) {
+                Ok(url) => println!("{}: would update from {}", cfg.name, url),
+                Err(e) => println!("{}: cannot update: {:#}", cfg.name, e),
+            }
+            continue;
+        }
+        match update_bundle(&dir, &cfg, &settings, Some(&snapshots), None) {
+            Ok(Outcome::UpToDate) => println!("{}: up to date", cfg.name),
+            Ok(Outcome::Updated { from, to }) => {
+                info!(app = %cfg.name, from = %from.display(), to = %to.display(), "updated AppImage");
+                println!(
+                    "{}: updated to {}",
+                    cfg.name,
+                    to.file_name().unwrap_or_default().to_string_lossy()
+                );
+                updated += 1;
+            }
+            Err(e) => {
+                eprintln!("{}: {:#}", cfg.name, e);
+                failed += 1;
+            }
+        }
+    }
+    if let Some(missing) = names.iter().find(|n| !seen.contains(n)) {
+        anyhow::bail!("no app named {:?} in {}", missing, apps_dir.display());
+    }
+    if updated > 0 && !watch::service_active() {
+        sync::run(false)?;
+    }
+    if failed > 0 {
+        anyhow::bail!("{} update(s) failed", failed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A minimal x86_64 ELF file with the sections named in `sections` (name, contents).
+    fn elf(sections: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut names = b"\0".to_vec();
+        let mut data = Vec::new();
+        let mut entries = vec![[0u8; 64]];
+        for (name, contents) in sections {
+            let mut entry = [0u8; 64];
+            entry[..4].copy_from_slice(&(names.len() as u32).to_le_bytes());
+            entry[0x18..0x20].copy_from_slice(&((64 + data.len()) as u64).to_le_bytes());
+            entry[0x20..0x28].copy_from_slice(&(contents.len() as u64).to_le_bytes());
+            names.extend_from_slice(name.as_bytes());
+            names.push(0);
+            data.extend_from_slice(contents);
+            entries.push(entry);
+        }
+        let mut strtab = [0u8; 64];
+        strtab[0x18..0x20].copy_from_slice(&((64 + data.len()) as u64).to_le_bytes());
+        strtab[0x20..0x28].copy_from_slice(&(names.len() as u64).to_le_bytes());
+        data.extend_from_slice(&names);
+        entries.push(strtab);
+        let mut out = vec![0u8; 64];
+        out[..4].copy_from_slice(b"\x7fELF");
+        out[4] = 2;
+        out[5] = 1;
+        out[18] = 0x3e;
+        out[0x28..0x30].copy_from_slice(&((64 + data.len()) as u64).to_le_bytes());
+        out[0x3a] = 64;
+        out[0x3c] = entries.len() as u8;
+        out[0x3e] = (entries.len() - 1) as u8;
+        out.extend_from_slice(&data);
+        for entry in entries {
+            out.extend_from_slice(&entry);
+        }
+        out
+    }
+
+    #[test]
+    fn reads_zsync_url_from_update_information() {
+        let dir = tempfile::tempdir().unwrap();
+        let appimage = dir.path().join("App-1.0-x86_64.AppImage");
+        let cfg = |update: &str| -> Config {
+            toml::from_str(&format!(
+                "name = \"App\"\nexecutable = \"run.sh\"\n[update]\nappimage_zsync = true\n{}",
+                update
+            ))
+            .unwrap()
+        };
+        std::fs::write(
+            &appimage,
+            elf(&[
+                (".text", b"code"),
+                (
+                    ".upd_info",
+                    b"zsync|https://example.org/App-latest-x86_64.AppImage.zsync\0\0\0",
+                ),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            update_information(&appimage).as_deref(),
+            Some("zsync|https://example.org/App-latest-x86_64.AppImage.zsync")
+        );
+        assert_eq!(
+            zsync_url(&cfg(""), &appimage).unwrap(),
+            "https://example.org/App-latest-x86_64.AppImage.zsync"
+        );
+        assert_eq!(
+            zsync_url(
+                &cfg("zsync_url = \"https://mirror.example/a.zsync\""),
+                &appimage
+            )
+            .unwrap(),
+            "https://mirror.example/a.zsync"
+        );
+
+        std::fs::write(
+            &appimage,
+            elf(&[(
+                ".upd_info",
+                b"gh-releases-zsync|owner|repo|latest|App-*.zsync",
+            )]),
+        )
+        .unwrap();
+        assert!(zsync_url(&cfg(""), &appimage)
+            .unwrap_err()
+            .to_string()
+            .contains("set [update] zsync_url"));
+        std::fs::write(&appimage, elf(&[(".text", b"code")])).unwrap();
+        assert_eq!(update_information(&appimage), None);
+        assert_eq!(update_information(&dir.path().join("missing")), None);
+    }
+
+    #[test]
+    fn update_adds_the_new_appimage_and_keeps_older_ones() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("App.lnx");
+        let bin = bundle.join("bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        std::fs::write(
+            bundle.join("config.toml"),
+            "name = \"App\"\nexecutable = \"bin/App-*-x86_64.AppImage\"\nkeep_versions = 2\n[update]\nappimage_zsync = true\nzsync_url = \"https://example.org/App.zsync\"\n",
+        )
+        .unwrap();
+        let image = elf(&[(".text", b"v1")]);
+        let old = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        for (i, version) in ["0.9", "1.0"].iter().enumerate() {
+            let path = bin.join(format!("App-{}-x86_64.AppImage", version));
+            std::fs::write(&path, &image).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            let f = std::fs::File::options().write(true).open(&path).unwrap();
+            f.set_modified(old + std::time::Duration::from_secs(i as u64))
+                .unwrap();
+        }
+        // Stands in for zsync2: writes the file the .zsync names, built from the seed (-i).
+        let tool = dir.path().join("zsync2");
+        let script = |out: &str, extra: &str| {
+            format!(
+                "#!/bin/sh\n[ \"$1\" = -i ] || exit 9\ncat \"$2\" > {}\nprintf '{}' >> {}\n",
+                out, extra, out
+            )
+        };
+        let write_tool = |text: String| {
+            std::fs::write(&tool, text).unwrap();
+            std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+        };
+        let cfg = config::load(&bundle).unwrap();
+        let settings = Settings::default();
+
+        write_tool(script("App-1.0-x86_64.AppImage", ""));
+        assert_eq!(
+            update_bundle(&bundle, &cfg, &settings, None, Some(&tool)).unwrap(),
+            Outcome::UpToDate
+        );
+
+        write_tool(script("App-1.1-x86_64.AppImage", "more"));
+        let outcome = update_bundle(&bundle, &cfg, &settings, None, Some(&tool)).unwrap();
+        let new = bin.join("App-1.1-x86_64.AppImage");
+        assert_eq!(
+            outcome,
+            Outcome::Updated {
+                from: bin.join("App-1.0-x86_64.AppImage"),
+                to: new.clone()
+            }
+        );
+        assert_eq!(bundle::resolve_executable(&bundle, &cfg.executable), new);
+        assert!(
+            bin.join("App-1.0-x86_64.AppImage").exists(),
+            "one older version is kept"
+        );
+        assert!(!bin.join("App-0.9-x86_64.AppImage").exists());
+        assert!(!bin.join(STAGING).exists());
+
+        write_tool(script("notes.txt", "x"));
+        assert!(update_bundle(&bundle, &cfg, &settings, None, Some(&tool)).is_err());
+        write_tool("#!/bin/sh\nprintf 'not elf' > App-2.0-x86_64.AppImage\n".to_string());
+        assert!(update_bundle(&bundle, &cfg, &settings, None, Some(&tool)).is_err());
+        assert!(!bin.join("App-2.0-x86_64.AppImage").exists());
+        assert_eq!(bundle::resolve_executable(&bundle, &cfg.executable), new);
+    }
+
+    #[test]
+    fn rollback_restores_the_appimage_and_its_data() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let (bundle, store) = (dir.path().join("App.lnx"), dir.path().join("snapshots"));
+        // Not there: the snapshot records it as missing, and the rollback leaves it alone.
+        let data = PathBuf::from(format!("/var/lib/dotlnx-test-{}", std::process::id()));
+        let bin = bundle.join("bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        std::fs::write(
+            bundle.join("config.toml"),
+            format!(
+                "name = \"App\"\nexecutable = \"bin/App-latest.AppImage\"\ndata_dirs = [{:?}]\n[update]\nappimage_zsync = true\nzsync_url = \"https://example.org/App.zsync\"\nsnapshot_data = true\n",
+                data
+            ),
+        )
+        .unwrap();
+        let current = bin.join("App-latest.AppImage");
+        let v1 = elf(&[(".text", b"v1")]);
+        std::fs::write(&current, &v1).unwrap();
+        std::fs::set_permissions(&current, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let tool = dir.path().join("zsync2");
+        std::fs::write(
+            &tool,
+            "#!/bin/sh\ncat \"$2\" > App-latest.AppImage\nprintf v2 >> App-latest.AppImage\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let cfg = config::load(&bundle).unwrap();
+
+        update_bundle(
+            &bundle,
+            &cfg,
+            &Settings::default(),
+            Some(&store),
+            Some(&tool),
+        )
+        .unwrap();
+        let snapshots = snapshot::list(&store.join("App"));
+        assert_eq!(snapshots.len(), 1);
+        let (snapshot_dir, taken) = &snapshots[0];
+        assert_eq!((&taken.installed, &taken.restore_as), (&current, &current));
+        assert_ne!(taken.previous, current);
+        // snapshot.rs tests copying and restoring data that is there.
+        assert_eq!(
+            taken.data,
+            [snapshot::DataCopy {
+                path: data.clone(),
+                saved: snapshot::Saved::Missing
+            }]
+        );
+
+        snapshot::restore(snapshot_dir, taken).unwrap();
+        assert_eq!(std::fs::read(&current).unwrap(), v1);
+        assert!(!taken.previous.exists());
+        assert!(!data.exists());
+    }
+}
@@ -1,13 +1,20 @@
 //! Validate .lnx bundle: layout, config.toml, executable path.
 
-use anyhow::Result;
-use std::path::Path;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::hash_map::{Entry, HashMap};
+use std::path::{Path, PathBuf};
+use tracing::warn;
 
+use crate::arch::Arch;
 use crate::bundle;
 use crate::config;
+use crate::deps;
+use crate::fscache;
+use crate::output::{self, Style};
 
 /// Reject paths that could escape the bundle (absolute or containing "..").
-fn path_stays_in_bundle(relative_path: &str) -> Result<()> {
+pub fn path_stays_in_bundle(relative_path: &str) -> Result<()> {
     if relative_path.is_empty() {
         anyhow::bail!("path must not be empty");
     }
@@ -24,9 +31,9 @@ fn path_stays_in_bundle(relative_path: &str) -> Result<()> {
 
 /// Ensure resolved path is under bundle_root (canonicalize and check prefix).
 pub fn path_under_bundle(resolved: &Path, bundle_root: &Path) -> Result<()> {
-    let bundle_canon = std::fs::canonicalize(bundle_root)
-        .map_err(|e| anyhow::anyhow!("bundle path: {}", e))?;
-    let resolved_canon = std::fs::canonicalize(resolved).map_err(|e| anyhow::anyhow!("{}", e))?;
+    let bundle_canon =
+        fscache::canonicalize(bundle_root).map_err(|e| anyhow::anyhow!("bundle path: {}", e))?;
+    let resolved_canon = fscache::canonicalize(resolved).map_err(|e| anyhow::anyhow!("{}", e))?;
     if !resolved_canon.starts_with(&bundle_canon) {
         anyhow::bail!(
             "path {} is outside bundle {}",
@@ -48,11 +55,85 @@ fn validate_desktop_string(label: &str, s: &str) -> Result<()> {
     Ok(())
 }
 
+/// Keywords= items: single-line, and no `;` (the list separator).
+fn validate_keywords(label: &str, keywords: &[String]) -> Result<()> {
+    for (i, k) in keywords.iter().enumerate() {
+        validate_desktop_string(&format!("{}[{}]", label, i), k)?;
+        if k.trim().is_empty() || k.contains(';') {
+            anyhow::bail!(
+                "config.toml: {}[{}] must be a non-empty word or phrase without ;",
+                label,
+                i
+            );
+        }
+    }
+    Ok(())
+}
+
+/// A user or group name as useradd/groupadd accept by default: lowercase letters, digits, `_`
+/// and `-`, not starting with a digit or `-`, at most 32 bytes.
+pub fn is_account_name(s: &str) -> bool {
+    s.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+        && s.chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+        && s.len() <= 32
+}
+
+/// A locale as desktop files key translations by: lang, optionally `_COUNTRY`, `.ENCODING`
+/// and `@MODIFIER` (e.g. "de", "pt_BR", "sr@latin").
+pub fn is_locale(s: &str) -> bool {
+    let lang = s.split(['_', '.', '@']).next().unwrap_or("");
+    (2..=3).contains(&lang.len())
+        && lang.chars().all(|c| c.is_ascii_lowercase())
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_.@-".contains(c))
+}
+
+/// `type/subtype` (e.g. "image/x-example"), with nothing a MimeType= list could split on.
+fn is_mime_type(s: &str) -> bool {
+    let part = |p: &str| {
+        !p.is_empty()
+            && p.chars()
+                .all(|c| c.is_ascii_alphanumeric() || "+-._".contains(c))
+    };
+    s.split_once('/')
+        .is_some_and(|(t, sub)| part(t) && part(sub))
+}
+
+/// A well-known D-Bus name: two or more dot-separated elements of `[A-Za-z0-9_-]`, none starting
+/// with a digit, at most 255 bytes.
+fn is_dbus_name(s: &str) -> bool {
+    let element = |e: &str| {
+        e.chars().next().is_some_and(|c| !c.is_ascii_digit())
+            && e.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    };
+    s.len() <= 255 && s.split('.').count() >= 2 && s.split('.').all(element)
+}
+
+/// A D-Bus object path: "/" or "/"-separated non-empty elements of `[A-Za-z0-9_]`.
+fn is_dbus_object_path(s: &str) -> bool {
+    s == "/"
+        || s.strip_prefix('/').is_some_and(|rest| {
+            rest.split('/')
+                .all(|e| !e.is_empty() && e.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+        })
+}
+
 /// Reject security paths that could break AppArmor profile or are ambiguous (e.g. "..", "#").
-fn validate_security_path(label: &str, p: &str) -> Result<()> {
+pub fn validate_security_path(label: &str, p: &str) -> Result<()> {
     if p.is_empty() {
         anyhow::bail!("config.toml: security path must not be empty");
     }
+    if p.len() > crate::apparmor::MAX_RULE_PATH_LEN {
+        anyhow::bail!(
+            "config.toml: security {} is longer than {} bytes (AppArmor rules name paths up to PATH_MAX)",
+            label,
+            crate::apparmor::MAX_RULE_PATH_LEN
+        );
+    }
     if p.contains('#') {
         anyhow::bail!(
             "config.toml: security {} must not contain # (AppArmor comment character)",
@@ -76,31 +157,322 @@ fn validate_security_path(label: &str, p: &str) -> Result<()> {
     Ok(())
 }
 
+/// An ephemeral_home persisted path: relative to the home, without wildcards (it is linked, not
+/// matched), and otherwise what a security path may be.
+pub fn validate_persist_path(label: &str, p: &str) -> Result<()> {
+    validate_security_path(label, p)?;
+    if p.starts_with('/') || p.starts_with('@') || p.starts_with('~') {
+        anyhow::bail!(
+            "config.toml: security {} must be relative to the home (e.g. .config/MyApp)",
+            label
+        );
+    }
+    if p.contains(['*', '?', '[', ']', '{', '}', ',']) {
+        anyhow::bail!(
+            "config.toml: security {} must not contain wildcards or commas",
+            label
+        );
+    }
+    if Path::new(p)
+        .components()
+        .all(|c| matches!(c, std::path::Component::CurDir))
+    {
+        anyhow::bail!(
+            "config.toml: security {} must name a path inside the home, not the home",
+            label
+        );
+    }
+    Ok(())
+}
+
+/// Where absolute data dirs may be: an app's own directory below one of these. `dotlnx rollback`
+/// moves a data dir aside and unpacks the snapshot over it as root, so never /etc or /usr.
+const DATA_DIR_ROOTS: [&str; 3] = ["/var/lib", "/srv", "/opt"];
+
+/// A data dir: relative to the home, or an app's directory below /var/lib, /srv or /opt; a plain
+/// path (no wildcards, `..` or control characters) naming a directory below the home or those.
+pub fn validate_data_dir(label: &str, p: &str) -> Result<()> {
+    if p.contains(['*', '?', '[', ']', '{', '}', '~', '@']) || p.chars().any(|c| c.is_control()) {
+        anyhow::bail!(
+            "config.toml: {} must be a plain path, without wildcards, ~ or variables",
+            label
+        );
+    }
+    let components: Vec<_> = Path::new(p).components().collect();
+    if components
+        .iter()
+        .any(|c| matches!(c, std::path::Component::ParentDir))
+    {
+        anyhow::bail!("config.toml: {} must not contain ..", label);
+    }
+    if !components
+        .iter()
+        .any(|c| matches!(c, std::path::Component::Normal(_)))
+    {
+        anyhow::bail!(
+            "config.toml: {} must name a directory inside the home or below /",
+            label
+        );
+    }
+    let path = Path::new(p);
+    let app_specific = |root: &str| {
+        path.strip_prefix(root).is_ok_and(|rest| {
+            rest.components()
+                .any(|c| matches!(c, std::path::Component::Normal(_)))
+        })
+    };
+    if path.is_absolute() && !DATA_DIR_ROOTS.iter().any(|r| app_specific(r)) {
+        anyhow::bail!(
+            "config.toml: {} must be relative to the home or an app's directory below {}",
+            label,
+            DATA_DIR_ROOTS.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Reject a bundle file whose full path is too long to name in a profile rule.
+fn check_rule_path_len(label: &str, path: &Path) -> Result<()> {
+    let len = path.as_os_str().len();
+    if len > crate::apparmor::MAX_RULE_PATH_LEN {
+        anyhow::bail!(
+            "config.toml: {} path is {} bytes, longer than AppArmor rules allow ({})",
+            label,
+            len,
+            crate::apparmor::MAX_RULE_PATH_LEN
+        );
+    }
+    Ok(())
+}
+
 /// Validate a single .lnx bundle at the given path.
 pub fn validate_bundle(bundle_root: &Path) -> Result<()> {
     if !bundle::is_lnx_bundle(bundle_root) {
         anyhow::bail!("not a .lnx bundle: {}", bundle_root.display());
     }
     let cfg = config::load(bundle_root)?;
+    validate_config(bundle_root, &cfg)?;
+    let privileged = privileged_files(bundle_root);
+    if !privileged.is_empty() {
+        let list: Vec<String> = privileged.iter().map(|f| f.to_string()).collect();
+        anyhow::bail!(
+            "{}: {} (strip with `dotlnx validate --fix`)",
+            bundle_root.display(),
+            list.join("; ")
+        );
+    }
+    for w in arch_warnings(bundle_root, &cfg) {
+        warn!(bundle = %bundle_root.display(), "{}", w);
+    }
+    let confined = cfg.security.as_ref().is_none_or(|s| s.confine);
+    let a11y_bus = cfg.security.as_ref().is_some_and(|s| s.accessibility);
+    if cfg.accessibility.as_ref().is_some_and(|a| a.force_bridges) && confined && !a11y_bus {
+        warn!(
+            bundle = %bundle_root.display(),
+            "[accessibility] force_bridges is set but [security] accessibility is not: the profile blocks the accessibility bus, so screen readers cannot reach the app"
+        );
+    }
+    // The profile maps the driver libraries it can find; say so when some are out of its reach.
+    if cfg.security.as_ref().is_some_and(|s| s.gpu && s.confine) {
+        for w in crate::gpu::detect(Path::new("/")).warnings() {
+            warn!(bundle = %bundle_root.display(), "{}", w);
+        }
+    }
+    Ok(())
+}
+
+/// Most AppImage versions `keep_versions` may hold on to.
+pub const MAX_KEEP_VERSIONS: usize = 20;
+
+/// Files in bin/ whose headers the architecture check reads at most.
+const MAX_BIN_SCAN: usize = 256;
+
+/// Signs the bundle was built for another machine: `arch` not listing this one, an ELF
+/// executable for another architecture, or ELF files in bin/ none of which run here. Warnings,
+/// not errors: the host may run them through binfmt/qemu.
+pub fn arch_warnings(bundle_root: &Path, cfg: &config::Config) -> Vec<String> {
+    let Some(host) = Arch::host() else {
+        return Vec::new();
+    };
+    let mut warnings = Vec::new();
+    if !cfg.arch.is_empty() && !cfg.arch.iter().any(|a| a.runs_on(host)) {
+        let listed: Vec<&str> = cfg.arch.iter().map(|a| a.as_str()).collect();
+        warnings.push(format!(
+            "built for {} (arch), but this machine is {}",
+            listed.join(", "),
+            host
+        ));
+    }
+    let exe = bundle::resolve_executable(bundle_root, &cfg.executable);
+    if let Some(a) = Arch::of_file(&exe).filter(|a| !a.runs_on(host)) {
+        let shown = exe.strip_prefix(bundle_root).unwrap_or(&exe);
+        warnings.push(format!(
+            "{} is a {} binary; this machine is {}",
+            shown.display(),
+            a,
+            host
+        ));
+        return warnings;
+    }
+    // Wrapper scripts (run.sh) start a binary in bin/: at least one must suit this machine.
+    let found: Vec<Arch> = std::fs::read_dir(bundle_root.join("bin"))
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .take(MAX_BIN_SCAN)
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+        .filter_map(|e| Arch::of_file(&e.path()))
+        .collect();
+    if !found.is_empty() && !found.iter().any(|a| a.runs_on(host)) {
+        let mut names: Vec<&str> = found.iter().map(|a| a.as_str()).collect();
+        names.sort_unstable();
+        names.dedup();
+        warnings.push(format!(
+            "bin/ has only {} binaries; this machine is {}",
+            names.join(", "),
+            host
+        ));
+    }
+    warnings
+}
+
+/// Validate an already-loaded config against its bundle (name, executable, paths, helpers).
+pub fn validate_config(bundle_root: &Path, cfg: &config::Config) -> Result<()> {
     if cfg.name.is_empty() {
         anyhow::bail!("config.toml: name is required");
     }
     validate_app_name(&cfg.name)?;
+    if let Some(vendor) = &cfg.vendor {
+        validate_vendor(vendor)?;
+        validate_app_name(&cfg.app_id()).context("config.toml: vendor and name together")?;
+    }
     if cfg.executable.is_empty() {
         anyhow::bail!("config.toml: executable is required");
     }
     path_stays_in_bundle(&cfg.executable)?;
-    let exe_path = bundle_root.join(&cfg.executable);
-    if !exe_path.exists() {
+    check_rule_path_len("executable", &bundle_root.join(&cfg.executable))?;
+    let wildcard_dir = Path::new(&cfg.executable)
+        .parent()
+        .is_some_and(|d| d.to_string_lossy().contains(['*', '?']));
+    if wildcard_dir {
+        anyhow::bail!(
+            "config.toml: executable may only use wildcards in its file name, not its directory"
+        );
+    }
+    let exe_path = bundle::resolve_executable(bundle_root, &cfg.executable);
+    if !fscache::exists(&exe_path) {
+        if cfg.executable_is_pattern() {
+            anyhow::bail!("no executable matches {}", exe_path.display());
+        }
         anyhow::bail!("executable not found: {}", exe_path.display());
     }
     path_under_bundle(&exe_path, bundle_root)?;
     if let Some(ref wd) = cfg.working_dir {
         path_stays_in_bundle(wd)?;
     }
+    for (i, pattern) in cfg.exclude.iter().enumerate() {
+        path_stays_in_bundle(pattern).with_context(|| format!("config.toml: exclude[{}]", i))?;
+    }
+    let relative_exe = exe_path
+        .strip_prefix(bundle_root)
+        .unwrap_or(Path::new(&cfg.executable));
+    for (what, path) in [
+        ("config.toml", Path::new("config.toml")),
+        ("the executable", relative_exe),
+    ] {
+        if bundle::is_excluded(&cfg.exclude, path) {
+            anyhow::bail!("config.toml: exclude must not cover {}", what);
+        }
+    }
+    if let Some(ref service) = cfg.service {
+        if cfg.kind != config::Kind::Service {
+            anyhow::bail!("config.toml: [service] is only for type = \"service\"");
+        }
+        if let Some(ref user) = service.user {
+            if !is_account_name(user) {
+                anyhow::bail!(
+                    "config.toml: [service] user {:?} is not a valid account name",
+                    user
+                );
+            }
+        }
+    }
+    let listen = cfg
+        .service
+        .as_ref()
+        .map(|s| &s.listen[..])
+        .unwrap_or_default();
+    if !listen.is_empty() && cfg.schedule.is_some() {
+        anyhow::bail!(
+            "config.toml: a service has either [service] listen sockets or a [schedule], not both"
+        );
+    }
+    let network = cfg
+        .security
+        .as_ref()
+        .map(|s| !s.confine || s.network)
+        .unwrap_or(false);
+    if let Some(l) = listen.iter().find(|l| l.is_inet() && !network) {
+        anyhow::bail!(
+            "config.toml: listening on {} needs [security] network = true (or confine = false)",
+            l.to_systemd()
+        );
+    }
+    if let Some(ref schedule) = cfg.schedule {
+        if cfg.kind != config::Kind::Service {
+            anyhow::bail!("config.toml: [schedule] is only for type = \"service\"");
+        }
+        let on_calendar = schedule.on_calendar.trim();
+        if on_calendar.is_empty() || on_calendar.contains(|c: char| c.is_control()) {
+            anyhow::bail!("config.toml: [schedule] on_calendar must be a non-empty single line");
+        }
+    }
+    if let Some(nice) = cfg.nice {
+        // Raising priority needs privileges `dotlnx run` does not have.
+        if !(0..=19).contains(&nice) {
+            anyhow::bail!("config.toml: nice must be between 0 and 19 (got {})", nice);
+        }
+    }
+    for (i, arg) in cfg.args.iter().enumerate() {
+        validate_desktop_string(&format!("args[{}]", i), arg)?;
+    }
     if let Some(ref comment) = cfg.comment {
         validate_desktop_string("comment", comment)?;
     }
+    if let Some(ref generic_name) = cfg.generic_name {
+        validate_desktop_string("generic_name", generic_name)?;
+    }
+    validate_keywords("keywords", &cfg.keywords)?;
+    for (label, names) in [
+        ("allowed_users", &cfg.allowed_users),
+        ("allowed_groups", &cfg.allowed_groups),
+    ] {
+        if let Some(name) = names.iter().find(|n| !is_account_name(n)) {
+            anyhow::bail!(
+                "config.toml: {} {:?} is not a valid user or group name",
+                label,
+                name
+            );
+        }
+    }
+    for (locale, t) in &cfg.translations {
+        if !is_locale(locale) {
+            anyhow::bail!(
+                "config.toml: invalid translations locale {:?} (expected e.g. \"de\", \"pt_BR\" or \"sr@latin\")",
+                locale
+            );
+        }
+        for (key, value) in [
+            ("name", &t.name),
+            ("generic_name", &t.generic_name),
+            ("comment", &t.comment),
+        ] {
+            if let Some(value) = value {
+                validate_desktop_string(&format!("translations.{}.{}", locale, key), value)?;
+            }
+        }
+        validate_keywords(&format!("translations.{}.keywords", locale), &t.keywords)?;
+    }
     if let Some(ref icon) = cfg.icon {
         validate_desktop_string("icon", icon)?;
     }
@@ -109,6 +481,85 @@ pub fn validate_bundle(bundle_root: &Path) -> Result<()> {
             validate_desktop_string(&format!("categories[{}]", i), c)?;
         }
     }
+    for (i, thumbnailer) in cfg.thumbnailers.iter().enumerate() {
+        path_stays_in_bundle(&thumbnailer.executable)?;
+        let program = bundle_root.join(&thumbnailer.executable);
+        if !fscache::is_file(&program) {
+            anyhow::bail!(
+                "config.toml: thumbnailers[{}] not found: {}",
+                i,
+                program.display()
+            );
+        }
+        path_under_bundle(&program, bundle_root)?;
+        for (j, arg) in thumbnailer.args.iter().enumerate() {
+            validate_desktop_string(&format!("thumbnailers[{}].args[{}]", i, j), arg)?;
+        }
+        if thumbnailer.mime_types.is_empty() {
+            anyhow::bail!(
+                "config.toml: thumbnailers[{}] needs at least one mime_types entry",
+                i
+            );
+        }
+        for (j, mime) in thumbnailer.mime_types.iter().enumerate() {
+            if !is_mime_type(mime) {
+                anyhow::bail!(
+                    "config.toml: thumbnailers[{}].mime_types[{}] {:?} is not a MIME type",
+                    i,
+                    j,
+                    mime
+                );
+            }
+        }
+    }
+    if let Some(ref provider) = cfg.search_provider {
+        if cfg.kind != config::Kind::App {
+            anyhow::bail!(
+                "config.toml: [search_provider] needs a menu entry (not type = \"service\")"
+            );
+        }
+        if !is_dbus_name(&provider.bus_name) {
+            anyhow::bail!(
+                "config.toml: [search_provider] bus_name {:?} is not a D-Bus name",
+                provider.bus_name
+            );
+        }
+        if !is_dbus_object_path(&provider.object_path) {
+            anyhow::bail!(
+                "config.toml: [search_provider] object_path {:?} is not a D-Bus object path",
+                provider.object_path
+            );
+        }
+        for (i, arg) in provider.args.iter().enumerate() {
+            validate_desktop_string(&format!("search_provider.args[{}]", i), arg)?;
+        }
+    }
+    if let Some(ref update) = cfg.update {
+        if let Some(ref url) = update.zsync_url {
+            if !url.starts_with("https://")
+                || url.contains(|c: char| c.is_whitespace() || c.is_control())
+            {
+                anyhow::bail!(
+                    "config.toml: [update] zsync_url {:?} must be an https:// URL",
+                    url
+                );
+            }
+        }
+    }
+    if let Some(keep) = cfg.keep_versions {
+        if !(1..=MAX_KEEP_VERSIONS).contains(&keep) {
+            anyhow::bail!(
+                "config.toml: keep_versions must be 1 to {}",
+                MAX_KEEP_VERSIONS
+            );
+        }
+    }
+    for (i, dir) in cfg.data_dirs.iter().enumerate() {
+        validate_data_dir(&format!("data_dirs[{}]", i), dir)?;
+    }
+    if cfg.update.as_ref().is_some_and(|u| u.snapshot_data) && cfg.data_dirs.is_empty() {
+        anyhow::bail!("config.toml: [update] snapshot_data needs data_dirs");
+    }
     if let Some(ref sec) = cfg.security {
         for (i, p) in sec.read_paths.iter().enumerate() {
             validate_security_path(&format!("read_paths[{}]", i), p)?;
@@ -116,15 +567,274 @@ pub fn validate_bundle(bundle_root: &Path) -> Result<()> {
         for (i, p) in sec.write_paths.iter().enumerate() {
             validate_security_path(&format!("write_paths[{}]", i), p)?;
         }
+        for (i, p) in sec.exec_paths.iter().enumerate() {
+            validate_security_path(&format!("exec_paths[{}]", i), p)?;
+        }
+        for (i, p) in sec.persist_paths.iter().enumerate() {
+            validate_persist_path(&format!("persist_paths[{}]", i), p)?;
+        }
+        if !sec.persist_paths.is_empty() && !sec.ephemeral_home {
+            anyhow::bail!("config.toml: persist_paths is only for ephemeral_home = true");
+        }
+        if let Some(ref mode) = sec.seccomp {
+            if *mode == config::Seccomp::Strict && sec.userns == config::Userns::Allow {
+                anyhow::bail!("config.toml: seccomp = \"strict\" refuses the user namespaces userns = true allows");
+            }
+            crate::seccomp::compile(mode, bundle_root).context("config.toml: seccomp")?;
+        }
+        let mut child_names = std::collections::HashSet::new();
+        for (i, helper) in sec.helpers.iter().enumerate() {
+            path_stays_in_bundle(&helper.path)?;
+            let helper_path = bundle_root.join(&helper.path);
+            check_rule_path_len(&format!("helpers[{}]", i), &helper_path)?;
+            if !fscache::is_file(&helper_path) {
+                anyhow::bail!(
+                    "config.toml: helpers[{}] not found: {}",
+                    i,
+                    helper_path.display()
+                );
+            }
+            path_under_bundle(&helper_path, bundle_root)?;
+            if Path::new(&helper.path) == Path::new(&cfg.executable) {
+                anyhow::bail!(
+                    "config.toml: helpers[{}] must not be the main executable",
+                    i
+                );
+            }
+            if !child_names.insert(crate::apparmor::helper_profile_name(&helper.path)) {
+                anyhow::bail!(
+                    "config.toml: helpers[{}] has the same file name as another helper (child profile names must be unique)",
+                    i
+                );
+            }
+            for (j, p) in helper.read_paths.iter().enumerate() {
+                validate_security_path(&format!("helpers[{}].read_paths[{}]", i, j), p)?;
+            }
+            for (j, p) in helper.write_paths.iter().enumerate() {
+                validate_security_path(&format!("helpers[{}].write_paths[{}]", i, j), p)?;
+            }
+        }
     }
     Ok(())
 }
 
+/// Problems that make a user-tier bundle unsafe for the root daemon to trust: files not owned by
+/// the user (or root), or writable by everyone. Checks the Applications dir, the bundle dir,
+/// config.toml, the executable, declared helpers and thumbnailers. Empty means trusted.
+#[cfg(unix)]
+pub fn bundle_trust_issues(
+    apps_dir: &Path,
+    bundle_root: &Path,
+    cfg: &config::Config,
+    owner_uid: u32,
+) -> Vec<String> {
+    use std::os::unix::fs::MetadataExt;
+    let mut issues = Vec::new();
+    let mut check = |label: &str, path: &Path, allow_sticky: bool| {
+        let meta = match fscache::metadata(path) {
+            Ok(m) => m,
+            Err(e) => {
+                issues.push(format!("{}: {}", label, e));
+                return;
+            }
+        };
+        if meta.uid() != owner_uid && meta.uid() != 0 {
+            issues.push(format!(
+                "{} {} is owned by uid {} (expected {})",
+                label,
+                path.display(),
+                meta.uid(),
+                owner_uid
+            ));
+        }
+        let world_writable = meta.mode() & 0o002 != 0;
+        let sticky = meta.mode() & 0o1000 != 0;
+        if world_writable && !(allow_sticky && sticky) {
+            issues.push(format!("{} {} is world-writable", label, path.display()));
+        }
+    };
+    check("applications dir", apps_dir, true);
+    check("bundle", bundle_root, false);
+    check("config.toml", &bundle_root.join("config.toml"), false);
+    check(
+        "executable",
+        &bundle::resolve_executable(bundle_root, &cfg.executable),
+        false,
+    );
+    if let Some(ref sec) = cfg.security {
+        for helper in &sec.helpers {
+            check("helper", &bundle_root.join(&helper.path), false);
+        }
+    }
+    for thumbnailer in &cfg.thumbnailers {
+        check(
+            "thumbnailer",
+            &bundle_root.join(&thumbnailer.executable),
+            false,
+        );
+    }
+    issues
+}
+
+#[cfg(not(unix))]
+pub fn bundle_trust_issues(
+    _apps_dir: &Path,
+    _bundle_root: &Path,
+    _cfg: &config::Config,
+    _owner_uid: u32,
+) -> Vec<String> {
+    Vec::new()
+}
+
+/// A bundle file that would run with more privileges than its caller: setuid/setgid bits or
+/// file capabilities (security.capability). A root service installing user-provided folders
+/// must not hand such files a menu entry or a profile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrivilegedFile {
+    /// Relative to the bundle.
+    pub path: PathBuf,
+    pub setuid: bool,
+    pub setgid: bool,
+    pub capabilities: bool,
+}
+
+impl std::fmt::Display for PrivilegedFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let what: Vec<&str> = [
+            (self.setuid, "setuid"),
+            (self.setgid, "setgid"),
+            (self.capabilities, "file capabilities"),
+        ]
+        .into_iter()
+        .filter_map(|(set, name)| set.then_some(name))
+        .collect();
+        write!(f, "{} has {}", self.path.display(), what.join(" and "))
+    }
+}
+
+#[cfg(target_os = "linux")]
+const CAPABILITY_XATTR: &std::ffi::CStr = c"security.capability";
+
+/// True when `path` (not followed if a symlink) carries file capabilities.
+#[cfg(target_os = "linux")]
+fn has_capabilities(path: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(c) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: both strings are NUL-terminated; a zero-size query writes nothing.
+    unsafe {
+        nix::libc::lgetxattr(
+            c.as_ptr(),
+            CAPABILITY_XATTR.as_ptr(),
+            std::ptr::null_mut(),
+            0,
+        ) >= 0
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn has_capabilities(_path: &Path) -> bool {
+    false
+}
+
+/// Every regular file in the bundle with setuid/setgid bits or file capabilities. The whole tree
+/// is walked (no cap: a limit would be a place to hide one); symlinks are not followed.
+#[cfg(unix)]
+pub fn privileged_files(bundle_root: &Path) -> Vec<PrivilegedFile> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut found = Vec::new();
+    let entries = walkdir::WalkDir::new(bundle_root)
+        .follow_links(false)
+        .sort_by_file_name();
+    for entry in entries.into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let Ok(meta) = entry.metadata() else { continue };
+        let mode = meta.permissions().mode();
+        // Capabilities only matter on something that can be executed.
+        let capabilities = mode & 0o111 != 0 && has_capabilities(entry.path());
+        if mode & 0o6000 != 0 || capabilities {
+            found.push(PrivilegedFile {
+                path: entry
+                    .path()
+                    .strip_prefix(bundle_root)
+                    .unwrap_or(entry.path())
+                    .to_path_buf(),
+                setuid: mode & 0o4000 != 0,
+                setgid: mode & 0o2000 != 0,
+                capabilities,
+            });
+        }
+    }
+    found
+}
+
+#[cfg(not(unix))]
+pub fn privileged_files(_bundle_root: &Path) -> Vec<PrivilegedFile> {
+    Vec::new()
+}
+
+/// Drop the setuid/setgid bits and file capabilities of `files` (from [`privileged_files`]).
+/// Removing capabilities needs CAP_SETFCAP (root).
+#[cfg(unix)]
+pub fn strip_privileges(bundle_root: &Path, files: &[PrivilegedFile]) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    for f in files {
+        let path = bundle_root.join(&f.path);
+        let meta = std::fs::symlink_metadata(&path)?;
+        if !meta.is_file() {
+            anyhow::bail!("{} is no longer a regular file", path.display());
+        }
+        if f.setuid || f.setgid {
+            let mode = meta.permissions().mode() & !0o6000;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))
+                .with_context(|| format!("chmod {}", path.display()))?;
+        }
+        #[cfg(target_os = "linux")]
+        if f.capabilities {
+            use std::os::unix::ffi::OsStrExt;
+            let c = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+            // SAFETY: both strings are NUL-terminated.
+            if unsafe { nix::libc::lremovexattr(c.as_ptr(), CAPABILITY_XATTR.as_ptr()) } != 0 {
+                let e = std::io::Error::last_os_error();
+                anyhow::bail!("remove file capabilities of {}: {}", path.display(), e);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn strip_privileges(_bundle_root: &Path, _files: &[PrivilegedFile]) -> Result<()> {
+    Ok(())
+}
+
+/// Longest app name, in bytes. Keeps dotlnx-<user>-<name>.desktop and profile file names well
+/// under NAME_MAX (255).
+pub const MAX_APP_NAME_LEN: usize = 128;
+/// Longest vendor, in bytes. The vendor and name together are held to `MAX_APP_NAME_LEN`.
+const MAX_VENDOR_LEN: usize = 64;
+
 /// App name must be safe for profile names and .desktop Exec (no path sep, no injection chars).
 pub fn validate_app_name(name: &str) -> Result<()> {
     if name.is_empty() {
         anyhow::bail!("app name must not be empty");
     }
+    if name.len() > MAX_APP_NAME_LEN {
+        anyhow::bail!(
+            "app name must be at most {} bytes (got {})",
+            MAX_APP_NAME_LEN,
+            name.len()
+        );
+    }
+    if name.starts_with('-') {
+        anyhow::bail!("app name must not start with - (would be taken for a command-line option)");
+    }
+    if name.trim() != name {
+        anyhow::bail!("app name must not start or end with whitespace");
+    }
     if name.contains('/') || name.contains('\\') || name.contains("..") {
         anyhow::bail!("app name must not contain path separators or ..");
     }
@@ -137,11 +847,255 @@ pub fn validate_app_name(name: &str) -> Result<()> {
     Ok(())
 }
 
+/// A vendor is a reverse-DNS name ("com.example"): dot-separated labels of letters, digits, - and
+/// _, so it reads the same in file names, profile names and `dotlnx run` arguments.
+pub fn validate_vendor(vendor: &str) -> Result<()> {
+    let label_ok = |l: &str| {
+        !l.is_empty()
+            && !l.starts_with('-')
+            && l.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    };
+    if vendor.len() > MAX_VENDOR_LEN || !vendor.split('.').all(label_ok) {
+        anyhow::bail!(
+            "config.toml: vendor {:?} must be a reverse-DNS name like \"com.example\" (letters, digits, - and _ between dots, at most {} bytes)",
+            vendor,
+            MAX_VENDOR_LEN
+        );
+    }
+    Ok(())
+}
+
+/// App names claimed in one tier. Two apps of a tier must not share a menu entry (same name) or
+/// an AppArmor profile (names that map to the same profile name); the first claim wins.
+#[derive(Debug, Default)]
+pub struct NameClaims {
+    by_profile: HashMap<String, (String, PathBuf)>,
+}
+
+impl NameClaims {
+    /// Claim `name` for `bundle`, or say which bundle already has it.
+    pub fn claim(&mut self, name: &str, bundle: &Path) -> Result<()> {
+        match self
+            .by_profile
+            .entry(crate::apparmor::profile_name_system("", name))
+        {
+            Entry::Vacant(v) => {
+                v.insert((name.to_string(), bundle.to_path_buf()));
+                Ok(())
+            }
+            Entry::Occupied(o) => {
+                let (other, other_bundle) = o.get();
+                if other == name {
+                    anyhow::bail!(
+                        "app name {:?} is already used by {}",
+                        name,
+                        other_bundle.display()
+                    );
+                }
+                anyhow::bail!(
+                    "app name {:?} maps to the same AppArmor profile as {:?} ({})",
+                    name,
+                    other,
+                    other_bundle.display()
+                )
+            }
+        }
+    }
+}
+
+/// Outcome of `dotlnx validate` for one bundle.
+#[derive(Debug, Default, Serialize)]
+pub struct Validated {
+    pub bundle: PathBuf,
+    /// App name, when the config could be read.
+    pub name: Option<String>,
+    pub valid: bool,
+    pub error: Option<String>,
+    /// What `--fix` stripped.
+    pub stripped: Vec<String>,
+    /// With `--check-deps`: libraries found neither in the bundle nor on this host, as
+    /// "file: library".
+    pub missing_libraries: Vec<String>,
+    /// With `--check-deps`: libraries found on this host only.
+    pub host_libraries: Vec<String>,
+}
+
+/// Validate one or more .lnx bundles (path can be a .lnx dir or a dir containing .lnx dirs),
+/// print the result for each, and fail when any is invalid or lacks libraries.
+/// With `fix`, setuid/setgid bits and file capabilities are stripped first.
+pub fn run(path: &Path, check_deps: bool, fix: bool, json: bool) -> Result<()> {
+    if !path.exists() {
+        anyhow::bail!("path does not exist: {}", path.display());
+    }
+    let mut bundles = Vec::new();
+    if bundle::is_lnx_bundle(path) {
+        bundles.push(path.to_path_buf());
+    } else if path.is_dir() {
+        bundles = bundle::discover_lnx_dirs(path);
+    } else {
+        anyhow::bail!("path is not a .lnx bundle or directory: {}", path.display());
+    }
+    if bundles.is_empty() {
+        anyhow::bail!("no .lnx bundles found at {}", path.display());
+    }
+    let host_dirs = check_deps.then(deps::host_lib_dirs);
+    let mut claims = NameClaims::default();
+    let mut results = Vec::new();
+    for b in &bundles {
+        let mut result = Validated {
+            bundle: b.clone(),
+            ..Validated::default()
+        };
+        if fix {
+            let privileged = privileged_files(b);
+            strip_privileges(b, &privileged)?;
+            result.stripped = privileged.iter().map(|f| f.to_string()).collect();
+        }
+        let checked = validate_bundle(b)
+            .and_then(|_| config::load(b))
+            .and_then(|cfg| {
+                claims.claim(&cfg.name, b)?;
+                Ok(cfg)
+            });
+        match checked {
+            Ok(cfg) => (result.name, result.valid) = (Some(cfg.name), true),
+            Err(e) => result.error = Some(format!("{:#}", e)),
+        }
+        if let (Some(dirs), true) = (&host_dirs, result.valid) {
+            for r in deps::check(b, dirs)? {
+                result.missing_libraries.extend(
+                    r.missing
+                        .iter()
+                        .map(|lib| format!("{}: {}", r.file.display(), lib)),
+                );
+                result.host_libraries.extend(
+                    r.vendor
+                        .iter()
+                        .map(|lib| format!("{}: {}", r.file.display(), lib)),
+                );
+            }
+        }
+        results.push(result);
+    }
+    let invalid = results.iter().filter(|r| !r.valid).count();
+    let missing: usize = results.iter().map(|r| r.missing_libraries.len()).sum();
+    if json {
+        output::json(
+            &serde_json::json!({ "valid": invalid == 0 && missing == 0, "bundles": results }),
+        )?;
+    } else {
+        print_results(&results, check_deps);
+    }
+    if let Some(first) = results.iter().find(|r| !r.valid) {
+        let error = format!(
+            "{}: {}",
+            first.bundle.display(),
+            first.error.as_deref().unwrap_or_default()
+        );
+        match invalid {
+            1 => anyhow::bail!("{}", error),
+            n => anyhow::bail!(
+                "{} of {} bundles are invalid, first {}",
+                n,
+                results.len(),
+                error
+            ),
+        }
+    }
+    if missing > 0 {
+        anyhow::bail!(
+            "{} shared librar{} not found",
+            missing,
+            if missing == 1 { "y" } else { "ies" }
+        );
+    }
+    Ok(())
+}
+
+fn print_results(results: &[Validated], check_deps: bool) {
+    for r in results {
+        let shown = r.bundle.display();
+        for f in &r.stripped {
+            println!("{}: stripped: {}", shown, f);
+        }
+        match r.error {
+            Some(ref e) => println!(
+                "{}: {}",
+                shown,
+                output::paint(&format!("invalid: {}", e), Style::Bad)
+            ),
+            None => println!("{}: {}", shown, output::paint("valid", Style::Good)),
+        }
+        if !check_deps || !r.valid {
+            continue;
+        }
+        if r.missing_libraries.is_empty() && r.host_libraries.is_empty() {
+            println!("  all shared libraries found");
+        }
+        for lib in &r.missing_libraries {
+            println!(
+                "  {}",
+                output::paint(&format!("missing {}", lib), Style::Bad)
+            );
+        }
+        for lib in &r.host_libraries {
+            println!(
+                "  {}",
+                output::paint(
+                    &format!("found on this host only; consider bundling it: {}", lib),
+                    Style::Warn
+                )
+            );
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::path::Path;
 
+    #[cfg(unix)]
+    #[test]
+    fn privileged_files_are_refused_and_stripped() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let b = dir.path().join("App.lnx");
+        std::fs::create_dir_all(b.join("bin")).unwrap();
+        std::fs::write(
+            b.join("config.toml"),
+            "name = \"App\"\nexecutable = \"bin/app\"\n",
+        )
+        .unwrap();
+        for (name, mode) in [("bin/app", 0o755), ("bin/su", 0o4755), ("bin/wall", 0o2755)] {
+            std::fs::write(b.join(name), "#!/bin/sh\n").unwrap();
+            std::fs::set_permissions(b.join(name), std::fs::Permissions::from_mode(mode)).unwrap();
+        }
+        // A setgid directory is ordinary (group-shared folders).
+        std::fs::create_dir(b.join("shared")).unwrap();
+        std::fs::set_permissions(b.join("shared"), std::fs::Permissions::from_mode(0o2775))
+            .unwrap();
+
+        let found = privileged_files(&b);
+        let described: Vec<String> = found.iter().map(|f| f.to_string()).collect();
+        assert_eq!(described, ["bin/su has setuid", "bin/wall has setgid"]);
+        let err = validate_bundle(&b).unwrap_err().to_string();
+        assert!(
+            err.contains("bin/su has setuid") && err.contains("--fix"),
+            "{}",
+            err
+        );
+
+        run(&b, false, true, false).unwrap();
+        assert!(privileged_files(&b).is_empty());
+        let mode = std::fs::metadata(b.join("bin/su"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o7777, 0o755);
+    }
+
     #[test]
     fn validate_app_name_ok() {
         assert!(validate_app_name("myapp").is_ok());
@@ -156,6 +1110,137 @@ mod tests {
         assert!(validate_app_name("a..b").is_err());
         assert!(validate_app_name("a;b").is_err());
         assert!(validate_app_name("a\nb").is_err());
+        assert!(validate_app_name("-app").is_err());
+        assert!(validate_app_name(" app").is_err());
+        assert!(validate_app_name(&"a".repeat(MAX_APP_NAME_LEN + 1)).is_err());
+        assert!(validate_app_name(&"a".repeat(MAX_APP_NAME_LEN)).is_ok());
+    }
+
+    #[test]
+    fn persist_paths_are_plain_paths_in_the_home() {
+        for ok in [".config/MyApp", "Documents/MyApp", ".local/share/MyApp/"] {
+            assert!(
+                validate_persist_path("persist_paths[0]", ok).is_ok(),
+                "{}",
+                ok
+            );
+        }
+        for bad in [
+            "/etc",
+            "@{HOME}/.config",
+            "~/.config",
+            ".config/*",
+            "../x",
+            ".",
+            "",
+            "a,b",
+        ] {
+            assert!(
+                validate_persist_path("persist_paths[0]", bad).is_err(),
+                "{}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn data_dirs_are_plain_paths() {
+        for ok in [
+            ".config/MyApp",
+            "/var/lib/myapp",
+            "/srv/myapp/data",
+            "/opt/myapp",
+            "Documents/My App",
+        ] {
+            assert!(validate_data_dir("data_dirs[0]", ok).is_ok(), "{}", ok);
+        }
+        for bad in [
+            "",
+            "/",
+            ".",
+            "~/.config",
+            "@{HOME}/x",
+            ".config/*",
+            "../x",
+            "a\nb",
+        ] {
+            assert!(validate_data_dir("data_dirs[0]", bad).is_err(), "{}", bad);
+        }
+        // Rollback renames and rewrites them as root: only an app's own directory.
+        for bad in [
+            "/etc",
+            "/usr",
+            "/var",
+            "/var/lib",
+            "/var/lib/.",
+            "/var/library",
+            "/home/alice/.config/x",
+        ] {
+            assert!(validate_data_dir("data_dirs[0]", bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn vendors_are_reverse_dns_names() {
+        for ok in ["com.example", "org.kde", "io.github.some_one", "acme"] {
+            assert!(validate_vendor(ok).is_ok(), "{}", ok);
+        }
+        for bad in [
+            "",
+            "com.",
+            ".com",
+            "com..example",
+            "-com.example",
+            "com example",
+            "com/example",
+            "ex\u{e4}mple.com",
+        ] {
+            assert!(validate_vendor(bad).is_err(), "{}", bad);
+        }
+        let cfg = config::parse(
+            "name = \"Editor\"\nvendor = \"com.example\"\nexecutable = \"bin/app\"\n",
+        )
+        .unwrap();
+        assert_eq!(cfg.app_id(), "com.example.Editor");
+        assert!(cfg.answers_to("Editor") && cfg.answers_to("com.example.Editor"));
+        assert!(!cfg.answers_to("example.Editor"));
+    }
+
+    #[test]
+    fn overlong_paths_are_rejected() {
+        let long = format!("/{}", "a".repeat(crate::apparmor::MAX_RULE_PATH_LEN));
+        let err = validate_security_path("read_paths[0]", &long).unwrap_err();
+        assert!(err.to_string().contains("longer than"), "{}", err);
+        assert!(check_rule_path_len("executable", Path::new(&long)).is_err());
+        assert!(check_rule_path_len("executable", Path::new("/A/x.lnx/bin/x")).is_ok());
+    }
+
+    #[test]
+    fn name_claims_reject_shared_names_and_profiles() {
+        let mut claims = NameClaims::default();
+        claims.claim("my_app", Path::new("/A/one.lnx")).unwrap();
+        // Distinct names whose sanitized forms match still get their own profile.
+        claims.claim("my app", Path::new("/A/two.lnx")).unwrap();
+        let err = claims
+            .claim("my_app", Path::new("/A/three.lnx"))
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("already used by /A/one.lnx"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn validate_dir_rejects_duplicate_names() {
+        let parent = tempfile::tempdir().unwrap();
+        for dir in ["a.lnx", "b.lnx"] {
+            let b = parent.path().join(dir);
+            std::fs::create_dir_all(&b).unwrap();
+            make_valid_bundle(&b, "same", "bin/app");
+        }
+        let err = run(parent.path(), false, false, false).unwrap_err();
+        assert!(err.to_string().contains("already used by"), "{}", err);
     }
 
     #[test]
@@ -178,12 +1263,14 @@ mod tests {
     }
 
     fn make_valid_bundle(root: &Path, name: &str, executable: &str) {
-        std::fs::create_dir_all(root.join(Path::new(executable).parent().unwrap_or(Path::new("."))))
-            .unwrap();
+        std::fs::create_dir_all(
+            root.join(Path::new(executable).parent().unwrap_or(Path::new("."))),
+        )
+        .unwrap();
         std::fs::write(root.join(executable), "#!/bin/sh\nexit 0").unwrap();
         std::fs::write(
             root.join("config.toml"),
-            &format!(
+            format!(
                 r#"
 name = "{}"
 executable = "{}"
@@ -203,6 +1290,27 @@ executable = "{}"
         assert!(validate_bundle(&bundle).is_ok());
     }
 
+    #[test]
+    fn keywords_and_translations_are_checked() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("myapp.lnx");
+        make_valid_bundle(&bundle, "myapp", "bin/myapp");
+        let check = |extra: &str| {
+            let cfg: config::Config = toml::from_str(&format!(
+                "name = \"myapp\"\nexecutable = \"bin/myapp\"\n{}",
+                extra
+            ))
+            .unwrap();
+            validate_config(&bundle, &cfg)
+        };
+        check("keywords = [\"web\"]\n[translations.pt_BR]\nname = \"Navegador\"\nkeywords = [\"rede\"]\n").unwrap();
+        check("[translations.\"sr@latin\"]\ncomment = \"x\"\n").unwrap();
+        assert!(check("keywords = [\"a;b\"]\n").is_err());
+        assert!(check("[translations.\"de]\"]\nname = \"x\"\n").is_err());
+        assert!(check("[translations.de]\nname = \"a\\nb\"\n").is_err());
+        assert!(!is_locale("x") && !is_locale("DE") && is_locale("de_DE.UTF-8"));
+    }
+
     #[test]
     fn validate_bundle_not_lnx_dir_err() {
         let dir = tempfile::tempdir().unwrap();
@@ -232,32 +1340,288 @@ executable = "bin/nonexistent"
         let parent = tempfile::tempdir().unwrap();
         let bundle = parent.path().join("myapp.lnx");
         std::fs::create_dir_all(&bundle).unwrap();
-        std::fs::write(bundle.join("config.toml"), r#"name = "bad/name"\nexecutable = "bin/app"\n"#).unwrap();
+        std::fs::write(
+            bundle.join("config.toml"),
+            r#"name = "bad/name"\nexecutable = "bin/app"\n"#,
+        )
+        .unwrap();
         std::fs::create_dir_all(bundle.join("bin")).unwrap();
         std::fs::write(bundle.join("bin/app"), "x").unwrap();
         let err = validate_bundle(&bundle).unwrap_err();
         assert!(err.to_string().contains("name"));
     }
-}
 
-/// Validate one or more .lnx bundles (path can be a .lnx dir or a dir containing .lnx dirs).
-pub fn run(path: &Path) -> Result<()> {
-    if !path.exists() {
-        anyhow::bail!("path does not exist: {}", path.display());
+    #[test]
+    fn validate_bundle_helper_checks() {
+        let parent = tempfile::tempdir().unwrap();
+        let bundle = parent.path().join("myapp.lnx");
+        std::fs::create_dir_all(&bundle).unwrap();
+        make_valid_bundle(&bundle, "myapp", "bin/myapp");
+        std::fs::write(bundle.join("bin/ffmpeg"), "x").unwrap();
+        let base = "name = \"myapp\"\nexecutable = \"bin/myapp\"\n";
+        std::fs::write(
+            bundle.join("config.toml"),
+            format!("{}[[security.helpers]]\npath = \"bin/ffmpeg\"\n", base),
+        )
+        .unwrap();
+        assert!(validate_bundle(&bundle).is_ok());
+
+        std::fs::write(
+            bundle.join("config.toml"),
+            format!("{}[[security.helpers]]\npath = \"bin/missing\"\n", base),
+        )
+        .unwrap();
+        assert!(validate_bundle(&bundle)
+            .unwrap_err()
+            .to_string()
+            .contains("helpers[0]"));
+
+        std::fs::write(
+            bundle.join("config.toml"),
+            format!("{}[[security.helpers]]\npath = \"bin/myapp\"\n", base),
+        )
+        .unwrap();
+        assert!(validate_bundle(&bundle)
+            .unwrap_err()
+            .to_string()
+            .contains("main executable"));
     }
-    let mut bundles = Vec::new();
-    if bundle::is_lnx_bundle(path) {
-        bundles.push(path.to_path_buf());
-    } else if path.is_dir() {
-        bundles = bundle::discover_lnx_dirs(path);
-    } else {
-        anyhow::bail!("path is not a .lnx bundle or directory: {}", path.display());
+
+    #[test]
+    fn validate_bundle_service_checks() {
+        let parent = tempfile::tempdir().unwrap();
+        let bundle = parent.path().join("myapp.lnx");
+        std::fs::create_dir_all(&bundle).unwrap();
+        make_valid_bundle(&bundle, "myapp", "bin/myapp");
+        let write = |extra: &str| {
+            let cfg = format!("name = \"myapp\"\nexecutable = \"bin/myapp\"\n{}", extra);
+            std::fs::write(bundle.join("config.toml"), cfg).unwrap();
+        };
+        write("type = \"service\"\n[service]\nuser = \"www-data\"\n");
+        assert!(validate_bundle(&bundle).is_ok());
+        write("type = \"service\"\n[service]\nuser = \"root\\nx\"\n");
+        assert!(validate_bundle(&bundle)
+            .unwrap_err()
+            .to_string()
+            .contains("account name"));
+        write("[service]\nrestart = \"always\"\n");
+        assert!(validate_bundle(&bundle)
+            .unwrap_err()
+            .to_string()
+            .contains("type"));
+        write("type = \"service\"\n[schedule]\non_calendar = \"daily\"\n");
+        assert!(validate_bundle(&bundle).is_ok());
+        write("type = \"service\"\n[schedule]\non_calendar = \" \"\n");
+        assert!(validate_bundle(&bundle)
+            .unwrap_err()
+            .to_string()
+            .contains("on_calendar"));
+        write("[schedule]\non_calendar = \"daily\"\n");
+        assert!(validate_bundle(&bundle)
+            .unwrap_err()
+            .to_string()
+            .contains("type"));
+        write("type = \"service\"\n[service]\nlisten = [\"/run/a.sock\"]\n");
+        assert!(validate_bundle(&bundle).is_ok());
+        write("type = \"service\"\n[service]\nlisten = [\"8080\"]\n");
+        assert!(validate_bundle(&bundle)
+            .unwrap_err()
+            .to_string()
+            .contains("network"));
+        write("type = \"service\"\n[service]\nlisten = [\"8080\"]\n[security]\nnetwork = true\n");
+        assert!(validate_bundle(&bundle).is_ok());
+        write("type = \"service\"\n[service]\nlisten = [\"/run/a.sock\"]\n[schedule]\non_calendar = \"daily\"\n");
+        assert!(validate_bundle(&bundle)
+            .unwrap_err()
+            .to_string()
+            .contains("not both"));
     }
-    if bundles.is_empty() {
-        anyhow::bail!("no .lnx bundles found at {}", path.display());
+
+    #[test]
+    fn validate_bundle_integration_checks() {
+        let parent = tempfile::tempdir().unwrap();
+        let bundle = parent.path().join("myapp.lnx");
+        std::fs::create_dir_all(&bundle).unwrap();
+        make_valid_bundle(&bundle, "myapp", "bin/myapp");
+        std::fs::write(bundle.join("bin/thumb"), "x").unwrap();
+        let write = |extra: &str| {
+            let cfg = format!("name = \"myapp\"\nexecutable = \"bin/myapp\"\n{}", extra);
+            std::fs::write(bundle.join("config.toml"), cfg).unwrap();
+        };
+        let thumbnailer = "[[thumbnailers]]\nexecutable = \"bin/thumb\"\nargs = [\"-s\", \"%s\", \"%i\", \"%o\"]\n";
+        write(&format!(
+            "{}mime_types = [\"image/x-example\"]\n",
+            thumbnailer
+        ));
+        assert!(validate_bundle(&bundle).is_ok());
+        write(&format!(
+            "{}mime_types = [\"image/png;text/plain\"]\n",
+            thumbnailer
+        ));
+        assert!(validate_bundle(&bundle)
+            .unwrap_err()
+            .to_string()
+            .contains("MIME type"));
+        write(&format!("{}mime_types = []\n", thumbnailer));
+        assert!(validate_bundle(&bundle)
+            .unwrap_err()
+            .to_string()
+            .contains("mime_types"));
+        write("[[thumbnailers]]\nexecutable = \"bin/missing\"\nmime_types = [\"image/png\"]\n");
+        assert!(validate_bundle(&bundle)
+            .unwrap_err()
+            .to_string()
+            .contains("thumbnailers[0]"));
+
+        let provider = |name: &str, path: &str| {
+            format!(
+                "[search_provider]\nbus_name = \"{}\"\nobject_path = \"{}\"\n",
+                name, path
+            )
+        };
+        write(&provider(
+            "org.example.MyApp.SearchProvider",
+            "/org/example/MyApp/SearchProvider",
+        ));
+        assert!(validate_bundle(&bundle).is_ok());
+        write(&provider("MyApp", "/org/example/MyApp"));
+        assert!(validate_bundle(&bundle)
+            .unwrap_err()
+            .to_string()
+            .contains("bus_name"));
+        write(&provider("org.example.1App", "/org/example/MyApp"));
+        assert!(validate_bundle(&bundle)
+            .unwrap_err()
+            .to_string()
+            .contains("bus_name"));
+        write(&provider("org.example.MyApp", "/org/example//MyApp"));
+        assert!(validate_bundle(&bundle)
+            .unwrap_err()
+            .to_string()
+            .contains("object_path"));
+        write(&format!(
+            "type = \"service\"\n{}",
+            provider("org.example.MyApp", "/org/example/MyApp")
+        ));
+        assert!(validate_bundle(&bundle)
+            .unwrap_err()
+            .to_string()
+            .contains("service"));
     }
-    for b in &bundles {
-        validate_bundle(b)?;
+
+    #[test]
+    fn validate_bundle_seccomp_checks() {
+        if std::env::consts::ARCH != "x86_64" && std::env::consts::ARCH != "aarch64" {
+            return;
+        }
+        let parent = tempfile::tempdir().unwrap();
+        let bundle = parent.path().join("myapp.lnx");
+        std::fs::create_dir_all(&bundle).unwrap();
+        make_valid_bundle(&bundle, "myapp", "bin/myapp");
+        let write = |sec: &str| {
+            let cfg = format!(
+                "name = \"myapp\"\nexecutable = \"bin/myapp\"\n[security]\n{}",
+                sec
+            );
+            std::fs::write(bundle.join("config.toml"), cfg).unwrap();
+        };
+        write("seccomp = \"strict\"\n");
+        assert!(validate_bundle(&bundle).is_ok());
+        write("seccomp = \"strict\"\nuserns = true\n");
+        assert!(validate_bundle(&bundle)
+            .unwrap_err()
+            .to_string()
+            .contains("userns"));
+        write("seccomp = \"filter.json\"\n");
+        assert!(validate_bundle(&bundle)
+            .unwrap_err()
+            .to_string()
+            .contains("seccomp"));
+        std::fs::write(bundle.join("filter.json"), "{\"main\": {\"mismatch_action\": \"allow\", \"filter_action\": \"log\", \"filter\": []}}").unwrap();
+        assert!(validate_bundle(&bundle).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn bundle_trust_issues_flags_world_writable() {
+        use std::os::unix::fs::PermissionsExt;
+        let parent = tempfile::tempdir().unwrap();
+        let bundle = parent.path().join("myapp.lnx");
+        std::fs::create_dir_all(&bundle).unwrap();
+        make_valid_bundle(&bundle, "myapp", "bin/myapp");
+        let cfg = config::load(&bundle).unwrap();
+        let uid = nix::unistd::geteuid().as_raw();
+        assert!(bundle_trust_issues(parent.path(), &bundle, &cfg, uid).is_empty());
+
+        std::fs::set_permissions(
+            bundle.join("bin/myapp"),
+            std::fs::Permissions::from_mode(0o777),
+        )
+        .unwrap();
+        let issues = bundle_trust_issues(parent.path(), &bundle, &cfg, uid);
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].contains("world-writable"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn bundle_trust_issues_flags_wrong_owner() {
+        let parent = tempfile::tempdir().unwrap();
+        let bundle = parent.path().join("myapp.lnx");
+        std::fs::create_dir_all(&bundle).unwrap();
+        make_valid_bundle(&bundle, "myapp", "bin/myapp");
+        let cfg = config::load(&bundle).unwrap();
+        let uid = nix::unistd::geteuid().as_raw();
+        if uid == 0 {
+            // Root-owned files are always accepted; nothing to flag when tests run as root.
+            return;
+        }
+        let issues = bundle_trust_issues(parent.path(), &bundle, &cfg, uid + 1);
+        assert!(issues.iter().any(|i| i.contains("owned by uid")));
+    }
+
+    #[test]
+    fn arch_warnings_for_other_machines() {
+        let Some(host) = Arch::host() else {
+            return;
+        };
+        let other = if host == Arch::Aarch64 {
+            "x86_64"
+        } else {
+            "aarch64"
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("A.lnx");
+        std::fs::create_dir_all(bundle.join("bin")).unwrap();
+        std::fs::write(bundle.join("run.sh"), "#!/bin/sh\n").unwrap();
+        let cfg = |extra: &str| {
+            config::parse(&format!("name = \"A\"\nexecutable = \"run.sh\"\n{}", extra)).unwrap()
+        };
+        assert!(arch_warnings(&bundle, &cfg("")).is_empty());
+        let w = arch_warnings(&bundle, &cfg(&format!("arch = [\"{}\"]\n", other)));
+        assert!(w.len() == 1 && w[0].contains(other), "{:?}", w);
+        assert!(arch_warnings(
+            &bundle,
+            &cfg(&format!("arch = [\"{}\", \"{}\"]\n", other, host))
+        )
+        .is_empty());
+
+        // An ELF binary in bin/ for this machine is fine, a foreign one alone is not.
+        std::fs::copy(std::env::current_exe().unwrap(), bundle.join("bin/native")).unwrap();
+        assert!(arch_warnings(&bundle, &cfg("")).is_empty());
+        std::fs::remove_file(bundle.join("bin/native")).unwrap();
+        let mut foreign = vec![0x7f, b'E', b'L', b'F', 2, 1];
+        foreign.resize(18, 0);
+        foreign.extend(
+            if host == Arch::Aarch64 {
+                0x3eu16
+            } else {
+                0xb7u16
+            }
+            .to_le_bytes(),
+        );
+        std::fs::write(bundle.join("bin/foreign"), &foreign).unwrap();
+        let w = arch_warnings(&bundle, &cfg(""));
+        assert!(w.len() == 1 && w[0].contains("bin/"), "{:?}", w);
     }
-    Ok(())
 }
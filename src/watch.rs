@@ -1,52 +1,807 @@
 //! Watch ~/Applications and /Applications; on .lnx add/remove/change, run sync (make state match folders).
 //! When run as root (daemon), watches all users' ~/Applications (/home/*/Applications, /root/Applications) and /Applications.
+//! Each bundle's top level is watched too, so editing config.toml or run.sh re-syncs that app, and
+//! a packed bundle (`.lnx.tar.zst`, `.lnx.zip`) dropped into an Applications dir is unpacked.
+//! Syncs run on a worker thread: the main loop keeps taking events, signals and watchdog pings
+//! while apparmor_parser works, and stops a sync early when newer changes supersede it.
 
 use anyhow::Result;
-use std::sync::mpsc;
-use std::time::Duration;
-use tracing::{error, warn};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
 
-use notify::{Config, Event, RecommendedWatcher, RecursiveMode, Watcher};
+use notify::event::{
+    AccessKind, AccessMode, CreateKind, EventKind, MetadataKind, ModifyKind, RenameMode,
+};
+use notify::{Config, Event, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
 
 use crate::bundle;
+use crate::pack;
+use crate::paths::Paths;
+use crate::sdnotify;
+use crate::settings;
 use crate::sync;
 
+/// Quiet period after the last event before syncing.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+/// Creating this file in an Applications dir asks a running watcher for a sync (`dotlnx tray`).
+pub const SYNC_REQUEST: &str = ".dotlnx-sync";
+/// Longest we keep waiting for a bundle's config.toml to reappear (editor rename in flight).
+const SETTLE_LIMIT: Duration = Duration::from_secs(5);
+
+/// Everything the main loop waits on: file events and the daemon's control signals.
+enum Msg {
+    Fs(Result<Event, notify::Error>),
+    /// SIGTERM/SIGINT: finish what is running, then exit cleanly.
+    Shutdown,
+    /// SIGHUP: reload site settings, re-enumerate users, rebuild watches, then sync.
+    Reload,
+    /// The background sync finished (Ok(None): it was cancelled).
+    SyncDone(Result<Option<sync::Summary>>),
+}
+
+/// The sync running on the worker thread.
+struct Running {
+    control: Arc<sync::Control>,
+    /// Started for SIGHUP: report READY=1 when it is done.
+    reload: bool,
+}
+
 /// Run the watcher. If `once` is true, run one full sync then exit (for service startup).
+/// Under systemd (Type=notify) it reports READY=1 after the initial sync, keeps a status line
+/// with the last sync's counts, and pings the watchdog from the main loop.
 pub fn run(once: bool) -> Result<()> {
     if once {
-        return sync::run(false);
+        return sync::run(false).map(|_| ());
     }
     let (tx, rx) = mpsc::channel();
-    let mut watcher = RecommendedWatcher::new(
-        move |res: Result<Event, notify::Error>| {
-            let _ = tx.send(res);
-        },
-        Config::default(),
-    )?;
+    spawn_signal_listener(tx.clone())?;
+    let mut watchdog = sdnotify::Watchdog::from_env();
+    let (mut watch_settings, mut paths) = load_settings();
+    let mut watches = Watches::new(tx.clone(), &watch_settings, paths.clone())?;
+    watches.refresh()?;
+    run_sync("initial");
+    sdnotify::notify("READY=1");
+    let mut next_reconcile = reconcile_deadline(&watch_settings);
+    let mut running: Option<Running> = None;
+    // Sync to start once the running one is done: (reason, started for SIGHUP).
+    let mut queued: Option<(&str, bool)> = None;
 
-    let is_root = bundle::is_root();
-    for (apps_dir, _, _) in bundle::user_tier_entries()? {
-        if apps_dir.exists() {
-            if let Err(e) = watcher.watch(&apps_dir, RecursiveMode::NonRecursive) {
-                warn!(path = %apps_dir.display(), "could not watch directory: {}", e);
+    loop {
+        let mut pending = Pending::new(&paths);
+        let deadline = [next_reconcile, watchdog.deadline()]
+            .into_iter()
+            .flatten()
+            .min();
+        match wait_for_msg(&rx, deadline)? {
+            Some(Msg::SyncDone(outcome)) => pending.finished = Some(outcome),
+            Some(msg) => {
+                pending.handle(msg);
+                if !pending.shutdown {
+                    // Debounce: keep collecting until the folders have been quiet for DEBOUNCE.
+                    collect_until_quiet(&rx, &mut pending, &mut watchdog)?;
+                    // Editors save by writing a temp file and renaming it over config.toml; give a
+                    // bundle whose config.toml is momentarily missing a little longer.
+                    let started = Instant::now();
+                    while !pending.shutdown
+                        && pending.mid_rename()
+                        && started.elapsed() < SETTLE_LIMIT
+                    {
+                        collect_until_quiet(&rx, &mut pending, &mut watchdog)?;
+                        std::thread::sleep(DEBOUNCE);
+                    }
+                }
+            }
+            None => {
+                watchdog.tick();
+                if next_reconcile.is_some_and(|d| Instant::now() >= d) {
+                    info!("periodic reconcile");
+                    pending.sync = true;
+                    next_reconcile = reconcile_deadline(&watch_settings);
+                }
             }
         }
-    }
-    if is_root {
-        let system_apps = bundle::system_applications_dir();
-        if system_apps.exists() {
-            if let Err(e) = watcher.watch(&system_apps, RecursiveMode::NonRecursive) {
-                warn!(path = %system_apps.display(), "could not watch directory: {}", e);
+        if let Some(outcome) = pending.finished.take() {
+            report_sync(outcome);
+            if running.take().is_some_and(|r| r.reload) {
+                sdnotify::notify("READY=1");
+            }
+            if let Err(e) = watches.refresh() {
+                warn!("could not refresh watches: {}", e);
+            }
+            watchdog.tick();
+        }
+        if pending.shutdown {
+            info!("received shutdown signal; exiting");
+            sdnotify::notify("STOPPING=1");
+            if let Some(r) = running {
+                // A sync stops between bundles and saves its state before it reports back.
+                r.control.cancel();
+                wait_for_sync(&rx);
+            }
+            return Ok(());
+        }
+        let reason = if pending.reload {
+            info!("received SIGHUP; reloading settings and rebuilding watches");
+            sdnotify::notify("RELOADING=1");
+            (watch_settings, paths) = load_settings();
+            // Dropping the old watchers removes all their watches.
+            watches = Watches::new(tx.clone(), &watch_settings, paths.clone())?;
+            Some("reload")
+        } else if pending.rescan {
+            warn!("watch events were dropped (queue overflow); rescanning all folders");
+            watches.reset();
+            Some("rescan")
+        } else if pending.sync {
+            Some("change")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            let reload = pending.reload || queued.is_some_and(|(_, r)| r);
+            queued = Some((reason, reload));
+            // The queued sync redoes what the running one drops: the whole pass after a reload or
+            // rescan, otherwise only the bundles it had already read before they changed again.
+            if let Some(r) = &running {
+                if pending.reload || pending.rescan {
+                    info!("newer changes supersede the running sync; restarting it");
+                    r.control.cancel();
+                } else {
+                    for bundle in &pending.bundles {
+                        if r.control.supersede(bundle) {
+                            info!(bundle = %bundle.display(), "bundle changed during the sync; syncing it again");
+                        }
+                    }
+                }
+            }
+        }
+        if running.is_none() {
+            if let Some((reason, reload)) = queued.take() {
+                running = Some(start_sync(&tx, reason, reload));
+                next_reconcile = reconcile_deadline(&watch_settings);
             }
         }
     }
+}
 
-    // Debounce: on any event, wait 500ms for more events then sync
-    loop {
-        let _ = rx.recv()?;
-        while rx.recv_timeout(Duration::from_millis(500)).is_ok() {}
-        if let Err(e) = sync::run(false) {
+/// Start a sync on the worker thread; it reports back with Msg::SyncDone.
+fn start_sync(tx: &mpsc::Sender<Msg>, reason: &str, reload: bool) -> Running {
+    sdnotify::status(&format!("Syncing ({})", reason));
+    let control = Arc::new(sync::Control::default());
+    let (worker_control, tx) = (control.clone(), tx.clone());
+    std::thread::spawn(move || {
+        let _ = tx.send(Msg::SyncDone(sync::run_with(false, &worker_control)));
+    });
+    Running { control, reload }
+}
+
+/// Block until the running sync reports back (other messages are dropped: we are exiting).
+fn wait_for_sync(rx: &mpsc::Receiver<Msg>) {
+    while let Ok(msg) = rx.recv() {
+        if let Msg::SyncDone(outcome) = msg {
+            report_sync(outcome);
+            return;
+        }
+    }
+}
+
+/// True when the dotlnx systemd service (the root watcher) is running on this host.
+pub fn service_active() -> bool {
+    std::process::Command::new("systemctl")
+        .args(["is-active", "--quiet", "dotlnx.service"])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success())
+}
+
+/// Run one full sync in the foreground (startup) and publish the outcome.
+fn run_sync(reason: &str) {
+    sdnotify::status(&format!("Syncing ({})", reason));
+    report_sync(sync::run(false).map(Some));
+}
+
+/// Publish a sync's outcome as the unit's status line.
+fn report_sync(outcome: Result<Option<sync::Summary>>) {
+    match outcome {
+        Ok(Some(s)) => sdnotify::status(&format!(
+            "Idle: {} apps installed, {} skipped, {} quarantined",
+            s.installed, s.skipped, s.quarantined
+        )),
+        // Superseded: the next sync is about to start and will set the status.
+        Ok(None) => {}
+        Err(e) => {
             error!("sync failed: {}", e);
+            sdnotify::status(&format!("Last sync failed: {}", e));
+        }
+    }
+}
+
+/// [watch] settings and [paths] from the site config. A typo must not stop the watcher; sync
+/// itself reports the error.
+fn load_settings() -> (settings::WatchSettings, Paths) {
+    match settings::load() {
+        Ok(s) => (s.watch, Paths::from_settings(&s.paths)),
+        Err(e) => {
+            warn!("{}; using default watch settings", e);
+            (
+                settings::WatchSettings::default(),
+                Paths::from_settings(&settings::PathSettings::default()),
+            )
+        }
+    }
+}
+
+fn reconcile_deadline(watch_settings: &settings::WatchSettings) -> Option<Instant> {
+    (watch_settings.reconcile_interval > 0)
+        .then(|| Instant::now() + Duration::from_secs(watch_settings.reconcile_interval))
+}
+
+/// Forward SIGTERM/SIGINT/SIGHUP to the main loop as messages.
+#[cfg(unix)]
+fn spawn_signal_listener(tx: mpsc::Sender<Msg>) -> Result<()> {
+    use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+    let mut signals = signal_hook::iterator::Signals::new([SIGTERM, SIGINT, SIGHUP])?;
+    std::thread::spawn(move || {
+        for sig in signals.forever() {
+            let msg = if sig == SIGHUP {
+                Msg::Reload
+            } else {
+                Msg::Shutdown
+            };
+            if tx.send(msg).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn spawn_signal_listener(_tx: mpsc::Sender<Msg>) -> Result<()> {
+    Ok(())
+}
+
+/// Block until a message arrives (Some) or the reconcile deadline passes (None).
+fn wait_for_msg(rx: &mpsc::Receiver<Msg>, deadline: Option<Instant>) -> Result<Option<Msg>> {
+    let Some(deadline) = deadline else {
+        return Ok(Some(rx.recv()?));
+    };
+    match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        Ok(msg) => Ok(Some(msg)),
+        Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
+        Err(mpsc::RecvTimeoutError::Disconnected) => anyhow::bail!("watcher stopped"),
+    }
+}
+
+/// Drain messages until none arrive for DEBOUNCE (or a shutdown is requested).
+fn collect_until_quiet(
+    rx: &mpsc::Receiver<Msg>,
+    pending: &mut Pending,
+    watchdog: &mut sdnotify::Watchdog,
+) -> Result<()> {
+    while !pending.shutdown {
+        watchdog.tick();
+        match rx.recv_timeout(DEBOUNCE) {
+            Ok(msg) => pending.handle(msg),
+            Err(mpsc::RecvTimeoutError::Timeout) => break,
+            Err(mpsc::RecvTimeoutError::Disconnected) => anyhow::bail!("watcher stopped"),
+        }
+    }
+    Ok(())
+}
+
+/// What one debounced batch of events asks for.
+#[derive(Debug, Default)]
+struct Pending {
+    /// Something relevant changed: run sync.
+    sync: bool,
+    /// Events may have been lost (overflow or watcher error): re-register watches and sync.
+    rescan: bool,
+    /// Bundles whose contents changed (for the rename settle check).
+    bundles: BTreeSet<PathBuf>,
+    /// Where sync writes profiles (files there are outputs, not inputs).
+    profile_dir: PathBuf,
+    /// Start of the menu entries sync writes, per tier.
+    desktop_prefixes: Vec<String>,
+    shutdown: bool,
+    reload: bool,
+    /// The background sync reported back during this batch.
+    finished: Option<Result<Option<sync::Summary>>>,
+}
+
+impl Pending {
+    fn new(paths: &Paths) -> Self {
+        Self {
+            profile_dir: paths.apparmor_dir.clone(),
+            desktop_prefixes: vec![paths.user_prefix.clone(), paths.system_prefix.clone()],
+            ..Self::default()
+        }
+    }
+
+    fn handle(&mut self, msg: Msg) {
+        match msg {
+            Msg::Fs(res) => self.note(res),
+            Msg::Shutdown => self.shutdown = true,
+            Msg::Reload => self.reload = true,
+            Msg::SyncDone(outcome) => self.finished = Some(outcome),
+        }
+    }
+
+    fn note(&mut self, res: Result<Event, notify::Error>) {
+        let event = match res {
+            Ok(event) => event,
+            Err(e) => {
+                warn!("watch error: {}", e);
+                self.rescan = true;
+                return;
+            }
+        };
+        if event.need_rescan() {
+            self.rescan = true;
+            return;
+        }
+        if !is_relevant_kind(&event.kind) {
+            return;
+        }
+        for path in &event.paths {
+            if is_ignored_path(path) {
+                continue;
+            }
+            if path.file_name().is_some_and(|n| n == SYNC_REQUEST) {
+                if matches!(event.kind, EventKind::Create(_)) {
+                    info!(path = %path.display(), "sync requested");
+                    self.sync = true;
+                }
+                continue;
+            }
+            // Generated files: only their disappearance matters (sync's own writes would loop).
+            if is_output(path, &self.profile_dir, &self.desktop_prefixes) {
+                if is_removal(&event.kind) {
+                    info!(path = %path.display(), "generated file removed; re-syncing");
+                    self.sync = true;
+                }
+                continue;
+            }
+            // A packed bundle dropped into Applications is unpacked by sync. Anything else
+            // outside a .lnx (stray files in Applications, other apps' .desktop files next to
+            // ours) does not affect what sync installs.
+            if let Some(bundle) = enclosing_bundle(path) {
+                self.sync = true;
+                self.bundles.insert(bundle);
+            } else if pack::is_packed_bundle(path) && finishes_archive(&event.kind) {
+                self.sync = true;
+            }
+        }
+    }
+
+    /// True while a changed bundle still exists but has no config.toml (rename in flight).
+    fn mid_rename(&self) -> bool {
+        self.bundles
+            .iter()
+            .any(|b| b.is_dir() && !b.join("config.toml").exists())
+    }
+}
+
+/// Reads (open, close-without-write) never change state; sync's own reads would otherwise loop.
+fn is_relevant_kind(kind: &EventKind) -> bool {
+    match kind {
+        EventKind::Access(AccessKind::Close(AccessMode::Write)) => true,
+        EventKind::Access(_) => false,
+        _ => true,
+    }
+}
+
+/// An archive is complete once it is closed after writing or renamed into place; the writes
+/// before that would unpack it half-copied. The poll backend reports neither, only that a file
+/// appeared or its mtime moved, so those count too and sync waits for the size to settle
+/// ([`pack::dropped_archives`]).
+fn finishes_archive(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Access(AccessKind::Close(AccessMode::Write))
+            | EventKind::Modify(ModifyKind::Name(RenameMode::To | RenameMode::Both))
+            | EventKind::Create(CreateKind::Any)
+            | EventKind::Modify(ModifyKind::Metadata(MetadataKind::WriteTime))
+    )
+}
+
+/// A file dotlnx generates: a profile in the dotlnx AppArmor dir or a <prefix>*.desktop entry.
+fn is_output(path: &Path, profile_dir: &Path, desktop_prefixes: &[String]) -> bool {
+    if path.parent() == Some(profile_dir) {
+        return true;
+    }
+    path.file_name().and_then(|n| n.to_str()).is_some_and(|n| {
+        n.ends_with(".desktop") && desktop_prefixes.iter().any(|p| n.starts_with(p.as_str()))
+    })
+}
+
+/// Deleted, or renamed away from its name.
+fn is_removal(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Remove(_)
+            | EventKind::Modify(ModifyKind::Name(RenameMode::From | RenameMode::Any))
+    )
+}
+
+/// Editor swap/backup/temp files, and the .directory file sync writes itself.
+fn is_ignored_path(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+    name == ".directory"
+        || name == "4913" // vim's write-permission probe
+        || name.ends_with('~')
+        || name.ends_with(".swp")
+        || name.ends_with(".swx")
+        || name.ends_with(".tmp")
+        || name.ends_with(".kate-swp")
+        || name.starts_with(".#")
+        || (name.starts_with('#') && name.ends_with('#'))
+        || name.starts_with(".goutputstream-")
+}
+
+/// The .lnx directory containing `path` (or `path` itself when it is a bundle).
+fn enclosing_bundle(path: &Path) -> Option<PathBuf> {
+    path.ancestors()
+        .find(|p| p.extension().is_some_and(|e| e == "lnx"))
+        .map(Path::to_path_buf)
+}
+
+/// Which notify backend watches a directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    /// inotify (or the platform's native API).
+    Native,
+    /// Periodic scans, for network filesystems where inotify misses remote changes.
+    Poll,
+}
+
+/// The set of directories currently watched: each Applications dir plus each bundle inside it.
+struct Watches {
+    tx: mpsc::Sender<Msg>,
+    native: RecommendedWatcher,
+    /// Created on first use: most hosts never need it.
+    poll: Option<PollWatcher>,
+    poll_interval: Duration,
+    watched: HashMap<PathBuf, Backend>,
+    paths: Paths,
+}
+
+impl Watches {
+    fn new(
+        tx: mpsc::Sender<Msg>,
+        watch_settings: &settings::WatchSettings,
+        paths: Paths,
+    ) -> Result<Self> {
+        let native_tx = tx.clone();
+        let native = RecommendedWatcher::new(
+            move |res: Result<Event, notify::Error>| {
+                let _ = native_tx.send(Msg::Fs(res));
+            },
+            Config::default(),
+        )?;
+        Ok(Self {
+            tx,
+            native,
+            poll: None,
+            poll_interval: Duration::from_secs(watch_settings.poll_interval.max(1)),
+            watched: HashMap::new(),
+            paths,
+        })
+    }
+
+    /// Directories that should be watched right now: the Applications dirs and their bundles
+    /// (inputs), plus the directories holding generated .desktop files and profiles (outputs),
+    /// so a deleted output is re-created instead of staying gone until the next bundle change.
+    fn wanted(paths: &Paths) -> Result<Vec<PathBuf>> {
+        let mut apps_dirs = Vec::new();
+        let mut output_dirs = Vec::new();
+        for (apps_dir, desktop_dir, _) in bundle::user_tier_entries(paths)? {
+            apps_dirs.push(apps_dir);
+            output_dirs.push(desktop_dir);
+        }
+        if bundle::is_root() {
+            apps_dirs.push(paths.system_apps.clone());
+            output_dirs.push(paths.system_desktop.clone());
+            output_dirs.push(paths.apparmor_dir.clone());
         }
+        let mut out = Vec::new();
+        for apps_dir in apps_dirs.into_iter().filter(|d| d.exists()) {
+            out.extend(bundle::discover_lnx_dirs(&apps_dir));
+            out.push(apps_dir);
+        }
+        out.extend(output_dirs.into_iter().filter(|d| d.is_dir()));
+        Ok(out)
+    }
+
+    fn watcher(&mut self, backend: Backend) -> Result<&mut dyn Watcher> {
+        Ok(match backend {
+            Backend::Native => &mut self.native,
+            Backend::Poll => {
+                if self.poll.is_none() {
+                    let tx = self.tx.clone();
+                    self.poll = Some(PollWatcher::new(
+                        move |res: Result<Event, notify::Error>| {
+                            let _ = tx.send(Msg::Fs(res));
+                        },
+                        Config::default().with_poll_interval(self.poll_interval),
+                    )?);
+                }
+                self.poll.as_mut().expect("poll watcher just created")
+            }
+        })
+    }
+
+    /// Add watches for new folders/bundles and forget ones that disappeared.
+    fn refresh(&mut self) -> Result<()> {
+        let wanted: HashSet<PathBuf> = Self::wanted(&self.paths)?.into_iter().collect();
+        let gone: Vec<PathBuf> = self
+            .watched
+            .keys()
+            .filter(|p| !wanted.contains(*p))
+            .cloned()
+            .collect();
+        for path in gone {
+            if let Some(backend) = self.watched.remove(&path) {
+                let _ = self.watcher(backend)?.unwatch(&path);
+            }
+        }
+        for path in wanted {
+            if self.watched.contains_key(&path) {
+                continue;
+            }
+            let backend = if is_network_fs(&path) {
+                info!(path = %path.display(), interval = ?self.poll_interval, "network filesystem; polling instead of inotify");
+                Backend::Poll
+            } else {
+                Backend::Native
+            };
+            match self
+                .watcher(backend)?
+                .watch(&path, RecursiveMode::NonRecursive)
+            {
+                Ok(()) => {
+                    self.watched.insert(path, backend);
+                }
+                Err(e) => warn!(path = %path.display(), "could not watch directory: {}", e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop every watch so the next refresh re-registers from scratch.
+    fn reset(&mut self) {
+        let watched: Vec<(PathBuf, Backend)> = self.watched.drain().collect();
+        for (path, backend) in watched {
+            if let Ok(w) = self.watcher(backend) {
+                let _ = w.unwatch(&path);
+            }
+        }
+        info!("cleared watches for rescan");
+    }
+}
+
+/// True when `path` is on NFS or SMB/CIFS, where inotify only sees changes made on this host.
+#[cfg(target_os = "linux")]
+fn is_network_fs(path: &Path) -> bool {
+    const NFS: u32 = 0x6969;
+    const SMB: u32 = 0x517b;
+    const CIFS: u32 = 0xff53_4d42;
+    const SMB2: u32 = 0xfe53_4d42;
+    match nix::sys::statfs::statfs(path) {
+        Ok(st) => {
+            let magic = st.filesystem_type().0 as u32;
+            matches!(magic, NFS | SMB | CIFS | SMB2)
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_network_fs(_path: &Path) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{DataChange, Flag, RemoveKind};
+
+    fn event(kind: EventKind, path: &str) -> Event {
+        Event::new(kind).add_path(PathBuf::from(path))
+    }
+
+    fn pending() -> Pending {
+        Pending::new(&Paths::under(Path::new("/")))
+    }
+
+    #[test]
+    fn editor_temp_files_are_ignored() {
+        for name in [
+            "/a/x.lnx/.config.toml.swp",
+            "/a/x.lnx/config.toml~",
+            "/a/x.lnx/4913",
+            "/a/x.lnx/.#config.toml",
+            "/a/x.lnx/#config.toml#",
+            "/a/x.lnx/.goutputstream-ABC123",
+            "/a/x.lnx/.directory",
+        ] {
+            assert!(is_ignored_path(Path::new(name)), "{}", name);
+        }
+        assert!(!is_ignored_path(Path::new("/a/x.lnx/config.toml")));
+        assert!(!is_ignored_path(Path::new("/a/x.lnx")));
+    }
+
+    #[test]
+    fn temp_only_batch_does_not_sync() {
+        let mut p = pending();
+        p.note(Ok(event(
+            EventKind::Create(CreateKind::File),
+            "/a/x.lnx/.config.toml.swp",
+        )));
+        p.note(Ok(event(
+            EventKind::Access(AccessKind::Open(AccessMode::Any)),
+            "/a/x.lnx/config.toml",
+        )));
+        assert!(!p.sync && !p.rescan);
+        p.note(Ok(event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)),
+            "/a/x.lnx/config.toml",
+        )));
+        assert!(p.sync);
+        assert!(p.bundles.contains(Path::new("/a/x.lnx")));
+    }
+
+    #[test]
+    fn generated_outputs_resync_only_on_removal() {
+        let desktop = "/home/a/.local/share/applications/dotlnx-x.desktop";
+        let profile = "/etc/apparmor.d/dotlnx.d/dotlnx-a-x";
+        let mut p = pending();
+        p.note(Ok(event(EventKind::Create(CreateKind::File), desktop)));
+        p.note(Ok(event(
+            EventKind::Access(AccessKind::Close(AccessMode::Write)),
+            profile,
+        )));
+        p.note(Ok(event(
+            EventKind::Remove(RemoveKind::File),
+            "/home/a/.local/share/applications/other.desktop",
+        )));
+        assert!(!p.sync);
+        p.note(Ok(event(EventKind::Remove(RemoveKind::File), profile)));
+        assert!(p.sync);
+        let mut p = pending();
+        p.note(Ok(event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)),
+            desktop,
+        )));
+        assert!(p.sync);
+        assert!(p.bundles.is_empty());
+    }
+
+    #[test]
+    fn sync_request_file_triggers_sync() {
+        let request = format!("/home/a/Applications/{}", SYNC_REQUEST);
+        let mut p = pending();
+        p.note(Ok(event(EventKind::Remove(RemoveKind::File), &request)));
+        assert!(!p.sync);
+        p.note(Ok(event(EventKind::Create(CreateKind::File), &request)));
+        assert!(p.sync);
+        assert!(p.bundles.is_empty());
+    }
+
+    #[test]
+    fn dropped_archives_trigger_sync_but_not_their_removal() {
+        let mut p = pending();
+        p.note(Ok(event(
+            EventKind::Remove(RemoveKind::File),
+            "/home/a/Applications/x.lnx.zip",
+        )));
+        p.note(Ok(event(
+            EventKind::Create(CreateKind::File),
+            "/home/a/Applications/notes.zip",
+        )));
+        assert!(!p.sync);
+        p.note(Ok(event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)),
+            "/home/a/Applications/x.lnx.tar.zst",
+        )));
+        assert!(p.sync);
+        assert!(p.bundles.is_empty());
+    }
+
+    #[test]
+    fn archives_being_copied_wait_for_close_write() {
+        let archive = "/home/a/Applications/x.lnx.tar.zst";
+        let mut p = pending();
+        p.note(Ok(event(EventKind::Create(CreateKind::File), archive)));
+        p.note(Ok(event(
+            EventKind::Modify(ModifyKind::Data(DataChange::Any)),
+            archive,
+        )));
+        assert!(!p.sync);
+        p.note(Ok(event(
+            EventKind::Access(AccessKind::Close(AccessMode::Write)),
+            archive,
+        )));
+        assert!(p.sync);
+    }
+
+    #[test]
+    fn overflow_and_errors_request_rescan() {
+        let mut p = pending();
+        p.note(Ok(Event::new(EventKind::Other).set_flag(Flag::Rescan)));
+        assert!(p.rescan);
+        let mut p = pending();
+        p.note(Err(notify::Error::generic("boom")));
+        assert!(p.rescan);
+    }
+
+    #[test]
+    fn mid_rename_waits_for_missing_config() {
+        let tmp = tempfile::tempdir().unwrap();
+        let bundle = tmp.path().join("x.lnx");
+        std::fs::create_dir_all(&bundle).unwrap();
+        let mut p = pending();
+        p.bundles.insert(bundle.clone());
+        assert!(p.mid_rename());
+        std::fs::write(bundle.join("config.toml"), "").unwrap();
+        assert!(!p.mid_rename());
+        // A removed bundle is a removal, not a rename in flight.
+        p.bundles.insert(tmp.path().join("gone.lnx"));
+        assert!(!p.mid_rename());
+    }
+
+    #[test]
+    fn wait_for_msg_times_out_at_deadline() {
+        let (tx, rx) = mpsc::channel();
+        let soon = Some(Instant::now() + Duration::from_millis(10));
+        assert!(wait_for_msg(&rx, soon).unwrap().is_none());
+        tx.send(Msg::Fs(Ok(Event::new(EventKind::Any)))).unwrap();
+        assert!(wait_for_msg(&rx, soon).unwrap().is_some());
+    }
+
+    #[test]
+    fn shutdown_stops_debounce() {
+        let (tx, rx) = mpsc::channel();
+        tx.send(Msg::Shutdown).unwrap();
+        tx.send(Msg::Fs(Ok(event(EventKind::Any, "/a/x.lnx/config.toml"))))
+            .unwrap();
+        let mut p = pending();
+        collect_until_quiet(&rx, &mut p, &mut sdnotify::Watchdog::from_env()).unwrap();
+        assert!(p.shutdown);
+        // Events queued behind the shutdown are left alone.
+        assert!(!p.sync);
+        p.handle(Msg::Reload);
+        assert!(p.reload);
+    }
+
+    #[test]
+    fn sync_result_is_kept_through_debounce() {
+        let (tx, rx) = mpsc::channel();
+        tx.send(Msg::Fs(Ok(event(EventKind::Any, "/a/x.lnx/config.toml"))))
+            .unwrap();
+        tx.send(Msg::SyncDone(Ok(None))).unwrap();
+        let mut p = pending();
+        collect_until_quiet(&rx, &mut p, &mut sdnotify::Watchdog::from_env()).unwrap();
+        assert!(p.sync);
+        assert!(matches!(p.finished, Some(Ok(None))));
+    }
+
+    #[test]
+    fn local_tempdir_is_not_network_fs() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert!(!is_network_fs(tmp.path()));
+    }
+
+    #[test]
+    fn enclosing_bundle_finds_lnx_ancestor() {
+        assert_eq!(
+            enclosing_bundle(Path::new("/home/a/Applications/x.lnx/config.toml")),
+            Some(PathBuf::from("/home/a/Applications/x.lnx"))
+        );
+        assert_eq!(enclosing_bundle(Path::new("/home/a/Applications")), None);
     }
 }
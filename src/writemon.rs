@@ -0,0 +1,131 @@
+//! `dotlnx run --report-writes`: watch an app's bundle while it runs and list what it wrote
+//! there. Apps that keep settings, caches or logs next to their binary break when the bundle is
+//! read-only (/Applications), make packed archives differ and fail integrity checks; the report
+//! tells authors which paths to move to the XDG directories or list in `exclude`.
+
+use anyhow::Result;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use notify::event::{AccessKind, AccessMode, EventKind};
+use notify::{Config, Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// Paths listed in the report; the rest are counted.
+const MAX_LISTED: usize = 20;
+
+/// Watches a bundle (recursively, via inotify) from `start` until `finish`.
+pub struct WriteMonitor {
+    watcher: RecommendedWatcher,
+    bundle: PathBuf,
+    written: Arc<Mutex<BTreeSet<PathBuf>>>,
+}
+
+impl WriteMonitor {
+    /// Start watching `bundle`. Fails when inotify is unavailable or out of watches.
+    pub fn start(bundle: &Path) -> Result<Self> {
+        let written = Arc::new(Mutex::new(BTreeSet::new()));
+        let sink = Arc::clone(&written);
+        let mut watcher = RecommendedWatcher::new(
+            move |res: Result<Event, notify::Error>| {
+                let Ok(event) = res else { return };
+                if is_write(&event.kind) {
+                    sink.lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .extend(event.paths);
+                }
+            },
+            Config::default(),
+        )?;
+        watcher.watch(bundle, RecursiveMode::Recursive)?;
+        Ok(Self {
+            watcher,
+            bundle: bundle.to_path_buf(),
+            written,
+        })
+    }
+
+    /// Stop watching; paths written (created, changed, renamed or removed) inside the bundle,
+    /// relative to it, leaving out `exclude`d ones.
+    pub fn finish(mut self, exclude: &[String]) -> Vec<PathBuf> {
+        // Events arrive on notify's thread: wait until they stop coming in.
+        let count = || self.written.lock().unwrap_or_else(|e| e.into_inner()).len();
+        for _ in 0..10 {
+            let before = count();
+            std::thread::sleep(Duration::from_millis(100));
+            if count() == before {
+                break;
+            }
+        }
+        let _ = self.watcher.unwatch(&self.bundle);
+        let written = std::mem::take(&mut *self.written.lock().unwrap_or_else(|e| e.into_inner()));
+        written
+            .into_iter()
+            .filter_map(|p| p.strip_prefix(&self.bundle).ok().map(Path::to_path_buf))
+            .filter(|p| !p.as_os_str().is_empty() && !crate::bundle::is_excluded(exclude, p))
+            .collect()
+    }
+}
+
+/// Events that mean the bundle's contents changed (not reads, not metadata).
+fn is_write(kind: &EventKind) -> bool {
+    match kind {
+        EventKind::Access(AccessKind::Close(AccessMode::Write)) => true,
+        EventKind::Access(_) | EventKind::Other | EventKind::Any => false,
+        EventKind::Modify(notify::event::ModifyKind::Metadata(_)) => false,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => true,
+    }
+}
+
+/// Print what the app wrote inside its bundle (stderr: stdout belongs to the app).
+pub fn report(app: &str, written: &[PathBuf]) {
+    if written.is_empty() {
+        eprintln!("dotlnx: {} wrote nothing inside its bundle", app);
+        return;
+    }
+    eprintln!(
+        "dotlnx: {} wrote {} path(s) inside its bundle:",
+        app,
+        written.len()
+    );
+    for path in written.iter().take(MAX_LISTED) {
+        eprintln!("  {}", path.display());
+    }
+    if written.len() > MAX_LISTED {
+        eprintln!("  ... and {} more", written.len() - MAX_LISTED);
+    }
+    eprintln!(
+        "Bundles in /Applications are read-only to apps. Keep settings and data in $XDG_CONFIG_HOME / \
+         $XDG_DATA_HOME (or the app's portable-mode data directory outside the bundle); for paths the \
+         app must own, add them to `exclude` in config.toml and grant them with write_paths."
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_writes_except_excluded() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("App.lnx");
+        std::fs::create_dir_all(bundle.join("bin")).unwrap();
+        std::fs::write(bundle.join("bin/app"), "app").unwrap();
+        let monitor = WriteMonitor::start(&bundle).unwrap();
+        let _ = std::fs::read(bundle.join("bin/app")).unwrap();
+        std::fs::create_dir(bundle.join("cache")).unwrap();
+        std::fs::write(bundle.join("cache/x"), "x").unwrap();
+        std::fs::write(bundle.join("settings.ini"), "a=1").unwrap();
+        std::fs::write(bundle.join("app.log"), "started").unwrap();
+        let written = monitor.finish(&["*.log".into()]);
+        assert!(
+            written.contains(&PathBuf::from("settings.ini")),
+            "{:?}",
+            written
+        );
+        assert!(written.contains(&PathBuf::from("cache")));
+        assert!(!written.contains(&PathBuf::from("bin/app")));
+        assert!(!written.contains(&PathBuf::from("app.log")));
+    }
+}
@@ -0,0 +1,253 @@
+//! Just enough of the .zip format to unpack a bundle someone zipped up (`Name.lnx.zip`): the
+//! central directory, stored and deflated entries, and the Unix mode bits for symlinks and
+//! executables. Encrypted and zip64 archives (over 4 GiB or 65535 entries) are refused; those
+//! bundles are better packed with `dotlnx pack`.
+
+use anyhow::{Context, Result};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIR: u32 = 0x0605_4b50;
+/// The end record (22 bytes) plus the longest comment it can carry.
+const MAX_TAIL: u64 = 22 + 0xffff;
+
+/// What an entry unpacks to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    File,
+    Dir,
+    Symlink,
+}
+
+/// One entry of the central directory.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Path in the archive, `/`-separated (directories end in `/`).
+    pub name: String,
+    pub kind: Kind,
+    /// A Unix archiver recorded an execute bit.
+    pub executable: bool,
+    method: u16,
+    crc: u32,
+    compressed_size: u64,
+    size: u64,
+    local_offset: u64,
+}
+
+/// True when the file starts like a zip archive.
+pub fn is_zip(path: &Path) -> Result<bool> {
+    let mut magic = [0u8; 4];
+    std::fs::File::open(path)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .with_context(|| format!("read {}", path.display()))?;
+    Ok(u32::from_le_bytes(magic) == LOCAL_HEADER)
+}
+
+/// The entries of the archive, in central directory order.
+pub fn entries(file: &mut std::fs::File) -> Result<Vec<Entry>> {
+    let len = file.metadata()?.len();
+    let tail_len = len.min(MAX_TAIL);
+    file.seek(SeekFrom::Start(len - tail_len))?;
+    let mut tail = vec![0u8; tail_len as usize];
+    file.read_exact(&mut tail)?;
+    let end = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&i| u32_at(&tail, i) == END_OF_CENTRAL_DIR)
+        // The end record comes last, so a zip still being copied has none yet.
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "the archive ends early (no end of central directory)",
+            )
+        })?;
+    let count = u16_at(&tail, end + 10);
+    let (dir_size, dir_offset) = (u32_at(&tail, end + 12), u32_at(&tail, end + 16));
+    if count == 0xffff || dir_size == u32::MAX || dir_offset == u32::MAX {
+        anyhow::bail!("zip64 archives are not supported");
+    }
+    file.seek(SeekFrom::Start(dir_offset.into()))?;
+    let mut dir = vec![0u8; dir_size as usize];
+    file.read_exact(&mut dir)
+        .context("truncated central directory")?;
+
+    let mut out = Vec::with_capacity(count.into());
+    let mut at = 0;
+    for _ in 0..count {
+        if at + 46 > dir.len() || u32_at(&dir, at) != CENTRAL_HEADER {
+            anyhow::bail!("corrupt central directory");
+        }
+        let made_on_unix = u16_at(&dir, at + 4) >> 8 == 3;
+        let flags = u16_at(&dir, at + 8);
+        let method = u16_at(&dir, at + 10);
+        let crc = u32_at(&dir, at + 16);
+        let (compressed_size, size) = (u32_at(&dir, at + 20), u32_at(&dir, at + 24));
+        let name_len = usize::from(u16_at(&dir, at + 28));
+        let extra_len = usize::from(u16_at(&dir, at + 30));
+        let comment_len = usize::from(u16_at(&dir, at + 32));
+        let mode = if made_on_unix {
+            u32_at(&dir, at + 38) >> 16
+        } else {
+            0
+        };
+        let local_offset = u32_at(&dir, at + 42);
+        let name = dir
+            .get(at + 46..at + 46 + name_len)
+            .context("corrupt central directory")?;
+        let name = String::from_utf8(name.to_vec()).context("entry name is not UTF-8")?;
+        at += 46 + name_len + extra_len + comment_len;
+        if flags & 1 != 0 {
+            anyhow::bail!("{}: encrypted entries are not supported", name);
+        }
+        if compressed_size == u32::MAX || size == u32::MAX || local_offset == u32::MAX {
+            anyhow::bail!("zip64 archives are not supported");
+        }
+        let kind = match mode & 0o170000 {
+            0o120000 => Kind::Symlink,
+            0o040000 => Kind::Dir,
+            _ if name.ends_with('/') => Kind::Dir,
+            _ => Kind::File,
+        };
+        out.push(Entry {
+            name,
+            kind,
+            executable: mode & 0o111 != 0,
+            method,
+            crc,
+            compressed_size: compressed_size.into(),
+            size: size.into(),
+            local_offset: local_offset.into(),
+        });
+    }
+    Ok(out)
+}
+
+impl Entry {
+    /// Copy the entry's contents (a symlink's target) into `out`, checking size and CRC.
+    pub fn copy_to(&self, file: &mut std::fs::File, out: &mut dyn Write) -> Result<()> {
+        file.seek(SeekFrom::Start(self.local_offset))?;
+        let mut header = [0u8; 30];
+        file.read_exact(&mut header)?;
+        if u32_at(&header, 0) != LOCAL_HEADER {
+            anyhow::bail!("{}: corrupt local header", self.name);
+        }
+        let skip = u64::from(u16_at(&header, 26)) + u64::from(u16_at(&header, 28));
+        file.seek(SeekFrom::Current(skip as i64))?;
+        let data = Read::by_ref(file).take(self.compressed_size);
+        let mut reader: Box<dyn Read + '_> = match self.method {
+            0 => Box::new(data),
+            8 => Box::new(flate2::read::DeflateDecoder::new(data)),
+            m => anyhow::bail!("{}: compression method {} is not supported", self.name, m),
+        };
+        let (mut crc, mut size) = (crc32fast::Hasher::new(), 0u64);
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = reader
+                .read(&mut buf)
+                .with_context(|| format!("read {}", self.name))?;
+            if n == 0 {
+                break;
+            }
+            crc.update(&buf[..n]);
+            size += n as u64;
+            out.write_all(&buf[..n])?;
+        }
+        if size != self.size || crc.finalize() != self.crc {
+            anyhow::bail!("{}: corrupt data (size or checksum mismatch)", self.name);
+        }
+        Ok(())
+    }
+}
+
+fn u16_at(b: &[u8], i: usize) -> u16 {
+    u16::from_le_bytes([b[i], b[i + 1]])
+}
+
+fn u32_at(b: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]])
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A stored (uncompressed) zip made by a "Unix" archiver: (name, mode, contents).
+    pub(crate) fn write_zip(path: &Path, entries: &[(&str, u32, &[u8])]) {
+        let (mut out, mut central) = (Vec::new(), Vec::new());
+        for (name, mode, data) in entries {
+            let crc = crc32fast::hash(data);
+            let offset = out.len() as u32;
+            let common = |v: &mut Vec<u8>| {
+                v.extend(20u16.to_le_bytes()); // version needed
+                v.extend([0u8; 8]); // flags, method (stored), time, date
+                v.extend(crc.to_le_bytes());
+                v.extend((data.len() as u32).to_le_bytes());
+                v.extend((data.len() as u32).to_le_bytes());
+                v.extend((name.len() as u16).to_le_bytes());
+                v.extend(0u16.to_le_bytes()); // extra
+            };
+            out.extend(LOCAL_HEADER.to_le_bytes());
+            common(&mut out);
+            out.extend(name.as_bytes());
+            out.extend(*data);
+            central.extend(CENTRAL_HEADER.to_le_bytes());
+            central.extend((3u16 << 8 | 20).to_le_bytes()); // made by: Unix
+            common(&mut central);
+            central.extend([0u8; 6]); // comment, disk, internal attributes
+            central.extend((mode << 16).to_le_bytes());
+            central.extend(offset.to_le_bytes());
+            central.extend(name.as_bytes());
+        }
+        let dir_offset = out.len() as u32;
+        out.extend(&central);
+        out.extend(END_OF_CENTRAL_DIR.to_le_bytes());
+        out.extend([0u8; 4]);
+        out.extend((entries.len() as u16).to_le_bytes());
+        out.extend((entries.len() as u16).to_le_bytes());
+        out.extend((central.len() as u32).to_le_bytes());
+        out.extend(dir_offset.to_le_bytes());
+        out.extend(0u16.to_le_bytes());
+        std::fs::write(path, out).unwrap();
+    }
+
+    #[test]
+    fn reads_entries_kinds_and_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let zip = dir.path().join("A.lnx.zip");
+        write_zip(
+            &zip,
+            &[
+                ("A.lnx/", 0o040755, b""),
+                ("A.lnx/run", 0o100755, b"#!/bin/sh\n"),
+                ("A.lnx/link", 0o120777, b"run"),
+            ],
+        );
+        assert!(is_zip(&zip).unwrap());
+        let mut file = std::fs::File::open(&zip).unwrap();
+        let entries = entries(&mut file).unwrap();
+        let kinds: Vec<(&str, Kind, bool)> = entries
+            .iter()
+            .map(|e| (e.name.as_str(), e.kind, e.executable))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("A.lnx/", Kind::Dir, true),
+                ("A.lnx/run", Kind::File, true),
+                ("A.lnx/link", Kind::Symlink, true)
+            ]
+        );
+        let mut data = Vec::new();
+        entries[1].copy_to(&mut file, &mut data).unwrap();
+        assert_eq!(data, b"#!/bin/sh\n");
+
+        // A flipped byte in the data fails the checksum.
+        let mut bytes = std::fs::read(&zip).unwrap();
+        let at = bytes.windows(9).position(|w| w == b"#!/bin/sh").unwrap();
+        bytes[at] = b'?';
+        std::fs::write(&zip, bytes).unwrap();
+        let mut file = std::fs::File::open(&zip).unwrap();
+        assert!(entries[1].copy_to(&mut file, &mut Vec::new()).is_err());
+    }
+}
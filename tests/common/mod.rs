@@ -0,0 +1,180 @@
+//! Harness for end-to-end tests: runs the real `dotlnx` binary against a throwaway home.
+//!
+//! User-tier tests point dotlnx at a temp home through the environment (DOTLNX_APPLICATIONS,
+//! XDG_DATA_HOME, DOTLNX_STATE_DIR, DOTLNX_CONFIG). When the tests themselves run as root, dotlnx
+//! is started in an unmapped user namespace (`unshare --user`) so it sees itself as an ordinary
+//! user and never falls into daemon mode against the real /home.
+//!
+//! Daemon-mode tests run dotlnx as root of a user namespace (`unshare --map-root-user --mount`)
+//! with temp directories bind-mounted over /root, /home and /usr/share/applications. Both need
+//! unprivileged user namespaces; where those are unavailable the tests are skipped with a note.
+
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+pub struct Sandbox {
+    dir: tempfile::TempDir,
+    bin: PathBuf,
+}
+
+impl Sandbox {
+    pub fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        // A private copy: bind mounts over /root would hide a target dir that lives there.
+        let bin = dir.path().join("dotlnx");
+        std::fs::copy(env!("CARGO_BIN_EXE_dotlnx"), &bin).unwrap();
+        for sub in ["home/Applications", "data", "state"] {
+            std::fs::create_dir_all(dir.path().join(sub)).unwrap();
+        }
+        Self { dir, bin }
+    }
+
+    pub fn path(&self, rel: &str) -> PathBuf {
+        self.dir.path().join(rel)
+    }
+
+    pub fn home(&self) -> PathBuf {
+        self.path("home")
+    }
+
+    pub fn apps(&self) -> PathBuf {
+        self.path("home/Applications")
+    }
+
+    /// Where user-tier .desktop files land (XDG_DATA_HOME/applications).
+    pub fn desktop_dir(&self) -> PathBuf {
+        self.path("data/applications")
+    }
+
+    pub fn state_dir(&self) -> PathBuf {
+        self.path("state")
+    }
+
+    /// Site config path (absent unless a test writes it).
+    pub fn site_config(&self) -> PathBuf {
+        self.path("site.toml")
+    }
+
+    pub fn state(&self) -> serde_json::Value {
+        let s = std::fs::read_to_string(self.state_dir().join("state.json")).unwrap();
+        serde_json::from_str(&s).unwrap()
+    }
+
+    /// `dotlnx <args>` as an ordinary user of the sandbox home. None when it cannot be run
+    /// safely (root without user namespaces).
+    pub fn user_cmd(&self, args: &[&str]) -> Option<Command> {
+        let mut cmd = if nix::unistd::geteuid().is_root() {
+            if !userns_available(&["--user"]) {
+                eprintln!("skipping: running as root and unshare --user is unavailable");
+                return None;
+            }
+            let mut c = Command::new("unshare");
+            c.arg("--user").arg("--").arg(&self.bin);
+            c
+        } else {
+            Command::new(&self.bin)
+        };
+        cmd.args(args)
+            .env_clear()
+            .env("PATH", "/usr/bin:/bin")
+            .env("HOME", self.home())
+            .env("USER", "tester")
+            .env("NO_COLOR", "1")
+            .env("DOTLNX_APPLICATIONS", self.apps())
+            .env("XDG_DATA_HOME", self.path("data"))
+            .env("DOTLNX_STATE_DIR", self.state_dir())
+            .env("DOTLNX_CONFIG", self.site_config());
+        Some(cmd)
+    }
+
+    /// `dotlnx <args>` as root of a user namespace, with the sandbox's `root/`, `home/` and
+    /// `usr-share-applications/` mounted over /root, /home and /usr/share/applications, and
+    /// `system/` as the system Applications dir. None when user namespaces are unavailable.
+    pub fn daemon_cmd(&self, args: &[&str]) -> Option<Command> {
+        if !userns_available(&["--map-root-user", "--mount"]) {
+            eprintln!("skipping: unshare --map-root-user --mount is unavailable");
+            return None;
+        }
+        for sub in ["root", "usr-share-applications", "system"] {
+            std::fs::create_dir_all(self.path(sub)).unwrap();
+        }
+        let script = "mount --bind \"$1\"/root /root \
+            && mount --bind \"$1\"/home /home \
+            && mount --bind \"$1\"/usr-share-applications /usr/share/applications \
+            && shift && exec \"$@\"";
+        let mut cmd = Command::new("unshare");
+        cmd.args(["--map-root-user", "--mount", "--", "sh", "-c", script, "sh"])
+            .arg(self.dir.path())
+            .arg(&self.bin)
+            .args(args)
+            .env_clear()
+            .env("PATH", "/usr/bin:/bin")
+            .env("NO_COLOR", "1")
+            .env("DOTLNX_SYSTEM_APPLICATIONS", self.path("system"))
+            .env("DOTLNX_STATE_DIR", self.state_dir())
+            .env("DOTLNX_CONFIG", self.site_config());
+        Some(cmd)
+    }
+}
+
+fn userns_available(flags: &[&str]) -> bool {
+    Command::new("unshare")
+        .args(flags)
+        .arg("true")
+        .output()
+        .is_ok_and(|o| o.status.success())
+}
+
+/// Run and fail the test with dotlnx's output when it exits non-zero.
+pub fn run_ok(mut cmd: Command) -> Output {
+    let out = cmd.output().expect("run dotlnx");
+    assert!(
+        out.status.success(),
+        "dotlnx failed ({}):\n{}",
+        out.status,
+        String::from_utf8_lossy(&out.stderr)
+    );
+    out
+}
+
+/// Parse the JSON summary printed by `--json`.
+pub fn json(out: &Output) -> serde_json::Value {
+    serde_json::from_slice(&out.stdout)
+        .unwrap_or_else(|e| panic!("bad JSON ({}): {}", e, String::from_utf8_lossy(&out.stdout)))
+}
+
+/// Write a minimal valid bundle `<apps>/<dir>` named `name`; its executable touches the file
+/// given as its first argument.
+pub fn bundle(apps: &Path, dir: &str, name: &str, extra: &str) -> PathBuf {
+    let b = apps.join(dir);
+    std::fs::create_dir_all(b.join("bin")).unwrap();
+    std::fs::write(b.join("bin/app"), "#!/bin/sh\ntouch \"$1\"\n").unwrap();
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(b.join("bin/app"), std::fs::Permissions::from_mode(0o755))
+            .unwrap();
+    }
+    std::fs::write(
+        b.join("config.toml"),
+        format!("name = \"{}\"\nexecutable = \"bin/app\"\n{}", name, extra),
+    )
+    .unwrap();
+    b
+}
+
+/// Actions in a `--json` summary, as (action, app) pairs.
+pub fn actions(summary: &serde_json::Value) -> Vec<(String, String)> {
+    summary["changes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| {
+            (
+                c["action"].as_str().unwrap().to_string(),
+                c["app"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect()
+}
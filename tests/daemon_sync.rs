@@ -0,0 +1,440 @@
+//! End-to-end: the root daemon's pass over every user's ~/Applications and /Applications, run as
+//! root of a user namespace with the system directories bind-mounted from the sandbox.
+
+mod common;
+
+use common::{actions, bundle, json, run_ok, Sandbox};
+
+#[test]
+fn daemon_syncs_every_tier() {
+    let sb = Sandbox::new();
+    let Some(sync) = sb.daemon_cmd(&["sync", "--json"]) else {
+        return;
+    };
+    // Unconfined: whether a profile can be loaded depends on the host's AppArmor, not on sync.
+    let unconfined = "[security]\nconfine = false\n";
+    bundle(
+        &sb.path("root/Applications"),
+        "Mine.lnx",
+        "Mine",
+        unconfined,
+    );
+    // /home/<name> without a matching account: nothing can be done as that user.
+    bundle(
+        &sb.path("home/ghost/Applications"),
+        "Ghost.lnx",
+        "Ghost",
+        "",
+    );
+    let shared = bundle(&sb.path("system"), "Shared.lnx", "Shared", unconfined);
+
+    let summary = json(&run_ok(sync));
+    let mut done = actions(&summary);
+    done.sort();
+    assert_eq!(
+        done,
+        [
+            ("install".into(), "Mine".into()),
+            ("install".into(), "Shared".into())
+        ]
+    );
+    assert_eq!(summary["skipped"], 1);
+    assert!(sb
+        .path("root/.local/share/applications/dotlnx-Mine.desktop")
+        .exists());
+    assert!(sb
+        .path("usr-share-applications/dotlnx-Shared.desktop")
+        .exists());
+    assert!(!sb
+        .path("home/ghost/.local/share/applications/dotlnx-Ghost.desktop")
+        .exists());
+    let state = sb.state();
+    assert_eq!(state["names"]["Mine"][0], "/root/Applications/Mine.lnx");
+
+    std::fs::remove_dir_all(&shared).unwrap();
+    let summary = json(&run_ok(sb.daemon_cmd(&["sync", "--json"]).unwrap()));
+    assert_eq!(actions(&summary), [("remove".into(), "Shared".into())]);
+    assert!(!sb
+        .path("usr-share-applications/dotlnx-Shared.desktop")
+        .exists());
+    assert_eq!(summary["unchanged"], serde_json::json!(["Mine"]));
+}
+
+#[test]
+fn held_apps_keep_their_installed_version() {
+    let sb = Sandbox::new();
+    let Some(sync) = sb.daemon_cmd(&["sync", "--json"]) else {
+        return;
+    };
+    let b = bundle(
+        &sb.path("system"),
+        "Kept.lnx",
+        "Kept",
+        "[security]\nconfine = false\n",
+    );
+    run_ok(sync);
+    run_ok(sb.daemon_cmd(&["hold", "Kept"]).unwrap());
+    let entry = sb.path("usr-share-applications/dotlnx-Kept.desktop");
+    let before = std::fs::read_to_string(&entry).unwrap();
+
+    let config = std::fs::read_to_string(b.join("config.toml")).unwrap();
+    std::fs::write(
+        b.join("config.toml"),
+        format!("comment = \"new version\"\n{}", config),
+    )
+    .unwrap();
+    let summary = json(&run_ok(sb.daemon_cmd(&["sync", "--json"]).unwrap()));
+    assert_eq!(summary["held"], serde_json::json!(["Kept"]));
+    assert!(actions(&summary).is_empty());
+    assert_eq!(std::fs::read_to_string(&entry).unwrap(), before);
+
+    let summary = json(&run_ok(
+        sb.daemon_cmd(&["sync", "--json", "--force"]).unwrap(),
+    ));
+    assert_eq!(actions(&summary), [("update".into(), "Kept".into())]);
+    assert!(std::fs::read_to_string(&entry)
+        .unwrap()
+        .contains("Comment=new version"));
+}
+
+#[test]
+fn pinned_system_apps_reach_only_their_users() {
+    let sb = Sandbox::new();
+    let Some(sync) = sb.daemon_cmd(&["sync", "--json"]) else {
+        return;
+    };
+    let extra = "allowed_users = [\"root\"]\n[security]\nconfine = false\n";
+    let lab = bundle(&sb.path("system"), "Lab.lnx", "Lab", extra);
+    bundle(
+        &sb.path("system"),
+        "Staff.lnx",
+        "Staff",
+        "allowed_groups = [\"no-such-group\"]\n[security]\nconfine = false\n",
+    );
+    run_ok(sync);
+    let member_entry = sb.path("root/.local/share/applications/dotlnx-system/dotlnx-Lab.desktop");
+    assert!(std::fs::read_to_string(&member_entry)
+        .unwrap()
+        .contains("Name=Lab"));
+    assert!(!sb
+        .path("usr-share-applications/dotlnx-Lab.desktop")
+        .exists());
+    assert!(!sb
+        .path("usr-share-applications/dotlnx-Staff.desktop")
+        .exists());
+    assert!(!sb
+        .path("root/.local/share/applications/dotlnx-system/dotlnx-Staff.desktop")
+        .exists());
+
+    // Opened to everyone: back to the shared entry.
+    std::fs::write(
+        lab.join("config.toml"),
+        "name = \"Lab\"\nexecutable = \"bin/app\"\n[security]\nconfine = false\n",
+    )
+    .unwrap();
+    run_ok(sb.daemon_cmd(&["sync", "--json"]).unwrap());
+    assert!(!member_entry.exists());
+    assert!(sb
+        .path("usr-share-applications/dotlnx-Lab.desktop")
+        .exists());
+}
+
+#[test]
+fn kiosk_mode_runs_one_app_at_boot() {
+    let sb = Sandbox::new();
+    let Some(sync) = sb.daemon_cmd(&["sync", "--json"]) else {
+        return;
+    };
+    // A stand-in systemctl that records calls and keeps the default target in a file.
+    let (log, default) = (sb.path("systemctl.log"), sb.path("default.target"));
+    std::fs::write(&default, "graphical.target\n").unwrap();
+    let systemctl = sb.path("systemctl");
+    std::fs::write(
+        &systemctl,
+        format!(
+            "#!/bin/sh\necho \"$@\" >> {log:?}\n\
+             case \"$1\" in get-default) cat {d:?};; set-default) echo \"$2\" > {d:?};; esac\n",
+            log = log.display().to_string(),
+            d = default.display().to_string()
+        ),
+    )
+    .unwrap();
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&systemctl, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let units = sb.path("units");
+    let paths = format!(
+        "[paths]\nsystemctl = {:?}\nsystemd_system_dir = {:?}\n",
+        systemctl.display().to_string(),
+        units.display().to_string()
+    );
+    std::fs::write(
+        sb.site_config(),
+        format!("{}[kiosk]\napp = \"Sign\"\n", paths),
+    )
+    .unwrap();
+    bundle(&sb.path("system"), "Sign.lnx", "Sign", "");
+    bundle(&sb.path("system"), "Other.lnx", "Other", "");
+    bundle(&sb.path("root/Applications"), "Mine.lnx", "Mine", "");
+
+    let summary = json(&run_ok(sync));
+    assert_eq!(summary["skipped"], 2);
+    assert!(
+        actions(&summary).iter().all(|(_, app)| app == "Sign"),
+        "{:?}",
+        actions(&summary)
+    );
+    assert!(!sb
+        .path("usr-share-applications/dotlnx-Other.desktop")
+        .exists());
+    assert!(!sb
+        .path("root/.local/share/applications/dotlnx-Mine.desktop")
+        .exists());
+    let service = std::fs::read_to_string(units.join("dotlnx-kiosk.service")).unwrap();
+    assert!(service.contains("\"run\" \"Sign\"") && service.contains("Restart=always"));
+    let target = std::fs::read_to_string(units.join("dotlnx-kiosk.target")).unwrap();
+    assert!(target.contains("Default target before kiosk mode: graphical.target"));
+    assert_eq!(
+        std::fs::read_to_string(&default).unwrap().trim(),
+        "dotlnx-kiosk.target"
+    );
+
+    let extra = bundle(&sb.path("downloads"), "Extra.lnx", "Extra", "");
+    let out = sb
+        .daemon_cmd(&["install", extra.to_str().unwrap()])
+        .unwrap()
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("kiosk mode runs only Sign"));
+    assert!(!sb.path("system/Extra.lnx").exists());
+
+    std::fs::write(sb.site_config(), paths).unwrap();
+    let summary = json(&run_ok(sb.daemon_cmd(&["sync", "--json"]).unwrap()));
+    assert!(actions(&summary).contains(&("remove".into(), "Sign".into())));
+    assert!(
+        !units.join("dotlnx-kiosk.service").exists() && !units.join("dotlnx-kiosk.target").exists()
+    );
+    assert_eq!(
+        std::fs::read_to_string(&default).unwrap().trim(),
+        "graphical.target"
+    );
+    assert!(sb
+        .path("usr-share-applications/dotlnx-Other.desktop")
+        .exists());
+}
+
+#[test]
+fn daemon_quarantines_bundles_others_can_modify() {
+    let sb = Sandbox::new();
+    let Some(sync) = sb.daemon_cmd(&["sync", "--json"]) else {
+        return;
+    };
+    let b = bundle(&sb.path("root/Applications"), "Open.lnx", "Open", "");
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(
+            b.join("config.toml"),
+            std::fs::Permissions::from_mode(0o666),
+        )
+        .unwrap();
+    }
+    let summary = json(&run_ok(sync));
+    assert_eq!(summary["quarantined"], 1);
+    assert!(!sb
+        .path("root/.local/share/applications/dotlnx-Open.desktop")
+        .exists());
+    let state = sb.state();
+    let reasons = &state["quarantined"]["/root/Applications/Open.lnx"]["reasons"];
+    assert!(
+        reasons[0].as_str().unwrap().contains("world-writable"),
+        "{}",
+        reasons
+    );
+}
+
+#[test]
+fn daemon_writes_and_loads_profiles_where_site_paths_say() {
+    let sb = Sandbox::new();
+    let Some(sync) = sb.daemon_cmd(&["sync", "--json"]) else {
+        return;
+    };
+    // A stand-in apparmor_parser that records how it was called.
+    let log = sb.path("parser.log");
+    let parser = sb.path("apparmor_parser");
+    std::fs::write(
+        &parser,
+        format!(
+            "#!/bin/sh\necho \"$@\" >> {:?}\n",
+            log.display().to_string()
+        ),
+    )
+    .unwrap();
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&parser, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let profiles = sb.path("profiles");
+    std::fs::write(
+        sb.site_config(),
+        format!(
+            "[paths]\napparmor_dir = {:?}\napparmor_parser = {:?}\napparmor_cache = \"/var/cache/dotlnx-aa\"\n",
+            profiles.display().to_string(),
+            parser.display().to_string()
+        ),
+    )
+    .unwrap();
+    let b = bundle(&sb.path("root/Applications"), "Boxed.lnx", "Boxed", "");
+    let profile = profiles.join("dotlnx-root-Boxed");
+
+    let summary = json(&run_ok(sync));
+    assert_eq!(actions(&summary), [("install".into(), "Boxed".into())]);
+    assert_eq!(
+        summary["changes"][0]["paths"][1],
+        profile.display().to_string()
+    );
+    assert!(std::fs::read_to_string(&profile)
+        .unwrap()
+        .contains("profile dotlnx-root-Boxed"));
+    let calls = std::fs::read_to_string(&log).unwrap();
+    assert_eq!(
+        calls,
+        format!(
+            "-r --write-cache --cache-loc /var/cache/dotlnx-aa {}\n",
+            profile.display()
+        )
+    );
+    let meta: serde_json::Value = serde_json::from_str(
+        &std::fs::read_to_string(profiles.join(".dotlnx/dotlnx-root-Boxed.json")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(meta["bundle"], "/root/Applications/Boxed.lnx");
+    assert_eq!(meta["tier"], "user:root");
+    assert_eq!(meta["loaded"], meta["hash"]);
+
+    let summary = json(&run_ok(sb.daemon_cmd(&["sync", "--json"]).unwrap()));
+    assert_eq!(summary["unchanged"], serde_json::json!(["Boxed"]));
+
+    std::fs::write(
+        b.join("config.toml"),
+        "name = \"Boxed\"\nexecutable = \"bin/app\"\n[security]\nconfine = false\n",
+    )
+    .unwrap();
+    let summary = json(&run_ok(sb.daemon_cmd(&["sync", "--json"]).unwrap()));
+    assert_eq!(actions(&summary), [("update".into(), "Boxed".into())]);
+    assert!(!profile.exists());
+    assert!(!profiles.join(".dotlnx/dotlnx-root-Boxed.json").exists());
+    let calls = std::fs::read_to_string(&log).unwrap();
+    assert!(
+        calls.ends_with(&format!("-R {}\n", profile.display())),
+        "{}",
+        calls
+    );
+}
+
+#[test]
+fn sync_removes_appimage_versions_beyond_keep_versions() {
+    let sb = Sandbox::new();
+    let Some(sync) = sb.daemon_cmd(&["sync"]) else {
+        return;
+    };
+    std::fs::write(sb.site_config(), "[resources]\nkeep_versions = 2\n").unwrap();
+    let unconfined = "[security]\nconfine = false\n";
+    let mine = bundle(
+        &sb.path("root/Applications"),
+        "Mine.lnx",
+        "Mine",
+        &format!("keep_versions = 1\n{}", unconfined),
+    );
+    let shared = bundle(&sb.path("system"), "Shared.lnx", "Shared", unconfined);
+    let held = bundle(&sb.path("system"), "Held.lnx", "Held", unconfined);
+    // Runs bin/app, so the newer AppImages next to it are not versions of it.
+    let fixed = bundle(
+        &sb.path("system"),
+        "Fixed.lnx",
+        "Fixed",
+        &format!("keep_versions = 1\n{}", unconfined),
+    );
+    for b in [&mine, &shared, &held, &fixed] {
+        for (i, version) in ["1.0", "1.1", "1.2"].iter().enumerate() {
+            let f = std::fs::File::create(b.join(format!("bin/App-{}.AppImage", version))).unwrap();
+            f.set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1000 + i as u64))
+                .unwrap();
+            use std::os::unix::fs::PermissionsExt;
+            f.set_permissions(std::fs::Permissions::from_mode(0o755))
+                .unwrap();
+        }
+        if b != &fixed {
+            let config = std::fs::read_to_string(b.join("config.toml")).unwrap();
+            let config = config.replace("\"bin/app\"", "\"bin/App-*.AppImage\"");
+            std::fs::write(b.join("config.toml"), config).unwrap();
+        }
+    }
+    run_ok(sb.daemon_cmd(&["hold", "Held"]).unwrap());
+    run_ok(sync);
+
+    let left = |b: &std::path::Path| {
+        let mut names: Vec<String> = std::fs::read_dir(b.join("bin"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    };
+    assert_eq!(left(&mine), ["App-1.2.AppImage", "app"]);
+    assert_eq!(
+        left(&shared),
+        ["App-1.1.AppImage", "App-1.2.AppImage", "app"]
+    );
+    assert_eq!(left(&held).len(), 4, "a held bundle is left as it was");
+    assert_eq!(
+        left(&fixed).len(),
+        4,
+        "only versions of the executable are pruned"
+    );
+}
+
+#[test]
+fn system_tier_wins_when_the_site_says_so() {
+    let sb = Sandbox::new();
+    let Some(sync) = sb.daemon_cmd(&["sync", "--json"]) else {
+        return;
+    };
+    std::fs::write(sb.site_config(), "[paths]\nprecedence = \"system\"\n").unwrap();
+    let unconfined = "[security]\nconfine = false\n";
+    bundle(
+        &sb.path("root/Applications"),
+        "Editor.lnx",
+        "Editor",
+        unconfined,
+    );
+    bundle(
+        &sb.path("root/Applications"),
+        "Mine.lnx",
+        "Mine",
+        unconfined,
+    );
+    bundle(&sb.path("system"), "Editor.lnx", "Editor", unconfined);
+
+    let summary = json(&run_ok(sync));
+    let mut done = actions(&summary);
+    done.sort();
+    assert_eq!(
+        done,
+        [
+            ("install".into(), "Editor".into()),
+            ("install".into(), "Mine".into())
+        ]
+    );
+    assert_eq!(summary["skipped"], 1);
+    assert!(sb
+        .path("usr-share-applications/dotlnx-Editor.desktop")
+        .exists());
+    assert!(!sb
+        .path("root/.local/share/applications/dotlnx-Editor.desktop")
+        .exists());
+    assert!(sb
+        .path("root/.local/share/applications/dotlnx-Mine.desktop")
+        .exists());
+}
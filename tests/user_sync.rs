@@ -0,0 +1,1044 @@
+//! End-to-end: one user's Applications folder through sync, run, install and uninstall.
+
+mod common;
+
+use common::{actions, bundle, json, run_ok, Sandbox};
+
+#[test]
+fn sync_installs_updates_and_removes() {
+    let sb = Sandbox::new();
+    let Some(mut sync) = sb.user_cmd(&["sync", "--json"]) else {
+        return;
+    };
+    let b = bundle(&sb.apps(), "Editor.lnx", "Editor", "comment = \"Edits\"\n");
+    let entry = sb.desktop_dir().join("dotlnx-Editor.desktop");
+
+    let summary = json(&run_ok(sync));
+    assert_eq!(actions(&summary), [("install".into(), "Editor".into())]);
+    let desktop = std::fs::read_to_string(&entry).unwrap();
+    assert!(desktop.contains("Name=Editor\n"));
+    assert!(desktop.contains("Comment=Edits\n"));
+    assert!(desktop.contains(&format!(
+        "Exec={}/bin/app %u",
+        b.canonicalize().unwrap().display()
+    )));
+
+    sync = sb.user_cmd(&["sync", "--json"]).unwrap();
+    let summary = json(&run_ok(sync));
+    assert_eq!(summary["changed"], false);
+    assert_eq!(summary["unchanged"], serde_json::json!(["Editor"]));
+
+    std::fs::write(
+        b.join("config.toml"),
+        "name = \"Editor\"\nexecutable = \"bin/app\"\ncomment = \"Edits text\"\n",
+    )
+    .unwrap();
+    let summary = json(&run_ok(sb.user_cmd(&["sync", "--json"]).unwrap()));
+    assert_eq!(actions(&summary), [("update".into(), "Editor".into())]);
+    assert!(std::fs::read_to_string(&entry)
+        .unwrap()
+        .contains("Comment=Edits text\n"));
+
+    std::fs::remove_dir_all(&b).unwrap();
+    let summary = json(&run_ok(sb.user_cmd(&["sync", "--json"]).unwrap()));
+    assert_eq!(actions(&summary), [("remove".into(), "Editor".into())]);
+    assert!(!entry.exists());
+
+    let state = sb.state();
+    assert_eq!(state["last_sync"]["installed"], 0);
+    let recorded: Vec<&str> = state["recent_changes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["action"].as_str().unwrap())
+        .collect();
+    assert_eq!(recorded, ["install", "update", "remove"]);
+}
+
+#[test]
+fn dry_run_and_check_change_nothing() {
+    let sb = Sandbox::new();
+    let Some(dry_run) = sb.user_cmd(&["sync", "--dry-run", "--json"]) else {
+        return;
+    };
+    bundle(&sb.apps(), "Tool.lnx", "Tool", "");
+    let summary = json(&run_ok(dry_run));
+    assert_eq!(actions(&summary), [("install".into(), "Tool".into())]);
+    assert!(!sb.desktop_dir().join("dotlnx-Tool.desktop").exists());
+
+    let check = sb.user_cmd(&["sync", "--check"]).unwrap().output().unwrap();
+    assert_eq!(check.status.code(), Some(2));
+    run_ok(sb.user_cmd(&["sync"]).unwrap());
+    let check = sb.user_cmd(&["sync", "--check"]).unwrap().output().unwrap();
+    assert_eq!(check.status.code(), Some(0));
+}
+
+#[test]
+fn invalid_bundles_are_skipped_not_installed() {
+    let sb = Sandbox::new();
+    let Some(sync) = sb.user_cmd(&["sync", "--json"]) else {
+        return;
+    };
+    bundle(&sb.apps(), "Good.lnx", "Good", "");
+    let broken = bundle(&sb.apps(), "Broken.lnx", "Broken", "");
+    std::fs::remove_file(broken.join("bin/app")).unwrap();
+    let summary = json(&run_ok(sync));
+    assert_eq!(summary["installed"], 1);
+    assert_eq!(summary["skipped"], 1);
+    assert!(sb.desktop_dir().join("dotlnx-Good.desktop").exists());
+    assert!(!sb.desktop_dir().join("dotlnx-Broken.desktop").exists());
+}
+
+#[test]
+fn install_run_and_uninstall_by_name() {
+    let sb = Sandbox::new();
+    let marker = sb.path("ran");
+    let args = format!("args = [{:?}]\n", marker.display().to_string());
+    let src = bundle(&sb.path("downloads"), "Notes.lnx", "Notes", &args);
+    let Some(mut check) = sb.user_cmd(&["install", "--check", src.to_str().unwrap()]) else {
+        return;
+    };
+    assert_eq!(check.output().unwrap().status.code(), Some(2));
+    assert!(!sb.apps().join("Notes.lnx").exists());
+    // Without a running service, install syncs by itself.
+    let out = run_ok(
+        sb.user_cmd(&["install", "--json", src.to_str().unwrap()])
+            .unwrap(),
+    );
+    assert_eq!(json(&out)["changes"][0]["app"], "Notes");
+    assert!(sb.apps().join("Notes.lnx/config.toml").is_file());
+    let mut check = sb
+        .user_cmd(&["install", "--check", src.to_str().unwrap()])
+        .unwrap();
+    assert_eq!(check.output().unwrap().status.code(), Some(0));
+    let entry = sb.desktop_dir().join("dotlnx-Notes.desktop");
+    assert!(entry.exists());
+    assert_eq!(
+        sb.state()["names"]["Notes"][0],
+        sb.apps().join("Notes.lnx").display().to_string()
+    );
+
+    run_ok(sb.user_cmd(&["run", "Notes"]).unwrap());
+    assert!(marker.exists(), "run started the bundle's executable");
+
+    let out = run_ok(sb.user_cmd(&["uninstall", "Notes", "--json"]).unwrap());
+    assert_eq!(json(&out)["changed"], true);
+    assert!(!entry.exists());
+}
+
+#[test]
+fn interrupted_install_is_rolled_back_and_redone() {
+    let sb = Sandbox::new();
+    let Some(sync) = sb.user_cmd(&["sync"]) else {
+        return;
+    };
+    bundle(&sb.apps(), "App.lnx", "App", "");
+    run_ok(sync);
+    let entry = sb.desktop_dir().join("dotlnx-App.desktop");
+    let good = std::fs::read_to_string(&entry).unwrap();
+    // A sync that died half-way through writing the entry.
+    std::fs::write(&entry, "[Desktop Entry]\n").unwrap();
+    let journal = serde_json::json!({
+        "op": "install",
+        "app": "App",
+        "desktop_dir": sb.desktop_dir(),
+        "user": null,
+        "profile": null,
+        "started": 0,
+    });
+    std::fs::write(sb.state_dir().join("journal.json"), journal.to_string()).unwrap();
+
+    let summary = json(&run_ok(sb.user_cmd(&["sync", "--json"]).unwrap()));
+    assert_eq!(actions(&summary), [("install".into(), "App".into())]);
+    assert_eq!(std::fs::read_to_string(&entry).unwrap(), good);
+    assert!(!sb.state_dir().join("journal.json").exists());
+}
+
+#[test]
+fn folder_icons_are_removed_with_the_icon_or_the_app() {
+    let sb = Sandbox::new();
+    let Some(sync) = sb.user_cmd(&["sync"]) else {
+        return;
+    };
+    let b = bundle(&sb.apps(), "Paint.lnx", "Paint", "icon = \"icon.png\"\n");
+    std::fs::write(b.join("icon.png"), "").unwrap();
+    let directory = b.join(".directory");
+    run_ok(sync);
+    assert!(std::fs::read_to_string(&directory)
+        .unwrap()
+        .contains("Icon="));
+    let key = b.display().to_string();
+    assert_eq!(sb.state()["decorations"][&key]["directory_file"], true);
+
+    // The app loses its icon.
+    std::fs::write(
+        b.join("config.toml"),
+        "name = \"Paint\"\nexecutable = \"bin/app\"\n",
+    )
+    .unwrap();
+    run_ok(sb.user_cmd(&["sync"]).unwrap());
+    assert!(!directory.exists());
+    assert!(sb.state()["decorations"].get(&key).is_none());
+
+    // The app is uninstalled (its bundle broke) while the folder stays.
+    std::fs::write(
+        b.join("config.toml"),
+        "name = \"Paint\"\nexecutable = \"bin/app\"\nicon = \"icon.png\"\n",
+    )
+    .unwrap();
+    run_ok(sb.user_cmd(&["sync"]).unwrap());
+    assert!(directory.exists());
+    std::fs::remove_file(b.join("bin/app")).unwrap();
+    let summary = json(&run_ok(sb.user_cmd(&["sync", "--json"]).unwrap()));
+    assert_eq!(actions(&summary), [("remove".into(), "Paint".into())]);
+    assert!(!directory.exists());
+    assert!(sb.state()["decorations"].get(&key).is_none());
+
+    // Nothing left for gc.
+    let out = run_ok(sb.user_cmd(&["gc", "--json"]).unwrap());
+    assert_eq!(json(&out)["changed"], false);
+}
+
+#[test]
+fn second_bundle_with_a_taken_name_is_skipped() {
+    let sb = Sandbox::new();
+    let Some(sync) = sb.user_cmd(&["sync", "--json"]) else {
+        return;
+    };
+    let first = bundle(&sb.apps(), "A.lnx", "Twin", "comment = \"first\"\n");
+    bundle(&sb.apps(), "B.lnx", "Twin", "comment = \"second\"\n");
+    let summary = json(&run_ok(sync));
+    assert_eq!(actions(&summary), [("install".into(), "Twin".into())]);
+    assert_eq!(summary["skipped"], 1);
+    let entry = std::fs::read_to_string(sb.desktop_dir().join("dotlnx-Twin.desktop")).unwrap();
+    assert!(entry.contains("Comment=first\n"));
+    assert_eq!(
+        sb.state()["names"]["Twin"],
+        serde_json::json!([first.display().to_string()])
+    );
+
+    // Stable: the next pass neither flips the entry nor reports a change.
+    let summary = json(&run_ok(sb.user_cmd(&["sync", "--json"]).unwrap()));
+    assert_eq!(summary["changed"], false);
+}
+
+#[test]
+fn vendors_keep_apps_of_the_same_name_apart() {
+    let sb = Sandbox::new();
+    let Some(sync) = sb.user_cmd(&["sync", "--json"]) else {
+        return;
+    };
+    let marker = sb.path("ran");
+    let args = format!("args = [{:?}]\n", marker.display().to_string());
+    bundle(
+        &sb.apps(),
+        "A.lnx",
+        "Twin",
+        &format!("vendor = \"com.alpha\"\n{}", args),
+    );
+    bundle(&sb.apps(), "B.lnx", "Twin", "vendor = \"org.beta\"\n");
+    let summary = json(&run_ok(sync));
+    let mut done = actions(&summary);
+    done.sort();
+    assert_eq!(
+        done,
+        [
+            ("install".into(), "com.alpha.Twin".into()),
+            ("install".into(), "org.beta.Twin".into())
+        ]
+    );
+    let entry =
+        std::fs::read_to_string(sb.desktop_dir().join("dotlnx-com.alpha.Twin.desktop")).unwrap();
+    assert!(entry.contains("Name=Twin\n"));
+    assert!(sb
+        .desktop_dir()
+        .join("dotlnx-org.beta.Twin.desktop")
+        .exists());
+
+    run_ok(sb.user_cmd(&["run", "com.alpha.Twin"]).unwrap());
+    assert!(marker.exists(), "the ID picks the vendor's bundle");
+}
+
+#[test]
+fn configured_prefix_names_entries_and_leaves_other_deployments_alone() {
+    let sb = Sandbox::new();
+    let Some(sync) = sb.user_cmd(&["sync", "--json"]) else {
+        return;
+    };
+    std::fs::write(sb.site_config(), "[paths]\nprefix = \"corp-apps-\"\n").unwrap();
+    // An entry written by another deployment with the default prefix.
+    std::fs::create_dir_all(sb.desktop_dir()).unwrap();
+    let foreign = sb.desktop_dir().join("dotlnx-Other.desktop");
+    std::fs::write(&foreign, "[Desktop Entry]\n").unwrap();
+    let b = bundle(&sb.apps(), "Mail.lnx", "Mail", "");
+
+    let summary = json(&run_ok(sync));
+    assert_eq!(actions(&summary), [("install".into(), "Mail".into())]);
+    let entry = sb.desktop_dir().join("corp-apps-Mail.desktop");
+    assert!(entry.exists());
+    assert!(!sb.desktop_dir().join("dotlnx-Mail.desktop").exists());
+
+    std::fs::remove_dir_all(&b).unwrap();
+    let summary = json(&run_ok(sb.user_cmd(&["sync", "--json"]).unwrap()));
+    assert_eq!(actions(&summary), [("remove".into(), "Mail".into())]);
+    assert!(!entry.exists());
+    assert!(foreign.exists());
+}
+
+#[test]
+fn clear_env_keeps_session_variables_from_the_app() {
+    let sb = Sandbox::new();
+    let out = sb.path("env.txt");
+    let Some(sync) = sb.user_cmd(&["sync"]) else {
+        return;
+    };
+    let b = bundle(
+        &sb.apps(),
+        "Env.lnx",
+        "Env",
+        "env = [\"GREETING=hi\"]\n[security]\nconfine = false\nclear_env = true\n",
+    );
+    std::fs::write(b.join("bin/app"), "#!/bin/sh\nenv > \"$1\"\n").unwrap();
+    run_ok(sync);
+    let entry = std::fs::read_to_string(sb.desktop_dir().join("dotlnx-Env.desktop")).unwrap();
+    assert!(entry.contains(" run Env %u\n"), "{}", entry);
+
+    let mut run = sb.user_cmd(&["run", "Env", out.to_str().unwrap()]).unwrap();
+    run.env("LD_PRELOAD", "/nonexistent.so");
+    run_ok(run);
+    let env = std::fs::read_to_string(&out).unwrap();
+    assert!(env.contains("GREETING=hi\n"));
+    assert!(env.contains(&format!("HOME={}\n", sb.home().display())));
+    assert!(!env.contains("LD_PRELOAD"), "{}", env);
+    assert!(!env.contains("DOTLNX_"), "{}", env);
+}
+
+#[test]
+fn seccomp_filter_is_installed_by_run() {
+    if !matches!(std::env::consts::ARCH, "x86_64" | "aarch64") {
+        return;
+    }
+    let sb = Sandbox::new();
+    let out = sb.path("status");
+    let Some(sync) = sb.user_cmd(&["sync"]) else {
+        return;
+    };
+    let b = bundle(
+        &sb.apps(),
+        "Svc.lnx",
+        "Svc",
+        "[security]\nconfine = false\nseccomp = \"strict\"\n",
+    );
+    std::fs::write(
+        b.join("bin/app"),
+        "#!/bin/sh\ngrep -E '^(Seccomp|NoNewPrivs):' /proc/self/status > \"$1\"\n",
+    )
+    .unwrap();
+    run_ok(sync);
+    let entry = std::fs::read_to_string(sb.desktop_dir().join("dotlnx-Svc.desktop")).unwrap();
+    assert!(entry.contains(" run Svc %u\n"), "{}", entry);
+
+    run_ok(sb.user_cmd(&["run", "Svc", out.to_str().unwrap()]).unwrap());
+    let status = std::fs::read_to_string(&out).unwrap();
+    assert!(status.contains("Seccomp:\t2\n"), "{}", status);
+    assert!(status.contains("NoNewPrivs:\t1\n"), "{}", status);
+}
+
+#[test]
+fn run_applies_umask_nice_and_site_no_new_privs() {
+    let sb = Sandbox::new();
+    let out = sb.path("procs");
+    let Some(sync) = sb.user_cmd(&["sync"]) else {
+        return;
+    };
+    std::fs::write(sb.site_config(), "[run]\nno_new_privs = true\n").unwrap();
+    let b = bundle(
+        &sb.apps(),
+        "Batch.lnx",
+        "Batch",
+        "umask = \"027\"\nnice = 7\nionice = \"idle\"\n[security]\nconfine = false\n",
+    );
+    std::fs::write(
+        b.join("bin/app"),
+        "#!/bin/sh\n{ grep NoNewPrivs: /proc/self/status; umask; cut -d' ' -f19 /proc/self/stat; } > \"$1\"\n",
+    )
+    .unwrap();
+    run_ok(sync);
+    let entry = std::fs::read_to_string(sb.desktop_dir().join("dotlnx-Batch.desktop")).unwrap();
+    assert!(entry.contains(" run Batch %u\n"), "{}", entry);
+
+    run_ok(
+        sb.user_cmd(&["run", "Batch", out.to_str().unwrap()])
+            .unwrap(),
+    );
+    let got = std::fs::read_to_string(&out).unwrap();
+    assert_eq!(got, "NoNewPrivs:\t1\n0027\n7\n");
+}
+
+#[test]
+fn service_bundles_get_a_user_unit_instead_of_a_menu_entry() {
+    let sb = Sandbox::new();
+    let Some(sync) = sb.user_cmd(&["sync", "--json"]) else {
+        return;
+    };
+    // A stand-in systemctl that records how it was called.
+    let log = sb.path("systemctl.log");
+    let systemctl = sb.path("systemctl");
+    std::fs::write(
+        &systemctl,
+        format!(
+            "#!/bin/sh\necho \"$@\" >> {:?}\n",
+            log.display().to_string()
+        ),
+    )
+    .unwrap();
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&systemctl, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    std::fs::write(
+        sb.site_config(),
+        format!(
+            "[paths]\nsystemctl = {:?}\n",
+            systemctl.display().to_string()
+        ),
+    )
+    .unwrap();
+    let b = bundle(&sb.apps(), "Api.lnx", "Api", "type = \"service\"\n");
+    let unit = sb.home().join(".config/systemd/user/dotlnx-Api.service");
+
+    let summary = json(&run_ok(sync));
+    assert_eq!(actions(&summary), [("install".into(), "Api".into())]);
+    assert_eq!(
+        summary["changes"][0]["paths"][0],
+        unit.display().to_string()
+    );
+    assert!(!sb.desktop_dir().join("dotlnx-Api.desktop").exists());
+    let contents = std::fs::read_to_string(&unit).unwrap();
+    assert!(contents.starts_with("# Generated by dotlnx for Api\n"));
+    assert!(contents.contains(&format!(
+        "ExecStart=\"{}/bin/app\"\n",
+        b.canonicalize().unwrap().display()
+    )));
+    assert_eq!(
+        std::fs::read_to_string(&log).unwrap(),
+        "--user daemon-reload\n--user enable dotlnx-Api.service\n--user restart dotlnx-Api.service\n"
+    );
+
+    let summary = json(&run_ok(sb.user_cmd(&["sync", "--json"]).unwrap()));
+    assert_eq!(summary["unchanged"], serde_json::json!(["Api"]));
+
+    std::fs::remove_dir_all(&b).unwrap();
+    let summary = json(&run_ok(sb.user_cmd(&["sync", "--json"]).unwrap()));
+    assert_eq!(actions(&summary), [("remove".into(), "Api".into())]);
+    assert!(!unit.exists());
+    let calls = std::fs::read_to_string(&log).unwrap();
+    assert!(
+        calls.ends_with("--user disable --now dotlnx-Api.service\n--user daemon-reload\n"),
+        "{}",
+        calls
+    );
+}
+
+#[test]
+fn low_memory_sync_installs_bundle_by_bundle() {
+    let sb = Sandbox::new();
+    let Some(sync) = sb.user_cmd(&["sync", "--low-memory", "--json"]) else {
+        return;
+    };
+    bundle(&sb.apps(), "One.lnx", "One", "");
+    bundle(&sb.apps(), "Two.lnx", "Two", "");
+    let mut installed = actions(&json(&run_ok(sync)));
+    installed.sort();
+    assert_eq!(
+        installed,
+        [
+            ("install".into(), "One".into()),
+            ("install".into(), "Two".into())
+        ]
+    );
+    assert!(sb.desktop_dir().join("dotlnx-One.desktop").exists());
+    assert!(sb.desktop_dir().join("dotlnx-Two.desktop").exists());
+
+    std::fs::write(sb.site_config(), "[resources]\nlow_memory = true\n").unwrap();
+    let summary = json(&run_ok(sb.user_cmd(&["sync", "--json"]).unwrap()));
+    assert_eq!(summary["changed"], false);
+}
+
+#[test]
+fn scanner_findings_quarantine_until_fixed() {
+    let sb = Sandbox::new();
+    let Some(sync) = sb.user_cmd(&["sync", "--json"]) else {
+        return;
+    };
+    std::fs::write(
+        sb.site_config(),
+        "[scan]\ncommand = [\"sh\", \"-c\", \"grep -rl EICAR \\\"$0\\\" && exit 1; exit 0\"]\n",
+    )
+    .unwrap();
+    let b = bundle(&sb.apps(), "Game.lnx", "Game", "");
+    std::fs::write(b.join("readme.txt"), "X5O EICAR test\n").unwrap();
+    let summary = json(&run_ok(sync));
+    assert_eq!(summary["quarantined"], 1);
+    assert!(!sb.desktop_dir().join("dotlnx-Game.desktop").exists());
+    let key = b.display().to_string();
+    let reasons = &sb.state()["quarantined"][&key]["reasons"];
+    assert!(
+        reasons[0].as_str().unwrap().ends_with("readme.txt"),
+        "{}",
+        reasons
+    );
+
+    std::fs::remove_file(b.join("readme.txt")).unwrap();
+    let summary = json(&run_ok(sb.user_cmd(&["sync", "--json"]).unwrap()));
+    assert_eq!(actions(&summary), [("install".into(), "Game".into())]);
+    assert!(sb.state()["quarantined"].as_object().unwrap().is_empty());
+}
+
+#[test]
+fn pinned_system_apps_refuse_other_users_at_launch() {
+    let sb = Sandbox::new();
+    let marker = sb.path("ran");
+    let extra = format!(
+        "args = [{:?}]\nallowed_users = [\"someone-else\"]\n[security]\nconfine = false\n",
+        marker.display().to_string()
+    );
+    bundle(&sb.path("system"), "Lab.lnx", "Lab", &extra);
+    let Some(mut run) = sb.user_cmd(&["run", "Lab"]) else {
+        return;
+    };
+    let out = run
+        .env("DOTLNX_SYSTEM_APPLICATIONS", sb.path("system"))
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("Lab may only be run by someone-else"),
+        "{}",
+        stderr
+    );
+    assert!(!marker.exists());
+}
+
+#[test]
+fn failed_menu_launches_show_a_dialog_and_are_logged() {
+    let sb = Sandbox::new();
+    let Some(sync) = sb.user_cmd(&["sync"]) else {
+        return;
+    };
+    let b = bundle(
+        &sb.apps(),
+        "Crash.lnx",
+        "Crash",
+        "[security]\nconfine = false\n",
+    );
+    std::fs::write(
+        b.join("bin/app"),
+        "#!/bin/sh\necho 'cannot open libfoo.so' >&2\nexit 3\n",
+    )
+    .unwrap();
+    run_ok(sync);
+    // A stand-in zenity records the dialog it was asked to show.
+    let fake = sb.path("fakebin");
+    std::fs::create_dir_all(&fake).unwrap();
+    let dialog = sb.path("dialog.txt");
+    let zenity = format!(
+        "#!/bin/sh\nprintf '%s\\n' \"$@\" > {:?}.tmp && mv {:?}.tmp {:?}\n",
+        dialog, dialog, dialog
+    );
+    std::fs::write(fake.join("zenity"), zenity).unwrap();
+    std::fs::set_permissions(
+        fake.join("zenity"),
+        std::os::unix::fs::PermissionsExt::from_mode(0o755),
+    )
+    .unwrap();
+    let menu_launch = |name: &str| {
+        let mut run = sb.user_cmd(&["run", name]).unwrap();
+        run.env("DISPLAY", ":0")
+            .env("PATH", format!("{}:/usr/bin:/bin", fake.display()));
+        let out = run.output().unwrap();
+        assert!(!out.status.success());
+        for _ in 0..100 {
+            if dialog.exists() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        let shown = std::fs::read_to_string(&dialog);
+        let _ = std::fs::remove_file(&dialog);
+        shown
+    };
+
+    let shown = menu_launch("Crash").unwrap();
+    assert!(shown.contains("Could not start Crash\n"), "{}", shown);
+    assert!(
+        shown.contains("exited with status 3 right after starting"),
+        "{}",
+        shown
+    );
+    assert!(shown.contains("cannot open libfoo.so"), "{}", shown);
+    assert!(shown.contains("dotlnx logs Crash"), "{}", shown);
+    let logs = run_ok(sb.user_cmd(&["logs", "Crash"]).unwrap());
+    assert!(String::from_utf8_lossy(&logs.stdout).contains("cannot open libfoo.so\n"));
+    let report = json(&run_ok(sb.user_cmd(&["report", "--json"]).unwrap()));
+    let app = &report["apps"][0];
+    assert_eq!(app["id"], "Crash");
+    assert_eq!(app["confinement"], "unconfined");
+    assert_eq!(app["installed"], true);
+    assert!(app["last_launch"].is_string());
+    assert_eq!(
+        app["recent_failures"][0]["reason"],
+        "exited with status 3 right after starting"
+    );
+
+    let shown = menu_launch("Nope").unwrap();
+    assert!(shown.contains("app not found: Nope"), "{}", shown);
+}
+
+#[test]
+fn run_check_smoke_tests_an_app() {
+    let sb = Sandbox::new();
+    let Some(sync) = sb.user_cmd(&["sync"]) else {
+        return;
+    };
+    let server = bundle(
+        &sb.apps(),
+        "Server.lnx",
+        "Server",
+        "[security]\nconfine = false\n",
+    );
+    std::fs::write(
+        server.join("bin/app"),
+        "#!/bin/sh\necho listening\nexec sleep 30\n",
+    )
+    .unwrap();
+    let broken = bundle(
+        &sb.apps(),
+        "Broken.lnx",
+        "Broken",
+        "[security]\nconfine = false\n",
+    );
+    std::fs::write(
+        broken.join("bin/app"),
+        "#!/bin/sh\necho 'missing libfoo.so' >&2\nexit 2\n",
+    )
+    .unwrap();
+    run_ok(sync);
+    let log = sb.path("out.log");
+
+    // Still running at the limit: passes, and the app is stopped.
+    let started = std::time::Instant::now();
+    let out = run_ok(
+        sb.user_cmd(&[
+            "run",
+            "--check",
+            "--wait",
+            "1",
+            "--output",
+            log.to_str().unwrap(),
+            "Server",
+        ])
+        .unwrap(),
+    );
+    assert!(started.elapsed() < std::time::Duration::from_secs(10));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "ok: Server still running after 1s\n"
+    );
+    assert_eq!(std::fs::read_to_string(&log).unwrap(), "listening\n");
+
+    let out = sb
+        .user_cmd(&[
+            "run",
+            "--check",
+            "--output",
+            log.to_str().unwrap(),
+            "Broken",
+        ])
+        .unwrap()
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(1));
+    assert!(
+        String::from_utf8_lossy(&out.stdout).starts_with("failed: Broken exited (exit status: 2)")
+    );
+    assert_eq!(
+        std::fs::read_to_string(&log).unwrap(),
+        "missing libfoo.so\n"
+    );
+
+    // --wait alone reports the timeout like timeout(1).
+    let out = sb
+        .user_cmd(&["run", "--wait", "1", "Server"])
+        .unwrap()
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(124));
+}
+
+#[test]
+fn ephemeral_home_is_discarded_when_the_app_exits() {
+    let sb = Sandbox::new();
+    let Some(sync) = sb.user_cmd(&["sync"]) else {
+        return;
+    };
+    let extra =
+        "[security]\nconfine = false\nephemeral_home = true\npersist_paths = [\".config/Demo\"]\n";
+    let demo = bundle(&sb.apps(), "Demo.lnx", "Demo", extra);
+    std::fs::write(
+        demo.join("bin/app"),
+        "#!/bin/sh\necho \"$HOME\"\necho scratch > \"$HOME/notes.txt\"\necho kept > \"$XDG_CONFIG_HOME/Demo/state\"\n",
+    )
+    .unwrap();
+    run_ok(sync);
+    assert!(
+        std::fs::read_to_string(sb.desktop_dir().join("dotlnx-Demo.desktop"))
+            .unwrap()
+            .contains(" run Demo")
+    );
+    let runtime = sb.path("runtime");
+    std::fs::create_dir(&runtime).unwrap();
+
+    let mut run = sb.user_cmd(&["run", "Demo"]).unwrap();
+    run.env("XDG_RUNTIME_DIR", &runtime);
+    let out = run_ok(run);
+    let home = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    assert!(
+        home.starts_with(runtime.join("dotlnx-home/Demo.").to_str().unwrap()),
+        "{}",
+        home
+    );
+    assert!(!std::path::Path::new(&home).exists());
+    assert!(!sb.home().join("notes.txt").exists());
+    assert_eq!(
+        std::fs::read_to_string(sb.home().join(".config/Demo/state")).unwrap(),
+        "kept\n"
+    );
+}
+
+#[test]
+fn list_shows_apps_and_what_is_installed() {
+    let sb = Sandbox::new();
+    let Some(list) = sb.user_cmd(&["list", "--json"]) else {
+        return;
+    };
+    bundle(
+        &sb.apps(),
+        "Editor.lnx",
+        "Editor",
+        "[security]\nconfine = false\n",
+    );
+    let listed = json(&run_ok(list));
+    let app = &listed["apps"][0];
+    assert_eq!(app["name"], "Editor");
+    assert_eq!(app["tier"], "user:tester");
+    assert_eq!(app["confined"], false);
+    assert_eq!(app["desktop_installed"], false);
+
+    run_ok(sb.user_cmd(&["sync"]).unwrap());
+    let listed = json(&run_ok(sb.user_cmd(&["list", "--json"]).unwrap()));
+    assert_eq!(listed["apps"][0]["desktop_installed"], true);
+    assert_eq!(listed["apps"][0]["profile"], serde_json::Value::Null);
+    let text =
+        String::from_utf8_lossy(&run_ok(sb.user_cmd(&["list"]).unwrap()).stdout).into_owned();
+    let row = text
+        .lines()
+        .find(|l| l.starts_with("Editor "))
+        .unwrap_or_else(|| panic!("{}", text));
+    assert!(
+        row.contains("unconfined") && row.contains(" yes ") && row.ends_with("Editor.lnx"),
+        "{}",
+        row
+    );
+}
+
+#[test]
+fn info_explains_how_an_app_is_launched() {
+    let sb = Sandbox::new();
+    let Some(info) = sb.user_cmd(&["info", "Viewer", "--json"]) else {
+        return;
+    };
+    bundle(
+        &sb.apps(),
+        "Viewer.lnx",
+        "Viewer",
+        "args = [\"--safe\"]\nenv = [\"VIEWER_MODE=kiosk\"]\n",
+    );
+    let shown = json(&run_ok(info));
+    assert_eq!(shown["tier"], "user");
+    assert_eq!(shown["executable_exists"], true);
+    assert_eq!(shown["args"][0], "--safe");
+    assert_eq!(shown["env"][0], "VIEWER_MODE=kiosk");
+    assert_eq!(shown["confined"], true);
+    assert_eq!(shown["profile"], "dotlnx-tester-Viewer");
+    assert_eq!(shown["desktop_installed"], false);
+    assert!(shown["desktop"].as_str().unwrap().contains("Name=Viewer\n"));
+    assert!(shown["problems"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|p| p.as_str().unwrap().contains("menu entry is not installed")));
+
+    run_ok(sb.user_cmd(&["sync"]).unwrap());
+    let text = String::from_utf8_lossy(&run_ok(sb.user_cmd(&["info", "Viewer"]).unwrap()).stdout)
+        .into_owned();
+    assert!(text.contains("Validation:   ok\n"), "{}", text);
+    assert!(text.contains("(installed)\n"), "{}", text);
+    let out = sb.user_cmd(&["info", "Nope"]).unwrap().output().unwrap();
+    assert!(!out.status.success());
+}
+
+#[test]
+fn hooks_see_installs_and_removals() {
+    let sb = Sandbox::new();
+    let Some(sync) = sb.user_cmd(&["sync"]) else {
+        return;
+    };
+    let hooks = sb.path("hooks");
+    let events = sb.path("events");
+    for event in ["pre-sync", "post-install", "post-remove"] {
+        let dir = hooks.join(format!("{}.d", event));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("50-log"),
+            format!("#!/bin/sh\ncat >> {:?}\n", events),
+        )
+        .unwrap();
+        std::fs::set_permissions(
+            dir.join("50-log"),
+            std::os::unix::fs::PermissionsExt::from_mode(0o755),
+        )
+        .unwrap();
+    }
+    std::fs::write(sb.site_config(), format!("[hooks]\ndir = {:?}\n", hooks)).unwrap();
+    let b = bundle(&sb.apps(), "Notes.lnx", "Notes", "");
+    let read = || -> Vec<serde_json::Value> {
+        let text = std::fs::read_to_string(&events).unwrap_or_default();
+        let _ = std::fs::remove_file(&events);
+        text.lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    };
+
+    run_ok(sync);
+    let seen = read();
+    assert_eq!(seen.len(), 2, "{:?}", seen);
+    assert_eq!(seen[0]["event"], "pre-sync");
+    assert_eq!(
+        (&seen[1]["event"], &seen[1]["action"], &seen[1]["app"]),
+        (&"post-install".into(), &"install".into(), &"Notes".into())
+    );
+
+    std::fs::remove_dir_all(&b).unwrap();
+    run_ok(sb.user_cmd(&["sync"]).unwrap());
+    let seen = read();
+    assert_eq!(
+        (&seen[1]["event"], &seen[1]["app"]),
+        (&"post-remove".into(), &"Notes".into())
+    );
+
+    // A dry run changes nothing, so no hook runs.
+    run_ok(sb.user_cmd(&["sync", "--dry-run"]).unwrap());
+    assert!(read().is_empty());
+}
+
+#[test]
+fn errors_follow_the_locale_and_keep_their_message_id() {
+    let sb = Sandbox::new();
+    let Some(mut run) = sb.user_cmd(&["run", "Nope"]) else {
+        return;
+    };
+    run.env("LANG", "de_DE.UTF-8");
+    let out = run.output().unwrap();
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("App nicht gefunden: Nope"), "{}", stderr);
+    assert!(
+        stderr.contains("message_id=\"app-not-found\""),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn validate_and_list_honour_output_and_color() {
+    let sb = Sandbox::new();
+    let Some(mut validate) = sb.user_cmd(&["validate", "--format", "json"]) else {
+        return;
+    };
+    bundle(&sb.apps(), "Good.lnx", "Good", "");
+    let bad = bundle(&sb.apps(), "Bad.lnx", "Bad", "");
+    std::fs::remove_file(bad.join("bin/app")).unwrap();
+    let out = validate.arg(sb.apps()).output().unwrap();
+    assert!(!out.status.success());
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(result["valid"], false);
+    let bundles = result["bundles"].as_array().unwrap();
+    assert_eq!(bundles.len(), 2);
+    assert!(bundles
+        .iter()
+        .any(|b| b["name"] == "Good" && b["valid"] == true));
+    assert!(bundles.iter().any(|b| b["valid"] == false
+        && b["error"]
+            .as_str()
+            .unwrap()
+            .contains("executable not found")));
+
+    // Piped: plain text, unless colour is asked for.
+    let good = sb.apps().join("Good.lnx");
+    let plain = run_ok(sb.user_cmd(&["validate", good.to_str().unwrap()]).unwrap());
+    assert_eq!(
+        String::from_utf8_lossy(&plain.stdout),
+        format!("{}: valid\n", good.display())
+    );
+    let listed = run_ok(sb.user_cmd(&["--color", "always", "list"]).unwrap());
+    assert!(String::from_utf8_lossy(&listed.stdout).contains("\x1b[1mNAME\x1b[0m"));
+}
+
+#[test]
+fn status_reports_the_last_sync_per_tier() {
+    let sb = Sandbox::new();
+    let Some(status) = sb.user_cmd(&["status", "--json"]) else {
+        return;
+    };
+    assert_eq!(json(&run_ok(status))["tiers"].as_array().unwrap().len(), 0);
+    bundle(&sb.apps(), "Viewer.lnx", "Viewer", "");
+    let broken = sb.apps().join("Broken.lnx");
+    std::fs::create_dir_all(&broken).unwrap();
+    std::fs::write(broken.join("config.toml"), "name = [\n").unwrap();
+    run_ok(sb.user_cmd(&["sync"]).unwrap());
+
+    let shown = json(&run_ok(
+        sb.user_cmd(&["status", "--format", "json"]).unwrap(),
+    ));
+    let tier = &shown["tiers"][0];
+    assert_eq!(tier["tier"], "user:tester");
+    assert_eq!(tier["discovered"], 2);
+    assert_eq!(tier["installed"], 1);
+    assert_eq!(tier["skipped"], 1);
+    assert!(tier["time"].as_u64().unwrap() > 0);
+    assert!(tier["invalid"][0]["bundle"]
+        .as_str()
+        .unwrap()
+        .ends_with("Broken.lnx"));
+    assert!(tier["invalid"][0]["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("config error"));
+    let text =
+        String::from_utf8_lossy(&run_ok(sb.user_cmd(&["status"]).unwrap()).stdout).into_owned();
+    assert!(text.contains("Not installed:\n"), "{}", text);
+}
+
+#[test]
+fn log_file_gets_debug_lines_whatever_the_console_shows() {
+    let sb = Sandbox::new();
+    let log = sb.path("debug.log");
+    let Some(sync) = sb.user_cmd(&["-q", "sync", "--log-file", log.to_str().unwrap()]) else {
+        return;
+    };
+    bundle(&sb.apps(), "Viewer.lnx", "Viewer", "");
+    let out = run_ok(sync);
+    assert!(
+        !String::from_utf8_lossy(&out.stderr).contains(" INFO "),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let text = std::fs::read_to_string(&log).unwrap();
+    assert!(
+        text.contains("DEBUG") && text.contains("dotlnx started"),
+        "{}",
+        text
+    );
+    assert!(text.contains("reading bundle"), "{}", text);
+
+    let verbose = run_ok(sb.user_cmd(&["sync", "-v"]).unwrap());
+    assert!(String::from_utf8_lossy(&verbose.stderr).contains("syncing tier"));
+    let missing = sb
+        .user_cmd(&["list", "--log-file", "/nonexistent/dir/log"])
+        .unwrap()
+        .output()
+        .unwrap();
+    assert!(!missing.status.success());
+    assert!(String::from_utf8_lossy(&missing.stderr).contains("cannot open log file"));
+}
+
+#[test]
+fn dropped_archives_are_unpacked_and_installed() {
+    let sb = Sandbox::new();
+    let src = bundle(&sb.path("src"), "Viewer.lnx", "Viewer", "");
+    let packed = sb.apps().join("Viewer.lnx.tar.gz");
+    let Some(pack) = sb.user_cmd(&[
+        "pack",
+        "-o",
+        packed.to_str().unwrap(),
+        src.to_str().unwrap(),
+    ]) else {
+        return;
+    };
+    run_ok(pack);
+    // A zipped bundle, deflated, as file managers make them.
+    bundle(&sb.path("src"), "Notes.lnx", "Notes", "");
+    let zip = std::process::Command::new("zip")
+        .args([
+            "-qr",
+            sb.apps().join("Notes.lnx.zip").to_str().unwrap(),
+            "Notes.lnx",
+        ])
+        .current_dir(sb.path("src"))
+        .status()
+        .is_ok_and(|s| s.success());
+    std::fs::write(sb.apps().join("Junk.lnx.zip"), "not a zip").unwrap();
+
+    let summary = json(&run_ok(sb.user_cmd(&["sync", "--json"]).unwrap()));
+    let mut installed = vec![("install".to_string(), "Viewer".to_string())];
+    if zip {
+        installed.insert(0, ("install".into(), "Notes".into()));
+    }
+    assert_eq!(actions(&summary), installed);
+    assert_eq!(summary["skipped"], 1);
+    assert!(sb.apps().join("Viewer.lnx/config.toml").is_file());
+    assert!(
+        !sb.apps().join("Viewer.lnx.tar.gz").exists() && !sb.apps().join("Notes.lnx.zip").exists()
+    );
+    assert!(sb.apps().join("Junk.lnx.zip.failed").is_file());
+    let status = json(&run_ok(sb.user_cmd(&["status", "--json"]).unwrap()));
+    assert!(status["tiers"][0]["invalid"][0]["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("archive error"));
+}
+
+#[test]
+fn export_and_import_restore_bundles() {
+    let sb = Sandbox::new();
+    let archive = sb.path("backup.tar.gz");
+    let Some(export) = sb.user_cmd(&["export", archive.to_str().unwrap()]) else {
+        return;
+    };
+    let b = bundle(&sb.apps(), "Editor.lnx", "Editor", "");
+    std::os::unix::fs::symlink("app", b.join("bin/editor")).unwrap();
+    run_ok(sb.user_cmd(&["sync"]).unwrap());
+    run_ok(export);
+
+    std::fs::remove_dir_all(&b).unwrap();
+    run_ok(sb.user_cmd(&["sync"]).unwrap());
+    run_ok(sb.user_cmd(&["import", archive.to_str().unwrap()]).unwrap());
+    assert!(b.join("config.toml").is_file());
+    assert_eq!(
+        std::fs::read_link(b.join("bin/editor")).unwrap(),
+        std::path::Path::new("app")
+    );
+    assert!(sb.desktop_dir().join("dotlnx-Editor.desktop").is_file());
+
+    // An existing bundle is kept unless --force, which replaces it whole.
+    std::fs::write(b.join("notes.txt"), "").unwrap();
+    run_ok(sb.user_cmd(&["import", archive.to_str().unwrap()]).unwrap());
+    assert!(b.join("notes.txt").exists());
+    run_ok(
+        sb.user_cmd(&["import", "--force", archive.to_str().unwrap()])
+            .unwrap(),
+    );
+    assert!(!b.join("notes.txt").exists() && b.join("bin/app").is_file());
+}